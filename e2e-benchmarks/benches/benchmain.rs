@@ -4,4 +4,5 @@ pub mod benchmarks;
 
 criterion_main! {
     benchmarks::joinbench::joinbench,
+    benchmarks::aggbench::aggbench,
 }
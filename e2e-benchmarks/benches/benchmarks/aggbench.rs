@@ -0,0 +1,28 @@
+use criterion::{criterion_group, Criterion};
+
+use crate::benchmarks::benchtemplate::BenchTemplate;
+use utilities::template::Template;
+
+const ROWS: i32 = 100_000;
+
+fn bench_agg_string_keys(c: &mut Criterion, groups: i32) {
+    let mut bt = Template::new();
+    bt.generate_string_key_table("a", groups, ROWS);
+    bt.add_command("select a.f0, count(a.f1), sum(a.f1) from a group by a.f0");
+    bt.bench_server_rows(c, &format!("agg_string_keys_{}", groups), ROWS as u64);
+}
+
+fn bench_agg_string_keys_10k(c: &mut Criterion) {
+    bench_agg_string_keys(c, 10_000);
+}
+
+fn bench_agg_string_keys_50k(c: &mut Criterion) {
+    bench_agg_string_keys(c, 50_000);
+}
+
+criterion_group! {
+    name = aggbench;
+    config = Criterion::default().sample_size(10);
+    targets = bench_agg_string_keys_10k,
+    bench_agg_string_keys_50k,
+}
@@ -1,10 +1,15 @@
+use utilities::latency::BenchReport;
 use utilities::template::Template;
 
-use criterion::{black_box, Criterion};
+use criterion::{black_box, Criterion, Throughput};
 use std::time::{Duration, Instant};
 
 pub trait BenchTemplate {
     fn bench_server(&mut self, c: &mut Criterion, name: &str);
+    /// Like bench_server, but reports throughput in rows and the server's peak memory.
+    fn bench_server_rows(&mut self, c: &mut Criterion, name: &str, rows: u64);
+    /// Writes the p50/p95/p99 latencies of the commands to a JSON report of the run.
+    fn write_latency_report(&self, name: &str);
 }
 
 impl BenchTemplate for Template {
@@ -14,7 +19,35 @@ impl BenchTemplate for Template {
         // println!("Starting main benchmark function...");
         c.bench_function(name, |b| b.iter(|| self.run_commands()));
         // println!("Starting main benchmark function...END");
+        self.write_latency_report(name);
         // println!("Cleaning up {:?}", name);
         self.run_cleanup();
     }
+
+    fn bench_server_rows(&mut self, c: &mut Criterion, name: &str, rows: u64) {
+        self.run_setup();
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(rows));
+        group.bench_function(name, |b| b.iter(|| self.run_commands()));
+        group.finish();
+        if let Some(kb) = self.server_peak_memory_kb() {
+            println!("{}: server peak memory {} KB", name, kb);
+        }
+        self.write_latency_report(name);
+        self.run_cleanup();
+    }
+
+    fn write_latency_report(&self, name: &str) {
+        let report = self.latency_report(name);
+        for command in &report.commands {
+            println!(
+                "{}: {:?} p50 {}us p95 {}us p99 {}us",
+                name, command.command, command.p50_us, command.p95_us, command.p99_us
+            );
+        }
+        match report.write(&BenchReport::default_dir()) {
+            Ok(path) => println!("{}: latency report written to {}", name, path.display()),
+            Err(e) => println!("{}: failed to write the latency report: {}", name, e),
+        }
+    }
 }
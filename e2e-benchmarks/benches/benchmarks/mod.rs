@@ -1,3 +1,4 @@
+pub mod aggbench;
 pub mod benchtemplate;
 pub mod filterbench;
 pub mod joinbench;
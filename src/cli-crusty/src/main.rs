@@ -7,10 +7,14 @@ use serde::Deserialize;
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
+
+use common::protocol::{self, ClientCommand, Response};
+use common::{ResultMetadata, TableSchema};
 use std::env;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::net::{Shutdown, TcpStream};
+use std::path::PathBuf;
 
 #[derive(Deserialize, Debug)]
 struct ClientConfig {
@@ -18,33 +22,177 @@ struct ClientConfig {
     port: String,
 }
 
-fn process_input(stream: &mut TcpStream, line: &str) -> bool {
+/// File query results sent as Arrow IPC streams are saved to, unless set with `\\o`.
+const DEFAULT_ARROW_PATH: &str = "result.arrow";
+
+/// Settings of the client, changed with commands the client handles itself.
+struct ClientOptions {
+    /// File query results in Arrow IPC format are saved to.
+    arrow_path: PathBuf,
+    /// Whether the rows and time of every statement are shown after its result.
+    timing: bool,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            arrow_path: PathBuf::from(DEFAULT_ARROW_PATH),
+            timing: false,
+        }
+    }
+}
+
+/// Sends a line to the server and prints its response.
+///
+/// `\\o PATH` and `\\timing` are handled by the client: the former sets the file that query
+/// results in Arrow IPC format are saved to, the latter toggles showing the rows and time of
+/// every statement after its result.
+///
+/// Returns false if the server told the client to quit.
+fn process_input(stream: &mut TcpStream, line: &str, options: &mut ClientOptions) -> bool {
+    if let Some(path) = line.strip_prefix("\\o ") {
+        options.arrow_path = PathBuf::from(path.trim());
+        println!(
+            "Arrow results are saved to {}",
+            options.arrow_path.display()
+        );
+        return true;
+    }
+    if line.trim() == "\\timing" {
+        options.timing = !options.timing;
+        println!("Timing is {}", if options.timing { "on" } else { "off" });
+        return true;
+    }
     stream.write_all(format!("{}\n", line).as_bytes()).unwrap();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match read_response(stream, &mut out, options) {
+        Ok(cont) => cont,
+        Err(e) => {
+            error!("Error reading response: {}", e);
+            true
+        }
+    }
+}
 
-    let mut data = [0 as u8; 256];
-    loop {
-        match stream.read(&mut data) {
-            Ok(_size) => {
-                //TODO: Remove echo and change to from_utf8
-                let s = String::from_utf8_lossy(&data);
-
-                //TODO this is dirty. Should likely be response type sent to client.
-                //quit command received from server
-                if s.starts_with("\\") {
-                    if s.starts_with("\\quit") {
-                        info!("Received Quit Command");
-                        return false;
-                    } else {
-                        info!("command received {}", s);
-                        panic!("No action specified for command {}", s);
-                    }
-                }
-                info!("{}", s);
-                return true;
-            },
-            Err(_) => return true
+/// Reads one response from the server and writes it to `out`. Rows are formatted as aligned
+/// text, those of a streamed result batch by batch as they arrive, and a query result in Arrow
+/// IPC format is saved to the client's Arrow path instead. With timing on, the rows and time of
+/// a statement follow its result.
+///
+/// Returns false if the server told the client to quit or closed the connection.
+///
+/// # Arguments
+///
+/// * `reader` - Connection to the server.
+/// * `out` - Where the response is written to.
+/// * `options` - Settings of the client.
+fn read_response<R: Read, W: Write>(
+    reader: &mut R,
+    out: &mut W,
+    options: &ClientOptions,
+) -> io::Result<bool> {
+    let response = match protocol::read_response(reader)? {
+        Some(response) => response,
+        None => {
+            info!("Server closed the connection");
+            return Ok(false);
         }
     };
+    match response {
+        Response::Command(ClientCommand::Quit) => {
+            info!("Received Quit Command");
+            return Ok(false);
+        }
+        Response::Command(ClientCommand::Save { summary, bytes }) => {
+            fs::write(&options.arrow_path, &bytes)?;
+            writeln!(
+                out,
+                "Saved {} ({} bytes) of Arrow IPC to {}",
+                summary,
+                bytes.len(),
+                options.arrow_path.display()
+            )?;
+        }
+        Response::Ok(s) | Response::Error(s) => writeln!(out, "{}", s)?,
+        Response::Header(schema) => return read_stream(reader, out, &schema, options),
+        response => {
+            write!(out, "{}", response)?;
+            if let Response::Done { .. } = response {
+                writeln!(out)?;
+            }
+            if let Some(metadata) = response.metadata() {
+                write_metadata(out, metadata, options)?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(true)
+}
+
+/// Writes the rows and time of a statement if timing is on.
+///
+/// # Arguments
+///
+/// * `out` - Where the line is written to.
+/// * `metadata` - What the statement did.
+/// * `options` - Settings of the client.
+fn write_metadata<W: Write>(
+    out: &mut W,
+    metadata: &ResultMetadata,
+    options: &ClientOptions,
+) -> io::Result<()> {
+    if options.timing && !metadata.is_empty() {
+        writeln!(out, "({})", metadata)?;
+    }
+    Ok(())
+}
+
+/// Reads the batches of a streamed query result after its header and writes them to `out`,
+/// until the end of the result or an error that ended it.
+///
+/// # Arguments
+///
+/// * `reader` - Connection to the server.
+/// * `out` - Where the rows are written to.
+/// * `schema` - Schema of the rows, from the header of the result.
+/// * `options` - Settings of the client.
+fn read_stream<R: Read, W: Write>(
+    reader: &mut R,
+    out: &mut W,
+    schema: &TableSchema,
+    options: &ClientOptions,
+) -> io::Result<bool> {
+    write!(out, "{}", protocol::format_rows(schema, &[]))?;
+    let width = protocol::column_width(schema);
+    loop {
+        out.flush()?;
+        match protocol::read_response(reader)? {
+            Some(Response::Batch(tuples)) => {
+                write!(out, "{}", protocol::format_tuples(width, &tuples))?
+            }
+            Some(Response::End(metadata)) => {
+                write_metadata(out, &metadata, options)?;
+                out.flush()?;
+                return Ok(true);
+            }
+            Some(Response::Error(s)) => {
+                writeln!(out, "{}", s)?;
+                out.flush()?;
+                return Ok(true);
+            }
+            Some(response) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unexpected response in a streamed result: {:?}", response),
+                ))
+            }
+            None => {
+                info!("Server closed the connection");
+                return Ok(false);
+            }
+        }
+    }
 }
 
 #[allow(unused_must_use)]
@@ -54,6 +202,7 @@ fn process_cli_input(stream: &mut TcpStream) {
         info!("No previous history.");
     }
     let prompt: &str = "[crustydb]>>";
+    let mut options = ClientOptions::default();
     let mut cont = true;
     while cont {
         let readline = rl.readline(prompt);
@@ -63,7 +212,7 @@ fn process_cli_input(stream: &mut TcpStream) {
                     continue;
                 }
                 rl.add_history_entry(line.as_str());
-                cont = process_input(stream, line.as_str());
+                cont = process_input(stream, line.as_str(), &mut options);
             }
             Err(ReadlineError::Interrupted) => {
                 info!("CTRL-C");
@@ -88,6 +237,7 @@ fn process_cli_input(stream: &mut TcpStream) {
 #[allow(unused_must_use)]
 fn process_script_input(stream: &mut TcpStream, script: String) {
     let lines = script.split(";");
+    let mut options = ClientOptions::default();
     for line in lines {
         let command = line.trim();
         if command == "" {
@@ -96,7 +246,7 @@ fn process_script_input(stream: &mut TcpStream, script: String) {
         let clean_command = &command.replace("\n", " ");
         info!("Script clean command: {}", clean_command);
 
-        if !process_input(stream, clean_command) {
+        if !process_input(stream, clean_command, &mut options) {
             panic!("Bad Script");
         }
     }
@@ -191,3 +341,164 @@ fn main() {
     }
     info!("Terminated.");
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::{DataType, Field, Tuple};
+    use std::time::Duration;
+
+    /// Hands out the data a few bytes at a time, like a slow connection.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = 3.min(buf.len()).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    /// The responses, as the server writes them.
+    fn frames(responses: &[Response]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for response in responses {
+            protocol::write_response(&mut data, response).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn test_read_response() {
+        let options = ClientOptions::default();
+        let schema = TableSchema::from_vecs(vec!["t.a", "t.b"], vec![DataType::Int, DataType::Int]);
+        let tuples: Vec<Tuple> = (0..1000)
+            .map(|i| Tuple::new(vec![Field::IntField(i), Field::IntField(-i)]))
+            .collect();
+        let rows = protocol::format_rows(&schema, &tuples);
+        let data = frames(&[
+            Response::Rows {
+                schema,
+                tuples,
+                metadata: ResultMetadata::default(),
+            },
+            Response::Ok(String::from("日本語")),
+            Response::Error(String::from("Crusty Error: no")),
+            Response::Command(ClientCommand::Quit),
+        ]);
+        let mut reader = Trickle { data, pos: 0 };
+        let mut out = Vec::new();
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        assert_eq!(rows, String::from_utf8(out).unwrap());
+        let mut out = Vec::new();
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        assert_eq!(
+            "日本語\nCrusty Error: no\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        assert!(!read_response(&mut reader, &mut out, &options).unwrap());
+        assert!(out.is_empty());
+        // the server closed the connection
+        assert!(!read_response(&mut reader, &mut out, &options).unwrap());
+    }
+
+    #[test]
+    fn test_read_stream() {
+        let options = ClientOptions::default();
+        let schema = TableSchema::from_vecs(vec!["a"], vec![DataType::Int]);
+        let tuples: Vec<Tuple> = (0..5)
+            .map(|i| Tuple::new(vec![Field::IntField(i)]))
+            .collect();
+        let data = frames(&[
+            Response::Header(schema.clone()),
+            Response::Batch(tuples[..3].to_vec()),
+            Response::Batch(tuples[3..].to_vec()),
+            Response::End(ResultMetadata::default()),
+            Response::Header(schema.clone()),
+            Response::Batch(tuples[..1].to_vec()),
+            Response::Error(String::from("Execution Error: out of rows")),
+            Response::Ok(String::from("ok")),
+        ]);
+        let mut reader = Trickle { data, pos: 0 };
+        let mut out = Vec::new();
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        assert_eq!(
+            protocol::format_rows(&schema, &tuples),
+            String::from_utf8(out).unwrap()
+        );
+        let mut out = Vec::new();
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        assert_eq!(
+            "a  \n0  \nExecution Error: out of rows\nok\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_timing() {
+        let metadata = ResultMetadata {
+            rows_affected: Some(2),
+            elapsed: Some(Duration::from_micros(1250)),
+            ..ResultMetadata::default()
+        };
+        let done = Response::Done {
+            message: String::from("Inserted 2 rows into t"),
+            metadata,
+        };
+        let data = frames(&[done.clone(), done]);
+        let mut reader = Trickle { data, pos: 0 };
+        let mut options = ClientOptions::default();
+        let mut out = Vec::new();
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        options.timing = true;
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        assert_eq!(
+            "Inserted 2 rows into t\nInserted 2 rows into t\n(2 rows affected, 1.250 ms)\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_arrow_response() {
+        let path = env::temp_dir().join(format!("cli-crusty-{}.arrow", std::process::id()));
+        let options = ClientOptions {
+            arrow_path: path.clone(),
+            ..ClientOptions::default()
+        };
+        let stream = vec![0xff, 0, 7, 0, 9];
+        let data = frames(&[Response::Command(ClientCommand::Save {
+            summary: String::from("2 rows"),
+            bytes: stream.clone(),
+        })]);
+        let mut reader = Trickle { data, pos: 0 };
+        let mut out = Vec::new();
+        assert!(read_response(&mut reader, &mut out, &options).unwrap());
+        assert_eq!(stream, fs::read(&path).unwrap());
+        assert_eq!(
+            format!(
+                "Saved 2 rows (5 bytes) of Arrow IPC to {}\n",
+                path.display()
+            ),
+            String::from_utf8(out).unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+
+        // A response cut short.
+        let mut data = frames(&[Response::Command(ClientCommand::Save {
+            summary: String::from("2 rows"),
+            bytes: stream,
+        })]);
+        data.truncate(data.len() - 2);
+        let mut reader = Trickle { data, pos: 0 };
+        assert!(read_response(&mut reader, &mut Vec::new(), &options).is_err());
+        assert!(!path.exists());
+    }
+}
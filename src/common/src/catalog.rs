@@ -1,5 +1,7 @@
+use crate::database::Database;
+use crate::ids::ContainerId;
 use crate::table::*;
-use crate::{CrustyError, TableSchema};
+use crate::{Attribute, CrustyError, TableSchema};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
@@ -88,3 +90,302 @@ pub trait Catalog {
         }
     }
 }
+
+/// Immutable copy of the tables of a catalog.
+///
+/// Taken when a statement starts so that translation, validation and planning all see the same
+/// tables, even if DDL changes the catalog in the meantime. Cloning a snapshot is cheap, the
+/// copied tables are shared between the clones.
+#[derive(Clone)]
+pub struct CatalogSnapshot {
+    /// Copies of the tables, not shared with the catalog they were taken from.
+    tables: Arc<RwLock<HashMap<u64, Arc<RwLock<Table>>>>>,
+    /// Table ids by table name.
+    names: Arc<HashMap<String, u64>>,
+}
+
+impl CatalogSnapshot {
+    /// Copies the tables of a catalog.
+    ///
+    /// # Arguments
+    ///
+    /// * `catalog` - Catalog to take the snapshot of.
+    pub fn new<T: Catalog>(catalog: &T) -> Self {
+        let tables = catalog.get_tables();
+        // Holding the read lock keeps DDL out until every table is copied.
+        let tables_ref = tables.read().unwrap();
+        let mut copies = HashMap::with_capacity(tables_ref.len());
+        let mut names = HashMap::with_capacity(tables_ref.len());
+        for (id, table_ptr) in tables_ref.iter() {
+            let table = table_ptr.read().unwrap().clone();
+            names.insert(table.name.clone(), *id);
+            copies.insert(*id, Arc::new(RwLock::new(table)));
+        }
+        Self {
+            tables: Arc::new(RwLock::new(copies)),
+            names: Arc::new(names),
+        }
+    }
+
+    /// Gets the id of a table by name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the table.
+    pub fn table_id(&self, name: &str) -> Option<u64> {
+        self.names.get(name).copied()
+    }
+
+    /// Number of tables in the snapshot.
+    pub fn table_count(&self) -> usize {
+        self.names.len()
+    }
+}
+
+impl Catalog for CatalogSnapshot {
+    fn get_tables(&self) -> Arc<RwLock<HashMap<u64, Arc<RwLock<Table>>>>> {
+        self.tables.clone()
+    }
+}
+
+/// Version of the catalog document produced by `export_catalog`.
+pub const CATALOG_DOC_VERSION: u32 = 1;
+
+/// Serializable description of every database known to a server.
+///
+/// Databases and tables are ordered by name so the same catalog always yields the same document.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CatalogDoc {
+    /// Version of the document format.
+    pub version: u32,
+    /// Databases in the catalog.
+    pub databases: Vec<DatabaseDoc>,
+}
+
+/// Serializable description of a database.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DatabaseDoc {
+    /// Name of the database.
+    pub name: String,
+    /// Tables of the database.
+    pub tables: Vec<TableDoc>,
+}
+
+/// Serializable description of a table.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TableDoc {
+    /// Table name.
+    pub name: String,
+    /// Table id.
+    pub id: u64,
+    /// Container holding the table's values in the storage manager.
+    pub container_id: ContainerId,
+    /// Columns of the table, in schema order.
+    pub columns: Vec<Attribute>,
+    /// Whether rows of the table are only ever added.
+    #[serde(default)]
+    pub append_only: bool,
+    /// Indexes on columns of the table.
+    #[serde(default)]
+    pub indexes: Vec<IndexInfo>,
+}
+
+/// Describes the given databases as a catalog document.
+///
+/// # Arguments
+///
+/// * `dbs` - Databases to describe.
+pub fn export_catalog(dbs: &[&Database]) -> CatalogDoc {
+    let mut databases = Vec::new();
+    for db in dbs {
+        let tables = db.get_tables();
+        let tables_ref = tables.read().unwrap();
+        let mut table_docs = Vec::new();
+        for table_ptr in tables_ref.values() {
+            let table = table_ptr.read().unwrap();
+            table_docs.push(TableDoc {
+                name: table.name.clone(),
+                id: table.id,
+                container_id: table.id as ContainerId,
+                columns: table.schema.attributes().cloned().collect(),
+                append_only: table.append_only,
+                indexes: table.indexes.clone(),
+            });
+        }
+        table_docs.sort_by(|a, b| a.name.cmp(&b.name));
+        databases.push(DatabaseDoc {
+            name: db.name.clone(),
+            tables: table_docs,
+        });
+    }
+    databases.sort_by(|a, b| a.name.cmp(&b.name));
+    CatalogDoc {
+        version: CATALOG_DOC_VERSION,
+        databases,
+    }
+}
+
+/// Exports the given databases as a pretty-printed JSON catalog document.
+///
+/// # Arguments
+///
+/// * `dbs` - Databases to export.
+pub fn export_catalog_json(dbs: &[&Database]) -> Result<String, CrustyError> {
+    serde_json::to_string_pretty(&export_catalog(dbs))
+        .map_err(|e| CrustyError::CrustyError(e.to_string()))
+}
+
+/// Rebuilds the databases described by a catalog document.
+///
+/// Table ids and container ids must match the ones derived from the table names, since they
+/// are how the rest of the system finds a table.
+///
+/// # Arguments
+///
+/// * `doc` - Catalog document to import.
+pub fn import_catalog(doc: &CatalogDoc) -> Result<Vec<Database>, CrustyError> {
+    if doc.version != CATALOG_DOC_VERSION {
+        return Err(CrustyError::ValidationError(format!(
+            "Unsupported catalog version {}",
+            doc.version
+        )));
+    }
+    let mut dbs = Vec::new();
+    for db_doc in &doc.databases {
+        let db = Database::new(db_doc.name.clone());
+        {
+            let mut tables = db.tables.write().unwrap();
+            for table_doc in &db_doc.tables {
+                let mut table = Table::new(
+                    table_doc.name.clone(),
+                    TableSchema::new(table_doc.columns.clone()),
+                );
+                table.append_only = table_doc.append_only;
+                table.indexes = table_doc.indexes.clone();
+                if table.id != table_doc.id || table.id as ContainerId != table_doc.container_id {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Ids of table {} do not match its name",
+                        table_doc.name
+                    )));
+                }
+                if tables.contains_key(&table.id) {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Duplicate table {} in database {}",
+                        table_doc.name, db_doc.name
+                    )));
+                }
+                tables.insert(table.id, Arc::new(RwLock::new(table)));
+            }
+        }
+        dbs.push(db);
+    }
+    Ok(dbs)
+}
+
+/// Rebuilds the databases from a JSON catalog document produced by `export_catalog_json`.
+///
+/// # Arguments
+///
+/// * `json` - JSON catalog document.
+pub fn import_catalog_json(json: &str) -> Result<Vec<Database>, CrustyError> {
+    let doc: CatalogDoc =
+        serde_json::from_str(json).map_err(|e| CrustyError::ValidationError(e.to_string()))?;
+    import_catalog(&doc)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::get_int_table_schema;
+    use crate::{DataType, TableSchema};
+
+    fn get_db() -> Database {
+        let db = Database::new(String::from("db"));
+        {
+            let mut tables = db.tables.write().unwrap();
+            for name in &["b", "a"] {
+                let table = Table::new(name.to_string(), get_int_table_schema(2));
+                tables.insert(table.id, Arc::new(RwLock::new(table)));
+            }
+            let schema =
+                TableSchema::new(vec![Attribute::new(String::from("s"), DataType::String)]);
+            let mut table = Table::new(String::from("c"), schema);
+            table.append_only = true;
+            table.indexes.push(IndexInfo {
+                name: String::from("c_s"),
+                column: String::from("s"),
+                container_id: 7,
+            });
+            tables.insert(table.id, Arc::new(RwLock::new(table)));
+        }
+        db
+    }
+
+    #[test]
+    fn test_export_is_stable() -> Result<(), CrustyError> {
+        let db = get_db();
+        let doc = export_catalog(&[&db]);
+        let names: Vec<&str> = doc.databases[0]
+            .tables
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect();
+        assert_eq!(vec!["a", "b", "c"], names);
+        assert_eq!(export_catalog_json(&[&db])?, export_catalog_json(&[&db])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), CrustyError> {
+        let db = get_db();
+        let json = export_catalog_json(&[&db])?;
+        let imported = import_catalog_json(&json)?;
+        assert_eq!(1, imported.len());
+        assert_eq!("db", imported[0].name);
+        let table_id = Table::get_table_id("c");
+        assert_eq!(
+            db.get_table_schema(table_id)?,
+            imported[0].get_table_schema(table_id)?
+        );
+        let table = imported[0].get_table_ptr(table_id)?;
+        assert!(table.read().unwrap().append_only);
+        assert_eq!("c_s", table.read().unwrap().indexes[0].name);
+        assert_eq!(json, export_catalog_json(&[&imported[0]])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_is_isolated() -> Result<(), CrustyError> {
+        let db = get_db();
+        let snapshot = CatalogSnapshot::new(&db);
+        let a_id = Table::get_table_id("a");
+        assert_eq!(3, snapshot.table_count());
+        assert_eq!(Some(a_id), snapshot.table_id("a"));
+        assert_eq!(None, snapshot.table_id("d"));
+
+        // DDL on the database after the snapshot was taken
+        {
+            let mut tables = db.tables.write().unwrap();
+            tables.remove(&a_id);
+            let table = Table::new(String::from("d"), get_int_table_schema(1));
+            tables.insert(table.id, Arc::new(RwLock::new(table)));
+            let c_ptr = tables.get(&Table::get_table_id("c")).unwrap();
+            c_ptr.write().unwrap().schema = get_int_table_schema(4);
+        }
+        assert!(snapshot.is_valid_table(a_id));
+        assert_eq!(get_int_table_schema(2), snapshot.get_table_schema(a_id)?);
+        assert!(!snapshot.is_valid_table(Table::get_table_id("d")));
+        assert!(snapshot.is_valid_column(Table::get_table_id("c"), "s"));
+        assert_eq!("a", snapshot.clone().get_table_name(a_id)?);
+        assert!(!db.is_valid_table(a_id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_rejects_bad_ids() {
+        let db = get_db();
+        let mut doc = export_catalog(&[&db]);
+        doc.databases[0].tables[0].id += 1;
+        assert!(import_catalog(&doc).is_err());
+    }
+}
@@ -1,8 +1,10 @@
+use crate::CrustyError;
 use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 
 static TXN_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 /// Permissions for locks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Permissions {
     ReadOnly,
     ReadWrite,
@@ -80,4 +82,136 @@ impl ValueId {
             slot_id: None,
         }
     }
+
+    /// Packs the id into a u64, for indexes, log records and cursors that need a small reference
+    /// to a value that stays the same across releases.
+    ///
+    /// From the most significant bits down, the container (16 bits), page (16 bits) and slot (16
+    /// bits), followed by 16 bits of flags: the segment (8 bits), then which of the segment, page
+    /// and slot are set. Packed ids of a container sort by page and then slot.
+    pub fn pack(&self) -> u64 {
+        let mut flags = (self.segment_id.unwrap_or(0) as u64) << 8;
+        if self.segment_id.is_some() {
+            flags |= HAS_SEGMENT;
+        }
+        if self.page_id.is_some() {
+            flags |= HAS_PAGE;
+        }
+        if self.slot_id.is_some() {
+            flags |= HAS_SLOT;
+        }
+        (self.container_id as u64) << 48
+            | (self.page_id.unwrap_or(0) as u64) << 32
+            | (self.slot_id.unwrap_or(0) as u64) << 16
+            | flags
+    }
+
+    /// Unpacks an id packed with `pack`.
+    ///
+    /// Fails if unknown flags are set or a segment, page or slot is not 0 without its flag, which
+    /// means the bits did not come from `pack`.
+    ///
+    /// # Arguments
+    ///
+    /// * `packed` - Packed id.
+    pub fn unpack(packed: u64) -> Result<Self, CrustyError> {
+        let field = |shift: u32, bits: u64| (packed >> shift) & bits;
+        let optional = |value: u64, flag: u64| -> Result<Option<u64>, CrustyError> {
+            match (packed & flag != 0, value) {
+                (true, v) => Ok(Some(v)),
+                (false, 0) => Ok(None),
+                (false, _) => Err(CrustyError::ValidationError(format!(
+                    "Invalid packed value id {:#018x}",
+                    packed
+                ))),
+            }
+        };
+        if packed & 0xff & !(HAS_SEGMENT | HAS_PAGE | HAS_SLOT) != 0 {
+            return Err(CrustyError::ValidationError(format!(
+                "Invalid packed value id {:#018x}",
+                packed
+            )));
+        }
+        Ok(ValueId {
+            container_id: field(48, 0xffff) as ContainerId,
+            segment_id: optional(field(8, 0xff), HAS_SEGMENT)?.map(|s| s as SegmentId),
+            page_id: optional(field(32, 0xffff), HAS_PAGE)?.map(|p| p as PageId),
+            slot_id: optional(field(16, 0xffff), HAS_SLOT)?.map(|s| s as SlotId),
+        })
+    }
+}
+
+/// Flags of a packed `ValueId` telling which of its optional parts are set.
+const HAS_SEGMENT: u64 = 1;
+const HAS_PAGE: u64 = 1 << 1;
+const HAS_SLOT: u64 = 1 << 2;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pack_round_trip() {
+        let edges16 = [0u16, 1, 0x7fff, 0x8000, 0xfffe, 0xffff];
+        let edges8 = [0u8, 1, 0x7f, 0x80, 0xff];
+        let mut packed = Vec::new();
+        for &container_id in edges16.iter() {
+            for segment_id in edges8.iter().map(|&s| Some(s)).chain(Some(None)) {
+                for page_id in edges16.iter().map(|&p| Some(p)).chain(Some(None)) {
+                    for slot_id in edges16.iter().map(|&s| Some(s)).chain(Some(None)) {
+                        let id = ValueId {
+                            container_id,
+                            segment_id,
+                            page_id,
+                            slot_id,
+                        };
+                        assert_eq!(id, ValueId::unpack(id.pack()).unwrap());
+                        packed.push(id.pack());
+                    }
+                }
+            }
+        }
+        // different ids never pack the same
+        let count = packed.len();
+        packed.sort_unstable();
+        packed.dedup();
+        assert_eq!(count, packed.len());
+    }
+
+    #[test]
+    fn test_pack_layout() {
+        let id = ValueId {
+            container_id: 0x1234,
+            segment_id: None,
+            page_id: Some(0x5678),
+            slot_id: Some(0x9abc),
+        };
+        assert_eq!(0x1234_5678_9abc_0006, id.pack());
+        assert_eq!(0x0001_0000_0000_0000, ValueId::new(1).pack());
+        // ids of a container sort by page, then slot
+        let slot = |page, slot| ValueId {
+            slot_id: Some(slot),
+            ..ValueId::new_page(1, page)
+        };
+        assert!(slot(0, 9).pack() < slot(1, 0).pack());
+        assert!(slot(1, 0).pack() < slot(1, 1).pack());
+    }
+
+    #[test]
+    fn test_unpack_every_flag() {
+        // of all 16 flag bits only pack's combinations are accepted
+        for flags in 0..=0xffffu64 {
+            let packed = 0x0001_0000_0000_0000 | flags;
+            let valid = flags & 0xf8 == 0 && (flags >> 8 == 0 || flags & HAS_SEGMENT != 0);
+            match ValueId::unpack(packed) {
+                Ok(id) => {
+                    assert!(valid, "{:#x} accepted", flags);
+                    assert_eq!(packed, id.pack());
+                }
+                Err(_) => assert!(!valid, "{:#x} rejected", flags),
+            }
+        }
+        assert!(ValueId::unpack(0x0001_0002_0000_0000).is_err());
+        assert!(ValueId::unpack(0x0001_0000_0003_0000).is_err());
+    }
 }
@@ -0,0 +1,159 @@
+use crate::ids::TransactionId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Pages read and written on behalf of a transaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct IoStats {
+    /// Pages the storage manager had to read from its files or memory.
+    pub pages_read: u64,
+    /// Pages the storage manager wrote to its files or memory.
+    pub pages_written: u64,
+}
+
+impl fmt::Display for IoStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pages read: {}, pages written: {}",
+            self.pages_read, self.pages_written
+        )
+    }
+}
+
+/// Counters a storage manager adds the IO of a single transaction to.
+#[derive(Debug, Default)]
+pub struct IoCounters {
+    pages_read: AtomicU64,
+    pages_written: AtomicU64,
+}
+
+impl IoCounters {
+    /// Counts pages read.
+    ///
+    /// # Arguments
+    ///
+    /// * `pages` - Number of pages read.
+    pub fn add_read(&self, pages: u64) {
+        self.pages_read.fetch_add(pages, Ordering::Relaxed);
+    }
+
+    /// Counts pages written.
+    ///
+    /// # Arguments
+    ///
+    /// * `pages` - Number of pages written.
+    pub fn add_written(&self, pages: u64) {
+        self.pages_written.fetch_add(pages, Ordering::Relaxed);
+    }
+
+    /// Returns the pages counted so far.
+    pub fn stats(&self) -> IoStats {
+        IoStats {
+            pages_read: self.pages_read.load(Ordering::Relaxed),
+            pages_written: self.pages_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The counters of the transactions whose IO is being tracked. Storage managers keep one and
+/// look up the counters of the transaction each call is made for, IO of transactions that are
+/// not tracked is not counted.
+#[derive(Debug, Default)]
+pub struct IoTracker {
+    counters: RwLock<HashMap<TransactionId, Arc<IoCounters>>>,
+}
+
+impl IoTracker {
+    /// Starts counting the IO of a transaction, from zero if it was already tracked.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction to track.
+    pub fn track(&self, tid: TransactionId) {
+        self.counters
+            .write()
+            .unwrap()
+            .insert(tid, Arc::new(IoCounters::default()));
+    }
+
+    /// Stops counting the IO of a transaction and returns what was counted. Returns zeros if the
+    /// transaction was not tracked.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction to stop tracking.
+    pub fn untrack(&self, tid: TransactionId) -> IoStats {
+        self.counters
+            .write()
+            .unwrap()
+            .remove(&tid)
+            .map(|c| c.stats())
+            .unwrap_or_default()
+    }
+
+    /// Returns the counters of a transaction, None if it is not tracked. Iterators that outlive
+    /// the call that created them keep the counters to add their reads to.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction the IO is done for.
+    pub fn counters(&self, tid: TransactionId) -> Option<Arc<IoCounters>> {
+        self.counters.read().unwrap().get(&tid).cloned()
+    }
+
+    /// Counts pages read for a transaction, if it is tracked.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction the pages were read for.
+    /// * `pages` - Number of pages read.
+    pub fn add_read(&self, tid: TransactionId, pages: u64) {
+        if let Some(c) = self.counters.read().unwrap().get(&tid) {
+            c.add_read(pages);
+        }
+    }
+
+    /// Counts pages written for a transaction, if it is tracked.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction the pages were written for.
+    /// * `pages` - Number of pages written.
+    pub fn add_written(&self, tid: TransactionId, pages: u64) {
+        if let Some(c) = self.counters.read().unwrap().get(&tid) {
+            c.add_written(pages);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_io_tracker() {
+        let tracker = IoTracker::default();
+        let (t1, t2) = (TransactionId::new(), TransactionId::new());
+        tracker.track(t1);
+        tracker.add_read(t1, 3);
+        tracker.add_written(t1, 1);
+        // IO of transactions that are not tracked is dropped
+        tracker.add_read(t2, 5);
+        let counters = tracker.counters(t1).unwrap();
+        counters.add_read(1);
+        assert!(tracker.counters(t2).is_none());
+        assert_eq!(
+            IoStats {
+                pages_read: 4,
+                pages_written: 1
+            },
+            tracker.untrack(t1)
+        );
+        assert_eq!(IoStats::default(), tracker.untrack(t1));
+        assert_eq!(IoStats::default(), tracker.untrack(t2));
+    }
+}
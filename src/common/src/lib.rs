@@ -6,19 +6,29 @@ extern crate log;
 use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Serialize, Serializer};
 use sqlparser::ast;
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::io;
+use std::mem::size_of;
+use std::time::Duration;
 pub mod crusty_graph;
 pub mod logical_plan;
-pub use logical_plan::{AggOp, PredicateOp};
+pub use logical_plan::{AggOp, JoinType, PredicateOp};
 pub mod catalog;
 pub mod database;
 pub mod ids;
+pub mod io_stats;
+pub mod lock_manager;
+pub mod protocol;
+pub mod row_format;
+pub mod statistics;
 pub mod storage_trait;
 pub mod table;
 pub mod testutil;
+pub mod undo_log;
 
 /// How big each page is
 pub const PAGE_SIZE: usize = 4096;
@@ -50,7 +60,9 @@ impl fmt::Display for CrustyError {
                 CrustyError::ExecutionError(s) => format!("Execution Error: {}", s),
                 CrustyError::CrustyError(s) => format!("Crusty Error: {}", s),
                 CrustyError::IOError(s) => s.to_string(),
-                CrustyError::TransactionAbortedError => String::from("Transaction Aborted Error"),
+                CrustyError::TransactionAbortedError => {
+                    String::from("Transaction Aborted Error (retry advised)")
+                }
             }
         )
     }
@@ -65,9 +77,62 @@ impl From<io::Error> for CrustyError {
 
 impl Error for CrustyError {}
 
+impl CrustyError {
+    /// Whether running the transaction again may succeed, e.g. after it was aborted to break a
+    /// deadlock.
+    pub fn is_retry_advised(&self) -> bool {
+        matches!(self, CrustyError::TransactionAbortedError)
+    }
+}
+
+/// What a statement did, sent to the client with its result.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ResultMetadata {
+    /// Rows the query returned.
+    pub rows_returned: Option<u64>,
+    /// Rows the statement inserted, changed or removed.
+    pub rows_affected: Option<u64>,
+    /// Time the statement took to run, reruns of aborted transactions included.
+    pub elapsed: Option<Duration>,
+    /// Operator tree of the optimized plan of the query, as `EXPLAIN` shows it.
+    pub plan: Option<String>,
+}
+
+impl ResultMetadata {
+    /// Whether nothing is known about the statement.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for ResultMetadata {
+    /// The rows and time of the statement on one line, e.g. `3 rows returned, 1.250 ms`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(rows) = self.rows_returned {
+            parts.push(format!("{} rows returned", rows));
+        }
+        if let Some(rows) = self.rows_affected {
+            parts.push(format!("{} rows affected", rows));
+        }
+        if let Some(elapsed) = self.elapsed {
+            parts.push(format!("{:.3} ms", elapsed.as_secs_f64() * 1000.0));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
 /// Return type for a query result.
 pub struct QueryResult {
     result: String,
+    /// Encoded rows sent after the result, for clients that asked for a binary format.
+    payload: Option<Vec<u8>>,
+    /// Rows of a query, sent to the client as they are and formatted by it.
+    rows: Option<(TableSchema, Vec<Tuple>)>,
+    /// Whether the rows were already sent to the client while the query ran.
+    streamed: bool,
+    /// What the statement did.
+    metadata: ResultMetadata,
 }
 
 impl QueryResult {
@@ -75,6 +140,10 @@ impl QueryResult {
     pub fn empty() -> Self {
         Self {
             result: String::from(""),
+            payload: None,
+            rows: None,
+            streamed: false,
+            metadata: ResultMetadata::default(),
         }
     }
 
@@ -86,12 +155,97 @@ impl QueryResult {
     pub fn new(result: &str) -> Self {
         Self {
             result: result.to_string(),
+            payload: None,
+            rows: None,
+            streamed: false,
+            metadata: ResultMetadata::default(),
+        }
+    }
+
+    /// Return a result with a summary and the rows encoded in a binary format.
+    ///
+    /// # Arguments
+    ///
+    /// * `result` - Summary of the result.
+    /// * `payload` - Encoded rows.
+    pub fn with_payload(result: &str, payload: Vec<u8>) -> Self {
+        Self {
+            result: result.to_string(),
+            payload: Some(payload),
+            rows: None,
+            streamed: false,
+            metadata: ResultMetadata::default(),
+        }
+    }
+
+    /// Return a result made of the rows of a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the rows.
+    /// * `tuples` - Rows of the result.
+    pub fn with_rows(schema: TableSchema, tuples: Vec<Tuple>) -> Self {
+        Self {
+            result: String::new(),
+            payload: None,
+            metadata: ResultMetadata {
+                rows_returned: Some(tuples.len() as u64),
+                ..ResultMetadata::default()
+            },
+            rows: Some((schema, tuples)),
+            streamed: false,
+        }
+    }
+
+    /// Return the result of a query whose rows were sent to the client while it ran.
+    ///
+    /// # Arguments
+    ///
+    /// * `rows` - Number of rows sent.
+    pub fn streamed(rows: usize) -> Self {
+        Self {
+            result: format!("{} rows", rows),
+            payload: None,
+            rows: None,
+            streamed: true,
+            metadata: ResultMetadata {
+                rows_returned: Some(rows as u64),
+                ..ResultMetadata::default()
+            },
         }
     }
 
-    /// Get the result.
-    pub fn result(&self) -> &str {
-        &self.result
+    /// What the statement did.
+    pub fn metadata(&self) -> &ResultMetadata {
+        &self.metadata
+    }
+
+    /// What the statement did, for the layers above the one that ran it to add to.
+    pub fn metadata_mut(&mut self) -> &mut ResultMetadata {
+        &mut self.metadata
+    }
+
+    /// Whether the rows of the result were already sent to the client.
+    pub fn is_streamed(&self) -> bool {
+        self.streamed
+    }
+
+    /// Get the result, with the rows formatted as text if it is made of rows.
+    pub fn result(&self) -> Cow<'_, str> {
+        match &self.rows {
+            Some((schema, tuples)) => Cow::Owned(protocol::format_rows(schema, tuples)),
+            None => Cow::Borrowed(&self.result),
+        }
+    }
+
+    /// Take the encoded rows out of the result, if it has them.
+    pub fn take_payload(&mut self) -> Option<Vec<u8>> {
+        self.payload.take()
+    }
+
+    /// Take the rows out of the result, if it is made of rows.
+    pub fn take_rows(&mut self) -> Option<(TableSchema, Vec<Tuple>)> {
+        self.rows.take()
     }
 }
 
@@ -206,7 +360,8 @@ impl TableSchema {
         self.attributes.len()
     }
 
-    /// Returns the size of the schema in bytes.
+    /// Returns the most bytes the values of a row of the schema take, not counting the version
+    /// byte and null bitmap of the row, see `Attribute::get_byte_len`.
     pub fn byte_size(&self) -> usize {
         let mut total: usize = 0;
         for attr in self.attributes.iter() {
@@ -223,6 +378,16 @@ pub struct Attribute {
     pub name: String,
     /// Attribute dtype.
     pub dtype: DataType,
+    /// Whether the attribute may be NULL. Schemas saved before NULL existed are nullable.
+    #[serde(default = "nullable_default")]
+    pub nullable: bool,
+    /// Most characters a string attribute holds, from `VARCHAR(n)`. None for no limit.
+    #[serde(default)]
+    pub max_len: Option<usize>,
+}
+
+fn nullable_default() -> bool {
+    true
 }
 
 impl Attribute {
@@ -233,7 +398,27 @@ impl Attribute {
     /// * `name` - Name of the attribute.
     /// * `dtype` - Dtype of the attribute.
     pub fn new(name: String, dtype: DataType) -> Self {
-        Self { name, dtype }
+        Self {
+            name,
+            dtype,
+            nullable: true,
+            max_len: None,
+        }
+    }
+
+    /// Create a new attribute that can't be NULL.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the attribute.
+    /// * `dtype` - Dtype of the attribute.
+    pub fn new_not_null(name: String, dtype: DataType) -> Self {
+        Self {
+            name,
+            dtype,
+            nullable: false,
+            max_len: None,
+        }
     }
 
     /// Returns the name of the attribute.
@@ -246,12 +431,26 @@ impl Attribute {
         &self.dtype
     }
 
-    // TODO(williamma12): Where does the 132 come from?
-    /// Returns the length of the dtype in bytes.
+    /// Returns whether the attribute may be NULL.
+    pub fn nullable(&self) -> bool {
+        self.nullable
+    }
+
+    /// Returns the most characters a string attribute holds, None for no limit.
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    /// Returns the most bytes a value of the attribute takes in a row. Strings are stored as
+    /// their length in 2 bytes followed by their UTF-8 bytes, at most 4 per character, so only
+    /// `VARCHAR(n)` strings are bounded below what the length can count.
     pub fn get_byte_len(&self) -> usize {
         match self.dtype {
-            DataType::Int => 4,
-            DataType::String => 132,
+            DataType::Int => size_of::<i32>(),
+            DataType::String => {
+                let max_bytes = u16::MAX as usize;
+                size_of::<u16>() + self.max_len.map_or(max_bytes, |n| max_bytes.min(4 * n))
+            }
         }
     }
 }
@@ -264,8 +463,13 @@ pub enum DataType {
 }
 
 /// For each of the dtypes, make sure that there is a corresponding field type.
+///
+/// `Null` is a missing value of any type. It is equal to itself and sorts before every other
+/// field, so DISTINCT and GROUP BY keep all NULLs together and ORDER BY puts them first, but
+/// predicates comparing a NULL are never true, see `PredicateOp::compare`.
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, PartialOrd, Ord, Clone, Hash)]
 pub enum Field {
+    Null,
     IntField(i32),
     StringField(String),
 }
@@ -274,23 +478,82 @@ impl Field {
     /// Function to convert a Tuple field into bytes for serialization
     ///
     /// This function always uses least endian byte ordering and stores strings in the format |string length|string contents|.
+    /// The length is the number of UTF-8 bytes, and the contents are not padded.
+    /// NULL has no bytes; formats holding NULLs record them apart from the values, as
+    /// `RowFormat::Nullable` does.
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
+            Field::Null => Vec::new(),
             Field::IntField(x) => x.to_le_bytes().to_vec(),
             Field::StringField(s) => {
                 let s_len: usize = s.len();
                 let mut result = s_len.to_le_bytes().to_vec();
-                let mut s_bytes = s.clone().into_bytes();
-                let padding_len: usize = 128 - s_bytes.len();
-                let pad = vec![0; padding_len];
-                s_bytes.extend(&pad);
-                result.extend(s_bytes);
+                result.extend_from_slice(s.as_bytes());
                 result
             }
         }
     }
 
+    /// Returns the value of an integer field. Error if the field is a string.
+    pub fn as_int(&self) -> Result<i32, CrustyError> {
+        match self {
+            Field::IntField(i) => Ok(*i),
+            _ => Err(CrustyError::ExecutionError(format!(
+                "Expected an integer, found {}",
+                self
+            ))),
+        }
+    }
+
+    /// Returns the value of a string field. Error if the field is an integer.
+    pub fn as_string(&self) -> Result<&str, CrustyError> {
+        match self {
+            Field::StringField(s) => Ok(s),
+            _ => Err(CrustyError::ExecutionError(format!(
+                "Expected a string, found {}",
+                self
+            ))),
+        }
+    }
+
+    /// Returns whether the field is NULL.
+    pub fn is_null(&self) -> bool {
+        *self == Field::Null
+    }
+
+    /// Compares two fields of the same type. Integers are ordered numerically and strings
+    /// lexicographically; an integer and a string are not comparable, and neither is NULL.
+    ///
+    /// Unlike `Ord`, which orders every integer before every string, this is an error for fields
+    /// of different types.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Field to compare to.
+    pub fn compare(&self, other: &Field) -> Result<Ordering, CrustyError> {
+        match (self, other) {
+            (Field::IntField(a), Field::IntField(b)) => Ok(a.cmp(b)),
+            (Field::StringField(a), Field::StringField(b)) => Ok(a.cmp(b)),
+            _ => {
+                let describe = |f: &Field| match f {
+                    Field::Null => String::from("NULL"),
+                    Field::IntField(i) => format!("the integer {}", i),
+                    Field::StringField(s) => format!("the string '{}'", s),
+                };
+                Err(CrustyError::ExecutionError(format!(
+                    "Cannot compare {} to {}",
+                    describe(self),
+                    describe(other)
+                )))
+            }
+        }
+    }
+
     /// Unwraps integer fields.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field is not an integer; `as_int` returns an error instead.
     pub fn unwrap_int_field(&self) -> i32 {
         match self {
             Field::IntField(i) => *i,
@@ -299,6 +562,10 @@ impl Field {
     }
 
     /// Unwraps string fields.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field is not a string; `as_string` returns an error instead.
     pub fn unwrap_string_field(&self) -> &str {
         match self {
             Field::StringField(s) => &s,
@@ -310,6 +577,7 @@ impl Field {
 impl fmt::Display for Field {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Field::Null => write!(f, "NULL"),
             Field::IntField(x) => write!(f, "{}", x),
             Field::StringField(x) => write!(f, "{}", x),
         }
@@ -375,6 +643,64 @@ impl Tuple {
         self.field_vals.get(i)
     }
 
+    /// Get the field at index. Error if the tuple has fewer fields.
+    ///
+    /// # Arguments
+    ///
+    /// * `i` - Index of the field.
+    pub fn field(&self, i: usize) -> Result<&Field, CrustyError> {
+        self.field_vals.get(i).ok_or_else(|| {
+            CrustyError::ExecutionError(format!(
+                "Tuple has {} fields, field {} requested",
+                self.size(),
+                i
+            ))
+        })
+    }
+
+    /// Get the field of a column. Error if the schema has no such column or the tuple does not
+    /// match the schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the column.
+    /// * `schema` - Schema of the tuple.
+    pub fn get_by_name(&self, name: &str, schema: &TableSchema) -> Result<&Field, CrustyError> {
+        match schema.get_field_index(name) {
+            Some(i) => self.field(*i),
+            None => Err(CrustyError::ExecutionError(format!(
+                "No column named {}",
+                name
+            ))),
+        }
+    }
+
+    /// Get the value of an integer column. Error if the column does not exist or is not an
+    /// integer.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the column.
+    /// * `schema` - Schema of the tuple.
+    pub fn get_int(&self, name: &str, schema: &TableSchema) -> Result<i32, CrustyError> {
+        self.get_by_name(name, schema)?
+            .as_int()
+            .map_err(|_| CrustyError::ExecutionError(format!("Column {} is not an integer", name)))
+    }
+
+    /// Get the value of a string column. Error if the column does not exist or is not a
+    /// string.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the column.
+    /// * `schema` - Schema of the tuple.
+    pub fn get_string(&self, name: &str, schema: &TableSchema) -> Result<&str, CrustyError> {
+        self.get_by_name(name, schema)?
+            .as_string()
+            .map_err(|_| CrustyError::ExecutionError(format!("Column {} is not a string", name)))
+    }
+
     /// Update the index at field.
     ///
     /// # Arguments
@@ -422,6 +748,29 @@ impl Tuple {
     pub fn from_bytes(bytes: &Vec<u8>) -> Self {
         serde_cbor::from_slice(&bytes).unwrap()
     }
+
+    /// Encodes the tuple as a stored row, in the current row format.
+    ///
+    /// Fields are written by position using the schema, so no column names or type tags are
+    /// stored: ints take 4 bytes and strings are length-prefixed. Rows are much smaller than
+    /// those of `get_bytes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the table the tuple belongs to.
+    pub fn to_bytes_with_schema(&self, schema: &TableSchema) -> Result<Vec<u8>, CrustyError> {
+        row_format::RowFormat::CURRENT.encode(self, schema)
+    }
+
+    /// Decodes a stored row, in any row format.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Encoded row.
+    /// * `schema` - Schema of the table the row belongs to.
+    pub fn from_bytes_with_schema(bytes: &[u8], schema: &TableSchema) -> Result<Self, CrustyError> {
+        row_format::RowFormat::decode(bytes, schema)
+    }
 }
 
 impl fmt::Display for Tuple {
@@ -429,6 +778,7 @@ impl fmt::Display for Tuple {
         let mut res = String::new();
         for field in &self.field_vals {
             let val = match field {
+                Field::Null => String::from("NULL"),
                 Field::IntField(i) => i.to_string(),
                 Field::StringField(s) => s.to_string(),
             };
@@ -470,6 +820,19 @@ pub fn get_attr(dtype: &ast::DataType) -> Result<DataType, CrustyError> {
     }
 }
 
+/// Retrieve the most characters of a `VARCHAR(n)` from the command parser object, None for
+/// other dtypes and `VARCHAR` without a length.
+///
+/// # Argument
+///
+/// * `dtype` - Name object from the command parser.
+pub fn get_max_len(dtype: &ast::DataType) -> Option<usize> {
+    match dtype {
+        ast::DataType::Varchar(Some(n)) => Some(*n as usize),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod libtests {
     use super::*;
@@ -482,4 +845,102 @@ mod libtests {
         let check_tuple: Tuple = Tuple::from_bytes(&tuple_bytes);
         assert_eq!(tuple, check_tuple);
     }
+
+    #[test]
+    fn test_tuple_bytes_with_schema() {
+        let schema =
+            TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String]);
+        let tuple = Tuple::new(vec![
+            Field::IntField(-3),
+            Field::StringField(String::from("widget")),
+        ]);
+        let bytes = tuple.to_bytes_with_schema(&schema).unwrap();
+        assert!(bytes.len() < tuple.get_bytes().len());
+        assert_eq!(
+            tuple,
+            Tuple::from_bytes_with_schema(&bytes, &schema).unwrap()
+        );
+        // Rows written before were CBOR encoded, and are still read.
+        assert_eq!(
+            tuple,
+            Tuple::from_bytes_with_schema(&tuple.get_bytes(), &schema).unwrap()
+        );
+        assert!(Tuple::from_bytes_with_schema(&bytes[..bytes.len() - 1], &schema).is_err());
+    }
+
+    #[test]
+    fn test_string_field_bytes() {
+        let usize_len = std::mem::size_of::<usize>();
+        let short = Field::StringField(String::from("café"));
+        let bytes = short.to_bytes();
+        assert_eq!(usize_len + 5, bytes.len());
+        assert_eq!(5usize.to_le_bytes(), bytes[..usize_len]);
+        assert_eq!("café".as_bytes(), &bytes[usize_len..usize_len + 5]);
+
+        // Longer strings are not cut off, and multi-byte characters count as their bytes.
+        let long = "日本語".repeat(50);
+        let bytes = Field::StringField(long.clone()).to_bytes();
+        assert_eq!(usize_len + long.len(), bytes.len());
+        assert_eq!(long.as_bytes(), &bytes[usize_len..]);
+
+        let tuple = Tuple::new(vec![
+            Field::StringField(long),
+            Field::StringField(String::from("o'hare")),
+        ]);
+        assert_eq!(tuple, Tuple::from_bytes(&tuple.get_bytes()));
+    }
+
+    #[test]
+    fn test_schema_byte_size() {
+        let mut name = Attribute::new(String::from("name"), DataType::String);
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("id"), DataType::Int),
+            name.clone(),
+        ]);
+        assert_eq!(4 + 2 + u16::MAX as usize, schema.byte_size());
+        name.max_len = Some(10);
+        assert_eq!(2 + 40, name.get_byte_len());
+        assert_eq!(Some(10), get_max_len(&ast::DataType::Varchar(Some(10))));
+        assert_eq!(None, get_max_len(&ast::DataType::Varchar(None)));
+    }
+
+    #[test]
+    fn test_typed_accessors() {
+        let schema =
+            TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String]);
+        let tuple = Tuple::new(vec![
+            Field::IntField(7),
+            Field::StringField(String::from("widget")),
+        ]);
+        assert_eq!(7, tuple.get_int("id", &schema).unwrap());
+        assert_eq!("widget", tuple.get_string("name", &schema).unwrap());
+        assert!(tuple.get_string("id", &schema).is_err());
+        assert!(tuple.get_int("name", &schema).is_err());
+        assert!(tuple.get_int("price", &schema).is_err());
+
+        // A tuple that is shorter than its schema
+        let short = Tuple::new(vec![Field::IntField(7)]);
+        assert!(short.get_string("name", &schema).is_err());
+        assert!(short.field(1).is_err());
+    }
+
+    #[test]
+    fn test_field_compare() {
+        let int = Field::IntField;
+        let string = |s: &str| Field::StringField(s.to_string());
+        assert_eq!(Ordering::Less, int(9).compare(&int(10)).unwrap());
+        assert_eq!(Ordering::Greater, int(-1).compare(&int(-2)).unwrap());
+        assert_eq!(Ordering::Equal, string("a").compare(&string("a")).unwrap());
+        assert_eq!(
+            Ordering::Greater,
+            string("9").compare(&string("10")).unwrap()
+        );
+        assert!(int(9).compare(&string("10")).is_err());
+        assert!(string("10").compare(&int(9)).is_err());
+        assert!(Field::Null.compare(&Field::Null).is_err());
+        assert!(int(1).compare(&Field::Null).is_err());
+        // NULLs group and sort together, before every value
+        assert!(Field::Null < int(i32::MIN) && Field::Null < string(""));
+        assert_eq!(Field::Null, Field::Null);
+    }
 }
@@ -0,0 +1,398 @@
+use crate::ids::{ContainerId, PageId, Permissions, TransactionId, ValueId};
+use crate::CrustyError;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a transaction waits for a lock before it is aborted, so a transaction that holds on
+/// to its locks, e.g. one a client left open, doesn't keep others waiting forever. Transactions
+/// that wait on each other are aborted right away instead.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a lock is taken on. A storage manager locks either values or pages, whichever it stores
+/// its values in, and sticks to that for all of its containers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockTarget {
+    /// A single value.
+    Value(ValueId),
+    /// A page of a container, and so every value on it.
+    Page(ContainerId, PageId),
+}
+
+/// Holders of a lock. `ReadOnly` locks are shared, a `ReadWrite` lock is held by one
+/// transaction alone.
+#[derive(Debug, Default)]
+struct Lock {
+    readers: HashSet<TransactionId>,
+    writer: Option<TransactionId>,
+}
+
+impl Lock {
+    /// Returns true if the transaction can take the lock without waiting. A transaction that is
+    /// the only reader may upgrade to writing.
+    fn grantable(&self, tid: TransactionId, perm: Permissions) -> bool {
+        let no_other_writer = self.writer.is_none() || self.writer == Some(tid);
+        match perm {
+            Permissions::ReadOnly => no_other_writer,
+            Permissions::ReadWrite => {
+                no_other_writer && self.readers.iter().all(|reader| *reader == tid)
+            }
+        }
+    }
+
+    fn grant(&mut self, tid: TransactionId, perm: Permissions) {
+        match perm {
+            Permissions::ReadOnly if self.writer != Some(tid) => {
+                self.readers.insert(tid);
+            }
+            Permissions::ReadOnly => {}
+            Permissions::ReadWrite => {
+                self.readers.remove(&tid);
+                self.writer = Some(tid);
+            }
+        }
+    }
+
+    fn is_free(&self) -> bool {
+        self.readers.is_empty() && self.writer.is_none()
+    }
+
+    /// Other transactions whose hold on the lock keeps the transaction from taking it.
+    fn blockers(
+        &self,
+        tid: TransactionId,
+        perm: Permissions,
+    ) -> impl Iterator<Item = TransactionId> + '_ {
+        let readers = match perm {
+            Permissions::ReadOnly => None,
+            Permissions::ReadWrite => Some(self.readers.iter().copied()),
+        };
+        self.writer
+            .into_iter()
+            .chain(readers.into_iter().flatten())
+            .filter(move |holder| *holder != tid)
+    }
+}
+
+#[derive(Debug, Default)]
+struct LockTable {
+    locks: HashMap<LockTarget, Lock>,
+    /// Targets locked by each started transaction.
+    held: HashMap<TransactionId, HashSet<LockTarget>>,
+    /// Started transactions that were aborted while waiting for a lock.
+    aborted: HashSet<TransactionId>,
+    /// What the transactions that wait for a lock want to lock.
+    waiting: HashMap<TransactionId, (LockTarget, Permissions)>,
+}
+
+impl LockTable {
+    /// Returns true if the transaction waits for itself, through the transactions that hold
+    /// what it waits for, the ones that hold what those wait for, and so on.
+    ///
+    /// The waits-for graph is walked as the locks are now. An edge is only added when a
+    /// transaction starts waiting or when a lock is granted to a transaction that is not
+    /// waiting, so checking the transaction that starts to wait finds every deadlock.
+    fn deadlocked(&self, tid: TransactionId) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![tid];
+        while let Some(waiter) = stack.pop() {
+            let (target, perm) = match self.waiting.get(&waiter) {
+                Some(wanted) => *wanted,
+                None => continue,
+            };
+            if let Some(lock) = self.locks.get(&target) {
+                for holder in lock.blockers(waiter, perm) {
+                    if holder == tid {
+                        return true;
+                    }
+                    if visited.insert(holder) {
+                        stack.push(holder);
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Strict two-phase locking of the transactions that were started. Storage managers keep one,
+/// lock what they read and write on behalf of a transaction, and release all of its locks only
+/// once it commits or aborts. Transactions that were not started don't take locks, e.g. those
+/// of imports and internal maintenance.
+#[derive(Debug)]
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    /// Notified whenever locks are released.
+    released: Condvar,
+    timeout: Duration,
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self::new(LOCK_TIMEOUT)
+    }
+}
+
+impl LockManager {
+    /// Creates a lock manager without any locks.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long a transaction waits for a lock before it is aborted.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            table: Mutex::new(LockTable::default()),
+            released: Condvar::new(),
+            timeout,
+        }
+    }
+
+    /// Starts taking locks for a transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction to take locks for.
+    pub fn start(&self, tid: TransactionId) {
+        let mut table = self.table.lock().unwrap();
+        table.held.entry(tid).or_default();
+        table.aborted.remove(&tid);
+    }
+
+    /// Locks a target for a transaction, waiting for conflicting locks of other transactions
+    /// to be released. Does nothing if the transaction was not started.
+    ///
+    /// Returns `CrustyError::TransactionAbortedError` if waiting would deadlock, if the lock is
+    /// not released in time, or if the transaction was aborted before. It keeps the locks it
+    /// has, which are released once the abort of the transaction is done. Of the transactions
+    /// in a deadlock, the one whose wait closes the cycle is aborted.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction to lock for.
+    /// * `target` - Value or page to lock.
+    /// * `perm` - `ReadOnly` for a shared lock, `ReadWrite` for an exclusive one.
+    pub fn lock(
+        &self,
+        tid: TransactionId,
+        target: LockTarget,
+        perm: Permissions,
+    ) -> Result<(), CrustyError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut table = self.table.lock().unwrap();
+        loop {
+            if !table.held.contains_key(&tid) {
+                return Ok(());
+            }
+            if table.aborted.contains(&tid) {
+                return Err(CrustyError::TransactionAbortedError);
+            }
+            if Self::try_grant(&mut table, tid, target, perm) {
+                table.waiting.remove(&tid);
+                return Ok(());
+            }
+            table.waiting.insert(tid, (target, perm));
+            let now = Instant::now();
+            if now >= deadline || table.deadlocked(tid) {
+                table.waiting.remove(&tid);
+                table.aborted.insert(tid);
+                return Err(CrustyError::TransactionAbortedError);
+            }
+            table = self.released.wait_timeout(table, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Locks a target for a transaction if that can be done without waiting. Returns false if
+    /// another transaction holds a conflicting lock or the transaction was aborted, true if it
+    /// has the lock now or was not started.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction to lock for.
+    /// * `target` - Value or page to lock.
+    /// * `perm` - `ReadOnly` for a shared lock, `ReadWrite` for an exclusive one.
+    pub fn try_lock(&self, tid: TransactionId, target: LockTarget, perm: Permissions) -> bool {
+        let mut table = self.table.lock().unwrap();
+        if !table.held.contains_key(&tid) {
+            return true;
+        }
+        !table.aborted.contains(&tid) && Self::try_grant(&mut table, tid, target, perm)
+    }
+
+    fn try_grant(
+        table: &mut LockTable,
+        tid: TransactionId,
+        target: LockTarget,
+        perm: Permissions,
+    ) -> bool {
+        let lock = table.locks.entry(target).or_default();
+        if !lock.grantable(tid, perm) {
+            return false;
+        }
+        lock.grant(tid, perm);
+        table.held.entry(tid).or_default().insert(target);
+        true
+    }
+
+    /// Returns `CrustyError::TransactionAbortedError` if the transaction was aborted while
+    /// waiting for a lock and is not finished yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction to check.
+    pub fn check(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        if self.table.lock().unwrap().aborted.contains(&tid) {
+            Err(CrustyError::TransactionAbortedError)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Releases every lock of a transaction that committed or aborted, and stops taking locks
+    /// for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction that finished.
+    pub fn release(&self, tid: TransactionId) {
+        let mut table = self.table.lock().unwrap();
+        table.aborted.remove(&tid);
+        table.waiting.remove(&tid);
+        let targets = match table.held.remove(&tid) {
+            Some(targets) => targets,
+            None => return,
+        };
+        for target in targets {
+            if let Some(lock) = table.locks.get_mut(&target) {
+                lock.readers.remove(&tid);
+                if lock.writer == Some(tid) {
+                    lock.writer = None;
+                }
+                if lock.is_free() {
+                    table.locks.remove(&target);
+                }
+            }
+        }
+        self.released.notify_all();
+    }
+
+    /// Drops the locks of every transaction and stops taking locks for them, e.g. once the
+    /// storage manager dropped all of its containers. Transactions that wait for a lock stop
+    /// waiting.
+    pub fn clear(&self) {
+        let mut table = self.table.lock().unwrap();
+        *table = LockTable::default();
+        self.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    const PAGE: LockTarget = LockTarget::Page(0, 0);
+
+    #[test]
+    fn test_shared_and_exclusive() {
+        let locks = LockManager::new(Duration::from_millis(20));
+        let (t1, t2, t3) = (
+            TransactionId::new(),
+            TransactionId::new(),
+            TransactionId::new(),
+        );
+        locks.start(t1);
+        locks.start(t2);
+        locks.lock(t1, PAGE, Permissions::ReadOnly).unwrap();
+        locks.lock(t2, PAGE, Permissions::ReadOnly).unwrap();
+        // an upgrade waits for the other reader
+        assert!(!locks.try_lock(t1, PAGE, Permissions::ReadWrite));
+        // transactions that were not started don't lock
+        locks.lock(t3, PAGE, Permissions::ReadWrite).unwrap();
+
+        locks.release(t2);
+        locks.lock(t1, PAGE, Permissions::ReadWrite).unwrap();
+        locks.lock(t1, PAGE, Permissions::ReadOnly).unwrap();
+        locks.start(t2);
+        assert!(!locks.try_lock(t2, PAGE, Permissions::ReadOnly));
+        assert!(locks.try_lock(t2, LockTarget::Page(0, 1), Permissions::ReadWrite));
+
+        // a transaction that waits too long is aborted until it finishes
+        assert_eq!(
+            Err(CrustyError::TransactionAbortedError),
+            locks.lock(t2, PAGE, Permissions::ReadOnly)
+        );
+        assert_eq!(Err(CrustyError::TransactionAbortedError), locks.check(t2));
+        assert!(!locks.try_lock(t2, LockTarget::Page(0, 2), Permissions::ReadOnly));
+        locks.release(t2);
+        assert_eq!(Ok(()), locks.check(t2));
+        locks.release(t1);
+        assert!(locks.table.lock().unwrap().locks.is_empty());
+    }
+
+    #[test]
+    fn test_wait_for_release() {
+        let locks = Arc::new(LockManager::new(Duration::from_secs(10)));
+        let (t1, t2) = (TransactionId::new(), TransactionId::new());
+        locks.start(t1);
+        locks.start(t2);
+        let value = LockTarget::Value(ValueId::new(1));
+        locks.lock(t1, value, Permissions::ReadWrite).unwrap();
+        let waiter = {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || locks.lock(t2, value, Permissions::ReadOnly))
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert!(!waiter.is_finished());
+        locks.release(t1);
+        assert_eq!(Ok(()), waiter.join().unwrap());
+        locks.start(t1);
+        assert!(!locks.try_lock(t1, value, Permissions::ReadWrite));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let locks = Arc::new(LockManager::new(Duration::from_secs(60)));
+        let tids: Vec<TransactionId> = (0..5).map(|_| TransactionId::new()).collect();
+        let (a, b, c) = (
+            LockTarget::Page(0, 1),
+            LockTarget::Page(0, 2),
+            LockTarget::Page(0, 3),
+        );
+        for tid in &tids {
+            locks.start(*tid);
+        }
+        let wait = |tid, target| {
+            let locks = Arc::clone(&locks);
+            let waiter = thread::spawn(move || locks.lock(tid, target, Permissions::ReadWrite));
+            thread::sleep(Duration::from_millis(20));
+            assert!(!waiter.is_finished());
+            waiter
+        };
+
+        // the first transaction waits for both readers of b, each of which closes a cycle
+        locks.lock(tids[0], a, Permissions::ReadWrite).unwrap();
+        locks.lock(tids[1], b, Permissions::ReadOnly).unwrap();
+        locks.lock(tids[2], b, Permissions::ReadOnly).unwrap();
+        let first = wait(tids[0], b);
+        for tid in &tids[1..3] {
+            assert_eq!(
+                Err(CrustyError::TransactionAbortedError),
+                locks.lock(*tid, a, Permissions::ReadOnly)
+            );
+            locks.release(*tid);
+        }
+        assert_eq!(Ok(()), first.join().unwrap());
+
+        // so do two readers that both upgrade
+        locks.lock(tids[3], c, Permissions::ReadOnly).unwrap();
+        locks.lock(tids[4], c, Permissions::ReadOnly).unwrap();
+        let upgrade = wait(tids[3], c);
+        assert_eq!(
+            Err(CrustyError::TransactionAbortedError),
+            locks.lock(tids[4], c, Permissions::ReadWrite)
+        );
+        locks.release(tids[4]);
+        assert_eq!(Ok(()), upgrade.join().unwrap());
+    }
+}
@@ -0,0 +1,650 @@
+use super::*;
+use crate::statistics::{ColumnStats, Statistics};
+use crate::Field;
+
+/// Selectivity assumed for an equality predicate without column statistics.
+const DEFAULT_EQ_SELECTIVITY: f64 = 0.1;
+/// Selectivity assumed for a range predicate without usable column statistics.
+const DEFAULT_RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+impl LogicalPlan {
+    /// Renders the plan as an indented operator tree, root first.
+    ///
+    /// When statistics are given, every operator is annotated with its estimated output rows,
+    /// filters and joins with their estimated selectivity, and scans with the table cardinality
+    /// the estimates start from. Estimates that need missing statistics are shown as `?`.
+    ///
+    /// # Arguments
+    ///
+    /// * `stats` - Statistics of the tables in the plan, if any were collected.
+    pub fn explain(&self, stats: Option<&Statistics>) -> String {
+        let mut out = String::new();
+        if let Some(root) = self.root {
+            self.explain_node(root, 0, stats, &mut out);
+        }
+        out
+    }
+
+    /// Appends the description of a node and its children, and returns the node's row estimate.
+    fn explain_node(
+        &self,
+        node: OpIndex,
+        depth: usize,
+        stats: Option<&Statistics>,
+        out: &mut String,
+    ) -> Option<f64> {
+        // Children are rendered into their own buffer since their estimates are needed first.
+        let mut children_out = String::new();
+        let children: Vec<Option<f64>> = self
+            .edges(node)
+            .map(|child| self.explain_node(child, depth + 1, stats, &mut children_out))
+            .collect();
+        let input = children.first().copied().flatten();
+        let op = self.get_operator(node).unwrap();
+        let (desc, rows, selectivity) = match op {
+            LogicalOp::Scan(ScanNode { alias }) => {
+                let rows = stats
+                    .and_then(|s| s.table(alias))
+                    .map(|t| t.cardinality as f64);
+                (format!("Scan {}", alias), rows, None)
+            }
+            LogicalOp::Project(ProjectNode { identifiers }) => {
+                let desc = match identifiers {
+                    ProjectIdentifiers::Wildcard => String::from("Project *"),
+                    ProjectIdentifiers::List(ids) => format!("Project [{}]", ident_list(ids)),
+                };
+                (desc, input, None)
+            }
+            LogicalOp::Aggregate(AggregateNode { fields, group_by }) => {
+                let mut desc = format!("Aggregate [{}]", ident_list(fields));
+                if !group_by.is_empty() {
+                    desc.push_str(&format!(" group by [{}]", ident_list(group_by)));
+                }
+                let rows = if group_by.is_empty() {
+                    Some(1.0)
+                } else {
+                    let groups = group_by.iter().try_fold(1.0, |acc, id| {
+                        column_stats(stats, id).map(|c| acc * c.distinct_count as f64)
+                    });
+                    match (groups, input) {
+                        (Some(g), Some(i)) => Some(g.min(i)),
+                        (_, i) => i,
+                    }
+                };
+                (desc, rows, None)
+            }
+            LogicalOp::Join(JoinNode {
+                left,
+                right,
+                op,
+                join_type,
+                ..
+            }) => {
+                let name = match join_type {
+                    JoinType::Inner => "Join",
+                    JoinType::LeftOuter => "Left join",
+                    JoinType::RightOuter => "Right join",
+                    JoinType::FullOuter => "Full join",
+                };
+                let desc = format!(
+                    "{} {} {} {}",
+                    name,
+                    ident_name(left),
+                    op_str(*op),
+                    ident_name(right)
+                );
+                let sel = join_selectivity(stats, left, *op, right);
+                let rows = match (children.first(), children.get(1), sel) {
+                    (Some(Some(l)), Some(Some(r)), Some(s)) => {
+                        // every tuple of a kept side is returned at least once
+                        let mut rows = l * r * s;
+                        if join_type.keeps_left() {
+                            rows = rows.max(*l);
+                        }
+                        if join_type.keeps_right() {
+                            rows = rows.max(*r);
+                        }
+                        Some(rows)
+                    }
+                    _ => None,
+                };
+                (desc, rows, Some(sel))
+            }
+            LogicalOp::Filter(FilterNode { predicate, .. }) => {
+                let desc = format!("Filter {}", predicate_str(predicate));
+                let sel = predicate_selectivity(stats, predicate);
+                (desc, input.map(|i| i * sel), Some(Some(sel)))
+            }
+            LogicalOp::Sort(SortNode { fields }) => {
+                let keys = fields
+                    .iter()
+                    .map(|f| {
+                        let name = ident_name(&f.field);
+                        if f.ascending {
+                            name
+                        } else {
+                            format!("{} desc", name)
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                (format!("Sort [{}]", keys.join(", ")), input, None)
+            }
+            LogicalOp::Limit(LimitNode { limit, offset }) => {
+                let mut desc = match limit {
+                    Some(limit) => format!("Limit {}", limit),
+                    None => String::from("Limit all"),
+                };
+                if *offset > 0 {
+                    desc.push_str(&format!(" offset {}", offset));
+                }
+                let rows = input.map(|i| {
+                    let rows = (i - *offset as f64).max(0.0);
+                    limit.map_or(rows, |l| rows.min(l as f64))
+                });
+                (desc, rows, None)
+            }
+            LogicalOp::Distinct => (String::from("Distinct"), input, None),
+            LogicalOp::CrossProduct => {
+                let rows = match (children.first(), children.get(1)) {
+                    (Some(Some(l)), Some(Some(r))) => Some(l * r),
+                    _ => None,
+                };
+                (String::from("CrossProduct"), rows, None)
+            }
+            LogicalOp::SetOp(node) => {
+                // upper bounds, duplicates are not estimated
+                let rows = match (node.op, children.first(), children.get(1)) {
+                    (SetOperator::Union, Some(Some(l)), Some(Some(r))) => Some(l + r),
+                    (SetOperator::Intersect, Some(Some(l)), Some(Some(r))) => Some(l.min(*r)),
+                    (SetOperator::Except, _, _) => input,
+                    _ => None,
+                };
+                (node.to_string(), rows, None)
+            }
+        };
+
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(&desc);
+        if stats.is_some() {
+            let rows_str = rows.map_or(String::from("?"), |r| format!("{:.0}", r));
+            match (op, selectivity) {
+                (LogicalOp::Scan(_), _) => out.push_str(&format!("  (rows: {})", rows_str)),
+                (_, Some(sel)) => {
+                    let sel_str = sel.map_or(String::from("?"), |s| format!("{:.4}", s));
+                    out.push_str(&format!(
+                        "  (est. rows: {}, selectivity: {})",
+                        rows_str, sel_str
+                    ))
+                }
+                (_, None) => out.push_str(&format!("  (est. rows: {})", rows_str)),
+            }
+        }
+        out.push('\n');
+        out.push_str(&children_out);
+        rows
+    }
+}
+
+/// Looks up the statistics of the column a field identifier refers to.
+fn column_stats<'a>(
+    stats: Option<&'a Statistics>,
+    id: &FieldIdentifier,
+) -> Option<&'a ColumnStats> {
+    stats?.table(id.table())?.column(id.column())
+}
+
+/// Estimates the fraction of pairs of rows that satisfy a join predicate, None if it is an
+/// equality of columns without statistics.
+///
+/// # Arguments
+///
+/// * `stats` - Statistics of the joined tables.
+/// * `left` - Column of the left side of the comparison.
+/// * `op` - Comparison of the columns.
+/// * `right` - Column of the right side of the comparison.
+pub fn join_selectivity(
+    stats: Option<&Statistics>,
+    left: &FieldIdentifier,
+    op: PredicateOp,
+    right: &FieldIdentifier,
+) -> Option<f64> {
+    match op {
+        PredicateOp::Equals => match (column_stats(stats, left), column_stats(stats, right)) {
+            (Some(l), Some(r)) => Some(1.0 / l.distinct_count.max(r.distinct_count).max(1) as f64),
+            _ => None,
+        },
+        PredicateOp::All => Some(1.0),
+        _ => Some(DEFAULT_RANGE_SELECTIVITY),
+    }
+}
+
+/// Estimates the fraction of rows that pass a predicate, taking its comparisons as independent.
+///
+/// # Arguments
+///
+/// * `stats` - Statistics of the tables the predicate refers to.
+/// * `pred` - Predicate of a filter.
+pub fn predicate_selectivity(stats: Option<&Statistics>, pred: &PredicateExpr) -> f64 {
+    match pred {
+        PredicateExpr::Compare(p) => filter_selectivity(stats, p),
+        PredicateExpr::And(preds) => preds
+            .iter()
+            .map(|p| predicate_selectivity(stats, p))
+            .product(),
+        PredicateExpr::Or(preds) => {
+            1.0 - preds
+                .iter()
+                .map(|p| 1.0 - predicate_selectivity(stats, p))
+                .product::<f64>()
+        }
+    }
+}
+
+/// Estimates the fraction of rows that pass a filter comparison.
+fn filter_selectivity(stats: Option<&Statistics>, pred: &PredicateNode) -> f64 {
+    // normalize to `ident op literal` where possible
+    let (ident, op, literal) = match (&pred.left, &pred.right) {
+        (PredExpr::Ident(i), PredExpr::Literal(f)) => (i, pred.op, Some(f)),
+        (PredExpr::Literal(f), PredExpr::Ident(i)) => (i, pred.op.flip(), Some(f)),
+        (PredExpr::Ident(i), PredExpr::Ident(_)) => (i, pred.op, None),
+        (PredExpr::Literal(l), PredExpr::Literal(r)) => {
+            return if pred.op.compare(l, r).unwrap_or(false) {
+                1.0
+            } else {
+                0.0
+            };
+        }
+    };
+    let col = column_stats(stats, ident);
+    let eq = col.map_or(DEFAULT_EQ_SELECTIVITY, |c| {
+        1.0 / c.distinct_count.max(1) as f64
+    });
+    match op {
+        PredicateOp::All => 1.0,
+        PredicateOp::Equals => eq,
+        PredicateOp::NotEq => 1.0 - eq,
+        PredicateOp::Like => DEFAULT_RANGE_SELECTIVITY,
+        PredicateOp::NotLike => 1.0 - DEFAULT_RANGE_SELECTIVITY,
+        PredicateOp::IsNull => DEFAULT_EQ_SELECTIVITY,
+        PredicateOp::IsNotNull => 1.0 - DEFAULT_EQ_SELECTIVITY,
+        PredicateOp::LessThan
+        | PredicateOp::LessThanOrEq
+        | PredicateOp::GreaterThan
+        | PredicateOp::GreaterThanOrEq => match (col, literal) {
+            (
+                Some(ColumnStats {
+                    min: Some(Field::IntField(min)),
+                    max: Some(Field::IntField(max)),
+                    ..
+                }),
+                Some(Field::IntField(v)),
+            ) => {
+                if max <= min {
+                    let (min, v) = (Field::IntField(*min), Field::IntField(*v));
+                    return if op.compare(&min, &v).unwrap_or(false) {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                }
+                // fraction of the [min, max] range below the literal
+                let below =
+                    ((*v as f64 - *min as f64) / (*max as f64 - *min as f64)).clamp(0.0, 1.0);
+                match op {
+                    PredicateOp::LessThan | PredicateOp::LessThanOrEq => below,
+                    _ => 1.0 - below,
+                }
+            }
+            _ => DEFAULT_RANGE_SELECTIVITY,
+        },
+    }
+}
+
+/// Text of a predicate, with parentheses around disjunctions inside conjunctions.
+fn predicate_str(pred: &PredicateExpr) -> String {
+    match pred {
+        PredicateExpr::Compare(p)
+            if p.op == PredicateOp::IsNull || p.op == PredicateOp::IsNotNull =>
+        {
+            format!("{} {}", expr_str(&p.left), op_str(p.op))
+        }
+        PredicateExpr::Compare(p) => format!(
+            "{} {} {}",
+            expr_str(&p.left),
+            op_str(p.op),
+            expr_str(&p.right)
+        ),
+        PredicateExpr::And(preds) => preds
+            .iter()
+            .map(|p| match p {
+                PredicateExpr::Or(_) => format!("({})", predicate_str(p)),
+                _ => predicate_str(p),
+            })
+            .collect::<Vec<_>>()
+            .join(" AND "),
+        PredicateExpr::Or(preds) => preds
+            .iter()
+            .map(predicate_str)
+            .collect::<Vec<_>>()
+            .join(" OR "),
+    }
+}
+
+/// Comma separated names of field identifiers.
+fn ident_list(ids: &[FieldIdentifier]) -> String {
+    ids.iter().map(ident_name).collect::<Vec<_>>().join(", ")
+}
+
+/// Display name of a field identifier, including its aggregate and alias.
+fn ident_name(id: &FieldIdentifier) -> String {
+    let mut name = match id.agg_op() {
+        Some(op) => format!("{}({})", op, id.column()),
+        None => id.column().to_string(),
+    };
+    if let Some(alias) = id.alias() {
+        if alias != id.column() {
+            name.push_str(&format!(" as {}", alias));
+        }
+    }
+    name
+}
+
+/// Display form of a predicate expression.
+fn expr_str(expr: &PredExpr) -> String {
+    match expr {
+        PredExpr::Literal(Field::StringField(s)) => format!("'{}'", s.replace('\'', "''")),
+        PredExpr::Literal(f) => f.to_string(),
+        PredExpr::Ident(i) => i.column().to_string(),
+    }
+}
+
+/// SQL form of a predicate operator.
+fn op_str(op: PredicateOp) -> &'static str {
+    match op {
+        PredicateOp::Equals => "=",
+        PredicateOp::GreaterThan => ">",
+        PredicateOp::LessThan => "<",
+        PredicateOp::LessThanOrEq => "<=",
+        PredicateOp::GreaterThanOrEq => ">=",
+        PredicateOp::NotEq => "<>",
+        PredicateOp::Like => "LIKE",
+        PredicateOp::NotLike => "NOT LIKE",
+        PredicateOp::IsNull => "IS NULL",
+        PredicateOp::IsNotNull => "IS NOT NULL",
+        PredicateOp::All => "ALL",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::statistics::TableStats;
+
+    fn get_plan() -> LogicalPlan {
+        let mut lp = LogicalPlan::new();
+        let scan = lp.add_node(LogicalOp::Scan(ScanNode {
+            alias: String::from("t"),
+        }));
+        let filter = lp.add_node(LogicalOp::Filter(FilterNode {
+            table: String::from("t"),
+            predicate: PredicateExpr::Compare(PredicateNode {
+                left: PredExpr::Ident(FieldIdentifier::new("t", "t.a")),
+                op: PredicateOp::Equals,
+                right: PredExpr::Literal(Field::IntField(5)),
+            }),
+        }));
+        let project = lp.add_node(LogicalOp::Project(ProjectNode {
+            identifiers: ProjectIdentifiers::Wildcard,
+        }));
+        lp.add_edge(filter, scan);
+        lp.add_edge(project, filter);
+        lp
+    }
+
+    #[test]
+    fn test_explain_without_stats() {
+        assert_eq!(
+            "Project *\n  Filter t.a = 5\n    Scan t\n",
+            get_plan().explain(None)
+        );
+    }
+
+    #[test]
+    fn test_explain_sort() {
+        let mut lp = get_plan();
+        let project = lp.root().unwrap();
+        let sort = lp.add_node(LogicalOp::Sort(SortNode {
+            fields: vec![
+                SortField {
+                    field: FieldIdentifier::new("t", "t.a"),
+                    ascending: true,
+                },
+                SortField {
+                    field: FieldIdentifier::new_column_alias("t", "t.b", "b"),
+                    ascending: false,
+                },
+            ],
+        }));
+        lp.add_edge(sort, project);
+        assert_eq!(
+            "Sort [t.a, t.b as b desc]\n  Project *\n    Filter t.a = 5\n      Scan t\n",
+            lp.explain(None)
+        );
+    }
+
+    #[test]
+    fn test_explain_limit() {
+        let limit_plan = |limit, offset| {
+            let mut lp = get_plan();
+            let project = lp.root().unwrap();
+            let node = lp.add_node(LogicalOp::Limit(LimitNode { limit, offset }));
+            lp.add_edge(node, project);
+            lp
+        };
+        let mut stats = Statistics::new();
+        stats.set_table("t", TableStats::new(1000));
+        // the filter is estimated to pass 100 rows
+        let first_line =
+            |lp: LogicalPlan| lp.explain(Some(&stats)).lines().next().unwrap().to_string();
+        assert_eq!(
+            "Limit 10 offset 15  (est. rows: 10)",
+            first_line(limit_plan(Some(10), 15))
+        );
+        assert_eq!(
+            "Limit all offset 95  (est. rows: 5)",
+            first_line(limit_plan(None, 95))
+        );
+        assert_eq!(
+            "Limit 5  (est. rows: 5)",
+            first_line(limit_plan(Some(5), 0))
+        );
+    }
+
+    #[test]
+    fn test_explain_distinct() {
+        let mut lp = get_plan();
+        let project = lp.root().unwrap();
+        let distinct = lp.add_node(LogicalOp::Distinct);
+        lp.add_edge(distinct, project);
+        let mut stats = Statistics::new();
+        stats.set_table("t", TableStats::new(1000));
+        assert_eq!(
+            "Distinct  (est. rows: 100)",
+            lp.explain(Some(&stats)).lines().next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_explain_cross_product() {
+        let mut lp = LogicalPlan::new();
+        let scan = |lp: &mut LogicalPlan, alias: &str| {
+            lp.add_node(LogicalOp::Scan(ScanNode {
+                alias: String::from(alias),
+            }))
+        };
+        let t = scan(&mut lp, "t");
+        let u = scan(&mut lp, "u");
+        let cross = lp.add_node(LogicalOp::CrossProduct);
+        lp.add_edge(cross, u);
+        lp.add_edge(cross, t);
+        let mut stats = Statistics::new();
+        stats.set_table("t", TableStats::new(1000));
+        stats.set_table("u", TableStats::new(20));
+        assert_eq!(
+            "CrossProduct  (est. rows: 20000)",
+            lp.explain(Some(&stats)).lines().next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_explain_set_op() {
+        let set_op_plan = |op, all| {
+            let mut lp = LogicalPlan::new();
+            let scan = |lp: &mut LogicalPlan, alias: &str| {
+                lp.add_node(LogicalOp::Scan(ScanNode {
+                    alias: String::from(alias),
+                }))
+            };
+            let t = scan(&mut lp, "t");
+            let u = scan(&mut lp, "u");
+            let set_op = lp.add_node(LogicalOp::SetOp(SetOpNode { op, all }));
+            lp.add_edge(set_op, u);
+            lp.add_edge(set_op, t);
+            lp
+        };
+        let mut stats = Statistics::new();
+        stats.set_table("t", TableStats::new(1000));
+        stats.set_table("u", TableStats::new(20));
+        let first_line =
+            |lp: LogicalPlan| lp.explain(Some(&stats)).lines().next().unwrap().to_string();
+        assert_eq!(
+            "Union all  (est. rows: 1020)",
+            first_line(set_op_plan(SetOperator::Union, true))
+        );
+        assert_eq!(
+            "Intersect  (est. rows: 20)",
+            first_line(set_op_plan(SetOperator::Intersect, false))
+        );
+        assert_eq!(
+            "Except all  (est. rows: 1000)",
+            first_line(set_op_plan(SetOperator::Except, true))
+        );
+        assert_eq!(
+            "Union\n  Scan t\n  Scan u\n",
+            set_op_plan(SetOperator::Union, false).explain(None)
+        );
+    }
+
+    #[test]
+    fn test_explain_string_literal() {
+        let mut lp = LogicalPlan::new();
+        let scan = lp.add_node(LogicalOp::Scan(ScanNode {
+            alias: String::from("t"),
+        }));
+        let filter = lp.add_node(LogicalOp::Filter(FilterNode {
+            table: String::from("t"),
+            predicate: PredicateExpr::Compare(PredicateNode {
+                left: PredExpr::Ident(FieldIdentifier::new("t", "t.b")),
+                op: PredicateOp::Equals,
+                right: PredExpr::Literal(Field::StringField(String::from("o'hare café"))),
+            }),
+        }));
+        lp.add_edge(filter, scan);
+        assert_eq!("Filter t.b = 'o''hare café'\n  Scan t\n", lp.explain(None));
+    }
+
+    #[test]
+    fn test_explain_with_stats() {
+        let mut stats = Statistics::new();
+        let mut t = TableStats::new(1000);
+        t.columns.insert(
+            String::from("a"),
+            ColumnStats {
+                distinct_count: 50,
+                min: Some(Field::IntField(0)),
+                max: Some(Field::IntField(100)),
+            },
+        );
+        stats.set_table("t", t);
+        assert_eq!(
+            "Project *  (est. rows: 20)\n  Filter t.a = 5  (est. rows: 20, selectivity: 0.0200)\n    Scan t  (rows: 1000)\n",
+            get_plan().explain(Some(&stats))
+        );
+    }
+
+    #[test]
+    fn test_explain_missing_table_stats() {
+        let stats = Statistics::new();
+        assert_eq!(
+            "Project *  (est. rows: ?)\n  Filter t.a = 5  (est. rows: ?, selectivity: 0.1000)\n    Scan t  (rows: ?)\n",
+            get_plan().explain(Some(&stats))
+        );
+    }
+
+    #[test]
+    fn test_range_selectivity() {
+        let mut stats = Statistics::new();
+        let mut t = TableStats::new(1000);
+        t.columns.insert(
+            String::from("a"),
+            ColumnStats {
+                distinct_count: 100,
+                min: Some(Field::IntField(0)),
+                max: Some(Field::IntField(100)),
+            },
+        );
+        stats.set_table("t", t);
+        let pred = PredicateNode {
+            left: PredExpr::Literal(Field::IntField(25)),
+            op: PredicateOp::GreaterThan,
+            right: PredExpr::Ident(FieldIdentifier::new("t", "t.a")),
+        };
+        // 25 > a  is  a < 25
+        assert!((filter_selectivity(Some(&stats), &pred) - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_explain_compound_predicate() {
+        let compare = |col: &str, op, val| {
+            PredicateExpr::Compare(PredicateNode {
+                left: PredExpr::Ident(FieldIdentifier::new("t", col)),
+                op,
+                right: PredExpr::Literal(Field::IntField(val)),
+            })
+        };
+        let mut lp = LogicalPlan::new();
+        let scan = lp.add_node(LogicalOp::Scan(ScanNode {
+            alias: String::from("t"),
+        }));
+        let filter = lp.add_node(LogicalOp::Filter(FilterNode {
+            table: String::from("t"),
+            predicate: PredicateExpr::And(vec![
+                compare("t.a", PredicateOp::GreaterThan, 1),
+                PredicateExpr::Or(vec![
+                    compare("t.b", PredicateOp::LessThan, 5),
+                    compare("t.b", PredicateOp::Equals, 7),
+                ]),
+            ]),
+        }));
+        lp.add_edge(filter, scan);
+        assert_eq!(
+            "Filter t.a > 1 AND (t.b < 5 OR t.b = 7)\n  Scan t\n",
+            lp.explain(None)
+        );
+
+        let mut stats = Statistics::new();
+        stats.set_table("t", TableStats::new(1000));
+        let sel = predicate_selectivity(Some(&stats), &compare("t.a", PredicateOp::Equals, 1));
+        let or = PredicateExpr::Or(vec![
+            compare("t.a", PredicateOp::Equals, 1),
+            compare("t.b", PredicateOp::Equals, 1),
+        ]);
+        let and = PredicateExpr::And(vec![
+            compare("t.a", PredicateOp::Equals, 1),
+            compare("t.b", PredicateOp::Equals, 1),
+        ]);
+        let or_sel = predicate_selectivity(Some(&stats), &or);
+        assert!((or_sel - (1.0 - (1.0 - sel) * (1.0 - sel))).abs() < 1e-9);
+        assert!((predicate_selectivity(Some(&stats), &and) - sel * sel).abs() < 1e-9);
+    }
+}
@@ -1,5 +1,6 @@
-use crate::Field;
+use crate::{CrustyError, DataType, Field};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Debug;
 
@@ -11,6 +12,14 @@ pub enum LogicalOp {
     Aggregate(AggregateNode),
     Join(JoinNode),
     Filter(FilterNode),
+    Sort(SortNode),
+    Limit(LimitNode),
+    /// Removes duplicate rows.
+    Distinct,
+    /// Combines every row of the left child with every row of the right child.
+    CrossProduct,
+    /// Combines the rows of the left and right children, whose columns have the same types.
+    SetOp(SetOpNode),
 }
 
 /// Scan node.
@@ -63,6 +72,33 @@ pub struct JoinNode {
     pub left_table: Option<String>,
     /// Left table.
     pub right_table: Option<String>,
+    /// Which unmatched tuples are kept.
+    pub join_type: JoinType,
+}
+
+/// Join types.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum JoinType {
+    /// Only matching pairs of tuples.
+    Inner,
+    /// Also left tuples without a match, padded on the right.
+    LeftOuter,
+    /// Also right tuples without a match, padded on the left.
+    RightOuter,
+    /// Also the unmatched tuples of both sides.
+    FullOuter,
+}
+
+impl JoinType {
+    /// Whether left tuples without a match are returned.
+    pub fn keeps_left(&self) -> bool {
+        matches!(self, JoinType::LeftOuter | JoinType::FullOuter)
+    }
+
+    /// Whether right tuples without a match are returned.
+    pub fn keeps_right(&self) -> bool {
+        matches!(self, JoinType::RightOuter | JoinType::FullOuter)
+    }
 }
 
 /// Filter node.
@@ -71,11 +107,78 @@ pub struct FilterNode {
     /// Table to filter.
     pub table: String,
     /// Predicate to filter by.
-    pub predicate: PredicateNode,
+    pub predicate: PredicateExpr,
+}
+
+/// Sort node.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SortNode {
+    /// Fields to sort on, most significant first.
+    pub fields: Vec<SortField>,
+}
+
+/// A field to sort on and its direction.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SortField {
+    /// Field to sort on.
+    pub field: FieldIdentifier,
+    /// Sort from smallest to largest.
+    pub ascending: bool,
+}
+
+/// Set operation node, the left child first.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetOpNode {
+    /// How the rows of the children are combined.
+    pub op: SetOperator,
+    /// Keep duplicate rows, rather than returning each row once.
+    pub all: bool,
+}
+
+impl fmt::Display for SetOpNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.op {
+            SetOperator::Union => "Union",
+            SetOperator::Intersect => "Intersect",
+            SetOperator::Except => "Except",
+        };
+        write!(f, "{}{}", name, if self.all { " all" } else { "" })
+    }
+}
+
+/// Set operators.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperator {
+    /// Rows of either child.
+    Union,
+    /// Rows of the left child that are also rows of the right child.
+    Intersect,
+    /// Rows of the left child that are not rows of the right child.
+    Except,
+}
+
+impl fmt::Display for SetOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op_str = match self {
+            SetOperator::Union => "union",
+            SetOperator::Intersect => "intersect",
+            SetOperator::Except => "except",
+        };
+        write!(f, "{}", op_str)
+    }
+}
+
+/// Limit node.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LimitNode {
+    /// Maximum number of rows to return, None for all of them.
+    pub limit: Option<usize>,
+    /// Number of rows to skip first.
+    pub offset: usize,
 }
 
 /// Predicate operators.
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum PredicateOp {
     Equals,
     GreaterThan,
@@ -83,6 +186,12 @@ pub enum PredicateOp {
     LessThanOrEq,
     GreaterThanOrEq,
     NotEq,
+    /// Matches a string against a pattern, `%` for any run of characters and `_` for one.
+    Like,
+    NotLike,
+    /// Whether the left field is NULL, the right one is ignored.
+    IsNull,
+    IsNotNull,
     All,
 }
 
@@ -93,16 +202,29 @@ impl PredicateOp {
     ///
     /// * `left_field` - Left field of the predicate.
     /// * `right_field` - Right field of the predicate.
-    pub fn compare<T: Ord>(&self, left_field: &T, right_field: &T) -> bool {
-        match self {
-            PredicateOp::Equals => left_field == right_field,
-            PredicateOp::GreaterThan => left_field > right_field,
-            PredicateOp::LessThan => left_field < right_field,
-            PredicateOp::LessThanOrEq => left_field <= right_field,
-            PredicateOp::GreaterThanOrEq => left_field >= right_field,
-            PredicateOp::NotEq => left_field != right_field,
-            PredicateOp::All => true,
-        }
+    ///
+    /// Errors if the fields are of different types, see `Field::compare`. A comparison with
+    /// NULL is unknown rather than true or false, and so never holds, not even `<>`.
+    pub fn compare(&self, left_field: &Field, right_field: &Field) -> Result<bool, CrustyError> {
+        let ord = match self {
+            PredicateOp::All => return Ok(true),
+            PredicateOp::IsNull => return Ok(left_field.is_null()),
+            PredicateOp::IsNotNull => return Ok(!left_field.is_null()),
+            _ if left_field.is_null() || right_field.is_null() => return Ok(false),
+            PredicateOp::Like | PredicateOp::NotLike => {
+                let matched = like(left_field.as_string()?, right_field.as_string()?);
+                return Ok(matched == (*self == PredicateOp::Like));
+            }
+            _ => left_field.compare(right_field)?,
+        };
+        Ok(match self {
+            PredicateOp::Equals => ord == Ordering::Equal,
+            PredicateOp::GreaterThan => ord == Ordering::Greater,
+            PredicateOp::LessThan => ord == Ordering::Less,
+            PredicateOp::LessThanOrEq => ord != Ordering::Greater,
+            PredicateOp::GreaterThanOrEq => ord != Ordering::Less,
+            _ => ord != Ordering::Equal,
+        })
     }
 
     /// Flip the operator.
@@ -117,6 +239,33 @@ impl PredicateOp {
     }
 }
 
+/// Whether a string matches a LIKE pattern.
+///
+/// # Arguments
+///
+/// * `s` - String to match.
+/// * `pattern` - Pattern, with `%` matching any run of characters and `_` any one character.
+pub fn like(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    // matches[i]: whether the pattern read so far matches the first i characters of s
+    let mut matches = vec![false; s.len() + 1];
+    matches[0] = true;
+    for p in pattern {
+        if p == '%' {
+            for i in 1..=s.len() {
+                matches[i] = matches[i] || matches[i - 1];
+            }
+        } else {
+            for i in (1..=s.len()).rev() {
+                matches[i] = matches[i - 1] && (p == '_' || p == s[i - 1]);
+            }
+            matches[0] = false;
+        }
+    }
+    matches[s.len()]
+}
+
 /// Predicate expression.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum PredExpr {
@@ -142,11 +291,84 @@ pub struct PredicateNode {
     pub right: PredExpr,
 }
 
+/// Predicate of a filter, comparisons combined with AND and OR.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum PredicateExpr {
+    /// A single comparison.
+    Compare(PredicateNode),
+    /// True if all of the predicates are.
+    And(Vec<PredicateExpr>),
+    /// True if any of the predicates is.
+    Or(Vec<PredicateExpr>),
+}
+
+impl PredicateExpr {
+    /// Conjunction of predicates, the predicate itself if there is only one.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicates` - Predicates that all have to be true, at least one.
+    pub fn and(mut predicates: Vec<PredicateExpr>) -> Self {
+        if predicates.len() == 1 {
+            predicates.pop().unwrap()
+        } else {
+            PredicateExpr::And(predicates)
+        }
+    }
+
+    /// Comparisons of the predicate, from left to right.
+    pub fn comparisons(&self) -> Vec<&PredicateNode> {
+        match self {
+            PredicateExpr::Compare(p) => vec![p],
+            PredicateExpr::And(preds) | PredicateExpr::Or(preds) => {
+                preds.iter().flat_map(|p| p.comparisons()).collect()
+            }
+        }
+    }
+
+    /// Replaces every comparison of the predicate.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Function returning the replacement of a comparison.
+    pub fn try_map<E, F>(self, f: &mut F) -> Result<Self, E>
+    where
+        F: FnMut(PredicateNode) -> Result<PredicateNode, E>,
+    {
+        Ok(match self {
+            PredicateExpr::Compare(p) => PredicateExpr::Compare(f(p)?),
+            PredicateExpr::And(preds) => PredicateExpr::And(
+                preds
+                    .into_iter()
+                    .map(|p| p.try_map(f))
+                    .collect::<Result<_, E>>()?,
+            ),
+            PredicateExpr::Or(preds) => PredicateExpr::Or(
+                preds
+                    .into_iter()
+                    .map(|p| p.try_map(f))
+                    .collect::<Result<_, E>>()?,
+            ),
+        })
+    }
+}
+
+impl From<PredicateNode> for PredicateExpr {
+    fn from(predicate: PredicateNode) -> Self {
+        PredicateExpr::Compare(predicate)
+    }
+}
+
 /// Aggregation operations.
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+///
+/// NULL values are skipped: COUNT counts the values that are not NULL, and the other aggregates
+/// of a group without such a value are NULL.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum AggOp {
     Avg,
     Count,
+    /// `COUNT(DISTINCT x)`, the number of distinct values that are not NULL.
+    CountDistinct,
     Max,
     Min,
     Sum,
@@ -157,6 +379,7 @@ impl fmt::Display for AggOp {
         let op_str = match self {
             AggOp::Avg => "avg",
             AggOp::Count => "count",
+            AggOp::CountDistinct => "count_distinct",
             AggOp::Max => "max",
             AggOp::Min => "min",
             AggOp::Sum => "sum",
@@ -165,6 +388,162 @@ impl fmt::Display for AggOp {
     }
 }
 
+/// Arithmetic operators of scalar expressions.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    /// Addition of INTs, or concatenation of VARCHARs.
+    Plus,
+    Minus,
+    Multiply,
+    /// Division of INTs, rounded towards zero.
+    Divide,
+    Modulus,
+}
+
+impl fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op_str = match self {
+            ArithOp::Plus => "+",
+            ArithOp::Minus => "-",
+            ArithOp::Multiply => "*",
+            ArithOp::Divide => "/",
+            ArithOp::Modulus => "%",
+        };
+        write!(f, "{}", op_str)
+    }
+}
+
+/// Built-in scalar functions, which take a single argument.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarFunction {
+    /// VARCHAR in upper case.
+    Upper,
+    /// VARCHAR in lower case.
+    Lower,
+    /// Number of characters of a VARCHAR.
+    Length,
+    /// Absolute value of an INT.
+    Abs,
+}
+
+/// Registry of the built-in scalar functions: the name each is called by, the type of its
+/// argument and the type of its result.
+const SCALAR_FUNCTIONS: [(&str, ScalarFunction, DataType, DataType); 4] = [
+    (
+        "UPPER",
+        ScalarFunction::Upper,
+        DataType::String,
+        DataType::String,
+    ),
+    (
+        "LOWER",
+        ScalarFunction::Lower,
+        DataType::String,
+        DataType::String,
+    ),
+    (
+        "LENGTH",
+        ScalarFunction::Length,
+        DataType::String,
+        DataType::Int,
+    ),
+    ("ABS", ScalarFunction::Abs, DataType::Int, DataType::Int),
+];
+
+impl ScalarFunction {
+    /// Finds the function called by a name, ignoring case, None if there is no such function.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the function.
+    pub fn lookup(name: &str) -> Option<Self> {
+        SCALAR_FUNCTIONS
+            .iter()
+            .find(|(n, ..)| n.eq_ignore_ascii_case(name))
+            .map(|(_, function, ..)| *function)
+    }
+
+    /// Entry of the function in the registry.
+    fn entry(&self) -> &'static (&'static str, ScalarFunction, DataType, DataType) {
+        SCALAR_FUNCTIONS
+            .iter()
+            .find(|(_, f, ..)| f == self)
+            .unwrap()
+    }
+
+    /// Returns the type of the argument.
+    pub fn arg_type(&self) -> DataType {
+        self.entry().2.clone()
+    }
+
+    /// Returns the type of the result.
+    pub fn return_type(&self) -> DataType {
+        self.entry().3.clone()
+    }
+}
+
+impl fmt::Display for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.entry().0)
+    }
+}
+
+/// Expression computing a value from the columns of a row.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ScalarExpr {
+    /// Value of a column.
+    Column(FieldIdentifier),
+    /// A constant.
+    Literal(Field),
+    /// Arithmetic on two INTs, or with `+` concatenation of two VARCHARs. NULL if either
+    /// operand is.
+    Binary {
+        left: Box<ScalarExpr>,
+        op: ArithOp,
+        right: Box<ScalarExpr>,
+    },
+    /// A built-in function of a value, NULL if the value is.
+    Function {
+        function: ScalarFunction,
+        arg: Box<ScalarExpr>,
+    },
+}
+
+impl ScalarExpr {
+    /// Columns the expression reads, from left to right.
+    pub fn columns(&self) -> Vec<&FieldIdentifier> {
+        match self {
+            ScalarExpr::Column(id) => vec![id],
+            ScalarExpr::Literal(_) => Vec::new(),
+            ScalarExpr::Binary { left, right, .. } => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            ScalarExpr::Function { arg, .. } => arg.columns(),
+        }
+    }
+}
+
+impl fmt::Display for ScalarExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScalarExpr::Column(id) => write!(f, "{}", id.column()),
+            ScalarExpr::Literal(Field::StringField(s)) => write!(f, "'{}'", s.replace('\'', "''")),
+            ScalarExpr::Literal(field) => write!(f, "{}", field),
+            ScalarExpr::Binary { left, op, right } => {
+                // operands that are operations themselves are parenthesized
+                let operand = |e: &ScalarExpr| match e {
+                    ScalarExpr::Binary { .. } => format!("({})", e),
+                    _ => e.to_string(),
+                };
+                write!(f, "{} {} {}", operand(left), op, operand(right))
+            }
+            ScalarExpr::Function { function, arg } => write!(f, "{}({})", function, arg),
+        }
+    }
+}
+
 /// Represents a field identifier.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FieldIdentifier {
@@ -176,6 +555,9 @@ pub struct FieldIdentifier {
     alias: Option<String>,
     /// An aggregate operation performed on column.
     op: Option<AggOp>,
+    /// Expression the field is computed with, None for a column of a table.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expr: Option<Box<ScalarExpr>>,
 }
 
 impl FieldIdentifier {
@@ -191,6 +573,7 @@ impl FieldIdentifier {
             column: column.to_string(),
             alias: None,
             op: None,
+            expr: None,
         }
     }
 
@@ -207,6 +590,24 @@ impl FieldIdentifier {
         id
     }
 
+    /// Creates a field identifier computed by an expression. Its column is the expression,
+    /// with the columns it reads named in full, and its table is that of the first of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression computing the field.
+    /// * `alias` - Name of the output field.
+    pub fn new_expr(expr: ScalarExpr, alias: &str) -> Self {
+        let table = expr
+            .columns()
+            .first()
+            .map(|c| c.table().to_string())
+            .unwrap_or_default();
+        let mut id = Self::new_column_alias(&table, &expr.to_string(), alias);
+        id.expr = Some(Box::new(expr));
+        id
+    }
+
     /// Returns the table.
     pub fn table(&self) -> &str {
         &self.table
@@ -227,6 +628,19 @@ impl FieldIdentifier {
         self.op
     }
 
+    /// Returns the expression the field is computed with, None for a column.
+    pub fn expr(&self) -> Option<&ScalarExpr> {
+        self.expr.as_deref()
+    }
+
+    /// Columns of the tables the field is read from: its own, or those its expression reads.
+    pub fn source_columns(&self) -> Vec<&str> {
+        match &self.expr {
+            Some(expr) => expr.columns().into_iter().map(|c| c.column()).collect(),
+            None => vec![self.column()],
+        }
+    }
+
     /// Set an alias for the field identifier.
     ///
     /// # Argument
@@ -252,3 +666,83 @@ impl FieldIdentifier {
         self.op = Some(op);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_like() {
+        assert!(like("abc", "abc"));
+        assert!(like("abc", "ab%"));
+        assert!(like("abc", "%"));
+        assert!(like("", "%"));
+        assert!(like("abc", "a_c"));
+        assert!(like("abcbc", "%bc"));
+        assert!(like("a%c", "a%%c"));
+        assert!(!like("abc", "ab"));
+        assert!(!like("abc", "b%"));
+        assert!(!like("ab", "a_c"));
+        assert!(!like("", "_"));
+
+        let s = |v: &str| Field::StringField(v.to_string());
+        assert!(PredicateOp::Like.compare(&s("crusty"), &s("cr%")).unwrap());
+        assert!(PredicateOp::NotLike
+            .compare(&s("crusty"), &s("db%"))
+            .unwrap());
+        assert!(PredicateOp::Like
+            .compare(&Field::IntField(1), &s("%"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let int = Field::IntField;
+        let s = |v: &str| Field::StringField(v.to_string());
+        assert!(PredicateOp::LessThan.compare(&int(9), &int(10)).unwrap());
+        assert!(PredicateOp::GreaterThanOrEq
+            .compare(&int(10), &int(10))
+            .unwrap());
+        assert!(PredicateOp::NotEq.compare(&s("a"), &s("b")).unwrap());
+        assert!(!PredicateOp::Equals.compare(&s("a"), &s("b")).unwrap());
+        assert!(PredicateOp::All.compare(&int(1), &s("b")).unwrap());
+        // mixed types are an error rather than ordered by variant
+        assert!(PredicateOp::LessThan.compare(&int(9), &s("10")).is_err());
+        assert!(PredicateOp::NotEq.compare(&s("1"), &int(1)).is_err());
+        // comparisons with NULL never hold
+        for op in &[
+            PredicateOp::Equals,
+            PredicateOp::NotEq,
+            PredicateOp::LessThan,
+        ] {
+            assert!(!op.compare(&Field::Null, &int(1)).unwrap());
+            assert!(!op.compare(&s("a"), &Field::Null).unwrap());
+            assert!(!op.compare(&Field::Null, &Field::Null).unwrap());
+        }
+        assert!(!PredicateOp::Like.compare(&Field::Null, &s("%")).unwrap());
+        assert!(PredicateOp::IsNull
+            .compare(&Field::Null, &Field::Null)
+            .unwrap());
+        assert!(!PredicateOp::IsNull.compare(&int(0), &Field::Null).unwrap());
+        assert!(PredicateOp::IsNotNull
+            .compare(&s(""), &Field::Null)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_scalar_functions() {
+        assert_eq!(Some(ScalarFunction::Upper), ScalarFunction::lookup("upper"));
+        assert_eq!(
+            Some(ScalarFunction::Length),
+            ScalarFunction::lookup("Length")
+        );
+        assert_eq!(None, ScalarFunction::lookup("sum"));
+        assert_eq!(DataType::String, ScalarFunction::Length.arg_type());
+        assert_eq!(DataType::Int, ScalarFunction::Length.return_type());
+        let call = ScalarExpr::Function {
+            function: ScalarFunction::Abs,
+            arg: Box::new(ScalarExpr::Column(FieldIdentifier::new("t", "t.a"))),
+        };
+        assert_eq!("ABS(t.a)", call.to_string());
+    }
+}
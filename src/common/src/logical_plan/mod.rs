@@ -1,8 +1,12 @@
 use crate::crusty_graph::{CrustyGraph, NodeIndex};
 use crate::CrustyError;
+pub use explain::{join_selectivity, predicate_selectivity};
 pub use logical_op::*;
 use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+mod explain;
 mod logical_op;
 use std::default::Default;
 use std::fmt;
@@ -63,6 +67,24 @@ impl LogicalPlan {
         self.dataflow.add_edge(source, target);
     }
 
+    /// Copies the nodes and edges of another plan into this one, without connecting them to
+    /// this plan's nodes or changing its root, and returns the index of the other plan's root.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Plan to copy.
+    pub fn add_plan(&mut self, other: &LogicalPlan) -> Option<OpIndex> {
+        let mut copies = HashMap::new();
+        for (index, node) in other.dataflow.node_references() {
+            copies.insert(index, self.dataflow.add_node(node.data().clone()));
+        }
+        for edge in other.dataflow.edge_references() {
+            self.dataflow
+                .add_edge(copies[&edge.source()], copies[&edge.target()]);
+        }
+        other.root.map(|root| copies[&root])
+    }
+
     /// Returns an iterator over all nodes that 'from' has an edge to.
     ///
     /// # Arguments
@@ -99,20 +121,23 @@ impl LogicalPlan {
     }
 
     /// Serializes the Logical Plan as json.
+    ///
+    /// Nodes are listed in OpIndex order and edges in the order they were added, so the same
+    /// plan always serializes to the same json and `from_json` restores the same OpIndexes.
     pub fn to_json(&self) -> serde_json::Value {
-        let mut node_map = HashMap::new();
-        let mut edge_map = HashMap::new();
-        for (i, node) in self.dataflow.node_references() {
-            node_map.insert(i, node.data());
-        }
-        for (_, edge) in self.dataflow.edge_references().enumerate() {
-            let source = edge.source();
-            let targets = edge_map.entry(source).or_insert_with(Vec::new);
-            targets.push(edge.target().to_string());
-        }
-        return json!({"nodes":node_map,
-                      "edges":edge_map,
-                      "root":self.root.map(|i| i.to_string())});
+        let nodes: Vec<&LogicalOp> = self
+            .dataflow
+            .node_references()
+            .map(|(_, node)| node.data())
+            .collect();
+        let edges: Vec<[OpIndex; 2]> = self
+            .dataflow
+            .edge_references()
+            .map(|edge| [edge.source(), edge.target()])
+            .collect();
+        json!({"nodes":nodes,
+               "edges":edges,
+               "root":self.root})
     }
 
     fn map_crusty_err<T>(
@@ -126,11 +151,49 @@ impl LogicalPlan {
     }
 
     /// De-Serializes a json representation of the Logical Plan created in to_json
+    ///
+    /// Plans serialized with node and edge maps keyed by index strings are accepted as well.
     pub fn from_json(json: &str) -> Result<Self, CrustyError> {
         let malformed_err =
             CrustyError::CrustyError(String::from("Malformatted logical plan json"));
         let v: Value =
             LogicalPlan::map_crusty_err(serde_json::from_str(json), malformed_err.clone())?;
+        if v["nodes"].is_object() {
+            return LogicalPlan::from_json_maps(&v, malformed_err);
+        }
+        let nodes: Vec<LogicalOp> = LogicalPlan::map_crusty_err(
+            serde_json::from_value(v["nodes"].clone()),
+            malformed_err.clone(),
+        )?;
+        let edges: Vec<[OpIndex; 2]> = LogicalPlan::map_crusty_err(
+            serde_json::from_value(v["edges"].clone()),
+            malformed_err.clone(),
+        )?;
+        let root: Option<OpIndex> = LogicalPlan::map_crusty_err(
+            serde_json::from_value(v["root"].clone()),
+            malformed_err.clone(),
+        )?;
+        let mut plan = LogicalPlan::new();
+        for op in nodes {
+            plan.dataflow.add_node(op);
+        }
+        for [source, target] in edges {
+            if source >= plan.node_count() || target >= plan.node_count() {
+                return Err(malformed_err);
+            }
+            plan.dataflow.add_edge(source, target);
+        }
+        match root {
+            Some(i) if i >= plan.node_count() => return Err(malformed_err),
+            _ => plan.root = root,
+        }
+        Ok(plan)
+    }
+
+    /// De-Serializes the older json layout with nodes and edges keyed by index strings.
+    ///
+    /// Nodes are added in numeric key order so OpIndexes are the same on every run.
+    fn from_json_maps(v: &Value, malformed_err: CrustyError) -> Result<Self, CrustyError> {
         let nodes: HashMap<String, LogicalOp> = LogicalPlan::map_crusty_err(
             serde_json::from_value(v["nodes"].clone()),
             malformed_err.clone(),
@@ -143,29 +206,82 @@ impl LogicalPlan {
             serde_json::from_value(v["root"].clone()),
             malformed_err.clone(),
         )?;
+        let parse = |key: &String| key.parse::<OpIndex>().map_err(|_| malformed_err.clone());
+        let mut keyed_nodes = nodes
+            .into_iter()
+            .map(|(i, op)| Ok((parse(&i)?, op)))
+            .collect::<Result<Vec<_>, CrustyError>>()?;
+        keyed_nodes.sort_by_key(|(i, _)| *i);
         let mut graph_map = HashMap::new();
         let mut plan = LogicalPlan::new();
-        for (i, val) in nodes.iter() {
-            let node = plan.dataflow.add_node(val.clone());
-            graph_map.insert(i, node);
+        for (i, op) in keyed_nodes {
+            graph_map.insert(i, plan.dataflow.add_node(op));
         }
 
         if let Some(i) = root {
-            let root_node = graph_map.get(&i).ok_or_else(|| malformed_err.clone())?;
+            let root_node = graph_map
+                .get(&parse(&i)?)
+                .ok_or_else(|| malformed_err.clone())?;
             plan.root = Some(*root_node);
         }
 
-        for (source, targets) in edges.iter() {
-            let source_node = graph_map.get(source).ok_or_else(|| malformed_err.clone())?;
+        let mut keyed_edges = edges
+            .iter()
+            .map(|(source, targets)| Ok((parse(source)?, targets)))
+            .collect::<Result<Vec<_>, CrustyError>>()?;
+        keyed_edges.sort_by_key(|(i, _)| *i);
+        for (source, targets) in keyed_edges {
+            let source_node = graph_map
+                .get(&source)
+                .ok_or_else(|| malformed_err.clone())?;
             for target in targets {
                 let target_node = graph_map
-                    .get(&target.to_string())
+                    .get(&parse(target)?)
                     .ok_or_else(|| malformed_err.clone())?;
                 plan.dataflow.add_edge(*source_node, *target_node);
             }
         }
         Ok(plan)
     }
+
+    /// Serializes the operator tree below the root, each operator with its inputs nested in
+    /// edge order.
+    ///
+    /// Unlike `to_json` this does not depend on the OpIndexes, so plans built in a different
+    /// order but with the same shape serialize the same.
+    pub fn canonical_json(&self) -> serde_json::Value {
+        match self.root {
+            Some(root) => self.canonical_node(root),
+            None => Value::Null,
+        }
+    }
+
+    fn canonical_node(&self, node: OpIndex) -> serde_json::Value {
+        let inputs: Vec<Value> = self
+            .edges(node)
+            .map(|child| self.canonical_node(child))
+            .collect();
+        json!({"op":self.get_operator(node),
+               "inputs":inputs})
+    }
+
+    /// Returns true if both plans have the same operators connected in the same way, regardless
+    /// of the OpIndexes they were given.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Plan to compare with.
+    pub fn structurally_eq(&self, other: &LogicalPlan) -> bool {
+        self.canonical_json() == other.canonical_json()
+    }
+
+    /// Hash of the canonical json, stable across runs. Plans that are structurally equal have the
+    /// same fingerprint, so it can be used as a cache key.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.canonical_json().to_string().hash(&mut hasher);
+        hasher.finish()
+    }
 }
 
 impl fmt::Display for LogicalPlan {
@@ -233,6 +349,33 @@ mod test {
         assert_eq!(lp.edge_count(), 2);
     }
 
+    #[test]
+    fn test_add_plan() {
+        let scan = |alias: &str| {
+            LogicalOp::Scan(ScanNode {
+                alias: String::from(alias),
+            })
+        };
+        let mut other = LogicalPlan::new();
+        let other_scan = other.add_node(scan("u"));
+        let other_distinct = other.add_node(LogicalOp::Distinct);
+        other.add_edge(other_distinct, other_scan);
+
+        let mut lp = LogicalPlan::new();
+        let t = lp.add_node(scan("t"));
+        let u = lp.add_plan(&other).unwrap();
+        assert_eq!(lp.root(), Some(t));
+        assert_eq!(lp.node_count(), 3);
+        let cross = lp.add_node(LogicalOp::CrossProduct);
+        lp.add_edge(cross, u);
+        lp.add_edge(cross, t);
+        assert_eq!(
+            "CrossProduct\n  Scan t\n  Distinct\n    Scan u\n",
+            lp.explain(None)
+        );
+        assert_eq!(None, lp.add_plan(&LogicalPlan::new()));
+    }
+
     #[test]
     fn test_edges() {
         let mut lp = LogicalPlan::new();
@@ -297,4 +440,80 @@ mod test {
             _ => panic!("Incorrect root"),
         }
     }
+
+    /// Scan a and b, join them, and project: built in the given node order.
+    fn join_plan(scans_first: bool) -> LogicalPlan {
+        let mut lp = LogicalPlan::new();
+        let (a, b, project) = if scans_first {
+            let a = lp.add_node(LogicalOp::Scan(ScanNode {
+                alias: String::from("a"),
+            }));
+            let b = lp.add_node(LogicalOp::Scan(ScanNode {
+                alias: String::from("b"),
+            }));
+            let project = lp.add_node(LogicalOp::Project(ProjectNode {
+                identifiers: ProjectIdentifiers::Wildcard,
+            }));
+            (a, b, project)
+        } else {
+            let project = lp.add_node(LogicalOp::Project(ProjectNode {
+                identifiers: ProjectIdentifiers::Wildcard,
+            }));
+            let b = lp.add_node(LogicalOp::Scan(ScanNode {
+                alias: String::from("b"),
+            }));
+            let a = lp.add_node(LogicalOp::Scan(ScanNode {
+                alias: String::from("a"),
+            }));
+            (a, b, project)
+        };
+        lp.add_edge(project, b);
+        lp.add_edge(project, a);
+        lp
+    }
+
+    #[test]
+    fn test_json_deterministic() {
+        let lp = join_plan(true);
+        let json = lp.to_json().to_string();
+        for _ in 0..10 {
+            let new_lp = LogicalPlan::from_json(&json).unwrap();
+            assert_eq!(json, new_lp.to_json().to_string());
+            assert_eq!(lp.root(), new_lp.root());
+            assert_eq!(
+                lp.edges(lp.root().unwrap()).collect::<Vec<_>>(),
+                new_lp.edges(new_lp.root().unwrap()).collect::<Vec<_>>()
+            );
+        }
+        assert!(LogicalPlan::from_json(r#"{"nodes":[],"edges":[[0,1]],"root":null}"#).is_err());
+    }
+
+    #[test]
+    fn test_json_legacy_maps() {
+        let lp = join_plan(true);
+        let legacy = json!({"nodes":{"0":lp.get_operator(0),
+                                     "1":lp.get_operator(1),
+                                     "2":lp.get_operator(2)},
+                            "edges":{"2":["1", "0"]},
+                            "root":"2"});
+        let new_lp = LogicalPlan::from_json(&legacy.to_string()).unwrap();
+        assert_eq!(lp.to_json(), new_lp.to_json());
+    }
+
+    #[test]
+    fn test_structurally_eq() {
+        let lp = join_plan(true);
+        let reordered = join_plan(false);
+        assert_ne!(lp.to_json(), reordered.to_json());
+        assert!(lp.structurally_eq(&reordered));
+        assert_eq!(lp.fingerprint(), reordered.fingerprint());
+
+        let mut other = LogicalPlan::new();
+        other.add_node(LogicalOp::Scan(ScanNode {
+            alias: String::from("a"),
+        }));
+        assert!(!lp.structurally_eq(&other));
+        assert_ne!(lp.fingerprint(), other.fingerprint());
+        assert!(LogicalPlan::new().structurally_eq(&LogicalPlan::new()));
+    }
 }
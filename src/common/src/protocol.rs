@@ -0,0 +1,343 @@
+use crate::{ResultMetadata, TableSchema, Tuple};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Responses longer than this are refused by `read_response`, so a corrupt length can't make a
+/// client allocate all of its memory.
+pub const MAX_RESPONSE_LEN: u64 = 1 << 34;
+
+/// What the server sends back for each line a client sends. On the wire every response is its
+/// length in bytes, as a big-endian u64, followed by the response encoded with CBOR.
+///
+/// The rows of a query are streamed while it runs: a `Header`, any number of `Batch`es, and
+/// an `End`, or an `Error` if the query fails part way. Results of statements carry their
+/// `ResultMetadata`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Response {
+    /// The request succeeded, with a message for the user.
+    Ok(String),
+    /// The request failed, with the error.
+    Error(String),
+    /// Rows of a query result.
+    Rows {
+        schema: TableSchema,
+        tuples: Vec<Tuple>,
+        metadata: ResultMetadata,
+    },
+    /// A statement succeeded, with a message for the user and what the statement did.
+    Done {
+        message: String,
+        metadata: ResultMetadata,
+    },
+    /// Something the client has to do.
+    Command(ClientCommand),
+    /// Start of a streamed query result, with the schema of its rows.
+    Header(TableSchema),
+    /// Rows of a streamed query result.
+    Batch(Vec<Tuple>),
+    /// End of a streamed query result, with what the query did.
+    End(ResultMetadata),
+}
+
+/// Actions the server asks a client to take.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ClientCommand {
+    /// Close the connection, the server is shutting down.
+    Quit,
+    /// Save a query result encoded in a binary format, e.g. an Arrow IPC stream, to a file.
+    Save { summary: String, bytes: Vec<u8> },
+}
+
+impl Response {
+    /// What the statement the response is the result of did, if it is one.
+    pub fn metadata(&self) -> Option<&ResultMetadata> {
+        match self {
+            Response::Rows { metadata, .. }
+            | Response::Done { metadata, .. }
+            | Response::End(metadata) => Some(metadata),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    /// The response as the CLI shows it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Response::Ok(s) | Response::Error(s) => write!(f, "{}", s),
+            Response::Done { message, .. } => write!(f, "{}", message),
+            Response::Rows { schema, tuples, .. } => {
+                write!(f, "{}", format_rows(schema, tuples))
+            }
+            Response::Command(ClientCommand::Quit) => write!(f, "Quit"),
+            Response::Command(ClientCommand::Save { summary, .. }) => write!(f, "{}", summary),
+            Response::Header(schema) => write!(f, "{}", format_rows(schema, &[])),
+            Response::Batch(tuples) => write!(f, "{} rows", tuples.len()),
+            Response::End(_) => Ok(()),
+        }
+    }
+}
+
+/// Formats rows as aligned text: a line with the names of the columns, then a line per row,
+/// every value padded to the width of the longest column name.
+///
+/// # Arguments
+///
+/// * `schema` - Schema of the rows.
+/// * `tuples` - Rows to format.
+pub fn format_rows(schema: &TableSchema, tuples: &[Tuple]) -> String {
+    let width = column_width(schema);
+    let mut res = String::new();
+    for attr in schema.attributes() {
+        res += &format!("{:width$}", attr.name(), width = width);
+    }
+    res += "\n";
+    res + &format_tuples(width, tuples)
+}
+
+/// Width every value of rows of a schema is padded to by `format_rows`.
+///
+/// # Arguments
+///
+/// * `schema` - Schema of the rows.
+pub fn column_width(schema: &TableSchema) -> usize {
+    schema
+        .attributes()
+        .map(|a| a.name().chars().count())
+        .max()
+        .unwrap_or(10)
+        + 2
+}
+
+/// Formats rows as aligned text without the line of column names, e.g. a batch of a streamed
+/// result.
+///
+/// # Arguments
+///
+/// * `width` - Width every value is padded to, see `column_width`.
+/// * `tuples` - Rows to format.
+pub fn format_tuples(width: usize, tuples: &[Tuple]) -> String {
+    let mut res = String::new();
+    for t in tuples {
+        for f in t.field_vals() {
+            res += &format!("{:width$}", f.to_string(), width = width);
+        }
+        res += "\n";
+    }
+    res
+}
+
+/// Writes a response, prefixed with its length.
+///
+/// # Arguments
+///
+/// * `writer` - Connection to the client.
+/// * `response` - Response to send.
+pub fn write_response<W: Write>(writer: &mut W, response: &Response) -> io::Result<()> {
+    let bytes = serde_cbor::to_vec(response)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    writer.write_all(&(bytes.len() as u64).to_be_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()
+}
+
+/// Reads a response written by `write_response`. Returns None if the connection was closed
+/// before a response started, and an error if it was closed in the middle of one.
+///
+/// # Arguments
+///
+/// * `reader` - Connection to the server.
+pub fn read_response<R: Read>(reader: &mut R) -> io::Result<Option<Response>> {
+    let mut len = [0u8; 8];
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => read += n,
+        }
+    }
+    let len = u64::from_be_bytes(len);
+    if len > MAX_RESPONSE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Response of {} bytes is too long", len),
+        ));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    serde_cbor::from_slice(&bytes)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Reads a response like `read_response`, collecting a streamed query result into a single
+/// `Rows` response. A stream that ends with an error is returned as that error.
+///
+/// # Arguments
+///
+/// * `reader` - Connection to the server.
+pub fn read_whole_response<R: Read>(reader: &mut R) -> io::Result<Option<Response>> {
+    let schema = match read_response(reader)? {
+        Some(Response::Header(schema)) => schema,
+        response => return Ok(response),
+    };
+    let mut tuples = Vec::new();
+    loop {
+        match read_response(reader)? {
+            Some(Response::Batch(batch)) => tuples.extend(batch),
+            Some(Response::End(metadata)) => {
+                return Ok(Some(Response::Rows {
+                    schema,
+                    tuples,
+                    metadata,
+                }))
+            }
+            Some(err @ Response::Error(_)) => return Ok(Some(err)),
+            Some(response) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unexpected response in a streamed result: {:?}", response),
+                ))
+            }
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DataType, Field};
+    use std::time::Duration;
+
+    /// Hands out the data a few bytes at a time, like a slow connection.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = 3.min(buf.len()).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let schema =
+            TableSchema::from_vecs(vec!["t.a", "t.b"], vec![DataType::Int, DataType::String]);
+        let tuples = (0..1000)
+            .map(|i| {
+                Tuple::new(vec![
+                    Field::IntField(i),
+                    Field::StringField(String::from("日本語\0")),
+                ])
+            })
+            .collect();
+        let responses = vec![
+            Response::Ok(String::from("Table t created")),
+            Response::Error(String::from("Crusty Error: no")),
+            Response::Rows {
+                schema,
+                tuples,
+                metadata: ResultMetadata::default(),
+            },
+            Response::Done {
+                message: String::from("Inserted 2 rows into t"),
+                metadata: ResultMetadata {
+                    rows_affected: Some(2),
+                    elapsed: Some(Duration::from_micros(1250)),
+                    ..ResultMetadata::default()
+                },
+            },
+            Response::Command(ClientCommand::Save {
+                summary: String::from("2 rows"),
+                bytes: vec![0xff, 0, 7],
+            }),
+            Response::Command(ClientCommand::Quit),
+        ];
+        let mut data = Vec::new();
+        for response in &responses {
+            write_response(&mut data, response).unwrap();
+        }
+        let mut reader = Trickle { data, pos: 0 };
+        for response in responses {
+            assert_eq!(Some(response), read_response(&mut reader).unwrap());
+        }
+        assert_eq!(None, read_response(&mut reader).unwrap());
+
+        // a connection closed in the middle of a response
+        let mut data = Vec::new();
+        write_response(&mut data, &Response::Ok(String::from("ok"))).unwrap();
+        data.pop();
+        assert!(read_response(&mut Trickle { data, pos: 0 }).is_err());
+    }
+
+    #[test]
+    fn test_read_whole_response() {
+        let schema = TableSchema::from_vecs(vec!["a"], vec![DataType::Int]);
+        let tuples: Vec<Tuple> = (0..3)
+            .map(|i| Tuple::new(vec![Field::IntField(i)]))
+            .collect();
+        let metadata = ResultMetadata {
+            rows_returned: Some(3),
+            plan: Some(String::from("Scan t\n")),
+            ..ResultMetadata::default()
+        };
+        let mut data = Vec::new();
+        for response in &[
+            Response::Header(schema.clone()),
+            Response::Batch(tuples[..2].to_vec()),
+            Response::Batch(tuples[2..].to_vec()),
+            Response::End(metadata.clone()),
+            Response::Header(schema.clone()),
+            Response::Batch(tuples[..1].to_vec()),
+            Response::Error(String::from("Execution Error: out of rows")),
+            Response::Ok(String::from("ok")),
+        ] {
+            write_response(&mut data, response).unwrap();
+        }
+        let mut reader = Trickle { data, pos: 0 };
+        let rows = Response::Rows {
+            schema,
+            tuples,
+            metadata: metadata.clone(),
+        };
+        assert_eq!(Some(&metadata), rows.metadata());
+        assert_eq!(Some(rows), read_whole_response(&mut reader).unwrap());
+        let err = Response::Error(String::from("Execution Error: out of rows"));
+        assert_eq!(Some(err), read_whole_response(&mut reader).unwrap());
+        let ok = Response::Ok(String::from("ok"));
+        assert_eq!(Some(ok), read_whole_response(&mut reader).unwrap());
+        assert_eq!(None, read_whole_response(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn test_format_rows() {
+        let schema = TableSchema::from_vecs(vec!["a", "b"], vec![DataType::Int, DataType::Int]);
+        let tuples = vec![Tuple::new(vec![Field::IntField(1), Field::IntField(22)])];
+        let rows = Response::Rows {
+            schema,
+            tuples,
+            metadata: ResultMetadata::default(),
+        };
+        assert_eq!("a  b  \n1  22 \n", rows.to_string());
+    }
+
+    #[test]
+    fn test_metadata_summary() {
+        let metadata = ResultMetadata {
+            rows_returned: Some(3),
+            elapsed: Some(Duration::from_micros(1250)),
+            plan: Some(String::from("Scan t\n")),
+            ..ResultMetadata::default()
+        };
+        assert_eq!("3 rows returned, 1.250 ms", metadata.to_string());
+        assert!(!metadata.is_empty());
+        assert!(ResultMetadata::default().is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{CrustyError, DataType, Field, TableSchema, Tuple};
+use std::convert::TryInto;
+use std::mem::size_of;
+
+/// Encoding of the rows stored in a container.
+///
+/// Rows in any format other than `Cbor` start with their format's version byte. A CBOR encoded
+/// tuple never starts with a byte that small, so the format of every row can be told apart and
+/// containers holding rows of several formats can be read while they are migrated.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RowFormat {
+    /// The whole tuple encoded with CBOR, untagged. Written before formats were versioned.
+    Cbor,
+    /// Fields encoded by position using the table schema: ints as 4 bytes, strings as their
+    /// length in 2 bytes followed by their UTF-8 bytes. Can't hold NULL.
+    Compact,
+    /// `Compact` preceded by a bitmap with a bit set for each NULL field, one bit per column of
+    /// the schema in the order of the columns. NULL fields take no other bytes.
+    Nullable,
+}
+
+impl Default for RowFormat {
+    /// Containers whose metadata does not name a format may hold rows written before formats
+    /// were versioned.
+    fn default() -> Self {
+        RowFormat::Cbor
+    }
+}
+
+impl RowFormat {
+    /// Format new rows are written in.
+    pub const CURRENT: RowFormat = RowFormat::Nullable;
+
+    /// Version number of the format.
+    pub fn version(self) -> u8 {
+        match self {
+            RowFormat::Cbor => 1,
+            RowFormat::Compact => 2,
+            RowFormat::Nullable => 3,
+        }
+    }
+
+    /// Gets the format of an encoded row.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Encoded row.
+    pub fn of(bytes: &[u8]) -> Result<Self, CrustyError> {
+        match bytes.first() {
+            Some(2) => Ok(RowFormat::Compact),
+            Some(3) => Ok(RowFormat::Nullable),
+            // CBOR major types 4 (array) and 5 (map)
+            Some(b) if (0x80..0xc0).contains(b) => Ok(RowFormat::Cbor),
+            _ => Err(CrustyError::ExecutionError(String::from(
+                "Row has an unknown format",
+            ))),
+        }
+    }
+
+    /// Encodes a tuple in this format.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to encode.
+    /// * `schema` - Schema of the table the tuple belongs to.
+    pub fn encode(self, tuple: &Tuple, schema: &TableSchema) -> Result<Vec<u8>, CrustyError> {
+        for (field, attr) in tuple.field_vals().zip(schema.attributes()) {
+            if field.is_null() && !attr.nullable() {
+                return Err(CrustyError::ValidationError(format!(
+                    "Column {} can't be NULL",
+                    attr.name()
+                )));
+            }
+            if let (Field::StringField(s), Some(max_len)) = (field, attr.max_len()) {
+                if s.chars().count() > max_len {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Value of column {} is longer than {} characters",
+                        attr.name(),
+                        max_len
+                    )));
+                }
+            }
+        }
+        match self {
+            RowFormat::Cbor => Ok(tuple.get_bytes()),
+            RowFormat::Compact | RowFormat::Nullable => encode_compact(self, tuple, schema),
+        }
+    }
+
+    /// Decodes a row in any format.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Encoded row.
+    /// * `schema` - Schema of the table the row belongs to.
+    pub fn decode(bytes: &[u8], schema: &TableSchema) -> Result<Tuple, CrustyError> {
+        match RowFormat::of(bytes)? {
+            RowFormat::Cbor => serde_cbor::from_slice(bytes)
+                .map_err(|e| CrustyError::ExecutionError(format!("Corrupt row: {}", e))),
+            format => decode_compact(format, &bytes[1..], schema),
+        }
+    }
+}
+
+fn mismatch() -> CrustyError {
+    CrustyError::ExecutionError(String::from("Row does not match the table schema"))
+}
+
+/// Number of bytes of the null bitmap of a row of the schema.
+fn bitmap_len(schema: &TableSchema) -> usize {
+    schema.size().div_ceil(8)
+}
+
+fn encode_compact(
+    format: RowFormat,
+    tuple: &Tuple,
+    schema: &TableSchema,
+) -> Result<Vec<u8>, CrustyError> {
+    if tuple.size() != schema.size() {
+        return Err(mismatch());
+    }
+    let mut bytes = vec![format.version()];
+    if format == RowFormat::Nullable {
+        let mut bitmap = vec![0u8; bitmap_len(schema)];
+        for (i, field) in tuple.field_vals().enumerate() {
+            if field.is_null() {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        bytes.extend(bitmap);
+    }
+    for (field, attr) in tuple.field_vals().zip(schema.attributes()) {
+        match (field, attr.dtype()) {
+            (Field::Null, _) if format == RowFormat::Nullable => {}
+            (Field::IntField(i), DataType::Int) => bytes.extend_from_slice(&i.to_le_bytes()),
+            (Field::StringField(s), DataType::String) => {
+                let len: u16 = s.len().try_into().map_err(|_| {
+                    CrustyError::ExecutionError(String::from("String too long to store"))
+                })?;
+                bytes.extend_from_slice(&len.to_le_bytes());
+                bytes.extend_from_slice(s.as_bytes());
+            }
+            _ => return Err(mismatch()),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Takes the next `n` bytes of a row.
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], CrustyError> {
+    if bytes.len() < n {
+        return Err(mismatch());
+    }
+    let (head, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(head)
+}
+
+fn decode_compact(
+    format: RowFormat,
+    mut bytes: &[u8],
+    schema: &TableSchema,
+) -> Result<Tuple, CrustyError> {
+    let bitmap = match format {
+        RowFormat::Nullable => take(&mut bytes, bitmap_len(schema))?,
+        _ => &[],
+    };
+    let mut fields = Vec::with_capacity(schema.size());
+    for (i, attr) in schema.attributes().enumerate() {
+        if bitmap.get(i / 8).is_some_and(|b| b & (1 << (i % 8)) != 0) {
+            fields.push(Field::Null);
+            continue;
+        }
+        match attr.dtype() {
+            DataType::Int => {
+                let raw = take(&mut bytes, size_of::<i32>())?;
+                fields.push(Field::IntField(i32::from_le_bytes(raw.try_into().unwrap())));
+            }
+            DataType::String => {
+                let raw = take(&mut bytes, size_of::<u16>())?;
+                let len = u16::from_le_bytes(raw.try_into().unwrap()) as usize;
+                let s = std::str::from_utf8(take(&mut bytes, len)?).map_err(|_| mismatch())?;
+                fields.push(Field::StringField(s.to_string()));
+            }
+        }
+    }
+    if !bytes.is_empty() {
+        return Err(mismatch());
+    }
+    Ok(Tuple::new(fields))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Attribute;
+
+    fn schema() -> TableSchema {
+        TableSchema::new(vec![
+            Attribute::new(String::from("id"), DataType::Int),
+            Attribute::new(String::from("name"), DataType::String),
+        ])
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), CrustyError> {
+        let schema = schema();
+        let tuple = Tuple::new(vec![
+            Field::IntField(-7),
+            Field::StringField(String::from("日本語")),
+        ]);
+        for format in &[RowFormat::Cbor, RowFormat::Compact, RowFormat::Nullable] {
+            let bytes = format.encode(&tuple, &schema)?;
+            assert_eq!(*format, RowFormat::of(&bytes)?);
+            assert_eq!(tuple, RowFormat::decode(&bytes, &schema)?);
+        }
+        // Rows written before formats were versioned
+        assert_eq!(tuple, RowFormat::decode(&tuple.get_bytes(), &schema)?);
+        assert!(
+            RowFormat::Compact.encode(&tuple, &schema)?.len()
+                < RowFormat::Cbor.encode(&tuple, &schema)?.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nulls() -> Result<(), CrustyError> {
+        let attrs: Vec<Attribute> = (0..10)
+            .map(|i| Attribute::new(format!("c{}", i), DataType::Int))
+            .chain(std::iter::once(Attribute::new(
+                String::from("s"),
+                DataType::String,
+            )))
+            .collect();
+        let schema = TableSchema::new(attrs);
+        let fields = (0..10)
+            .map(|i| {
+                if i % 3 == 0 {
+                    Field::Null
+                } else {
+                    Field::IntField(i)
+                }
+            })
+            .chain(std::iter::once(Field::Null))
+            .collect();
+        let tuple = Tuple::new(fields);
+        for format in &[RowFormat::Cbor, RowFormat::Nullable] {
+            let bytes = format.encode(&tuple, &schema)?;
+            assert_eq!(tuple, RowFormat::decode(&bytes, &schema)?);
+        }
+        // version, 2 bytes of bitmap and the 6 ints that are not NULL
+        assert_eq!(
+            1 + 2 + 6 * 4,
+            RowFormat::Nullable.encode(&tuple, &schema)?.len()
+        );
+        assert!(RowFormat::Compact.encode(&tuple, &schema).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_len() -> Result<(), CrustyError> {
+        let mut name = Attribute::new(String::from("name"), DataType::String);
+        name.max_len = Some(4);
+        let schema = TableSchema::new(vec![name]);
+        // characters are counted, not bytes
+        let fits = Tuple::new(vec![Field::StringField(String::from("café"))]);
+        let bytes = RowFormat::CURRENT.encode(&fits, &schema)?;
+        assert_eq!(1 + 1 + 2 + 5, bytes.len());
+        assert_eq!(fits, RowFormat::decode(&bytes, &schema)?);
+        let long = Tuple::new(vec![Field::StringField(String::from("cafés"))]);
+        assert!(RowFormat::CURRENT.encode(&long, &schema).is_err());
+        assert!(RowFormat::Cbor.encode(&long, &schema).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_mismatch() -> Result<(), CrustyError> {
+        let schema = schema();
+        let wrong = Tuple::new(vec![Field::IntField(1), Field::IntField(2)]);
+        assert!(RowFormat::Compact.encode(&wrong, &schema).is_err());
+        let short = Tuple::new(vec![Field::IntField(1)]);
+        assert!(RowFormat::Compact.encode(&short, &schema).is_err());
+
+        let bytes = RowFormat::Compact.encode(
+            &Tuple::new(vec![
+                Field::IntField(1),
+                Field::StringField(String::from("a")),
+            ]),
+            &schema,
+        )?;
+        assert!(RowFormat::decode(&bytes[..bytes.len() - 1], &schema).is_err());
+        let mut long = bytes.clone();
+        long.push(0);
+        assert!(RowFormat::decode(&long, &schema).is_err());
+        assert!(RowFormat::decode(&[], &schema).is_err());
+        assert!(RowFormat::decode(&[9, 0], &schema).is_err());
+        Ok(())
+    }
+}
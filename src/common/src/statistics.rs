@@ -0,0 +1,168 @@
+use crate::{Field, TableSchema, Tuple};
+use std::collections::{HashMap, HashSet};
+
+/// Statistics about a single column of a table.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ColumnStats {
+    /// Number of distinct values in the column, NULL not counted.
+    pub distinct_count: usize,
+    /// Smallest value in the column that is not NULL, if there is one.
+    pub min: Option<Field>,
+    /// Largest value in the column that is not NULL, if there is one.
+    pub max: Option<Field>,
+}
+
+/// Statistics about a table.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TableStats {
+    /// Number of rows in the table.
+    pub cardinality: usize,
+    /// Per-column statistics keyed by column name.
+    pub columns: HashMap<String, ColumnStats>,
+}
+
+impl TableStats {
+    /// Creates statistics for a table with no column statistics.
+    ///
+    /// # Arguments
+    ///
+    /// * `cardinality` - Number of rows in the table.
+    pub fn new(cardinality: usize) -> Self {
+        Self {
+            cardinality,
+            columns: HashMap::new(),
+        }
+    }
+
+    /// Computes the statistics of a table from all of its rows.
+    ///
+    /// Distinct values are counted exactly, so this keeps every distinct value of the table in
+    /// memory. NULLs are neither counted as a value nor taken as the smallest one.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the table.
+    /// * `rows` - Rows of the table.
+    pub fn collect(schema: &TableSchema, rows: impl IntoIterator<Item = Tuple>) -> Self {
+        let mut values: Vec<HashSet<Field>> = vec![HashSet::new(); schema.size()];
+        let mut cardinality = 0;
+        for row in rows {
+            cardinality += 1;
+            for (column, field) in values.iter_mut().zip(row.field_vals) {
+                if !field.is_null() {
+                    column.insert(field);
+                }
+            }
+        }
+        let columns = schema
+            .attributes()
+            .zip(values)
+            .map(|(attr, values)| {
+                let stats = ColumnStats {
+                    distinct_count: values.len(),
+                    min: values.iter().min().cloned(),
+                    max: values.iter().max().cloned(),
+                };
+                (attr.name().to_string(), stats)
+            })
+            .collect();
+        Self {
+            cardinality,
+            columns,
+        }
+    }
+
+    /// Gets the statistics of a column.
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - Column name, optionally qualified as `table.column`.
+    pub fn column(&self, column: &str) -> Option<&ColumnStats> {
+        let name = match column.rfind('.') {
+            Some(i) => &column[i + 1..],
+            None => column,
+        };
+        self.columns.get(name)
+    }
+}
+
+/// Statistics for the tables of a database, keyed by table name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Statistics {
+    tables: HashMap<String, TableStats>,
+}
+
+impl Statistics {
+    /// Creates an empty set of statistics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the statistics of a table, if they have been collected.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - Name of the table.
+    pub fn table(&self, table: &str) -> Option<&TableStats> {
+        self.tables.get(table)
+    }
+
+    /// Sets the statistics of a table, replacing any previous ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - Name of the table.
+    /// * `stats` - Statistics of the table.
+    pub fn set_table(&mut self, table: &str, stats: TableStats) {
+        self.tables.insert(table.to_string(), stats);
+    }
+
+    /// Drops the statistics of a table.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - Name of the table.
+    pub fn remove_table(&mut self, table: &str) {
+        self.tables.remove(table);
+    }
+
+    /// Returns true if no table has statistics.
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Attribute, DataType};
+
+    #[test]
+    fn test_collect() {
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("a"), DataType::Int),
+            Attribute::new(String::from("b"), DataType::String),
+        ]);
+        let rows = (0..10).map(|i| {
+            let b = match i % 4 {
+                0 => Field::Null,
+                n => Field::StringField(n.to_string()),
+            };
+            Tuple::new(vec![Field::IntField(i % 5 - 1), b])
+        });
+        let stats = TableStats::collect(&schema, rows);
+        assert_eq!(10, stats.cardinality);
+        let a = stats.column("t.a").unwrap();
+        assert_eq!(5, a.distinct_count);
+        assert_eq!(Some(Field::IntField(-1)), a.min);
+        assert_eq!(Some(Field::IntField(3)), a.max);
+        // NULL sorts first but is not the smallest value
+        let b = stats.column("b").unwrap();
+        assert_eq!(3, b.distinct_count);
+        assert_eq!(Some(Field::StringField(String::from("1"))), b.min);
+
+        let empty = TableStats::collect(&schema, Vec::new());
+        assert_eq!(0, empty.cardinality);
+        assert_eq!(None, empty.column("a").unwrap().max);
+    }
+}
@@ -1,6 +1,166 @@
 use crate::ids::Permissions;
 use crate::ids::*;
-use crate::CrustyError;
+use crate::io_stats::IoStats;
+use crate::row_format::RowFormat;
+use crate::{CrustyError, Field};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::ops::{Bound, RangeBounds};
+
+/// What a container stores.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerType {
+    /// Records of a table.
+    Table,
+    /// Entries of an index.
+    Index,
+    /// Intermediate results that do not outlive a query, e.g. spilled runs.
+    Temp,
+}
+
+/// How a container hands out the slot ids of new values.
+///
+/// Reusing the lowest free slot keeps page headers small, but a value id no longer names one
+/// value for the life of the container: once a value is deleted, an index entry or an older
+/// version that still points at its id can silently resolve to whatever was inserted next, so
+/// indexes must be updated in the same step as the delete and a multi-version scheme can't
+/// keep old versions behind the id. Monotonic ids never come back. A deleted slot leaves a
+/// tombstone that keeps its id taken, so stale references find nothing instead of the wrong
+/// value, at the cost of header space until a vacuum rewrites the page after every reference
+/// to its tombstones is gone.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SlotPolicy {
+    /// New values take the lowest slot id that is not in use.
+    #[default]
+    ReuseLowest,
+    /// New values take a slot id higher than any the page has handed out, deleted ones included.
+    Monotonic,
+}
+
+/// How the pages of a container are compressed when they are written to disk.
+///
+/// Compression is transparent: pages are compressed when they are written and decompressed when
+/// they are read, so everything above the storage manager, its buffer pool included, sees
+/// decompressed pages. Storage managers that don't write pages only record the setting.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PageCompression {
+    /// Pages are written as they are.
+    #[default]
+    None,
+    /// Pages are compressed with Snappy, unless that does not make them smaller.
+    Snappy,
+}
+
+/// Metadata a storage manager keeps, and persists, for each container.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ContainerMetadata {
+    /// Id of the container.
+    pub container_id: ContainerId,
+    /// Name the container was registered under, e.g. the table name.
+    pub name: Option<String>,
+    /// What the container stores.
+    pub container_type: ContainerType,
+    /// Oldest format the container's rows may be in. New rows are always written in
+    /// `RowFormat::CURRENT`.
+    #[serde(default)]
+    pub row_format: RowFormat,
+    /// How slot ids of new values are chosen.
+    #[serde(default)]
+    pub slot_policy: SlotPolicy,
+    /// How pages written from now on are compressed.
+    #[serde(default)]
+    pub compression: PageCompression,
+}
+
+/// Space used by the containers of a storage manager, as reported by `\stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Number of containers.
+    pub containers: usize,
+    /// Size of the container files, holes included. This is what a backup that does not keep
+    /// files sparse has to copy.
+    pub logical_bytes: u64,
+    /// Bytes the filesystem has allocated for the container files.
+    pub allocated_bytes: u64,
+    /// Bytes given back to the filesystem since the storage manager was started.
+    pub reclaimed_bytes: u64,
+    /// Bytes that compressed pages did not have to write, since the containers were opened.
+    pub compression_saved_bytes: u64,
+}
+
+impl fmt::Display for StorageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "containers: {}", self.containers)?;
+        writeln!(f, "logical bytes: {}", self.logical_bytes)?;
+        writeln!(f, "allocated bytes: {}", self.allocated_bytes)?;
+        writeln!(f, "reclaimed bytes: {}", self.reclaimed_bytes)?;
+        write!(
+            f,
+            "compression saved bytes: {}",
+            self.compression_saved_bytes
+        )
+    }
+}
+
+/// Space used by a single container, as reported by `\du`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContainerUsage {
+    /// Size of the container's file.
+    pub disk_bytes: u64,
+    /// Bytes taken by the values stored in the container.
+    pub live_bytes: u64,
+}
+
+impl ContainerUsage {
+    /// Bytes of the container's file that do not hold values, i.e. page headers, deleted values
+    /// and unused space.
+    pub fn free_bytes(&self) -> u64 {
+        self.disk_bytes.saturating_sub(self.live_bytes)
+    }
+}
+
+/// Returns the bytes the filesystem has allocated for a file, which is less than its length if
+/// the file has holes. Falls back to the length where the allocation is not known.
+///
+/// # Arguments
+///
+/// * `metadata` - Metadata of the file.
+pub fn allocated_bytes(metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len()
+    }
+}
+
+/// Iterator over the values of a container that can also lend them, so a reader that only
+/// decodes the values, like a scan, does not need a copy of each. A value that can't be read,
+/// e.g. because its page is corrupted, is returned as an error, which ends the iteration.
+pub trait ValueRefIterator: Iterator<Item = Result<Vec<u8>, CrustyError>> {
+    /// Advances the iterator like `next`, but returns the value borrowed from the iterator. It
+    /// is valid until the iterator is advanced again.
+    fn next_ref(&mut self) -> Option<Result<&[u8], CrustyError>>;
+
+    /// Keeps the pages the iterator visits from now on in the storage manager's cache until the
+    /// iterator is dropped, not just the page it is on. For containers that are read again and
+    /// again, like the inner side of a nested loop join. Storage managers that don't cache
+    /// pages ignore it.
+    fn hold_pages(&mut self) {}
+
+    /// Returns the values left, or the error that ended the iteration before every value was
+    /// returned.
+    fn collect_values(&mut self) -> Result<Vec<Vec<u8>>, CrustyError>
+    where
+        Self: Sized,
+    {
+        self.by_ref().collect()
+    }
+}
 
 // TODO: What does ContainerId add as a type? If nothing, then make it u16 and make it easier for clients of
 // TODO: storage managers to use them
@@ -11,7 +171,7 @@ use crate::CrustyError;
 pub trait StorageTrait {
     /// The associated type of the iterator that will need to be written and defined for the storage manager
     /// This iterator will be used to scan records of a container
-    type ValIterator: Iterator<Item = Vec<u8>>;
+    type ValIterator: ValueRefIterator;
 
     /// Create a new storage manager that will use storage_path as the location to persist data
     /// (if the storage manager persists records on disk)
@@ -54,16 +214,73 @@ pub trait StorageTrait {
         tid: TransactionId,
     ) -> Result<ValueId, CrustyError>;
 
-    /// Create a new container to be stored. The name must be unique. Multiple calls for the same name
-    /// should return the same containerId.
-    // fn create_container(&self, name: String) -> ContainerId;
-    fn create_container(&self, container_id: ContainerId) -> Result<(), CrustyError>;
+    /// Create a new container to be stored, registering its name and type with it.
+    /// Creating a container that already exists with the same name is a no-op, so callers can
+    /// call this whenever they need the container. It is an error to reuse an id under a
+    /// different name, or a name for a different id.
+    fn create_container(
+        &self,
+        container_id: ContainerId,
+        name: Option<String>,
+        container_type: ContainerType,
+    ) -> Result<(), CrustyError>;
+
+    /// Metadata of every existing container, ordered by container id.
+    fn list_containers(&self) -> Vec<ContainerMetadata>;
 
     /// Remove the container and all stored values in the container. 
-    /// If the container is persisted remove the underlying files
+    /// If the container is persisted remove the underlying files.
+    /// Iterators that are already open keep reading the values as of when they were created.
     fn remove_container(&self, container_id: ContainerId) -> Result<(), CrustyError>;
 
-    /// Get an iterator that returns all valid records
+    /// Remove all stored values in the container but keep the container itself.
+    /// This should release the container's storage wholesale rather than deleting value by value.
+    /// Truncating a container that does not exist is an error.
+    /// Iterators that are already open keep reading the values as of when they were created.
+    fn truncate_container(&self, container_id: ContainerId) -> Result<(), CrustyError>;
+
+    /// Gives the space of a container's free pages back to the filesystem, e.g. after values
+    /// were deleted. Returns the number of bytes reclaimed.
+    fn reclaim_space(&self, container_id: ContainerId) -> Result<u64, CrustyError>;
+
+    /// Reports the space used by the containers.
+    fn storage_stats(&self) -> StorageStats;
+
+    /// Reports the space used by a container. Error if the container does not exist.
+    fn container_usage(&self, container_id: ContainerId) -> Result<ContainerUsage, CrustyError>;
+
+    /// Records the oldest format the container's rows are in, e.g. after they were rewritten in
+    /// the current format. Error if the container does not exist.
+    fn set_row_format(
+        &self,
+        container_id: ContainerId,
+        row_format: RowFormat,
+    ) -> Result<(), CrustyError>;
+
+    /// Sets how the slot ids of values inserted into the container from now on are chosen.
+    /// Slots freed before the change are not affected. Error if the container does not exist.
+    fn set_slot_policy(
+        &self,
+        container_id: ContainerId,
+        slot_policy: SlotPolicy,
+    ) -> Result<(), CrustyError>;
+
+    /// Sets how the pages of the container written from now on are compressed. Pages already on
+    /// disk stay as they are until they are written again. Error if the container does not exist.
+    fn set_compression(
+        &self,
+        container_id: ContainerId,
+        compression: PageCompression,
+    ) -> Result<(), CrustyError>;
+
+    /// Returns true if the container was created and has not been removed since.
+    fn container_exists(&self, container_id: ContainerId) -> bool;
+
+    /// Get an iterator that returns all valid records.
+    /// Values written by `tid` before the iterator is created must be visible to it, even if
+    /// `tid` has not finished yet (read-your-writes). Callers that want to see later writes
+    /// should request a new iterator. Callers should check `container_exists` first; a missing
+    /// container must not cause a panic.
     fn get_iterator(
         &self,
         container_id: ContainerId,
@@ -71,7 +288,47 @@ pub trait StorageTrait {
         perm: Permissions,
     ) -> Self::ValIterator;
 
-    /// Get the data for a particular ValueId. Error if does not exists
+    /// Like `get_iterator`, but the iterator may read up to `prefetch_pages` pages ahead of the
+    /// page it is returning values from, trading memory for fewer, larger reads. Storage managers
+    /// that don't keep pages ignore the hint.
+    fn get_prefetching_iterator(
+        &self,
+        container_id: ContainerId,
+        tid: TransactionId,
+        perm: Permissions,
+        _prefetch_pages: usize,
+    ) -> Self::ValIterator {
+        self.get_iterator(container_id, tid, perm)
+    }
+
+    /// Like `get_prefetching_iterator`, but the iterator only returns the values of part
+    /// `partition` of `partitions` parts of the container, so several threads can scan it at
+    /// once. The parts are contiguous and in order: the values of part 0, then of part 1 and
+    /// so on, are those `get_iterator` returns, in the same order. Storage managers that keep
+    /// pages split the container by pages.
+    fn get_partition_iterator(
+        &self,
+        container_id: ContainerId,
+        tid: TransactionId,
+        perm: Permissions,
+        prefetch_pages: usize,
+        partition: usize,
+        partitions: usize,
+    ) -> Self::ValIterator;
+
+    /// Reads up to `max_pages` of the first pages of a container into the storage manager's cache,
+    /// so the first queries after a restart don't wait on disk. Returns how many pages of the
+    /// container are cached afterwards. Storage managers that don't cache pages load nothing.
+    fn warm_container(
+        &self,
+        _container_id: ContainerId,
+        _max_pages: usize,
+    ) -> Result<usize, CrustyError> {
+        Ok(0)
+    }
+
+    /// Get the data for a particular ValueId. Error if does not exists.
+    /// As with `get_iterator`, values written by `tid` are visible before `tid` finishes.
     fn get_value(
         &self,
         id: ValueId,
@@ -79,12 +336,241 @@ pub trait StorageTrait {
         perm: Permissions,
     ) -> Result<Vec<u8>, CrustyError>;
 
+    /// Get every valid record of a container with its id, in the order of `get_iterator`, e.g.
+    /// to build an index over the values already stored. Empty if the container does not exist.
+    /// Error if a value can't be read.
+    fn get_values_with_ids(
+        &self,
+        container_id: ContainerId,
+        tid: TransactionId,
+        perm: Permissions,
+    ) -> Result<Vec<(ValueId, Vec<u8>)>, CrustyError>;
+
+    /// Adds entries to the index stored in a container. An entry is a key and the packed id
+    /// (`ValueId::pack`) of the value it points at. Adding an entry that is already in the
+    /// index is a no-op.
+    ///
+    /// By default every entry is stored as a value of the container and `index_range` reads
+    /// all of them, storage managers that keep pages store a B-tree instead.
+    fn index_insert(
+        &self,
+        container_id: ContainerId,
+        entries: Vec<(Field, u64)>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let mut existing = HashSet::new();
+        for bytes in self.get_iterator(container_id, tid, Permissions::ReadOnly) {
+            existing.insert(decode_entry(&bytes?)?);
+        }
+        self.check_transaction(tid)?;
+        let mut values = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let bytes = serde_cbor::to_vec(&entry).map_err(|e| {
+                CrustyError::CrustyError(format!("Cannot serialize index entry: {}", e))
+            })?;
+            if existing.insert(entry) {
+                values.push(bytes);
+            }
+        }
+        self.insert_values(container_id, values, tid);
+        Ok(())
+    }
+
+    /// Packed ids of the entries of an index whose keys are within the bounds, ordered by key
+    /// and then by id.
+    fn index_range(
+        &self,
+        container_id: ContainerId,
+        low: Bound<&Field>,
+        high: Bound<&Field>,
+        tid: TransactionId,
+    ) -> Result<Vec<u64>, CrustyError> {
+        let mut entries = Vec::new();
+        for bytes in self.get_iterator(container_id, tid, Permissions::ReadOnly) {
+            let entry = decode_entry(&bytes?)?;
+            if (low, high).contains(&entry.0) {
+                entries.push(entry);
+            }
+        }
+        self.check_transaction(tid)?;
+        entries.sort();
+        Ok(entries.into_iter().map(|(_, id)| id).collect())
+    }
+
+    /// Starts counting the pages read and written on behalf of a transaction, e.g. by the query
+    /// it runs. Iterators count their reads as they go, including after the call that created
+    /// them.
+    fn track_io(&self, tid: TransactionId);
+
+    /// Stops counting the IO of a transaction and returns the pages it read and wrote since
+    /// `track_io`. Returns zeros if the transaction was not tracked.
+    fn untrack_io(&self, tid: TransactionId) -> IoStats;
+
+    /// Starts a transaction whose writes can be undone with `abort_transaction`. Writes made for
+    /// a transaction that was not started take effect right away and can't be undone, e.g. those
+    /// of imports and internal maintenance.
+    ///
+    /// A started transaction locks what it reads and writes, waiting for the conflicting locks of
+    /// other transactions, and keeps its locks until it commits or aborts. If waiting would
+    /// deadlock, or it waits too long, it is aborted: calls for it fail with
+    /// `CrustyError::TransactionAbortedError`, except iterators, which end early, so
+    /// `check_transaction` needs to be called once they are done.
+    fn start_transaction(&self, tid: TransactionId);
+
+    /// Returns `CrustyError::TransactionAbortedError` if the transaction was aborted while
+    /// waiting for a lock, in which case it needs to be rolled back with `abort_transaction`.
+    fn check_transaction(&self, tid: TransactionId) -> Result<(), CrustyError>;
+
     /// Notify the storage manager that the transaction is finished so that any held resources can be released.
+    /// The writes of the transaction are committed and can no longer be undone.
     fn transaction_finished(&self, tid: TransactionId);
 
-    /// Reset all state associated the storage manager.
-    fn reset(&self);
+    /// Undoes the writes made for a started transaction, the latest first, and finishes it,
+    /// releasing its locks. Writes to containers that were removed since are skipped. Does
+    /// nothing if the transaction was not started.
+    fn abort_transaction(&self, tid: TransactionId) -> Result<(), CrustyError>;
+
+    /// Reset all state associated the storage manager: every container is removed, cached
+    /// pages are dropped without being written back, and the files persisted in the storage path
+    /// are deleted. Transactions that are still running are forgotten.
+    fn reset(&self) -> Result<(), CrustyError>;
 
     /// Call shutdown to persist state or clean up. Will be called by drop in addition to explicitly.
-    fn shutdown(&self);
+    /// Error if the state could not be persisted, in which case writes may be lost.
+    fn shutdown(&self) -> Result<(), CrustyError>;
+}
+
+/// Decodes an index entry stored as a value by the default `StorageTrait::index_insert`.
+fn decode_entry(bytes: &[u8]) -> Result<(Field, u64), CrustyError> {
+    serde_cbor::from_slice(bytes)
+        .map_err(|e| CrustyError::CrustyError(format!("Cannot deserialize index entry: {}", e)))
+}
+
+/// Sets the row format in the metadata of a container.
+///
+/// # Arguments
+///
+/// * `existing` - Metadata of the existing containers by id.
+/// * `container_id` - Id of the container.
+/// * `row_format` - Oldest format of the container's rows.
+pub fn update_row_format(
+    existing: &mut HashMap<ContainerId, ContainerMetadata>,
+    container_id: ContainerId,
+    row_format: RowFormat,
+) -> Result<(), CrustyError> {
+    match existing.get_mut(&container_id) {
+        Some(meta) => {
+            meta.row_format = row_format;
+            Ok(())
+        }
+        None => Err(CrustyError::CrustyError(format!(
+            "Container {:?} not found",
+            container_id
+        ))),
+    }
+}
+
+/// Sets the slot policy in the metadata of a container.
+///
+/// # Arguments
+///
+/// * `existing` - Metadata of the existing containers by id.
+/// * `container_id` - Id of the container.
+/// * `slot_policy` - How slot ids of new values are chosen.
+pub fn update_slot_policy(
+    existing: &mut HashMap<ContainerId, ContainerMetadata>,
+    container_id: ContainerId,
+    slot_policy: SlotPolicy,
+) -> Result<(), CrustyError> {
+    match existing.get_mut(&container_id) {
+        Some(meta) => {
+            meta.slot_policy = slot_policy;
+            Ok(())
+        }
+        None => Err(CrustyError::CrustyError(format!(
+            "Container {:?} not found",
+            container_id
+        ))),
+    }
+}
+
+/// Sets the page compression in the metadata of a container.
+///
+/// # Arguments
+///
+/// * `existing` - Metadata of the existing containers by id.
+/// * `container_id` - Id of the container.
+/// * `compression` - How pages written from now on are compressed.
+pub fn update_compression(
+    existing: &mut HashMap<ContainerId, ContainerMetadata>,
+    container_id: ContainerId,
+    compression: PageCompression,
+) -> Result<(), CrustyError> {
+    match existing.get_mut(&container_id) {
+        Some(meta) => {
+            meta.compression = compression;
+            Ok(())
+        }
+        None => Err(CrustyError::CrustyError(format!(
+            "Container {:?} not found",
+            container_id
+        ))),
+    }
+}
+
+/// Registers a create_container call in the metadata of the existing containers.
+///
+/// Returns Ok(false) if the container already exists, Ok(true) if it is new and should be
+/// created, and an error if the id or the name is already taken by something else. An existing
+/// container without a name takes the given name.
+///
+/// # Arguments
+///
+/// * `existing` - Metadata of the existing containers by id.
+/// * `container_id` - Id of the container to create.
+/// * `name` - Name of the container to create.
+/// * `container_type` - Type of the container to create.
+pub fn register_container(
+    existing: &mut HashMap<ContainerId, ContainerMetadata>,
+    container_id: ContainerId,
+    name: Option<String>,
+    container_type: ContainerType,
+) -> Result<bool, CrustyError> {
+    if name.is_some()
+        && existing
+            .values()
+            .any(|meta| meta.container_id != container_id && meta.name == name)
+    {
+        return Err(CrustyError::CrustyError(format!(
+            "Container name {:?} is already in use",
+            name
+        )));
+    }
+    match existing.get_mut(&container_id) {
+        Some(meta) => {
+            if meta.name.is_none() {
+                meta.name = name;
+            } else if name.is_some() && meta.name != name {
+                return Err(CrustyError::CrustyError(format!(
+                    "Container {} already exists as {:?}",
+                    container_id, meta.name
+                )));
+            }
+            Ok(false)
+        }
+        None => {
+            existing.insert(
+                container_id,
+                ContainerMetadata {
+                    container_id,
+                    name,
+                    container_type,
+                    row_format: RowFormat::CURRENT,
+                    slot_policy: SlotPolicy::default(),
+                    compression: PageCompression::default(),
+                },
+            );
+            Ok(true)
+        }
+    }
 }
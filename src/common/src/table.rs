@@ -1,3 +1,4 @@
+use crate::ids::ContainerId;
 use crate::TableSchema;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
@@ -11,6 +12,23 @@ pub struct Table {
     pub id: u64,
     /// Table schema.
     pub schema: TableSchema,
+    /// Rows of an append-only table are only ever added, never rewritten or removed.
+    #[serde(default)]
+    pub append_only: bool,
+    /// Indexes on columns of the table.
+    #[serde(default)]
+    pub indexes: Vec<IndexInfo>,
+}
+
+/// Catalog entry of an index on a column of a table.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct IndexInfo {
+    /// Index name, unique within the database.
+    pub name: String,
+    /// Name of the indexed column.
+    pub column: String,
+    /// Container the index entries are stored in.
+    pub container_id: ContainerId,
 }
 
 impl Table {
@@ -28,6 +46,8 @@ impl Table {
             name,
             id: table_id,
             schema,
+            append_only: false,
+            indexes: Vec::new(),
         }
     }
 
@@ -0,0 +1,128 @@
+use crate::ids::{ContainerId, TransactionId, ValueId};
+use crate::Field;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A write a storage manager made on behalf of a transaction, with what it takes to undo it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Write {
+    /// A value was inserted under the id.
+    Insert(ValueId),
+    /// The value with the bytes was deleted from the id.
+    Delete(ValueId, Vec<u8>),
+    /// Entries were added to the index stored in the container. Only entries that were not in
+    /// the index already are recorded.
+    IndexInsert(ContainerId, Vec<(Field, u64)>),
+}
+
+/// The writes of the transactions that were started and have not finished. Storage managers
+/// keep one, record each write under the transaction it is made for, and undo the writes when
+/// the transaction aborts. Writes of transactions that were not started are not recorded, so
+/// they take effect right away and can't be undone.
+#[derive(Debug, Default)]
+pub struct UndoLog {
+    writes: Mutex<HashMap<TransactionId, Vec<Write>>>,
+}
+
+impl UndoLog {
+    /// Starts recording the writes of a transaction, dropping any recorded before.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction to record writes of.
+    pub fn start(&self, tid: TransactionId) {
+        self.writes.lock().unwrap().insert(tid, Vec::new());
+    }
+
+    /// Returns true if the writes of the transaction are recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction to check.
+    pub fn is_started(&self, tid: TransactionId) -> bool {
+        self.writes.lock().unwrap().contains_key(&tid)
+    }
+
+    /// Records writes made for a transaction, if it was started. The writes are only consumed
+    /// if they are recorded, so building them may be left to the iterator.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction the writes were made for.
+    /// * `writes` - Writes in the order they were made.
+    pub fn record(&self, tid: TransactionId, writes: impl IntoIterator<Item = Write>) {
+        if let Some(log) = self.writes.lock().unwrap().get_mut(&tid) {
+            log.extend(writes);
+        }
+    }
+
+    /// Forgets the writes of a transaction that committed.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction that committed.
+    pub fn commit(&self, tid: TransactionId) {
+        self.writes.lock().unwrap().remove(&tid);
+    }
+
+    /// Stops recording the writes of a transaction that aborted and returns them, the latest
+    /// first, so they can be undone in that order. Empty if the transaction was not started.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Transaction that aborted.
+    pub fn abort(&self, tid: TransactionId) -> Vec<Write> {
+        let mut writes = self.writes.lock().unwrap().remove(&tid).unwrap_or_default();
+        writes.reverse();
+        writes
+    }
+
+    /// Forgets the writes of every transaction, e.g. once the storage manager dropped all of
+    /// its containers.
+    pub fn clear(&self) {
+        self.writes.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn id(slot_id: u16) -> ValueId {
+        ValueId {
+            slot_id: Some(slot_id),
+            ..ValueId::new(0)
+        }
+    }
+
+    #[test]
+    fn test_undo_log() {
+        let log = UndoLog::default();
+        let (t1, t2) = (TransactionId::new(), TransactionId::new());
+        log.start(t1);
+        assert!(log.is_started(t1));
+        log.record(t1, vec![Write::Insert(id(0)), Write::Insert(id(1))]);
+        log.record(t1, vec![Write::Delete(id(0), vec![1, 2])]);
+        // writes of transactions that were not started are not recorded
+        log.record(t2, (0..).map(|i| Write::Insert(id(i))));
+        assert!(!log.is_started(t2));
+        assert_eq!(
+            vec![
+                Write::Delete(id(0), vec![1, 2]),
+                Write::Insert(id(1)),
+                Write::Insert(id(0))
+            ],
+            log.abort(t1)
+        );
+        assert!(log.abort(t1).is_empty());
+
+        log.start(t2);
+        log.record(
+            t2,
+            vec![Write::IndexInsert(1, vec![(Field::IntField(1), 0)])],
+        );
+        log.commit(t2);
+        assert!(!log.is_started(t2));
+        assert!(log.abort(t2).is_empty());
+    }
+}
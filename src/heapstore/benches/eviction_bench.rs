@@ -0,0 +1,103 @@
+use criterion::{black_box, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs;
+use std::time::Instant;
+
+use common::ids::PageId;
+use common::testutil::gen_random_dir;
+use common::PAGE_SLOTS;
+use heapstore::buffer_pool::{BufferPoolConfig, EvictionPolicy};
+use heapstore::storage_manager::StorageManager;
+use heapstore::testutil::{bench_sm_clear_pool, bench_sm_fill, bench_sm_read_pages};
+
+/// Number of distinct pages accessed. Larger than the pool so every policy has to evict.
+const WORKING_SET: usize = PAGE_SLOTS * 10;
+const ACCESSES: usize = 20_000;
+const SEED: u64 = 42;
+
+/// The policies of the buffer pool being compared.
+const POLICIES: [EvictionPolicy; 3] = [
+    EvictionPolicy::Lru,
+    EvictionPolicy::Clock,
+    EvictionPolicy::Fifo,
+];
+
+/// A storage manager whose buffer pool of PAGE_SLOTS frames uses `policy`, with a container of
+/// WORKING_SET pages, and the directory it keeps them in.
+fn new_sm(policy: EvictionPolicy) -> (StorageManager, String) {
+    let path = gen_random_dir().to_string_lossy().to_string();
+    let config = BufferPoolConfig {
+        capacity: PAGE_SLOTS,
+        policy,
+    };
+    let sm = StorageManager::with_config(path.clone(), config);
+    bench_sm_fill(&sm, 1, WORKING_SET as PageId);
+    (sm, path)
+}
+
+/// Page ids drawn from a zipfian distribution with exponent `s`; page 0 is the hottest.
+fn zipf_trace(s: f64) -> Vec<PageId> {
+    let mut cdf = Vec::with_capacity(WORKING_SET);
+    let mut total = 0.0;
+    for k in 1..=WORKING_SET {
+        total += 1.0 / (k as f64).powf(s);
+        cdf.push(total);
+    }
+    let mut rng = StdRng::seed_from_u64(SEED);
+    (0..ACCESSES)
+        .map(|_| {
+            let u = rng.gen::<f64>() * total;
+            cdf.partition_point(|&c| c < u).min(WORKING_SET - 1) as PageId
+        })
+        .collect()
+}
+
+/// Runs the trace through the buffer pool, starting empty, and returns the number of hits.
+fn run_trace(sm: &StorageManager, trace: &[PageId]) -> usize {
+    bench_sm_clear_pool(sm);
+    bench_sm_read_pages(sm, 1, trace)
+}
+
+/// Prints hit rate and time per access of each policy for a few levels of skew. The time
+/// includes reading the misses from the heapfile, mostly from the OS cache.
+fn print_summary(sms: &[(StorageManager, String)]) {
+    println!(
+        "\nEviction policies: {} frames, {} pages, {} accesses",
+        PAGE_SLOTS, WORKING_SET, ACCESSES
+    );
+    println!(
+        "{:<8} {:<8} {:>10} {:>12}",
+        "zipf s", "policy", "hit rate", "ns/access"
+    );
+    for s in &[0.6, 0.8, 1.0, 1.2] {
+        let trace = zipf_trace(*s);
+        for (policy, (sm, _)) in POLICIES.iter().zip(sms) {
+            let start = Instant::now();
+            let hits = run_trace(sm, &trace);
+            let elapsed = start.elapsed();
+            println!(
+                "{:<8} {:<8} {:>9.1}% {:>12.1}",
+                s,
+                format!("{:?}", policy),
+                100.0 * hits as f64 / ACCESSES as f64,
+                elapsed.as_nanos() as f64 / ACCESSES as f64
+            );
+        }
+    }
+    println!();
+}
+
+pub fn eviction_benchmark(c: &mut Criterion) {
+    let sms: Vec<(StorageManager, String)> = POLICIES.iter().map(|p| new_sm(*p)).collect();
+    print_summary(&sms);
+    let trace = zipf_trace(1.0);
+    for (policy, (sm, _)) in POLICIES.iter().zip(&sms) {
+        let name = format!("eviction {:?} zipf 1.0", policy);
+        c.bench_function(&name, |b| b.iter(|| run_trace(sm, black_box(&trace))));
+    }
+    for (sm, path) in sms {
+        drop(sm);
+        let _ = fs::remove_dir_all(path);
+    }
+}
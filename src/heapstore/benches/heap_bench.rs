@@ -1,7 +1,16 @@
 use criterion::{criterion_group, criterion_main};
 
+mod eviction_bench;
 mod page_bench;
+mod scan_bench;
 mod sm_bench;
 
-criterion_group!(benches, page_bench::page_benchmark, sm_bench::sm_ins_bench);
+criterion_group!(
+    benches,
+    page_bench::page_benchmark,
+    page_bench::page_ops_benchmark,
+    sm_bench::sm_ins_bench,
+    eviction_bench::eviction_benchmark,
+    scan_bench::scan_benchmark
+);
 criterion_main!(benches);
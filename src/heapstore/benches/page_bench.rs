@@ -1,7 +1,12 @@
-use criterion::{black_box, Criterion};
+use criterion::{black_box, BatchSize, BenchmarkId, Criterion};
 
-use common::testutil::get_random_vec_of_byte_vec;
-use heapstore::testutil::bench_page_insert;
+use common::testutil::{get_random_byte_vec, get_random_vec_of_byte_vec};
+use heapstore::testutil::{bench_page_insert, BenchPage};
+
+/// Fractions of the page filled with values before the operation is timed.
+const FILL_LEVELS: [f64; 3] = [0.0, 0.5, 0.9];
+/// Sizes of the values in bytes: small tuples, tuples with a few strings, and wide rows.
+const VALUE_SIZES: [usize; 3] = [16, 100, 400];
 
 pub fn page_benchmark(c: &mut Criterion) {
     let to_insert = get_random_vec_of_byte_vec(40, 80, 100);
@@ -14,3 +19,64 @@ pub fn page_benchmark(c: &mut Criterion) {
         b.iter(|| bench_page_insert(black_box(&to_insert)))
     });
 }
+
+/// Times single page operations at several fill levels and value sizes, e.g. to compare slot
+/// directory layouts or compaction strategies without the buffer pool and files in the way.
+///
+/// Inserts time adding one value to a page at the fill level. Reads, deletes and iteration
+/// go over every value of the page, so their times grow with the number of values.
+pub fn page_ops_benchmark(c: &mut Criterion) {
+    let pages: Vec<(String, BenchPage, usize)> = VALUE_SIZES
+        .iter()
+        .flat_map(|size| {
+            FILL_LEVELS.iter().map(move |fill| {
+                let id = format!("{}B/{}%", size, (fill * 100.0) as usize);
+                (id, BenchPage::filled(*fill, *size), *size)
+            })
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("page add_value");
+    for (id, page, size) in &pages {
+        let value = get_random_byte_vec(*size);
+        group.bench_with_input(BenchmarkId::from_parameter(id), page, |b, page| {
+            b.iter_batched(
+                || page.clone(),
+                |mut p| p.add_value(black_box(&value)).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+
+    // An empty page has nothing to read, delete or iterate over
+    let filled = pages.iter().filter(|(_, page, _)| !page.is_empty());
+
+    let mut group = c.benchmark_group("page get_value");
+    for (id, page, _) in filled.clone() {
+        group.bench_with_input(BenchmarkId::from_parameter(id), page, |b, page| {
+            b.iter(|| page.get_values())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("page delete_value");
+    for (id, page, _) in filled.clone() {
+        group.bench_with_input(BenchmarkId::from_parameter(id), page, |b, page| {
+            b.iter_batched(
+                || page.clone(),
+                |mut p| p.delete_values(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("page iterate");
+    for (id, page, _) in filled {
+        group.bench_with_input(BenchmarkId::from_parameter(id), page, |b, page| {
+            b.iter_batched(|| page.clone(), |p| p.iterate(), BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
@@ -0,0 +1,26 @@
+use criterion::{black_box, BenchmarkId, Criterion};
+
+use common::storage_trait::StorageTrait;
+use common::PAGE_SLOTS;
+use heapstore::storage_manager::StorageManager;
+use heapstore::testutil::{bench_sm_fill, bench_sm_scan};
+
+/// Pages scanned. More than the pool holds, like the tables scans are tuned for.
+const NUM_PAGES: u16 = PAGE_SLOTS as u16 * 4;
+
+/// Scans the container with a few amounts of prefetching. With the file in the OS cache the
+/// difference is within the noise, reading 2 pages per call being slightly ahead; reading more
+/// only holds more pages per open scan. That is the default `scan_prefetch_pages` of 1.
+pub fn scan_benchmark(c: &mut Criterion) {
+    let sm = StorageManager::new_test_sm();
+    let cid = 1;
+    bench_sm_fill(&sm, cid, NUM_PAGES);
+
+    let mut group = c.benchmark_group("sm scan prefetch");
+    for prefetch in [0, 1, 4, 8, 16, 64].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(prefetch), prefetch, |b, p| {
+            b.iter(|| bench_sm_scan(&sm, cid, black_box(*p)))
+        });
+    }
+    group.finish();
+}
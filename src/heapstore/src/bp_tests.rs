@@ -4,20 +4,26 @@
 
 #[cfg(test)]
 mod tests {
+    use crate::buffer_pool::{BufferPool, BufferPoolConfig, EvictionPolicy};
+    use crate::heapfile::HeapFile;
+    use crate::page::Page;
     use crate::storage_manager::StorageManager;
     use crate::testutil::*;
     use common::ids::{PageId, Permissions, TransactionId, ValueId};
-    use common::storage_trait::StorageTrait;
+    use common::storage_trait::{ContainerType, StorageTrait};
     use common::testutil::*;
     use common::PAGE_SLOTS;
+    use std::fs::File;
     use std::sync::Arc;
     use std::thread;
+    use std::time::Duration;
+    use temp_testdir::TempDir;
 
     #[test]
     fn test_bp_a_get() {
         let sm = StorageManager::new_test_sm();
         let hfid = 1;
-        sm.create_container(hfid).unwrap();
+        sm.create_container(hfid, None, ContainerType::Table).unwrap();
         let byte_1 = get_random_byte_vec(40);
         let tid = TransactionId::new();
         let val_id = sm.insert_value(hfid, byte_1.clone(), tid);
@@ -45,7 +51,7 @@ mod tests {
 
         assert_eq!(byte_check, byte_1);
 
-        sm.reset();
+        sm.clear_buffer_pool().unwrap();
         sm.get_page(
             val_id.container_id,
             val_id.page_id.unwrap(),
@@ -66,7 +72,7 @@ mod tests {
         //Create a temp file
         let sm = StorageManager::new_test_sm();
         let hfid = 1;
-        sm.create_container(hfid).unwrap();
+        sm.create_container(hfid, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let to_fill = PAGE_SLOTS + 1;
         fill_hf_sm(&sm, hfid, to_fill as PageId, 10, 100, 100);
@@ -152,7 +158,7 @@ mod tests {
     fn test_bp_write() {
         let sm = StorageManager::new_test_sm();
         let hfid = 1;
-        sm.create_container(hfid).unwrap();
+        sm.create_container(hfid, None, ContainerType::Table).unwrap();
         let byte_1 = get_random_byte_vec(40);
         let tid = TransactionId::new();
         let val_id = sm.insert_value(hfid, byte_1, tid);
@@ -185,6 +191,14 @@ mod tests {
         let p1_bytes = p.get_bytes();
         sm.write_page(val_id.container_id, p, tid).unwrap();
 
+        // the page is only written back when flushed
+        #[cfg(feature = "profile")]
+        {
+            let (_rc, wc) = sm.get_hf_read_write_count(hfid);
+            assert_eq!(1, wc);
+        }
+        sm.flush_container(val_id.container_id).unwrap();
+        sm.flush_all().unwrap();
         #[cfg(feature = "profile")]
         {
             let (_rc, wc) = sm.get_hf_read_write_count(hfid);
@@ -208,7 +222,7 @@ mod tests {
         init();
         let sm = StorageManager::new_test_sm();
         let hfid = 1;
-        sm.create_container(hfid).unwrap();
+        sm.create_container(hfid, None, ContainerType::Table).unwrap();
         let byte_1 = get_random_byte_vec(40);
         let tid = TransactionId::new();
         let val_id = sm.insert_value(hfid, byte_1, tid);
@@ -243,4 +257,96 @@ mod tests {
             assert_eq!(1, rc);
         }
     }
+
+    #[test]
+    fn test_bp_evict_write_failure() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let mut path = tdir.to_path_buf();
+        path.push(gen_rand_string(4));
+        path.set_extension("hf");
+        let hf = Arc::new(HeapFile::new(path.clone(), 1).unwrap());
+        let bp = BufferPool::new(BufferPoolConfig{capacity: 1, policy: EvictionPolicy::Clock});
+        let mut p0 = Page::new(0);
+        let bytes = get_random_byte_vec(40);
+        p0.add_value(&bytes);
+        bp.put_page(&hf, p0, true, None).unwrap();
+
+        // the heapfile can't be written anymore, so page 0 can't be written back to make room
+        let writable = std::mem::replace(&mut *hf.file.write().unwrap(), File::open(&path).unwrap());
+        assert!(bp.put_page(&hf, Page::new(1), true, None).is_err());
+        assert!(bp.is_cached(1, 0));
+        assert!(!bp.is_cached(1, 1));
+        assert_eq!(1, bp.num_frames());
+
+        // once writes work again the dirty page is written back and evicted as usual
+        *hf.file.write().unwrap() = writable;
+        bp.put_page(&hf, Page::new(1), true, None).unwrap();
+        assert!(!bp.is_cached(1, 0));
+        assert!(bp.is_cached(1, 1));
+        assert_eq!(Some(bytes), hf.read_page_from_file(0).unwrap().get_value(0));
+        bp.put_page(&hf, Page::new(2), true, None).unwrap();
+        assert!(bp.is_cached(1, 2));
+    }
+
+    #[test]
+    fn test_bp_loading() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let mut path = tdir.to_path_buf();
+        path.push(gen_rand_string(4));
+        path.set_extension("hf");
+        let hf = Arc::new(HeapFile::new(path, 1).unwrap());
+        for pid in 0..2 {
+            hf.write_page_to_file(Page::new(pid)).unwrap();
+        }
+        let bp = Arc::new(BufferPool::new(BufferPoolConfig{capacity: 4, policy: EvictionPolicy::Lru}));
+
+        // while page 0 is being loaded, other pages are read and page 0 is waited for
+        bp.mark_loading(1, 0);
+        let (bp2, hf2) = (bp.clone(), hf.clone());
+        let waiting = thread::spawn(move || bp2.get_page(&hf2, 0, None).unwrap());
+        bp.get_page(&hf, 1, None).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        // the waiting request gets what was cached by the load
+        let mut p0 = Page::new(0);
+        let bytes = get_random_byte_vec(40);
+        p0.add_value(&bytes);
+        bp.put_page(&hf, p0, true, None).unwrap();
+        bp.unmark_loading(1, 0);
+        assert_eq!(Some(bytes), waiting.join().unwrap().get_value(0));
+    }
+
+    #[test]
+    fn test_bp_get_pages_pinned() {
+        init();
+        let tdir = TempDir::new(gen_random_dir(), true);
+        let mut path = tdir.to_path_buf();
+        path.push(gen_rand_string(4));
+        path.set_extension("hf");
+        let hf = Arc::new(HeapFile::new(path, 1).unwrap());
+        for pid in 0..3 {
+            hf.write_page_to_file(Page::new(pid)).unwrap();
+        }
+        let bp = BufferPool::new(BufferPoolConfig{capacity: 2, policy: EvictionPolicy::Lru});
+
+        // the pages come pinned, so caching the last one doesn't evict the first ones
+        let pages = bp.get_pages(&hf, 0, 3, None).unwrap();
+        assert_eq!(3, pages.len());
+        for pid in 0..3 {
+            assert_eq!(1, bp.pin_count(1, pid));
+        }
+        assert!(bp.is_cached(1, 0) && bp.is_cached(1, 1));
+        bp.put_page(&hf, Page::new(3), false, None).unwrap();
+        assert!(!bp.is_cached(1, 3));
+
+        drop(pages);
+        for pid in 0..3 {
+            assert_eq!(0, bp.pin_count(1, pid));
+        }
+        bp.put_page(&hf, Page::new(3), false, None).unwrap();
+        assert!(bp.is_cached(1, 3));
+    }
 }
@@ -0,0 +1,644 @@
+#[allow(unused_imports)]
+use crate::heapfile::HeapFile;
+#[allow(unused_imports)]
+use crate::page::Page;
+#[allow(unused_imports)]
+use crate::pin::{PagePin, PinTable};
+#[allow(unused_imports)]
+use common::ids::{ContainerId, PageId};
+#[allow(unused_imports)]
+use common::io_stats::IoCounters;
+#[allow(unused_imports)]
+use common::{CrustyError, PAGE_SLOTS};
+#[allow(unused_imports)]
+use std::collections::{BTreeMap, HashMap, HashSet};
+#[allow(unused_imports)]
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+
+/*  enum EvictionPolicy
+ *  Purpose:
+ *      which unpinned page the buffer pool evicts when it is full
+ *  Variants:
+ *      Lru: the least recently used page
+ *      Clock: sweeps the frames in a circle, giving pages used since the last sweep a second chance
+ *      Fifo: the page that was cached first, no matter how often it was used since
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    Lru,
+    Clock,
+    Fifo,
+}
+
+/*  struct BufferPoolConfig
+ *  Purpose:
+ *      settings of the buffer pool, given to StorageManager::with_config
+ *  Elements:
+ *      capacity: how many pages the pool caches at most
+ *      policy: which page is evicted when the pool is full
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferPoolConfig {
+    pub capacity: usize,
+    pub policy: EvictionPolicy,
+}
+
+impl Default for BufferPoolConfig {
+    fn default() -> Self {
+        BufferPoolConfig{capacity: PAGE_SLOTS, policy: EvictionPolicy::Lru}
+    }
+}
+
+/*  struct Frame
+ *  Purpose:
+ *      a cached page
+ *  Elements:
+ *      hf: the heapfile the page belongs to, so it can be written back when evicted
+ *      page: the cached page
+ *      dirty: if the page changed since it was read from or written to the heapfile
+ *      last_used: tick of the latest access, used to pick the hot pages
+ *      pos: the key of the page in the eviction order of Lru and Fifo
+ *      referenced: if the page was used since the clock hand last passed it, used by Clock
+ *      uses: how many times the page was read since it was cached, used to pick the hot pages
+ */
+struct Frame {
+    hf: Arc<HeapFile>,
+    page: Page,
+    dirty: bool,
+    last_used: u64,
+    pos: u64,
+    referenced: bool,
+    uses: u64,
+}
+
+/// First position handed out in the eviction order, cold pages are placed before it
+const FIRST_POS: u64 = 1 << 62;
+
+/*  struct Frames
+ *  Purpose:
+ *      the frame table, kept behind a single lock
+ *  Elements:
+ *      table: the frame of every cached (container, page)
+ *      tick: counts accesses, used to order the frames by recency
+ *      order: the cached pages by position, the first is evicted first under Lru and Fifo
+ *      next_pos: the last position handed out at the end of order
+ *      next_cold: the last position handed out at the front of order
+ *      ring: the cached pages in the order the clock hand visits them, only kept under Clock
+ *      hand: index in ring of the next page the clock hand looks at
+ *      loading: the pages being read from their heapfile without holding the lock
+ */
+struct Frames {
+    table: HashMap<(ContainerId, PageId), Frame>,
+    tick: u64,
+    order: BTreeMap<u64, (ContainerId, PageId)>,
+    next_pos: u64,
+    next_cold: u64,
+    ring: Vec<(ContainerId, PageId)>,
+    hand: usize,
+    loading: HashSet<(ContainerId, PageId)>,
+}
+
+impl Frames {
+    /*  place
+     *      purpose: put a page at the end of the eviction order, or at its front if it's cold
+     *  Outputs:
+     *      the position of the page, to be stored in its frame
+     */
+    fn place(&mut self, key: (ContainerId, PageId), cold: bool) -> u64 {
+        let pos = if cold {
+            self.next_cold -= 1;
+            self.next_cold
+        } else {
+            self.next_pos += 1;
+            self.next_pos
+        };
+        self.order.insert(pos, key);
+        pos
+    }
+    /*  move_to
+     *      purpose: move a cached page to the end of the eviction order, or its front if cold
+     */
+    fn move_to(&mut self, key: (ContainerId, PageId), cold: bool) {
+        let old = match self.table.get(&key) {
+            Some(frame) => frame.pos,
+            None => return,
+        };
+        self.order.remove(&old);
+        let pos = self.place(key, cold);
+        self.table.get_mut(&key).unwrap().pos = pos;
+    }
+    /*  remove
+     *      purpose: drop a cached page from the table and the eviction order
+     */
+    fn remove(&mut self, key: &(ContainerId, PageId)) -> Option<Frame> {
+        let frame = self.table.remove(key)?;
+        self.order.remove(&frame.pos);
+        Some(frame)
+    }
+}
+
+/*  struct BufferPool
+ *  Purpose:
+ *      caches pages so repeated reads of the same page don't go to the heapfile
+ *  Elements:
+ *      capacity: how many pages are cached at most
+ *      policy: which page is evicted when the pool is full
+ *      frames: the frame table
+ *      loaded: signalled whenever pages are done loading
+ *      pins: how many PagePin guards hold each page, pinned pages are never evicted
+ *  Notes:
+ *      - a miss marks the page as loading and reads it without holding the frame table lock, so
+ *        reads of other pages go on meanwhile. get_page waits for a page another request is
+ *        loading instead of reading it again
+ *      - if every frame is pinned the page is handed out without being cached
+ *      - changed pages are only written back when evicted or flushed
+ *      - a frame only serves reads through the heapfile it was cached from. a reader still
+ *        holding the heapfile a truncate replaced reads its pages from that heapfile
+ */
+pub(crate) struct BufferPool {
+    capacity: usize,
+    policy: EvictionPolicy,
+    frames: Mutex<Frames>,
+    loaded: Condvar,
+    pins: PinTable,
+}
+
+impl BufferPool {
+    /*  new
+     *      purpose: create an empty buffer pool
+     *  Inputs:
+     *      config: the settings of the pool
+     *  Outputs:
+     *      a buffer pool without any cached page
+     */
+    pub(crate) fn new(config: BufferPoolConfig) -> Self {
+        BufferPool{capacity: config.capacity,
+                   policy: config.policy,
+                   frames: Mutex::new(Frames{table: HashMap::with_capacity(config.capacity), tick: 0,
+                                             order: BTreeMap::new(), next_pos: FIRST_POS, next_cold: FIRST_POS,
+                                             ring: Vec::new(), hand: 0, loading: HashSet::new()}),
+                   loaded: Condvar::new(),
+                   pins: Arc::new(RwLock::new(HashMap::new()))}
+    }
+    /*  pin
+     *      purpose: keep a page from being evicted until the returned guard is dropped
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      container_id: the container the page belongs to
+     *      page_id: the page to pin
+     *  Outputs:
+     *      a guard holding the pin
+     *  Notes:
+     *      - meant for iterators that hold on to a page across many next() calls
+     */
+    pub(crate) fn pin(&self, container_id: ContainerId, page_id: PageId) -> PagePin {
+        PagePin::new(container_id, page_id, self.pins.clone())
+    }
+    /*  pin_count
+     *      purpose: how many guards are holding a page
+     *  Outputs:
+     *      the number of live PagePin guards for the page, 0 if it can be evicted
+     */
+    #[allow(dead_code)]
+    pub(crate) fn pin_count(&self, container_id: ContainerId, page_id: PageId) -> usize {
+        *self.pins.read().unwrap().get(&(container_id, page_id)).unwrap_or(&0)
+    }
+    /*  get_page
+     *      purpose: get a page, reading it from the heapfile only if it's not cached
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      hf: the heapfile the page belongs to
+     *      page_id: the page to get
+     *      io: the counters of the transaction the page is read for, if its IO is tracked
+     *  Outputs:
+     *      a copy of the page, or a CrustyError if it had to be read and the read failed
+     */
+    pub(crate) fn get_page(&self, hf: &Arc<HeapFile>, page_id: PageId, io: Option<&IoCounters>) -> Result<Page, CrustyError> {
+        let key = (hf.container_id, page_id);
+        let mut frames = self.loaded.wait_while(self.frames.lock().unwrap(), |f| f.loading.contains(&key)).unwrap();
+        frames.tick += 1;
+        if let Some(page) = self.hit(&mut frames, hf, page_id) {
+            return Ok(page);
+        }
+        frames.loading.insert(key);
+        drop(frames);
+        let read = hf.read_page_from_file(page_id);
+        let mut frames = self.done_loading(&[key]);
+        let page = read?;
+        if let Some(io) = io {
+            io.add_read(1);
+        }
+        // a write while the page was read is newer than what was read
+        if let Some(newer) = self.hit(&mut frames, hf, page_id) {
+            return Ok(newer);
+        }
+        self.cache_read(&mut frames, hf, page.clone(), io)?;
+        Ok(page)
+    }
+    /*  get_pages
+     *      purpose: get consecutive pages, reading the ones that aren't cached with a single read
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      hf: the heapfile the pages belong to
+     *      start: the first page to get
+     *      count: how many pages to get
+     *      io: the counters of the transaction the pages are read for, if its IO is tracked
+     *  Outputs:
+     *      copies of the pages in order along with their pins, fewer than count if the heapfile
+     *      ends first, or a CrustyError if the read failed
+     *  Notes:
+     *      - the read covers the pages from the first to the last one that isn't cached, the
+     *        cached pages in between are taken from the pool since they may be newer
+     *      - every page is pinned while the frame table is locked, before it can be evicted, and
+     *        stays pinned until the caller drops the pin
+     *      - pages another request is loading are read again rather than waited for
+     */
+    pub(crate) fn get_pages(&self, hf: &Arc<HeapFile>, start: PageId, count: PageId, io: Option<&IoCounters>)
+        -> Result<Vec<(Page, PagePin)>, CrustyError> {
+        let cid = hf.container_id;
+        let mut frames = self.frames.lock().unwrap();
+        frames.tick += 1;
+        let mut pages: Vec<Option<(Page, PagePin)>> = (start..start + count)
+            .map(|pid| self.hit(&mut frames, hf, pid).map(|page| (page, self.pin(cid, pid))))
+            .collect();
+        if let (Some(first), Some(last)) = (pages.iter().position(Option::is_none), pages.iter().rposition(Option::is_none)) {
+            let marked: Vec<(ContainerId, PageId)> = (first..=last)
+                .filter(|i| pages[*i].is_none())
+                .map(|i| (cid, start + i as PageId))
+                .filter(|key| frames.loading.insert(*key))
+                .collect();
+            drop(frames);
+            let read = hf.read_pages_from_file(start + first as PageId, (last - first + 1) as PageId);
+            let mut frames = self.done_loading(&marked);
+            let read = read?;
+            if let Some(io) = io {
+                io.add_read(read.len() as u64);
+            }
+            for (page, slot) in read.into_iter().zip(pages[first..].iter_mut()) {
+                if slot.is_none() {
+                    let pid = page.get_page_id();
+                    let page = match self.hit(&mut frames, hf, pid) {
+                        Some(newer) => newer,
+                        None => {
+                            self.cache_read(&mut frames, hf, page.clone(), io)?;
+                            page
+                        }
+                    };
+                    *slot = Some((page, self.pin(cid, pid)));
+                }
+            }
+        }
+        Ok(pages.into_iter().map_while(|page| page).collect())
+    }
+    /*  done_loading
+     *      purpose: lock the frame table again after reading pages, and wake up the requests
+     *          waiting for them
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      keys: the pages that were marked as loading
+     *  Outputs:
+     *      the locked frame table
+     */
+    fn done_loading(&self, keys: &[(ContainerId, PageId)]) -> MutexGuard<'_, Frames> {
+        let mut frames = self.frames.lock().unwrap();
+        for key in keys {
+            frames.loading.remove(key);
+        }
+        self.loaded.notify_all();
+        frames
+    }
+    /*  hit
+     *      purpose: get a cached page, counting the access
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      frames: the locked frame table
+     *      hf: the heapfile the page is read through
+     *      page_id: the page to get
+     *  Outputs:
+     *      a copy of the page, None if it isn't cached from hf
+     */
+    fn hit(&self, frames: &mut Frames, hf: &Arc<HeapFile>, page_id: PageId) -> Option<Page> {
+        let key = (hf.container_id, page_id);
+        let tick = frames.tick;
+        if !Arc::ptr_eq(&frames.table.get(&key)?.hf, hf) {
+            return None;
+        }
+        if self.policy == EvictionPolicy::Lru {
+            frames.move_to(key, false);
+        }
+        let frame = frames.table.get_mut(&key).unwrap();
+        frame.last_used = tick;
+        frame.referenced = true;
+        frame.uses += 1;
+        Some(frame.page.clone())
+    }
+    /*  cache_read
+     *      purpose: cache a page that was just read from its heapfile
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      frames: the locked frame table
+     *      hf: the heapfile the page was read from
+     *      page: the page
+     *      io: the counters of the transaction the page was read for, if its IO is tracked
+     *  Outputs:
+     *      Ok(()), or a CrustyError if writing back an evicted page failed
+     *  Notes:
+     *      - a page cached from another heapfile of the container is left alone, it's either newer
+     *        or dirty
+     */
+    fn cache_read(&self, frames: &mut Frames, hf: &Arc<HeapFile>, page: Page, io: Option<&IoCounters>)
+        -> Result<(), CrustyError> {
+        let key = (hf.container_id, page.get_page_id());
+        if frames.table.contains_key(&key) {
+            return Ok(());
+        }
+        self.cache(frames, hf, page, false, io)?;
+        if let Some(frame) = frames.table.get_mut(&key) {
+            frame.uses = 1;
+        }
+        Ok(())
+    }
+    /*  preload
+     *      purpose: read a page into the pool ahead of the queries that will use it
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      hf: the heapfile the page belongs to
+     *      page_id: the page to load
+     *  Outputs:
+     *      true if the page is cached afterwards, or a CrustyError if the read failed
+     *  Notes:
+     *      - a preloaded page isn't counted as used, so it is the first to go under Lru and Clock
+     *        and only becomes hot once queries read it
+     */
+    pub(crate) fn preload(&self, hf: &Arc<HeapFile>, page_id: PageId) -> Result<bool, CrustyError> {
+        let key = (hf.container_id, page_id);
+        let mut frames = self.loaded.wait_while(self.frames.lock().unwrap(), |f| f.loading.contains(&key)).unwrap();
+        if frames.table.contains_key(&key) {
+            return Ok(true);
+        }
+        frames.loading.insert(key);
+        drop(frames);
+        let read = hf.read_page_from_file(page_id);
+        let mut frames = self.done_loading(&[key]);
+        let page = read?;
+        if frames.table.contains_key(&key) {
+            return Ok(true);
+        }
+        self.cache(&mut frames, hf, page, false, None)?;
+        if self.policy == EvictionPolicy::Lru {
+            frames.move_to(key, true);
+        }
+        if let Some(frame) = frames.table.get_mut(&key) {
+            frame.last_used = 0;
+            frame.referenced = false;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+    /*  hot_pages
+     *      purpose: list the cached pages, the most used first
+     *  Outputs:
+     *      the (container, page) of every cached page, ties broken by the most recently used first
+     */
+    pub(crate) fn hot_pages(&self) -> Vec<(ContainerId, PageId)> {
+        let frames = self.frames.lock().unwrap();
+        let mut pages: Vec<(&(ContainerId, PageId), &Frame)> = frames.table.iter().collect();
+        pages.sort_by(|(a_key, a), (b_key, b)| {
+            b.uses.cmp(&a.uses).then(b.last_used.cmp(&a.last_used)).then(a_key.cmp(b_key))
+        });
+        pages.into_iter().map(|(key, _)| *key).collect()
+    }
+    /*  capacity
+     *      purpose: how many pages the pool caches at most
+     */
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /*  put_page
+     *      purpose: cache a page that was just changed
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      hf: the heapfile the page belongs to
+     *      page: the new version of the page
+     *      dirty: true if the heapfile doesn't have this version of the page yet
+     *      io: the counters of the transaction that changed the page, if its IO is tracked
+     *  Outputs:
+     *      Ok(()), or a CrustyError if writing back an evicted page failed
+     *  Notes:
+     *      - a dirty page stays dirty until it's evicted or flushed, even if a clean copy is put later
+     */
+    pub(crate) fn put_page(&self, hf: &Arc<HeapFile>, page: Page, dirty: bool, io: Option<&IoCounters>)
+        -> Result<(), CrustyError> {
+        let mut frames = self.frames.lock().unwrap();
+        frames.tick += 1;
+        self.cache(&mut frames, hf, page, dirty, io)
+    }
+    /*  flush
+     *      purpose: write the dirty cached pages back to their heapfiles, keeping them cached
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      container_id: only flush the pages of this container, None to flush every page
+     *  Outputs:
+     *      the number of pages written, or a CrustyError if a write failed
+     *  Notes:
+     *      - pages written before a failed write stay clean, the others stay dirty
+     */
+    pub(crate) fn flush(&self, container_id: Option<ContainerId>) -> Result<usize, CrustyError> {
+        let mut frames = self.frames.lock().unwrap();
+        let mut written = 0;
+        for (key, frame) in frames.table.iter_mut() {
+            if !frame.dirty || container_id.is_some_and(|cid| cid != key.0) {
+                continue;
+            }
+            frame.hf.write_page_to_file(frame.page.clone())?;
+            frame.dirty = false;
+            written += 1;
+        }
+        debug!("heapstore::buffer_pool flushed {} pages of {:?}", written, container_id);
+        Ok(written)
+    }
+    /*  num_pages
+     *      purpose: how many pages a container has counting the dirty pages past the end of its heapfile
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      container_id: the container
+     *      file_pages: how many pages its heapfile has on disk
+     *  Outputs:
+     *      file_pages, or one more than the highest dirty page id if that is larger
+     */
+    pub(crate) fn num_pages(&self, container_id: ContainerId, file_pages: PageId) -> PageId {
+        let frames = self.frames.lock().unwrap();
+        frames.table.iter()
+            .filter(|(key, frame)| key.0 == container_id && frame.dirty)
+            .map(|(key, _)| key.1 + 1)
+            .fold(file_pages, PageId::max)
+    }
+    /*  discard_container
+     *      purpose: drop the cached pages of a container without writing them back
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      container_id: the container whose heapfile was removed or replaced
+     */
+    pub(crate) fn discard_container(&self, container_id: ContainerId) {
+        let mut frames = self.frames.lock().unwrap();
+        frames.table.retain(|key, _| key.0 != container_id);
+        frames.order.retain(|_, key| key.0 != container_id);
+        frames.ring.retain(|key| key.0 != container_id);
+        frames.hand = if frames.ring.is_empty() { 0 } else { frames.hand % frames.ring.len() };
+    }
+    /*  clear
+     *      purpose: drop every cached page without writing it back
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *  Notes:
+     *      - pins are kept, PagePin guards still release theirs
+     */
+    pub(crate) fn clear(&self) {
+        let mut frames = self.frames.lock().unwrap();
+        frames.table.clear();
+        frames.order.clear();
+        frames.ring.clear();
+        frames.hand = 0;
+    }
+    /*  num_frames
+     *      purpose: how many pages are cached right now
+     */
+    #[allow(dead_code)]
+    pub(crate) fn num_frames(&self) -> usize {
+        self.frames.lock().unwrap().table.len()
+    }
+    /*  is_cached
+     *      purpose: check if a page is in the pool, without counting it as an access
+     */
+    #[allow(dead_code)]
+    pub(crate) fn is_cached(&self, container_id: ContainerId, page_id: PageId) -> bool {
+        self.frames.lock().unwrap().table.contains_key(&(container_id, page_id))
+    }
+    /*  mark_loading
+     *      purpose: mark a page as loading, like a miss does while it reads the page
+     */
+    #[cfg(test)]
+    pub(crate) fn mark_loading(&self, container_id: ContainerId, page_id: PageId) {
+        self.frames.lock().unwrap().loading.insert((container_id, page_id));
+    }
+    /*  unmark_loading
+     *      purpose: end a load started with mark_loading, waking up the requests waiting for it
+     */
+    #[cfg(test)]
+    pub(crate) fn unmark_loading(&self, container_id: ContainerId, page_id: PageId) {
+        drop(self.done_loading(&[(container_id, page_id)]));
+    }
+    /*  cache
+     *      purpose: put a page in a frame, evicting an unpinned page chosen by the policy if full
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      frames: the locked frame table
+     *      hf: the heapfile the page belongs to
+     *      page: the page to cache
+     *      dirty: if the page has to be written back when evicted
+     *      io: the counters of the transaction the page is cached for, the writes it causes are
+     *          counted there
+     *  Outputs:
+     *      Ok(()) even if every frame is pinned and the page isn't cached, a CrustyError if
+     *      writing back the evicted page or writing through the uncached page failed
+     *  Notes:
+     *      - if writing back the victim fails, the victim stays cached and dirty and the page
+     *        isn't cached
+     */
+    fn cache(&self, frames: &mut Frames, hf: &Arc<HeapFile>, page: Page, dirty: bool, io: Option<&IoCounters>)
+        -> Result<(), CrustyError> {
+        let key = (hf.container_id, page.get_page_id());
+        let tick = frames.tick;
+        if frames.table.contains_key(&key) {
+            if self.policy == EvictionPolicy::Lru {
+                frames.move_to(key, false);
+            }
+            let frame = frames.table.get_mut(&key).unwrap();
+            frame.hf = hf.clone();
+            frame.page = page;
+            frame.dirty = frame.dirty || dirty;
+            frame.last_used = tick;
+            frame.referenced = true;
+            return Ok(());
+        }
+        let mut ring_pos = None;
+        if frames.table.len() >= self.capacity {
+            match self.choose_victim(frames) {
+                Some((victim, victim_ring_pos)) => {
+                    let frame = &frames.table[&victim];
+                    debug!("heapstore::buffer_pool evicting {:?} dirty: {}", victim, frame.dirty);
+                    // the victim is only dropped once it's written back, a failed write leaves it cached
+                    if frame.dirty {
+                        frame.hf.write_page_to_file(frame.page.clone())?;
+                        if let Some(io) = io {
+                            io.add_written(1);
+                        }
+                    }
+                    frames.remove(&victim);
+                    ring_pos = victim_ring_pos;
+                }
+                None => {
+                    debug!("heapstore::buffer_pool every frame is pinned, not caching {:?}", key);
+                    // a dirty page that can't be cached is written through so it isn't lost
+                    if dirty {
+                        hf.write_page_to_file(page)?;
+                        if let Some(io) = io {
+                            io.add_written(1);
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        // the new page takes the place of the evicted one, so the clock hand reaches it last
+        match ring_pos {
+            Some(pos) => frames.ring[pos] = key,
+            None if self.policy == EvictionPolicy::Clock => frames.ring.push(key),
+            None => {}
+        }
+        let pos = frames.place(key, false);
+        frames.table.insert(key, Frame{hf: hf.clone(), page, dirty, last_used: tick, pos,
+                                       referenced: true, uses: 0});
+        Ok(())
+    }
+    /*  choose_victim
+     *      purpose: pick the page to evict according to the policy
+     *  Inputs:
+     *      &self: a reference to the buffer pool
+     *      frames: the locked frame table
+     *  Outputs:
+     *      the page to evict and, under Clock, its index in the ring. None if every cached page
+     *      is pinned
+     *  Notes:
+     *      - Lru and Fifo take the first unpinned page of the eviction order, Lru moves a page to
+     *        its end whenever it's used and Fifo only when it's cached
+     *      - the clock hand clears the referenced bits it passes, so it finds a page within two sweeps
+     *        unless they are all pinned
+     */
+    fn choose_victim(&self, frames: &mut Frames) -> Option<((ContainerId, PageId), Option<usize>)> {
+        let pins = self.pins.read().unwrap();
+        match self.policy {
+            EvictionPolicy::Lru | EvictionPolicy::Fifo => {
+                frames.order.values().find(|key| !pins.contains_key(key)).map(|key| (*key, None))
+            }
+            EvictionPolicy::Clock => {
+                let n = frames.ring.len();
+                for _ in 0..2 * n {
+                    let index = frames.hand;
+                    let key = frames.ring[index];
+                    frames.hand = (frames.hand + 1) % n;
+                    if pins.contains_key(&key) {
+                        continue;
+                    }
+                    let frame = frames.table.get_mut(&key).unwrap();
+                    if frame.referenced {
+                        frame.referenced = false;
+                    } else {
+                        return Some((key, Some(index)));
+                    }
+                }
+                None
+            }
+        }
+    }
+}
@@ -3,21 +3,57 @@ use crate::page::Page;
 #[allow(unused_imports)]
 use common::ids::{ContainerId, PageId};
 #[allow(unused_imports)]
+use common::storage_trait::{allocated_bytes, ContainerUsage, PageCompression};
+#[allow(unused_imports)]
 use common::{CrustyError, PAGE_SIZE};
 #[allow(unused_imports)]
+use std::convert::TryInto;
+#[allow(unused_imports)]
 use std::fs::{File, OpenOptions};
 #[allow(unused_imports)]
 use std::io::prelude::*;
 #[allow(unused_imports)]
-use std::path::PathBuf;
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::path::{Path, PathBuf};
 #[allow(unused_imports)]
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 #[allow(unused_imports)]
 use std::sync::{Arc, RwLock};
 #[allow(unused_imports)]
 use std::io::BufWriter;
 #[allow(unused_imports)]
 use std::io::{Seek, SeekFrom};
+
+/// Stands in for the number of slots of a page written compressed. A page can't have that many
+/// slots, so the two are told apart by the same two bytes.
+const COMPRESSED_MARKER: u16 = u16::MAX;
+
+/// Bytes before the compressed page: its page_id, COMPRESSED_MARKER and the compressed length.
+const FRAME_HEADER_SIZE: usize = 6;
+
+/// Takes the place of COMPRESSED_MARKER in a frame of the frame file once a later write of its
+/// page has replaced it.
+const DEAD_MARKER: u16 = u16::MAX - 1;
+
+/// The extension of the frame file next to a heapfile.
+const FRAMES_EXTENSION: &str = "frames";
+
+/// The compressed pages of a heapfile, packed one after another in a file next to it.
+///
+/// A compressed page's own place in the heapfile is punched out, so the heapfile keeps its
+/// length but the filesystem gets the space back, and the frame file only grows by the
+/// compressed bytes. A page whose place in the heapfile reads as zeros is looked up here.
+pub(crate) struct FrameFile {
+    path: PathBuf,
+    // None until the first page is compressed, the file isn't created before
+    file: Option<File>,
+    // where the frame of every compressed page starts and how long it is
+    frames: HashMap<PageId, (u64, usize)>,
+    // where the next frame goes
+    end: u64,
+}
+
 /// The struct for a heap file.  
 ///
 /// HINT: You likely will want to design for interior mutability for concurrent accesses.
@@ -27,6 +63,14 @@ pub(crate) struct HeapFile {
     pub container_id: ContainerId, // container_id is the ID for the heapfile
     pub read_count: AtomicU16,
     pub write_count: AtomicU16,
+    // free bytes of every page, None until the first insert since the heapfile was opened or shrunk
+    pub free_space: RwLock<Option<Vec<u16>>>,
+    // how pages are compressed when they are written, see the container's metadata
+    pub compression: RwLock<PageCompression>,
+    // bytes compressed pages didn't have to write since the heapfile was opened
+    pub saved_bytes: AtomicU64,
+    // the compressed pages, locked after file
+    pub frames: RwLock<FrameFile>,
 }
 impl HeapFile {
     /*  new
@@ -38,8 +82,10 @@ impl HeapFile {
      *      Return Result<Self> if able to create.
      *  Notes:
      *      Errors could arise from permissions, space, etc when trying to create the file used by HeapFile.
+     *      The frame file of its compressed pages is opened too if there is one.
      */ 
     pub(crate) fn new(file_path: PathBuf, container_id: ContainerId) -> Result<Self, CrustyError> {
+        let frames = FrameFile::open(frames_path(&file_path))?;
         let mut options :OpenOptions = OpenOptions::new();
         let file  = options.read(true).write(true).create(true).open(&file_path).unwrap();
         let lock = RwLock::new(file);
@@ -51,6 +97,10 @@ impl HeapFile {
           //  page_count: num_pages as PageId,
             read_count: AtomicU16::new(0),
             write_count: AtomicU16::new(0),
+            free_space: RwLock::new(None),
+            compression: RwLock::new(PageCompression::None),
+            saved_bytes: AtomicU64::new(0),
+            frames: RwLock::new(frames),
         })
     }   
     /*  num_pages
@@ -81,6 +131,10 @@ impl HeapFile {
      *      - This could be an existing page or a new page
      *      - The underlying file can be part of your HeapFile implementation (e.g. stored as part of the struct).
      *      - you don't need to add new pages directly to your HeapFile struct (i.e. as long as you have other ways of accessing the pages).
+     *      - a compressed page is appended to the frame file and its place in the heapfile is
+     *        punched out, or zeroed where holes can't be punched. the frame is written first, so
+     *        a reader finds the page in one of the two
+     *      - a page written uncompressed replaces the frame of an earlier write
      */ 
     pub(crate) fn write_page_to_file(&self, page: Page) -> Result<(), CrustyError> {
         #[cfg(feature = "profile")]
@@ -88,20 +142,29 @@ impl HeapFile {
             self.write_count.fetch_add(1, Ordering::Relaxed);
         }
         // get access to the file we're working with and other pertinent info
-        let mut file = &*self.file.read().unwrap(); 
+        let file = self.file.read().unwrap(); 
         //get pertinent information for the page
         let page_id = page.header.page_id;
 
-        // move the cursor to where we want to start inputting data
-        file.seek(SeekFrom::Start((page_id as usize * PAGE_SIZE) as u64));
-        
-        // everything should be right up until this point
-        let mut buffer = BufWriter::new(file);
-        let bytes = page.get_bytes();
-        for i in 0..PAGE_SIZE{
-            buffer.write(&bytes[i..i+1]).unwrap();
+        let compression = *self.compression.read().unwrap();
+        let bytes = encode_page(&page, compression);
+        let offset = (page_id as usize * PAGE_SIZE) as u64;
+        if bytes.len() < PAGE_SIZE {
+            self.frames.write().unwrap().append(page_id, &bytes)?;
+            self.saved_bytes.fetch_add((PAGE_SIZE - bytes.len()) as u64, Ordering::Relaxed);
+            // a new last page is left as a hole, so the file still counts it
+            if file.metadata()?.len() < offset + PAGE_SIZE as u64 {
+                file.set_len(offset + PAGE_SIZE as u64)?;
+            } else if !punch_hole(&file, offset, PAGE_SIZE as u64)? {
+                write_at(&file, &[0; PAGE_SIZE], offset)?;
+            }
+        } else {
+            // written at the page's offset without moving the cursor, readers may share the file
+            write_at(&file, &bytes, offset)?;
+            if self.frames.read().unwrap().contains(page_id) {
+                self.frames.write().unwrap().remove(page_id)?;
+            }
         }
-        buffer.flush().unwrap();
         return Ok(());
     }
     /* read_page_from_file
@@ -112,27 +175,531 @@ impl HeapFile {
      *  outputs:
      *      either the page that we wanted to retrieve or a CrustyError
      *  Notes:
-     *      - Errors could arise from the filesystem or invalid pageId
+     *      - Errors could arise from the filesystem or invalid pageId, or a page whose checksum
+     *        doesn't match because it was corrupted on disk
      *      - Given a page_id we need the right offset for the page and we need to return the page itself
+     *      - a page past the end of the file was never written, and is empty
      */ 
     pub(crate) fn read_page_from_file(&self, pid: PageId) -> Result<Page, CrustyError> {
         #[cfg(feature = "profile")]
         {
             self.read_count.fetch_add(1, Ordering::Relaxed);
         }
-        let mut file = &*self.file.read().unwrap();
+        let file = self.file.read().unwrap();
         let start_index= PAGE_SIZE * pid as usize;
-        // we need to find the right place to start
-        file.seek(SeekFrom::Start(start_index as u64));
         let mut buffer = [0; PAGE_SIZE];
-        file.read_exact(&mut buffer);
+        match read_at(&file, &mut buffer, start_index as u64) {
+            Ok(()) => self.decode_slot(&buffer, pid),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(Page::new(pid)),
+            Err(e) => Err(e.into()),
+        }
+    }
+    /*  read_pages_from_file
+     *      purpose: read consecutive pages of the heapfile with a single read
+     *  inputs:
+     *      &self: a reference to the heapfile that we're pulling the pages from
+     *      start: the first page to read
+     *      count: how many pages to read
+     *  outputs:
+     *      the pages in order, fewer than count if the heapfile ends first, or a CrustyError
+     *  Notes:
+     *      - every page read counts as a read, the same as with read_page_from_file
+     *      - a corrupted page fails the whole read
+     */
+    pub(crate) fn read_pages_from_file(&self, start: PageId, count: PageId) -> Result<Vec<Page>, CrustyError> {
+        let count = count.min(self.num_pages().saturating_sub(start));
+        #[cfg(feature = "profile")]
+        {
+            self.read_count.fetch_add(count, Ordering::Relaxed);
+        }
+        let file = self.file.read().unwrap();
+        let mut buffer = vec![0; count as usize * PAGE_SIZE];
+        read_at(&file, &mut buffer, (start as usize * PAGE_SIZE) as u64)?;
+        buffer.chunks(PAGE_SIZE).zip(start..).map(|(bytes, pid)| self.decode_slot(bytes, pid)).collect()
+    }
+    /*  decode_slot
+     *      purpose: build a page from the bytes at its place in the heapfile
+     *  inputs:
+     *      &self: a reference to the heapfile the bytes were read from
+     *      bytes: the PAGE_SIZE bytes at the page's offset
+     *      pid: the page_id of the page
+     *  outputs:
+     *      the page, or a CrustyError if it is corrupted
+     *  Notes:
+     *      - a place that reads as zeros holds a compressed page if the frame file has it,
+     *        and an empty page otherwise
+     */
+    fn decode_slot(&self, bytes: &[u8], pid: PageId) -> Result<Page, CrustyError> {
+        if bytes.iter().all(|b| *b == 0) {
+            if let Some(frame) = self.frames.read().unwrap().read(pid)? {
+                return decode_page(&frame, pid);
+            }
+        }
+        decode_page(bytes, pid)
+    }
+    /*  allocate_pages
+     *      purpose: grow the heapfile by an extent of empty pages at once
+     *  inputs:
+     *      &self: a reference to the heapfile we're growing
+     *      first: the first page of the extent
+     *      count: how many pages the extent has
+     *  outputs:
+     *      Ok(()) once the file covers the extent, or a CrustyError
+     *  Notes:
+     *      - pages between the end of the file and first are added too, they could be cached in
+     *        the buffer pool and not written back yet
+     *      - the new pages read back as zeros, which read_page_from_file takes for empty pages
+     *      - the space is reserved with fallocate where the filesystem supports it, so the pages of
+     *        an extent tend to be next to each other on disk. otherwise the file is just extended
+     *      - growing doesn't count as a write, no page was written
+     */
+    pub(crate) fn allocate_pages(&self, first: PageId, count: PageId) -> Result<(), CrustyError> {
+        // hold the write lock so no page is appended while the file grows
+        let file = self.file.write().unwrap();
+        let len = file.metadata()?.len();
+        let new_len = ((first as usize + count as usize) * PAGE_SIZE) as u64;
+        if new_len > len && !allocate(&file, len, new_len - len)? {
+            file.set_len(new_len)?;
+        }
+        Ok(())
+    }
+    /*  reclaim_space
+     *      purpose: give the space of empty pages back to the filesystem
+     *  inputs:
+     *      &self: a reference to the heapfile we're shrinking
+     *      punch_holes: if empty pages before the last used page should be punched out
+     *  outputs:
+     *      the number of bytes given back, or a CrustyError
+     *  Notes:
+     *      - trailing empty pages are cut off with set_len, which works on every filesystem
+     *      - punched pages keep the file length but leave it sparse, so backups that don't
+     *        keep holes copy the full length again. that's why punching is optional
+     *      - pages that are already all zeros are taken to be holes and not counted again
+     *      - the frames of empty compressed pages are dropped, and the frame file is packed so
+     *        the frames replaced by later writes are given back too
+     */
+    pub(crate) fn reclaim_space(&self, punch_holes: bool) -> Result<u64, CrustyError> {
+        // hold the write lock so no page is written while we look at and cut the file
+        let file = self.file.write().unwrap();
+        let mut frames = self.frames.write().unwrap();
+        let num_pages = file.metadata()?.len() as usize / PAGE_SIZE;
+        // (empty, all zeros) for every page
+        let mut pages = Vec::with_capacity(num_pages);
+        let mut buffer = [0; PAGE_SIZE];
+        for pid in 0..num_pages {
+            read_at(&file, &mut buffer, (pid * PAGE_SIZE) as u64)?;
+            let zeros = buffer.iter().all(|b| *b == 0);
+            let page = if zeros {
+                frames.read(pid as PageId)?.map(|frame| decode_page(&frame, pid as PageId)).transpose()?
+            } else {
+                Some(decode_page(&buffer, pid as PageId)?)
+            };
+            // a page with tombstones isn't empty, punching it would hand their slot ids out again
+            let empty = page.is_none_or(|page| page.header.slots.is_empty());
+            pages.push((empty, zeros));
+        }
+        let keep = pages.iter().rposition(|(empty, _)| !empty).map_or(0, |pid| pid + 1);
+        for (pid, (empty, _)) in pages.iter().enumerate() {
+            if (*empty || pid >= keep) && frames.contains(pid as PageId) {
+                frames.remove(pid as PageId)?;
+            }
+        }
+        let mut reclaimed = frames.pack()? + ((num_pages - keep) * PAGE_SIZE) as u64;
+        if keep < num_pages {
+            file.set_len((keep * PAGE_SIZE) as u64)?;
+        }
+        if punch_holes {
+            for (pid, (empty, zeros)) in pages[..keep].iter().enumerate() {
+                if *empty && !*zeros && punch_hole(&file, (pid * PAGE_SIZE) as u64, PAGE_SIZE as u64)? {
+                    reclaimed += PAGE_SIZE as u64;
+                }
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    /*  usage
+     *      purpose: measure how much of the heapfile holds values
+     *  inputs:
+     *      &self: a reference to the heapfile
+     *  outputs:
+     *      the length of the file and the bytes of its values, or a CrustyError
+     *  Notes:
+     *      - every page is read, so this is as slow as a scan
+     *      - the length of the frame file is counted with the length of the heapfile
+     */
+    pub(crate) fn usage(&self) -> Result<ContainerUsage, CrustyError> {
+        let file = self.file.read().unwrap();
+        let file_len = file.metadata()?.len();
+        let mut live_bytes = 0;
+        let mut buffer = [0; PAGE_SIZE];
+        for pid in 0..file_len as usize / PAGE_SIZE {
+            read_at(&file, &mut buffer, (pid * PAGE_SIZE) as u64)?;
+            if buffer.iter().all(|b| *b == 0) && !self.frames.read().unwrap().contains(pid as PageId) {
+                continue;
+            }
+            live_bytes += self.decode_slot(&buffer, pid as PageId)?.header.slots.iter().map(|s| s.size as u64).sum::<u64>();
+        }
+        Ok(ContainerUsage{disk_bytes: file_len + self.frames.read().unwrap().len(), live_bytes})
+    }
+
+    /*  disk_size
+     *      purpose: measure the space the heapfile takes on disk
+     *  inputs:
+     *      &self: a reference to the heapfile
+     *  outputs:
+     *      the length of the heapfile and its frame file, and the bytes the filesystem allocated
+     *      for them, or a CrustyError
+     */
+    pub(crate) fn disk_size(&self) -> Result<(u64, u64), CrustyError> {
+        let meta = self.file.read().unwrap().metadata()?;
+        let (mut logical, mut allocated) = (meta.len(), allocated_bytes(&meta));
+        if let Some(meta) = self.frames.read().unwrap().metadata()? {
+            logical += meta.len();
+            allocated += allocated_bytes(&meta);
+        }
+        Ok((logical, allocated))
+    }
+}
+
+/*  frames_path
+ *      purpose: get where the frame file of a heapfile lives
+ *  inputs:
+ *      path: the path of the heapfile
+ *  outputs:
+ *      the path with the extension of frame files, next to the heapfile
+ */
+pub(crate) fn frames_path(path: &Path) -> PathBuf {
+    path.with_extension(FRAMES_EXTENSION)
+}
+
+impl FrameFile {
+    /*  open
+     *      purpose: open the frame file of a heapfile and find the frames in it
+     *  inputs:
+     *      path: where the frame file is, it doesn't have to exist
+     *  outputs:
+     *      the frame file, or a CrustyError if it can't be read or is corrupted
+     *  Notes:
+     *      - a frame cut short at the end was being appended, its page was still in the heapfile
+     *        and the frame is written over by the next one
+     */
+    fn open(path: PathBuf) -> Result<Self, CrustyError> {
+        let mut frame_file = FrameFile{path, file: None, frames: HashMap::new(), end: 0};
+        if !frame_file.path.exists() {
+            return Ok(frame_file);
+        }
+        let mut file = OpenOptions::new().read(true).write(true).open(&frame_file.path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut offset = 0;
+        while offset + FRAME_HEADER_SIZE <= bytes.len() {
+            let header = &bytes[offset..offset + FRAME_HEADER_SIZE];
+            let pid = u16::from_le_bytes(header[0..2].try_into().unwrap());
+            let marker = u16::from_le_bytes(header[2..4].try_into().unwrap());
+            let len = FRAME_HEADER_SIZE + u16::from_le_bytes(header[4..6].try_into().unwrap()) as usize;
+            if offset + len > bytes.len() {
+                break;
+            }
+            match marker {
+                COMPRESSED_MARKER => {
+                    frame_file.frames.insert(pid, (offset as u64, len));
+                }
+                DEAD_MARKER => {}
+                _ => {
+                    return Err(CrustyError::CrustyError(format!(
+                        "Frame file {:?} is corrupted at byte {}", frame_file.path, offset)))
+                }
+            }
+            offset += len;
+        }
+        frame_file.end = offset as u64;
+        frame_file.file = Some(file);
+        Ok(frame_file)
+    }
+    /*  contains
+     *      purpose: check if a page is compressed in the frame file
+     */
+    fn contains(&self, pid: PageId) -> bool {
+        self.frames.contains_key(&pid)
+    }
+    /*  len
+     *      purpose: get how many bytes of the frame file are in use, dead frames included
+     */
+    fn len(&self) -> u64 {
+        self.end
+    }
+    /*  metadata
+     *      purpose: get the metadata of the frame file, None if there is none yet
+     */
+    fn metadata(&self) -> Result<Option<std::fs::Metadata>, CrustyError> {
+        match &self.file {
+            Some(file) => Ok(Some(file.metadata()?)),
+            None => Ok(None),
+        }
+    }
+    /*  read
+     *      purpose: read the frame of a compressed page
+     *  inputs:
+     *      pid: the page to read
+     *  outputs:
+     *      the frame, None if the page isn't compressed, or a CrustyError
+     */
+    fn read(&self, pid: PageId) -> Result<Option<Vec<u8>>, CrustyError> {
+        match (&self.file, self.frames.get(&pid)) {
+            (Some(file), Some((offset, len))) => {
+                let mut frame = vec![0; *len];
+                read_at(file, &mut frame, *offset)?;
+                Ok(Some(frame))
+            }
+            _ => Ok(None),
+        }
+    }
+    /*  append
+     *      purpose: add the frame of a page to the end of the frame file
+     *  inputs:
+     *      pid: the page the frame holds
+     *      frame: the frame from encode_page
+     *  outputs:
+     *      Ok(()) once the frame is written and an earlier frame of the page is marked dead
+     *  Notes:
+     *      - the file is created with the first frame
+     */
+    fn append(&mut self, pid: PageId, frame: &[u8]) -> Result<(), CrustyError> {
+        if self.file.is_none() {
+            self.file = Some(OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&self.path)?);
+        }
+        write_at(self.file.as_ref().unwrap(), frame, self.end)?;
+        self.remove(pid)?;
+        self.frames.insert(pid, (self.end, frame.len()));
+        self.end += frame.len() as u64;
+        Ok(())
+    }
+    /*  remove
+     *      purpose: mark the frame of a page dead, it is given back by pack
+     *  inputs:
+     *      pid: the page whose frame goes
+     *  outputs:
+     *      Ok(()) also if the page has no frame
+     */
+    fn remove(&mut self, pid: PageId) -> Result<(), CrustyError> {
+        if let (Some(file), Some((offset, _))) = (&self.file, self.frames.remove(&pid)) {
+            write_at(file, &DEAD_MARKER.to_le_bytes(), offset + 2)?;
+        }
+        Ok(())
+    }
+    /*  pack
+     *      purpose: move the live frames to the front of the frame file and cut off the rest
+     *  outputs:
+     *      the bytes given back, or a CrustyError
+     *  Notes:
+     *      - frames only move towards the front, a frame is read whole before it is written
+     */
+    fn pack(&mut self) -> Result<u64, CrustyError> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(0),
+        };
+        let mut live: Vec<(PageId, u64, usize)> = self.frames.iter().map(|(pid, (offset, len))| (*pid, *offset, *len)).collect();
+        live.sort_by_key(|(_, offset, _)| *offset);
+        let mut end = 0;
+        for (pid, offset, len) in live {
+            if offset != end {
+                let mut frame = vec![0; len];
+                read_at(file, &mut frame, offset)?;
+                write_at(file, &frame, end)?;
+                self.frames.insert(pid, (end, len));
+            }
+            end += len as u64;
+        }
+        file.set_len(end)?;
+        let reclaimed = self.end - end;
+        self.end = end;
+        Ok(reclaimed)
+    }
+}
 
-        let new_page = Page::from_bytes(&buffer);
-        
-        Ok(new_page)
+/*  encode_page
+ *      purpose: serialize a page the way it is written to the heapfile
+ *  inputs:
+ *      page: the page to write
+ *      compression: how the page is compressed
+ *  outputs:
+ *      PAGE_SIZE bytes of the page, or a frame of the compressed page that is shorter
+ *  Notes:
+ *      - a frame is the page_id, COMPRESSED_MARKER and the compressed length, 2 bytes each,
+ *        followed by the compressed page. the page's checksum is inside the compressed bytes
+ *      - a page that doesn't get smaller is written as it is
+ */
+fn encode_page(page: &Page, compression: PageCompression) -> Vec<u8> {
+    let bytes = page.get_bytes();
+    if compression == PageCompression::None {
+        return bytes;
+    }
+    match snap::raw::Encoder::new().compress_vec(&bytes) {
+        Ok(compressed) if compressed.len() + FRAME_HEADER_SIZE < PAGE_SIZE => {
+            let mut frame = Vec::with_capacity(compressed.len() + FRAME_HEADER_SIZE);
+            frame.extend(page.header.page_id.to_le_bytes().to_vec());
+            frame.extend(COMPRESSED_MARKER.to_le_bytes().to_vec());
+            frame.extend((compressed.len() as u16).to_le_bytes().to_vec());
+            frame.extend(compressed);
+            frame
+        }
+        _ => bytes,
     }
 }
 
+/*  decode_page
+ *      purpose: build a page from the bytes read from its place in the heapfile
+ *  inputs:
+ *      bytes: the PAGE_SIZE bytes at the page's offset
+ *      pid: the page_id of the page
+ *  outputs:
+ *      the page, or a CrustyError if it is corrupted
+ *  Notes:
+ *      - a page that was punched out or never written reads back as zeros, and is empty
+ *      - compressed pages are decompressed, the buffer pool only ever holds decompressed pages
+ *      - bytes after a frame are left over from earlier writes and are ignored
+ *      - a page with another page_id was written to or read from the wrong offset
+ */
+fn decode_page(bytes: &[u8], pid: PageId) -> Result<Page, CrustyError> {
+    if bytes.iter().all(|b| *b == 0) {
+        return Ok(Page::new(pid));
+    }
+    let corrupted = |reason: String| CrustyError::CrustyError(format!("Page {} is corrupted, {}", pid, reason));
+    let page = if u16::from_le_bytes(bytes[2..4].try_into().unwrap()) != COMPRESSED_MARKER {
+        Page::from_bytes(bytes)?
+    } else {
+        // the frame header isn't covered by the checksum, so check it before trusting its length
+        let frame_pid = u16::from_le_bytes(bytes[0..2].try_into().unwrap());
+        if frame_pid != pid {
+            return Err(corrupted(format!("its frame holds page {}", frame_pid)));
+        }
+        let len = u16::from_le_bytes(bytes[4..6].try_into().unwrap()) as usize;
+        let compressed = bytes.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len)
+            .ok_or_else(|| corrupted(String::from("its compressed length is too long")))?;
+        let mut page = [0; PAGE_SIZE];
+        match snap::raw::Decoder::new().decompress(compressed, &mut page) {
+            Ok(PAGE_SIZE) => Page::from_bytes(&page)?,
+            Ok(n) => return Err(corrupted(format!("it decompresses to {} bytes", n))),
+            Err(e) => return Err(corrupted(format!("it can't be decompressed: {}", e))),
+        }
+    };
+    if page.get_page_id() != pid {
+        return Err(corrupted(format!("it holds page {}", page.get_page_id())));
+    }
+    Ok(page)
+}
+
+/*  read_at
+ *      purpose: fill a buffer with the bytes of a file at an offset
+ *  inputs:
+ *      file: the file to read
+ *      buffer: where the bytes go, as many as it holds are read
+ *      offset: where in the file the bytes start
+ *  outputs:
+ *      Ok(()) once the buffer is full, an UnexpectedEof error if the file ends first
+ *  Notes:
+ *      - the read doesn't go through the file's cursor, so threads sharing a heapfile, e.g. the
+ *        workers of a parallel scan, can read at the same time under the read lock
+ */
+#[cfg(unix)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buffer, offset)
+}
+
+#[cfg(not(unix))]
+fn read_at(mut file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<()> {
+    // no positional reads here, the cursor is moved
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)
+}
+
+/*  write_at
+ *      purpose: write bytes to a file at an offset
+ *  inputs:
+ *      file: the file to write
+ *      bytes: the bytes to write
+ *      offset: where in the file the bytes go
+ *  outputs:
+ *      Ok(()) once every byte is written
+ *  Notes:
+ *      - like read_at, the file's cursor isn't used, so a write can't move a read to another page
+ */
+#[cfg(unix)]
+fn write_at(file: &File, bytes: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(bytes, offset)
+}
+
+#[cfg(not(unix))]
+fn write_at(mut file: &File, bytes: &[u8], offset: u64) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(bytes)
+}
+
+/*  punch_hole
+ *      purpose: deallocate a range of a file without changing its length
+ *  inputs:
+ *      file: the file to punch
+ *      offset: where the range starts
+ *      len: how long the range is
+ *  outputs:
+ *      true if the range was punched, false if the filesystem can't punch holes
+ */
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, len: u64) -> Result<bool, CrustyError> {
+    use std::os::unix::io::AsRawFd;
+    let res = unsafe {
+        libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t, len as libc::off_t)
+    };
+    if res == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+        Ok(false)
+    } else {
+        Err(err.into())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _len: u64) -> Result<bool, CrustyError> {
+    Ok(false)
+}
+
+/*  allocate
+ *      purpose: reserve disk space for a range of a file, extending the file if needed
+ *  inputs:
+ *      file: the file to grow
+ *      offset: where the range starts
+ *      len: how long the range is
+ *  outputs:
+ *      true if the range was allocated, false if the filesystem can't allocate ahead
+ */
+#[cfg(target_os = "linux")]
+fn allocate(file: &File, offset: u64, len: u64) -> Result<bool, CrustyError> {
+    use std::os::unix::io::AsRawFd;
+    let res = unsafe { libc::fallocate(file.as_raw_fd(), 0, offset as libc::off_t, len as libc::off_t) };
+    if res == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+        Ok(false)
+    } else {
+        Err(err.into())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allocate(_file: &File, _offset: u64, _len: u64) -> Result<bool, CrustyError> {
+    Ok(false)
+}
+
 #[cfg(test)]
 #[allow(unused_must_use)]
 mod test {
@@ -194,5 +761,141 @@ mod test {
             assert_eq!(*hf.read_count.get_mut(), 3);
             assert_eq!(*hf.write_count.get_mut(), 2);
         }
+
+        // an extent of empty pages after the written ones
+        hf.allocate_pages(2, 4).unwrap();
+        assert_eq!(6, hf.num_pages());
+        assert_eq!(Page::new(5).get_bytes(), hf.read_page_from_file(5).unwrap().get_bytes());
+        assert_eq!(p1_bytes, hf.read_page_from_file(1).unwrap().get_bytes());
+    }
+
+    #[test]
+    fn hs_hf_corrupt_page() {
+        init();
+        let f = gen_random_dir();
+        let tdir = TempDir::new(f, true);
+        let mut f = tdir.to_path_buf();
+        f.push(gen_rand_string(4));
+        f.set_extension("hf");
+        let hf = HeapFile::new(f.to_path_buf(), 1).unwrap();
+
+        let mut p0 = Page::new(0);
+        p0.add_value(&get_random_byte_vec(100));
+        hf.write_page_to_file(p0).unwrap();
+        let mut p1 = Page::new(1);
+        p1.add_value(&get_random_byte_vec(100));
+        hf.write_page_to_file(p1).unwrap();
+
+        // flip a byte of page 1's value on disk
+        {
+            let mut file = &*hf.file.read().unwrap();
+            let mut byte = [0; 1];
+            file.seek(SeekFrom::Start((2 * PAGE_SIZE - 1) as u64)).unwrap();
+            file.read_exact(&mut byte).unwrap();
+            byte[0] ^= 0xff;
+            file.seek(SeekFrom::Start((2 * PAGE_SIZE - 1) as u64)).unwrap();
+            file.write_all(&byte).unwrap();
+        }
+        assert!(hf.read_page_from_file(0).is_ok());
+        assert!(hf.read_page_from_file(1).is_err());
+        assert!(hf.read_pages_from_file(0, 2).is_err());
+        assert!(hf.usage().is_err());
+    }
+
+    #[test]
+    fn hs_hf_compressed_page() {
+        init();
+        let f = gen_random_dir();
+        let tdir = TempDir::new(f, true);
+        let mut f = tdir.to_path_buf();
+        f.push(gen_rand_string(4));
+        f.set_extension("hf");
+        let hf = HeapFile::new(f.to_path_buf(), 1).unwrap();
+        *hf.compression.write().unwrap() = PageCompression::Snappy;
+
+        let mut p0 = Page::new(0);
+        p0.add_value(&vec![1; 500]);
+        p0.add_value(&vec![2; 500]);
+        let p0_bytes = p0.get_bytes();
+        hf.write_page_to_file(p0).unwrap();
+        assert_eq!(1, hf.num_pages());
+        assert!(hf.saved_bytes.load(Ordering::Relaxed) > PAGE_SIZE as u64 / 2);
+        assert_eq!(p0_bytes, hf.read_page_from_file(0).unwrap().get_bytes());
+
+        // random bytes don't compress and are written as they are
+        let mut p1 = Page::new(1);
+        p1.add_value(&get_random_byte_vec(PAGE_SIZE - 20));
+        let saved = hf.saved_bytes.load(Ordering::Relaxed);
+        let p1_bytes = p1.get_bytes();
+        hf.write_page_to_file(p1).unwrap();
+        assert_eq!(saved, hf.saved_bytes.load(Ordering::Relaxed));
+        assert_eq!(p1_bytes, hf.read_page_from_file(1).unwrap().get_bytes());
+
+        // a frame at another page's offset is an error too
+        {
+            let file = &*hf.file.read().unwrap();
+            let frame = hf.frames.read().unwrap().read(0).unwrap().unwrap();
+            let mut bytes = [0; PAGE_SIZE];
+            bytes[..frame.len()].clone_from_slice(&frame);
+            write_at(file, &bytes, 2 * PAGE_SIZE as u64).unwrap();
+        }
+        assert!(hf.read_page_from_file(2).is_err());
+
+        // a corrupted frame is an error, not a page
+        {
+            let frames = hf.frames.read().unwrap();
+            write_at(frames.file.as_ref().unwrap(), &[0xff; 4], FRAME_HEADER_SIZE as u64 + 2).unwrap();
+        }
+        assert!(hf.read_page_from_file(0).is_err());
+    }
+
+    #[test]
+    fn hs_hf_compression_frees_space() {
+        init();
+        let f = gen_random_dir();
+        let tdir = TempDir::new(f, true);
+        let mut f = tdir.to_path_buf();
+        f.push(gen_rand_string(4));
+        f.set_extension("hf");
+        let hf = HeapFile::new(f.to_path_buf(), 1).unwrap();
+
+        let pages: Vec<Vec<u8>> = (0..8).map(|pid| {
+            let mut page = Page::new(pid);
+            page.add_value(&vec![pid as u8; 1000]);
+            page.add_value(&vec![pid as u8 + 1; 1000]);
+            page.get_bytes()
+        }).collect();
+        for bytes in &pages {
+            hf.write_page_to_file(Page::from_bytes(bytes).unwrap()).unwrap();
+        }
+        let (_, uncompressed) = hf.disk_size().unwrap();
+        assert!(uncompressed >= 8 * PAGE_SIZE as u64);
+
+        // written again compressed, the heapfile keeps its length but gives its blocks back
+        *hf.compression.write().unwrap() = PageCompression::Snappy;
+        for bytes in &pages {
+            hf.write_page_to_file(Page::from_bytes(bytes).unwrap()).unwrap();
+        }
+        let (logical, compressed) = hf.disk_size().unwrap();
+        assert!(compressed < uncompressed / 4);
+        assert!(logical > 8 * PAGE_SIZE as u64);
+        assert_eq!(8, hf.num_pages());
+        let read: Vec<Vec<u8>> = hf.read_pages_from_file(0, 8).unwrap().iter().map(|p| p.get_bytes()).collect();
+        assert_eq!(pages, read);
+
+        // page 0 goes back in place, its frame is dead until the frame file is packed
+        *hf.compression.write().unwrap() = PageCompression::None;
+        hf.write_page_to_file(Page::from_bytes(&pages[0]).unwrap()).unwrap();
+        let frames_len = hf.frames.read().unwrap().len();
+        assert!(hf.reclaim_space(false).unwrap() > 0);
+        assert!(hf.frames.read().unwrap().len() < frames_len);
+
+        // the frames are found again once the heapfile is reopened
+        drop(hf);
+        let hf = HeapFile::new(f.to_path_buf(), 1).unwrap();
+        for (pid, bytes) in pages.iter().enumerate() {
+            assert_eq!(*bytes, hf.read_page_from_file(pid as PageId).unwrap().get_bytes());
+        }
+        assert_eq!(7, hf.frames.read().unwrap().frames.len());
     }
 }
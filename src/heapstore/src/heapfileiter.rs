@@ -1,10 +1,26 @@
 #[allow(unused_imports)]
+use crate::buffer_pool::BufferPool;
+#[allow(unused_imports)]
 use crate::heapfile::HeapFile;
 #[allow(unused_imports)]
-use crate::page::PageIter;
+use crate::page::{Page, PageIter};
+#[allow(unused_imports)]
+use crate::pin::PagePin;
 #[allow(unused_imports)]
 use common::ids::{ContainerId, PageId, TransactionId};
 #[allow(unused_imports)]
+use common::io_stats::IoCounters;
+#[allow(unused_imports)]
+use common::lock_manager::{LockManager, LockTarget};
+#[allow(unused_imports)]
+use common::storage_trait::ValueRefIterator;
+#[allow(unused_imports)]
+use common::ids::Permissions;
+#[allow(unused_imports)]
+use common::CrustyError;
+#[allow(unused_imports)]
+use std::collections::VecDeque;
+#[allow(unused_imports)]
 use std::sync::Arc;
 
 #[allow(dead_code)]
@@ -17,18 +33,47 @@ use std::sync::Arc;
 /*  struct HeapFileIterator
  *      Purpose: let's the storage manager iterate through all the values stored in a heapfile
  *  Elements:
- *      container_id: 
- *      txn_id:
- *      hfile: 
+ *      container_id: the container the values belong to
+ *      tid: the transaction the values are read for
+ *      hf: the heapfile, None if the container doesn't exist
+ *      pool: the buffer pool of the storage manager, the pages are read through it
+ *      num_pages: how many pages the heapfile had when the iterator was created, counting the
+ *          dirty pages past the end of its file
+ *      curr_p_iter: the iterator over the page we're at, None before the first page is read
+ *      next_pid: the page to read once curr_p_iter is done
+ *      prefetch: how many pages to read ahead of next_pid in the same read
+ *      prefetched: pages already read that come after the current one, with their pins
+ *      io: the counters of the transaction, if its IO is tracked
+ *      locks: the lock manager of the storage manager, every page is locked before it's read
+ *      perm: the permissions the pages are locked with
+ *      error: why a page could not be read, if that ended the iteration
+ *      pin: the pin of the page we're at, None if it is held
+ *      held: the pins of the pages visited since hold_pages was called
+ *      hold: if the pages visited stay pinned until the iterator is dropped
  *  Notes:
  *      - Needs to walk through all the pages, and for each page walk through all the values
+ *      - pages added after the iterator was created aren't visited, get a new iterator to see them
+ *      - dirty pages are read from the buffer pool, so the values written before the iterator
+ *        was created are visited without writing them back first
+ *      - the page the values are lent from stays cached while the iterator is on it
  */ 
 pub struct HeapFileIterator {
     container_id: ContainerId,
     tid: TransactionId,
-    hf: Arc<HeapFile>,
-    curr_p_iter: PageIter,
-    curr_pid: PageId
+    hf: Option<Arc<HeapFile>>,
+    pool: Arc<BufferPool>,
+    num_pages: PageId,
+    curr_p_iter: Option<PageIter>,
+    next_pid: PageId,
+    prefetch: PageId,
+    prefetched: VecDeque<(Page, PagePin)>,
+    io: Option<Arc<IoCounters>>,
+    locks: Arc<LockManager>,
+    perm: Permissions,
+    error: Option<CrustyError>,
+    pin: Option<PagePin>,
+    held: Vec<PagePin>,
+    hold: bool,
 }
 
 impl HeapFileIterator {
@@ -37,63 +82,152 @@ impl HeapFileIterator {
      *  Inputs:
      *      container_id: the containerID associated with the heapfile
      *      tid: the transaction id
-     *      hf: the heapfile itself
+     *      hf: the heapfile itself, None for a container that doesn't exist
+     *      pool: the buffer pool the pages are read through
+     *      prefetch: how many pages to read ahead, 0 to read one page at a time
+     *      io: the counters the pages read are added to, if any
+     *      locks: the lock manager the pages are locked in for tid
+     *      perm: the permissions the pages are locked with
      *  Outputs:
-     *      a new heapfile iterator
+     *      a new heapfile iterator, that doesn't read anything until next is called
      *  Notes:
-     *      - When you implement HeapFile, there is also a method you need to implement called num_pages
-     *      - After implementing this, you can call this method to get the number of pages in the heapfile you are iterating through.
+     *      - an empty or missing heapfile gives an iterator without values
      */ 
-    pub(crate) fn new(container_id: ContainerId, tid: TransactionId, hf: Arc<HeapFile>) -> Self {
-       
-        let mut file = &hf.clone();
-        let mut p = HeapFile::read_page_from_file(file, 0).unwrap(); //understand this part later. 
-        let mut iter = p.into_iter();
-        let new_hf = HeapFileIterator{container_id: container_id, 
-                                        tid: tid,
-                                        hf: hf, 
-                                        curr_p_iter: iter, 
-                                        curr_pid: 0,};
-        return new_hf;
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(container_id: ContainerId, tid: TransactionId, hf: Option<Arc<HeapFile>>, pool: Arc<BufferPool>,
+        prefetch: PageId, io: Option<Arc<IoCounters>>, locks: Arc<LockManager>, perm: Permissions) -> Self {
+        let num_pages = hf.as_ref().map_or(0, |hf| pool.num_pages(container_id, hf.num_pages()));
+        HeapFileIterator{container_id, tid, hf, pool, num_pages, curr_p_iter: None, next_pid: 0, prefetch,
+                         prefetched: VecDeque::new(), io, locks, perm, error: None, pin: None,
+                         held: Vec::new(), hold: false}
+    }
+
+    /*  with_partition
+     *      purpose: limits the iterator to one of several ranges of the pages of the heapfile
+     *  Inputs:
+     *      self: the iterator, before it read anything
+     *      partition: which of the ranges to visit
+     *      partitions: how many ranges the pages are split into
+     *  Outputs:
+     *      the iterator, visiting the pages of the range
+     *  Notes:
+     *      - the ranges split the pages the heapfile had when the iterator was created, as evenly
+     *        as they can, and are empty if there are fewer pages than ranges
+     */
+    pub(crate) fn with_partition(mut self, partition: usize, partitions: usize) -> Self {
+        let num_pages = self.num_pages as usize;
+        let partitions = partitions.max(1);
+        self.next_pid = (num_pages * partition / partitions) as PageId;
+        self.num_pages = (num_pages * (partition + 1) / partitions) as PageId;
+        self
     }
-    
 }
 
-impl Iterator for HeapFileIterator {
-    type Item = Vec<u8>;
-    /*  next
-     *      purpose: move onto the next page within the heapfile
+impl HeapFileIterator {
+    /*  next_page
+     *      purpose: move onto the next page, getting it from the buffer pool along with the pages
+     *          to prefetch
      *  Inputs:
-     *      &mut self: a mutable reference to the heapfile that we are iterating through
+     *      &mut self: the iterator
      *  Outputs:
-     *      the stuff in the heapfile
+     *      false once every page was visited, or a page could not be read or locked
      *  Note:
-     *      - Note this will need to iterate through the pages and their respective iterators.
+     *      - the transaction was aborted if a page could not be locked
+     *      - the pages to prefetch that aren't cached are read with a single read
+     *      - the pages come pinned from the pool, the previous one is unpinned unless the pages
+     *        are held. at most half of the pool is held, so the other pages still have frames to
+     *        use, past that only the page we're at and the prefetched ones are pinned
      */
-    fn next(&mut self) -> Option<Self::Item> {
-        // the number of pages to be iterated through
-        let pageCnt = self.hf.num_pages();
-        // loop through all the pages
-        while self.curr_pid <= pageCnt {
-            match self.curr_p_iter.next(){
-                Some(data) => {
-                    return Some(data);
+    fn next_page(&mut self) -> bool {
+        if self.next_pid >= self.num_pages {
+            return false;
+        }
+        if self.prefetched.is_empty() {
+            let count = (self.num_pages - self.next_pid).min(self.prefetch.saturating_add(1));
+            for page_id in self.next_pid..self.next_pid + count {
+                if self.locks.lock(self.tid, LockTarget::Page(self.container_id, page_id), self.perm).is_err() {
+                    debug!("heapstore::heapfileiter {:?} was aborted waiting for page {} of {:?}", self.tid, page_id, self.container_id);
+                    self.num_pages = self.next_pid;
+                    return false;
+                }
+            }
+            let hf = match self.hf.as_ref() {
+                Some(hf) => hf,
+                None => return false,
+            };
+            match self.pool.get_pages(hf, self.next_pid, count, self.io.as_deref()) {
+                Ok(pages) => self.prefetched.extend(pages),
+                Err(e) => {
+                    debug!("heapstore::heapfileiter can't read page {} of {:?}: {}", self.next_pid, self.container_id, e);
+                    self.num_pages = self.next_pid;
+                    self.error = Some(e);
+                    return false;
                 }
-                None => {
-                    //increment the current page id
-                    self.curr_pid += 1;
-                    // read the next page that we need to iterate through
-                    let mut file = &self.hf.clone();
-                    let p = HeapFile::read_page_from_file(file, self.curr_pid).unwrap();
-                    // create the iterator for that page
-                    let iter = p.into_iter();
-                    // set the new iterator in the HeapFileIterator struct
-                    self.curr_p_iter = iter;
-                    
+            }
+        }
+        // the heapfile may have been cut short since the iterator was created
+        match self.prefetched.pop_front() {
+            Some((page, pin)) => {
+                if self.hold && self.held.len() < self.pool.capacity() / 2 {
+                    self.held.push(pin);
+                    self.pin = None;
+                } else {
+                    self.pin = Some(pin);
                 }
+                self.curr_p_iter = Some(page.into_iter());
+                self.next_pid += 1;
+                true
             }
+            None => false,
         }
-        return None;  
     }
+}
+
+impl ValueRefIterator for HeapFileIterator {
+    /*  next_ref
+     *      purpose: get the next value, borrowed from the page it is on instead of copied
+     *  Inputs:
+     *      &mut self: the iterator
+     *  Outputs:
+     *      the next value in the heapfile, valid until the iterator moves on, None once every
+     *      page was visited
+     *  Note:
+     *      - a page that can't be read is returned as its error, and ends the iteration
+     *      - a page that can't be locked ends it too, the transaction was aborted then
+     */
+    fn next_ref(&mut self) -> Option<Result<&[u8], CrustyError>> {
+        while self.curr_p_iter.as_ref().map_or(true, |iter| iter.is_done()) {
+            if !self.next_page() {
+                return self.error.take().map(Err);
+            }
+        }
+        self.curr_p_iter.as_mut()?.next_ref().map(Ok)
+    }
+    /*  hold_pages
+     *      purpose: keep the pages visited from now on pinned until the iterator is dropped
+     *  Inputs:
+     *      &mut self: the iterator
+     *  Notes:
+     *      - the first pages are held until half of the pool is pinned, see next_page
+     */
+    fn hold_pages(&mut self) {
+        self.hold = true;
+    }
+}
 
+impl Iterator for HeapFileIterator {
+    type Item = Result<Vec<u8>, CrustyError>;
+    /*  next
+     *      purpose: get the next value, moving onto the next page when this one is done
+     *  Inputs:
+     *      &mut self: a mutable reference to the heapfile that we are iterating through
+     *  Outputs:
+     *      a copy of the next value in the heapfile, None once every page was visited
+     *  Note:
+     *      - Note this will need to iterate through the pages and their respective iterators.
+     *      - a page that can't be read is returned as its error, see next_ref
+     */
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_ref().map(|value| value.map(<[u8]>::to_vec))
+    }
 }
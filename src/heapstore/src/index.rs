@@ -0,0 +1,365 @@
+use crate::page::{Page, Slot};
+use crate::storage_manager::StorageManager;
+use common::ids::{ContainerId, PageId, Permissions, TransactionId};
+use common::{CrustyError, Field};
+use serde::{Deserialize, Serialize};
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+
+/// An entry of an index: the key and the packed id of the value it points at
+pub(crate) type Entry = (Field, u64);
+
+/// The root of an index never moves, so finding it needs no metadata
+const ROOT: PageId = 0;
+
+/* enum Node
+ *  Purpose:
+ *      A node of a B+tree, stored as the only value of its page
+ *  Elements:
+ *      Leaf: entries in order, and the leaf with the next entries if there is one
+ *      Internal: keys in order and one more child than keys. the entries under children[i]
+ *                are at least keys[i - 1] and less than keys[i]
+ *  Notes:
+ *      - keys are whole entries, so entries with the same field are ordered by id and every
+ *        entry has exactly one place in the tree
+ */
+#[derive(Serialize, Deserialize)]
+enum Node {
+    Leaf { entries: Vec<Entry>, next: Option<PageId> },
+    Internal { keys: Vec<Entry>, children: Vec<PageId> },
+}
+
+/* struct BTree
+ *  Purpose:
+ *      A B+tree index kept in the pages of a heapfile, one node per page
+ *  Elements:
+ *      sm: the storage manager the pages are read and written through
+ *      container_id: the heapfile of the index
+ *      tid: the transaction the pages are read and written for
+ *  Notes:
+ *      - the root is page 0, an empty heapfile is an empty index
+ *      - callers keep writers apart, see StorageManager::index_insert
+ */
+pub(crate) struct BTree<'a> {
+    sm: &'a StorageManager,
+    container_id: ContainerId,
+    tid: TransactionId,
+}
+
+impl<'a> BTree<'a> {
+    pub(crate) fn new(sm: &'a StorageManager, container_id: ContainerId, tid: TransactionId) -> Self {
+        BTree { sm, container_id, tid }
+    }
+    /*  node_capacity
+     *      purpose: the most bytes a node may take, what an empty page holds in one value
+     */
+    fn node_capacity() -> usize {
+        Page::new(0).header.largest_free_space as usize - mem::size_of::<Slot>()
+    }
+    /*  max_entry_size
+     *      purpose: the most bytes an entry may take
+     *  Outputs:
+     *      a quarter of a node, so the halves of a full node always fit on their pages
+     */
+    pub(crate) fn max_entry_size() -> usize {
+        Self::node_capacity() / 4
+    }
+    /*  insert
+     *      purpose: add an entry to the index
+     *  Inputs:
+     *      &self: the index
+     *      entry: the key and packed value id
+     *  Outputs:
+     *      Ok(true) once the entry is in the index, Ok(false) if it already was, an error if
+     *      it's too large or a page is bad
+     *  Notes:
+     *      - an entry that's already in the index is left as is
+     *      - when the root splits, its left half moves to a new page and the root becomes an
+     *        internal node over the two halves
+     */
+    pub(crate) fn insert(&self, entry: Entry) -> Result<bool, CrustyError> {
+        let size = serde_cbor::to_vec(&entry).map_err(Self::encode_error)?.len();
+        if size > Self::max_entry_size() {
+            return Err(CrustyError::ValidationError(format!(
+                "Index key {:?} takes {} bytes, at most {} fit",
+                entry.0, size, Self::max_entry_size()
+            )));
+        }
+        let (is_new, split) = self.insert_into(ROOT, entry)?;
+        if let Some((separator, right)) = split {
+            let left = self.read_node(ROOT)?;
+            let left_id = self.sm.get_num_pages(self.container_id);
+            self.write_node(left_id, &left)?;
+            self.write_node(ROOT, &Node::Internal { keys: vec![separator], children: vec![left_id, right] })?;
+        }
+        Ok(is_new)
+    }
+    /*  insert_into
+     *      purpose: add an entry to the subtree of a node
+     *  Inputs:
+     *      &self: the index
+     *      page_id: the node's page
+     *      entry: the entry
+     *  Outputs:
+     *      if the entry is new, and the separator and the page of the new right half if the
+     *      node split
+     */
+    fn insert_into(&self, page_id: PageId, entry: Entry) -> Result<(bool, Option<(Entry, PageId)>), CrustyError> {
+        match self.read_node(page_id)? {
+            Node::Leaf { mut entries, next } => {
+                match entries.binary_search(&entry) {
+                    Ok(_) => Ok((false, None)),
+                    Err(pos) => {
+                        entries.insert(pos, entry);
+                        Ok((true, self.store(page_id, Node::Leaf { entries, next })?))
+                    }
+                }
+            }
+            Node::Internal { mut keys, mut children } => {
+                let child = Self::child_for(&keys, &entry);
+                match self.insert_into(children[child], entry)? {
+                    (is_new, None) => Ok((is_new, None)),
+                    (is_new, Some((separator, right))) => {
+                        keys.insert(child, separator);
+                        children.insert(child + 1, right);
+                        Ok((is_new, self.store(page_id, Node::Internal { keys, children })?))
+                    }
+                }
+            }
+        }
+    }
+    /*  remove
+     *      purpose: take an entry out of the index, eg when its insert is undone
+     *  Inputs:
+     *      &self: the index
+     *      entry: the key and packed value id
+     *  Outputs:
+     *      Ok(true) if the entry was in the index, Ok(false) if it wasn't
+     *  Notes:
+     *      - nodes are not merged, a leaf can be left empty and is skipped by range
+     */
+    pub(crate) fn remove(&self, entry: &Entry) -> Result<bool, CrustyError> {
+        let mut page_id = ROOT;
+        loop {
+            match self.read_node(page_id)? {
+                Node::Leaf { mut entries, next } => {
+                    return match entries.binary_search(entry) {
+                        Ok(pos) => {
+                            entries.remove(pos);
+                            self.write_node(page_id, &Node::Leaf { entries, next })?;
+                            Ok(true)
+                        }
+                        Err(_) => Ok(false),
+                    };
+                }
+                Node::Internal { keys, children } => page_id = children[Self::child_for(&keys, entry)],
+            }
+        }
+    }
+    /*  child_for
+     *      purpose: find the child of an internal node whose subtree has the place of an entry
+     *  Inputs:
+     *      keys: the keys of the node
+     *      entry: the entry
+     *  Outputs:
+     *      the position of the child in the node's children
+     */
+    fn child_for(keys: &[Entry], entry: &Entry) -> usize {
+        match keys.binary_search(entry) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+    /*  store
+     *      purpose: write a node, split in two if it doesn't fit on its page
+     *  Inputs:
+     *      &self: the index
+     *      page_id: the node's page, which keeps the left half of a split
+     *      node: the node
+     *  Outputs:
+     *      the separator and the page of the right half if the node split
+     *  Notes:
+     *      - the right half goes to a new page at the end of the heapfile
+     */
+    fn store(&self, page_id: PageId, node: Node) -> Result<Option<(Entry, PageId)>, CrustyError> {
+        if Self::encode(&node)?.len() <= Self::node_capacity() {
+            self.write_node(page_id, &node)?;
+            return Ok(None);
+        }
+        let right_id = self.sm.get_num_pages(self.container_id);
+        let (left, separator, right) = match node {
+            Node::Leaf { mut entries, next } => {
+                let right = entries.split_off(entries.len() / 2);
+                let separator = right[0].clone();
+                (Node::Leaf { entries, next: Some(right_id) }, separator, Node::Leaf { entries: right, next })
+            }
+            Node::Internal { mut keys, mut children } => {
+                let mid = keys.len() / 2;
+                let right_keys = keys.split_off(mid + 1);
+                let separator = keys.pop().unwrap();
+                let right_children = children.split_off(mid + 1);
+                (Node::Internal { keys, children }, separator, Node::Internal { keys: right_keys, children: right_children })
+            }
+        };
+        self.write_node(right_id, &right)?;
+        self.write_node(page_id, &left)?;
+        Ok(Some((separator, right_id)))
+    }
+    /*  range
+     *      purpose: find the entries whose fields are within bounds
+     *  Inputs:
+     *      &self: the index
+     *      low: the lower bound of the fields
+     *      high: the upper bound of the fields
+     *  Outputs:
+     *      the packed value ids of the entries, ordered by field and then id
+     *  Notes:
+     *      - descends to the first leaf that can hold a field within bounds and follows the
+     *        leaves from there until a field is past the upper bound
+     */
+    pub(crate) fn range(&self, low: Bound<&Field>, high: Bound<&Field>) -> Result<Vec<u64>, CrustyError> {
+        let mut node = self.read_node(ROOT)?;
+        while let Node::Internal { keys, children } = node {
+            let child = match low {
+                Bound::Included(field) | Bound::Excluded(field) => keys.iter().take_while(|key| key.0 < *field).count(),
+                Bound::Unbounded => 0,
+            };
+            node = self.read_node(children[child])?;
+        }
+        let mut ids = Vec::new();
+        loop {
+            let (entries, next) = match node {
+                Node::Leaf { entries, next } => (entries, next),
+                Node::Internal { .. } => return Err(self.corrupt("leaves link to an internal node")),
+            };
+            for (field, id) in entries {
+                if (low, high).contains(&field) {
+                    ids.push(id);
+                } else if (low, Bound::Unbounded).contains(&field) {
+                    // past the upper bound
+                    return Ok(ids);
+                }
+            }
+            match next {
+                Some(page_id) => node = self.read_node(page_id)?,
+                None => return Ok(ids),
+            }
+        }
+    }
+    /*  read_node
+     *      purpose: read the node of a page
+     *  Inputs:
+     *      &self: the index
+     *      page_id: the page
+     *  Outputs:
+     *      the node, an empty leaf for the root of an empty heapfile
+     */
+    fn read_node(&self, page_id: PageId) -> Result<Node, CrustyError> {
+        if page_id == ROOT && self.sm.get_num_pages(self.container_id) == 0 {
+            return Ok(Node::Leaf { entries: Vec::new(), next: None });
+        }
+        let page = self.sm.get_page(self.container_id, page_id, self.tid, Permissions::ReadOnly, false)
+            .ok_or_else(|| self.corrupt("the container does not exist"))?;
+        let bytes = page.get_value(0).ok_or_else(|| self.corrupt(&format!("page {} has no node", page_id)))?;
+        serde_cbor::from_slice(&bytes).map_err(|e| self.corrupt(&e.to_string()))
+    }
+    /*  write_node
+     *      purpose: replace the node of a page, or write the node of a new page
+     *  Inputs:
+     *      &self: the index
+     *      page_id: the page
+     *      node: the node, which has to fit on the page
+     */
+    fn write_node(&self, page_id: PageId, node: &Node) -> Result<(), CrustyError> {
+        let bytes = Self::encode(node)?;
+        if bytes.len() > Self::node_capacity() {
+            return Err(self.corrupt(&format!("node of page {} does not fit", page_id)));
+        }
+        let mut page = Page::new(page_id);
+        page.add_value(&bytes);
+        self.sm.write_page(self.container_id, page, self.tid)
+    }
+    fn encode(node: &Node) -> Result<Vec<u8>, CrustyError> {
+        serde_cbor::to_vec(node).map_err(Self::encode_error)
+    }
+    fn encode_error(e: serde_cbor::Error) -> CrustyError {
+        CrustyError::CrustyError(format!("Cannot serialize index node: {}", e))
+    }
+    fn corrupt(&self, reason: &str) -> CrustyError {
+        CrustyError::CrustyError(format!("Index {} is corrupt: {}", self.container_id, reason))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::ids::ValueId;
+    use common::storage_trait::{ContainerType, StorageTrait};
+
+    fn entry(key: i32, slot: u16) -> Entry {
+        let mut id = ValueId::new_page(1, 0);
+        id.slot_id = Some(slot);
+        (Field::IntField(key), id.pack())
+    }
+
+    #[test]
+    fn hs_index_split_and_range() {
+        let sm = StorageManager::new_test_sm();
+        let cid = 2;
+        sm.create_container(cid, None, ContainerType::Index).unwrap();
+        let tid = TransactionId::new();
+        let index = BTree::new(&sm, cid, tid);
+        assert!(index.range(Bound::Unbounded, Bound::Unbounded).unwrap().is_empty());
+        // every key twice and out of order, enough entries for the root to split more than once
+        let keys: Vec<i32> = (0..3000).map(|i| (i * 7919) % 3000).collect();
+        for (slot, key) in keys.iter().enumerate() {
+            index.insert(entry(*key, slot as u16)).unwrap();
+            index.insert(entry(*key, slot as u16 + 3000)).unwrap();
+        }
+        // inserting an entry again leaves it as is
+        assert!(!index.insert(entry(keys[0], 0)).unwrap());
+        assert!(sm.get_num_pages(cid) > 10);
+        match index.read_node(ROOT).unwrap() {
+            Node::Internal { children, .. } => assert!(children.len() > 2),
+            Node::Leaf { .. } => panic!("root did not split"),
+        }
+
+        let all = index.range(Bound::Unbounded, Bound::Unbounded).unwrap();
+        assert_eq!(6000, all.len());
+        let (low, high) = (Field::IntField(100), Field::IntField(110));
+        let ids = index.range(Bound::Included(&low), Bound::Excluded(&high)).unwrap();
+        let slot = |key: i32| keys.iter().position(|k| *k == key).unwrap() as u16;
+        let expected: Vec<u64> = (100..110)
+            .flat_map(|key| vec![entry(key, slot(key)).1, entry(key, slot(key) + 3000).1])
+            .collect();
+        assert_eq!(expected, ids);
+        let ids = index.range(Bound::Excluded(&high), Bound::Unbounded).unwrap();
+        assert_eq!(2 * (3000 - 111), ids.len());
+        assert!(index.range(Bound::Included(&Field::IntField(5000)), Bound::Unbounded).unwrap().is_empty());
+
+        // removing every entry of a range leaves the others
+        for key in 100..110 {
+            assert!(index.remove(&entry(key, slot(key))).unwrap());
+            assert!(index.remove(&entry(key, slot(key) + 3000)).unwrap());
+        }
+        assert!(!index.remove(&entry(100, slot(100))).unwrap());
+        assert!(index.range(Bound::Included(&low), Bound::Excluded(&high)).unwrap().is_empty());
+        assert_eq!(6000 - 20, index.range(Bound::Unbounded, Bound::Unbounded).unwrap().len());
+        assert!(index.insert(entry(100, slot(100))).unwrap());
+    }
+
+    #[test]
+    fn hs_index_large_key() {
+        let sm = StorageManager::new_test_sm();
+        let cid = 2;
+        sm.create_container(cid, None, ContainerType::Index).unwrap();
+        let index = BTree::new(&sm, cid, TransactionId::new());
+        let key = Field::StringField("x".repeat(BTree::max_entry_size()));
+        assert!(index.insert((key, 0)).is_err());
+        let key = Field::StringField("x".repeat(900));
+        for id in 0..20 {
+            index.insert((key.clone(), id)).unwrap();
+        }
+        assert_eq!((0..20).collect::<Vec<u64>>(), index.range(Bound::Included(&key), Bound::Included(&key)).unwrap());
+    }
+}
@@ -1,10 +1,11 @@
 #[macro_use]
 extern crate log;
 mod bp_tests;
+pub mod buffer_pool;
 mod heapfile;
 mod heapfileiter;
+mod index;
 mod page;
+mod pin;
 pub mod storage_manager;
 pub mod testutil;
-
-pub(crate) const IS_LRU: bool = true;
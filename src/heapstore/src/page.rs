@@ -1,14 +1,23 @@
 #[allow(unused_imports)]
 use common::ids::{PageId, SlotId};
+use common::storage_trait::SlotPolicy;
 #[allow(unused_imports)]
-use common::PAGE_SIZE;
+use common::{CrustyError, PAGE_SIZE};
 #[allow(unused_imports)]
 use std::convert::TryInto;
 #[allow(unused_imports)]
 use std::mem;
 #[allow(unused_imports)]
 use std::ptr;
+use xxhash_rust::xxh3::Xxh3Default;
 
+/// The offset of a tombstone, the slot a deleted value leaves behind under SlotPolicy::Monotonic
+/// so its id is never handed out again. Tombstones have no data and a size of 0.
+const TOMBSTONE_OFFSET: u16 = u16::MAX;
+
+/// Where the checksum of a serialized page starts, after the page_id and the number of slots.
+/// The slots follow it.
+const CHECKSUM_OFFSET: usize = 4;
 
 /// The struct for a page. Note this can hold more elements/meta data when created,
 /// but it must be able to be packed/serialized/marshalled into the data array of size
@@ -25,6 +34,7 @@ use std::ptr;
  *      header: contains metadata about the page
  *      data: the actual data that the page holds
  */
+#[derive(Clone)]
 pub(crate) struct Page {
     /// The data for data
     pub header: Header, 
@@ -41,6 +51,7 @@ pub(crate) struct Page {
  *  Note: slot metadata can't exceed 6 bytes
  */ 
 // the slot metadata can't exceed 6 bytes
+#[derive(Clone)]
 pub struct Slot{
     pub slot_id: SlotId, 
     pub slot_offset: u16 , 
@@ -53,7 +64,10 @@ pub struct Slot{
  *      page_id: the unique identifier for the page
  *      slots: a vector of the slots/records found in the page
  *      largest_free_space: the largest amount of free contiguous space in the page
+ *  Note: serialized as the page_id, the number of slots, a checksum of the page and the slots,
+ *        see get_bytes. the checksum is only kept on disk
  */
+#[derive(Clone)]
 pub struct Header{
     pub page_id: PageId, //u8 - 1byte
     pub slots: Vec<Slot>, // 
@@ -78,6 +92,16 @@ impl Slot{
         };
         return new_slot;
     }
+    /*  is_tombstone
+     *      purpose: check if the slot only holds on to the id of a deleted value
+     *  inputs:
+     *      &self: the slot to check
+     *  outputs:
+     *      true if the slot is a tombstone
+     */
+    pub fn is_tombstone(&self) -> bool {
+        self.slot_offset == TOMBSTONE_OFFSET
+    }
 }
 
 impl Header {
@@ -90,7 +114,7 @@ impl Header {
      *  Note: static metadata can be 8 bytes while each additional slot is allowed to be 6 bytes max
      */ 
     pub(crate) fn get_size(&self) -> usize {
-       return mem::size_of::<PageId>() + (mem::size_of::<Slot>() * self.slots.len()) +mem::size_of::<u16>(); 
+       return mem::size_of::<PageId>() + (mem::size_of::<Slot>() * self.slots.len()) +mem::size_of::<u16>() + mem::size_of::<u32>(); 
     }
 }
 
@@ -107,7 +131,7 @@ impl Page {
             page_id: page_id,
             slots: Vec::new(),
             //largest_free_space is the size of the data array without the size of the header
-            largest_free_space: (PAGE_SIZE - mem::size_of::<PageId>() - mem::size_of::<u16>()) as u16, 
+            largest_free_space: (PAGE_SIZE - mem::size_of::<PageId>() - mem::size_of::<u16>() - mem::size_of::<u32>()) as u16, 
         };
         let new_page = Page{
             header: new_header,
@@ -130,31 +154,30 @@ impl Page {
      *  inputs:
      *      &mut self: a mutable reference to the page that we want to find available space from
      *      input_size: the size of the data that we want to put into the page
+     *      slot_policy: how the new slot_id is chosen
      *  outputs:
      *      a vector with 2 elements with the first element being the new slot_id and the second 
      *      element being the index in the data array where we can begin inserting data
+     *  Notes:
+     *      - tombstones take up an id but no data, so only the live slots are used to find space
+     *      - the caller checks that the page has enough free bytes, see find_start
      */ 
-    pub fn find_free(&mut self, input_size: usize) -> Vec<usize> {
-        let mut slot_vec = &self.header.slots;
-        let vec_len = slot_vec.len();
+    pub fn find_free(&mut self, input_size: usize, slot_policy: SlotPolicy) -> Vec<usize> {
         let mut new_s_id = 0;
-        let mut start_index;
-        let mut counter = 0;
-        let mut offset_vec = Vec::new();
         let mut id_vec = Vec::new();
         let mut ret_vec = Vec::new();
 
-        // create a vector that holds all the offset values and id values that will help with our calculations
-        for slot in slot_vec{
-            offset_vec.push(slot.slot_offset);
+        for slot in &self.header.slots{
             id_vec.push(slot.slot_id);
         }
-        // reverse offset_vec to make calculations easier
-        offset_vec.sort();
-        offset_vec.reverse();
         id_vec.sort();
         // find the new id value
-        while new_s_id <= id_vec.len() {
+        if slot_policy == SlotPolicy::Monotonic {
+            // one past every id the page has handed out, tombstones included
+            new_s_id = id_vec.last().map_or(0, |id| *id as usize + 1);
+            ret_vec.push(new_s_id);
+        }
+        while slot_policy == SlotPolicy::ReuseLowest && new_s_id <= id_vec.len() {
             if new_s_id < id_vec.len() && new_s_id == id_vec[new_s_id] as usize {
                 new_s_id += 1;
                 continue;
@@ -163,38 +186,82 @@ impl Page {
                 break;
             }
         }
-        if vec_len ==0{
-            new_s_id = 0;
-            start_index = PAGE_SIZE - input_size as usize;
-            ret_vec.push(start_index);
-        } else {
-            while counter <= vec_len {
-                if vec_len == 0 {
-                    let space_bet = PAGE_SIZE - (slot_vec[counter].slot_offset as usize + slot_vec[counter].size as usize);
-                    if space_bet >= input_size.into(){
-                        start_index = (slot_vec[counter].slot_offset + slot_vec[counter].size).into();
-                        ret_vec.push(start_index);
-                        break;
-                    } else {
-                        counter += 1;
-                    }
-                } else if counter == vec_len {
-                    start_index = (slot_vec[counter - 1].slot_offset - input_size as u16).into();
-                    ret_vec.push(start_index);
-                    break;
-                // you just need to figure out when vec_len is getting to 0
-                // the problem is that the slot_vec isn't sorted!
-                } else if counter != vec_len - 1 &&  slot_vec[counter].slot_offset > (slot_vec[counter+1].slot_offset + slot_vec[counter+1].size) && slot_vec[counter].slot_offset  - (slot_vec[counter+1].slot_offset + slot_vec[counter+1].size) >= input_size as u16{
-                    start_index = (slot_vec[counter+1].slot_offset+slot_vec[counter+1].size) as usize;
-                    ret_vec.push(start_index);
-                    counter += 1;
-                    break;
-                } else {
-                    counter += 1;
-                }
+        ret_vec.push(self.find_start(input_size));
+        return ret_vec;
+    }
+    /*  find_start
+     *      purpose: find where in the data array a new value goes
+     *  inputs:
+     *      &mut self: the page
+     *      input_size: the size of the value
+     *  outputs:
+     *      the index in the data array the value begins at
+     *  Notes:
+     *      - the value goes in the first gap between values, from the end of the page down, that
+     *        it fits in, else below the lowest value
+     *      - if the free bytes are split up so that no gap fits the value and a new slot in the
+     *        header, the values are moved together first
+     */
+    fn find_start(&mut self, input_size: usize) -> usize {
+        match self.find_gap(input_size) {
+            Some(start_index) => start_index,
+            None => {
+                self.compact();
+                self.find_gap(input_size).unwrap_or(0)
             }
         }
-        return ret_vec;
+    }
+    /*  find_gap
+     *      purpose: find free space for a value without moving other values
+     *  inputs:
+     *      &self: the page
+     *      input_size: the size of the value
+     *  outputs:
+     *      the index in the data array the value can begin at, None if there is no room for it
+     *      and the header growing by a slot
+     */
+    fn find_gap(&self, input_size: usize) -> Option<usize> {
+        let header_end = self.header.get_size() + mem::size_of::<Slot>();
+        let mut live: Vec<&Slot> = self.header.slots.iter().filter(|slot| !slot.is_tombstone()).collect();
+        live.sort_by_key(|slot| std::cmp::Reverse(slot.slot_offset));
+        // the start of the value above the gap being looked at
+        let mut end = PAGE_SIZE;
+        let mut gap = None;
+        for slot in live {
+            let top = (slot.slot_offset + slot.size) as usize;
+            if gap.is_none() && end - top >= input_size {
+                gap = Some(end - input_size);
+            }
+            end = slot.slot_offset as usize;
+        }
+        if end < header_end {
+            // the new slot would run into the lowest value
+            return None;
+        }
+        gap.or_else(|| if end - header_end >= input_size { Some(end - input_size) } else { None })
+    }
+    /*  compact
+     *      purpose: move the values to the end of the data array, so all the free space is
+     *               between the header and the lowest value
+     *  inputs:
+     *      &mut self: the page
+     *  Notes:
+     *      - values keep their slot_ids, only their offsets change
+     */
+    fn compact(&mut self) {
+        let mut live: Vec<usize> = (0..self.header.slots.len()).filter(|i| !self.header.slots[*i].is_tombstone()).collect();
+        live.sort_by_key(|i| std::cmp::Reverse(self.header.slots[*i].slot_offset));
+        let mut end = PAGE_SIZE;
+        for i in live {
+            let slot = &mut self.header.slots[i];
+            let start_index = slot.slot_offset as usize;
+            end -= slot.size as usize;
+            self.data.copy_within(start_index..start_index + slot.size as usize, end);
+            slot.slot_offset = end as u16;
+        }
+        for byte in &mut self.data[..end] {
+            *byte = 0;
+        }
     }
     /*  add_value
      *      purpose: given an array of values, insert it into the page's array
@@ -208,24 +275,81 @@ impl Page {
      *                      inserted the bytes array into it or we return
      *                      None if we weren't able to add the array of bytes
      *                      into the page
+     *  Notes:
+     *      - the slot_id is the lowest one not in use, see add_value_with_policy
      */
     pub fn add_value(&mut self, bytes: &Vec<u8>) -> Option<SlotId> {
+        self.add_value_with_policy(bytes, SlotPolicy::ReuseLowest)
+    }
+    /*  add_value_with_policy
+     *      purpose: insert an array of bytes into the page, choosing its slot_id by a policy
+     *  inputs:
+     *      &mut self: a mutable reference the the page that we are adding the
+     *                 new array of bytes into
+     *      bytes: the new array of bytes to be inserted into the data array
+     *             of the page
+     *      slot_policy: ReuseLowest takes the lowest slot_id not in use, Monotonic
+     *                   takes one higher than any the page has handed out
+     *  ouputs:
+     *      Option<SlotId>: the slot_id of the bytes, or None if they don't fit
+     */
+    pub fn add_value_with_policy(&mut self, bytes: &Vec<u8>, slot_policy: SlotPolicy) -> Option<SlotId> {
         let input_len = bytes.len();
-        let place_in = Page::find_free(self,input_len);
-        let slot_vec = &mut self.header.slots;
+        // the value and its slot in the header
+        if input_len + mem::size_of::<Slot>() > self.header.largest_free_space as usize{
+            return None;
+        }
+        let place_in = Page::find_free(self,input_len, slot_policy);
         let new_id = place_in[0];
         let start_index = place_in[1];
-        let end_index = start_index + input_len;
-        if input_len > self.header.largest_free_space as usize{
+        self.put_value(new_id as SlotId, start_index, bytes);
+        Some(new_id as u16)
+    }
+    /*  put_value
+     *      purpose: copy a value into the data array and give it a slot
+     *  inputs:
+     *      &mut self: the page
+     *      slot_id: the slot_id of the value
+     *      start_index: where the value begins in the data array, see find_free
+     *      bytes: the value
+     */
+    fn put_value(&mut self, slot_id: SlotId, start_index: usize, bytes: &[u8]) {
+        let end_index = start_index + bytes.len();
+        self.data[start_index..end_index].clone_from_slice(bytes);
+        self.header.slots.push(Slot::new(slot_id, start_index as u16, bytes.len() as u16));
+        self.header.largest_free_space -= (bytes.len() + mem::size_of::<Slot>()) as u16;
+    }
+    /*  restore_value
+     *      purpose: put a deleted value back under the slot_id it had, eg when the delete is undone
+     *  inputs:
+     *      &mut self: the page
+     *      slot_id: the slot_id the value had
+     *      bytes: the value
+     *  outputs:
+     *      Option<()>: Some(()) if the value is back, None if the slot_id was handed out again or
+     *                  the value no longer fits
+     *  Notes:
+     *      - a tombstone the value left behind is replaced by the value
+     */
+    pub fn restore_value(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()> {
+        let tombstone = match self.header.slots.iter().position(|slot| slot.slot_id == slot_id) {
+            Some(index) if self.header.slots[index].is_tombstone() => Some(index),
+            Some(_) => return None,
+            None => None,
+        };
+        // the tombstone's slot is taken over by the value
+        let freed = if tombstone.is_some() { mem::size_of::<Slot>() } else { 0 };
+        if bytes.len() + mem::size_of::<Slot>() > self.header.largest_free_space as usize + freed {
             return None;
-        } else {
-            self.data[start_index..end_index].clone_from_slice(&bytes);
-            let new_slot = Slot::new(new_id as SlotId, start_index as u16, input_len as u16);
-            slot_vec.push(new_slot);
-            self.header.largest_free_space -= (input_len + mem::size_of::<Slot>()) as u16;
-            return Some(new_id as u16);
         }
-    }  
+        if let Some(index) = tombstone {
+            self.header.slots.remove(index);
+            self.header.largest_free_space += freed as u16;
+        }
+        let start_index = self.find_start(bytes.len());
+        self.put_value(slot_id, start_index, bytes);
+        Some(())
+    }
     /*  get_value
      *      purpose: return the bytes for the slotId
      *  inputs: 
@@ -235,28 +359,48 @@ impl Page {
      *      Option<Vec<u8>>: if the slot_id is valid, we return the bytes in 
      *                       vector form. If the slot_id is invalid, we return
      *                       None.
+     *  Notes:
+     *      - copies the bytes, see get_value_ref to read them in place
      */ 
     pub fn get_value(&self, slot_id: SlotId) -> Option<Vec<u8>> {
-        // get corresponding information for the slotId
-        let slot_vec = &self.header.slots;
-        let start_index;
-        let end_index;
-        let ret_val;
-        let mut id_vec = Vec::new();
-        for slot in slot_vec{
-            id_vec.push(slot.slot_id);
-        }
-        id_vec.sort();
-
-        if !id_vec.contains(&slot_id){
-            return None;
-        } else {
-            let index = slot_vec.into_iter().position(|s| s.slot_id == slot_id).unwrap();
-            start_index = slot_vec[index].slot_offset;                  
-            end_index = slot_vec[index].slot_offset + slot_vec[index].size;
-            ret_val = self.data[usize::from(start_index)..usize::from(end_index)].to_vec();
-            return Some(ret_val);
-        }
+        self.get_value_ref(slot_id).map(<[u8]>::to_vec)
+    }
+    /*  get_value_ref
+     *      purpose: borrow the bytes for the slotId from the page, without copying them
+     *  inputs:
+     *      &self: the page that holds the value
+     *      slot_id: the slot of the value
+     *  outputs:
+     *      the bytes of the value, None if the slot_id is invalid or was deleted
+     */
+    pub fn get_value_ref(&self, slot_id: SlotId) -> Option<&[u8]> {
+        let slot = self.header.slots.iter().find(|s| s.slot_id == slot_id && !s.is_tombstone())?;
+        Some(self.slot_bytes(slot))
+    }
+    /*  value_slots
+     *      purpose: find the slots of the header that hold a value
+     *  inputs:
+     *      &self: the page
+     *  outputs:
+     *      the indexes of the slots in the header, in the order of their slot_ids
+     */
+    fn value_slots(&self) -> Vec<usize> {
+        let slots = &self.header.slots;
+        let mut indexes: Vec<usize> = (0..slots.len()).filter(|i| !slots[*i].is_tombstone()).collect();
+        indexes.sort_by_key(|i| slots[*i].slot_id);
+        indexes
+    }
+    /*  slot_bytes
+     *      purpose: borrow the bytes a slot points to
+     *  inputs:
+     *      &self: the page
+     *      slot: a slot of the page that is not a tombstone
+     *  outputs:
+     *      the bytes of the slot's value
+     */
+    fn slot_bytes(&self, slot: &Slot) -> &[u8] {
+        let start = usize::from(slot.slot_offset);
+        &self.data[start..start + usize::from(slot.size)]
     }
     /*  delete_value
      *      purpose: delete the bytes/slot for the slotId
@@ -268,51 +412,52 @@ impl Page {
      *      Option<()>: we either return Some(()) or None, since there's really
      *                  nothing we'd return bc changes are just being made to 
      *                  data array
+     *  Notes:
+     *      - the slot is removed so its slot_id can be reused, see delete_value_with_policy
      */ 
     pub fn delete_value(&mut self, slot_id: SlotId) -> Option<()> {
-        let mut offset_vec = Vec::new();
-        let mut id_vec = Vec::new();
-
-
-        // create a vector that holds all the offset values and id values that will help with our calculations
-        for slot in &mut self.header.slots{
-            offset_vec.push(slot.slot_offset);
-            id_vec.push(slot.slot_id);
-        }
-        // reverse offset_vec to make calculations easier
-        offset_vec.sort();
-        offset_vec.reverse();
-        id_vec.sort();
-        let start_index;
-        let end_index; 
+        self.delete_value_with_policy(slot_id, SlotPolicy::ReuseLowest)
+    }
+    /*  delete_value_with_policy
+     *      purpose: delete the bytes/slot for the slotId, keeping the slot_id taken if the
+     *               page hands out slot_ids by SlotPolicy::Monotonic
+     *  inputs:
+     *      &mut self: a mutable reference to the page
+     *      slot_id: the slot to be deleted
+     *      slot_policy: ReuseLowest removes the slot, Monotonic leaves a tombstone in its place
+     *  outputs:
+     *      Option<()>: Some(()) if the slot was deleted, None if it wasn't in use
+     */
+    pub fn delete_value_with_policy(&mut self, slot_id: SlotId, slot_policy: SlotPolicy) -> Option<()> {
         let slot_vec = &mut self.header.slots;
-        let mut counter:u16 = 0;
-       
-        if !id_vec.contains(&slot_id){
-            return None;
-        } else {
-            if slot_id == 0 {
-                // deleting the very first slot
-                start_index = slot_vec[slot_id as usize].slot_offset; 
-                end_index = PAGE_SIZE;
-                while counter < (end_index - start_index as usize) as u16{
-                    self.data[(start_index+counter) as usize] = 0;
-                    counter += 1;
-                }
-                let index = slot_vec.into_iter().position(|s| s.slot_id == slot_id).unwrap();
-                slot_vec.remove(index);
-                return Some(());
-            } else {
-                let index = slot_vec.into_iter().position(|s| s.slot_id == slot_id).unwrap();
-                
-                start_index = slot_vec[index].slot_offset;
-                end_index = (slot_vec[index].slot_offset + slot_vec[index].size) as usize;
-                while counter < (end_index - start_index as usize) as u16{
-                    self.data[(start_index+counter) as usize] = 0;
-                    counter += 1;
-                }
+        let index = slot_vec.iter().position(|s| s.slot_id == slot_id && !s.is_tombstone())?;
+        let start_index = slot_vec[index].slot_offset as usize;
+        let end_index = start_index + slot_vec[index].size as usize;
+        for byte in &mut self.data[start_index..end_index] {
+            *byte = 0;
+        }
+        self.header.largest_free_space += (end_index - start_index) as u16;
+        if slot_policy == SlotPolicy::ReuseLowest {
+            // the slot is gone from the header too
+            self.header.largest_free_space += mem::size_of::<Slot>() as u16;
+        }
+        Page::free_slot(slot_vec, index, slot_policy);
+        Some(())
+    }
+    /*  free_slot
+     *      purpose: give up the slot of a deleted value
+     *  inputs:
+     *      slot_vec: the slots of the page
+     *      index: the position of the slot in slot_vec
+     *      slot_policy: ReuseLowest removes the slot, Monotonic turns it into a tombstone
+     */
+    fn free_slot(slot_vec: &mut Vec<Slot>, index: usize, slot_policy: SlotPolicy) {
+        match slot_policy {
+            SlotPolicy::ReuseLowest => {
                 slot_vec.remove(index);
-                return Some(());
+            }
+            SlotPolicy::Monotonic => {
+                slot_vec[index] = Slot::new(slot_vec[index].slot_id, TOMBSTONE_OFFSET, 0);
             }
         }
     }
@@ -322,19 +467,25 @@ impl Page {
      *      data: the data array that we will be extracting pertinent data from to 
      *            construct a new page
      *  outputs:
-     *      a new page
+     *      a new page, or a CrustyError if the checksum doesn't match the bytes
      *  Notes: 
+     *      - a page whose checksum doesn't match was corrupted on disk, and its slots can't be
+     *        trusted to point inside the page
      *      HINT to create a primitive data type from a slice you can use the following
      *      (the example is for a u16 type and the data store in little endian)
      *      u16::from_le_bytes(data[X..Y].try_into().unwrap());
      */ 
-    pub fn from_bytes(data: &[u8]) -> Self {
-        let mut index = 4;
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CrustyError> {
+        let mut index = CHECKSUM_OFFSET + mem::size_of::<u32>();
         let mut counter = 0;
         //find page_id and num_slots
         let page_id = u16::from_le_bytes(data[0..2].try_into().unwrap());
         let slot_num = u16::from_le_bytes(data[2..4].try_into().unwrap());
-        let mut largest_free_space = PAGE_SIZE - (mem::size_of::<PageId>() + (mem::size_of::<Slot>() * slot_num as usize) + mem::size_of::<u16>());
+        let stored = u32::from_le_bytes(data[CHECKSUM_OFFSET..index].try_into().unwrap());
+        if stored != Page::checksum(data) {
+            return Err(CrustyError::CrustyError(format!("Page {} is corrupted, its checksum doesn't match", page_id)));
+        }
+        let mut largest_free_space = PAGE_SIZE.saturating_sub(index + mem::size_of::<Slot>() * slot_num as usize);
         //build the slot arary
         let mut slot_vec = Vec::new();
         while counter < slot_num {
@@ -363,7 +514,7 @@ impl Page {
         let page = Page{header: header,
                         data: data_array
                         };
-        return page;
+        return Ok(page);
     }
     /*  get_bytes
      *      purpose: given a page, serialize it and turn it into an array 
@@ -384,20 +535,38 @@ impl Page {
         // put page_id and num_slots into ret_vec
         header_info.extend(page_id.to_le_bytes().to_vec());
         header_info.extend(num_slots.to_le_bytes().to_vec());
+        // room for the checksum, filled in once the rest of the page is
+        header_info.extend(0u32.to_le_bytes().to_vec());
         // go through the slots
         for slot in slot_vec {
             header_info.extend(slot.slot_id.to_le_bytes().to_vec());
             header_info.extend(slot.slot_offset.to_le_bytes().to_vec());
             header_info.extend(slot.size.to_le_bytes().to_vec());
         }
-        // check taht header doesn't overlap with the data
-        if header_info.len() > self.header.largest_free_space as usize{
+        // check taht header doesn't overlap with the data, which starts at the lowest slot offset
+        let data_start = slot_vec.iter().map(|slot| slot.slot_offset as usize).min().unwrap_or(PAGE_SIZE);
+        if header_info.len() > data_start{
             panic!("Header information and data overlap!");
         }
         // put header info into the ret_vec
         ret_vec[0..header_info.len()].clone_from_slice(&header_info);
+        let checksum = Page::checksum(&ret_vec);
+        ret_vec[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].clone_from_slice(&checksum.to_le_bytes());
         return ret_vec.to_vec();
     }
+    /*  checksum
+     *      purpose: hash a serialized page to tell if its bytes changed
+     *  inputs:
+     *      data: the serialized page
+     *  outputs:
+     *      the low 32 bits of the xxh3 hash of every byte of the page but the checksum itself
+     */
+    fn checksum(data: &[u8]) -> u32 {
+        let mut hasher = Xxh3Default::new();
+        hasher.update(&data[..CHECKSUM_OFFSET]);
+        hasher.update(&data[CHECKSUM_OFFSET + 4..]);
+        hasher.digest() as u32
+    }
     
     /// A utility function to determine the size of the header in the page
     /// when serialized/to_bytes.
@@ -420,15 +589,39 @@ impl Page {
  *  Purpose: 
  *      iterate through the slots of the page
  *  Elements:
- *      slot: the slot we begin at
- *      slot_count: the total number of slots in the page
+ *      slots: the indexes in the header of the slots holding a value, by slot_id
+ *      next: the position in slots of the next value
  *      page: the page that we're iterating through
  */
 pub struct PageIter {
-    slot: usize,
-    slot_count: usize,
-    page: Page, 
-     
+    slots: Vec<usize>,
+    next: usize,
+    page: Page,
+}
+
+impl PageIter {
+    /*  next_ref
+     *      purpose: move onto the next value, borrowing it from the page instead of copying it
+     *  inputs:
+     *      &mut self: the iterator
+     *  outputs:
+     *      the bytes of the value, valid until the iterator moves on, None once it is done
+     */
+    pub fn next_ref(&mut self) -> Option<&[u8]> {
+        let index = *self.slots.get(self.next)?;
+        self.next += 1;
+        Some(self.page.slot_bytes(&self.page.header.slots[index]))
+    }
+    /*  is_done
+     *      purpose: check if every value of the page was returned
+     *  inputs:
+     *      &self: the iterator
+     *  outputs:
+     *      true if next would return None
+     */
+    pub fn is_done(&self) -> bool {
+        self.next >= self.slots.len()
+    }
 }
 
 impl Iterator for PageIter { 
@@ -438,21 +631,10 @@ impl Iterator for PageIter {
      *  inputs: 
      *      &mut self: a mutable reference to the page that we are iterating through
      *  outputs:
-     *      the current slot we're at
+     *      a copy of the value of the slot, see next_ref to read it in place
      */ 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.slot <= self.slot_count {
-            match self.page.get_value(self.slot as u16){
-                Some(data) => {
-                    self.slot += 1;
-                    return Some(data);
-                }
-                None => {
-                    self.slot += 1;
-                }
-            }
-        }
-        return None;
+        self.next_ref().map(<[u8]>::to_vec)
     }
 }
 
@@ -468,8 +650,8 @@ impl IntoIterator for Page {
      */
     fn into_iter(self) -> Self::IntoIter {
         PageIter{
-            slot: 0,
-            slot_count: self.header.slots.len(),
+            slots: self.value_slots(),
+            next: 0,
             page: self,
         }
     }
@@ -576,6 +758,28 @@ mod tests {
         assert_eq!(None, p.get_value(2));
     }
 
+    #[test]
+    fn hs_page_get_value_ref() {
+        init();
+        let mut p = Page::new(0);
+        let vals = get_random_vec_of_byte_vec(4, 20, 40);
+        for (i, val) in vals.iter().enumerate() {
+            assert_eq!(Some(i as SlotId), p.add_value(val));
+        }
+        assert_eq!(Some(vals[2].as_slice()), p.get_value_ref(2));
+        assert_eq!(Some(()), p.delete_value(1));
+        assert_eq!(None, p.get_value_ref(1));
+        assert_eq!(None, p.get_value_ref(4));
+        // slot 1 is reused, but the values still come in the order of their slots
+        assert_eq!(Some(1), p.add_value(&vals[1]));
+        let mut iter = p.clone().into_iter();
+        assert_eq!(Some(vals[0].as_slice()), iter.next_ref());
+        assert_eq!(Some(vals[1].clone()), iter.next());
+        assert_eq!(Some(vals[2].as_slice()), iter.next_ref());
+        assert_eq!(Some(vals[3].clone()), iter.next());
+        assert_eq!(None, iter.next_ref());
+    }
+
     // DONE
     #[test]
     fn hs_page_header_size_small() {
@@ -743,6 +947,42 @@ mod tests {
         assert_eq!(Some(4), p.add_value(&tuple_bytes_small2));
     }
 
+    #[test]
+    fn hs_page_monotonic_slots() {
+        init();
+        let mut p = Page::new(0);
+        let tuple_bytes = get_random_byte_vec(20);
+        let tuple_bytes2 = get_random_byte_vec(20);
+        let tuple_bytes3 = get_random_byte_vec(20);
+        assert_eq!(Some(0), p.add_value_with_policy(&tuple_bytes, SlotPolicy::Monotonic));
+        assert_eq!(Some(1), p.add_value_with_policy(&tuple_bytes2, SlotPolicy::Monotonic));
+        //Deleting leaves a tombstone, so 1 is not handed out again
+        assert_eq!(Some(()), p.delete_value_with_policy(1, SlotPolicy::Monotonic));
+        assert_eq!(None, p.get_value(1));
+        assert_eq!(None, p.delete_value_with_policy(1, SlotPolicy::Monotonic));
+        assert_eq!(Some(2), p.add_value_with_policy(&tuple_bytes3, SlotPolicy::Monotonic));
+        assert_eq!(tuple_bytes, p.get_value(0).unwrap());
+        assert_eq!(tuple_bytes3, p.get_value(2).unwrap());
+        //The tombstone keeps its header space
+        assert_eq!(3 * HEADER_PER_VAL_SIZE, p.get_header_size() - Page::new(0).get_header_size());
+        //Deleting the highest slot doesn't free its id either
+        assert_eq!(Some(()), p.delete_value_with_policy(2, SlotPolicy::Monotonic));
+        assert_eq!(Some(3), p.add_value_with_policy(&tuple_bytes2, SlotPolicy::Monotonic));
+
+        //Tombstones survive serialization and are skipped by the iterator
+        let p2 = Page::from_bytes(&p.get_bytes()).unwrap();
+        assert_eq!(None, p2.get_value(1));
+        assert_eq!(None, p2.get_value(2));
+        assert_eq!(Some(4), p2.clone().add_value_with_policy(&tuple_bytes, SlotPolicy::Monotonic));
+        let values: Vec<Vec<u8>> = p2.into_iter().collect();
+        assert_eq!(vec![tuple_bytes.clone(), tuple_bytes2.clone()], values);
+
+        //Reusing the lowest free id doesn't reuse tombstones
+        assert_eq!(Some(4), p.add_value(&tuple_bytes));
+        assert_eq!(Some(()), p.delete_value(0));
+        assert_eq!(Some(0), p.add_value(&tuple_bytes3));
+    }
+
     // DONE
     #[test]
     fn hs_page_size() {
@@ -756,6 +996,23 @@ mod tests {
         assert_eq!(PAGE_SIZE, page_bytes.len());
     }
 
+    #[test]
+    fn hs_page_checksum() {
+        init();
+        let mut p = Page::new(3);
+        let bytes = get_random_byte_vec(40);
+        assert_eq!(Some(0), p.add_value(&bytes));
+        let mut page_bytes = p.get_bytes();
+        assert_eq!(bytes, Page::from_bytes(&page_bytes).unwrap().get_value(0).unwrap());
+
+        //A flipped bit in the data or in the header is caught
+        page_bytes[PAGE_SIZE - 1] ^= 1;
+        assert!(Page::from_bytes(&page_bytes).is_err());
+        page_bytes[PAGE_SIZE - 1] ^= 1;
+        page_bytes[2] ^= 0x80;
+        assert!(Page::from_bytes(&page_bytes).is_err());
+    }
+
     //DONE
     #[test]
     fn hs_page_simple_byte_serialize() {
@@ -771,7 +1028,7 @@ mod tests {
 
         //Get bytes and create from bytes
         let bytes = p.get_bytes();
-        let mut p2 = Page::from_bytes(&bytes);
+        let mut p2 = Page::from_bytes(&bytes).unwrap();
         assert_eq!(0, p2.get_page_id());
         
         //Check reads
@@ -832,7 +1089,7 @@ mod tests {
         assert_eq!(None, iter.next());
         
         //Check another way
-        let p = Page::from_bytes(&page_bytes);
+        let p = Page::from_bytes(&page_bytes).unwrap();
         assert_eq!(Some(tuple_bytes.clone()), p.get_value(0));
 
         for (i, x) in p.into_iter().enumerate() {
@@ -841,7 +1098,7 @@ mod tests {
             assert_eq!(tup_vec[i], x);
         }
 
-        let p = Page::from_bytes(&page_bytes);
+        let p = Page::from_bytes(&page_bytes).unwrap();
         let mut count = 0;
         for _ in p {
             count += 1;
@@ -849,7 +1106,7 @@ mod tests {
         assert_eq!(count, 4);
         
         //Add a value and check
-        let mut p = Page::from_bytes(&page_bytes);
+        let mut p = Page::from_bytes(&page_bytes).unwrap();
         assert_eq!(Some(4), p.add_value(&tuple_bytes));
         //get the updated bytes
         let page_bytes = p.get_bytes();
@@ -860,7 +1117,7 @@ mod tests {
         assert_eq!(count, 5);
         
         //Delete
-        let mut p = Page::from_bytes(&page_bytes);
+        let mut p = Page::from_bytes(&page_bytes).unwrap();
         p.delete_value(2);
         let mut iter = p.into_iter();
         assert_eq!(Some(tuple_bytes.clone()), iter.next());
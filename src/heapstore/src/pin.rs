@@ -0,0 +1,58 @@
+#[allow(unused_imports)]
+use common::ids::{ContainerId, PageId};
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::sync::{Arc, RwLock};
+
+/// How many guards currently hold each (container, page). Shared between the buffer pool and
+/// the guards so a guard can unpin itself after the call that pinned the page has returned.
+pub(crate) type PinTable = Arc<RwLock<HashMap<(ContainerId, PageId), usize>>>;
+
+/*  struct PagePin
+ *  Purpose:
+ *      RAII guard that keeps a page pinned in the buffer pool while an iterator holds it across
+ *      next() calls
+ *  Elements:
+ *      key: the container and page id that the pin is counted under
+ *      pins: the buffer pool's pin table
+ *  Notes:
+ *      - The pin is released when the guard is dropped, pinned pages must not be evicted
+ *      - a page can be pinned before it is cached, it just can't be evicted once it is
+ */
+pub(crate) struct PagePin {
+    key: (ContainerId, PageId),
+    pins: PinTable,
+}
+
+impl PagePin {
+    /*  new
+     *      purpose: pin a page
+     *  Inputs:
+     *      container_id: the container the page belongs to
+     *      page_id: the page being pinned
+     *      pins: the pin table to count the pin in
+     *  Outputs:
+     *      a guard holding the pin
+     */
+    pub(crate) fn new(container_id: ContainerId, page_id: PageId, pins: PinTable) -> Self {
+        let key = (container_id, page_id);
+        *pins.write().unwrap().entry(key).or_insert(0) += 1;
+        PagePin{key, pins}
+    }
+}
+
+impl Drop for PagePin {
+    /*  drop
+     *      purpose: unpin the page, the entry is removed once nobody holds it
+     */
+    fn drop(&mut self) {
+        let mut pins = self.pins.write().unwrap();
+        if let Some(count) = pins.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                pins.remove(&self.key);
+            }
+        }
+    }
+}
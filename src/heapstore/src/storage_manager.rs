@@ -1,35 +1,66 @@
 #[allow(unused_imports)]
-use crate::heapfile::HeapFile;
+use crate::buffer_pool::{BufferPool, BufferPoolConfig, EvictionPolicy};
+#[allow(unused_imports)]
+use crate::heapfile::{frames_path, HeapFile};
 #[allow(unused_imports)]
 use crate::heapfileiter::HeapFileIterator;
 #[allow(unused_imports)]
-use crate::page::Page;
+use crate::index::BTree;
+#[allow(unused_imports)]
+use crate::page::{Page, Slot};
 #[allow(unused_imports)]
 use common::ids::{ContainerId, PageId, Permissions, TransactionId, ValueId};
 #[allow(unused_imports)]
-use common::storage_trait::StorageTrait;
+use common::storage_trait::{register_container, update_compression, update_row_format, update_slot_policy,
+    ContainerMetadata, ContainerType, ContainerUsage, PageCompression, SlotPolicy, StorageStats, StorageTrait};
+#[allow(unused_imports)]
+use common::io_stats::{IoCounters, IoStats, IoTracker};
+#[allow(unused_imports)]
+use common::lock_manager::{LockManager, LockTarget};
+#[allow(unused_imports)]
+use common::row_format::RowFormat;
+#[allow(unused_imports)]
+use common::undo_log::{UndoLog, Write};
 #[allow(unused_imports)]
 use common::testutil::gen_random_dir;
 #[allow(unused_imports)]
-use common::{CrustyError, PAGE_SIZE};
+use common::{CrustyError, Field, PAGE_SIZE};
 #[allow(unused_imports)]
 use std::collections::HashMap;
 #[allow(unused_imports)]
 use std::fs;
 #[allow(unused_imports)]
+use std::mem;
+#[allow(unused_imports)]
+use std::ops::Bound;
+#[allow(unused_imports)]
 use std::path::PathBuf;
 #[allow(unused_imports)]
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
 #[allow(unused_imports)]
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 
+/// Name of the file in storage_path that the container metadata is persisted to
+const METADATA_FILE: &str = "containers.meta";
+
+/// Name of the file in storage_path that the hot pages are recorded in at shutdown
+const WARMUP_FILE: &str = "warmup.meta";
+
 /// The StorageManager struct
 pub struct StorageManager {
     hash_map: Arc<RwLock<HashMap<ContainerId, Arc<HeapFile>>>>,
+    metadata: Arc<RwLock<HashMap<ContainerId, ContainerMetadata>>>, // name and type of each container
+    buffer_pool: Arc<BufferPool>, // cached pages and the pins of the pages iterators hold, shared with the iterators
+    punch_holes: AtomicBool, // if reclaim_space also punches out empty pages in the middle of a heapfile
+    extent_pages: AtomicU16, // how many pages a heapfile grows by when inserts run out of room
+    reclaimed: AtomicU64, // bytes given back to the filesystem since the sm was created
+    io: IoTracker, // pages read and written by the transactions whose IO is tracked
+    undo: UndoLog, // writes of the started transactions, undone if they abort
+    locks: Arc<LockManager>, // locks of the started transactions, taken on the pages of tables
+    index_writer: Mutex<()>, // held while an index is written to, nodes are split across several pages
     pub storage_path: String,
-    is_temp: bool, // just used for testing, checks if it's a temporary directory
-        //if temp==true when we drop the sm we should be deleting everything
+    is_temp: bool, // just used for testing, the directory is removed when the sm is dropped
 }
 
 /// The required functions in HeapStore's StorageManager that are specific for HeapFiles
@@ -43,18 +74,23 @@ impl StorageManager {
      *      _perm: 
      *      _pin:
      *  Outputs:
-     *      the page requested
+     *      the page requested, None if the container doesn't exist or the page can't be read
+     *  Notes:
+     *      - the page is only read from the heapfile if it's not in the buffer pool, only then it
+     *        counts as a read of the transaction
+     *      - a page that can't be read, e.g. because it's corrupted, is logged as an error
      */ 
-    pub(crate) fn get_page( &self, container_id: ContainerId, page_id: PageId, _tid: TransactionId,
+    pub(crate) fn get_page( &self, container_id: ContainerId, page_id: PageId, tid: TransactionId,
         _perm: Permissions, _pin: bool,) -> Option<Page> {
-        let map = &*self.hash_map.read().unwrap();
-        if !map.contains_key(&container_id){
-            None
-        } else {
-            let heapfile = map[&container_id].clone();
-            let ret_page = HeapFile::read_page_from_file(&heapfile, page_id);
-            Some(ret_page.unwrap())
-        }    
+        let heapfile = self.hash_map.read().unwrap().get(&container_id)?.clone();
+        let io = self.io.counters(tid);
+        match self.buffer_pool.get_page(&heapfile, page_id, io.as_deref()) {
+            Ok(page) => Some(page),
+            Err(e) => {
+                error!("heapstore::get_page can't read page {} of {:?}: {}", page_id, container_id, e);
+                None
+            }
+        }
     }
     /*  write_page
      *      purpose: write a page to the heapfile
@@ -64,15 +100,200 @@ impl StorageManager {
      *      page: the page that we want to write into the heapfile
      *      _tid: unique identifier for the transaction id
      *  Outputs: 
-     *      Ok() since we just wrote a page to the heapfile
+     *      Ok() once the page is in the buffer pool, a CrustyError if evicting another page failed
+     *  Notes:
+     *      - the page is marked dirty in the buffer pool and only reaches the heapfile when it's
+     *        evicted or flushed, so writing the same page many times costs a single write
+     *      - the transaction is charged for the pages written back to make room for the page
      */ 
-    pub(crate) fn write_page(&self, container_id: ContainerId, page: Page, _tid: TransactionId,) -> Result<(), CrustyError> {
+    pub(crate) fn write_page(&self, container_id: ContainerId, page: Page, tid: TransactionId,) -> Result<(), CrustyError> {
         // get the hashmap
         let map = &*self.hash_map.read().unwrap();
         // get the heapfile we want to write the page into using container_id as the identifier
         let mut hf = map.get(&container_id).unwrap();
-        // just write it to the page
-        HeapFile::write_page_to_file(&hf, page);
+        // inserts look for room in the new version of the page
+        if let Some(free_space) = hf.free_space.write().unwrap().as_mut() {
+            let pid = page.header.page_id as usize;
+            if pid >= free_space.len() {
+                // pages skipped over read back as empty pages
+                free_space.resize(pid + 1, Page::new(0).header.largest_free_space);
+            }
+            free_space[pid] = page.header.largest_free_space;
+        }
+        // keep the new version cached until it's written back
+        let io = self.io.counters(tid);
+        self.buffer_pool.put_page(hf, page, true, io.as_deref())
+    }
+    /*  write_back
+     *      purpose: write a page that values were inserted into
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      hf: the heapfile the page belongs to
+     *      page: the page
+     *      is_new: if the page is past the end of the heapfile
+     *      tid: the transaction the values were inserted for
+     *      io: the counters of the transaction, if its IO is tracked
+     *  Notes:
+     *      - new pages are appended to the heapfile right away so the file covers every page the
+     *        free space map has, other pages are cached dirty in the buffer pool
+     */
+    fn write_back(&self, hf: &Arc<HeapFile>, page: Page, is_new: bool, tid: TransactionId, io: Option<&IoCounters>) {
+        if is_new {
+            hf.write_page_to_file(page).unwrap();
+            self.io.add_written(tid, 1);
+        } else {
+            self.buffer_pool.put_page(hf, page, true, io).unwrap();
+        }
+    }
+    /*  update_page
+     *      purpose: change a page of a heapfile in place
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: the heapfile's unique identifier
+     *      page_id: the page to change
+     *      tid: the transaction the page is changed for
+     *      change: what to do to the page, returns None if it left the page as is
+     *  Outputs:
+     *      what change returned, None if the container or the page doesn't exist
+     *  Notes:
+     *      - the heapfile's free space map is held until the page is back in the buffer pool, so
+     *        an insert can't fill the page in between
+     */
+    fn update_page<R>(&self, container_id: ContainerId, page_id: PageId, tid: TransactionId,
+        change: impl FnOnce(&mut Page) -> Option<R>) -> Result<Option<R>, CrustyError> {
+        let hf = match self.hash_map.read().unwrap().get(&container_id) {
+            Some(hf) => hf.clone(),
+            None => return Ok(None),
+        };
+        if page_id >= self.get_num_pages(container_id) {
+            return Ok(None);
+        }
+        let io = self.io.counters(tid);
+        let mut free_space = hf.free_space.write().unwrap();
+        let mut page = self.buffer_pool.get_page(&hf, page_id, io.as_deref())?;
+        let res = match change(&mut page) {
+            Some(res) => res,
+            None => return Ok(None),
+        };
+        if let Some(free) = free_space.as_mut().and_then(|free_space| free_space.get_mut(page_id as usize)) {
+            *free = page.header.largest_free_space;
+        }
+        self.buffer_pool.put_page(&hf, page, true, io.as_deref())?;
+        Ok(Some(res))
+    }
+    /*  undo
+     *      purpose: undo a write made for a transaction that aborted
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      write: the write
+     *      tid: the transaction
+     *  Outputs:
+     *      Ok(()) once the write is undone or its container is gone, an error if a deleted value
+     *      can't be put back
+     *  Notes:
+     *      - undoing an insert deletes the value by the container's slot policy, so under
+     *        SlotPolicy::Monotonic the id is not handed out again
+     */
+    fn undo(&self, write: Write, tid: TransactionId) -> Result<(), CrustyError> {
+        match write {
+            Write::Insert(id) => {
+                let slot_policy = self.metadata.read().unwrap().get(&id.container_id).map(|meta| meta.slot_policy).unwrap_or_default();
+                if let (Some(page_id), Some(slot_id)) = (id.page_id, id.slot_id) {
+                    self.update_page(id.container_id, page_id, tid, |page| page.delete_value_with_policy(slot_id, slot_policy))?;
+                }
+            }
+            Write::Delete(id, value) => {
+                if let (Some(page_id), Some(slot_id)) = (id.page_id, id.slot_id) {
+                    if self.container_exists(id.container_id)
+                        && self.update_page(id.container_id, page_id, tid, |page| page.restore_value(slot_id, &value))?.is_none() {
+                        return Err(CrustyError::CrustyError(format!("Cannot undo the delete of {:?}, its slot was taken", id)));
+                    }
+                }
+            }
+            Write::IndexInsert(container_id, entries) => {
+                if self.container_exists(container_id) {
+                    let _writer = self.index_writer.lock().unwrap();
+                    let index = BTree::new(self, container_id, tid);
+                    for entry in entries.iter().rev() {
+                        index.remove(entry)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /*  build_free_space
+     *      purpose: find how many bytes are free on every page of a heapfile
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      hf: the heapfile
+     *      io: the counters of the transaction the pages are read for, if its IO is tracked
+     *  Outputs:
+     *      the free bytes of every page, in page order
+     *  Notes:
+     *      - every page is read once, through the buffer pool so dirty pages count with their
+     *        latest version. after that the map is kept up to date by inserts and write_page
+     *      - a page that can't be read is logged and counted as full, so inserts never write
+     *        over it
+     */
+    fn build_free_space(&self, hf: &Arc<HeapFile>, io: Option<&IoCounters>) -> Vec<u16> {
+        let num_pages = self.buffer_pool.num_pages(hf.container_id, hf.num_pages());
+        let mut free_space = Vec::with_capacity(num_pages as usize);
+        for pid in 0..num_pages {
+            match self.buffer_pool.get_page(hf, pid, io) {
+                Ok(page) => free_space.push(page.header.largest_free_space),
+                Err(e) => {
+                    error!("heapstore::build_free_space can't read page {} of {:?}: {}", pid, hf.container_id, e);
+                    free_space.push(0);
+                }
+            }
+        }
+        free_space
+    }
+    /*  flush_container
+     *      purpose: write the dirty pages of a container back to its heapfile
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: the heapfile's unique identifier
+     *  Outputs:
+     *      Ok(()) once the heapfile has every page written to the container, an error if the
+     *      container doesn't exist or a write failed
+     */
+    pub fn flush_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+        if !self.hash_map.read().unwrap().contains_key(&container_id) {
+            return Err(CrustyError::CrustyError(format!(
+                "Container {:?} not found for flush",
+                container_id
+            )));
+        }
+        self.buffer_pool.flush(Some(container_id))?;
+        Ok(())
+    }
+    /*  flush_all
+     *      purpose: write the dirty pages of every container back to their heapfiles
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *  Outputs:
+     *      Ok(()) once the heapfiles have every page written to the storage manager, an error if a
+     *      write failed
+     *  Notes:
+     *      - this is the checkpoint, the heapfiles alone are up to date afterwards
+     */
+    pub fn flush_all(&self) -> Result<(), CrustyError> {
+        self.buffer_pool.flush(None)?;
+        Ok(())
+    }
+    /*  clear_buffer_pool
+     *      purpose: write the dirty pages back and empty the buffer pool
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *  Notes:
+     *      - the next read of every page goes to its heapfile, used by tests that count reads
+     */
+    #[allow(dead_code)]
+    pub(crate) fn clear_buffer_pool(&self) -> Result<(), CrustyError> {
+        self.buffer_pool.flush(None)?;
+        self.buffer_pool.clear();
         Ok(())
     }
     /*  get_num_pages
@@ -82,12 +303,224 @@ impl StorageManager {
      *      container_id: unique identifier for the heapfile that we want to get the number of pages of
      *  Outputs: 
      *      the number of pages found in the heapfile returned as a PageId type
+     *  Notes:
+     *      - counts the dirty pages past the end of the heapfile that were not written back yet
      */ 
-    fn get_num_pages(&self, container_id: ContainerId) -> PageId {
+    pub(crate) fn get_num_pages(&self, container_id: ContainerId) -> PageId {
         let map = &*self.hash_map.read().unwrap();
         let mut hf = map.get(&container_id).unwrap();
         let num_pages = HeapFile::num_pages(&hf);
-        return num_pages;
+        return self.buffer_pool.num_pages(container_id, num_pages);
+    }
+    /*  container_path
+     *      purpose: get where the heapfile for a container lives
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *  Outputs:
+     *      the path storage_path/container_id.hf
+     */
+    fn container_path(&self, container_id: ContainerId) -> PathBuf {
+        let mut path = PathBuf::from(&self.storage_path);
+        path.push(container_id.to_string());
+        path.set_extension("hf");
+        path
+    }
+    /*  remove_container_files
+     *      purpose: remove the heapfile of a container and the frame file of its compressed pages
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *  Outputs:
+     *      the length of the files removed
+     */
+    fn remove_container_files(&self, container_id: ContainerId) -> Result<u64, CrustyError> {
+        let path = self.container_path(container_id);
+        let mut removed = 0;
+        for path in [frames_path(&path), path] {
+            if let Ok(meta) = fs::metadata(&path) {
+                removed += meta.len();
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(removed)
+    }
+    /*  lock_container
+     *      purpose: lock every page of a container for writing
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *      hf: the heapfile of the container
+     *      tid: the transaction taking the locks, it has to be started
+     *  Outputs:
+     *      Ok(()) once all pages are locked, an error if another transaction held one of them
+     *      past the lock timeout
+     */
+    fn lock_container(&self, container_id: ContainerId, hf: &HeapFile, tid: TransactionId) -> Result<(), CrustyError> {
+        for page_id in 0..self.buffer_pool.num_pages(container_id, hf.num_pages()) {
+            self.locks.lock(tid, LockTarget::Page(container_id, page_id), Permissions::ReadWrite).map_err(|_| {
+                CrustyError::CrustyError(format!(
+                    "Container {:?} is in use by another transaction", container_id))
+            })?;
+        }
+        Ok(())
+    }
+    /*  with_config
+     *      purpose: create a storage manager whose buffer pool uses the given settings
+     *  Inputs:
+     *      storage_path: the location that future data will ultimately be stored to
+     *      config: the settings of the buffer pool, eg its capacity
+     *  Outputs:
+     *      a new storage manager
+     */
+    pub fn with_config(storage_path: String, config: BufferPoolConfig) -> Self {
+        StorageManager::load(storage_path, false, config)
+    }
+    /*  load
+     *      purpose: create a storage manager and reopen the containers persisted in storage_path
+     *  Inputs:
+     *      storage_path: where the heapfiles and the container metadata live
+     *      is_temp: if the storage manager is just for testing
+     *      config: the settings of the buffer pool
+     *  Outputs:
+     *      a storage manager with a heapfile for every container in the metadata file
+     */
+    fn load(storage_path: String, is_temp: bool, config: BufferPoolConfig) -> Self {
+        let sm = StorageManager{hash_map: Arc::new(RwLock::new(HashMap::new())),
+                                metadata: Arc::new(RwLock::new(HashMap::new())),
+                                buffer_pool: Arc::new(BufferPool::new(config)),
+                                punch_holes: AtomicBool::new(false),
+                                extent_pages: AtomicU16::new(1),
+                                reclaimed: AtomicU64::new(0),
+                                io: IoTracker::default(),
+                                undo: UndoLog::default(),
+                                locks: Arc::new(LockManager::default()),
+                                index_writer: Mutex::new(()),
+                                storage_path: storage_path, is_temp: is_temp};
+        let mut meta_path = PathBuf::from(&sm.storage_path);
+        meta_path.push(METADATA_FILE);
+        if let Ok(file) = fs::File::open(&meta_path) {
+            let metas: Vec<ContainerMetadata> =
+                serde_cbor::from_reader(file).expect("cannot read container metadata");
+            let mut map = sm.hash_map.write().unwrap();
+            let mut metadata = sm.metadata.write().unwrap();
+            for meta in metas {
+                debug!("heapstore::load container_id: {:?}", &meta.container_id);
+                let hf = HeapFile::new(sm.container_path(meta.container_id), meta.container_id)
+                    .expect("cannot open heapfile");
+                *hf.compression.write().unwrap() = meta.compression;
+                map.insert(meta.container_id, Arc::new(hf));
+                metadata.insert(meta.container_id, meta);
+            }
+        }
+        sm.preload_hot_pages();
+        sm
+    }
+    /*  save_hot_pages
+     *      purpose: record the pages in the buffer pool, the most used first, for the next start
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *  Outputs:
+     *      Ok(()) if the pages were written to the warmup file in storage_path
+     *  Notes:
+     *      - with nothing cached the old warmup file is removed
+     */
+    fn save_hot_pages(&self) -> Result<(), CrustyError> {
+        let pages = self.buffer_pool.hot_pages();
+        let mut path = PathBuf::from(&self.storage_path);
+        path.push(WARMUP_FILE);
+        if pages.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        fs::create_dir_all(&self.storage_path)?;
+        let file = fs::File::create(path)?;
+        serde_cbor::to_writer(file, &pages).map_err(|e| CrustyError::IOError(e.to_string()))
+    }
+    /*  preload_hot_pages
+     *      purpose: read the pages recorded at the last shutdown back into the buffer pool
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *  Notes:
+     *      - the hottest pages are loaded first, until the pool is full
+     *      - pages of containers that are gone or were cut short since are skipped, and a
+     *        missing or unreadable warmup file just starts the pool empty
+     *      - the file is written by shutdown, which the server calls before it exits
+     */
+    fn preload_hot_pages(&self) {
+        let mut path = PathBuf::from(&self.storage_path);
+        path.push(WARMUP_FILE);
+        let pages: Vec<(ContainerId, PageId)> = match fs::File::open(&path) {
+            Ok(file) => match serde_cbor::from_reader(file) {
+                Ok(pages) => pages,
+                Err(e) => {
+                    debug!("heapstore::preload_hot_pages cannot read {:?}: {}", path, e);
+                    return;
+                }
+            },
+            Err(_) => return,
+        };
+        let map = self.hash_map.read().unwrap();
+        let mut loaded = 0;
+        for (container_id, page_id) in pages {
+            if loaded == self.buffer_pool.capacity() {
+                break;
+            }
+            let hf = match map.get(&container_id) {
+                Some(hf) if page_id < hf.num_pages() => hf,
+                _ => continue,
+            };
+            match self.buffer_pool.preload(hf, page_id) {
+                Ok(true) => loaded += 1,
+                Ok(false) => break,
+                Err(e) => debug!("heapstore::preload_hot_pages cannot read {:?}: {}", (container_id, page_id), e),
+            }
+        }
+        debug!("heapstore::preload_hot_pages loaded {} pages", loaded);
+    }
+    /*  persist_metadata
+     *      purpose: write the metadata of all containers to storage_path
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      metadata: the current metadata (the caller holds the lock)
+     *  Outputs:
+     *      Ok(()) if the metadata file was written
+     */
+    fn persist_metadata(&self, metadata: &HashMap<ContainerId, ContainerMetadata>) -> Result<(), CrustyError> {
+        fs::create_dir_all(&self.storage_path)?;
+        let mut metas: Vec<&ContainerMetadata> = metadata.values().collect();
+        metas.sort_by_key(|meta| meta.container_id);
+        let mut meta_path = PathBuf::from(&self.storage_path);
+        meta_path.push(METADATA_FILE);
+        let file = fs::File::create(meta_path)?;
+        serde_cbor::to_writer(file, &metas).map_err(|e| CrustyError::IOError(e.to_string()))
+    }
+    /*  set_punch_holes
+     *      purpose: choose if reclaim_space punches holes for empty pages in the middle of heapfiles
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      punch_holes: true to punch holes where the filesystem supports it
+     *  Notes:
+     *      - off by default, sparse heapfiles grow back to full size when copied by a backup
+     *        that doesn't keep holes. trailing empty pages are always cut off
+     */
+    pub fn set_punch_holes(&self, punch_holes: bool) {
+        self.punch_holes.store(punch_holes, Ordering::Relaxed);
+    }
+    /*  set_extent_pages
+     *      purpose: choose how many pages a heapfile grows by when inserts need a new page
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      pages: pages per extent, 1 to grow a page at a time
+     *  Notes:
+     *      - 1 by default. bigger extents keep the pages of a bulk load next to each other on
+     *        disk and grow the file less often, the unused pages of the last extent read back
+     *        empty and are given back by reclaim_space
+     */
+    pub fn set_extent_pages(&self, pages: PageId) {
+        self.extent_pages.store(pages.max(1), Ordering::Relaxed);
     }
     /*  get_hf_read_write_count
      *      purpose: counts the reads and writes served by the heapfile
@@ -125,8 +558,7 @@ impl StorageTrait for StorageManager {
      *      a new storage manager
      */ 
     fn new(storage_path: String) -> Self {
-        let new_sm = StorageManager{hash_map: Arc::new(RwLock::new(HashMap::new())), storage_path: storage_path, is_temp: false};
-        return new_sm;
+        return StorageManager::load(storage_path, false, BufferPoolConfig::default());
     }
     /*  new_test_sm
      *      purpose: create a new storage manager for testing
@@ -135,12 +567,12 @@ impl StorageTrait for StorageManager {
      *  Outputs:
      *      a storage manager for testing
      *  Notes: 
-     *      - Creates a temporary directory that will have to be cleaned up once it leaves the scope
+     *      - Creates a temporary directory that is removed when the storage manager is dropped
      */ 
     fn new_test_sm() -> Self {
         let storage_path = gen_random_dir().to_string_lossy().to_string();
         debug!("Making new temp storage_manager {}", storage_path);
-        let new_sm = StorageManager{hash_map: Arc::new(RwLock::new(HashMap::new())), storage_path: storage_path, is_temp: true};
+        let new_sm = StorageManager::load(storage_path, true, BufferPoolConfig::default());
         return new_sm;
     }
     /*  insert_value
@@ -156,59 +588,10 @@ impl StorageTrait for StorageManager {
      *      - Any validation will be assumed to happen before.
      *      - Function will need to find the first page that can hold the value.
      *      - A new page may need to be created if no space on existing pages can be found.
+     *      - a batch of one value, see insert_values
      */ 
     fn insert_value(&self, container_id: ContainerId, value: Vec<u8>, tid: TransactionId,) -> ValueId {
-        // Check
-        if value.len() > PAGE_SIZE {
-            panic!("Cannot handle inserting a value larger than the page size");
-        } else {
-            // get the actual heapfile from the hash map
-            let map = &*self.hash_map.read().unwrap();
-            let mut hf = map.get(&container_id).unwrap();
-            // once we have the heapfile, find all the keys and their corresponding heapfiles
-            let mut page_id = 0;
-            let num_pages = HeapFile::num_pages(hf);
-
-            while page_id < num_pages{
-                match hf.read_page_from_file(page_id){ 
-                    Ok(mut page) => {
-                        match page.add_value(&value){ 
-                            Some(slot_id) => {
-                                return ValueId{
-                                    container_id: hf.container_id,
-                                    segment_id: None,
-                                    page_id: Some(page.header.page_id),
-                                    slot_id: Some(slot_id),
-                                }
-                            } // closes Some(slot_id)
-                            None => {
-                                // go to the next page
-                                page_id +=1; 
-                            } // closes None
-                        } // closes match page.add_value(&value)
-                    } // closes Ok(mut page)
-                    _ => {
-                        panic!("doesn't work");
-                    } // closes _ 
-                } //closes match.hf.read_page_from_file(page_id)
-            }
-
-            let mut new_page = Page::new(page_id);
-            hf.write_page_to_file(new_page);
-            let new_val_id = ValueId{ 
-                container_id: hf.container_id,
-                segment_id: None,
-                page_id: Some(page_id),
-                slot_id: Some(0),
-            };
-            return new_val_id;
-
-
-            // need to make a new page
-            // write the value into the page
-            // return a value_id
-
-        }
+        self.insert_values(container_id, vec![value], tid).pop().unwrap()
     }
     /*  insert_values 
      *      purpose: insert some bytes into a container for a vector of values
@@ -221,12 +604,116 @@ impl StorageTrait for StorageManager {
      *      Returns a vector of value ids associated with the stored values.
      *  Notes:
      *      - Any validation will be assumed to happen before.
-     *      - Returns a vector of value ids associated with the stored values.
+     *      - Returns a vector of value ids associated with the stored values, in the order of values.
+     *      - the values are packed in one pass: each goes to the page being filled if it fits, else
+     *        to the first page the heapfile's free space map says has room, else to a new page.
+     *        full pages are skipped without being read
+     *      - slot ids are chosen by the container's slot policy
+     *      - a page is written once when the batch moves on from it, existing pages through the
+     *        buffer pool and new pages appended to the heapfile right away so the file covers them
+     *      - pages are locked for writing. pages another transaction holds a lock on are skipped
+     *        instead of waited for, the free space map is held meanwhile
+     *      - pages that can't be read are logged and skipped as if they were full
      */ 
     fn insert_values(&self, container_id: ContainerId, values: Vec<Vec<u8>>,tid: TransactionId,
     ) -> Vec<ValueId> {
-
-        panic!("TODO milestone hs");
+        if values.iter().any(|value| value.len() > PAGE_SIZE) {
+            panic!("Cannot handle inserting a value larger than the page size");
+        }
+        let hf = self.hash_map.read().unwrap().get(&container_id).expect("Container ID Missing on insert").clone();
+        // holding the map for the whole batch keeps two inserts from taking the same slot
+        let mut free_space = hf.free_space.write().unwrap();
+        // a truncate swapped in a new heapfile while we waited for the map, insert into that one
+        if !Arc::ptr_eq(&hf, self.hash_map.read().unwrap().get(&container_id).expect("Container ID Missing on insert")) {
+            drop(free_space);
+            return self.insert_values(container_id, values, tid);
+        }
+        let slot_policy = self.metadata.read().unwrap().get(&container_id).map(|meta| meta.slot_policy).unwrap_or_default();
+        let io = self.io.counters(tid);
+        if free_space.is_none() {
+            *free_space = Some(self.build_free_space(&hf, io.as_deref()));
+        }
+        let free_space = free_space.as_mut().unwrap();
+        // the page being filled and if it's new to the heapfile
+        let mut open: Option<(Page, bool)> = None;
+        // pages of an extent allocated by this batch, they are empty and need not be read
+        let mut fresh = 0..0;
+        let mut ids = Vec::with_capacity(values.len());
+        for value in &values {
+            // the value and its slot in the header
+            let needed = value.len() + mem::size_of::<Slot>();
+            let mut slot_id = match open.as_mut() {
+                Some((page, _)) if page.header.largest_free_space as usize >= needed => page.add_value_with_policy(value, slot_policy),
+                _ => None,
+            };
+            let mut page_id = 0;
+            while slot_id.is_none() {
+                // move on from the page being filled
+                if let Some((page, is_new)) = open.take() {
+                    free_space[page.header.page_id as usize] = page.header.largest_free_space;
+                    self.write_back(&hf, page, is_new, tid, io.as_deref());
+                }
+                while page_id < free_space.len() && (free_space[page_id] as usize) < needed {
+                    page_id += 1;
+                }
+                let extent_pages = self.extent_pages.load(Ordering::Relaxed);
+                if page_id == free_space.len() && extent_pages > 1 {
+                    // grow the heapfile by an extent and fill its pages next
+                    hf.allocate_pages(page_id as PageId, extent_pages).unwrap();
+                    free_space.resize(page_id + extent_pages as usize, Page::new(0).header.largest_free_space);
+                    fresh = page_id..free_space.len();
+                    continue;
+                }
+                let target = LockTarget::Page(container_id, page_id as PageId);
+                if page_id == free_space.len() {
+                    // need to make a new page, nobody else can hold a lock on it
+                    self.locks.try_lock(tid, target, Permissions::ReadWrite);
+                    let mut page = Page::new(page_id as PageId);
+                    slot_id = Some(page.add_value_with_policy(value, slot_policy).expect("value does not fit on an empty page"));
+                    free_space.push(page.header.largest_free_space);
+                    open = Some((page, true));
+                } else if !self.locks.try_lock(tid, target, Permissions::ReadWrite) && !fresh.contains(&page_id) {
+                    page_id += 1;
+                } else {
+                    let mut page = if fresh.contains(&page_id) {
+                        // empty pages are filled in order, the ones before this are in use
+                        fresh.start = page_id + 1;
+                        Page::new(page_id as PageId)
+                    } else {
+                        match self.buffer_pool.get_page(&hf, page_id as PageId, io.as_deref()) {
+                            Ok(page) => page,
+                            Err(e) => {
+                                // an unreadable page is taken as full, so it's left as it is on disk
+                                error!("heapstore::insert_values can't read page {} of {:?}: {}", page_id, container_id, e);
+                                free_space[page_id] = 0;
+                                page_id += 1;
+                                continue;
+                            }
+                        }
+                    };
+                    slot_id = page.add_value_with_policy(value, slot_policy);
+                    if slot_id.is_some() {
+                        open = Some((page, false));
+                    } else {
+                        // the free bytes are split up, don't try values this big here again
+                        free_space[page_id] = (needed - 1) as u16;
+                        page_id += 1;
+                    }
+                }
+            }
+            ids.push(ValueId{
+                container_id,
+                segment_id: None,
+                page_id: open.as_ref().map(|(page, _)| page.header.page_id),
+                slot_id,
+            });
+        }
+        if let Some((page, is_new)) = open {
+            free_space[page.header.page_id as usize] = page.header.largest_free_space;
+            self.write_back(&hf, page, is_new, tid, io.as_deref());
+        }
+        self.undo.record(tid, ids.iter().map(|id| Write::Insert(*id)));
+        ids
     }
     /*  delete_value
      *      purpose: Delete the data for a value. 
@@ -235,12 +722,32 @@ impl StorageTrait for StorageManager {
      *      id: 
      *      tid: 
      *  Outputs:    
-     *      Ok()
+     *      Ok(), an error if the container doesn't exist or the transaction was aborted waiting
+     *      for the page
      *  Notes:
      *      - If the valueID is not found it returns Ok() still.
+     *      - the slot is freed by the container's slot policy
+     *      - the page is locked for writing first
      */ 
     fn delete_value(&self, id: ValueId, tid: TransactionId) -> Result<(), CrustyError> {
-        panic!("TODO milestone hs");
+        if !self.container_exists(id.container_id) {
+            return Err(CrustyError::CrustyError(format!("Container {:?} not found for delete", id.container_id)));
+        }
+        let (page_id, slot_id) = match (id.page_id, id.slot_id) {
+            (Some(page_id), Some(slot_id)) => (page_id, slot_id),
+            _ => return Ok(()),
+        };
+        self.locks.lock(tid, LockTarget::Page(id.container_id, page_id), Permissions::ReadWrite)?;
+        let slot_policy = self.metadata.read().unwrap().get(&id.container_id).map(|meta| meta.slot_policy).unwrap_or_default();
+        let deleted = self.update_page(id.container_id, page_id, tid, |page| {
+            let value = page.get_value(slot_id)?;
+            page.delete_value_with_policy(slot_id, slot_policy)?;
+            Some(value)
+        })?;
+        if let Some(value) = deleted {
+            self.undo.record(tid, vec![Write::Delete(id, value)]);
+        }
+        Ok(())
     }
     /*  update_value
      *      purpose: updates a value
@@ -254,54 +761,52 @@ impl StorageTrait for StorageManager {
      *  Notes:
      *      - Returns record ID on update (which may have changed).
      *      - Any process that needs to determine if a value changed will need to compare the return valueId against the sent value.
+     *      - the value is deleted and inserted again, so it may move to another page
      */ 
-    fn update_value(&self, value: Vec<u8>, id: ValueId,_tid: TransactionId,
+    fn update_value(&self, value: Vec<u8>, id: ValueId,tid: TransactionId,
     ) -> Result<ValueId, CrustyError> {
-        panic!("TODO milestone hs");
+        self.delete_value(id, tid)?;
+        Ok(self.insert_value(id.container_id, value, tid))
     }
     /*  create_container
      *      purpose: create a new container (heapfile) to be stored
      *  Inputs:
      *      &self: 
-     *      container_id
+     *      container_id: unique identifier for the new heapfile
+     *      name: optional name to register the container under (eg the table name)
+     *      container_type: what the container stores
      *  Outputs: 
-     *      Ok(())
+     *      Ok(()) if the container was created or already exists under the same name
+     *  Notes:
+     *      - the metadata is written to storage_path so the container comes back on restart
      */ 
-    fn create_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
-        /*
-        let mut map = &mut self.hash_map.write().unwrap().clone();
-        if map.contains_key(&container_id) {
-            debug!("memstore::create_container container_id: {:?} already exists", &container_id);
-            return Ok(());
-        }
-        debug!("memstore::create_container container_id: {:?} does not exist yet", &container_id);
-        //get the path
-        let path = &mut self.storage_path.clone();
-        // make the new path
-        path.push_str(&container_id.to_string());
-        let buffer = PathBuf::from(path.clone());
-        let mut new_hf = HeapFile::new(buffer, container_id).unwrap();
-        map.insert(container_id, Arc::new(new_hf));
-        Ok(())
-        */
+    fn create_container(&self, container_id: ContainerId, name: Option<String>,
+        container_type: ContainerType) -> Result<(), CrustyError> {
         let mut map = self.hash_map.write().unwrap();
-        let hf = HeapFile::new(self.  
-
-        let map = &*self.hash_map.read().unwrap();
-        let mut hf = map.get(&container_id).unwrap();
-
-
-        let mut map = &mut self.hash_map.read().unwrap().clone();
-        //get the path
-        let path = &mut self.storage_path.clone();
-        // make the new path
-        path.push_str(&container_id.to_string());
-        let buffer = PathBuf::from(path.clone());
-        let mut new_hf = HeapFile::new(buffer, container_id).unwrap();
-        println!("container_id: {:?}", container_id);
+        let mut metadata = self.metadata.write().unwrap();
+        if !register_container(&mut metadata, container_id, name, container_type)? {
+            debug!("heapstore::create_container container_id: {:?} already exists", &container_id);
+            // an unnamed container may have just been given a name
+            return self.persist_metadata(&metadata);
+        }
+        debug!("heapstore::create_container container_id: {:?} does not exist yet", &container_id);
+        // make sure the directory we are storing heapfiles in exists
+        fs::create_dir_all(&self.storage_path)?;
+        let new_hf = HeapFile::new(self.container_path(container_id), container_id)?;
         map.insert(container_id, Arc::new(new_hf));
-        Ok(())
-        
+        self.persist_metadata(&metadata)
+    }
+    /*  list_containers
+     *      purpose: get the metadata of every container
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *  Outputs:
+     *      the metadata ordered by container id
+     */
+    fn list_containers(&self) -> Vec<ContainerMetadata> {
+        let mut res: Vec<ContainerMetadata> = self.metadata.read().unwrap().values().cloned().collect();
+        res.sort_by_key(|meta| meta.container_id);
+        res
     }
     /*  remove_container
      *      purpose: remove the container and all the stored values in the container
@@ -313,96 +818,600 @@ impl StorageTrait for StorageManager {
      *  Notes: 
      *      - If the container is persisted remove the underlying files
      *      - fs::remove_dir_all()
+     *      - the dirty pages are written back first, open iterators keep reading the heapfile
      */ 
     fn remove_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
-        panic!("TODO milestone hs");
+        let mut map = self.hash_map.write().unwrap();
+        let mut metadata = self.metadata.write().unwrap();
+        if map.remove(&container_id).is_none() {
+            debug!("heapstore::remove_container container_id: {:?} does not exist", &container_id);
+            return Ok(());
+        }
+        debug!("heapstore::remove_container container_id: {:?} exists. dropping", &container_id);
+        metadata.remove(&container_id);
+        // open iterators read the heapfile once the cached pages are gone, so it needs the dirty ones
+        self.buffer_pool.flush(Some(container_id))?;
+        self.buffer_pool.discard_container(container_id);
+        self.persist_metadata(&metadata)?;
+        // open iterators still hold the heapfile and its file handles, so they can finish reading
+        self.remove_container_files(container_id)?;
+        Ok(())
     }
-    /*  get_iterator
-     *      purpose: gets an iterator that returns all valid records
+    /*  truncate_container
+     *      purpose: remove all the stored values in the container without removing the container
      *  Inputs:
-     *      &self: 
-     *      container_id:
-     *      tid: 
-     *      _perm:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile we are emptying
      *  Outputs:
-     *      A ValIterator
-     */ 
-    fn get_iterator(&self, container_id: ContainerId, tid: TransactionId, _perm: Permissions,
-    ) -> Self::ValIterator {
-        panic!("TODO milestone hs");
+     *      Ok(()) if the heapfile was emptied, an error if the container doesn't exist or another
+     *      transaction holds on to one of its pages past the lock timeout
+     *  Notes:
+     *      - all pages are dropped at once by swapping in a new empty heapfile, no values are visited
+     *      - every page is locked for writing first, so the writes of other transactions are
+     *        committed or rolled back before their values go. the truncate can't be undone
+     *      - the free space map of the old heapfile is held while it is swapped out, so an insert
+     *        batch either finishes before or starts over with the new heapfile
+     *      - open iterators keep the old heapfile, whose file goes away once they are done with it.
+     *        the dirty pages are written back to it first so they can still read them
+     */
+    fn truncate_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+        let hf = match self.hash_map.read().unwrap().get(&container_id) {
+            Some(hf) => hf.clone(),
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Container {:?} not found for truncate",
+                    container_id
+                )))
+            }
+        };
+        debug!("heapstore::truncate_container container_id: {:?}", &container_id);
+        let tid = TransactionId::new();
+        self.locks.start(tid);
+        let res = self.lock_container(container_id, &hf, tid).and_then(|()| {
+            let _free_space = hf.free_space.write().unwrap();
+            // pages an insert batch added while we waited for the map
+            self.lock_container(container_id, &hf, tid)?;
+            let mut map = self.hash_map.write().unwrap();
+            // open iterators read the old heapfile once the cached pages are gone, so it needs the dirty ones
+            self.buffer_pool.flush(Some(container_id))?;
+            let old_len = self.remove_container_files(container_id)?;
+            self.reclaimed.fetch_add(old_len, Ordering::Relaxed);
+            let new_hf = HeapFile::new(self.container_path(container_id), container_id)?;
+            *new_hf.compression.write().unwrap() = *hf.compression.read().unwrap();
+            map.insert(container_id, Arc::new(new_hf));
+            self.buffer_pool.discard_container(container_id);
+            Ok(())
+        });
+        self.locks.release(tid);
+        res
     }
-    /*  get_value
-     *      purpose: get the data for a particular ValueId
-     *  Inputs: 
-     *      &self: 
-     *      id: 
-     *      tid:
-     *      perm:
+    /*  reclaim_space
+     *      purpose: give the space of a heapfile's empty pages back to the filesystem
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile to shrink
      *  Outputs:
-     *      The value that we wanted to retrieve in vector form or an Error
-     */ 
-    fn get_value(&self, id: ValueId, tid: TransactionId,perm: Permissions,
-    ) -> Result<Vec<u8>, CrustyError> {
-        panic!("TODO milestone hs");
+     *      the number of bytes reclaimed, an error if the container doesn't exist
+     *  Notes:
+     *      - holes are only punched if set_punch_holes(true) was called
+     */
+    fn reclaim_space(&self, container_id: ContainerId) -> Result<u64, CrustyError> {
+        let hf = match self.hash_map.read().unwrap().get(&container_id) {
+            Some(hf) => hf.clone(),
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Container {:?} not found for reclaim",
+                    container_id
+                )))
+            }
+        };
+        // the empty pages are found in the heapfile, so it has to be up to date
+        self.buffer_pool.flush(Some(container_id))?;
+        // nothing is dirty now, and cached pages that get cut off must not outlive the cut
+        self.buffer_pool.discard_container(container_id);
+        let reclaimed = hf.reclaim_space(self.punch_holes.load(Ordering::Relaxed))?;
+        // trailing pages are gone, the next insert maps the pages that are left
+        *hf.free_space.write().unwrap() = None;
+        debug!("heapstore::reclaim_space container_id: {:?} reclaimed {} bytes", &container_id, reclaimed);
+        self.reclaimed.fetch_add(reclaimed, Ordering::Relaxed);
+        Ok(reclaimed)
     }
-
-    /// Notify the storage manager that the transaction is finished so that any held resources can be released.
-    /*  transaction_finished
-     *      purpose: notify the SM that the trasnaction is finished so that any held resources can be released
+    /*  storage_stats
+     *      purpose: report the space used by the heapfiles
      *  Inputs:
-     *      &self:
-     *      tid: 
+     *      &self: a reference to the storage manager
      *  Outputs:
-     *      i actually don't know
-     */ 
-    fn transaction_finished(&self, tid: TransactionId) {
-        panic!("TODO milestone tm");
+     *      the number of heapfiles, their logical and allocated bytes, the bytes reclaimed so far
+     *      and the bytes compression saved writing pages
+     */
+    fn storage_stats(&self) -> StorageStats {
+        let map = self.hash_map.read().unwrap();
+        let mut stats = StorageStats{containers: map.len(),
+                                     reclaimed_bytes: self.reclaimed.load(Ordering::Relaxed),
+                                     ..StorageStats::default()};
+        for hf in map.values() {
+            if let Ok((logical, allocated)) = hf.disk_size() {
+                stats.logical_bytes += logical;
+                stats.allocated_bytes += allocated;
+            }
+            stats.compression_saved_bytes += hf.saved_bytes.load(Ordering::Relaxed);
+        }
+        stats
     }
-    /*  reset
-     *      purpose: Testing utility to reset all state associated the storage manager.
-     *  Inputs: 
-     *      &self: 
-     *  Outputs: 
-     *      Not sure
+    /*  container_usage
+     *      purpose: report the space used by a single heapfile
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *  Outputs:
+     *      the length of the heapfile and the bytes of its values, an error if the container doesn't exist
      *  Notes:
-     *      - If there is a buffer pool it should be reset.
-     */ 
-    fn reset(&self) {
-        panic!("TODO milestone hs");
+     *      - dirty pages are written back first so they are counted
+     */
+    fn container_usage(&self, container_id: ContainerId) -> Result<ContainerUsage, CrustyError> {
+        match self.hash_map.read().unwrap().get(&container_id) {
+            Some(hf) => {
+                self.buffer_pool.flush(Some(container_id))?;
+                hf.usage()
+            }
+            None => Err(CrustyError::CrustyError(format!(
+                "Container {:?} not found for usage",
+                container_id
+            ))),
+        }
     }
-    /*  shutdown
-     *      purpose: shut down the SM
-     *  Inputs: 
-     *      &self: 
+    /*  set_row_format
+     *      purpose: record the oldest format the rows of a container are in
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *      row_format: the oldest format of its rows
      *  Outputs:
-     *      none(?)
-     *  Notes: 
-     *      - If temp, this should remove all stored files.
-     *      - Can call drop. Should be safe to call multiple times.
-     *      - Implement shut down and then call it in drop!!!!!
-     */ 
-    fn shutdown(&self) {
-        panic!("TODO milestone hs");
+     *      Ok(()) once the metadata is persisted, an error if the container doesn't exist
+     */
+    fn set_row_format(&self, container_id: ContainerId, row_format: RowFormat) -> Result<(), CrustyError> {
+        let mut metadata = self.metadata.write().unwrap();
+        update_row_format(&mut metadata, container_id, row_format)?;
+        self.persist_metadata(&metadata)
     }
-}
-
-/// Trait Impl for Drop
-impl Drop for StorageManager {
-    /*  drop
-     *      purpose: shutdown the storage manager
-     *  Inputs: 
-     *      &mut self:
+    /*  set_slot_policy
+     *      purpose: record how the slot ids of values inserted into a container are chosen
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *      slot_policy: ReuseLowest or Monotonic
      *  Outputs:
-     *      None, just shuts the storage manager down
-     *  Notes: 
-     *      - Can call be called by shutdown. 
-     *      - Should be safe to call multiple times.
-     *      - If temp, this should remove all stored files.
+     *      Ok(()) once the metadata is persisted, an error if the container doesn't exist
+     *  Notes:
+     *      - tombstones left under Monotonic stay if the policy goes back to ReuseLowest,
+     *        their ids are not reused
+     */
+    fn set_slot_policy(&self, container_id: ContainerId, slot_policy: SlotPolicy) -> Result<(), CrustyError> {
+        let mut metadata = self.metadata.write().unwrap();
+        update_slot_policy(&mut metadata, container_id, slot_policy)?;
+        self.persist_metadata(&metadata)
+    }
+    /*  set_compression
+     *      purpose: set how the pages of a container are compressed when they are written
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *      compression: None or Snappy
+     *  Outputs:
+     *      Ok(()) once the metadata is persisted, an error if the container doesn't exist
+     *  Notes:
+     *      - pages on disk are read whether they are compressed or not, so they are only
+     *        compressed or decompressed once they are written back
+     *      - the buffer pool holds decompressed pages either way
+     */
+    fn set_compression(&self, container_id: ContainerId, compression: PageCompression) -> Result<(), CrustyError> {
+        // the same lock order as create_container
+        let map = self.hash_map.read().unwrap();
+        let mut metadata = self.metadata.write().unwrap();
+        update_compression(&mut metadata, container_id, compression)?;
+        if let Some(hf) = map.get(&container_id) {
+            *hf.compression.write().unwrap() = compression;
+        }
+        self.persist_metadata(&metadata)
+    }
+    /*  container_exists
+     *      purpose: check if a container was created and hasn't been removed since
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *  Outputs:
+     *      true if the storage manager has a heapfile for the container
+     */
+    fn container_exists(&self, container_id: ContainerId) -> bool {
+        self.hash_map.read().unwrap().contains_key(&container_id)
+    }
+    /*  get_iterator
+     *      purpose: gets an iterator that returns all valid records
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile to iterate through
+     *      tid: the transaction the values are read for
+     *      perm: the permissions the pages are locked with
+     *  Outputs:
+     *      A ValIterator, without any values if the container is empty or doesn't exist
+     *  Notes:
+     *      - the iterator reads the pages through the buffer pool, so it sees every write made
+     *        before it was created without the dirty pages being written back
+     *      - it keeps the heapfile, so it can finish even if the container is truncated or removed
      */ 
-    fn drop(&mut self) {
-        //switch around with drop
-        println!("srry");
-        //panic!("TODO milestone hs");
+    fn get_iterator(&self, container_id: ContainerId, tid: TransactionId, perm: Permissions,
+    ) -> Self::ValIterator {
+        self.get_prefetching_iterator(container_id, tid, perm, 0)
+    }
+    /*  get_prefetching_iterator
+     *      purpose: gets an iterator that reads several pages at once
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile to iterate through
+     *      tid: the transaction the values are read for
+     *      perm: the permissions the pages are locked with
+     *      prefetch_pages: how many pages to read along with the one the iterator moves onto
+     *  Outputs:
+     *      A ValIterator, the same as get_iterator
+     *  Notes:
+     *      - the pages are read through the buffer pool, prefetched pages that aren't cached with a
+     *        single read, and cached as they are read
+     *      - pages are locked when the iterator gets to them, prefetched pages as they are read
+     */
+    fn get_prefetching_iterator(&self, container_id: ContainerId, tid: TransactionId, perm: Permissions,
+        prefetch_pages: usize) -> Self::ValIterator {
+        let hf = self.hash_map.read().unwrap().get(&container_id).cloned();
+        if hf.is_none() {
+            debug!("heapstore::get_iterator container_id: {:?} does not exist", &container_id);
+        }
+        let prefetch = prefetch_pages.min(PageId::MAX as usize) as PageId;
+        HeapFileIterator::new(container_id, tid, hf, self.buffer_pool.clone(), prefetch, self.io.counters(tid),
+                              self.locks.clone(), perm)
+    }
+    /*  get_partition_iterator
+     *      purpose: gets an iterator over a range of the pages of a heapfile, for one of several
+     *          threads that scan it together
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile to iterate through
+     *      tid: the transaction the values are read for
+     *      perm: the permissions the pages are locked with
+     *      prefetch_pages: how many pages to read along with the one the iterator moves onto
+     *      partition: which of the ranges to iterate through
+     *      partitions: how many ranges the pages are split into
+     *  Outputs:
+     *      A ValIterator over the values of the pages of the range
+     *  Notes:
+     *      - see HeapFileIterator::with_partition for how the pages are split
+     */
+    fn get_partition_iterator(&self, container_id: ContainerId, tid: TransactionId, perm: Permissions,
+        prefetch_pages: usize, partition: usize, partitions: usize) -> Self::ValIterator {
+        self.get_prefetching_iterator(container_id, tid, perm, prefetch_pages)
+            .with_partition(partition, partitions)
+    }
+    /*  warm_container
+     *      purpose: read the first pages of a heapfile into the buffer pool ahead of queries
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile to warm
+     *      max_pages: how many of its first pages to read at most
+     *  Outputs:
+     *      how many of those pages are cached afterwards, an error if the container doesn't exist
+     *      or a read failed
+     *  Notes:
+     *      - never more pages than the pool holds, so warming doesn't evict the pages it loaded
+     *      - scans read through the pool, so the first scan doesn't read warmed pages again. a
+     *        table bigger than the pool evicts them with its own reads before it gets to them
+     */
+    fn warm_container(&self, container_id: ContainerId, max_pages: usize) -> Result<usize, CrustyError> {
+        let hf = match self.hash_map.read().unwrap().get(&container_id) {
+            Some(hf) => hf.clone(),
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Container {:?} not found for warm",
+                    container_id
+                )))
+            }
+        };
+        let num_pages = (self.get_num_pages(container_id) as usize)
+            .min(max_pages)
+            .min(self.buffer_pool.capacity());
+        let mut cached = 0;
+        for page_id in 0..num_pages {
+            if self.buffer_pool.preload(&hf, page_id as PageId)? {
+                cached += 1;
+            }
+        }
+        debug!("heapstore::warm_container container_id: {:?} cached {} pages", &container_id, cached);
+        Ok(cached)
+    }
+    /*  get_values_with_ids
+     *      purpose: get every value of a container with its id
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile
+     *      tid: the transaction the values are read for
+     *      perm: the permissions the pages are locked with
+     *  Outputs:
+     *      the values and their ids in page and then slot order, the order of get_iterator, or
+     *      the error of a page that can't be read or locked
+     *  Notes:
+     *      - the pages are read through the buffer pool
+     *      - a page that can't be locked fails the call, the transaction was aborted then
+     */
+    fn get_values_with_ids(&self, container_id: ContainerId, tid: TransactionId, perm: Permissions,
+    ) -> Result<Vec<(ValueId, Vec<u8>)>, CrustyError> {
+        let hf = match self.hash_map.read().unwrap().get(&container_id) {
+            Some(hf) => hf.clone(),
+            None => return Ok(Vec::new()),
+        };
+        let io = self.io.counters(tid);
+        let mut values = Vec::new();
+        for page_id in 0..self.get_num_pages(container_id) {
+            self.locks.lock(tid, LockTarget::Page(container_id, page_id), perm)?;
+            let page = self.buffer_pool.get_page(&hf, page_id, io.as_deref())?;
+            let mut slot_ids: Vec<u16> = page.header.slots.iter().filter(|slot| !slot.is_tombstone()).map(|slot| slot.slot_id).collect();
+            slot_ids.sort();
+            for slot_id in slot_ids {
+                if let Some(value) = page.get_value(slot_id) {
+                    values.push((ValueId{container_id, segment_id: None, page_id: Some(page_id), slot_id: Some(slot_id)}, value));
+                }
+            }
+        }
+        Ok(values)
+    }
+    /*  index_insert
+     *      purpose: add entries to the B-tree index of a container
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile of the index
+     *      entries: the keys and packed ids of the values they point at
+     *      tid: the transaction the pages are written for
+     *  Outputs:
+     *      Ok(()) once the entries are in the index, an error if the container doesn't exist or
+     *      a key is too large
+     *  Notes:
+     *      - one writer at a time, for all indexes, a split writes several pages
+     *      - the entries before one that fails stay in the index
+     *      - only the entries that are new to the index are undone if the transaction aborts
+     */
+    fn index_insert(&self, container_id: ContainerId, entries: Vec<(Field, u64)>, tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        if !self.container_exists(container_id) {
+            return Err(CrustyError::CrustyError(format!("Container {:?} not found", container_id)));
+        }
+        let _writer = self.index_writer.lock().unwrap();
+        let index = BTree::new(self, container_id, tid);
+        let mut added = Vec::new();
+        let mut res = Ok(());
+        for entry in entries {
+            match index.insert(entry.clone()) {
+                Ok(true) => added.push(entry),
+                Ok(false) => {}
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            }
+        }
+        self.undo.record(tid, vec![Write::IndexInsert(container_id, added)]);
+        res
+    }
+    /*  index_range
+     *      purpose: look up the entries of the B-tree index of a container within bounds
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      container_id: unique identifier for the heapfile of the index
+     *      low: the lower bound of the keys
+     *      high: the upper bound of the keys
+     *      tid: the transaction the pages are read for
+     *  Outputs:
+     *      the packed value ids of the entries ordered by key and then id, an error if the
+     *      container doesn't exist
+     *  Notes:
+     *      - waits for a writer, whose splits are only consistent once it's done
+     */
+    fn index_range(&self, container_id: ContainerId, low: Bound<&Field>, high: Bound<&Field>, tid: TransactionId,
+    ) -> Result<Vec<u64>, CrustyError> {
+        if !self.container_exists(container_id) {
+            return Err(CrustyError::CrustyError(format!("Container {:?} not found", container_id)));
+        }
+        let _writer = self.index_writer.lock().unwrap();
+        BTree::new(self, container_id, tid).range(low, high)
+    }
+    /*  get_value
+     *      purpose: get the data for a particular ValueId
+     *  Inputs: 
+     *      &self: a reference to the storage manager
+     *      id: the container, page and slot of the value
+     *      tid: the transaction the value is read for
+     *      perm: the permissions the page is locked with
+     *  Outputs:
+     *      The value that we wanted to retrieve in vector form or an Error if the container, the page
+     *      or the slot doesn't exist, or if the transaction was aborted waiting for the page
+     *  Notes:
+     *      - the page is read through the buffer pool
+     */ 
+    fn get_value(&self, id: ValueId, tid: TransactionId, perm: Permissions,
+    ) -> Result<Vec<u8>, CrustyError> {
+        let hf = match self.hash_map.read().unwrap().get(&id.container_id) {
+            Some(hf) => hf.clone(),
+            None => return Err(CrustyError::ExecutionError(format!("File ID not found {:?}", id))),
+        };
+        let (page_id, slot_id) = match (id.page_id, id.slot_id) {
+            (Some(page_id), Some(slot_id)) if page_id < self.get_num_pages(id.container_id) => (page_id, slot_id),
+            _ => return Err(CrustyError::ExecutionError(format!("Record ID not found {:?}", id))),
+        };
+        self.locks.lock(tid, LockTarget::Page(id.container_id, page_id), perm)?;
+        let io = self.io.counters(tid);
+        let page = self.buffer_pool.get_page(&hf, page_id, io.as_deref())?;
+        page.get_value(slot_id)
+            .ok_or_else(|| CrustyError::ExecutionError(format!("Record ID not found {:?}", id)))
+    }
+
+    /*  track_io
+     *      purpose: start counting the pages read and written for a transaction
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      tid: the transaction, usually the one a query runs in
+     *  Notes:
+     *      - only pages read from or written to a heapfile count, buffer pool hits don't
+     */
+    fn track_io(&self, tid: TransactionId) {
+        self.io.track(tid);
+    }
+    /*  untrack_io
+     *      purpose: stop counting the IO of a transaction
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      tid: the transaction
+     *  Outputs:
+     *      the pages read and written for the transaction since track_io, zeros if it wasn't tracked
+     */
+    fn untrack_io(&self, tid: TransactionId) -> IoStats {
+        self.io.untrack(tid)
+    }
+
+    /*  start_transaction
+     *      purpose: start recording the writes of a transaction so they can be undone
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      tid: the transaction
+     *  Notes:
+     *      - from now on the transaction locks the pages of tables it reads and writes
+     */
+    fn start_transaction(&self, tid: TransactionId) {
+        self.undo.start(tid);
+        self.locks.start(tid);
+    }
+    /*  check_transaction
+     *      purpose: find out if a transaction was aborted waiting for a lock
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      tid: the transaction
+     *  Outputs:
+     *      Ok(()), or CrustyError::TransactionAbortedError until the transaction is aborted
+     */
+    fn check_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.locks.check(tid)
+    }
+    /// Notify the storage manager that the transaction is finished so that any held resources can be released.
+    /*  transaction_finished
+     *      purpose: notify the SM that the trasnaction is finished so that any held resources can be released
+     *  Inputs:
+     *      &self:
+     *      tid: the transaction that committed
+     *  Notes:
+     *      - the writes are already in the buffer pool, only the record of them is dropped
+     *      - the locks of the transaction are released
+     */ 
+    fn transaction_finished(&self, tid: TransactionId) {
+        self.undo.commit(tid);
+        self.locks.release(tid);
+    }
+    /*  abort_transaction
+     *      purpose: undo the writes of a started transaction and finish it
+     *  Inputs:
+     *      &self: a reference to the storage manager
+     *      tid: the transaction that aborted
+     *  Outputs:
+     *      Ok(()) once every write is undone, else the error of the first write that couldn't be
+     *  Notes:
+     *      - writes are undone the latest first, so the slot of a deleted value is free again
+     *        by the time its delete is undone
+     *      - the writes after one that fails are still undone
+     *      - the locks of the transaction are released once its writes are undone
+     */
+    fn abort_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        let mut res = Ok(());
+        for write in self.undo.abort(tid) {
+            if let Err(e) = self.undo(write, tid) {
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        self.locks.release(tid);
+        res
+    }
+    /*  reset
+     *      purpose: Testing utility to reset all state associated the storage manager.
+     *  Inputs: 
+     *      &self: 
+     *  Outputs: 
+     *      Ok(()) once every container and its heapfile is gone, an error if a file could not be removed
+     *  Notes:
+     *      - The buffer pool is emptied without writing the dirty pages back.
+     *      - The heapfiles, the container metadata and the warmup file are removed from storage_path,
+     *        other files in it are left alone.
+     *      - running transactions are forgotten, their writes can't be undone anymore
+     */ 
+    fn reset(&self) -> Result<(), CrustyError> {
+        let mut map = self.hash_map.write().unwrap();
+        let mut metadata = self.metadata.write().unwrap();
+        debug!("heapstore::reset dropping {} containers", map.len());
+        self.buffer_pool.clear();
+        self.undo.clear();
+        self.locks.clear();
+        self.reclaimed.store(0, Ordering::Relaxed);
+        // open iterators still hold their heapfile and its file handle, so they can finish reading
+        for container_id in map.keys() {
+            self.remove_container_files(*container_id)?;
+        }
+        map.clear();
+        metadata.clear();
+        for file in [METADATA_FILE, WARMUP_FILE] {
+            let mut path = PathBuf::from(&self.storage_path);
+            path.push(file);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+    /*  shutdown
+     *      purpose: shut down the SM
+     *  Inputs: 
+     *      &self: 
+     *  Outputs:
+     *      Ok(()) once every dirty page is written back and the hot pages are recorded, the error
+     *      of the first write that failed otherwise
+     *  Notes: 
+     *      - Should be safe to call multiple times, drop calls it too.
+     *      - the hot pages are recorded so the next storage manager on storage_path preloads them
+     *      - callers that exit the process without dropping the SM must call it, or the writes
+     *        still cached in the buffer pool are lost
+     */ 
+    fn shutdown(&self) -> Result<(), CrustyError> {
+        self.flush_all()?;
+        self.save_hot_pages()
+    }
+}
+
+/// Trait Impl for Drop
+impl Drop for StorageManager {
+    /*  drop
+     *      purpose: shutdown the storage manager
+     *  Inputs: 
+     *      &mut self:
+     *  Outputs:
+     *      None, just shuts the storage manager down
+     *  Notes: 
+     *      - Should be safe to call multiple times.
+     *      - If temp, storage_path is removed with everything in it instead of being written to.
+     *      - shuts down like shutdown, drop can't return its error so it's logged
+     */ 
+    fn drop(&mut self) {
+        if self.is_temp {
+            debug!("Removing temp storage_manager {}", self.storage_path);
+            if let Err(e) = fs::remove_dir_all(&self.storage_path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    error!("heapstore::drop could not remove {}: {}", self.storage_path, e);
+                }
+            }
+        } else if let Err(e) = self.shutdown() {
+            error!("heapstore::drop could not shut down: {}", e);
+        }
     }
 }
 
@@ -411,7 +1420,7 @@ impl Drop for StorageManager {
 mod test {
     use super::*;
     use crate::storage_manager::StorageManager;
-    use common::storage_trait::StorageTrait;
+    use common::storage_trait::{ContainerType, StorageTrait, ValueRefIterator};
     use common::testutil::*;
 
     #[test]
@@ -420,7 +1429,7 @@ mod test {
         init();
         let sm = StorageManager::new_test_sm(); // create a new Storage Manager
         let cid = 1;
-        sm.create_container(cid); // create a new container, which is equivalent to creating a new heapfile
+        sm.create_container(cid, None, ContainerType::Table); // create a new container, which is equivalent to creating a new heapfile
         let bytes = get_random_byte_vec(40);
         let tid = TransactionId::new(); 
         println!("GOT HERE TOO");
@@ -445,12 +1454,719 @@ mod test {
         assert_ne!(p1.get_bytes()[..], p2.get_bytes()[..]);
     }
 
+    #[test]
+    fn hs_sm_free_space_map() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let sm = StorageManager::load(storage_path, true, BufferPoolConfig{capacity: 2, policy: EvictionPolicy::Lru});
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        // 10 values of 400 bytes fill a page
+        let vals = get_random_vec_of_byte_vec(100, 0, 400);
+        sm.track_io(tid);
+        let ids: Vec<ValueId> = vals.iter().map(|v| sm.insert_value(cid, v.clone(), tid)).collect();
+        let num_pages = sm.get_num_pages(cid);
+        assert_eq!(10, num_pages);
+        // full pages are skipped without being read, so no page is read more than once
+        assert!(sm.untrack_io(tid).pages_read <= num_pages as u64);
+        for (id, val) in ids.iter().zip(vals.iter()) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+
+        // the map is rebuilt after the heapfile shrinks, and small values go to the first page with room
+        sm.reclaim_space(cid).unwrap();
+        let small = get_random_byte_vec(20);
+        let id = sm.insert_value(cid, small.clone(), tid);
+        assert_eq!(Some(0), id.page_id);
+        assert_eq!(small, sm.get_value(id, tid, Permissions::ReadOnly).unwrap());
+        assert_eq!(10, sm.get_num_pages(cid));
+    }
+
+    #[test]
+    fn hs_sm_insert_values_packing() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        let first = sm.insert_value(cid, get_random_byte_vec(20), tid);
+        assert_eq!((Some(0), Some(0)), (first.page_id, first.slot_id));
+
+        // 10 values of 400 bytes fill a page, the page that has room is filled first
+        let vals = get_random_vec_of_byte_vec(25, 0, 400);
+        sm.track_io(tid);
+        let ids = sm.insert_values(cid, vals.clone(), tid);
+        let pages: Vec<PageId> = ids.iter().map(|id| id.page_id.unwrap()).collect();
+        assert_eq!([vec![0; 10], vec![1; 10], vec![2; 5]].concat(), pages);
+        assert_eq!(Some(1), ids[0].slot_id);
+        assert_eq!(Some(0), ids[10].slot_id);
+        assert_eq!(3, sm.get_num_pages(cid));
+        // page 0 is read once, the new pages are written once each
+        assert_eq!(IoStats{pages_read: 1, pages_written: 2}, sm.untrack_io(tid));
+        for (id, val) in ids.iter().zip(vals.iter()) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        assert!(sm.insert_values(cid, Vec::new(), tid).is_empty());
+    }
+
+    #[test]
+    fn hs_sm_extent_growth() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        sm.set_extent_pages(4);
+        let tid = TransactionId::new();
+        // 10 values of 400 bytes fill a page
+        let vals = get_random_vec_of_byte_vec(15, 0, 400);
+        sm.track_io(tid);
+        let ids = sm.insert_values(cid, vals.clone(), tid);
+        let pages: Vec<PageId> = ids.iter().map(|id| id.page_id.unwrap()).collect();
+        assert_eq!([vec![0; 10], vec![1; 5]].concat(), pages);
+        // the heapfile grew by a whole extent, and the empty pages were not read
+        assert_eq!(4, sm.get_num_pages(cid));
+        assert_eq!(IoStats{pages_read: 0, pages_written: 0}, sm.untrack_io(tid));
+
+        // later inserts fill the extent before growing the heapfile again
+        let more = get_random_vec_of_byte_vec(30, 0, 400);
+        let more_ids: Vec<ValueId> = more.iter().map(|v| sm.insert_value(cid, v.clone(), tid)).collect();
+        assert_eq!(Some(1), more_ids[0].page_id);
+        assert_eq!(Some(4), more_ids[29].page_id);
+        assert_eq!(8, sm.get_num_pages(cid));
+        for (id, val) in ids.iter().chain(more_ids.iter()).zip(vals.iter().chain(more.iter())) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        assert_eq!(45, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+
+        // the unused pages of the last extent are given back
+        sm.flush_container(cid).unwrap();
+        assert_eq!(3 * PAGE_SIZE as u64, sm.reclaim_space(cid).unwrap());
+        assert_eq!(5, sm.get_num_pages(cid));
+    }
+
+    #[test]
+    fn hs_sm_truncate_and_remove() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let tid = TransactionId::new();
+        for pid in 0..2 {
+            let mut p = Page::new(pid);
+            p.add_value(&get_random_byte_vec(40));
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        assert_eq!(2, sm.get_num_pages(cid));
+        sm.flush_container(cid).unwrap();
+
+        // hold on to the heapfile the way an open iterator would
+        let old_hf = sm.hash_map.read().unwrap().get(&cid).unwrap().clone();
+        sm.truncate_container(cid).unwrap();
+        assert_eq!(0, sm.get_num_pages(cid));
+        assert_eq!(2, old_hf.num_pages());
+        assert!(old_hf.read_page_from_file(1).is_ok());
+
+        let mut p = Page::new(0);
+        p.add_value(&get_random_byte_vec(40));
+        sm.write_page(cid, p, tid).unwrap();
+        sm.flush_all().unwrap();
+        let old_hf = sm.hash_map.read().unwrap().get(&cid).unwrap().clone();
+        sm.remove_container(cid).unwrap();
+        assert!(!sm.container_exists(cid));
+        assert!(!sm.container_path(cid).exists());
+        assert_eq!(1, old_hf.num_pages());
+        assert!(sm.truncate_container(cid).is_err());
+    }
+
+    #[test]
+    fn hs_sm_truncate_locks() {
+        init();
+        let sm = Arc::new(StorageManager::new_test_sm());
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        sm.start_transaction(tid);
+        sm.insert_value(cid, get_random_byte_vec(40), tid);
+
+        // the transaction still holds the page, so its insert isn't truncated away
+        assert!(sm.truncate_container(cid).is_err());
+        assert_eq!(1, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+        sm.transaction_finished(tid);
+        sm.truncate_container(cid).unwrap();
+        assert_eq!(0, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+
+        // an insert waiting on the old heapfile's free space map goes to the one swapped in
+        let old_hf = sm.hash_map.read().unwrap().get(&cid).unwrap().clone();
+        let free_space = old_hf.free_space.write().unwrap();
+        let inserter = {
+            let sm = sm.clone();
+            std::thread::spawn(move || sm.insert_value(cid, get_random_byte_vec(40), TransactionId::new()))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let new_hf = Arc::new(HeapFile::new(PathBuf::from(&sm.storage_path).join("swapped.hf"), cid).unwrap());
+        sm.hash_map.write().unwrap().insert(cid, new_hf.clone());
+        drop(free_space);
+        inserter.join().unwrap();
+        sm.flush_all().unwrap();
+        assert_eq!(0, old_hf.num_pages());
+        assert_eq!(1, new_hf.num_pages());
+    }
+
+    #[test]
+    fn hs_sm_reset() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        sm.create_container(1, Some(String::from("t")), ContainerType::Table).unwrap();
+        sm.create_container(2, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        sm.start_transaction(tid);
+        let id = sm.insert_value(1, get_random_byte_vec(40), tid);
+        sm.insert_value(2, get_random_byte_vec(40), TransactionId::new());
+        sm.flush_all().unwrap();
+        sm.get_value(id, tid, Permissions::ReadOnly).unwrap();
+        let mut other_file = PathBuf::from(&sm.storage_path);
+        other_file.push("other.json");
+        fs::write(&other_file, "{}").unwrap();
+
+        sm.reset().unwrap();
+        assert!(!sm.container_exists(1));
+        assert!(sm.list_containers().is_empty());
+        assert_eq!(0, sm.buffer_pool.num_frames());
+        assert!(!sm.container_path(1).exists());
+        assert!(!sm.container_path(2).exists());
+        assert!(other_file.exists());
+        assert!(StorageManager::new(sm.storage_path.clone()).list_containers().is_empty());
+
+        // the page locks of the forgotten transaction don't block a new one
+        sm.abort_transaction(tid).unwrap();
+        sm.create_container(1, None, ContainerType::Table).unwrap();
+        let tid2 = TransactionId::new();
+        sm.start_transaction(tid2);
+        let id = sm.insert_value(1, get_random_byte_vec(40), tid2);
+        assert_eq!(Some(0), id.page_id);
+        assert!(sm.get_value(id, tid2, Permissions::ReadWrite).is_ok());
+    }
+
+    #[test]
+    fn hs_sm_temp_dir_removed() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        sm.create_container(1, Some(String::from("t")), ContainerType::Table).unwrap();
+        sm.insert_value(1, get_random_byte_vec(40), TransactionId::new());
+        sm.flush_all().unwrap();
+        let storage_path = PathBuf::from(&sm.storage_path);
+        assert!(sm.container_path(1).exists());
+        drop(sm);
+        assert!(!storage_path.exists());
+
+        // a storage manager on a directory that is kept leaves it behind
+        let storage_path = gen_random_dir();
+        let sm = StorageManager::new(storage_path.to_string_lossy().to_string());
+        sm.create_container(1, None, ContainerType::Table).unwrap();
+        drop(sm);
+        assert!(storage_path.join(METADATA_FILE).exists());
+        fs::remove_dir_all(&storage_path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_iterator_pins() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let sm = StorageManager::load(storage_path, true, BufferPoolConfig{capacity: 4, policy: EvictionPolicy::Lru});
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let tid = TransactionId::new();
+        let bytes = get_random_byte_vec(40);
+        for pid in 0..5 {
+            let mut p = Page::new(pid);
+            p.add_value(&bytes);
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        let pins = |sm: &StorageManager| (0..5).map(|pid| sm.buffer_pool.pin_count(cid, pid)).collect::<Vec<_>>();
+
+        // the page the values are lent from is pinned until the iterator moves on
+        let mut iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
+        assert_eq!(vec![0, 0, 0, 0, 0], pins(&sm));
+        assert_eq!(Some(&bytes[..]), iter.next_ref().transpose().unwrap());
+        assert_eq!(vec![1, 0, 0, 0, 0], pins(&sm));
+        iter.next_ref().unwrap();
+        assert_eq!(vec![0, 1, 0, 0, 0], pins(&sm));
+        drop(iter);
+        assert_eq!(vec![0, 0, 0, 0, 0], pins(&sm));
+
+        // held pages stay pinned, up to half of the pool
+        let mut iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
+        iter.hold_pages();
+        assert_eq!(5, iter.by_ref().count());
+        assert_eq!(vec![1, 1, 0, 0, 1], pins(&sm));
+        // another scan of the container leaves them alone
+        assert_eq!(5, sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap().len());
+        assert_eq!(vec![1, 1, 0, 0, 1], pins(&sm));
+        drop(iter);
+        assert_eq!(vec![0, 0, 0, 0, 0], pins(&sm));
+    }
+
+    #[test]
+    fn hs_sm_buffer_pool() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let sm = StorageManager::load(storage_path, true, BufferPoolConfig{capacity: 3, policy: EvictionPolicy::Lru});
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let tid = TransactionId::new();
+        let bytes = get_random_byte_vec(40);
+        for pid in 0..5 {
+            let mut p = Page::new(pid);
+            p.add_value(&bytes);
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        // written pages are cached, only the 3 most recent ones fit and the 2 evicted ones were written back
+        assert_eq!(3, sm.buffer_pool.num_frames());
+        let p4 = sm.get_page(cid, 4, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(Some(bytes.clone()), p4.get_value(0));
+        assert_eq!((0, 2), sm.get_hf_read_write_count(cid));
+        sm.get_page(cid, 0, tid, Permissions::ReadOnly, false).unwrap();
+        sm.get_page(cid, 0, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(1, sm.get_hf_read_write_count(cid).0);
+
+        // the pinned page stays cached even though it's the least recently used
+        let guard = sm.buffer_pool.pin(cid, 3);
+        sm.get_page(cid, 1, tid, Permissions::ReadOnly, false).unwrap();
+        sm.get_page(cid, 2, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(3, sm.get_hf_read_write_count(cid).0);
+        sm.get_page(cid, 3, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(3, sm.get_hf_read_write_count(cid).0);
+        assert_eq!(3, sm.buffer_pool.num_frames());
+        drop(guard);
+
+        // truncating drops the cached pages of the old heapfile
+        sm.truncate_container(cid).unwrap();
+        let p3 = sm.get_page(cid, 3, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(None, p3.get_value(0));
+    }
+
+    #[test]
+    fn hs_sm_buffer_pool_all_pinned() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let sm = StorageManager::load(storage_path, true, BufferPoolConfig{capacity: 1, policy: EvictionPolicy::Lru});
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let tid = TransactionId::new();
+        for pid in 0..2 {
+            sm.write_page(cid, Page::new(pid), tid).unwrap();
+        }
+        let _guard = sm.buffer_pool.pin(cid, 1);
+        // page 0 can't be cached, so every get reads it
+        sm.get_page(cid, 0, tid, Permissions::ReadOnly, false).unwrap();
+        sm.get_page(cid, 0, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(2, sm.get_hf_read_write_count(cid).0);
+        assert_eq!(1, sm.buffer_pool.num_frames());
+    }
+
+    #[test]
+    fn hs_sm_dirty_write_back() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let sm = StorageManager::load(storage_path, true, BufferPoolConfig{capacity: 2, policy: EvictionPolicy::Lru});
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let tid = TransactionId::new();
+        let hf = sm.hash_map.read().unwrap().get(&cid).unwrap().clone();
+
+        // rewriting a cached page doesn't touch the heapfile
+        let mut p0 = Page::new(0);
+        for _ in 0..3 {
+            p0.add_value(&get_random_byte_vec(40));
+            sm.write_page(cid, p0.clone(), tid).unwrap();
+        }
+        assert_eq!((0, 0), sm.get_hf_read_write_count(cid));
+        assert_eq!(0, hf.num_pages());
+        assert_eq!(1, sm.get_num_pages(cid));
+
+        // evicting the dirty page writes its latest version back
+        sm.write_page(cid, Page::new(1), tid).unwrap();
+        sm.write_page(cid, Page::new(2), tid).unwrap();
+        assert_eq!((0, 1), sm.get_hf_read_write_count(cid));
+        assert_eq!(p0.get_bytes()[..], hf.read_page_from_file(0).unwrap().get_bytes()[..]);
+        assert_eq!(3, sm.get_num_pages(cid));
+
+        // flushing writes the dirty pages once, they stay cached
+        sm.flush_container(cid).unwrap();
+        assert_eq!(3, hf.num_pages());
+        sm.flush_all().unwrap();
+        assert_eq!((1, 3), sm.get_hf_read_write_count(cid));
+        assert_eq!(2, sm.buffer_pool.num_frames());
+        assert!(sm.flush_container(2).is_err());
+
+        // a dirty page that can't be cached is written through
+        let _guard1 = sm.buffer_pool.pin(cid, 1);
+        let _guard2 = sm.buffer_pool.pin(cid, 2);
+        sm.write_page(cid, p0, tid).unwrap();
+        assert_eq!(4, sm.get_hf_read_write_count(cid).1);
+    }
+
+    #[test]
+    fn hs_sm_track_io() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let sm = StorageManager::load(storage_path, true, BufferPoolConfig{capacity: 2, policy: EvictionPolicy::Lru});
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let (t1, t2) = (TransactionId::new(), TransactionId::new());
+        sm.track_io(t1);
+        // page 0 is written back to make room for page 2
+        for pid in 0..3 {
+            sm.write_page(cid, Page::new(pid), t1).unwrap();
+        }
+        assert_eq!(IoStats{pages_read: 0, pages_written: 1}, sm.io.counters(t1).unwrap().stats());
+        // t2 isn't tracked, the read of page 0 and the write back of page 1 aren't counted
+        sm.get_page(cid, 0, t2, Permissions::ReadOnly, false).unwrap();
+        // hits are free, misses are reads and evicting dirty page 2 is a write
+        sm.get_page(cid, 0, t1, Permissions::ReadOnly, false).unwrap();
+        sm.get_page(cid, 1, t1, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(IoStats{pages_read: 1, pages_written: 2}, sm.untrack_io(t1));
+        assert_eq!((2, 3), sm.get_hf_read_write_count(cid));
+        assert_eq!(IoStats::default(), sm.untrack_io(t1));
+    }
+
+    #[test]
+    fn hs_sm_eviction_policies() {
+        init();
+        let cid = 1;
+        let tid = TransactionId::new();
+        let accesses = [0, 1, 2, 3, 1, 4, 3, 2, 5];
+        let expected = [(EvictionPolicy::Lru, [2, 3, 5]),
+                        (EvictionPolicy::Fifo, [3, 4, 5]),
+                        (EvictionPolicy::Clock, [2, 4, 5])];
+        for (policy, cached) in expected.iter() {
+            let storage_path = gen_random_dir().to_string_lossy().to_string();
+            let sm = StorageManager::load(storage_path, true, BufferPoolConfig{capacity: 3, policy: *policy});
+            sm.create_container(cid, None, ContainerType::Table);
+            // write around the pool so it starts out empty
+            let hf = sm.hash_map.read().unwrap().get(&cid).unwrap().clone();
+            for pid in 0..6 {
+                hf.write_page_to_file(Page::new(pid)).unwrap();
+            }
+            for pid in accesses.iter() {
+                sm.get_page(cid, *pid, tid, Permissions::ReadOnly, false).unwrap();
+            }
+            for pid in 0..6 {
+                assert_eq!(cached.contains(&pid), sm.buffer_pool.is_cached(cid, pid), "{:?} page {}", policy, pid);
+            }
+
+            // the page the policy would evict next stays while it's pinned
+            let victim = match policy {
+                EvictionPolicy::Lru => 3,
+                EvictionPolicy::Fifo => 3,
+                EvictionPolicy::Clock => 4,
+            };
+            let guard = sm.buffer_pool.pin(cid, victim);
+            sm.get_page(cid, 0, tid, Permissions::ReadOnly, false).unwrap();
+            sm.get_page(cid, 1, tid, Permissions::ReadOnly, false).unwrap();
+            assert!(sm.buffer_pool.is_cached(cid, victim), "{:?}", policy);
+            drop(guard);
+        }
+    }
+
+    #[test]
+    fn hs_sm_warmup() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let config = BufferPoolConfig{capacity: 3, policy: EvictionPolicy::Lru};
+        let (cid, other) = (1, 2);
+        let tid = TransactionId::new();
+        {
+            let sm = StorageManager::with_config(storage_path.clone(), config);
+            sm.create_container(cid, None, ContainerType::Table).unwrap();
+            sm.create_container(other, None, ContainerType::Table).unwrap();
+            let hf = sm.hash_map.read().unwrap().get(&cid).unwrap().clone();
+            for pid in 0..5 {
+                hf.write_page_to_file(Page::new(pid)).unwrap();
+            }
+            // warming stops at the capacity of the pool and doesn't read cached pages again
+            assert_eq!(2, sm.warm_container(cid, 2).unwrap());
+            assert_eq!(2, sm.get_hf_read_write_count(cid).0);
+            assert_eq!(3, sm.warm_container(cid, 10).unwrap());
+            assert_eq!(3, sm.get_hf_read_write_count(cid).0);
+            assert!(sm.warm_container(3, 10).is_err());
+            // pages 4 and 3 are hot, 4 the hottest
+            for pid in [4, 3, 4].iter() {
+                sm.get_page(cid, *pid, tid, Permissions::ReadOnly, false).unwrap();
+            }
+            let hot = sm.buffer_pool.hot_pages();
+            assert_eq!(3, hot.len());
+            assert_eq!(vec![(cid, 4), (cid, 3)], hot[..2].to_vec());
+        }
+        // the next start preloads them
+        let sm = StorageManager::with_config(storage_path.clone(), config);
+        assert_eq!(3, sm.buffer_pool.num_frames());
+        sm.get_page(cid, 4, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(3, sm.get_hf_read_write_count(cid).0);
+        drop(sm);
+        // pages cut off the heapfile since are skipped
+        let path = PathBuf::from(&storage_path).join(format!("{}.hf", cid));
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(3 * PAGE_SIZE as u64).unwrap();
+        let sm = StorageManager::with_config(storage_path.clone(), config);
+        assert!(!sm.buffer_pool.is_cached(cid, 3));
+        assert!(!sm.buffer_pool.is_cached(cid, 4));
+        drop(sm);
+        fs::remove_dir_all(&storage_path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_warmup_scan() {
+        init();
+        let storage_path = gen_random_dir().to_string_lossy().to_string();
+        let config = BufferPoolConfig{capacity: 4, policy: EvictionPolicy::Lru};
+        let cid = 1;
+        let tid = TransactionId::new();
+        let bytes = get_random_byte_vec(1000);
+        {
+            let sm = StorageManager::with_config(storage_path.clone(), config);
+            sm.create_container(cid, None, ContainerType::Table).unwrap();
+            let hf = sm.hash_map.read().unwrap().get(&cid).unwrap().clone();
+            for pid in 0..3 {
+                let mut p = Page::new(pid);
+                p.add_value(&bytes);
+                hf.write_page_to_file(p).unwrap();
+            }
+            // the table fits in the pool, so the scan leaves all of it cached
+            assert_eq!(3, sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap().len());
+            sm.shutdown().unwrap();
+        }
+        // a restart preloads it, so the first scan doesn't read the heapfile
+        let sm = StorageManager::with_config(storage_path.clone(), config);
+        sm.track_io(tid);
+        assert_eq!(3, sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap().len());
+        assert_eq!(0, sm.untrack_io(tid).pages_read);
+        drop(sm);
+        // without the warmup file every page is read
+        fs::remove_file(PathBuf::from(&storage_path).join(WARMUP_FILE)).unwrap();
+        let sm = StorageManager::with_config(storage_path.clone(), config);
+        sm.track_io(tid);
+        assert_eq!(3, sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap().len());
+        assert_eq!(3, sm.untrack_io(tid).pages_read);
+        drop(sm);
+        fs::remove_dir_all(&storage_path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_reclaim_space() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let tid = TransactionId::new();
+        // values on pages 0 and 3, pages 1, 2, 4 and 5 are empty
+        let bytes = get_random_byte_vec(40);
+        for pid in 0..6 {
+            let mut p = Page::new(pid);
+            if pid == 0 || pid == 3 {
+                p.add_value(&bytes);
+            }
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        assert_eq!(6, sm.get_num_pages(cid));
+
+        // trailing empty pages are cut off
+        assert_eq!(2 * PAGE_SIZE as u64, sm.reclaim_space(cid).unwrap());
+        assert_eq!(4, sm.get_num_pages(cid));
+        assert_eq!(0, sm.reclaim_space(cid).unwrap());
+
+        // empty pages in the middle are punched out where the filesystem can
+        sm.set_punch_holes(true);
+        let punched = sm.reclaim_space(cid).unwrap();
+        assert!(punched == 0 || punched == 2 * PAGE_SIZE as u64);
+        assert_eq!(0, sm.reclaim_space(cid).unwrap());
+        assert_eq!(4, sm.get_num_pages(cid));
+        let p1 = sm.get_page(cid, 1, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(1, p1.get_page_id());
+        assert_eq!(None, p1.get_value(0));
+        let p3 = sm.get_page(cid, 3, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(Some(bytes), p3.get_value(0));
+
+        let stats = sm.storage_stats();
+        assert_eq!(1, stats.containers);
+        assert_eq!(4 * PAGE_SIZE as u64, stats.logical_bytes);
+        assert_eq!(2 * PAGE_SIZE as u64 + punched, stats.reclaimed_bytes);
+        let usage = sm.container_usage(cid).unwrap();
+        assert_eq!(4 * PAGE_SIZE as u64, usage.disk_bytes);
+        assert_eq!(2 * 40, usage.live_bytes);
+        assert!(sm.container_usage(2).is_err());
+
+        // truncating gives back the whole file
+        sm.truncate_container(cid).unwrap();
+        assert_eq!(6 * PAGE_SIZE as u64 + punched, sm.storage_stats().reclaimed_bytes);
+        assert!(sm.reclaim_space(2).is_err());
+    }
+
+    #[test]
+    fn hs_sm_container_metadata() {
+        init();
+        let sm = StorageManager::new(gen_random_dir().to_string_lossy().to_string());
+        sm.create_container(2, Some(String::from("b")), ContainerType::Table).unwrap();
+        sm.create_container(1, Some(String::from("a")), ContainerType::Index).unwrap();
+        // same id and name again is a no-op, a clash is an error
+        sm.create_container(1, Some(String::from("a")), ContainerType::Index).unwrap();
+        assert!(sm.create_container(1, Some(String::from("c")), ContainerType::Table).is_err());
+        assert!(sm.create_container(3, Some(String::from("a")), ContainerType::Table).is_err());
+        let listed = sm.list_containers();
+        assert_eq!(vec![1, 2], listed.iter().map(|m| m.container_id).collect::<Vec<_>>());
+        assert_eq!(Some(String::from("a")), listed[0].name);
+        assert_eq!(ContainerType::Index, listed[0].container_type);
+        assert_eq!(SlotPolicy::ReuseLowest, listed[0].slot_policy);
+        sm.set_slot_policy(1, SlotPolicy::Monotonic).unwrap();
+        assert!(sm.set_slot_policy(3, SlotPolicy::Monotonic).is_err());
+
+        // the metadata and the heapfiles come back with a new storage manager
+        sm.remove_container(2).unwrap();
+        let sm2 = StorageManager::new(sm.storage_path.clone());
+        assert_eq!(sm.list_containers(), sm2.list_containers());
+        assert!(sm2.container_exists(1));
+        assert!(!sm2.container_exists(2));
+        assert_eq!(SlotPolicy::Monotonic, sm2.list_containers()[0].slot_policy);
+        fs::remove_dir_all(&sm.storage_path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_page_compression() {
+        init();
+        let sm = StorageManager::new(gen_random_dir().to_string_lossy().to_string());
+        let cid = 1;
+        let tid = TransactionId::new();
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        assert_eq!(PageCompression::None, sm.list_containers()[0].compression);
+        sm.set_compression(cid, PageCompression::Snappy).unwrap();
+        assert!(sm.set_compression(2, PageCompression::Snappy).is_err());
+
+        // values that compress well, spread over a few pages
+        let values: Vec<Vec<u8>> = (0..60).map(|i| vec![i as u8; 200]).collect();
+        for value in &values {
+            sm.insert_value(cid, value.clone(), tid);
+        }
+        sm.clear_buffer_pool().unwrap();
+        let num_pages = sm.get_num_pages(cid) as u64;
+        assert!(num_pages > 1);
+        // the pages are holes in the heapfile, and their frames take less than a page each
+        let stats = sm.storage_stats();
+        assert!(stats.logical_bytes > num_pages * PAGE_SIZE as u64);
+        assert!(stats.allocated_bytes < num_pages * PAGE_SIZE as u64);
+        let saved = sm.storage_stats().compression_saved_bytes;
+        assert!(saved > (num_pages - 1) * (PAGE_SIZE as u64 / 2));
+        // the buffer pool reads the pages back decompressed
+        assert_eq!(values, sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap());
+        let page = sm.get_page(cid, 0, tid, Permissions::ReadOnly, false).unwrap();
+        assert_eq!(Some(values[0].clone()), page.get_value(0));
+
+        // compressed and uncompressed pages are read alike after the setting changes
+        sm.set_compression(cid, PageCompression::None).unwrap();
+        sm.insert_value(cid, vec![7; 10], tid);
+        sm.clear_buffer_pool().unwrap();
+        assert_eq!(saved, sm.storage_stats().compression_saved_bytes);
+        assert_eq!(values.len() + 1, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+
+        // the setting comes back with a new storage manager
+        sm.set_compression(cid, PageCompression::Snappy).unwrap();
+        let sm2 = StorageManager::new(sm.storage_path.clone());
+        assert_eq!(PageCompression::Snappy, sm2.list_containers()[0].compression);
+        assert_eq!(values.len() + 1, sm2.get_iterator(cid, tid, Permissions::ReadOnly).count());
+        fs::remove_dir_all(&sm.storage_path).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_monotonic_slots() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        let tid = TransactionId::new();
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        sm.set_slot_policy(cid, SlotPolicy::Monotonic).unwrap();
+        let ids = sm.insert_values(cid, vec![get_random_byte_vec(40); 2], tid);
+        assert_eq!(vec![Some(0), Some(1)], ids.iter().map(|id| id.slot_id).collect::<Vec<_>>());
+        // the id of the deleted last value is not handed out again
+        sm.delete_value(ids[1], tid).unwrap();
+        assert_eq!(Some(2), sm.insert_value(cid, get_random_byte_vec(40), tid).slot_id);
+    }
+
+    #[test]
+    fn hs_sm_abort_transaction() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let (cid, index_id) = (1, 2);
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        sm.create_container(index_id, None, ContainerType::Index).unwrap();
+        let committed = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(100, 40, 80);
+        let ids = sm.insert_values(cid, vals.clone(), committed);
+        sm.index_insert(index_id, vec![(Field::IntField(0), ids[0].pack())], committed).unwrap();
+
+        let tid = TransactionId::new();
+        sm.start_transaction(tid);
+        // enough inserts for new pages, deletes that leave holes the inserts fill
+        for id in ids.iter().step_by(3) {
+            sm.delete_value(*id, tid).unwrap();
+        }
+        sm.insert_values(cid, get_random_vec_of_byte_vec(200, 20, 100), tid);
+        let moved = sm.update_value(get_random_byte_vec(30), ids[1], tid).unwrap();
+        let entries = vec![(Field::IntField(0), ids[0].pack()), (Field::IntField(1), moved.pack())];
+        sm.index_insert(index_id, entries, tid).unwrap();
+        assert_eq!(2, sm.index_range(index_id, Bound::Unbounded, Bound::Unbounded, tid).unwrap().len());
+        sm.abort_transaction(tid).unwrap();
+
+        assert_eq!(vals, sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap());
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        assert_eq!(vec![ids[0].pack()], sm.index_range(index_id, Bound::Unbounded, Bound::Unbounded, tid).unwrap());
+
+        // the writes of a committed transaction stay, as do those of one that was not started
+        sm.start_transaction(tid);
+        sm.delete_value(ids[0], tid).unwrap();
+        sm.transaction_finished(tid);
+        sm.delete_value(ids[1], committed).unwrap();
+        sm.abort_transaction(tid).unwrap();
+        sm.abort_transaction(committed).unwrap();
+        assert_eq!(98, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+    }
+
+    #[test]
+    fn hs_sm_page_locks() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        let (reader, writer) = (TransactionId::new(), TransactionId::new());
+        sm.start_transaction(reader);
+        sm.start_transaction(writer);
+        let first = sm.insert_value(cid, get_random_byte_vec(40), reader);
+        assert_eq!(1, sm.get_iterator(cid, reader, Permissions::ReadOnly).count());
+
+        // inserts skip the page the reader locked instead of waiting for it
+        let second = sm.insert_value(cid, get_random_byte_vec(40), writer);
+        assert_eq!(Some(1), second.page_id);
+        // the reader waits for the page with the uncommitted value and is aborted
+        assert_eq!(Err(CrustyError::TransactionAbortedError), sm.get_value(second, reader, Permissions::ReadOnly));
+        assert_eq!(Err(CrustyError::TransactionAbortedError), sm.check_transaction(reader));
+        // nor does it get a part of the values, e.g. to build an index from
+        assert_eq!(Err(CrustyError::TransactionAbortedError), sm.get_values_with_ids(cid, reader, Permissions::ReadOnly));
+        sm.abort_transaction(reader).unwrap();
+        assert_eq!(Ok(()), sm.check_transaction(reader));
+
+        // the locks of the reader are gone, so the writer can delete its value
+        sm.delete_value(first, writer).unwrap();
+        sm.transaction_finished(writer);
+        let ids: Vec<ValueId> = sm.get_values_with_ids(cid, reader, Permissions::ReadOnly).unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(vec![second], ids);
+    }
+
     #[test]
     fn hs_sm_b_iter_small() {
         init();
         let sm = StorageManager::new_test_sm();
         let cid = 1;
-        sm.create_container(cid);
+        sm.create_container(cid, None, ContainerType::Table);
         let tid = TransactionId::new();
 
         //Test one page
@@ -463,7 +2179,7 @@ mod test {
         }
         let iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
         for (i, x) in iter.enumerate() {
-            assert_eq!(byte_vec[i], x);
+            assert_eq!(byte_vec[i], x.unwrap());
         }
 
         let mut byte_vec2: Vec<Vec<u8>> = Vec::new();
@@ -480,7 +2196,7 @@ mod test {
 
         let iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
         for (i, x) in iter.enumerate() {
-            assert_eq!(byte_vec[i], x);
+            assert_eq!(byte_vec[i], x.unwrap());
         }
 
         let mut byte_vec2: Vec<Vec<u8>> = Vec::new();
@@ -496,8 +2212,218 @@ mod test {
 
         let iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
         for (i, x) in iter.enumerate() {
-            assert_eq!(byte_vec[i], x);
+            assert_eq!(byte_vec[i], x.unwrap());
+        }
+    }
+
+    #[test]
+    fn hs_sm_get_iterator_and_value() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        let tid = TransactionId::new();
+        // missing and empty containers have nothing to iterate
+        assert_eq!(0, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+        sm.create_container(cid, None, ContainerType::Table);
+        assert_eq!(0, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+
+        // the pages are still dirty in the buffer pool, the iterator sees them anyway
+        let mut expected = Vec::new();
+        let mut ids = Vec::new();
+        for pid in 0..3 {
+            let (p, slots) = crate::testutil::get_random_page(pid, 4, 20, 60);
+            for slot in &slots {
+                expected.push(p.get_value(*slot).unwrap());
+                ids.push(ValueId{container_id: cid, segment_id: None, page_id: Some(pid), slot_id: Some(*slot)});
+            }
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        // the dirty pages are scanned in the buffer pool, without being written back
+        sm.track_io(tid);
+        let mut iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
+        assert_eq!(expected, iter.collect_values().unwrap());
+        assert_eq!(0, sm.untrack_io(tid).pages_written);
+        for (id, val) in ids.iter().zip(&expected) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+
+        // values that don't exist are errors
+        let mut missing = ids[0];
+        missing.slot_id = Some(10);
+        assert!(sm.get_value(missing, tid, Permissions::ReadOnly).is_err());
+        missing.page_id = Some(3);
+        assert!(sm.get_value(missing, tid, Permissions::ReadOnly).is_err());
+        missing.container_id = 2;
+        assert!(sm.get_value(missing, tid, Permissions::ReadOnly).is_err());
+
+        // an open iterator finishes after the container is emptied, dirty pages included
+        let iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
+        sm.truncate_container(cid).unwrap();
+        assert_eq!(expected.len(), iter.count());
+        assert_eq!(0, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+    }
+
+    #[test]
+    fn hs_sm_prefetching_iterator() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let tid = TransactionId::new();
+        for pid in 0..5 {
+            let (p, _slots) = crate::testutil::get_random_page(pid, 3, 20, 60);
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        let expected = sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap();
+        assert_eq!(15, expected.len());
+        for prefetch in [1, 2, 4, 100].iter() {
+            sm.clear_buffer_pool().unwrap();
+            sm.track_io(tid);
+            let mut iter = sm.get_prefetching_iterator(cid, tid, Permissions::ReadOnly, *prefetch);
+            assert_eq!(expected, iter.collect_values().unwrap(), "prefetch {}", prefetch);
+            // every page is read once no matter how many are read at a time
+            assert_eq!(5, sm.untrack_io(tid).pages_read, "prefetch {}", prefetch);
+        }
+        // the scans cached the pages, so scanning again reads none
+        sm.track_io(tid);
+        assert_eq!(expected, sm.get_prefetching_iterator(cid, tid, Permissions::ReadOnly, 2).collect_values().unwrap());
+        assert_eq!(0, sm.untrack_io(tid).pages_read);
+        // the pages missing from the pool are read together, with the cached page between them
+        sm.clear_buffer_pool().unwrap();
+        sm.get_page(cid, 2, tid, Permissions::ReadOnly, false).unwrap();
+        sm.track_io(tid);
+        assert_eq!(expected, sm.get_prefetching_iterator(cid, tid, Permissions::ReadOnly, 100).collect_values().unwrap());
+        assert_eq!(5, sm.untrack_io(tid).pages_read);
+        sm.track_io(tid);
+        assert_eq!(expected, sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap());
+        assert_eq!(0, sm.untrack_io(tid).pages_read);
+    }
+
+    #[test]
+    fn hs_sm_partition_iterator() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table);
+        let tid = TransactionId::new();
+        let num_pages = 200;
+        for pid in 0..num_pages {
+            let (p, _slots) = crate::testutil::get_random_page(pid, 3, 20, 60);
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        let expected = sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap();
+        for partitions in 1..=8 {
+            // the partitions read the heapfile, not the buffer pool
+            sm.clear_buffer_pool().unwrap();
+            sm.track_io(tid);
+            let parts: Vec<Vec<Vec<u8>>> = (0..partitions)
+                .map(|p| sm.get_partition_iterator(cid, tid, Permissions::ReadOnly, 1, p, partitions).collect_values().unwrap())
+                .collect();
+            assert_eq!(expected, parts.concat(), "partitions {}", partitions);
+            // every page is in one of the partitions
+            assert_eq!(num_pages as u64, sm.untrack_io(tid).pages_read, "partitions {}", partitions);
+
+            // the partitions scanned again and again on their own threads, all at the same time
+            let start = std::sync::Barrier::new(partitions);
+            std::thread::scope(|scope| {
+                for (p, part) in parts.iter().enumerate() {
+                    let (sm, start) = (&sm, &start);
+                    scope.spawn(move || {
+                        start.wait();
+                        for _ in 0..20 {
+                            let values =
+                                sm.get_partition_iterator(cid, tid, Permissions::ReadOnly, 1, p, partitions).collect_values().unwrap();
+                            assert_eq!(part, &values, "partition {} of {}", p, partitions);
+                        }
+                    });
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn hs_sm_iterator_corrupt_page() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        for pid in 0..5 {
+            let (p, _slots) = crate::testutil::get_random_page(pid, 3, 20, 60);
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        sm.clear_buffer_pool().unwrap();
+        assert_eq!(15, sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap().len());
+
+        // flip the last byte of page 2, in the middle of the file
+        {
+            use std::os::unix::fs::FileExt;
+            let file = fs::OpenOptions::new().read(true).write(true).open(sm.container_path(cid)).unwrap();
+            let offset = (3 * PAGE_SIZE - 1) as u64;
+            let mut byte = [0; 1];
+            file.read_exact_at(&mut byte, offset).unwrap();
+            file.write_all_at(&[byte[0] ^ 0xff], offset).unwrap();
+        }
+        // the first scan cached the pages, so they are read from the heapfile again
+        sm.clear_buffer_pool().unwrap();
+        // the scan stops at the corrupted page and says why, instead of just ending
+        let values: Vec<Result<Vec<u8>, CrustyError>> = sm.get_iterator(cid, tid, Permissions::ReadOnly).collect();
+        assert_eq!(7, values.len());
+        assert!(values[..6].iter().all(Result::is_ok));
+        assert!(values[6].is_err());
+        assert!(sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().is_err());
+    }
+
+    #[test]
+    fn hs_sm_corrupt_page_errors() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        for pid in 0..3 {
+            let (p, _slots) = crate::testutil::get_random_page(pid, 3, 20, 60);
+            sm.write_page(cid, p, tid).unwrap();
+        }
+        sm.clear_buffer_pool().unwrap();
+        // flip the last byte of page 0
+        {
+            use std::os::unix::fs::FileExt;
+            let file = fs::OpenOptions::new().read(true).write(true).open(sm.container_path(cid)).unwrap();
+            let offset = (PAGE_SIZE - 1) as u64;
+            let mut byte = [0; 1];
+            file.read_exact_at(&mut byte, offset).unwrap();
+            file.write_all_at(&[byte[0] ^ 0xff], offset).unwrap();
+        }
+        // reads of the page fail instead of panicking
+        assert!(sm.get_page(cid, 0, tid, Permissions::ReadOnly, false).is_none());
+        let id = ValueId{container_id: cid, segment_id: None, page_id: Some(0), slot_id: Some(0)};
+        assert!(sm.get_value(id, tid, Permissions::ReadOnly).is_err());
+        assert!(sm.get_values_with_ids(cid, tid, Permissions::ReadOnly).is_err());
+        // inserts skip the page, leaving it as it is on disk
+        let id = sm.insert_value(cid, get_random_byte_vec(40), tid);
+        assert_eq!(Some(1), id.page_id);
+        sm.clear_buffer_pool().unwrap();
+        assert!(sm.get_page(cid, 0, tid, Permissions::ReadOnly, false).is_none());
+    }
+
+    #[test]
+    fn hs_sm_iterator_next_ref() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(300, 40, 400);
+        sm.insert_values(cid, vals.clone(), tid);
+        let mut iter = sm.get_iterator(cid, tid, Permissions::ReadOnly);
+        let mut borrowed = Vec::new();
+        while let Some(bytes) = iter.next_ref() {
+            borrowed.push(bytes.unwrap().to_vec());
         }
+        let copied = sm.get_iterator(cid, tid, Permissions::ReadOnly).collect_values().unwrap();
+        assert_eq!(copied, borrowed);
+        assert_eq!(300, borrowed.len());
     }
 
     #[test]
@@ -507,7 +2433,7 @@ mod test {
         let sm = StorageManager::new_test_sm();
         let cid = 1;
 
-        sm.create_container(cid).unwrap();
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
 
         let vals = get_random_vec_of_byte_vec(1000, 40, 400);
@@ -1,10 +1,11 @@
 use crate::heapfile::HeapFile;
-use crate::page::Page;
+use crate::page::{Page, Slot};
 use crate::storage_manager::StorageManager;
 use common::ids::TransactionId;
-use common::ids::{ContainerId, PageId, SlotId};
-use common::storage_trait::StorageTrait;
+use common::ids::{ContainerId, PageId, Permissions, SlotId};
+use common::storage_trait::{ContainerType, StorageTrait};
 use common::testutil::*;
+use common::PAGE_SIZE;
 use std::sync::Arc;
 
 #[allow(dead_code)]
@@ -21,7 +22,7 @@ pub(crate) fn fill_hf_sm(
         let (p, _slots) = get_random_page(i, vals_per_page, min_size, max_size);
         sm.write_page(container_id, p, tid).unwrap();
     }
-    sm.reset();
+    sm.clear_buffer_pool().unwrap();
 }
 
 #[allow(dead_code)]
@@ -60,11 +61,117 @@ pub fn bench_page_insert(vals: &Vec<Vec<u8>>) {
     }
 }
 
+/// A page filled for the page microbenchmarks, so they can time single page operations at the
+/// fill levels and value sizes tables see.
+#[derive(Clone)]
+pub struct BenchPage {
+    page: Page,
+    slots: Vec<SlotId>,
+}
+
+impl BenchPage {
+    /// Fills a page with random values until `fill` of its bytes are values, leaving room for
+    /// one more value so inserts can be timed at any fill level.
+    ///
+    /// # Arguments
+    ///
+    /// * `fill` - Fraction of the page to fill, between 0 and 1.
+    /// * `value_size` - Size of the values in bytes.
+    pub fn filled(fill: f64, value_size: usize) -> Self {
+        let mut page = Page::new(0);
+        let mut slots = Vec::new();
+        let target = (PAGE_SIZE as f64 * fill) as usize;
+        let mut used = 0;
+        // A value takes its bytes and a slot in the header
+        let footprint = value_size + std::mem::size_of::<Slot>();
+        while used + value_size <= target
+            && page.header.largest_free_space as usize >= 2 * footprint
+        {
+            slots.push(page.add_value(&get_random_byte_vec(value_size)).unwrap());
+            used += value_size;
+        }
+        Self { page, slots }
+    }
+
+    /// Number of values on the page.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Whether the page has no values.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Adds a value to the page.
+    pub fn add_value(&mut self, value: &Vec<u8>) -> Option<SlotId> {
+        self.page.add_value(value)
+    }
+
+    /// Reads every value of the page by slot id and returns the bytes read.
+    pub fn get_values(&self) -> usize {
+        self.slots
+            .iter()
+            .map(|s| self.page.get_value(*s).unwrap().len())
+            .sum()
+    }
+
+    /// Deletes every value of the page, in the order they were added.
+    pub fn delete_values(&mut self) {
+        for s in &self.slots {
+            self.page.delete_value(*s).unwrap();
+        }
+    }
+
+    /// Iterates over the values of the page and returns how many there were.
+    pub fn iterate(self) -> usize {
+        self.page.into_iter().count()
+    }
+}
+
 pub fn bench_sm_insert(sm: &StorageManager, to_insert: &Vec<Vec<u8>>) {
     let cid = 1; //TODO make random
-    sm.create_container(cid).unwrap();
+    sm.create_container(cid, None, ContainerType::Table).unwrap();
     let tid = TransactionId::new();
     for x in to_insert {
         sm.insert_value(cid, x.to_vec(), tid);
     }
 }
+
+pub fn bench_sm_fill(sm: &StorageManager, container_id: ContainerId, num_pages: PageId) {
+    sm.create_container(container_id, None, ContainerType::Table)
+        .unwrap();
+    let tid = TransactionId::new();
+    for i in 0..num_pages {
+        let (p, _slots) = get_random_page(i, 20, 80, 100);
+        sm.write_page(container_id, p, tid).unwrap();
+    }
+    sm.flush_all().unwrap();
+}
+
+pub fn bench_sm_scan(sm: &StorageManager, container_id: ContainerId, prefetch: usize) -> usize {
+    let tid = TransactionId::new();
+    sm.get_prefetching_iterator(container_id, tid, Permissions::ReadOnly, prefetch)
+        .count()
+}
+
+/// Reads the pages of a trace one at a time through the buffer pool and returns how many of
+/// them were already cached.
+pub fn bench_sm_read_pages(
+    sm: &StorageManager,
+    container_id: ContainerId,
+    trace: &[PageId],
+) -> usize {
+    let tid = TransactionId::new();
+    sm.track_io(tid);
+    for page_id in trace {
+        sm.get_page(container_id, *page_id, tid, Permissions::ReadOnly, false)
+            .unwrap();
+    }
+    trace.len() - sm.untrack_io(tid).pages_read as usize
+}
+
+/// Empties the buffer pool, writing back its dirty pages, so a trace starts out cold.
+pub fn bench_sm_clear_pool(sm: &StorageManager) {
+    sm.clear_buffer_pool().unwrap();
+}
@@ -1,24 +1,46 @@
 use common::ids::Permissions;
 use common::ids::*;
-use common::storage_trait::StorageTrait;
+use common::io_stats::{IoCounters, IoStats, IoTracker};
+use common::lock_manager::{LockManager, LockTarget};
+use common::row_format::RowFormat;
+use common::storage_trait::{
+    allocated_bytes, register_container, update_compression, update_row_format, update_slot_policy,
+    ContainerMetadata, ContainerType, ContainerUsage, PageCompression, SlotPolicy, StorageStats,
+    StorageTrait, ValueRefIterator,
+};
+use common::undo_log::{UndoLog, Write};
 use common::CrustyError;
 
 use std::collections::HashMap;
 use std::fs;
 use std::fs::OpenOptions;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
 /// This is the basic data structure a container that maps a value ID to bytes
 type ContainerMap = Arc<RwLock<HashMap<ValueId, Vec<u8>>>>; // more of a page strcture
 
+/// Name of the file the container metadata is persisted to
+const METADATA_FILE: &str = "containers.meta";
+
 /// The MemStore StorageManager. A map for storing containers, a map for tracking the next insert ID,
-/// and where to persist on shutdown/startup
+/// the containers' metadata, and where to persist on shutdown/startup
 pub struct StorageManager {
     containers: Arc<RwLock<HashMap<ContainerId, ContainerMap>>>,
+    metadata: Arc<RwLock<HashMap<ContainerId, ContainerMetadata>>>,
     last_insert: Arc<RwLock<HashMap<ContainerId, ValueId>>>, // don't need this for our implementation
     persist_path: PathBuf, // we want something similar in our implementation
+    /// Bytes of persisted container files given back since the storage manager was created.
+    reclaimed: AtomicU64,
+    /// IO of the tracked transactions. There are no pages, every value read or written counts as one.
+    io: IoTracker,
+    /// Writes of the started transactions, undone if they abort.
+    undo: UndoLog,
+    /// Locks of the started transactions, taken on values.
+    locks: Arc<LockManager>,
 }
 
 impl Drop for StorageManager {
@@ -44,8 +66,13 @@ impl StorageTrait for StorageManager {
             );
             StorageManager {
                 containers: Arc::new(RwLock::new(HashMap::new())),
+                metadata: Arc::new(RwLock::new(HashMap::new())),
                 last_insert: Arc::new(RwLock::new(HashMap::new())),
                 persist_path: PathBuf::from(storage_path),
+                reclaimed: AtomicU64::new(0),
+                io: IoTracker::default(),
+                undo: UndoLog::default(),
+                locks: Arc::new(LockManager::default()),
             }
         }
     }
@@ -60,7 +87,7 @@ impl StorageTrait for StorageManager {
         &self,
         container_id: ContainerId,
         value: Vec<u8>,
-        _tid: TransactionId,
+        tid: TransactionId,
     ) -> ValueId {
         // Get the container
         let mut containers = self.containers.write().unwrap();
@@ -86,30 +113,63 @@ impl StorageTrait for StorageManager {
         debug!("memstore:storage_manager insert key: {:?} value: {:?}", &rid, &value);
         vals.insert(rid, value);
         last_insert.insert(container_id, rid.clone());
+        // nobody else can hold a lock on a new id
+        self.locks
+            .try_lock(tid, LockTarget::Value(rid), Permissions::ReadWrite);
+        self.io.add_written(tid, 1);
+        self.undo.record(tid, vec![Write::Insert(rid)]);
         rid
     }
 
-    /// Insert multiple values
+    /// Insert multiple values. The locks are held for the whole batch, so an iterator sees
+    /// either all of the values or none of them.
     fn insert_values(
         &self,
         container_id: ContainerId,
         values: Vec<Vec<u8>>,
         tid: TransactionId,
     ) -> Vec<ValueId> {
-        let mut ret = Vec::new();
-        for x in values {
-            ret.push(self.insert_value(container_id, x, tid));
+        let mut containers = self.containers.write().unwrap();
+        let mut last_insert = self.last_insert.write().unwrap();
+        let mut vals = containers
+            .get_mut(&container_id)
+            .expect("Container ID Missing on insert")
+            .write()
+            .unwrap();
+        let first_slot = match last_insert.get(&container_id) {
+            None => 0,
+            Some(slot) => slot.slot_id.expect("Missing SlotId") + 1,
+        };
+        let mut ret = Vec::with_capacity(values.len());
+        for (slot_id, value) in (first_slot..).zip(values) {
+            let rid = ValueId {
+                container_id,
+                segment_id: None,
+                page_id: None,
+                slot_id: Some(slot_id),
+            };
+            vals.insert(rid, value);
+            last_insert.insert(container_id, rid);
+            self.locks
+                .try_lock(tid, LockTarget::Value(rid), Permissions::ReadWrite);
+            self.io.add_written(tid, 1);
+            ret.push(rid);
         }
+        self.undo
+            .record(tid, ret.iter().map(|rid| Write::Insert(*rid)));
         ret
     }
 
     /// Remove the value from the container
-    fn delete_value(&self, id: ValueId, _tid: TransactionId) -> Result<(), CrustyError> {
+    fn delete_value(&self, id: ValueId, tid: TransactionId) -> Result<(), CrustyError> {
+        self.locks
+            .lock(tid, LockTarget::Value(id), Permissions::ReadWrite)?;
         let containers = self.containers.write().unwrap();
         if containers.contains_key(&id.container_id) {
             let mut table_map = containers.get(&id.container_id).unwrap().write().unwrap();
-            if table_map.contains_key(&id) {
-                table_map.remove(&id);
+            if let Some(value) = table_map.remove(&id) {
+                self.io.add_written(tid, 1);
+                self.undo.record(tid, vec![Write::Delete(id, value)]);
                 Ok(())
             } else {
                 //Key not found, no need to delete.
@@ -134,9 +194,15 @@ impl StorageTrait for StorageManager {
     }
 
     /// Add a new container
-    fn create_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+    fn create_container(
+        &self,
+        container_id: ContainerId,
+        name: Option<String>,
+        container_type: ContainerType,
+    ) -> Result<(), CrustyError> {
         let mut containers = self.containers.write().unwrap();
-        if containers.contains_key(&container_id) {
+        let mut metadata = self.metadata.write().unwrap();
+        if !register_container(&mut metadata, container_id, name, container_type)? {
             debug!("memstore::create_container container_id: {:?} already exists", &container_id);
             return Ok(());
         }
@@ -145,6 +211,14 @@ impl StorageTrait for StorageManager {
         Ok(())
     }
 
+    /// List the metadata of all containers
+    fn list_containers(&self) -> Vec<ContainerMetadata> {
+        let mut res: Vec<ContainerMetadata> =
+            self.metadata.read().unwrap().values().cloned().collect();
+        res.sort_by_key(|meta| meta.container_id);
+        res
+    }
+
     /// Remove the container and all stored values in the container. 
     /// If the container is persisted remove the underlying files
     fn remove_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
@@ -155,40 +229,218 @@ impl StorageTrait for StorageManager {
         }
         debug!("memstore::remove_container container_id: {:?} exists. dropping", &container_id);
         containers.remove(&container_id).unwrap();
+        self.metadata.write().unwrap().remove(&container_id);
+        // otherwise the container would come back from its file on the next load
+        if let Some(path) = self.container_file(container_id) {
+            if let Ok(meta) = fs::metadata(&path) {
+                fs::remove_file(&path)?;
+                self.reclaimed.fetch_add(meta.len(), Ordering::Relaxed);
+            }
+        }
         Ok(())
     }
 
-    /// Get an iterator for a container
+    /// Drop every value in a container and restart its value ids.
+    /// The container gets a fresh map so open iterators keep the old values.
+    fn truncate_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+        let mut containers = self.containers.write().unwrap();
+        let mut last_insert = self.last_insert.write().unwrap();
+        match containers.get_mut(&container_id) {
+            Some(vals) => {
+                debug!("memstore::truncate_container container_id: {:?}", &container_id);
+                *vals = Arc::new(RwLock::new(HashMap::new()));
+                last_insert.remove(&container_id);
+                Ok(())
+            }
+            None => Err(CrustyError::CrustyError(format!(
+                "Container {:?} not found for truncate",
+                container_id
+            ))),
+        }
+    }
+
+    /// Values live in memory, so the only space to reclaim is that of a persisted file that is
+    /// larger than the container now is. The file is rewritten if that is the case.
+    fn reclaim_space(&self, container_id: ContainerId) -> Result<u64, CrustyError> {
+        let containers = self.containers.read().unwrap();
+        let vals = containers.get(&container_id).ok_or_else(|| {
+            CrustyError::CrustyError(format!("Container {:?} not found", container_id))
+        })?;
+        match self.container_file(container_id) {
+            Some(path) if path.exists() => {
+                let vals = vals.read().unwrap();
+                self.persist_container(&path, &vals)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// Space used by the persisted container files.
+    fn storage_stats(&self) -> StorageStats {
+        let containers = self.containers.read().unwrap();
+        let mut stats = StorageStats {
+            containers: containers.len(),
+            reclaimed_bytes: self.reclaimed.load(Ordering::Relaxed),
+            ..StorageStats::default()
+        };
+        for c_id in containers.keys() {
+            if let Some(meta) = self
+                .container_file(*c_id)
+                .and_then(|path| fs::metadata(path).ok())
+            {
+                stats.logical_bytes += meta.len();
+                stats.allocated_bytes += allocated_bytes(&meta);
+            }
+        }
+        stats
+    }
+
+    /// Space used by a container. Values that were not persisted yet take no disk space.
+    fn container_usage(&self, container_id: ContainerId) -> Result<ContainerUsage, CrustyError> {
+        let containers = self.containers.read().unwrap();
+        let vals = containers.get(&container_id).ok_or_else(|| {
+            CrustyError::CrustyError(format!("Container {:?} not found", container_id))
+        })?;
+        let live_bytes = vals.read().unwrap().values().map(|v| v.len() as u64).sum();
+        let disk_bytes = self
+            .container_file(container_id)
+            .and_then(|path| fs::metadata(path).ok())
+            .map_or(0, |meta| meta.len());
+        Ok(ContainerUsage {
+            disk_bytes,
+            live_bytes,
+        })
+    }
+
+    /// Record the oldest row format of a container
+    fn set_row_format(
+        &self,
+        container_id: ContainerId,
+        row_format: RowFormat,
+    ) -> Result<(), CrustyError> {
+        update_row_format(&mut self.metadata.write().unwrap(), container_id, row_format)
+    }
+
+    /// Record the slot policy of a container. Slot ids are handed out in increasing order under
+    /// either policy, so the policy is only recorded.
+    fn set_slot_policy(
+        &self,
+        container_id: ContainerId,
+        slot_policy: SlotPolicy,
+    ) -> Result<(), CrustyError> {
+        update_slot_policy(&mut self.metadata.write().unwrap(), container_id, slot_policy)
+    }
+
+    /// Record the page compression of a container. Values are not kept in pages, so nothing is
+    /// compressed.
+    fn set_compression(
+        &self,
+        container_id: ContainerId,
+        compression: PageCompression,
+    ) -> Result<(), CrustyError> {
+        update_compression(
+            &mut self.metadata.write().unwrap(),
+            container_id,
+            compression,
+        )
+    }
+
+    /// Check if a container exists
+    fn container_exists(&self, container_id: ContainerId) -> bool {
+        self.containers.read().unwrap().contains_key(&container_id)
+    }
+
+    /// Get an iterator for a container. The iterator holds a reference to the container's map,
+    /// so it is unaffected by the container being removed or truncated while it is open. It locks
+    /// each value before reading it.
     fn get_iterator(
         &self,
         container_id: ContainerId,
-        _tid: TransactionId,
-        _perm: Permissions,
+        tid: TransactionId,
+        perm: Permissions,
     ) -> ValueIterator {
-        let table_map = self
-            .containers
-            .read()
-            .unwrap()
-            .get(&container_id)
-            .unwrap()
-            .clone();
+        self.get_partition_iterator(container_id, tid, perm, 0, 0, 1)
+    }
+
+    /// Splits the slots of the container, the empty ones of deleted values included, into
+    /// `partitions` ranges.
+    fn get_partition_iterator(
+        &self,
+        container_id: ContainerId,
+        tid: TransactionId,
+        perm: Permissions,
+        _prefetch_pages: usize,
+        partition: usize,
+        partitions: usize,
+    ) -> ValueIterator {
+        let table_map = match self.containers.read().unwrap().get(&container_id) {
+            Some(table_map) => table_map.clone(),
+            None => {
+                debug!("memstore::get_iterator container_id: {:?} does not exist", &container_id);
+                Arc::new(RwLock::new(HashMap::new()))
+            }
+        };
         let last_insert = self.last_insert.read().unwrap();
         debug!("memstore::get_iterator container_id: {:?}", &container_id);
-        let max = last_insert.get(&container_id).unwrap().slot_id.unwrap_or(0);
-        ValueIterator::new(table_map, container_id, max)
+        // an empty (or truncated) container has no last insert
+        let slots = match last_insert.get(&container_id) {
+            Some(rid) => rid.slot_id.unwrap_or(0) as usize + 1,
+            None => 1,
+        };
+        let partitions = partitions.max(1);
+        let start = slots * partition / partitions;
+        let end = slots * (partition + 1) / partitions;
+        ValueIterator::new(
+            table_map,
+            container_id,
+            start as u32..end as u32,
+            self.io.counters(tid),
+            ValueLocks {
+                locks: Arc::clone(&self.locks),
+                tid,
+                perm,
+            },
+        )
+    }
+
+    /// Get the values of a container with their ids, in slot order. The values are locked one
+    /// after another, a lock that can't be taken fails the call.
+    fn get_values_with_ids(
+        &self,
+        container_id: ContainerId,
+        tid: TransactionId,
+        perm: Permissions,
+    ) -> Result<Vec<(ValueId, Vec<u8>)>, CrustyError> {
+        let vals = match self.containers.read().unwrap().get(&container_id) {
+            Some(vals) => vals.clone(),
+            None => return Ok(Vec::new()),
+        };
+        let mut ids: Vec<ValueId> = vals.read().unwrap().keys().copied().collect();
+        ids.sort_by_key(|id| id.slot_id);
+        let mut values = Vec::with_capacity(ids.len());
+        for id in ids {
+            self.locks.lock(tid, LockTarget::Value(id), perm)?;
+            if let Some(value) = vals.read().unwrap().get(&id) {
+                values.push((id, value.clone()));
+            }
+        }
+        self.io.add_read(tid, values.len() as u64);
+        Ok(values)
     }
 
     /// Get the bytes for a given value if found
     fn get_value(
         &self,
         id: ValueId,
-        _tid: TransactionId,
-        _perm: Permissions,
+        tid: TransactionId,
+        perm: Permissions,
     ) -> Result<Vec<u8>, CrustyError> {
+        self.locks.lock(tid, LockTarget::Value(id), perm)?;
         let containers = self.containers.read().unwrap();
         if containers.contains_key(&id.container_id) {
             let map = containers.get(&id.container_id).unwrap().read().unwrap();
             if map.contains_key(&id) {
+                self.io.add_read(tid, 1);
                 Ok(map.get(&id).unwrap().clone())
             } else {
                 Err(CrustyError::ExecutionError(format!(
@@ -204,48 +456,156 @@ impl StorageTrait for StorageManager {
         }
     }
 
-    fn transaction_finished(&self, _tid: TransactionId) {
-        panic!("Not implemented");
+    fn track_io(&self, tid: TransactionId) {
+        self.io.track(tid);
+    }
+
+    fn untrack_io(&self, tid: TransactionId) -> IoStats {
+        self.io.untrack(tid)
+    }
+
+    fn start_transaction(&self, tid: TransactionId) {
+        self.undo.start(tid);
+        self.locks.start(tid);
+    }
+
+    fn check_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.locks.check(tid)
+    }
+
+    fn transaction_finished(&self, tid: TransactionId) {
+        self.undo.commit(tid);
+        self.locks.release(tid);
+    }
+
+    /// Removes the inserted values and puts deleted ones back under their old ids.
+    fn abort_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        let containers = self.containers.read().unwrap();
+        for write in self.undo.abort(tid) {
+            match write {
+                Write::Insert(id) => {
+                    if let Some(vals) = containers.get(&id.container_id) {
+                        vals.write().unwrap().remove(&id);
+                    }
+                }
+                Write::Delete(id, value) => {
+                    if let Some(vals) = containers.get(&id.container_id) {
+                        vals.write().unwrap().insert(id, value);
+                    }
+                }
+                // index entries are stored as values, so they are undone as inserts
+                Write::IndexInsert(..) => {}
+            }
+        }
+        self.locks.release(tid);
+        Ok(())
     }
 
-    fn reset(&self) {
-        panic!("Not implemented");
+    fn reset(&self) -> Result<(), CrustyError> {
+        info!("Resetting memstore::storage_manager");
+        let mut containers = self.containers.write().unwrap();
+        let mut metadata = self.metadata.write().unwrap();
+        let mut last_insert = self.last_insert.write().unwrap();
+        containers.clear();
+        metadata.clear();
+        last_insert.clear();
+        self.undo.clear();
+        self.locks.clear();
+        self.reclaimed.store(0, Ordering::Relaxed);
+        if self.persist_path.as_os_str().is_empty() || !self.persist_path.exists() {
+            return Ok(());
+        }
+        // only the files the storage manager persists, the path may be shared
+        for entry in fs::read_dir(&self.persist_path)? {
+            let path = entry?.path();
+            let is_container = path.extension().is_some_and(|ext| ext == "ms");
+            if is_container || path.file_name().is_some_and(|name| name == METADATA_FILE) {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
     }
 
-    fn shutdown(&self) {
+    fn shutdown(&self) -> Result<(), CrustyError> {
         info!("Shutting down and persisting containers");
         if self.persist_path.to_string_lossy() == String::from("") {
             info!("Test SM or no path, not persisting");
-            return;
+            return Ok(());
         }
-        fs::create_dir_all(self.persist_path.to_path_buf())
-            .expect("Unable to create dir to store SM");
+        fs::create_dir_all(self.persist_path.to_path_buf())?;
         let containers = self.containers.read().unwrap();
         for (c_id, vals_lock) in containers.iter() {
             let vals = vals_lock.read().unwrap();
-            let mut file_path = self.persist_path.clone();
-            file_path.push(format!("{}", c_id));
-            file_path.set_extension("ms");
-            let file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(file_path)
-                .expect("Failed to create file");
-            serde_cbor::to_writer(file, &*vals).expect("Failed on persisting container");
+            let file_path = self.container_file(*c_id).unwrap();
+            self.persist_container(&file_path, &vals)?;
         }
+        let mut meta_path = self.persist_path.clone();
+        meta_path.push(METADATA_FILE);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(meta_path)?;
+        serde_cbor::to_writer(file, &self.list_containers())
+            .map_err(|e| CrustyError::IOError(e.to_string()))
     }
 }
 
 impl StorageManager {
+    /// Path of the file a container is persisted to, None if the SM is not persisted.
+    fn container_file(&self, container_id: ContainerId) -> Option<PathBuf> {
+        if self.persist_path.as_os_str().is_empty() {
+            return None;
+        }
+        let mut file_path = self.persist_path.clone();
+        file_path.push(format!("{}", container_id));
+        file_path.set_extension("ms");
+        Some(file_path)
+    }
+
+    /// Writes the values of a container to its file, replacing what was there. Returns by how
+    /// many bytes the file shrank.
+    fn persist_container(
+        &self,
+        file_path: &Path,
+        vals: &HashMap<ValueId, Vec<u8>>,
+    ) -> Result<u64, CrustyError> {
+        let old_len = fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(file_path)?;
+        serde_cbor::to_writer(&file, vals).map_err(|e| CrustyError::IOError(e.to_string()))?;
+        let reclaimed = old_len.saturating_sub(file.metadata()?.len());
+        self.reclaimed.fetch_add(reclaimed, Ordering::Relaxed);
+        Ok(reclaimed)
+    }
+
     /// Create a Memstore SM from a file path and populate from the files
     fn load(path: String) -> Self {
         let mut container_map = HashMap::new();
         let mut last_ins = HashMap::new();
+        let mut metadata = HashMap::new();
+        // Read the container metadata if it was persisted
+        let mut meta_path = PathBuf::from(&path);
+        meta_path.push(METADATA_FILE);
+        if meta_path.exists() {
+            let file = OpenOptions::new()
+                .read(true)
+                .open(meta_path)
+                .expect("Failed to read file");
+            let metas: Vec<ContainerMetadata> =
+                serde_cbor::from_reader(file).expect("cannot read file");
+            for meta in metas {
+                metadata.insert(meta.container_id, meta);
+            }
+        }
         // Find the files that end with .ms
         let entries: Vec<fs::DirEntry> = fs::read_dir(&path)
             .unwrap()
             .filter_map(Result::ok)
-            .filter(|x| x.path().extension().unwrap() == "ms")
+            .filter(|x| x.path().extension().map_or(false, |ext| ext == "ms"))
             .collect();
         // populate
         for entry in entries {
@@ -284,51 +644,99 @@ impl StorageManager {
             }
             container_map.insert(cid, Arc::new(RwLock::new(container)));
             last_ins.insert(cid, max_val);
+            // containers persisted without metadata are assumed to be unnamed tables
+            metadata.entry(cid).or_insert(ContainerMetadata {
+                container_id: cid,
+                name: None,
+                container_type: ContainerType::Table,
+                row_format: RowFormat::Cbor,
+                slot_policy: SlotPolicy::default(),
+                compression: PageCompression::default(),
+            });
         }
         StorageManager {
             containers: Arc::new(RwLock::new(container_map)),
+            metadata: Arc::new(RwLock::new(metadata)),
             last_insert: Arc::new(RwLock::new(last_ins)),
             persist_path: PathBuf::from(path),
+            reclaimed: AtomicU64::new(0),
+            io: IoTracker::default(),
+            undo: UndoLog::default(),
+            locks: Arc::new(LockManager::default()),
         }
     }
 }
 
+/// Locks an iterator takes on the values it reads.
+struct ValueLocks {
+    locks: Arc<LockManager>,
+    tid: TransactionId,
+    perm: Permissions,
+}
+
 // The iterator struct
 pub struct ValueIterator {
     tracker: ValueId,
-    max: u16,
     table_map: ContainerMap,
-    current: u16,
+    /// Slot of the next value, and the slot the iterator stops at.
+    slots: Range<u32>,
+    /// Copy of the last value `next_ref` returned. The values stay in the container, behind
+    /// its lock, so they are copied out, but into the same buffer every time.
+    buf: Vec<u8>,
+    /// Counters of the transaction the iterator was created for, if its IO is tracked.
+    io: Option<Arc<IoCounters>>,
+    locks: ValueLocks,
 }
 
 impl ValueIterator {
     //Create a new iterator for a container
-    fn new(table_map: ContainerMap, container_id: ContainerId, max: u16) -> Self {
-        debug!("new iterator {:?} max {}", container_id, max);
+    fn new(
+        table_map: ContainerMap,
+        container_id: ContainerId,
+        slots: Range<u32>,
+        io: Option<Arc<IoCounters>>,
+        locks: ValueLocks,
+    ) -> Self {
+        debug!("new iterator {:?} slots {:?}", container_id, slots);
         let mut tracker = ValueId::new(container_id);
-        tracker.slot_id = Some(0);
+        tracker.slot_id = Some(slots.start as u16);
         ValueIterator {
             tracker,
-            max,
             table_map,
-            current: 0,
+            slots,
+            buf: Vec::new(),
+            io,
+            locks,
         }
     }
-}
 
-impl Iterator for ValueIterator {
-    type Item = Vec<u8>;
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.current <= self.max {
+    /// Advances the iterator and reads the next value with `read`.
+    ///
+    /// # Arguments
+    ///
+    /// * `read` - Reads the value while the container is locked.
+    fn next_with<T>(&mut self, read: impl FnOnce(&Vec<u8>) -> T) -> Option<T> {
+        while self.slots.start < self.slots.end {
+            let ValueLocks { locks, tid, perm } = &self.locks;
+            if locks
+                .lock(*tid, LockTarget::Value(self.tracker), *perm)
+                .is_err()
+            {
+                // the transaction was aborted, see StorageTrait::check_transaction
+                return None;
+            }
             match self.table_map.read().unwrap().get(&self.tracker) {
                 Some(res) => {
-                    self.tracker.slot_id = Some(self.tracker.slot_id.unwrap() + 1);
-                    self.current += 1;
-                    return Some(res.clone());
+                    self.tracker.slot_id = Some(self.tracker.slot_id.unwrap().wrapping_add(1));
+                    self.slots.start += 1;
+                    if let Some(io) = &self.io {
+                        io.add_read(1);
+                    }
+                    return Some(read(res));
                 }
                 None => {
-                    self.tracker.slot_id = Some(self.tracker.slot_id.unwrap() + 1);
-                    self.current += 1;
+                    self.tracker.slot_id = Some(self.tracker.slot_id.unwrap().wrapping_add(1));
+                    self.slots.start += 1;
                 }
             }
         }
@@ -336,6 +744,27 @@ impl Iterator for ValueIterator {
     }
 }
 
+/// The values are in memory, reading them can't fail.
+impl ValueRefIterator for ValueIterator {
+    fn next_ref(&mut self) -> Option<Result<&[u8], CrustyError>> {
+        let mut buf = std::mem::take(&mut self.buf);
+        let found = self.next_with(|value| {
+            buf.clear();
+            buf.extend_from_slice(value);
+        });
+        self.buf = buf;
+        found?;
+        Some(Ok(self.buf.as_slice()))
+    }
+}
+
+impl Iterator for ValueIterator {
+    type Item = Result<Vec<u8>, CrustyError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with(|value| Ok(value.clone()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -351,7 +780,7 @@ mod tests {
         let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
         let sm = StorageManager::new_test_sm();
         let container_id = 1;
-        sm.create_container(container_id).unwrap();
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let rid = sm.insert_value(container_id, tuple_bytes.clone(), tid);
         let check_bytes = sm.get_value(rid, tid, Permissions::ReadOnly).unwrap();
@@ -368,7 +797,7 @@ mod tests {
         let tuple_bytes2 = serde_cbor::to_vec(&tuple2).unwrap();
         let sm = StorageManager::new_test_sm();
         let container_id = 1;
-        sm.create_container(container_id).unwrap();
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let rid = sm.insert_value(container_id, tuple_bytes.clone(), tid);
         let rid2 = sm.insert_value(container_id, tuple_bytes2.clone(), tid);
@@ -391,7 +820,7 @@ mod tests {
         let byte_vec = vec![tuple_bytes.clone(), tuple_bytes2.clone()];
         let sm = StorageManager::new_test_sm();
         let container_id = 1;
-        sm.create_container(container_id).unwrap();
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let rid = sm.insert_values(container_id, byte_vec, tid);
         let mut check_bytes = sm
@@ -414,7 +843,7 @@ mod tests {
         let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
         let sm = StorageManager::new_test_sm();
         let container_id = 1;
-        sm.create_container(container_id).unwrap();
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let rid = sm.insert_value(container_id, tuple_bytes.clone(), tid);
         let check_bytes = sm.get_value(rid, tid, Permissions::ReadOnly).unwrap();
@@ -436,17 +865,17 @@ mod tests {
         let tuple_bytes2 = serde_cbor::to_vec(&tuple2).unwrap();
         let sm = StorageManager::new_test_sm();
         let container_id = 1;
-        sm.create_container(container_id).unwrap();
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let _rid = sm.insert_value(container_id, tuple_bytes.clone(), tid);
         let _rid2 = sm.insert_value(container_id, tuple_bytes2.clone(), tid);
         let mut iter = sm.get_iterator(container_id, tid, Permissions::ReadOnly);
 
-        let mut check_bytes = iter.next().unwrap();
+        let mut check_bytes = iter.next().unwrap().unwrap();
         let mut check_tuple: Tuple = serde_cbor::from_slice(&check_bytes).unwrap();
         assert_eq!(tuple_bytes, check_bytes);
         assert_eq!(tuple, check_tuple);
-        check_bytes = iter.next().unwrap();
+        check_bytes = iter.next().unwrap().unwrap();
         check_tuple = serde_cbor::from_slice(&check_bytes).unwrap();
         assert_eq!(tuple_bytes2, check_bytes);
         assert_eq!(tuple2, check_tuple);
@@ -462,17 +891,17 @@ mod tests {
         let tuple_bytes2 = serde_cbor::to_vec(&tuple2).unwrap();
         let sm = StorageManager::new_test_sm();
         let container_id = 1;
-        sm.create_container(container_id).unwrap();
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let rid = sm.insert_value(container_id, tuple_bytes.clone(), tid);
         let _rid2 = sm.insert_value(container_id, tuple_bytes2.clone(), tid);
         let mut iter = sm.get_iterator(container_id, tid, Permissions::ReadOnly);
 
-        let mut check_bytes = iter.next().unwrap();
+        let mut check_bytes = iter.next().unwrap().unwrap();
         let mut check_tuple: Tuple = serde_cbor::from_slice(&check_bytes).unwrap();
         assert_eq!(tuple_bytes, check_bytes);
         assert_eq!(tuple, check_tuple);
-        check_bytes = iter.next().unwrap();
+        check_bytes = iter.next().unwrap().unwrap();
         check_tuple = serde_cbor::from_slice(&check_bytes).unwrap();
         assert_eq!(tuple_bytes2, check_bytes);
         assert_eq!(tuple2, check_tuple);
@@ -480,20 +909,73 @@ mod tests {
 
         sm.delete_value(rid, tid).unwrap();
         let mut iter2 = sm.get_iterator(container_id, tid, Permissions::ReadOnly);
-        check_bytes = iter2.next().unwrap();
+        check_bytes = iter2.next().unwrap().unwrap();
         check_tuple = serde_cbor::from_slice(&check_bytes).unwrap();
         assert_eq!(tuple_bytes2, check_bytes);
         assert_eq!(tuple2, check_tuple);
         assert_eq!(None, iter2.next());
     }
 
+    #[test]
+    fn test_partition_iter() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let container_id = 1;
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        let rids: Vec<ValueId> = (0..10)
+            .map(|i| sm.insert_value(container_id, vec![i], tid))
+            .collect();
+        sm.delete_value(rids[4], tid).unwrap();
+        let all = sm
+            .get_iterator(container_id, tid, Permissions::ReadOnly)
+            .collect_values()
+            .unwrap();
+        // the parts, in order, hold the values of the container in order
+        for partitions in 1..=12 {
+            let parts: Result<Vec<Vec<u8>>, CrustyError> = (0..partitions)
+                .flat_map(|p| {
+                    sm.get_partition_iterator(
+                        container_id,
+                        tid,
+                        Permissions::ReadOnly,
+                        0,
+                        p,
+                        partitions,
+                    )
+                })
+                .collect();
+            assert_eq!(all, parts.unwrap());
+        }
+        assert_eq!(9, all.len());
+    }
+
+    #[test]
+    fn test_iter_next_ref() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let container_id = 1;
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        let rids: Vec<ValueId> = (0..5)
+            .map(|i| sm.insert_value(container_id, vec![i; i as usize + 1], tid))
+            .collect();
+        sm.delete_value(rids[1], tid).unwrap();
+        let mut iter = sm.get_iterator(container_id, tid, Permissions::ReadOnly);
+        assert_eq!(Some(Ok(&[0][..])), iter.next_ref());
+        assert_eq!(Some(Ok(&[2, 2, 2][..])), iter.next_ref());
+        assert_eq!(Some(Ok(vec![3; 4])), iter.next());
+        assert_eq!(Some(Ok(&[4; 5][..])), iter.next_ref());
+        assert_eq!(None, iter.next_ref());
+    }
+
     #[test]
     fn test_not_found() {
         let tuple = int_vec_to_tuple(vec![0, 1, 2]);
         let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
         let sm = StorageManager::new_test_sm();
         let container_id = 1;
-        sm.create_container(container_id).unwrap();
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let rid = sm.insert_value(container_id, tuple_bytes.clone(), tid);
         let check_bytes = sm.get_value(rid, tid, Permissions::ReadOnly).unwrap();
@@ -521,9 +1003,9 @@ mod tests {
         info!("{:?}", persist);
         let sm = StorageManager::new(persist.to_string_lossy().to_string());
         let container_id = 1;
-        sm.create_container(container_id).unwrap();
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
 
-        sm.create_container(2).unwrap();
+        sm.create_container(2, None, ContainerType::Table).unwrap();
         let tid = TransactionId::new();
         let bytes1 = get_random_byte_vec(100);
         let bytes2 = get_random_byte_vec(300);
@@ -533,7 +1015,7 @@ mod tests {
         let vid3 = sm.insert_value(2, bytes3.clone(), tid);
         let vid4 = sm.insert_value(container_id, bytes2.clone(), tid);
         sm.delete_value(vid4, tid).unwrap();
-        sm.shutdown();
+        sm.shutdown().unwrap();
 
         let sm2 = StorageManager::new(persist.to_string_lossy().to_string());
         let byte_check = sm2
@@ -556,4 +1038,255 @@ mod tests {
 
         fs::remove_dir_all(persist).unwrap();
     }
+
+    #[test]
+    fn test_reset() {
+        init();
+        let persist = gen_random_dir();
+        let path = persist.to_string_lossy().to_string();
+        let sm = StorageManager::new(path.clone());
+        sm.create_container(1, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        sm.start_transaction(tid);
+        sm.insert_value(1, get_random_byte_vec(100), tid);
+        sm.shutdown().unwrap();
+        let other_file = persist.join("other.json");
+        fs::write(&other_file, "{}").unwrap();
+
+        sm.reset().unwrap();
+        assert!(!sm.container_exists(1));
+        assert!(sm.list_containers().is_empty());
+        assert_eq!(
+            vec![other_file.clone()],
+            fs::read_dir(&persist)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect::<Vec<_>>()
+        );
+        // the transaction is forgotten and its locks are gone
+        sm.abort_transaction(tid).unwrap();
+        sm.create_container(1, None, ContainerType::Table).unwrap();
+        let tid2 = TransactionId::new();
+        sm.start_transaction(tid2);
+        let vid = sm.insert_value(1, get_random_byte_vec(10), tid2);
+        assert_eq!(Some(0), vid.slot_id);
+        assert!(sm.get_value(vid, tid2, Permissions::ReadOnly).is_ok());
+        assert!(StorageManager::new(path).list_containers().is_empty());
+
+        fs::remove_dir_all(persist).unwrap();
+    }
+
+    #[test]
+    fn test_container_metadata() {
+        init();
+        let persist = gen_random_dir();
+        let sm = StorageManager::new(persist.to_string_lossy().to_string());
+        sm.create_container(2, Some(String::from("b")), ContainerType::Table)
+            .unwrap();
+        sm.create_container(1, Some(String::from("a")), ContainerType::Index)
+            .unwrap();
+        let tid = TransactionId::new();
+        let vid = sm.insert_value(1, get_random_byte_vec(10), tid);
+
+        // Creating the same container again keeps its data
+        sm.create_container(1, Some(String::from("a")), ContainerType::Index)
+            .unwrap();
+        sm.create_container(1, None, ContainerType::Index).unwrap();
+        assert!(sm.get_value(vid, tid, Permissions::ReadOnly).is_ok());
+        // A different name for the id, or a taken name, is an error
+        assert!(sm
+            .create_container(1, Some(String::from("c")), ContainerType::Table)
+            .is_err());
+        assert!(sm
+            .create_container(3, Some(String::from("a")), ContainerType::Table)
+            .is_err());
+
+        let listed = sm.list_containers();
+        assert_eq!(
+            vec![1, 2],
+            listed.iter().map(|m| m.container_id).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(String::from("a")), listed[0].name);
+        assert_eq!(ContainerType::Index, listed[0].container_type);
+
+        sm.shutdown().unwrap();
+        let sm2 = StorageManager::new(persist.to_string_lossy().to_string());
+        assert_eq!(listed, sm2.list_containers());
+        fs::remove_dir_all(persist).unwrap();
+    }
+
+    #[test]
+    fn test_reclaim_space() {
+        init();
+        let persist = gen_random_dir();
+        let sm = StorageManager::new(persist.to_string_lossy().to_string());
+        sm.create_container(1, None, ContainerType::Table).unwrap();
+        sm.create_container(2, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        for _ in 0..100 {
+            sm.insert_value(1, get_random_byte_vec(100), tid);
+            sm.insert_value(2, get_random_byte_vec(100), tid);
+        }
+        // Nothing is persisted yet
+        assert_eq!(0, sm.reclaim_space(1).unwrap());
+        let usage = sm.container_usage(1).unwrap();
+        assert_eq!(100 * 100, usage.live_bytes);
+        assert_eq!(0, usage.disk_bytes);
+        sm.shutdown().unwrap();
+        let stats = sm.storage_stats();
+        assert_eq!(2, stats.containers);
+        let usage = sm.container_usage(1).unwrap();
+        assert!(usage.disk_bytes > usage.live_bytes);
+        assert!(sm.container_usage(3).is_err());
+        assert!(stats.logical_bytes > 2 * 100 * 100);
+        assert_eq!(0, stats.reclaimed_bytes);
+
+        // The file shrinks to the now empty container
+        sm.truncate_container(1).unwrap();
+        let reclaimed = sm.reclaim_space(1).unwrap();
+        assert!(reclaimed > 100 * 100);
+        assert_eq!(0, sm.reclaim_space(1).unwrap());
+        assert!(sm.reclaim_space(3).is_err());
+
+        // Removing a container removes its file, so it does not come back
+        sm.remove_container(2).unwrap();
+        let after = sm.storage_stats();
+        assert_eq!(1, after.containers);
+        assert!(after.logical_bytes < 100);
+        assert_eq!(stats.logical_bytes, after.logical_bytes + after.reclaimed_bytes);
+        let sm2 = StorageManager::new(persist.to_string_lossy().to_string());
+        assert!(!sm2.container_exists(2));
+        assert_eq!(0, sm2.get_iterator(1, tid, Permissions::ReadOnly).count());
+        fs::remove_dir_all(persist).unwrap();
+    }
+
+    #[test]
+    fn test_truncate() {
+        let sm = StorageManager::new_test_sm();
+        let container_id = 1;
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
+        sm.create_container(2, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        let bytes = get_random_byte_vec(100);
+        let vid1 = sm.insert_value(container_id, bytes.clone(), tid);
+        sm.insert_value(container_id, bytes.clone(), tid);
+        let other = sm.insert_value(2, bytes.clone(), tid);
+        sm.truncate_container(container_id).unwrap();
+        assert_eq!(
+            0,
+            sm.get_iterator(container_id, tid, Permissions::ReadOnly)
+                .count()
+        );
+        assert!(sm.get_value(vid1, tid, Permissions::ReadOnly).is_err());
+        // other containers are untouched
+        assert_eq!(
+            bytes[..],
+            sm.get_value(other, tid, Permissions::ReadOnly).unwrap()[..]
+        );
+        // the container can be reused after truncation
+        sm.insert_value(container_id, bytes.clone(), tid);
+        assert_eq!(
+            1,
+            sm.get_iterator(container_id, tid, Permissions::ReadOnly)
+                .count()
+        );
+        assert!(sm.truncate_container(3).is_err());
+    }
+
+    #[test]
+    fn test_iterator_outlives_container() {
+        let sm = StorageManager::new_test_sm();
+        let container_id = 1;
+        sm.create_container(container_id, None, ContainerType::Table).unwrap();
+        let tid = TransactionId::new();
+        for _ in 0..3 {
+            sm.insert_value(container_id, get_random_byte_vec(10), tid);
+        }
+        let mut truncated = sm.get_iterator(container_id, tid, Permissions::ReadOnly);
+        assert!(truncated.next().is_some());
+        sm.truncate_container(container_id).unwrap();
+        // the open iterator finishes against the values it started with
+        assert_eq!(2, truncated.count());
+
+        sm.insert_value(container_id, get_random_byte_vec(10), tid);
+        let removed = sm.get_iterator(container_id, tid, Permissions::ReadOnly);
+        sm.remove_container(container_id).unwrap();
+        assert!(!sm.container_exists(container_id));
+        assert_eq!(1, removed.count());
+        // iterating a missing container does not panic
+        assert_eq!(
+            0,
+            sm.get_iterator(container_id, tid, Permissions::ReadOnly)
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_track_io() {
+        let sm = StorageManager::new_test_sm();
+        let container_id = 1;
+        sm.create_container(container_id, None, ContainerType::Table)
+            .unwrap();
+        let (t1, t2) = (TransactionId::new(), TransactionId::new());
+        sm.track_io(t1);
+        let mut rids = Vec::new();
+        for i in 0..3 {
+            rids.push(sm.insert_value(container_id, vec![i], t1));
+        }
+        // IO of other transactions is not counted
+        sm.insert_value(container_id, vec![3], t2);
+        sm.get_value(rids[0], t1, Permissions::ReadOnly).unwrap();
+        sm.delete_value(rids[1], t1).unwrap();
+        let iter = sm.get_iterator(container_id, t1, Permissions::ReadOnly);
+        assert_eq!(3, iter.count());
+        assert_eq!(
+            IoStats {
+                pages_read: 4,
+                pages_written: 4
+            },
+            sm.untrack_io(t1)
+        );
+        assert_eq!(IoStats::default(), sm.untrack_io(t2));
+    }
+
+    #[test]
+    fn test_abort_transaction() {
+        let sm = StorageManager::new_test_sm();
+        let container_id = 1;
+        sm.create_container(container_id, None, ContainerType::Table)
+            .unwrap();
+        let committed = TransactionId::new();
+        let kept = sm.insert_values(container_id, vec![vec![0], vec![1]], committed);
+
+        let tid = TransactionId::new();
+        sm.start_transaction(tid);
+        sm.insert_value(container_id, vec![2], tid);
+        sm.delete_value(kept[0], tid).unwrap();
+        let updated = sm.update_value(vec![3], kept[1], tid).unwrap();
+        assert_eq!(
+            vec![vec![2], vec![3]],
+            sm.get_iterator(container_id, tid, Permissions::ReadOnly)
+                .collect_values()
+                .unwrap()
+        );
+        sm.abort_transaction(tid).unwrap();
+        assert!(sm.get_value(updated, tid, Permissions::ReadOnly).is_err());
+        assert_eq!(
+            vec![vec![0], vec![1]],
+            sm.get_iterator(container_id, tid, Permissions::ReadOnly)
+                .collect_values()
+                .unwrap()
+        );
+
+        // writes of a committed transaction stay
+        sm.start_transaction(tid);
+        sm.delete_value(kept[0], tid).unwrap();
+        sm.transaction_finished(tid);
+        sm.abort_transaction(tid).unwrap();
+        assert_eq!(
+            1,
+            sm.get_iterator(container_id, tid, Permissions::ReadOnly)
+                .count()
+        );
+    }
 }
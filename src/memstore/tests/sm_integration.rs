@@ -2,7 +2,7 @@ extern crate common;
 extern crate memstore as sm;
 use common::ids::Permissions;
 use common::ids::{ContainerId, TransactionId};
-use common::storage_trait::StorageTrait;
+use common::storage_trait::{ContainerType, StorageTrait, ValueRefIterator};
 use common::testutil::*;
 use rand::{thread_rng, Rng};
 use sm::storage_manager::StorageManager;
@@ -17,9 +17,9 @@ fn sm_inserts() {
     for i in sizes {
         let vals1 = get_random_vec_of_byte_vec(i, 50, 100);
         let cid = i as ContainerId;
-        sm.create_container(cid).unwrap();
+        sm.create_container(cid, None, ContainerType::Table).unwrap();
         sm.insert_values(cid, vals1.clone(), t);
-        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).collect();
+        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).collect_values().unwrap();
         assert_eq!(
             true,
             compare_unordered_byte_vecs(&vals1, check_vals),
@@ -36,12 +36,12 @@ fn sm_insert_delete() {
     let t = TransactionId::new();
     let mut vals1 = get_random_vec_of_byte_vec(100, 50, 100);
     let cid = 1;
-    sm.create_container(cid).unwrap();
+    sm.create_container(cid, None, ContainerType::Table).unwrap();
     let mut val_ids = sm.insert_values(cid, vals1.clone(), t);
     for _ in 0..10 {
         let idx_to_del = rng.gen_range(0..vals1.len());
         sm.delete_value(val_ids[idx_to_del], t).unwrap();
-        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).collect();
+        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).collect_values().unwrap();
         assert_eq!(
             false,
             compare_unordered_byte_vecs(&vals1, check_vals.clone())
@@ -59,7 +59,7 @@ fn sm_insert_updates() {
     let t = TransactionId::new();
     let mut vals1 = get_random_vec_of_byte_vec(100, 50, 100);
     let cid = 1;
-    sm.create_container(cid).unwrap();
+    sm.create_container(cid, None, ContainerType::Table).unwrap();
     let mut val_ids = sm.insert_values(cid, vals1.clone(), t);
     for _ in 0..10 {
         let idx_to_upd = rng.gen_range(0..vals1.len());
@@ -67,7 +67,7 @@ fn sm_insert_updates() {
         let new_val_id = sm
             .update_value(new_bytes.clone(), val_ids[idx_to_upd], t)
             .unwrap();
-        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).collect();
+        let check_vals: Vec<Vec<u8>> = sm.get_iterator(cid, t, RO).collect_values().unwrap();
         assert_eq!(
             false,
             compare_unordered_byte_vecs(&vals1, check_vals.clone())
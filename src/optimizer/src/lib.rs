@@ -1 +1,4 @@
 pub mod optimizer;
+pub mod rules;
+#[cfg(test)]
+mod testutil;
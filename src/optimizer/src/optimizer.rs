@@ -1,3 +1,20 @@
+use crate::rules;
+use common::catalog::Catalog;
+use common::logical_plan::*;
+use common::statistics::Statistics;
+use common::table::Table;
+use common::CrustyError;
+
+/// Rewrites logical plans into equivalent ones that are cheaper to run.
+///
+/// The inner joins and cross products of the tables of a query are reordered by the estimated
+/// size of their results, using the statistics `ANALYZE` and imports collect. Filters above them
+/// are split into their conjuncts, and each conjunct is moved down to the first point of the new
+/// order where all of its columns are available. Joins of tables without statistics, and joins
+/// of anything else than tables, are left as they are.
+///
+/// Then, with or without statistics, the rules of [`rules`](crate::rules) push the remaining
+/// filters down and drop the columns the query doesn't use.
 pub struct Optimizer {}
 
 impl Optimizer {
@@ -6,5 +23,466 @@ impl Optimizer {
         sm
     }
 
-    pub fn do_your_work(&self) {}
+    /// Returns the optimized plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `lp` - Translated logical plan of a query.
+    /// * `catalog` - Catalog of the tables of the query.
+    /// * `stats` - Statistics of the tables of the database.
+    pub fn optimize<T: Catalog>(
+        &self,
+        lp: LogicalPlan,
+        catalog: &T,
+        stats: &Statistics,
+    ) -> Result<LogicalPlan, CrustyError> {
+        let lp = match lp.root() {
+            Some(root) if !stats.is_empty() => {
+                let mut rewrite = Rewrite {
+                    old: &lp,
+                    new: LogicalPlan::new(),
+                    catalog,
+                    stats,
+                };
+                rewrite.copy(root)?;
+                rewrite.new
+            }
+            _ => lp,
+        };
+        let lp = rules::push_down_filters(lp)?;
+        rules::prune_columns(lp, catalog)
+    }
+}
+
+/// A table joined by a reordered join, with the filters moved down to it.
+struct Input {
+    alias: String,
+    /// Node of the filtered table in the new plan.
+    node: OpIndex,
+    /// Estimated rows that pass the filters.
+    rows: f64,
+}
+
+/// Copy of a logical plan being optimized.
+struct Rewrite<'a, T: Catalog> {
+    old: &'a LogicalPlan,
+    new: LogicalPlan,
+    catalog: &'a T,
+    stats: &'a Statistics,
+}
+
+impl<'a, T: Catalog> Rewrite<'a, T> {
+    /// Copies a node of the old plan and its children to the new plan and returns its index in
+    /// the new plan, reordering the joins it is the top of.
+    fn copy(&mut self, node: OpIndex) -> Result<OpIndex, CrustyError> {
+        if self.is_join_tree(node) {
+            self.copy_joins(node)
+        } else {
+            self.copy_node(node)
+        }
+    }
+
+    /// Copies a node as it is, its children with `copy`.
+    fn copy_node(&mut self, node: OpIndex) -> Result<OpIndex, CrustyError> {
+        let old_children: Vec<OpIndex> = self.old.edges(node).collect();
+        let children = old_children
+            .iter()
+            .map(|&c| self.copy(c))
+            .collect::<Result<Vec<OpIndex>, CrustyError>>()?;
+        let op = match self.old.get_operator(node).ok_or_else(malformed)? {
+            // A reordered join returns the columns of its tables in another order, so `*`
+            // is spelled out in the order of the query.
+            LogicalOp::Project(ProjectNode {
+                identifiers: ProjectIdentifiers::Wildcard,
+            }) if scans(self.old, old_children[0]) != scans(&self.new, children[0]) => {
+                let mut identifiers = Vec::new();
+                for alias in scans(self.old, old_children[0]) {
+                    let table = self.catalog.get_table_ptr(Table::get_table_id(&alias))?;
+                    for attr in table.read().unwrap().schema.attributes() {
+                        let column = format!("{}.{}", alias, attr.name());
+                        identifiers.push(FieldIdentifier::new(&alias, &column));
+                    }
+                }
+                LogicalOp::Project(ProjectNode {
+                    identifiers: ProjectIdentifiers::List(identifiers),
+                })
+            }
+            op => op.clone(),
+        };
+        Ok(self.add(op, &children))
+    }
+
+    /// Adds an operator over the given children to the new plan.
+    fn add(&mut self, op: LogicalOp, children: &[OpIndex]) -> OpIndex {
+        add_node(&mut self.new, op, children)
+    }
+
+    /// Returns whether a node is an inner join or cross product, or filters above one.
+    fn is_join_tree(&self, node: OpIndex) -> bool {
+        match self.old.get_operator(node) {
+            Some(LogicalOp::Join(JoinNode {
+                join_type: JoinType::Inner,
+                ..
+            }))
+            | Some(LogicalOp::CrossProduct) => true,
+            Some(LogicalOp::Filter(_)) => self
+                .old
+                .edges(node)
+                .next()
+                .map_or(false, |c| self.is_join_tree(c)),
+            _ => false,
+        }
+    }
+
+    /// Collects the inputs of a tree of inner joins, cross products and filters, and the
+    /// conjuncts of their predicates.
+    fn flatten(
+        &self,
+        node: OpIndex,
+        inputs: &mut Vec<OpIndex>,
+        conjuncts: &mut Vec<PredicateExpr>,
+    ) -> Result<(), CrustyError> {
+        let op = self.old.get_operator(node).ok_or_else(malformed)?;
+        match op {
+            LogicalOp::Join(JoinNode {
+                left,
+                op,
+                right,
+                join_type: JoinType::Inner,
+                ..
+            }) => conjuncts.push(PredicateExpr::Compare(PredicateNode {
+                left: PredExpr::Ident(left.clone()),
+                op: *op,
+                right: PredExpr::Ident(right.clone()),
+            })),
+            LogicalOp::Filter(FilterNode { predicate, .. }) => match predicate {
+                PredicateExpr::And(preds) => conjuncts.extend(preds.iter().cloned()),
+                pred => conjuncts.push(pred.clone()),
+            },
+            LogicalOp::CrossProduct => {}
+            _ => {
+                inputs.push(node);
+                return Ok(());
+            }
+        }
+        for child in self.old.edges(node) {
+            if self.is_join_tree(child) {
+                self.flatten(child, inputs, conjuncts)?;
+            } else {
+                inputs.push(child);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies a tree of inner joins, cross products and filters, joining its tables in the order
+    /// that keeps the estimated intermediate results smallest.
+    ///
+    /// The smallest filtered table comes first, then the table whose join with the tables so far
+    /// is estimated to return the fewest rows, among the tables a predicate connects to them
+    /// if there are any. The tree is copied as it is if one of its inputs is not a table with
+    /// statistics.
+    fn copy_joins(&mut self, node: OpIndex) -> Result<OpIndex, CrustyError> {
+        let mut old_inputs = Vec::new();
+        let mut conjuncts = Vec::new();
+        self.flatten(node, &mut old_inputs, &mut conjuncts)?;
+        let mut tables: Vec<(OpIndex, String, f64)> = Vec::new();
+        for &input in &old_inputs {
+            match self.old.get_operator(input) {
+                Some(LogicalOp::Scan(ScanNode { alias }))
+                    if !tables.iter().any(|(_, a, _)| a == alias) =>
+                {
+                    match self.stats.table(alias) {
+                        Some(t) => tables.push((input, alias.clone(), t.cardinality as f64)),
+                        None => return self.copy_node(node),
+                    }
+                }
+                _ => return self.copy_node(node),
+            }
+        }
+
+        // Conjuncts of a single table filter it, the others wait for their tables to be joined
+        let mut pending: Vec<(PredicateExpr, Vec<String>)> = Vec::new();
+        let mut filters: Vec<Vec<PredicateExpr>> = vec![Vec::new(); tables.len()];
+        for conjunct in conjuncts {
+            let refs = referenced_tables(&conjunct);
+            match tables
+                .iter()
+                .position(|(_, alias, _)| refs == [alias.clone()])
+            {
+                Some(i) => filters[i].push(conjunct),
+                None => pending.push((conjunct, refs)),
+            }
+        }
+        let mut inputs = Vec::new();
+        for ((old, alias, mut rows), filter) in tables.into_iter().zip(filters) {
+            let mut node = self.copy_node(old)?;
+            if !filter.is_empty() {
+                let predicate = PredicateExpr::and(filter);
+                rows *= predicate_selectivity(Some(self.stats), &predicate);
+                let op = LogicalOp::Filter(FilterNode {
+                    table: alias.clone(),
+                    predicate,
+                });
+                node = self.add(op, &[node]);
+            }
+            inputs.push(Input { alias, node, rows });
+        }
+
+        let first = (0..inputs.len())
+            .min_by(|&a, &b| inputs[a].rows.total_cmp(&inputs[b].rows))
+            .ok_or_else(malformed)?;
+        let first = inputs.remove(first);
+        let mut joined = vec![first.alias.clone()];
+        let mut current = first.node;
+        let mut current_rows = first.rows;
+        while !inputs.is_empty() {
+            // Conjuncts that can be checked once each input is joined
+            let applicable: Vec<Vec<usize>> = inputs
+                .iter()
+                .map(|input| {
+                    (0..pending.len())
+                        .filter(|&i| {
+                            let refs = &pending[i].1;
+                            refs.contains(&input.alias)
+                                && refs.iter().all(|t| *t == input.alias || joined.contains(t))
+                        })
+                        .collect()
+                })
+                .collect();
+            let connected = applicable.iter().any(|a| !a.is_empty());
+            let mut best: Option<(usize, f64)> = None;
+            for (i, input) in inputs.iter().enumerate() {
+                if connected && applicable[i].is_empty() {
+                    continue;
+                }
+                let selectivity: f64 = applicable[i]
+                    .iter()
+                    .map(|&p| self.selectivity(&pending[p].0))
+                    .product();
+                let rows = current_rows * input.rows * selectivity;
+                if best.map_or(true, |(_, best_rows)| rows < best_rows) {
+                    best = Some((i, rows));
+                }
+            }
+            let (next, rows) = best.ok_or_else(malformed)?;
+            // removed from the back so the positions of the others stay valid
+            let mut checked: Vec<PredicateExpr> = applicable[next]
+                .iter()
+                .rev()
+                .map(|&p| pending.remove(p).0)
+                .collect();
+            checked.reverse();
+            let input = inputs.remove(next);
+            let alias = input.alias.clone();
+            let left_table = match joined.as_slice() {
+                [alias] => Some(alias.clone()),
+                _ => None,
+            };
+            current = self.join(current, input, left_table, &joined, checked);
+            joined.push(alias);
+            current_rows = rows;
+        }
+        // Conjuncts of tables that are not in the tree
+        if !pending.is_empty() {
+            let predicate = PredicateExpr::and(pending.into_iter().map(|(p, _)| p).collect());
+            let op = LogicalOp::Filter(FilterNode {
+                table: joined[0].clone(),
+                predicate,
+            });
+            current = self.add(op, &[current]);
+        }
+        Ok(current)
+    }
+
+    /// Joins an input to the tables joined so far, on one of the conjuncts that connect them,
+    /// an equality if there is one, and filters the result by the others.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - Node of the tables joined so far.
+    /// * `input` - Input to join.
+    /// * `left_table` - Table of `current` if it is a single table.
+    /// * `joined` - Tables joined so far.
+    /// * `conjuncts` - Conjuncts that can be checked once the input is joined.
+    fn join(
+        &mut self,
+        current: OpIndex,
+        input: Input,
+        left_table: Option<String>,
+        joined: &[String],
+        mut conjuncts: Vec<PredicateExpr>,
+    ) -> OpIndex {
+        let join_predicate = |p: &PredicateExpr| match p {
+            PredicateExpr::Compare(PredicateNode {
+                left: PredExpr::Ident(l),
+                op,
+                right: PredExpr::Ident(r),
+            }) if l.expr().is_none() && r.expr().is_none() => {
+                if joined.iter().any(|t| t == l.table()) && r.table() == input.alias {
+                    Some((l.clone(), *op, r.clone()))
+                } else if joined.iter().any(|t| t == r.table()) && l.table() == input.alias {
+                    Some((r.clone(), op.flip(), l.clone()))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        let position = conjuncts
+            .iter()
+            .position(|p| matches!(join_predicate(p), Some((_, PredicateOp::Equals, _))))
+            .or_else(|| conjuncts.iter().position(|p| join_predicate(p).is_some()));
+        let op = match position {
+            Some(i) => {
+                let (left, op, right) = join_predicate(&conjuncts.remove(i)).unwrap();
+                LogicalOp::Join(JoinNode {
+                    left,
+                    right,
+                    op,
+                    left_table,
+                    right_table: Some(input.alias.clone()),
+                    join_type: JoinType::Inner,
+                })
+            }
+            None => LogicalOp::CrossProduct,
+        };
+        let mut node = self.add(op, &[current, input.node]);
+        if !conjuncts.is_empty() {
+            let op = LogicalOp::Filter(FilterNode {
+                table: input.alias,
+                predicate: PredicateExpr::and(conjuncts),
+            });
+            node = self.add(op, &[node]);
+        }
+        node
+    }
+
+    /// Estimates the fraction of the rows, or pairs of rows, that pass a conjunct.
+    fn selectivity(&self, conjunct: &PredicateExpr) -> f64 {
+        let stats = Some(self.stats);
+        match conjunct {
+            PredicateExpr::Compare(PredicateNode {
+                left: PredExpr::Ident(l),
+                op,
+                right: PredExpr::Ident(r),
+            }) => join_selectivity(stats, l, *op, r)
+                .unwrap_or_else(|| predicate_selectivity(stats, conjunct)),
+            _ => predicate_selectivity(stats, conjunct),
+        }
+    }
+}
+
+/// Adds an operator over the given children, left to right, to a plan.
+pub(crate) fn add_node(lp: &mut LogicalPlan, op: LogicalOp, children: &[OpIndex]) -> OpIndex {
+    let node = lp.add_node(op);
+    // The edges of a node are listed last added first
+    for &child in children.iter().rev() {
+        lp.add_edge(node, child);
+    }
+    node
+}
+
+/// Tables scanned under a node, in the order their columns appear in its output.
+pub(crate) fn scans(lp: &LogicalPlan, node: OpIndex) -> Vec<String> {
+    match lp.get_operator(node) {
+        Some(LogicalOp::Scan(ScanNode { alias })) => vec![alias.clone()],
+        _ => lp.edges(node).flat_map(|c| scans(lp, c)).collect(),
+    }
+}
+
+/// Tables whose columns a predicate refers to, in the order they first appear.
+pub(crate) fn referenced_tables(predicate: &PredicateExpr) -> Vec<String> {
+    let mut tables: Vec<String> = Vec::new();
+    for comparison in predicate.comparisons() {
+        for expr in [&comparison.left, &comparison.right].iter() {
+            let ids = match expr.ident().map(|id| (id, id.expr())) {
+                Some((_, Some(computed))) => computed.columns(),
+                Some((id, None)) => vec![id],
+                None => Vec::new(),
+            };
+            for id in ids {
+                if !tables.iter().any(|t| t == id.table()) {
+                    tables.push(id.table().to_string());
+                }
+            }
+        }
+    }
+    tables
+}
+
+pub(crate) fn malformed() -> CrustyError {
+    CrustyError::ExecutionError(String::from("Malformed logical plan"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::*;
+
+    fn optimize(sql: &str, stats: &Statistics) -> Result<String, CrustyError> {
+        let db = test_db();
+        let lp = translate(sql, &db)?;
+        Ok(Optimizer::new().optimize(lp, &db, stats)?.explain(None))
+    }
+
+    #[test]
+    fn test_join_order() -> Result<(), CrustyError> {
+        let stats = test_stats(&[
+            ("t", 1000, &[("a", 100), ("b", 50)]),
+            ("u", 10, &[("c", 10), ("d", 10)]),
+            ("v", 100, &[("e", 100)]),
+        ]);
+        // u is the smallest, and joined with the 20 rows of t with b = 5 it is estimated to
+        // return fewer rows than with v
+        assert_eq!(
+            "Project [t.a, t.b, u.c, u.d, v.e]\n  Join u.d = v.e\n    Join u.c = t.a\n      Scan u\n      Filter t.b = 5\n        Scan t\n    Scan v\n",
+            optimize(
+                "SELECT * FROM t, u, v WHERE t.a = u.c AND u.d = v.e AND t.b = 5",
+                &stats
+            )?
+        );
+        // tables are joined on any predicate between them rather than crossed with a smaller one
+        assert_eq!(
+            "Project [t.b as b]\n  Join t.a = v.e\n    Join u.d < t.b\n      Project [u.d]\n        Scan u\n      Scan t\n    Scan v\n",
+            optimize("SELECT b FROM t, u, v WHERE t.a = v.e AND t.b > u.d", &stats)?
+        );
+        // the join is on the equality, the other predicate filters its result
+        assert_eq!(
+            "Project [u.d as d]\n  Filter t.b < u.d\n    Join u.c = t.a\n      Scan u\n      Scan t\n",
+            optimize("SELECT d FROM t, u WHERE t.b < u.d AND t.a = u.c", &stats)?
+        );
+        // functions aren't joined on, and are checked once all the tables they read are joined
+        assert_eq!(
+            "Project [u.d as d]\n  Filter ABS(t.b - u.d) < 3\n    Join u.c = t.a\n      Scan u\n      Filter ABS(t.b) = 5\n        Scan t\n",
+            optimize(
+                "SELECT d FROM t, u WHERE ABS(t.b - u.d) < 3 AND ABS(t.b) = 5 AND t.a = u.c",
+                &stats
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_plans_left_alone() -> Result<(), CrustyError> {
+        let sql = "SELECT * FROM t, u WHERE t.a = u.c AND t.b = 5";
+        // the filter is still pushed down
+        let written = "Project *\n  Join t.a = u.c\n    Filter t.b = 5\n      Scan t\n    Scan u\n";
+        // without statistics of u
+        let stats = test_stats(&[("t", 1000, &[])]);
+        assert_eq!(written, optimize(sql, &stats)?);
+        assert_eq!(written, optimize(sql, &Statistics::new())?);
+        // outer joins keep their order
+        let stats = test_stats(&[("t", 1000, &[]), ("u", 10, &[])]);
+        assert_eq!(
+            "Project *\n  Left join t.a = u.c\n    Scan t\n    Scan u\n",
+            optimize("SELECT * FROM t LEFT JOIN u ON t.a = u.c", &stats)?
+        );
+        // already in order
+        assert_eq!(
+            "Project *\n  Join u.c = t.a\n    Scan u\n    Filter t.b = 5\n      Scan t\n",
+            optimize("SELECT * FROM u, t WHERE t.a = u.c AND t.b = 5", &stats)?
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,325 @@
+use crate::optimizer::{add_node, malformed, referenced_tables, scans};
+use common::catalog::Catalog;
+use common::logical_plan::*;
+use common::table::Table;
+use common::CrustyError;
+use std::collections::HashSet;
+
+/// Moves the conjuncts of filters down through joins and cross products, to the side that has
+/// all of their columns, so fewer rows are joined.
+///
+/// A conjunct is not moved to the side an outer join pads with NULLs: above the join it
+/// rejects the padded rows, below it would let them through. Filters that end up above each
+/// other are merged, so a filter of a table stays right above its scan, where an index can
+/// serve it.
+pub fn push_down_filters(lp: LogicalPlan) -> Result<LogicalPlan, CrustyError> {
+    let root = match lp.root() {
+        Some(root) => root,
+        None => return Ok(lp),
+    };
+    let mut new = LogicalPlan::new();
+    push_down(&lp, &mut new, root, Vec::new())?;
+    Ok(new)
+}
+
+/// Copies a node and its children to the new plan, filtered by the conjuncts pushed down to it,
+/// and returns its index in the new plan.
+///
+/// # Arguments
+///
+/// * `old` - Plan being rewritten.
+/// * `new` - Rewritten plan.
+/// * `node` - Node of the old plan to copy.
+/// * `pushed` - Conjuncts of the filters above the node that only refer to its columns.
+fn push_down(
+    old: &LogicalPlan,
+    new: &mut LogicalPlan,
+    node: OpIndex,
+    mut pushed: Vec<PredicateExpr>,
+) -> Result<OpIndex, CrustyError> {
+    let op = old.get_operator(node).ok_or_else(malformed)?;
+    let children: Vec<OpIndex> = old.edges(node).collect();
+    let (copy, rest) = match op {
+        LogicalOp::Filter(FilterNode { predicate, .. }) => {
+            let child = *children.first().ok_or_else(malformed)?;
+            let mut conjuncts = match predicate {
+                PredicateExpr::And(preds) => preds.clone(),
+                pred => vec![pred.clone()],
+            };
+            conjuncts.append(&mut pushed);
+            return push_down(old, new, child, conjuncts);
+        }
+        LogicalOp::Join(_) | LogicalOp::CrossProduct => {
+            let (left, right) = match children.as_slice() {
+                [left, right] => (*left, *right),
+                _ => return Err(malformed()),
+            };
+            // A side is padded if the rows of the other side without a match are kept
+            let (left_padded, right_padded) = match op {
+                LogicalOp::Join(JoinNode { join_type, .. }) => {
+                    (join_type.keeps_right(), join_type.keeps_left())
+                }
+                _ => (false, false),
+            };
+            let left_tables = scans(old, left);
+            let right_tables = scans(old, right);
+            let mut to_left = Vec::new();
+            let mut to_right = Vec::new();
+            let mut rest = Vec::new();
+            for conjunct in pushed {
+                let refs = referenced_tables(&conjunct);
+                let within =
+                    |tables: &[String]| !refs.is_empty() && refs.iter().all(|t| tables.contains(t));
+                if !left_padded && within(&left_tables) {
+                    to_left.push(conjunct);
+                } else if !right_padded && within(&right_tables) {
+                    to_right.push(conjunct);
+                } else {
+                    rest.push(conjunct);
+                }
+            }
+            let left = push_down(old, new, left, to_left)?;
+            let right = push_down(old, new, right, to_right)?;
+            (add_node(new, op.clone(), &[left, right]), rest)
+        }
+        _ => {
+            let children = children
+                .iter()
+                .map(|&c| push_down(old, new, c, Vec::new()))
+                .collect::<Result<Vec<OpIndex>, CrustyError>>()?;
+            (add_node(new, op.clone(), &children), pushed)
+        }
+    };
+    if rest.is_empty() {
+        return Ok(copy);
+    }
+    let table = match referenced_tables(&rest[0]).into_iter().next() {
+        Some(table) => table,
+        None => scans(new, copy).into_iter().next().unwrap_or_default(),
+    };
+    let op = LogicalOp::Filter(FilterNode {
+        table,
+        predicate: PredicateExpr::and(rest),
+    });
+    Ok(add_node(new, op, &[copy]))
+}
+
+/// Adds a projection above each table that is joined, crossed or sorted, which drops the
+/// columns no operator of the plan refers to, so the rows those operators hold are smaller.
+///
+/// The projection goes above the filters of the table. Plans that return all columns with `*`
+/// are left as they are.
+pub fn prune_columns<T: Catalog>(lp: LogicalPlan, catalog: &T) -> Result<LogicalPlan, CrustyError> {
+    let root = match lp.root() {
+        Some(root) => root,
+        None => return Ok(lp),
+    };
+    let mut used = HashSet::new();
+    if !used_columns(&lp, root, &mut used)? {
+        return Ok(lp);
+    }
+    let mut new = LogicalPlan::new();
+    prune(&lp, &mut new, root, &used, catalog)?;
+    Ok(new)
+}
+
+/// Collects the columns the operators under a node refer to. Returns false if one of them
+/// returns all columns of its input.
+fn used_columns(
+    lp: &LogicalPlan,
+    node: OpIndex,
+    used: &mut HashSet<String>,
+) -> Result<bool, CrustyError> {
+    let ids: Vec<&FieldIdentifier> = match lp.get_operator(node).ok_or_else(malformed)? {
+        LogicalOp::Project(ProjectNode { identifiers }) => match identifiers {
+            ProjectIdentifiers::Wildcard => return Ok(false),
+            ProjectIdentifiers::List(ids) => ids.iter().collect(),
+        },
+        LogicalOp::Aggregate(AggregateNode { fields, group_by }) => {
+            fields.iter().chain(group_by.iter()).collect()
+        }
+        LogicalOp::Join(JoinNode { left, right, .. }) => vec![left, right],
+        LogicalOp::Filter(FilterNode { predicate, .. }) => predicate
+            .comparisons()
+            .into_iter()
+            .flat_map(|c| c.left.ident().into_iter().chain(c.right.ident()))
+            .collect(),
+        LogicalOp::Sort(SortNode { fields }) => fields.iter().map(|f| &f.field).collect(),
+        _ => Vec::new(),
+    };
+    used.extend(
+        ids.into_iter()
+            .flat_map(|id| id.source_columns())
+            .map(String::from),
+    );
+    for child in lp.edges(node) {
+        if !used_columns(lp, child, used)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Copies a node and its children to the new plan, projecting the tables it joins, crosses or
+/// sorts to the used columns, and returns its index in the new plan.
+fn prune<T: Catalog>(
+    old: &LogicalPlan,
+    new: &mut LogicalPlan,
+    node: OpIndex,
+    used: &HashSet<String>,
+    catalog: &T,
+) -> Result<OpIndex, CrustyError> {
+    let op = old.get_operator(node).ok_or_else(malformed)?;
+    let holds_rows = matches!(
+        op,
+        LogicalOp::Join(_) | LogicalOp::CrossProduct | LogicalOp::Sort(_)
+    );
+    let mut children = Vec::new();
+    for child in old.edges(node).collect::<Vec<OpIndex>>() {
+        let mut copy = prune(old, new, child, used, catalog)?;
+        if let Some(alias) = scanned_table(old, child).filter(|_| holds_rows) {
+            let table = catalog.get_table_ptr(Table::get_table_id(&alias))?;
+            let columns: Vec<String> = table
+                .read()
+                .unwrap()
+                .schema
+                .attributes()
+                .map(|attr| format!("{}.{}", alias, attr.name()))
+                .collect();
+            let mut kept: Vec<&String> = columns.iter().filter(|c| used.contains(*c)).collect();
+            // A row keeps a column even if none is used, so it still counts in a cross product
+            if kept.is_empty() {
+                kept.extend(columns.first());
+            }
+            if kept.len() < columns.len() {
+                let identifiers = kept
+                    .into_iter()
+                    .map(|column| FieldIdentifier::new(&alias, column))
+                    .collect();
+                let op = LogicalOp::Project(ProjectNode {
+                    identifiers: ProjectIdentifiers::List(identifiers),
+                });
+                copy = add_node(new, op, &[copy]);
+            }
+        }
+        children.push(copy);
+    }
+    Ok(add_node(new, op.clone(), &children))
+}
+
+/// Table a node reads, through the filters above its scan, None if the node is not a scan or
+/// a filter of one.
+fn scanned_table(lp: &LogicalPlan, node: OpIndex) -> Option<String> {
+    match lp.get_operator(node)? {
+        LogicalOp::Scan(ScanNode { alias }) => Some(alias.clone()),
+        LogicalOp::Filter(_) => scanned_table(lp, lp.edges(node).next()?),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::*;
+
+    fn push_down(sql: &str) -> Result<String, CrustyError> {
+        let lp = translate(sql, &test_db())?;
+        Ok(push_down_filters(lp)?.explain(None))
+    }
+
+    fn prune(sql: &str) -> Result<String, CrustyError> {
+        let db = test_db();
+        let lp = push_down_filters(translate(sql, &db)?)?;
+        Ok(prune_columns(lp, &db)?.explain(None))
+    }
+
+    #[test]
+    fn test_push_down_filters() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project *\n  Join t.a = u.c\n    Filter t.b = 5\n      Scan t\n    Filter u.d > 2\n      Scan u\n",
+            push_down("SELECT * FROM t, u WHERE t.a = u.c AND t.b = 5 AND u.d > 2")?
+        );
+        // through both joins
+        assert_eq!(
+            "Project *\n  Join t.a = v.e\n    Join t.b < u.d\n      Filter t.b = 5\n        Scan t\n      Scan u\n    Filter v.e > 1\n      Scan v\n",
+            push_down("SELECT * FROM t, u, v WHERE t.a = v.e AND t.b < u.d AND t.b = 5 AND v.e > 1")?
+        );
+        // a disjunction of two tables stays above their join
+        assert_eq!(
+            "Project *\n  Filter t.b = 5 OR u.d = 1\n    Join t.a = u.c\n      Scan t\n      Scan u\n",
+            push_down("SELECT * FROM t, u WHERE t.a = u.c AND (t.b = 5 OR u.d = 1)")?
+        );
+        // HAVING stays above the aggregate
+        assert_eq!(
+            "Project [a, count_t.b]\n  Filter count_t.b > 1\n    Aggregate [t.a as a, count(t.b) as count_t.b] group by [t.a as a]\n      Filter t.a = 1\n        Scan t\n",
+            push_down("SELECT a, COUNT(b) FROM t WHERE a = 1 GROUP BY a HAVING COUNT(b) > 1")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_down_outer_joins() -> Result<(), CrustyError> {
+        // only to the side whose rows are kept
+        assert_eq!(
+            "Project *\n  Filter u.d IS NULL\n    Left join t.a = u.c\n      Filter t.b = 5\n        Scan t\n      Scan u\n",
+            push_down("SELECT * FROM t LEFT JOIN u ON t.a = u.c WHERE t.b = 5 AND u.d IS NULL")?
+        );
+        assert_eq!(
+            "Project *\n  Filter t.b = 5\n    Right join t.a = u.c\n      Scan t\n      Filter u.d = 1\n        Scan u\n",
+            push_down("SELECT * FROM t RIGHT JOIN u ON t.a = u.c WHERE t.b = 5 AND u.d = 1")?
+        );
+        assert_eq!(
+            "Project *\n  Filter t.b = 5 AND u.d = 1\n    Full join t.a = u.c\n      Scan t\n      Scan u\n",
+            push_down("SELECT * FROM t FULL JOIN u ON t.a = u.c WHERE t.b = 5 AND u.d = 1")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_columns() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project [t.a as a]\n  Join t.a = u.c\n    Project [t.a]\n      Scan t\n    Project [u.c]\n      Scan u\n",
+            prune("SELECT a FROM t, u WHERE t.a = u.c")?
+        );
+        // above the filter, which uses its own columns
+        assert_eq!(
+            "Project [u.d as d]\n  Join t.a = u.d\n    Project [t.a]\n      Filter t.a > 1\n        Scan t\n    Project [u.d]\n      Scan u\n",
+            prune("SELECT d FROM t, u WHERE t.a = u.d AND t.a > 1")?
+        );
+        // a table none of whose columns are used keeps one
+        assert_eq!(
+            "Project [t.b as b]\n  CrossProduct\n    Project [t.b]\n      Scan t\n    Project [u.c]\n      Scan u\n",
+            prune("SELECT b FROM t, u")?
+        );
+        assert_eq!(
+            "Project [t.a as a]\n  Sort [t.a as a]\n    Project [t.a]\n      Scan t\n",
+            prune("SELECT a FROM t ORDER BY a")?
+        );
+        // the columns of a computed field are kept
+        assert_eq!(
+            "Project [u.d - 1 as d - 1]\n  Join t.a = u.c\n    Project [t.a]\n      Scan t\n    Scan u\n",
+            prune("SELECT d - 1 FROM t, u WHERE t.a = u.c")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_columns_left_alone() -> Result<(), CrustyError> {
+        // all columns are returned
+        assert_eq!(
+            "Project *\n  Join t.a = u.c\n    Scan t\n    Scan u\n",
+            prune("SELECT * FROM t, u WHERE t.a = u.c")?
+        );
+        // all columns are used
+        assert_eq!(
+            "Project [t.b as b, u.d as d]\n  Join t.a = u.c\n    Scan t\n    Scan u\n",
+            prune("SELECT b, d FROM t, u WHERE t.a = u.c")?
+        );
+        // not under a join or sort
+        assert_eq!(
+            "Project [t.a as a]\n  Filter t.b = 1\n    Scan t\n",
+            prune("SELECT a FROM t WHERE b = 1")?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use common::database::Database;
+use common::logical_plan::LogicalPlan;
+use common::statistics::{ColumnStats, Statistics, TableStats};
+use common::table::Table;
+use common::{Attribute, CrustyError, DataType, TableSchema};
+use queryexe::query::TranslateAndValidate;
+use sqlparser::ast::Statement;
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+use std::sync::{Arc, RwLock};
+
+/// Tables t(a, b), u(c, d) and v(e).
+pub fn test_db() -> Database {
+    let db = Database::new(String::from("test"));
+    for (name, columns) in &[
+        ("t", vec!["a", "b"]),
+        ("u", vec!["c", "d"]),
+        ("v", vec!["e"]),
+    ] {
+        let attrs = columns
+            .iter()
+            .map(|c| Attribute::new(c.to_string(), DataType::Int))
+            .collect();
+        let table = Table::new(name.to_string(), TableSchema::new(attrs));
+        db.tables
+            .write()
+            .unwrap()
+            .insert(table.id, Arc::new(RwLock::new(table)));
+    }
+    db
+}
+
+/// Statistics of the tables, with the given row counts and distinct values per column.
+pub fn test_stats(tables: &[(&str, usize, &[(&str, usize)])]) -> Statistics {
+    let mut stats = Statistics::new();
+    for (name, rows, columns) in tables {
+        let mut table = TableStats::new(*rows);
+        for (column, distinct_count) in columns.iter() {
+            let column_stats = ColumnStats {
+                distinct_count: *distinct_count,
+                min: None,
+                max: None,
+            };
+            table.columns.insert(column.to_string(), column_stats);
+        }
+        stats.set_table(name, table);
+    }
+    stats
+}
+
+/// Translates a query on the tables of the database to a logical plan.
+pub fn translate(sql: &str, db: &Database) -> Result<LogicalPlan, CrustyError> {
+    let statements = Parser::parse_sql(&GenericDialect {}, sql.to_string()).unwrap();
+    match &statements[0] {
+        Statement::Query(query) => TranslateAndValidate::from_sql(query, db),
+        _ => panic!("Not a query"),
+    }
+}
@@ -0,0 +1,66 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use common::testutil::{get_int_table_schema, int_vec_to_tuple};
+use common::{Field, Tuple};
+use queryexe::opiterator::{OpIterator, ProjectIterator, TupleIterator};
+
+const WIDTH: usize = 64;
+const ROWS: usize = 5_000;
+
+/// Wide tuples of int fields.
+fn wide_tuples() -> Vec<Tuple> {
+    (0..ROWS)
+        .map(|r| int_vec_to_tuple((0..WIDTH as i32).map(|c| r as i32 + c).collect()))
+        .collect()
+}
+
+/// Runs a projection over the tuples and drains it.
+fn run_project(tuples: &[Tuple], fields: &[usize]) -> usize {
+    let child = TupleIterator::new(tuples.to_vec(), get_int_table_schema(WIDTH));
+    let mut project = ProjectIterator::new(fields.to_vec(), Box::new(child));
+    project.open().unwrap();
+    let mut count = 0;
+    while let Some(t) = project.next().unwrap() {
+        count += t.size();
+    }
+    count
+}
+
+/// The old per-field projection: look up and clone every field of every tuple.
+fn run_per_field(tuples: &[Tuple], fields: &[usize]) -> usize {
+    let mut child = TupleIterator::new(tuples.to_vec(), get_int_table_schema(WIDTH));
+    child.open().unwrap();
+    let mut count = 0;
+    while let Some(t) = child.next().unwrap() {
+        let vals: Vec<Field> = fields
+            .iter()
+            .map(|i| t.get_field(*i).unwrap().clone())
+            .collect();
+        count += Tuple::new(vals).size();
+    }
+    count
+}
+
+pub fn project_benchmark(c: &mut Criterion) {
+    let tuples = wide_tuples();
+    let all: Vec<usize> = (0..WIDTH).collect();
+    let half: Vec<usize> = (0..WIDTH).rev().step_by(2).collect();
+
+    let mut group = c.benchmark_group("wide project");
+    group.bench_function("pass-through compiled", |b| {
+        b.iter(|| run_project(black_box(&tuples), &all))
+    });
+    group.bench_function("pass-through per-field", |b| {
+        b.iter(|| run_per_field(black_box(&tuples), &all))
+    });
+    group.bench_function("half reversed compiled", |b| {
+        b.iter(|| run_project(black_box(&tuples), &half))
+    });
+    group.bench_function("half reversed per-field", |b| {
+        b.iter(|| run_per_field(black_box(&tuples), &half))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, project_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,73 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::Arc;
+
+use common::ids::TransactionId;
+use common::storage_trait::StorageTrait;
+use common::testutil::{gen_random_dir, int_vec_to_tuple};
+use common::Tuple;
+use queryexe::memory_budget::MemoryBudget;
+use queryexe::opiterator::{ExternalSort, SortConfig, SortKey};
+use queryexe::StorageManager;
+
+const ROWS: usize = 50_000;
+
+/// Two int fields in pseudo-random order.
+fn shuffled_tuples() -> Vec<Tuple> {
+    (0..ROWS as i32)
+        .map(|i| int_vec_to_tuple(vec![(i * 7919) % ROWS as i32, i]))
+        .collect()
+}
+
+/// Sorts the tuples on the first field and drains the result.
+fn run_sort(sm: &Arc<StorageManager>, tuples: &[Tuple], config: SortConfig) -> usize {
+    let mut sort = ExternalSort::new(
+        vec![SortKey::new(0, true)],
+        config,
+        Arc::new(MemoryBudget::unbounded()),
+        sm.clone(),
+        TransactionId::new(),
+    );
+    for t in tuples {
+        sort.insert(t.clone()).unwrap();
+    }
+    let mut sorted = sort.finish().unwrap();
+    let mut count = 0;
+    while sorted.next().unwrap().is_some() {
+        count += 1;
+    }
+    count
+}
+
+pub fn sort_benchmark(c: &mut Criterion) {
+    let tuples = shuffled_tuples();
+    let sm = Arc::new(StorageManager::new(
+        gen_random_dir().to_str().unwrap().to_string(),
+    ));
+
+    let mut group = c.benchmark_group("external sort run size");
+    for run_size in &[1_000, 5_000, 25_000, ROWS] {
+        let config = SortConfig {
+            run_size: *run_size,
+            fan_in: 64,
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(run_size), &config, |b, cfg| {
+            b.iter(|| run_sort(&sm, black_box(&tuples), *cfg))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("external sort fan-in");
+    for fan_in in &[2, 4, 16, 64] {
+        let config = SortConfig {
+            run_size: 1_000,
+            fan_in: *fan_in,
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(fan_in), &config, |b, cfg| {
+            b.iter(|| run_sort(&sm, black_box(&tuples), *cfg))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sort_benchmark);
+criterion_main!(benches);
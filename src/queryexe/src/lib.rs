@@ -1,4 +1,13 @@
+#[macro_use]
+extern crate log;
+
+pub mod memory_budget;
 pub mod opiterator;
 pub mod query;
+/// Storage manager the operators read and spill through, the heapstore one if the `heapstore`
+/// feature is enabled.
+#[cfg(feature = "heapstore")]
+pub use heapstore::storage_manager::StorageManager;
+#[cfg(not(feature = "heapstore"))]
 pub use memstore::storage_manager::StorageManager;
 
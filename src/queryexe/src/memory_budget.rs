@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tracks how much memory operators of a query may hold at once.
+///
+/// Operators reserve bytes before growing an in-memory structure and release them when the
+/// structure is dropped or spilled. A failed reservation is the operator's cue to spill.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    /// Maximum number of bytes that can be reserved.
+    limit: usize,
+    /// Bytes currently reserved.
+    used: AtomicUsize,
+    /// Most bytes reserved at once.
+    peak: AtomicUsize,
+}
+
+impl MemoryBudget {
+    /// Creates a budget that allows `limit` bytes to be reserved at once.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    /// Creates a budget that never refuses a reservation.
+    pub fn unbounded() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    /// Tries to reserve `bytes`. Returns false, reserving nothing, if the budget would be exceeded.
+    pub fn try_reserve(&self, bytes: usize) -> bool {
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let next = match used.checked_add(bytes) {
+                Some(next) if next <= self.limit => next,
+                _ => return false,
+            };
+            match self
+                .used
+                .compare_exchange_weak(used, next, Ordering::AcqRel, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    self.peak.fetch_max(next, Ordering::Relaxed);
+                    return true;
+                }
+                Err(actual) => used = actual,
+            }
+        }
+    }
+
+    /// Returns `bytes` previously reserved to the budget.
+    pub fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+
+    /// Number of bytes currently reserved.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Most bytes reserved at once since the budget was created.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Maximum number of bytes that can be reserved.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reserve_release() {
+        let budget = MemoryBudget::new(100);
+        assert!(budget.try_reserve(60));
+        assert!(!budget.try_reserve(41));
+        assert_eq!(60, budget.used());
+        assert!(budget.try_reserve(40));
+        budget.release(100);
+        assert_eq!(0, budget.used());
+        assert_eq!(100, budget.peak());
+        assert!(MemoryBudget::unbounded().try_reserve(usize::MAX));
+    }
+}
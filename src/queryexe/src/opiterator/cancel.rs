@@ -0,0 +1,220 @@
+use super::OpIterator;
+use common::{CrustyError, TableSchema, Tuple};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Flag another thread sets to stop the query of a client.
+///
+/// Clones share the flag, so the token given to a plan can be cancelled through the one the
+/// server keeps for the client's session.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the query holding the token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears a cancellation, e.g. before the next statement of the client starts.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns true if the query was asked to stop.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails if the query was asked to stop.
+    pub fn check(&self) -> Result<(), CrustyError> {
+        if self.is_cancelled() {
+            Err(CrustyError::ExecutionError(String::from("Query cancelled")))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CancelToken({})", self.is_cancelled())
+    }
+}
+
+/// Time by which a query must be done.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueryDeadline {
+    /// When the query is stopped.
+    at: Instant,
+    /// Time the query was given, for the error message.
+    limit: Duration,
+}
+
+impl QueryDeadline {
+    /// Deadline of a query that starts now.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - Time the query may run.
+    pub fn after(limit: Duration) -> Self {
+        Self {
+            at: Instant::now() + limit,
+            limit,
+        }
+    }
+
+    /// Fails if the deadline passed.
+    pub fn check(&self) -> Result<(), CrustyError> {
+        if Instant::now() >= self.at {
+            Err(CrustyError::ExecutionError(format!(
+                "Query exceeded max_query_time of {} ms and was aborted",
+                self.limit.as_millis()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Stops a query once its cancel token is set or its deadline passed.
+///
+/// Wraps the scans of a plan, which every operator reads its tuples from, so a sort or an
+/// aggregate still consuming its input stops as soon as it asks for the next tuple.
+pub struct Cancellable {
+    /// Token checked before every tuple.
+    token: CancelToken,
+    /// Deadline checked before every tuple, if the query has one.
+    deadline: Option<QueryDeadline>,
+    /// Schema of the child.
+    schema: TableSchema,
+    /// Child operator passing data into operator.
+    child: Box<dyn OpIterator>,
+}
+
+impl Cancellable {
+    /// Cancellable constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Token that stops the child once it is set.
+    /// * `child` - OpIterator to stop.
+    pub fn new(token: CancelToken, child: Box<dyn OpIterator>) -> Self {
+        Self {
+            token,
+            deadline: None,
+            schema: child.get_schema().clone(),
+            child,
+        }
+    }
+
+    /// Also stops the child once a deadline passed.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - Deadline of the query, None for no deadline.
+    pub fn with_deadline(mut self, deadline: Option<QueryDeadline>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Fails if the query was cancelled or ran out of time.
+    fn check(&self) -> Result<(), CrustyError> {
+        self.token.check()?;
+        match &self.deadline {
+            Some(deadline) => deadline.check(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl OpIterator for Cancellable {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.check()?;
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        self.check()?;
+        self.child.next()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Vec<Tuple>>, CrustyError> {
+        self.check()?;
+        self.child.next_batch()
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.check()?;
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TupleIterator;
+    use super::*;
+    use common::testutil::*;
+
+    #[test]
+    fn test_cancel() -> Result<(), CrustyError> {
+        let rows = (0..10).map(|i| vec![i]).collect();
+        let child = TupleIterator::new(create_tuple_list(rows), get_int_table_schema(1));
+        let token = CancelToken::new();
+        let mut op = Cancellable::new(token.clone(), Box::new(child));
+        op.open()?;
+        assert!(op.next()?.is_some());
+        token.cancel();
+        match op.next() {
+            Err(CrustyError::ExecutionError(msg)) => assert_eq!("Query cancelled", msg),
+            other => panic!("Expected an execution error, got {:?}", other),
+        }
+        // the token is shared by its clones until it is reset
+        assert!(token.clone().is_cancelled());
+        token.reset();
+        assert!(op.next()?.is_some());
+        op.close()
+    }
+
+    #[test]
+    fn test_deadline() -> Result<(), CrustyError> {
+        let rows = (0..10).map(|i| vec![i]).collect();
+        let child = TupleIterator::new(create_tuple_list(rows), get_int_table_schema(1));
+        let deadline = QueryDeadline::after(Duration::from_millis(20));
+        let mut op =
+            Cancellable::new(CancelToken::new(), Box::new(child)).with_deadline(Some(deadline));
+        op.open()?;
+        assert!(op.next()?.is_some());
+        std::thread::sleep(Duration::from_millis(30));
+        match op.next() {
+            Err(CrustyError::ExecutionError(msg)) => assert_eq!(
+                "Query exceeded max_query_time of 20 ms and was aborted",
+                msg
+            ),
+            other => panic!("Expected an execution error, got {:?}", other),
+        }
+        op.close()
+    }
+}
@@ -0,0 +1,164 @@
+use super::{close_both, OpIterator};
+use common::{CrustyError, TableSchema, Tuple};
+
+/// Nested loops cross product, for FROM lists without a join predicate.
+///
+/// Every tuple of the left child is combined with every tuple of the right child, which is
+/// rewound once per left tuple.
+pub struct CrossJoinIterator {
+    /// Left tuple being combined with the right child.
+    left_tuple: Option<Tuple>,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Left child, read once.
+    left_child: Box<dyn OpIterator>,
+    /// Right child, read once per left tuple. Holds its pages so they aren't read again.
+    right_child: Box<dyn OpIterator>,
+}
+
+impl CrossJoinIterator {
+    /// CrossJoinIterator constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_child` - Left child of the cross product.
+    /// * `right_child` - Right child of the cross product.
+    pub fn new(left_child: Box<dyn OpIterator>, mut right_child: Box<dyn OpIterator>) -> Self {
+        right_child.hold_pages();
+        Self {
+            left_tuple: None,
+            schema: left_child.get_schema().merge(right_child.get_schema()),
+            open: false,
+            left_child,
+            right_child,
+        }
+    }
+}
+
+impl OpIterator for CrossJoinIterator {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.left_child.open()?;
+        self.right_child.open()?;
+        self.left_tuple = None;
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        loop {
+            if self.left_tuple.is_none() {
+                self.left_tuple = self.left_child.next()?;
+                if self.left_tuple.is_none() {
+                    return Ok(None);
+                }
+            }
+            match self.right_child.next()? {
+                Some(right) => return Ok(Some(self.left_tuple.as_ref().unwrap().merge(&right))),
+                None => {
+                    self.right_child.rewind()?;
+                    self.left_tuple = None;
+                }
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.left_tuple = None;
+        self.open = false;
+        close_both(self.left_child.as_mut(), self.right_child.as_mut())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left_child.rewind()?;
+        self.right_child.rewind()?;
+        self.left_tuple = None;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::testutil::HoldSpy;
+    use super::super::TupleIterator;
+    use super::*;
+    use common::testutil::*;
+
+    fn get_cross(left: Vec<Vec<i32>>, right: Vec<Vec<i32>>) -> CrossJoinIterator {
+        let left_width = left.first().map_or(1, |r| r.len());
+        let right_width = right.first().map_or(1, |r| r.len());
+        let left = TupleIterator::new(create_tuple_list(left), get_int_table_schema(left_width));
+        let right = TupleIterator::new(create_tuple_list(right), get_int_table_schema(right_width));
+        CrossJoinIterator::new(Box::new(left), Box::new(right))
+    }
+
+    fn collect(cross: &mut CrossJoinIterator) -> Result<Vec<Vec<i32>>, CrustyError> {
+        let mut rows = Vec::new();
+        while let Some(t) = cross.next()? {
+            rows.push(t.field_vals().map(|f| f.as_int().unwrap()).collect());
+        }
+        Ok(rows)
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let mut cross = get_cross(vec![vec![1]], vec![vec![2]]);
+        cross.next().unwrap();
+    }
+
+    #[test]
+    fn test_cross() -> Result<(), CrustyError> {
+        let mut cross = get_cross(
+            vec![vec![1], vec![2]],
+            vec![vec![3, 4], vec![5, 6], vec![7, 8]],
+        );
+        assert_eq!(3, cross.get_schema().size());
+        cross.open()?;
+        let expected = vec![
+            vec![1, 3, 4],
+            vec![1, 5, 6],
+            vec![1, 7, 8],
+            vec![2, 3, 4],
+            vec![2, 5, 6],
+            vec![2, 7, 8],
+        ];
+        assert_eq!(expected, collect(&mut cross)?);
+        cross.rewind()?;
+        assert_eq!(expected, collect(&mut cross)?);
+        cross.close()
+    }
+
+    #[test]
+    fn test_holds_right_pages() {
+        let left = TupleIterator::new(create_tuple_list(vec![vec![1]]), get_int_table_schema(1));
+        let right = TupleIterator::new(create_tuple_list(vec![vec![2]]), get_int_table_schema(1));
+        let (left, left_held) = HoldSpy::new(Box::new(left));
+        let (right, right_held) = HoldSpy::new(Box::new(right));
+        CrossJoinIterator::new(Box::new(left), Box::new(right));
+        assert!(!left_held.get());
+        assert!(right_held.get());
+    }
+
+    #[test]
+    fn test_empty_side() -> Result<(), CrustyError> {
+        let mut cross = get_cross(vec![vec![1], vec![2]], vec![]);
+        cross.open()?;
+        assert!(cross.next()?.is_none());
+        let mut cross = get_cross(vec![], vec![vec![1]]);
+        cross.open()?;
+        assert!(cross.next()?.is_none());
+        Ok(())
+    }
+}
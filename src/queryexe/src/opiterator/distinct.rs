@@ -0,0 +1,245 @@
+use super::{ExternalSort, OpIterator, SortKey, SortOptions, SortedTuples};
+use crate::memory_budget::MemoryBudget;
+use crate::StorageManager;
+use common::ids::TransactionId;
+use common::{CrustyError, Field, TableSchema, Tuple};
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// Distinct operator, for SELECT DISTINCT.
+///
+/// Tuples are returned as they are read, the first time their values are seen, remembering the
+/// values in a hash set. If the set outgrows half of the memory limit, the rest of the input is
+/// sorted on all fields with the other half, and the sorted tuples are deduplicated by
+/// comparing neighbours and skipping those already returned. The output is in input order
+/// until the fallback, so a sort of the result has to go above this operator.
+pub struct DistinctIterator {
+    options: SortOptions,
+    storage_manager: Arc<StorageManager>,
+    /// Transaction the runs of the fallback sort are written with.
+    tid: TransactionId,
+    /// Values of the tuples returned before the fallback.
+    seen: HashSet<Vec<Field>>,
+    /// Budget `seen` is accounted against.
+    budget: Arc<MemoryBudget>,
+    /// Bytes reserved from the budget for `seen`.
+    reserved: usize,
+    /// Rest of the input in sorted order, once the hash set ran out of memory.
+    sorted: Option<SortedTuples>,
+    /// Values of the last tuple read from `sorted`.
+    last: Option<Vec<Field>>,
+    /// Schema of the child.
+    schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Child operator passing data into operator.
+    child: Box<dyn OpIterator>,
+}
+
+impl DistinctIterator {
+    /// Distinct constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Memory limit of the operator and merging of its fallback sort.
+    /// * `storage_manager` - Storage manager the fallback sort spills to.
+    /// * `tid` - Transaction the runs of the fallback sort are written with.
+    /// * `child` - Child OpIterator passing data into the operator.
+    pub fn new(
+        options: SortOptions,
+        storage_manager: Arc<StorageManager>,
+        tid: TransactionId,
+        child: Box<dyn OpIterator>,
+    ) -> Self {
+        Self {
+            options,
+            storage_manager,
+            tid,
+            seen: HashSet::new(),
+            budget: Arc::new(MemoryBudget::new(options.memory_limit / 2)),
+            reserved: 0,
+            sorted: None,
+            last: None,
+            schema: child.get_schema().clone(),
+            open: false,
+            child,
+        }
+    }
+
+    /// Approximate number of bytes the values of a tuple occupy in the hash set.
+    fn footprint(values: &[Field]) -> usize {
+        size_of::<Vec<Field>>()
+            + values
+                .iter()
+                .map(|f| match f {
+                    Field::Null | Field::IntField(_) => size_of::<Field>(),
+                    Field::StringField(s) => size_of::<Field>() + s.capacity(),
+                })
+                .sum::<usize>()
+    }
+
+    /// Sorts the rest of the input, starting with the tuple that did not fit in the hash set.
+    fn fall_back(&mut self, first: Tuple) -> Result<SortedTuples, CrustyError> {
+        let keys = (0..self.schema.size())
+            .map(|i| SortKey::new(i, true))
+            .collect();
+        let limit = self.options.memory_limit - self.options.memory_limit / 2;
+        let mut sort = ExternalSort::new(
+            keys,
+            self.options.config,
+            Arc::new(MemoryBudget::new(limit)),
+            self.storage_manager.clone(),
+            self.tid,
+        );
+        sort.insert(first)?;
+        while let Some(t) = self.child.next()? {
+            sort.insert(t)?;
+        }
+        debug!(
+            "Distinct fell back to sorting after {} distinct tuples, spilled {} runs",
+            self.seen.len(),
+            sort.num_runs()
+        );
+        sort.finish()
+    }
+
+    /// Forgets the tuples returned so far and releases their memory.
+    fn reset(&mut self) {
+        self.seen.clear();
+        self.budget.release(self.reserved);
+        self.reserved = 0;
+        self.sorted = None;
+        self.last = None;
+    }
+}
+
+impl OpIterator for DistinctIterator {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.reset();
+        self.open = true;
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        if self.sorted.is_none() {
+            while let Some(t) = self.child.next()? {
+                if self.seen.contains(&t.field_vals) {
+                    continue;
+                }
+                let bytes = Self::footprint(&t.field_vals);
+                if !self.budget.try_reserve(bytes) {
+                    self.sorted = Some(self.fall_back(t)?);
+                    break;
+                }
+                self.reserved += bytes;
+                self.seen.insert(t.field_vals.clone());
+                return Ok(Some(t));
+            }
+        }
+        if let Some(sorted) = self.sorted.as_mut() {
+            while let Some(t) = sorted.next()? {
+                if self.last.as_ref() == Some(&t.field_vals) || self.seen.contains(&t.field_vals) {
+                    continue;
+                }
+                self.last = Some(t.field_vals.clone());
+                return Ok(Some(t));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.reset();
+        self.open = false;
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.reset();
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{SortConfig, TupleIterator};
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+
+    /// Rows (i % m, i % m % 3) for i in 0..n in reverse, with m distinct rows.
+    fn get_distinct(n: i32, m: i32, memory_limit: usize) -> DistinctIterator {
+        let rows = (0..n).rev().map(|i| vec![i % m, i % m % 3]).collect();
+        let child = TupleIterator::new(create_tuple_list(rows), get_int_table_schema(2));
+        let sm = Arc::new(StorageManager::new(
+            gen_random_dir().to_str().unwrap().to_string(),
+        ));
+        let options = SortOptions {
+            memory_limit,
+            config: SortConfig {
+                run_size: 10_000,
+                fan_in: 4,
+            },
+        };
+        DistinctIterator::new(options, sm, TransactionId::new(), Box::new(child))
+    }
+
+    fn collect(distinct: &mut DistinctIterator) -> Result<Vec<(i32, i32)>, CrustyError> {
+        let mut rows = Vec::new();
+        while let Some(t) = distinct.next()? {
+            rows.push((t.field(0)?.as_int()?, t.field(1)?.as_int()?));
+        }
+        Ok(rows)
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let mut distinct = get_distinct(10, 5, 1 << 20);
+        distinct.next().unwrap();
+    }
+
+    #[test]
+    fn test_in_memory() -> Result<(), CrustyError> {
+        // the distinct rows are returned in input order
+        let mut distinct = get_distinct(100, 6, 1 << 20);
+        distinct.open()?;
+        let expected: Vec<(i32, i32)> = [3, 2, 1, 0, 5, 4].iter().map(|&i| (i, i % 3)).collect();
+        assert_eq!(expected, collect(&mut distinct)?);
+        distinct.rewind()?;
+        assert_eq!(expected, collect(&mut distinct)?);
+        distinct.close()
+    }
+
+    #[test]
+    fn test_sort_fallback() -> Result<(), CrustyError> {
+        let row = DistinctIterator::footprint(&[Field::IntField(0), Field::IntField(0)]);
+        let mut distinct = get_distinct(3000, 500, 2 * 50 * row);
+        distinct.open()?;
+        let mut rows = collect(&mut distinct)?;
+        assert_eq!(500, rows.len());
+        // the first 50 are returned before falling back to sorting
+        assert_eq!((499, 1), rows[0]);
+        assert_eq!((450, 0), rows[49]);
+        // then the rest in sorted order
+        assert_eq!((0, 0), rows[50]);
+        assert_eq!((449, 2), rows[499]);
+        rows.sort_unstable();
+        rows.dedup();
+        assert_eq!(500, rows.len());
+        distinct.close()?;
+        assert!(distinct.storage_manager.list_containers().is_empty());
+        Ok(())
+    }
+}
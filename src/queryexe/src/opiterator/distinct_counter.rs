@@ -0,0 +1,322 @@
+use crate::memory_budget::MemoryBudget;
+use common::{CrustyError, Field};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Counts the distinct values of a column, e.g. for `COUNT(DISTINCT x)`.
+///
+/// Values are deduplicated in a hash set whose memory is reserved from a `MemoryBudget`.
+/// When the budget runs out the set is sorted and spilled to disk as a run; `count` then
+/// merges the runs to count each value once.
+///
+/// Several counters can share a budget, e.g. one per group of an aggregate. So that a counter
+/// can make progress when the others hold the rest of the budget, its owner reserves
+/// `MIN_RESERVATION` bytes for it up front and hands them over with `with_reserved`; the
+/// counter keeps them across spills and releases them when it is dropped.
+pub struct DistinctCounter {
+    /// Values seen since the last spill.
+    seen: HashSet<Field>,
+    /// Bytes of the values in `seen`.
+    used: usize,
+    /// Bytes reserved from the budget, at least `used`.
+    reserved: usize,
+    /// Bytes reserved by the owner, kept when the values are spilled.
+    floor: usize,
+    budget: Arc<MemoryBudget>,
+    /// Directory the runs are written to. Removed when the counter is dropped.
+    spill_dir: PathBuf,
+    /// Sorted runs spilled so far.
+    runs: Vec<PathBuf>,
+}
+
+/// Bytes the owner of a counter sharing a budget reserves for it, enough for a few values.
+pub const MIN_RESERVATION: usize = 16 * size_of::<Field>();
+
+impl DistinctCounter {
+    /// Creates a new counter.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - Memory budget the hash set is accounted against.
+    /// * `spill_dir` - Directory for spilled runs; created on the first spill.
+    pub fn new(budget: Arc<MemoryBudget>, spill_dir: PathBuf) -> Self {
+        Self::with_reserved(budget, spill_dir, 0)
+    }
+
+    /// Creates a new counter that takes over bytes already reserved from the budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - Memory budget the hash set is accounted against.
+    /// * `spill_dir` - Directory for spilled runs; created on the first spill.
+    /// * `reserved` - Bytes reserved from `budget` by the caller, released when the counter is
+    ///   dropped. Usually `MIN_RESERVATION`.
+    pub fn with_reserved(budget: Arc<MemoryBudget>, spill_dir: PathBuf, reserved: usize) -> Self {
+        Self {
+            seen: HashSet::new(),
+            used: 0,
+            reserved,
+            floor: reserved,
+            budget,
+            spill_dir,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Approximate number of bytes a field occupies in the hash set.
+    fn footprint(field: &Field) -> usize {
+        match field {
+            Field::Null | Field::IntField(_) => size_of::<Field>(),
+            Field::StringField(s) => size_of::<Field>() + s.capacity(),
+        }
+    }
+
+    /// Reserves room for `bytes` more, beyond what is already reserved if need be.
+    fn make_room(&mut self, bytes: usize) -> bool {
+        let missing = (self.used + bytes).saturating_sub(self.reserved);
+        if missing == 0 {
+            return true;
+        }
+        if !self.budget.try_reserve(missing) {
+            return false;
+        }
+        self.reserved += missing;
+        true
+    }
+
+    /// Adds a value, spilling the values in memory if the budget is exhausted.
+    pub fn insert(&mut self, field: Field) -> Result<(), CrustyError> {
+        if self.seen.contains(&field) {
+            return Ok(());
+        }
+        let bytes = Self::footprint(&field);
+        if !self.make_room(bytes) {
+            self.spill()?;
+            if !self.make_room(bytes) {
+                return Err(CrustyError::ExecutionError(String::from(
+                    "Memory budget too small to count a distinct value",
+                )));
+            }
+        }
+        self.used += bytes;
+        self.seen.insert(field);
+        Ok(())
+    }
+
+    /// Adds the values another counter has seen, e.g. in another part of the input. The runs
+    /// it spilled are moved over rather than read.
+    pub fn merge(&mut self, mut other: DistinctCounter) -> Result<(), CrustyError> {
+        for field in std::mem::take(&mut other.seen) {
+            self.insert(field)?;
+        }
+        for run in std::mem::take(&mut other.runs) {
+            fs::create_dir_all(&self.spill_dir)?;
+            let mut path = self.spill_dir.clone();
+            path.push(format!("run{}", self.runs.len()));
+            fs::rename(run, &path)?;
+            self.runs.push(path);
+        }
+        let _ = fs::remove_dir_all(&other.spill_dir);
+        Ok(())
+    }
+
+    /// Number of sorted runs written to disk so far.
+    pub fn num_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Writes the values in memory to disk as a sorted run and releases their memory.
+    fn spill(&mut self) -> Result<(), CrustyError> {
+        if self.seen.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.spill_dir)?;
+        let mut path = self.spill_dir.clone();
+        path.push(format!("run{}", self.runs.len()));
+        let mut vals: Vec<Field> = self.seen.drain().collect();
+        vals.sort();
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for val in vals {
+            serde_cbor::to_writer(&mut writer, &val)
+                .map_err(|e| CrustyError::IOError(e.to_string()))?;
+        }
+        writer.flush()?;
+        debug!("DistinctCounter spilled run {:?}", path);
+        self.runs.push(path);
+        self.budget.release(self.reserved - self.floor);
+        self.reserved = self.floor;
+        self.used = 0;
+        Ok(())
+    }
+
+    /// Returns the number of distinct values inserted.
+    pub fn count(&mut self) -> Result<usize, CrustyError> {
+        if self.runs.is_empty() {
+            return Ok(self.seen.len());
+        }
+        self.spill()?;
+        let mut readers = Vec::new();
+        for path in &self.runs {
+            let reader = BufReader::new(File::open(path)?);
+            readers.push(serde_cbor::Deserializer::from_reader(reader).into_iter::<Field>());
+        }
+        // k-way merge of the sorted runs, counting each value once
+        let mut heap = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some(val) = reader.next() {
+                let val = val.map_err(|e| CrustyError::IOError(e.to_string()))?;
+                heap.push(Reverse((val, i)));
+            }
+        }
+        let mut count = 0;
+        let mut last: Option<Field> = None;
+        while let Some(Reverse((val, i))) = heap.pop() {
+            if last.as_ref() != Some(&val) {
+                count += 1;
+                last = Some(val);
+            }
+            if let Some(next) = readers[i].next() {
+                let next = next.map_err(|e| CrustyError::IOError(e.to_string()))?;
+                heap.push(Reverse((next, i)));
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl Drop for DistinctCounter {
+    fn drop(&mut self) {
+        self.budget.release(self.reserved);
+        if !self.runs.is_empty() {
+            let _ = fs::remove_dir_all(&self.spill_dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::gen_random_dir;
+
+    #[test]
+    fn test_count_in_memory() -> Result<(), CrustyError> {
+        let budget = Arc::new(MemoryBudget::unbounded());
+        let mut counter = DistinctCounter::new(budget, gen_random_dir());
+        for i in 0..100 {
+            counter.insert(Field::IntField(i % 10))?;
+        }
+        assert_eq!(10, counter.count()?);
+        assert_eq!(0, counter.num_runs());
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_without_room() -> Result<(), CrustyError> {
+        let budget = Arc::new(MemoryBudget::new(MIN_RESERVATION + size_of::<Field>()));
+        assert!(budget.try_reserve(MIN_RESERVATION));
+        assert!(budget.try_reserve(size_of::<Field>()));
+        let dir = gen_random_dir();
+        {
+            // the rest of the budget is held by someone else, the counter spills whenever the
+            // values it was given room for are full
+            let mut counter =
+                DistinctCounter::with_reserved(budget.clone(), dir.clone(), MIN_RESERVATION);
+            for i in 0..300 {
+                counter.insert(Field::IntField(i % 250))?;
+            }
+            // the repeated values were spilled already, so they fill runs again
+            assert_eq!(300 / 16, counter.num_runs());
+            assert_eq!(250, counter.count()?);
+            assert_eq!(MIN_RESERVATION + size_of::<Field>(), budget.peak());
+        }
+        assert_eq!(size_of::<Field>(), budget.used());
+        assert!(!dir.exists());
+
+        // without a reservation of its own, it can't count anything
+        assert!(budget.try_reserve(MIN_RESERVATION));
+        let mut counter = DistinctCounter::new(budget, gen_random_dir());
+        assert!(counter.insert(Field::IntField(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_many_counters() -> Result<(), CrustyError> {
+        // room for what the counters are given and 100 values more, which the first counters
+        // to grow take
+        let (counters, values) = (50, 100);
+        let limit = counters * MIN_RESERVATION + 100 * size_of::<Field>();
+        let budget = Arc::new(MemoryBudget::new(limit));
+        let mut all: Vec<DistinctCounter> = (0..counters)
+            .map(|_| {
+                assert!(budget.try_reserve(MIN_RESERVATION));
+                DistinctCounter::with_reserved(budget.clone(), gen_random_dir(), MIN_RESERVATION)
+            })
+            .collect();
+        for i in 0..2 * values {
+            for (c, counter) in all.iter_mut().enumerate() {
+                counter.insert(Field::IntField((i + c as i32) % values))?;
+                // every value in memory is paid for
+                assert!(counter.used <= counter.reserved);
+                assert_eq!(counter.used, counter.seen.len() * size_of::<Field>());
+            }
+            assert!(budget.used() <= limit);
+        }
+        for counter in all.iter_mut() {
+            assert_eq!(values as usize, counter.count()?);
+        }
+        assert!(budget.peak() <= limit);
+        drop(all);
+        assert_eq!(0, budget.used());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<(), CrustyError> {
+        let budget = Arc::new(MemoryBudget::new(100 * size_of::<Field>()));
+        let (dir, other_dir) = (gen_random_dir(), gen_random_dir());
+        {
+            let mut counter = DistinctCounter::new(budget.clone(), dir.clone());
+            let mut other = DistinctCounter::new(budget.clone(), other_dir.clone());
+            for i in 0..1000 {
+                counter.insert(Field::IntField(i))?;
+                other.insert(Field::IntField(i + 500))?;
+            }
+            assert!(other.num_runs() > 0);
+            counter.merge(other)?;
+            assert!(!other_dir.exists());
+            assert_eq!(1500, counter.count()?);
+        }
+        assert_eq!(0, budget.used());
+        assert!(!dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_spills_large_cardinality() -> Result<(), CrustyError> {
+        let budget = Arc::new(MemoryBudget::new(1000 * size_of::<Field>()));
+        let dir = gen_random_dir();
+        {
+            let mut counter = DistinctCounter::new(budget.clone(), dir.clone());
+            // 50,000 distinct values, each inserted twice across different runs
+            for round in 0..2 {
+                for i in 0..50_000 {
+                    let v = if round == 0 { i } else { 49_999 - i };
+                    counter.insert(Field::IntField(v))?;
+                    counter.insert(Field::StringField(format!("s{}", v % 7)))?;
+                }
+            }
+            assert!(counter.num_runs() > 1);
+            assert!(budget.used() <= budget.limit());
+            assert_eq!(50_007, counter.count()?);
+        }
+        // memory is returned and runs are cleaned up on drop
+        assert_eq!(0, budget.used());
+        assert!(!dir.exists());
+        Ok(())
+    }
+}
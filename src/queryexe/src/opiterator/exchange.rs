@@ -0,0 +1,288 @@
+use super::OpIterator;
+use common::{CrustyError, TableSchema, Tuple};
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Builds the operators that read one part of the input of a parallel operator, given the
+/// index of the part.
+///
+/// It is called on the thread the part is read on, so the operators never leave that thread.
+pub type PartitionFactory =
+    Arc<dyn Fn(usize) -> Result<Box<dyn OpIterator>, CrustyError> + Send + Sync>;
+
+/// Tuples a worker sends at once.
+const EXCHANGE_BATCH: usize = 64;
+
+/// Batches a worker may get ahead of the consumer of its part.
+const EXCHANGE_BUFFER_BATCHES: usize = 64;
+
+/// A batch of tuples of a part, or the error that ended the part.
+type Message = Result<Vec<Tuple>, CrustyError>;
+
+/// Reads the parts of its input on worker threads, one per part, and returns their tuples.
+///
+/// All the tuples of part 0 are returned first, then those of part 1 and so on, so the result
+/// does not depend on how the threads are scheduled: the parts of a table split by
+/// `SeqScan::with_partition` come out in the order of a scan of the whole table. Each worker
+/// runs ahead of the consumer by a bounded number of tuples and then waits for its turn.
+///
+/// The workers are started by the first call to `next`, so an Exchange that is opened but
+/// never read costs no threads.
+pub struct Exchange {
+    /// Number of parts, and of workers.
+    partitions: usize,
+    /// Builds the operators of a part.
+    factory: PartitionFactory,
+    /// Receivers of the parts not returned yet, and the workers sending to them, in order.
+    parts: VecDeque<(Receiver<Message>, JoinHandle<()>)>,
+    /// Set to stop the workers once the consumer no longer needs their tuples.
+    stopped: Arc<AtomicBool>,
+    /// Tuples of the last batch received not returned yet.
+    batch: std::vec::IntoIter<Tuple>,
+    /// Whether the workers were started since open or rewind.
+    started: bool,
+    /// Schema of the tuples of every part.
+    schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+}
+
+impl Exchange {
+    /// Exchange constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `partitions` - Number of parts, read by a thread each.
+    /// * `factory` - Builds the operators of a part.
+    /// * `schema` - Schema of the tuples of the operators the factory builds.
+    pub fn new(partitions: usize, factory: PartitionFactory, schema: TableSchema) -> Self {
+        Self {
+            partitions: partitions.max(1),
+            factory,
+            parts: VecDeque::new(),
+            stopped: Arc::new(AtomicBool::new(false)),
+            batch: Vec::new().into_iter(),
+            started: false,
+            schema,
+            open: false,
+        }
+    }
+
+    /// Starts a worker for every part.
+    fn start(&mut self) {
+        self.stopped = Arc::new(AtomicBool::new(false));
+        for partition in 0..self.partitions {
+            let (sender, receiver) = mpsc::sync_channel(EXCHANGE_BUFFER_BATCHES);
+            let factory = self.factory.clone();
+            let stopped = self.stopped.clone();
+            let worker = thread::spawn(move || {
+                if let Err(e) = Self::run_partition(&factory, partition, &sender, &stopped) {
+                    // the consumer may be gone already
+                    let _ = sender.send(Err(e));
+                }
+            });
+            self.parts.push_back((receiver, worker));
+        }
+        self.started = true;
+    }
+
+    /// Builds and runs the operators of a part, and closes them even if they failed.
+    fn run_partition(
+        factory: &PartitionFactory,
+        partition: usize,
+        sender: &SyncSender<Message>,
+        stopped: &AtomicBool,
+    ) -> Result<(), CrustyError> {
+        let mut op = factory(partition)?;
+        op.open()?;
+        let sent = Self::send_tuples(op.as_mut(), sender, stopped);
+        let closed = op.close();
+        sent.and(closed)
+    }
+
+    /// Sends the tuples of a part a batch at a time, until there are none left or the
+    /// consumer stopped the workers.
+    fn send_tuples(
+        op: &mut dyn OpIterator,
+        sender: &SyncSender<Message>,
+        stopped: &AtomicBool,
+    ) -> Result<(), CrustyError> {
+        let mut batch = Vec::with_capacity(EXCHANGE_BATCH);
+        while !stopped.load(Ordering::Relaxed) {
+            match op.next()? {
+                Some(t) => batch.push(t),
+                None => break,
+            }
+            if batch.len() == EXCHANGE_BATCH {
+                let full = mem::replace(&mut batch, Vec::with_capacity(EXCHANGE_BATCH));
+                if sender.send(Ok(full)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        if !batch.is_empty() {
+            let _ = sender.send(Ok(batch));
+        }
+        Ok(())
+    }
+
+    /// Stops the workers and waits for them to close their operators.
+    fn stop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        let parts: Vec<_> = self.parts.drain(..).collect();
+        for (receiver, worker) in parts {
+            // a worker waiting for room in its buffer sees the receiver is gone
+            drop(receiver);
+            if worker.join().is_err() {
+                warn!("Worker of a stopped exchange panicked");
+            }
+        }
+        self.batch = Vec::new().into_iter();
+        self.started = false;
+    }
+}
+
+impl OpIterator for Exchange {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.stop();
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        if !self.started {
+            self.start();
+        }
+        loop {
+            if let Some(t) = self.batch.next() {
+                return Ok(Some(t));
+            }
+            let message = match self.parts.front() {
+                Some((receiver, _)) => receiver.recv(),
+                None => return Ok(None),
+            };
+            match message {
+                Ok(Ok(batch)) => self.batch = batch.into_iter(),
+                Ok(Err(e)) => return Err(e),
+                // the worker of the part is done, and dropped its sender
+                Err(_) => {
+                    let (_, worker) = self.parts.pop_front().unwrap();
+                    if worker.join().is_err() {
+                        return Err(CrustyError::ExecutionError(String::from(
+                            "A worker of a parallel operator panicked",
+                        )));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the rest of the last batch a worker sent, or the next one.
+    fn next_batch(&mut self) -> Result<Option<Vec<Tuple>>, CrustyError> {
+        let first = match self.next()? {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let mut batch = Vec::with_capacity(self.batch.len() + 1);
+        batch.push(first);
+        batch.extend(&mut self.batch);
+        Ok(Some(batch))
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.stop();
+        self.open = false;
+        Ok(())
+    }
+
+    /// Reads the parts again, on new workers, on the next call to `next`.
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.stop();
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+impl Drop for Exchange {
+    /// Stops the workers of an Exchange that was not closed, e.g. of an aborted plan, without
+    /// waiting for them.
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TupleIterator;
+    use super::*;
+    use common::testutil::*;
+
+    /// An Exchange over `partitions` parts of the rows 0 to `n`, failing in part `fail`.
+    fn exchange(n: i32, partitions: usize, fail: Option<usize>) -> Exchange {
+        let factory: PartitionFactory = Arc::new(move |partition| {
+            if Some(partition) == fail {
+                return Err(CrustyError::ExecutionError(String::from("part failed")));
+            }
+            let (p, parts) = (partition as i32, partitions as i32);
+            let rows = (n * p / parts..n * (p + 1) / parts)
+                .map(|i| vec![i])
+                .collect();
+            let child = TupleIterator::new(create_tuple_list(rows), get_int_table_schema(1));
+            Ok(Box::new(child) as Box<dyn OpIterator>)
+        });
+        Exchange::new(partitions, factory, get_int_table_schema(1))
+    }
+
+    fn values(op: &mut dyn OpIterator) -> Result<Vec<i32>, CrustyError> {
+        let mut values = Vec::new();
+        while let Some(t) = op.next()? {
+            values.push(t.field(0)?.as_int()?);
+        }
+        Ok(values)
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        exchange(10, 2, None).next().unwrap();
+    }
+
+    #[test]
+    fn test_parts_in_order() -> Result<(), CrustyError> {
+        for partitions in 1..6 {
+            let mut op = exchange(1000, partitions, None);
+            op.open()?;
+            assert_eq!((0..1000).collect::<Vec<i32>>(), values(&mut op)?);
+            op.rewind()?;
+            assert_eq!(1000, values(&mut op)?.len());
+            op.close()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_part() -> Result<(), CrustyError> {
+        let mut op = exchange(1000, 4, Some(2));
+        op.open()?;
+        assert!(values(&mut op).is_err());
+        op.close()?;
+        // closing an exchange whose workers are still running stops them
+        let mut op = exchange(100_000, 4, None);
+        op.open()?;
+        assert!(op.next()?.is_some());
+        op.close()
+    }
+}
@@ -0,0 +1,274 @@
+use common::logical_plan::{ArithOp, ScalarFunction};
+use common::{CrustyError, DataType, Field, TableSchema, Tuple};
+
+/// Expression over the fields of a tuple, what a `ScalarExpr` of the logical plan is compiled
+/// to once its columns are resolved to indices.
+#[derive(Debug)]
+pub enum Expression {
+    /// A field of the tuple, by index.
+    Field(usize),
+    /// A constant value.
+    Literal(Field),
+    /// Arithmetic on two INTs, or with `+` concatenation of two strings.
+    Binary {
+        left: Box<Expression>,
+        op: ArithOp,
+        right: Box<Expression>,
+    },
+    /// A built-in function of an expression.
+    Function {
+        function: ScalarFunction,
+        arg: Box<Expression>,
+    },
+}
+
+impl Expression {
+    /// Creates an operation on two expressions.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Left operand.
+    /// * `op` - Operator.
+    /// * `right` - Right operand.
+    pub fn binary(left: Expression, op: ArithOp, right: Expression) -> Self {
+        Expression::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    /// Creates a call of a built-in function.
+    ///
+    /// # Arguments
+    ///
+    /// * `function` - Function called.
+    /// * `arg` - Argument of the function.
+    pub fn function(function: ScalarFunction, arg: Expression) -> Self {
+        Expression::Function {
+            function,
+            arg: Box::new(arg),
+        }
+    }
+
+    /// Computes the value of the expression for a tuple.
+    ///
+    /// An operation or a function with a NULL operand is NULL. Errors if an INT operation
+    /// overflows or divides by zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple whose fields the expression reads.
+    pub fn eval(&self, tuple: &Tuple) -> Result<Field, CrustyError> {
+        match self {
+            Expression::Field(i) => Ok(tuple.field(*i)?.clone()),
+            Expression::Literal(field) => Ok(field.clone()),
+            Expression::Binary { left, op, right } => {
+                let left = left.eval(tuple)?;
+                let right = right.eval(tuple)?;
+                Self::apply(*op, left, right)
+            }
+            Expression::Function { function, arg } => Self::call(*function, arg.eval(tuple)?),
+        }
+    }
+
+    /// Applies a function to the value of its argument.
+    fn call(function: ScalarFunction, arg: Field) -> Result<Field, CrustyError> {
+        match (function, arg) {
+            (_, Field::Null) => Ok(Field::Null),
+            (ScalarFunction::Upper, Field::StringField(s)) => {
+                Ok(Field::StringField(s.to_uppercase()))
+            }
+            (ScalarFunction::Lower, Field::StringField(s)) => {
+                Ok(Field::StringField(s.to_lowercase()))
+            }
+            (ScalarFunction::Length, Field::StringField(s)) => {
+                Ok(Field::IntField(s.chars().count() as i32))
+            }
+            (ScalarFunction::Abs, Field::IntField(i)) => {
+                i.checked_abs().map(Field::IntField).ok_or_else(|| {
+                    CrustyError::ExecutionError(format!("{}({}) overflows an INT", function, i))
+                })
+            }
+            (function, arg) => Err(CrustyError::ExecutionError(format!(
+                "{} can't be applied to {}",
+                function, arg
+            ))),
+        }
+    }
+
+    /// Applies an operator to the values of its operands.
+    fn apply(op: ArithOp, left: Field, right: Field) -> Result<Field, CrustyError> {
+        let (l, r) = match (left, right) {
+            (Field::Null, _) | (_, Field::Null) => return Ok(Field::Null),
+            (Field::IntField(l), Field::IntField(r)) => (l, r),
+            (Field::StringField(mut l), Field::StringField(r)) if op == ArithOp::Plus => {
+                l.push_str(&r);
+                return Ok(Field::StringField(l));
+            }
+            (l, r) => {
+                return Err(CrustyError::ExecutionError(format!(
+                    "{} can't be applied to {} and {}",
+                    op, l, r
+                )))
+            }
+        };
+        if r == 0 && matches!(op, ArithOp::Divide | ArithOp::Modulus) {
+            return Err(CrustyError::ExecutionError(format!(
+                "{} {} {} divides by zero",
+                l, op, r
+            )));
+        }
+        let value = match op {
+            ArithOp::Plus => l.checked_add(r),
+            ArithOp::Minus => l.checked_sub(r),
+            ArithOp::Multiply => l.checked_mul(r),
+            ArithOp::Divide => l.checked_div(r),
+            ArithOp::Modulus => l.checked_rem(r),
+        };
+        value.map(Field::IntField).ok_or_else(|| {
+            CrustyError::ExecutionError(format!("{} {} {} overflows an INT", l, op, r))
+        })
+    }
+
+    /// Type of the values of the expression over tuples of a schema, None if it only has NULL
+    /// literals.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuples.
+    pub fn dtype(&self, schema: &TableSchema) -> Option<DataType> {
+        match self {
+            Expression::Field(i) => schema.get_attribute(*i).map(|a| a.dtype().clone()),
+            Expression::Literal(Field::IntField(_)) => Some(DataType::Int),
+            Expression::Literal(Field::StringField(_)) => Some(DataType::String),
+            Expression::Literal(Field::Null) => None,
+            Expression::Binary { left, right, .. } => {
+                left.dtype(schema).or_else(|| right.dtype(schema))
+            }
+            Expression::Function { function, .. } => Some(function.return_type()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tuple() -> Tuple {
+        Tuple::new(vec![
+            Field::IntField(7),
+            Field::IntField(2),
+            Field::StringField(String::from("ab")),
+            Field::Null,
+        ])
+    }
+
+    fn eval(left: Expression, op: ArithOp, right: Expression) -> Result<Field, CrustyError> {
+        Expression::binary(left, op, right).eval(&tuple())
+    }
+
+    #[test]
+    fn test_arithmetic() -> Result<(), CrustyError> {
+        let cases = [
+            (ArithOp::Plus, 9),
+            (ArithOp::Minus, 5),
+            (ArithOp::Multiply, 14),
+            (ArithOp::Divide, 3),
+            (ArithOp::Modulus, 1),
+        ];
+        for (op, expected) in cases.iter() {
+            let value = eval(Expression::Field(0), *op, Expression::Field(1))?;
+            assert_eq!(Field::IntField(*expected), value);
+        }
+        // nested, (7 - 2) * 3
+        let nested = Expression::binary(
+            Expression::binary(Expression::Field(0), ArithOp::Minus, Expression::Field(1)),
+            ArithOp::Multiply,
+            Expression::Literal(Field::IntField(3)),
+        );
+        assert_eq!(Field::IntField(15), nested.eval(&tuple())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concat_and_null() -> Result<(), CrustyError> {
+        let c = Expression::Literal(Field::StringField(String::from("c")));
+        assert_eq!(
+            Field::StringField(String::from("abc")),
+            eval(Expression::Field(2), ArithOp::Plus, c)?
+        );
+        assert_eq!(
+            Field::Null,
+            eval(Expression::Field(0), ArithOp::Divide, Expression::Field(3))?
+        );
+        assert!(eval(Expression::Field(2), ArithOp::Minus, Expression::Field(2)).is_err());
+        assert!(eval(Expression::Field(2), ArithOp::Plus, Expression::Field(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() {
+        let zero = || Expression::Literal(Field::IntField(0));
+        assert!(eval(Expression::Field(0), ArithOp::Divide, zero()).is_err());
+        assert!(eval(Expression::Field(0), ArithOp::Modulus, zero()).is_err());
+        let max = Expression::Literal(Field::IntField(i32::MAX));
+        assert!(eval(max, ArithOp::Plus, Expression::Field(1)).is_err());
+        let min = Expression::Literal(Field::IntField(i32::MIN));
+        let minus_one = Expression::Literal(Field::IntField(-1));
+        assert!(eval(min, ArithOp::Divide, minus_one).is_err());
+    }
+
+    #[test]
+    fn test_functions() -> Result<(), CrustyError> {
+        let call = |function, arg| Expression::function(function, arg).eval(&tuple());
+        let s = |s: &str| Field::StringField(String::from(s));
+        let literal = |s: &str| Expression::Literal(Field::StringField(String::from(s)));
+        assert_eq!(s("AB"), call(ScalarFunction::Upper, Expression::Field(2))?);
+        assert_eq!(s("mixed"), call(ScalarFunction::Lower, literal("MiXeD"))?);
+        assert_eq!(
+            Field::IntField(3),
+            call(ScalarFunction::Length, literal("été"))?
+        );
+        let minus_seven = Expression::binary(
+            Expression::Literal(Field::IntField(0)),
+            ArithOp::Minus,
+            Expression::Field(0),
+        );
+        assert_eq!(Field::IntField(7), call(ScalarFunction::Abs, minus_seven)?);
+        assert_eq!(
+            Field::Null,
+            call(ScalarFunction::Upper, Expression::Field(3))?
+        );
+        let min = Expression::Literal(Field::IntField(i32::MIN));
+        assert!(call(ScalarFunction::Abs, min).is_err());
+        assert!(call(ScalarFunction::Length, Expression::Field(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dtype() {
+        let schema = TableSchema::from_vecs(
+            vec!["a", "b", "c", "d"],
+            vec![
+                DataType::Int,
+                DataType::Int,
+                DataType::String,
+                DataType::Int,
+            ],
+        );
+        let null = || Expression::Literal(Field::Null);
+        assert_eq!(
+            Some(DataType::String),
+            Expression::binary(null(), ArithOp::Plus, Expression::Field(2)).dtype(&schema)
+        );
+        assert_eq!(
+            None,
+            Expression::binary(null(), ArithOp::Plus, null()).dtype(&schema)
+        );
+        assert_eq!(
+            Some(DataType::Int),
+            Expression::function(ScalarFunction::Length, null()).dtype(&schema)
+        );
+    }
+}
@@ -0,0 +1,583 @@
+use crate::memory_budget::MemoryBudget;
+use crate::StorageManager;
+use common::ids::{ContainerId, Permissions, TransactionId};
+use common::storage_trait::{ContainerType, StorageTrait, ValueRefIterator};
+use common::{CrustyError, Field, Tuple, PAGE_SIZE};
+use std::cmp::Ordering;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU16, Ordering as AtomicOrdering};
+use std::sync::Arc;
+
+/// Number of tuples stored together in one value of a run container.
+const BLOCK_TUPLES: usize = 256;
+
+/// Largest block written as one value. Larger blocks are split so they fit on a page of
+/// storage managers that keep values on pages.
+const BLOCK_BYTES: usize = PAGE_SIZE / 2;
+
+/// Next id to try for a temporary run container. Ids are handed out from the top of the id
+/// space down so they stay clear of table containers.
+static NEXT_TEMP_CONTAINER: AtomicU16 = AtomicU16::new(ContainerId::MAX);
+
+/// A field to sort on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortKey {
+    /// Index of the field in the tuple.
+    pub field: usize,
+    /// Sort from smallest to largest.
+    pub ascending: bool,
+}
+
+impl SortKey {
+    pub fn new(field: usize, ascending: bool) -> Self {
+        Self { field, ascending }
+    }
+}
+
+/// Tunables of an external sort.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortConfig {
+    /// Maximum number of tuples in a sorted run. Runs are also cut short when the memory budget
+    /// runs out.
+    pub run_size: usize,
+    /// Maximum number of runs merged at once. More runs are merged in several passes.
+    pub fan_in: usize,
+}
+
+impl Default for SortConfig {
+    fn default() -> Self {
+        Self {
+            run_size: 10_000,
+            fan_in: 16,
+        }
+    }
+}
+
+/// Compares two tuples on the sort keys.
+fn compare(keys: &[SortKey], a: &Tuple, b: &Tuple) -> Ordering {
+    for key in keys {
+        let ord = a.get_field(key.field).cmp(&b.get_field(key.field));
+        let ord = if key.ascending { ord } else { ord.reverse() };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Sorts tuples that may not fit in memory, e.g. for ORDER BY or a sort-merge join.
+///
+/// Tuples are buffered until the run size or the memory budget is reached, then the buffer is
+/// sorted and spilled to a temporary container as a run. `finish` merges the runs with a loser
+/// tree. The sort is stable: tuples with equal keys come out in the order they were inserted.
+pub struct ExternalSort {
+    keys: Vec<SortKey>,
+    config: SortConfig,
+    budget: Arc<MemoryBudget>,
+    /// Bytes reserved from the budget for `buffer`.
+    reserved: usize,
+    buffer: Vec<Tuple>,
+    runs: Runs,
+}
+
+impl ExternalSort {
+    /// Creates a new sort.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - Fields to sort on, most significant first.
+    /// * `config` - Run size and fan-in.
+    /// * `budget` - Memory budget the buffered tuples are accounted against.
+    /// * `storage_manager` - Storage manager the runs are spilled to.
+    /// * `tid` - Transaction the runs are written with.
+    pub fn new(
+        keys: Vec<SortKey>,
+        config: SortConfig,
+        budget: Arc<MemoryBudget>,
+        storage_manager: Arc<StorageManager>,
+        tid: TransactionId,
+    ) -> Self {
+        Self {
+            keys,
+            config: SortConfig {
+                run_size: config.run_size.max(1),
+                fan_in: config.fan_in.max(2),
+            },
+            budget,
+            reserved: 0,
+            buffer: Vec::new(),
+            runs: Runs {
+                storage_manager,
+                tid,
+                containers: Vec::new(),
+            },
+        }
+    }
+
+    /// Approximate number of bytes a tuple occupies in the buffer.
+    fn footprint(tuple: &Tuple) -> usize {
+        size_of::<Tuple>()
+            + tuple
+                .field_vals()
+                .map(|f| match f {
+                    Field::Null | Field::IntField(_) => size_of::<Field>(),
+                    Field::StringField(s) => size_of::<Field>() + s.capacity(),
+                })
+                .sum::<usize>()
+    }
+
+    /// Adds a tuple, spilling the buffer as a run if it is full.
+    pub fn insert(&mut self, tuple: Tuple) -> Result<(), CrustyError> {
+        if self.buffer.len() >= self.config.run_size {
+            self.spill()?;
+        }
+        let bytes = Self::footprint(&tuple);
+        if !self.budget.try_reserve(bytes) {
+            self.spill()?;
+            if !self.budget.try_reserve(bytes) {
+                return Err(CrustyError::ExecutionError(String::from(
+                    "Memory budget too small to sort a tuple",
+                )));
+            }
+        }
+        self.reserved += bytes;
+        self.buffer.push(tuple);
+        Ok(())
+    }
+
+    /// Number of runs spilled so far.
+    pub fn num_runs(&self) -> usize {
+        self.runs.containers.len()
+    }
+
+    /// Sorts the buffer, writes it to a new run and releases its memory.
+    fn spill(&mut self) -> Result<(), CrustyError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let keys = &self.keys;
+        self.buffer.sort_by(|a, b| compare(keys, a, b));
+        let mut tuples = std::mem::take(&mut self.buffer).into_iter();
+        self.runs.write_run(|| Ok(tuples.next()))?;
+        self.budget.release(self.reserved);
+        self.reserved = 0;
+        Ok(())
+    }
+
+    /// Returns the tuples in sorted order.
+    ///
+    /// If nothing was spilled the buffer is sorted in memory and stays reserved in the budget
+    /// until the result is dropped. Otherwise the runs are merged, in several passes if there
+    /// are more than the fan-in.
+    pub fn finish(mut self) -> Result<SortedTuples, CrustyError> {
+        let empty_runs = self.runs.empty();
+        if self.runs.containers.is_empty() {
+            let keys = &self.keys;
+            self.buffer.sort_by(|a, b| compare(keys, a, b));
+            let reserved = std::mem::replace(&mut self.reserved, 0);
+            return Ok(SortedTuples {
+                source: Source::InMemory(std::mem::take(&mut self.buffer).into_iter()),
+                _runs: empty_runs,
+                budget: self.budget.clone(),
+                reserved,
+            });
+        }
+        self.spill()?;
+        while self.runs.containers.len() > self.config.fan_in {
+            // The previous runs are removed when `old` is dropped, also on error.
+            let old = std::mem::replace(&mut self.runs, empty_runs.empty());
+            // Merge groups of consecutive runs so that ties still resolve in insertion order.
+            for group in old.containers.chunks(self.config.fan_in) {
+                let mut merge = old.merge(group, self.keys.clone())?;
+                self.runs.write_run(|| merge.next())?;
+            }
+            debug!("ExternalSort merge pass left {} runs", self.num_runs());
+        }
+        let merge = self.runs.merge(&self.runs.containers, self.keys.clone())?;
+        Ok(SortedTuples {
+            source: Source::Merged(merge),
+            _runs: std::mem::replace(&mut self.runs, empty_runs),
+            budget: self.budget.clone(),
+            reserved: 0,
+        })
+    }
+}
+
+impl Drop for ExternalSort {
+    fn drop(&mut self) {
+        self.budget.release(self.reserved);
+    }
+}
+
+/// Temporary containers holding sorted runs. Removed when dropped.
+struct Runs {
+    storage_manager: Arc<StorageManager>,
+    tid: TransactionId,
+    containers: Vec<ContainerId>,
+}
+
+impl Runs {
+    /// Creates an empty set of runs on the same storage manager.
+    fn empty(&self) -> Self {
+        Runs {
+            storage_manager: self.storage_manager.clone(),
+            tid: self.tid,
+            containers: Vec::new(),
+        }
+    }
+
+    /// Creates a temporary container with an unused id.
+    fn new_container(&self) -> Result<ContainerId, CrustyError> {
+        for _ in 0..=ContainerId::MAX {
+            let cid = NEXT_TEMP_CONTAINER.fetch_sub(1, AtomicOrdering::Relaxed);
+            if !self.storage_manager.container_exists(cid) {
+                self.storage_manager
+                    .create_container(cid, None, ContainerType::Temp)?;
+                return Ok(cid);
+            }
+        }
+        Err(CrustyError::ExecutionError(String::from(
+            "No container id left for a sort run",
+        )))
+    }
+
+    /// Writes the tuples produced by `next` to a new run, in blocks of BLOCK_TUPLES.
+    fn write_run<F>(&mut self, mut next: F) -> Result<(), CrustyError>
+    where
+        F: FnMut() -> Result<Option<Tuple>, CrustyError>,
+    {
+        let cid = self.new_container()?;
+        self.containers.push(cid);
+        let mut block = Vec::with_capacity(BLOCK_TUPLES);
+        while let Some(tuple) = next()? {
+            block.push(tuple);
+            if block.len() == BLOCK_TUPLES {
+                self.write_block(cid, &block)?;
+                block.clear();
+            }
+        }
+        self.write_block(cid, &block)
+    }
+
+    /// Writes a block of tuples as one value, or as several if it is over BLOCK_BYTES.
+    fn write_block(&self, cid: ContainerId, block: &[Tuple]) -> Result<(), CrustyError> {
+        if block.is_empty() {
+            return Ok(());
+        }
+        let bytes = serde_cbor::to_vec(&block).map_err(|e| CrustyError::IOError(e.to_string()))?;
+        if bytes.len() > BLOCK_BYTES && block.len() > 1 {
+            let (first, second) = block.split_at(block.len() / 2);
+            self.write_block(cid, first)?;
+            return self.write_block(cid, second);
+        }
+        self.storage_manager.insert_value(cid, bytes, self.tid);
+        Ok(())
+    }
+
+    /// Starts merging the given runs.
+    fn merge(&self, containers: &[ContainerId], keys: Vec<SortKey>) -> Result<Merge, CrustyError> {
+        let mut readers = Vec::new();
+        let mut heads = Vec::new();
+        for cid in containers {
+            let mut reader = RunReader {
+                values: self
+                    .storage_manager
+                    .get_iterator(*cid, self.tid, Permissions::ReadOnly),
+                block: Vec::new().into_iter(),
+            };
+            heads.push(reader.next()?);
+            readers.push(reader);
+        }
+        Ok(Merge {
+            readers,
+            tree: LoserTree::new(heads, Box::new(move |a, b| compare(&keys, a, b))),
+        })
+    }
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for cid in &self.containers {
+            if let Err(e) = self.storage_manager.remove_container(*cid) {
+                error!("Could not remove sort run {}: {}", cid, e);
+            }
+        }
+    }
+}
+
+/// Reads the tuples of a run back in order.
+struct RunReader {
+    values: <StorageManager as StorageTrait>::ValIterator,
+    block: std::vec::IntoIter<Tuple>,
+}
+
+impl RunReader {
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        loop {
+            if let Some(tuple) = self.block.next() {
+                return Ok(Some(tuple));
+            }
+            match self.values.next_ref() {
+                Some(bytes) => {
+                    let block: Vec<Tuple> = serde_cbor::from_slice(bytes?)
+                        .map_err(|e| CrustyError::IOError(e.to_string()))?;
+                    self.block = block.into_iter();
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Compares tuples on the sort keys.
+type TupleCmp = Box<dyn Fn(&Tuple, &Tuple) -> Ordering>;
+
+/// k-way merge of runs.
+struct Merge {
+    readers: Vec<RunReader>,
+    tree: LoserTree<Tuple, TupleCmp>,
+}
+
+impl Merge {
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let source = self.tree.winner();
+        let next = match self.readers.get_mut(source) {
+            Some(reader) => reader.next()?,
+            None => return Ok(None),
+        };
+        Ok(self.tree.replace_winner(next))
+    }
+}
+
+/// Tournament tree that keeps the loser of each match in the internal nodes, so replacing the
+/// winner takes one comparison per level. Exhausted sources lose every match, and ties go to
+/// the source with the lower index.
+struct LoserTree<T, F> {
+    /// tree[0] is the winner, tree[1..k] the losers of the matches. Leaf i sits at k + i.
+    tree: Vec<usize>,
+    /// Current head of each source.
+    heads: Vec<Option<T>>,
+    cmp: F,
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> LoserTree<T, F> {
+    fn new(heads: Vec<Option<T>>, cmp: F) -> Self {
+        let k = heads.len();
+        // Start with every match held by a sentinel (index k) that beats everything; replaying
+        // the leaves pushes the sentinels out.
+        let mut tree = Self {
+            tree: vec![k; k.max(1)],
+            heads,
+            cmp,
+        };
+        for leaf in (0..k).rev() {
+            tree.replay(leaf);
+        }
+        tree
+    }
+
+    /// Whether source `a` comes before source `b`.
+    fn beats(&self, a: usize, b: usize) -> bool {
+        let k = self.heads.len();
+        if a == k || b == k {
+            return a == k;
+        }
+        match (&self.heads[a], &self.heads[b]) {
+            (Some(x), Some(y)) => match (self.cmp)(x, y) {
+                Ordering::Less => true,
+                Ordering::Greater => false,
+                Ordering::Equal => a < b,
+            },
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => a < b,
+        }
+    }
+
+    /// Plays the matches from a leaf up to the root.
+    fn replay(&mut self, leaf: usize) {
+        let k = self.heads.len();
+        let mut winner = leaf;
+        let mut node = (leaf + k) / 2;
+        while node > 0 {
+            if self.beats(self.tree[node], winner) {
+                std::mem::swap(&mut self.tree[node], &mut winner);
+            }
+            node /= 2;
+        }
+        self.tree[0] = winner;
+    }
+
+    /// Source of the smallest head.
+    fn winner(&self) -> usize {
+        self.tree[0]
+    }
+
+    /// Takes the smallest head, replacing it with the next value of its source.
+    fn replace_winner(&mut self, next: Option<T>) -> Option<T> {
+        let winner = self.winner();
+        let head = std::mem::replace(self.heads.get_mut(winner)?, next);
+        self.replay(winner);
+        head
+    }
+}
+
+/// Where the sorted tuples come from.
+enum Source {
+    /// Everything fit in memory.
+    InMemory(std::vec::IntoIter<Tuple>),
+    /// Tuples are merged from runs.
+    Merged(Merge),
+}
+
+/// Output of an external sort. Runs and reserved memory are released when it is dropped.
+pub struct SortedTuples {
+    source: Source,
+    _runs: Runs,
+    budget: Arc<MemoryBudget>,
+    /// Bytes still reserved for in-memory tuples.
+    reserved: usize,
+}
+
+impl SortedTuples {
+    /// Returns the next tuple in sorted order, None when all tuples were returned.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match &mut self.source {
+            Source::InMemory(tuples) => Ok(tuples.next()),
+            Source::Merged(merge) => merge.next(),
+        }
+    }
+}
+
+impl Drop for SortedTuples {
+    fn drop(&mut self) {
+        self.budget.release(self.reserved);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::gen_random_dir;
+
+    fn new_sort(keys: Vec<SortKey>, config: SortConfig, budget: Arc<MemoryBudget>) -> ExternalSort {
+        let sm = Arc::new(StorageManager::new(
+            gen_random_dir().to_str().unwrap().to_string(),
+        ));
+        ExternalSort::new(keys, config, budget, sm, TransactionId::new())
+    }
+
+    fn collect(sort: ExternalSort) -> Result<Vec<Tuple>, CrustyError> {
+        let mut sorted = sort.finish()?;
+        let mut tuples = Vec::new();
+        while let Some(t) = sorted.next()? {
+            tuples.push(t);
+        }
+        Ok(tuples)
+    }
+
+    /// (key, insertion order) pairs; the key repeats so stability can be checked.
+    fn tuples(n: i32) -> Vec<Tuple> {
+        (0..n)
+            .map(|i| Tuple::new(vec![Field::IntField((i * 7919) % 97), Field::IntField(i)]))
+            .collect()
+    }
+
+    fn assert_sorted_stable(tuples: &[Tuple], ascending: bool) {
+        for w in tuples.windows(2) {
+            let (a, b) = (w[0].get_field(0), w[1].get_field(0));
+            if a == b {
+                assert!(w[0].get_field(1) < w[1].get_field(1));
+            } else {
+                assert_eq!(ascending, a < b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sort_in_memory() -> Result<(), CrustyError> {
+        let keys = vec![SortKey::new(0, true)];
+        let mut sort = new_sort(
+            keys,
+            SortConfig::default(),
+            Arc::new(MemoryBudget::unbounded()),
+        );
+        for t in tuples(1000) {
+            sort.insert(t)?;
+        }
+        assert_eq!(0, sort.num_runs());
+        let sorted = collect(sort)?;
+        assert_eq!(1000, sorted.len());
+        assert_sorted_stable(&sorted, true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_descending_multi_pass() -> Result<(), CrustyError> {
+        let keys = vec![SortKey::new(0, false)];
+        let config = SortConfig {
+            run_size: 100,
+            fan_in: 2,
+        };
+        let mut sort = new_sort(keys, config, Arc::new(MemoryBudget::unbounded()));
+        for t in tuples(2000) {
+            sort.insert(t)?;
+        }
+        assert_eq!(19, sort.num_runs());
+        let sorted = collect(sort)?;
+        assert_eq!(2000, sorted.len());
+        assert_sorted_stable(&sorted, false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_spills_on_budget() -> Result<(), CrustyError> {
+        let budget = Arc::new(MemoryBudget::new(200 * size_of::<Tuple>()));
+        let sm = Arc::new(StorageManager::new(
+            gen_random_dir().to_str().unwrap().to_string(),
+        ));
+        let keys = vec![SortKey::new(0, true), SortKey::new(1, false)];
+        {
+            let mut sort = ExternalSort::new(
+                keys,
+                SortConfig::default(),
+                budget.clone(),
+                sm.clone(),
+                TransactionId::new(),
+            );
+            for t in tuples(5000) {
+                sort.insert(t)?;
+            }
+            assert!(sort.num_runs() > 1);
+            assert!(budget.used() <= budget.limit());
+            let mut sorted = sort.finish()?;
+            let mut prev: Option<Tuple> = None;
+            let mut count = 0;
+            while let Some(t) = sorted.next()? {
+                if let Some(p) = prev {
+                    let (a, b) = (p.get_field(0), t.get_field(0));
+                    assert!(a < b || (a == b && p.get_field(1) > t.get_field(1)));
+                }
+                prev = Some(t);
+                count += 1;
+            }
+            assert_eq!(5000, count);
+        }
+        // memory is returned and runs are removed on drop
+        assert_eq!(0, budget.used());
+        assert!(sm.list_containers().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_loser_tree() {
+        let heads = vec![Some(3), None, Some(1), Some(3), Some(2)];
+        let mut tree = LoserTree::new(heads, |a: &i32, b: &i32| a.cmp(b));
+        let mut values = Vec::new();
+        while let Some(v) = tree.replace_winner(None) {
+            values.push(v);
+        }
+        assert_eq!(vec![1, 2, 3, 3], values);
+    }
+}
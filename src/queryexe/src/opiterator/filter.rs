@@ -1,14 +1,38 @@
-use super::OpIterator;
+use super::{Expression, OpIterator};
 use common::{CrustyError, Field, PredicateOp, TableSchema, Tuple};
 
-/// Compares the fields of tuples.
-pub struct FilterPredicate {
-    /// Operation used to compare.
-    op: PredicateOp,
-    /// Index of the field to filter.
-    field_ind: usize,
-    /// Operand to compare against.
-    operand: Field,
+/// What a field of the tuples is compared against.
+pub enum FilterOperand {
+    /// A constant value.
+    Literal(Field),
+    /// Another field of the same tuple, by index.
+    Field(usize),
+}
+
+/// Compares the fields of tuples, comparisons combined with AND and OR.
+pub enum FilterPredicate {
+    /// Compares a field of the tuples to an operand.
+    Compare {
+        /// Operation used to compare.
+        op: PredicateOp,
+        /// Index of the field to filter.
+        field_ind: usize,
+        /// Operand to compare against.
+        operand: FilterOperand,
+    },
+    /// Compares the values of two expressions over the fields of the tuples.
+    Expressions {
+        /// Operation used to compare.
+        op: PredicateOp,
+        /// Left side of the comparison.
+        left: Expression,
+        /// Right side of the comparison.
+        right: Expression,
+    },
+    /// Passes tuples that pass all of the predicates.
+    And(Vec<FilterPredicate>),
+    /// Passes tuples that pass any of the predicates.
+    Or(Vec<FilterPredicate>),
 }
 
 impl FilterPredicate {
@@ -19,22 +43,78 @@ impl FilterPredicate {
     /// * `op` - The operation to apply (as defined in common-old::PredicateOp)
     /// * `field_ind` - Field index to compare against
     /// * `operand` - Field value to compare passed in tuples to    
-    fn new(op: PredicateOp, field_ind: usize, operand: Field) -> Self {
-        Self {
+    pub fn new(op: PredicateOp, field_ind: usize, operand: Field) -> Self {
+        FilterPredicate::Compare {
             op,
             field_ind,
-            operand,
+            operand: FilterOperand::Literal(operand),
+        }
+    }
+
+    /// Creates a predicate comparing two fields of the same tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation used to compare.
+    /// * `left_ind` - Index of the left field.
+    /// * `right_ind` - Index of the right field.
+    pub fn fields(op: PredicateOp, left_ind: usize, right_ind: usize) -> Self {
+        FilterPredicate::Compare {
+            op,
+            field_ind: left_ind,
+            operand: FilterOperand::Field(right_ind),
         }
     }
 
+    /// Creates a predicate comparing the values of two expressions over the tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation used to compare.
+    /// * `left` - Left side of the comparison.
+    /// * `right` - Right side of the comparison.
+    pub fn expressions(op: PredicateOp, left: Expression, right: Expression) -> Self {
+        FilterPredicate::Expressions { op, left, right }
+    }
+
     /// Apply the predicate to the specified tuple.
     ///
     /// # Arguments
     ///
     /// * `tuple` - Tuple to apply the filter to.
-    fn filter(&self, tuple: &Tuple) -> bool {
-        let field = tuple.get_field(self.field_ind).unwrap();
-        self.op.compare(field, &self.operand)
+    fn filter(&self, tuple: &Tuple) -> Result<bool, CrustyError> {
+        match self {
+            FilterPredicate::Compare {
+                op,
+                field_ind,
+                operand,
+            } => {
+                let operand = match operand {
+                    FilterOperand::Literal(field) => field,
+                    FilterOperand::Field(i) => tuple.field(*i)?,
+                };
+                op.compare(tuple.field(*field_ind)?, operand)
+            }
+            FilterPredicate::Expressions { op, left, right } => {
+                op.compare(&left.eval(tuple)?, &right.eval(tuple)?)
+            }
+            FilterPredicate::And(preds) => {
+                for pred in preds {
+                    if !pred.filter(tuple)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            FilterPredicate::Or(preds) => {
+                for pred in preds {
+                    if pred.filter(tuple)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
     }
 }
 
@@ -51,11 +131,13 @@ pub struct Filter {
 }
 
 impl Filter {
-    /// Filter constructor.
+    /// Filter constructor, for a single comparison.
     ///
     /// # Arguments
     ///
-    /// * `predicate` - Predicate to filter by.
+    /// * `op` - Operation used to compare.
+    /// * `field_ind` - Index of the field to filter.
+    /// * `operand` - Operand to compare against.
     /// * `child` - Child OpIterator passing data into the operator.
     pub fn new(
         op: PredicateOp,
@@ -63,8 +145,18 @@ impl Filter {
         operand: Field,
         child: Box<dyn OpIterator>,
     ) -> Self {
+        Self::with_predicate(FilterPredicate::new(op, field_ind, operand), child)
+    }
+
+    /// Filter constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Predicate to filter by.
+    /// * `child` - Child OpIterator passing data into the operator.
+    pub fn with_predicate(predicate: FilterPredicate, child: Box<dyn OpIterator>) -> Self {
         Self {
-            predicate: FilterPredicate::new(op, field_ind, operand),
+            predicate,
             schema: child.get_schema().clone(),
             open: false,
             child,
@@ -85,7 +177,7 @@ impl OpIterator for Filter {
 
         let mut res = None;
         while let Some(t) = self.child.next()? {
-            if self.predicate.filter(&t) {
+            if self.predicate.filter(&t)? {
                 res = Some(t);
                 break;
             }
@@ -93,19 +185,39 @@ impl OpIterator for Filter {
         Ok(res)
     }
 
+    /// Filters the batches of the child, skipping those with no tuple left.
+    fn next_batch(&mut self) -> Result<Option<Vec<Tuple>>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        while let Some(batch) = self.child.next_batch()? {
+            let mut kept = Vec::with_capacity(batch.len());
+            for t in batch {
+                if self.predicate.filter(&t)? {
+                    kept.push(t);
+                }
+            }
+            if !kept.is_empty() {
+                return Ok(Some(kept));
+            }
+        }
+        Ok(None)
+    }
+
     fn close(&mut self) -> Result<(), CrustyError> {
-        self.child.close()?;
         self.open = false;
-        Ok(())
+        self.child.close()
     }
 
     fn rewind(&mut self) -> Result<(), CrustyError> {
         if !self.open {
             panic!("Operator has not been opened")
         }
-        self.child.rewind()?;
-        self.close()?;
-        self.open()
+        self.child.rewind()
+    }
+
+    fn hold_pages(&mut self) {
+        self.child.hold_pages();
     }
 
     fn get_schema(&self) -> &TableSchema {
@@ -118,6 +230,7 @@ mod test {
     use super::super::TupleIterator;
     use super::*;
     use crate::opiterator::testutil::*;
+    use common::logical_plan::ScalarFunction;
     use common::testutil::*;
 
     const WIDTH: usize = 3;
@@ -203,6 +316,129 @@ mod test {
         filter.close()
     }
 
+    #[test]
+    fn test_next_batch() -> Result<(), CrustyError> {
+        let ti = mock_ti(0, 200, WIDTH);
+        let mut filter = Filter::new(
+            PredicateOp::GreaterThanOrEq,
+            0,
+            Field::IntField(100),
+            Box::new(ti),
+        );
+        filter.open()?;
+        // the child's first batch has no tuple left and is skipped
+        let batch = filter.next_batch()?.unwrap();
+        assert_eq!(&tuple_repeat_field(100, WIDTH), batch.first().unwrap());
+        let mut count = batch.len();
+        assert_eq!(
+            Some(tuple_repeat_field(count as i32 + 100, WIDTH)),
+            filter.next()?
+        );
+        count += 1;
+        while let Some(batch) = filter.next_batch()? {
+            assert!(!batch.is_empty());
+            count += batch.len();
+        }
+        assert_eq!(100, count);
+        filter.close()
+    }
+
+    #[test]
+    fn test_string_predicates() -> Result<(), CrustyError> {
+        let names = vec!["apple", "café", "cafe", "o'hare", "日本語"];
+        let tuples: Vec<Tuple> = names
+            .iter()
+            .map(|n| Tuple::new(vec![Field::StringField(n.to_string())]))
+            .collect();
+        let schema = TableSchema::new(vec![common::Attribute::new(
+            String::from("name"),
+            common::DataType::String,
+        )]);
+        let run = |op, operand: &str| -> Result<Vec<String>, CrustyError> {
+            let ti = TupleIterator::new(tuples.clone(), schema.clone());
+            let operand = Field::StringField(operand.to_string());
+            let mut filter = Filter::new(op, 0, operand, Box::new(ti));
+            filter.open()?;
+            let mut res = Vec::new();
+            while let Some(t) = filter.next()? {
+                res.push(t.get_string("name", &schema)?.to_string());
+            }
+            Ok(res)
+        };
+        assert_eq!(vec!["café"], run(PredicateOp::Equals, "café")?);
+        assert_eq!(vec!["o'hare"], run(PredicateOp::Equals, "o'hare")?);
+        // Strings compare by code point, so multi-byte characters sort after ASCII.
+        assert_eq!(vec!["日本語"], run(PredicateOp::GreaterThan, "z")?);
+        assert_eq!(
+            vec!["café", "o'hare", "日本語"],
+            run(PredicateOp::GreaterThan, "cafe")?
+        );
+        assert_eq!(vec!["apple"], run(PredicateOp::Like, "a%")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_types() -> Result<(), CrustyError> {
+        // an integer is not ordered before a string, it can't be compared to one
+        let ti = TupleIterator::new(create_tuple_list(vec![vec![9]]), get_int_table_schema(1));
+        let operand = Field::StringField(String::from("10"));
+        let mut filter = Filter::new(PredicateOp::LessThan, 0, operand, Box::new(ti));
+        filter.open()?;
+        assert!(filter.next().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compound_predicate() -> Result<(), CrustyError> {
+        // -4 < a AND (a < -2 OR a >= 3)
+        let predicate = FilterPredicate::And(vec![
+            FilterPredicate::new(PredicateOp::GreaterThan, 0, Field::IntField(-4)),
+            FilterPredicate::Or(vec![
+                FilterPredicate::new(PredicateOp::LessThan, 1, Field::IntField(-2)),
+                FilterPredicate::new(PredicateOp::GreaterThanOrEq, 2, Field::IntField(3)),
+            ]),
+        ]);
+        let mut filter = Filter::with_predicate(predicate, Box::new(mock_ti(-5, 5, WIDTH)));
+        filter.open()?;
+        let mut res = Vec::new();
+        while let Some(t) = filter.next()? {
+            res.push(t.get_field(0).unwrap().as_int().unwrap());
+        }
+        assert_eq!(vec![-3, 3, 4], res);
+        Ok(())
+    }
+
+    #[test]
+    fn test_field_predicate() -> Result<(), CrustyError> {
+        let tuples = create_tuple_list(vec![vec![1, 2], vec![3, 3], vec![5, 4], vec![0, 9]]);
+        let ti = TupleIterator::new(tuples, get_int_table_schema(2));
+        let predicate = FilterPredicate::fields(PredicateOp::LessThanOrEq, 0, 1);
+        let mut filter = Filter::with_predicate(predicate, Box::new(ti));
+        filter.open()?;
+        let mut res = Vec::new();
+        while let Some(t) = filter.next()? {
+            res.push(t.get_field(0).unwrap().as_int().unwrap());
+        }
+        assert_eq!(vec![1, 3, 0], res);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expression_predicate() -> Result<(), CrustyError> {
+        // ABS(a) >= 4
+        let abs = Expression::function(ScalarFunction::Abs, Expression::Field(0));
+        let four = Expression::Literal(Field::IntField(4));
+        let predicate = FilterPredicate::expressions(PredicateOp::GreaterThanOrEq, abs, four);
+        let mut filter = Filter::with_predicate(predicate, Box::new(mock_ti(-5, 5, WIDTH)));
+        filter.open()?;
+        let mut res = Vec::new();
+        while let Some(t) = filter.next()? {
+            res.push(t.get_field(0).unwrap().as_int().unwrap());
+        }
+        assert_eq!(vec![-5, -4, 4], res);
+        Ok(())
+    }
+
     #[test]
     fn test_some_less_than() -> Result<(), CrustyError> {
         let mut filter = get_filter(0, PredicateOp::LessThan, Field::IntField(2));
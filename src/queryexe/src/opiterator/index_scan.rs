@@ -0,0 +1,277 @@
+use super::{OpIterator, SeqScan};
+use crate::StorageManager;
+use common::ids::{ContainerId, Permissions, TransactionId, ValueId};
+use common::logical_plan::PredicateOp;
+use common::storage_trait::StorageTrait;
+use common::table::Table;
+use common::{CrustyError, Field, TableSchema, Tuple};
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+
+/// Keys of an index an index scan reads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyRange {
+    pub low: Bound<Field>,
+    pub high: Bound<Field>,
+}
+
+impl KeyRange {
+    /// Every key but NULL.
+    pub fn not_null() -> Self {
+        Self {
+            low: Bound::Excluded(Field::Null),
+            high: Bound::Unbounded,
+        }
+    }
+
+    /// Keys a comparison of the key with a value holds for, None if the comparison can't be
+    /// answered with a range of keys.
+    ///
+    /// A comparison with NULL holds for no key, so the keys of the other comparisons exclude
+    /// NULL too. Only `IS NULL` reads the NULL keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Comparison of the key with the value, the key on the left.
+    /// * `value` - Value the key is compared with, not NULL.
+    pub fn from_comparison(op: PredicateOp, value: &Field) -> Option<Self> {
+        let (low, high) = match op {
+            PredicateOp::Equals => (
+                Bound::Included(value.clone()),
+                Bound::Included(value.clone()),
+            ),
+            PredicateOp::LessThan => (Bound::Unbounded, Bound::Excluded(value.clone())),
+            PredicateOp::LessThanOrEq => (Bound::Unbounded, Bound::Included(value.clone())),
+            PredicateOp::GreaterThan => (Bound::Excluded(value.clone()), Bound::Unbounded),
+            PredicateOp::GreaterThanOrEq => (Bound::Included(value.clone()), Bound::Unbounded),
+            PredicateOp::IsNull => {
+                return Some(Self {
+                    low: Bound::Included(Field::Null),
+                    high: Bound::Included(Field::Null),
+                })
+            }
+            PredicateOp::IsNotNull => return Some(Self::not_null()),
+            _ => return None,
+        };
+        Some(Self::not_null().intersect(Self { low, high }))
+    }
+
+    /// Keys in both ranges.
+    pub fn intersect(self, other: Self) -> Self {
+        Self {
+            low: Self::tighter(self.low, other.low, Ordering::Greater),
+            high: Self::tighter(self.high, other.high, Ordering::Less),
+        }
+    }
+
+    /// The bound of `a` and `b` that lets fewer keys through: the one whose value is ordered
+    /// `keep` relative to the other, or the excluded one of equal values.
+    fn tighter(a: Bound<Field>, b: Bound<Field>, keep: Ordering) -> Bound<Field> {
+        let value = |bound: &Bound<Field>| match bound {
+            Bound::Included(v) | Bound::Excluded(v) => Some(v.clone()),
+            Bound::Unbounded => None,
+        };
+        match (value(&a), value(&b)) {
+            (None, _) => b,
+            (_, None) => a,
+            (Some(x), Some(y)) => match x.cmp(&y) {
+                Ordering::Equal if matches!(a, Bound::Excluded(_)) => a,
+                Ordering::Equal => b,
+                ord if ord == keep => a,
+                _ => b,
+            },
+        }
+    }
+
+    /// Whether the range is bounded on both ends, so likely narrower than a range bounded on
+    /// one end.
+    pub fn is_bounded(&self) -> bool {
+        !matches!(self.low, Bound::Unbounded) && !matches!(self.high, Bound::Unbounded)
+    }
+}
+
+/// Reads the rows of a table whose keys in an index are within a range, in the order of
+/// their keys.
+pub struct IndexScan {
+    storage_manager: Arc<StorageManager>,
+    schema: TableSchema,
+    /// Container of the table.
+    container_id: ContainerId,
+    /// Container of the index.
+    index_id: ContainerId,
+    range: KeyRange,
+    transaction_id: TransactionId,
+    /// Packed ids of the rows not returned yet, None if the scan is not open.
+    ids: Option<std::vec::IntoIter<u64>>,
+}
+
+impl IndexScan {
+    /// Constructor for the index scan operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - Table to read rows of.
+    /// * `table_alias` - Table alias given by the user.
+    /// * `index_id` - Container of the index on the table.
+    /// * `range` - Keys of the rows to read.
+    /// * `tid` - Transaction used to read the table.
+    pub fn new(
+        storage_manager: Arc<StorageManager>,
+        table: Arc<RwLock<Table>>,
+        table_alias: &str,
+        index_id: ContainerId,
+        range: KeyRange,
+        tid: TransactionId,
+    ) -> Self {
+        let table_ref = table.read().unwrap();
+        Self {
+            storage_manager,
+            schema: SeqScan::schema(&table_ref.schema, table_alias),
+            container_id: table_ref.id as ContainerId,
+            index_id,
+            range,
+            transaction_id: tid,
+            ids: None,
+        }
+    }
+
+    /// Looks up the ids of the rows in the index, failing if the table or index was dropped.
+    fn lookup(&self) -> Result<std::vec::IntoIter<u64>, CrustyError> {
+        for id in [self.container_id, self.index_id].iter() {
+            if !self.storage_manager.container_exists(*id) {
+                return Err(CrustyError::ExecutionError(format!(
+                    "Container {} of the scanned index no longer exists",
+                    id
+                )));
+            }
+        }
+        let ids = self.storage_manager.index_range(
+            self.index_id,
+            self.range.low.as_ref(),
+            self.range.high.as_ref(),
+            self.transaction_id,
+        )?;
+        Ok(ids.into_iter())
+    }
+}
+
+impl OpIterator for IndexScan {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.ids = Some(self.lookup()?);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let ids = match self.ids.as_mut() {
+            Some(ids) => ids,
+            None => panic!("Operator has not been opened"),
+        };
+        match ids.next() {
+            Some(packed) => {
+                let bytes = self.storage_manager.get_value(
+                    ValueId::unpack(packed)?,
+                    self.transaction_id,
+                    Permissions::ReadOnly,
+                )?;
+                Ok(Some(Tuple::from_bytes_with_schema(&bytes, &self.schema)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.ids = None;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if self.ids.is_none() {
+            panic!("Operator has not been opened")
+        }
+        self.ids = Some(self.lookup()?);
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::opiterator::testutil::sum_int_fields;
+    use common::storage_trait::ContainerType;
+    use common::testutil::*;
+
+    fn range(op: PredicateOp, value: i32) -> KeyRange {
+        KeyRange::from_comparison(op, &Field::IntField(value)).unwrap()
+    }
+
+    #[test]
+    fn test_key_range() {
+        let five = Field::IntField(5);
+        assert_eq!(
+            KeyRange {
+                low: Bound::Excluded(Field::Null),
+                high: Bound::Excluded(five.clone()),
+            },
+            range(PredicateOp::LessThan, 5)
+        );
+        // 3 <= a AND a < 5 AND a <= 5
+        let between = range(PredicateOp::GreaterThanOrEq, 3)
+            .intersect(range(PredicateOp::LessThan, 5))
+            .intersect(range(PredicateOp::LessThanOrEq, 5));
+        assert_eq!(Bound::Included(Field::IntField(3)), between.low);
+        assert_eq!(Bound::Excluded(five.clone()), between.high);
+        assert!(between.is_bounded());
+        assert!(!range(PredicateOp::GreaterThan, 5).is_bounded());
+        assert!(KeyRange::from_comparison(PredicateOp::NotEq, &five).is_none());
+        assert!(KeyRange::from_comparison(PredicateOp::Like, &five).is_none());
+    }
+
+    #[test]
+    fn test_index_scan() -> Result<(), CrustyError> {
+        let schema = get_int_table_schema(2);
+        let table = Arc::new(RwLock::new(Table::new(String::from("IndexScan"), schema)));
+        let table_id = table.read().unwrap().id as ContainerId;
+        let index_id = table_id.wrapping_add(1);
+        let sm = Arc::new(StorageManager::new_test_sm());
+        sm.create_container(table_id, None, ContainerType::Table)?;
+        sm.create_container(index_id, None, ContainerType::Index)?;
+        let tid = TransactionId::new();
+        let rows = (0..20).map(|i| int_vec_to_tuple(vec![i % 10, i]).get_bytes());
+        let ids = sm.insert_values(table_id, rows.collect(), tid);
+        let entries = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (Field::IntField(i as i32 % 10), id.pack()))
+            .collect();
+        sm.index_insert(index_id, entries, tid)?;
+
+        // 7 <= a, two rows for every key
+        let mut scan = IndexScan::new(
+            sm.clone(),
+            table,
+            "t",
+            index_id,
+            range(PredicateOp::GreaterThanOrEq, 7),
+            tid,
+        );
+        assert_eq!(2, scan.get_schema().size());
+        scan.open()?;
+        assert_eq!(
+            2 * (7 + 8 + 9) + (7 + 8 + 9 + 17 + 18 + 19),
+            sum_int_fields(&mut scan)?
+        );
+        scan.rewind()?;
+        let first = scan.next()?.unwrap();
+        assert_eq!(Some(&Field::IntField(7)), first.get_field(0));
+        scan.close()?;
+
+        sm.remove_container(index_id)?;
+        assert!(scan.open().is_err());
+        Ok(())
+    }
+}
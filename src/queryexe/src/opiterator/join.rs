@@ -1,17 +1,96 @@
-use super::{OpIterator, TupleIterator};
+use super::{close_both, Exchange, OpIterator, PartitionFactory};
 use common::{CrustyError, Field, PredicateOp, TableSchema, Tuple};
-use std::collections::HashMap;
-
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// Tuples of the build input of a hash join by the value of their join field.
+type HashTable = HashMap<Field, Vec<Tuple>>;
+
+/// Joins a probed tuple with its matches in a hash table.
+///
+/// # Arguments
+///
+/// * `table` - Hash table of the build input.
+/// * `probe` - Tuple of the probed input.
+/// * `index` - Index of the join field of the probed tuple.
+/// * `build_left` - Whether the hash table holds the left input.
+/// * `pending` - Queue the joined tuples are added to.
+fn probe_table(
+    table: &HashTable,
+    probe: &Tuple,
+    index: usize,
+    build_left: bool,
+    pending: &mut VecDeque<Tuple>,
+) -> Result<(), CrustyError> {
+    // NULL keys were left out of the table, so they find no match
+    if let Some(matches) = table.get(probe.field(index)?) {
+        for m in matches {
+            let joined = if build_left {
+                m.merge(probe)
+            } else {
+                probe.merge(m)
+            };
+            pending.push_back(joined);
+        }
+    }
+    Ok(())
+}
 
 /// Compares the fields of two tuples using a predicate.
 pub struct JoinPredicate {
+    /// Operation to compare the fields with.
+    op: PredicateOp,
+    /// Index of the field of the left tuple.
+    left_index: usize,
+    /// Index of the field of the right tuple.
+    right_index: usize,
 }
 
+impl JoinPredicate {
+    /// Constructor that determines if two tuples satisfy the join condition.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation to compare the two fields with.
+    /// * `left_index` - Index of the field to compare in the left tuple.
+    /// * `right_index` - Index of the field to compare in the right tuple.
+    pub fn new(op: PredicateOp, left_index: usize, right_index: usize) -> Self {
+        Self {
+            op,
+            left_index,
+            right_index,
+        }
+    }
+
+    /// Whether a pair of tuples satisfies the join condition, see `PredicateOp::compare`.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Tuple of the left child.
+    /// * `right` - Tuple of the right child.
+    pub fn matches(&self, left: &Tuple, right: &Tuple) -> Result<bool, CrustyError> {
+        self.op
+            .compare(left.field(self.left_index)?, right.field(self.right_index)?)
+    }
+}
 
 /// Nested loop join implementation.
+///
+/// The right child is read once for each tuple of the left child, and rewound after it runs
+/// out. The output follows the order of the left child, then of the right one.
 pub struct Join {
+    /// Join condition.
+    predicate: JoinPredicate,
+    /// Left tuple being joined with the right child.
+    left_tuple: Option<Tuple>,
     /// Schema of the result.
     schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Left child, read once.
+    left_child: Box<dyn OpIterator>,
+    /// Right child, read once per left tuple. Holds its pages so they aren't read again.
+    right_child: Box<dyn OpIterator>,
 }
 
 impl Join {
@@ -25,27 +104,73 @@ impl Join {
     /// * `left_child` - Left child of join operator.
     /// * `right_child` - Left child of join operator.
     pub fn new(
+        op: PredicateOp,
+        left_index: usize,
+        right_index: usize,
+        left_child: Box<dyn OpIterator>,
+        mut right_child: Box<dyn OpIterator>,
     ) -> Self {
-        panic!("TODO milestone op");
+        right_child.hold_pages();
+        Self {
+            predicate: JoinPredicate::new(op, left_index, right_index),
+            left_tuple: None,
+            schema: left_child.get_schema().merge(right_child.get_schema()),
+            open: false,
+            left_child,
+            right_child,
+        }
     }
 }
 
 impl OpIterator for Join {
     fn open(&mut self) -> Result<(), CrustyError> {
-        panic!("TODO milestone op");
+        self.left_child.open()?;
+        self.right_child.open()?;
+        self.left_tuple = None;
+        self.open = true;
+        Ok(())
     }
 
     /// Calculates the next tuple for a nested loop join.
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
-        panic!("TODO milestone op");
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        loop {
+            if self.left_tuple.is_none() {
+                self.left_tuple = self.left_child.next()?;
+                if self.left_tuple.is_none() {
+                    return Ok(None);
+                }
+            }
+            match self.right_child.next()? {
+                Some(right) => {
+                    let left = self.left_tuple.as_ref().unwrap();
+                    if self.predicate.matches(left, &right)? {
+                        return Ok(Some(left.merge(&right)));
+                    }
+                }
+                None => {
+                    self.right_child.rewind()?;
+                    self.left_tuple = None;
+                }
+            }
+        }
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
-        panic!("TODO milestone op");
+        self.left_tuple = None;
+        self.open = false;
+        close_both(self.left_child.as_mut(), self.right_child.as_mut())
     }
 
     fn rewind(&mut self) -> Result<(), CrustyError> {
-        panic!("TODO milestone op");
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left_tuple = None;
+        self.left_child.rewind()?;
+        self.right_child.rewind()
     }
 
     fn get_schema(&self) -> &TableSchema {
@@ -54,8 +179,41 @@ impl OpIterator for Join {
 }
 
 /// Hash equi-join implementation.
+///
+/// The hash table is built on the smaller input, found by reading both children in turn until
+/// one of them runs out, and the other input is probed against it. The tuples of the larger input
+/// read while looking are probed first. The output follows the order of the probed input.
+///
+/// With `with_partitions`, one input is probed in parts on threads of their own, see
+/// `HashEqJoin::with_partitions`.
 pub struct HashEqJoin {
+    /// Operation in join condition, must be equality.
+    op: PredicateOp,
+    /// Index of the left field in join condition.
+    left_index: usize,
+    /// Index of the right field in join condition.
+    right_index: usize,
+    /// Tuples of the smaller input by the value of their join field.
+    table: Arc<HashTable>,
+    /// Whether the hash table holds the left input.
+    build_left: bool,
+    /// Tuples of the probed input read while looking for the smaller input.
+    probe_buffer: VecDeque<Tuple>,
+    /// Joined tuples of the last probed tuple not returned yet.
+    pending: VecDeque<Tuple>,
+    /// Number of parts of the probed input, the factory of their operators, and whether they
+    /// are parts of the left input.
+    partitions: Option<(usize, PartitionFactory, bool)>,
+    /// Joins the parts of the probed input with the hash table, once it is built.
+    exchange: Option<Exchange>,
+    /// Schema of the result.
     schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Left child of the join.
+    left_child: Box<dyn OpIterator>,
+    /// Right child of the join.
+    right_child: Box<dyn OpIterator>,
 }
 
 impl HashEqJoin {
@@ -68,28 +226,290 @@ impl HashEqJoin {
     /// * `right_index` - Index of the right field in join condition.
     /// * `left_child` - Left child of join operator.
     /// * `right_child` - Left child of join operator.
-    #[allow(dead_code)]
     pub fn new(
+        op: PredicateOp,
+        left_index: usize,
+        right_index: usize,
+        left_child: Box<dyn OpIterator>,
+        right_child: Box<dyn OpIterator>,
+    ) -> Self {
+        Self {
+            op,
+            left_index,
+            right_index,
+            table: Arc::default(),
+            build_left: true,
+            probe_buffer: VecDeque::new(),
+            pending: VecDeque::new(),
+            partitions: None,
+            exchange: None,
+            schema: left_child.get_schema().merge(right_child.get_schema()),
+            open: false,
+            left_child,
+            right_child,
+        }
+    }
+
+    /// Probes the parts of one input on worker threads, one per part, against a hash table
+    /// built on the whole other input. The joined tuples of part 0 come first, then those of
+    /// part 1 and so on, so the output follows the order of the probed input as without parts.
+    /// The child of the probed input is not read.
+    ///
+    /// # Arguments
+    ///
+    /// * `partitions` - Number of parts of the probed input.
+    /// * `factory` - Builds the operators of a part, whose tuples have the schema of the child.
+    /// * `left` - Whether the parts are parts of the left input.
+    pub fn with_partitions(
+        mut self,
+        partitions: usize,
+        factory: PartitionFactory,
+        left: bool,
     ) -> Self {
-        panic!("TODO milestone op");
+        self.partitions = Some((partitions, factory, left));
+        self
+    }
+
+    /// Reads both children until one runs out and builds the hash table on that one.
+    fn build(&mut self) -> Result<(), CrustyError> {
+        if let Some((partitions, factory, probe_left)) = self.partitions.clone() {
+            return self.build_partitioned(partitions, factory, probe_left);
+        }
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+        let (mut left_done, mut right_done) = (false, false);
+        while !left_done && !right_done {
+            match self.left_child.next()? {
+                Some(t) => left.push(t),
+                None => left_done = true,
+            }
+            match self.right_child.next()? {
+                Some(t) => right.push(t),
+                None => right_done = true,
+            }
+        }
+        self.build_left = left_done;
+        let (build, probe, index) = if left_done {
+            (left, right, self.left_index)
+        } else {
+            (right, left, self.right_index)
+        };
+        self.table = Arc::new(Self::hash(build, index)?);
+        self.probe_buffer = probe.into();
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Builds the hash table on the whole input that is not probed in parts, and the
+    /// exchange probing the parts of the other one.
+    fn build_partitioned(
+        &mut self,
+        partitions: usize,
+        factory: PartitionFactory,
+        probe_left: bool,
+    ) -> Result<(), CrustyError> {
+        let (build_child, build_index, probe_index) = if probe_left {
+            (&mut self.right_child, self.right_index, self.left_index)
+        } else {
+            (&mut self.left_child, self.left_index, self.right_index)
+        };
+        let mut build = Vec::new();
+        while let Some(t) = build_child.next()? {
+            build.push(t);
+        }
+        self.build_left = !probe_left;
+        self.table = Arc::new(Self::hash(build, build_index)?);
+        self.probe_buffer.clear();
+        self.pending.clear();
+        let (table, build_left) = (self.table.clone(), self.build_left);
+        let schema = self.schema.clone();
+        let probe: PartitionFactory = Arc::new(move |partition| {
+            let probe = HashProbe {
+                table: table.clone(),
+                index: probe_index,
+                build_left,
+                pending: VecDeque::new(),
+                schema: schema.clone(),
+                child: factory(partition)?,
+            };
+            Ok(Box::new(probe) as Box<dyn OpIterator>)
+        });
+        let mut exchange = Exchange::new(partitions, probe, self.schema.clone());
+        exchange.open()?;
+        self.exchange = Some(exchange);
+        Ok(())
+    }
+
+    /// Hash table of the tuples of an input.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuples` - Tuples of the input.
+    /// * `index` - Index of the join field of the input.
+    fn hash(tuples: Vec<Tuple>, index: usize) -> Result<HashTable, CrustyError> {
+        let mut table = HashTable::new();
+        for t in tuples {
+            let key = t.field(index)?.clone();
+            // NULL equals nothing, not even another NULL
+            if !key.is_null() {
+                table.entry(key).or_insert_with(Vec::new).push(t);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Child whose tuples are probed against the hash table.
+    fn probe_child(&mut self) -> &mut Box<dyn OpIterator> {
+        if self.build_left {
+            &mut self.right_child
+        } else {
+            &mut self.left_child
+        }
     }
 }
 
 impl OpIterator for HashEqJoin {
     fn open(&mut self) -> Result<(), CrustyError> {
-        panic!("TODO milestone op");
+        if !matches!(self.op, PredicateOp::Equals) {
+            return Err(CrustyError::ExecutionError(String::from(
+                "Hash join needs an equality predicate",
+            )));
+        }
+        // tuples are matched by hashing their join fields, so the comparison of fields of
+        // different types that `PredicateOp::compare` rejects has to be caught up front
+        let left = self.left_child.get_schema().get_attribute(self.left_index);
+        let right = self
+            .right_child
+            .get_schema()
+            .get_attribute(self.right_index);
+        if let (Some(l), Some(r)) = (left, right) {
+            if l.dtype() != r.dtype() {
+                return Err(CrustyError::ExecutionError(format!(
+                    "Cannot join {} of type {:?} with {} of type {:?}",
+                    l.name(),
+                    l.dtype(),
+                    r.name(),
+                    r.dtype()
+                )));
+            }
+        }
+        self.left_child.open()?;
+        self.right_child.open()?;
+        self.build()?;
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        if let Some(exchange) = &mut self.exchange {
+            return exchange.next();
+        }
+        loop {
+            if let Some(t) = self.pending.pop_front() {
+                return Ok(Some(t));
+            }
+            let probe = match self.probe_buffer.pop_front() {
+                Some(t) => t,
+                None => match self.probe_child().next()? {
+                    Some(t) => t,
+                    None => return Ok(None),
+                },
+            };
+            let index = if self.build_left {
+                self.right_index
+            } else {
+                self.left_index
+            };
+            probe_table(
+                &self.table,
+                &probe,
+                index,
+                self.build_left,
+                &mut self.pending,
+            )?;
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if let Some(mut exchange) = self.exchange.take() {
+            exchange.close()?;
+        }
+        self.table = Arc::default();
+        self.probe_buffer.clear();
+        self.pending.clear();
+        self.open = false;
+        close_both(self.left_child.as_mut(), self.right_child.as_mut())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        // the hash table is kept, only the probed input is read again
+        if let Some(exchange) = &mut self.exchange {
+            return exchange.rewind();
+        }
+        self.probe_buffer.clear();
+        self.pending.clear();
+        self.probe_child().rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+/// Joins the tuples of a part of the probed input of a hash join with its hash table, on the
+/// worker thread of the part.
+struct HashProbe {
+    /// Hash table shared by the parts.
+    table: Arc<HashTable>,
+    /// Index of the join field of the probed input.
+    index: usize,
+    /// Whether the hash table holds the left input.
+    build_left: bool,
+    /// Joined tuples of the last probed tuple not returned yet.
+    pending: VecDeque<Tuple>,
+    /// Schema of the result of the join.
+    schema: TableSchema,
+    /// Operators of the part.
+    child: Box<dyn OpIterator>,
+}
+
+impl OpIterator for HashProbe {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()
     }
 
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
-        panic!("TODO milestone op");
+        loop {
+            if let Some(t) = self.pending.pop_front() {
+                return Ok(Some(t));
+            }
+            match self.child.next()? {
+                Some(probe) => probe_table(
+                    &self.table,
+                    &probe,
+                    self.index,
+                    self.build_left,
+                    &mut self.pending,
+                )?,
+                None => return Ok(None),
+            }
+        }
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
-        panic!("TODO milestone op");
+        self.pending.clear();
+        self.child.close()
     }
 
     fn rewind(&mut self) -> Result<(), CrustyError> {
-        panic!("TODO milestone op");
+        self.pending.clear();
+        self.child.rewind()
     }
 
     fn get_schema(&self) -> &TableSchema {
@@ -101,6 +521,7 @@ impl OpIterator for HashEqJoin {
 mod test {
     use super::*;
     use crate::opiterator::testutil::*;
+    use crate::opiterator::{Filter, TupleIterator};
     use common::testutil::*;
 
     const WIDTH1: usize = 2;
@@ -165,10 +586,8 @@ mod test {
         let s1 = Box::new(scan1());
         let s2 = Box::new(scan2());
         match ty {
-            JoinType::NestedLoop => Box::new(Join::new(
-            )),
-            JoinType::HashEq => Box::new(HashEqJoin::new(
-            )),
+            JoinType::NestedLoop => Box::new(Join::new(op, left_index, right_index, s1, s2)),
+            JoinType::HashEq => Box::new(HashEqJoin::new(op, left_index, right_index, s1, s2)),
         }
     }
 
@@ -254,6 +673,20 @@ mod test {
         fn gt_join() -> Result<(), CrustyError> {
             test_gt_join(JoinType::NestedLoop)
         }
+
+        #[test]
+        fn holds_right_pages() -> Result<(), CrustyError> {
+            let (left, left_held) = HoldSpy::new(Box::new(scan1()));
+            let (right, right_held) = HoldSpy::new(Box::new(scan2()));
+            // through the filter, which passes it on
+            let right = Filter::new(PredicateOp::GreaterThan, 0, Field::IntField(0), Box::new(right));
+            let mut op = Join::new(PredicateOp::Equals, 0, 0, Box::new(left), Box::new(right));
+            assert!(!left_held.get());
+            assert!(right_held.get());
+            op.open()?;
+            assert_eq!(3, num_tuples(&mut op)?);
+            op.close()
+        }
     }
 
     mod hash_join {
@@ -285,5 +718,130 @@ mod test {
         fn eq_join() -> Result<(), CrustyError> {
             test_eq_join(JoinType::HashEq)
         }
+
+        #[test]
+        fn build_right() -> Result<(), CrustyError> {
+            // the right input is smaller, so the output follows the order of the left one
+            let left = create_tuple_list(vec![vec![5, 0], vec![3, 1], vec![1, 2], vec![3, 3]]);
+            let right = create_tuple_list(vec![vec![3, 30], vec![5, 50]]);
+            let mut op = HashEqJoin::new(
+                PredicateOp::Equals,
+                0,
+                0,
+                Box::new(TupleIterator::new(left, get_int_table_schema(2))),
+                Box::new(TupleIterator::new(right, get_int_table_schema(2))),
+            );
+            op.open()?;
+            let expected = TupleIterator::new(
+                create_tuple_list(vec![
+                    vec![5, 0, 5, 50],
+                    vec![3, 1, 3, 30],
+                    vec![3, 3, 3, 30],
+                ]),
+                get_int_table_schema(4),
+            );
+            let mut expected = Box::new(expected);
+            expected.open()?;
+            match_all_tuples(Box::new(op), expected)
+        }
+
+        #[test]
+        fn null_keys() -> Result<(), CrustyError> {
+            let tuples = || {
+                vec![
+                    Tuple::new(vec![Field::Null, Field::IntField(0)]),
+                    Tuple::new(vec![Field::IntField(1), Field::IntField(1)]),
+                ]
+            };
+            let mut op = HashEqJoin::new(
+                PredicateOp::Equals,
+                0,
+                0,
+                Box::new(TupleIterator::new(tuples(), get_int_table_schema(2))),
+                Box::new(TupleIterator::new(tuples(), get_int_table_schema(2))),
+            );
+            op.open()?;
+            let expected = TupleIterator::new(
+                create_tuple_list(vec![vec![1, 1, 1, 1]]),
+                get_int_table_schema(4),
+            );
+            let mut expected = Box::new(expected);
+            expected.open()?;
+            match_all_tuples(Box::new(op), expected)
+        }
+
+        #[test]
+        fn partitions() -> Result<(), CrustyError> {
+            // rows (i % 7, i) for i in 0..500 probed in 3 parts, against (k, -k) for k in 0..5
+            let part = |p: i32| -> Vec<Vec<i32>> {
+                (500 * p / 3..500 * (p + 1) / 3)
+                    .map(|i| vec![i % 7, i])
+                    .collect()
+            };
+            let factory: PartitionFactory = Arc::new(move |p| {
+                let rows = create_tuple_list(part(p as i32));
+                let child = TupleIterator::new(rows, get_int_table_schema(2));
+                Ok(Box::new(child) as Box<dyn OpIterator>)
+            });
+            let build = || {
+                let rows = create_tuple_list((0..5).map(|k| vec![k, -k]).collect());
+                Box::new(TupleIterator::new(rows, get_int_table_schema(2)))
+            };
+            let expected: Vec<Vec<i32>> = (0..500)
+                .filter(|i| i % 7 < 5)
+                .map(|i| vec![i % 7, i, i % 7, -(i % 7)])
+                .collect();
+            for probe_left in [true, false] {
+                let probe = Box::new(Exchange::new(3, factory.clone(), get_int_table_schema(2)));
+                let (left, right, rows): (Box<dyn OpIterator>, Box<dyn OpIterator>, _) =
+                    if probe_left {
+                        (probe, build(), expected.clone())
+                    } else {
+                        // the build input comes first in the output
+                        let rows = expected.iter().map(|r| vec![r[2], r[3], r[0], r[1]]);
+                        (build(), probe, rows.collect())
+                    };
+                let mut op = HashEqJoin::new(PredicateOp::Equals, 0, 0, left, right)
+                    .with_partitions(3, factory.clone(), probe_left);
+                op.open()?;
+                for _ in 0..2 {
+                    let mut expected = TupleIterator::new(
+                        create_tuple_list(rows.clone()),
+                        get_int_table_schema(4),
+                    );
+                    expected.open()?;
+                    let mut actual = Vec::new();
+                    while let Some(t) = op.next()? {
+                        actual.push(t);
+                    }
+                    assert_eq!(rows.len(), actual.len());
+                    for t in actual {
+                        assert_eq!(Some(t), expected.next()?);
+                    }
+                    op.rewind()?;
+                }
+                op.close()?;
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn mismatched_types() {
+            let right = TupleIterator::new(
+                vec![Tuple::new(vec![Field::StringField(String::from("1"))])],
+                TableSchema::new(vec![common::Attribute::new(
+                    String::from("s"),
+                    common::DataType::String,
+                )]),
+            );
+            let mut op = HashEqJoin::new(
+                PredicateOp::Equals,
+                0,
+                0,
+                Box::new(scan1()),
+                Box::new(right),
+            );
+            assert!(op.open().is_err());
+        }
     }
 }
@@ -0,0 +1,181 @@
+use super::OpIterator;
+use common::{CrustyError, TableSchema, Tuple};
+
+/// Limit operator, for LIMIT and OFFSET.
+///
+/// Skips the first `offset` tuples of the child and returns at most `limit` of the rest. Once
+/// the limit is reached the child is not pulled from anymore, so a scan below stops early.
+pub struct LimitIterator {
+    /// Maximum number of tuples to return, None for all of them.
+    limit: Option<usize>,
+    /// Number of tuples to skip first.
+    offset: usize,
+    /// Number of tuples returned since open or rewind.
+    returned: usize,
+    /// Whether the offset tuples were skipped already.
+    skipped: bool,
+    /// Schema of the child.
+    schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Child operator passing data into operator.
+    child: Box<dyn OpIterator>,
+}
+
+impl LimitIterator {
+    /// Limit constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - Maximum number of tuples to return, None for all of them.
+    /// * `offset` - Number of tuples to skip first.
+    /// * `child` - Child OpIterator passing data into the operator.
+    pub fn new(limit: Option<usize>, offset: usize, child: Box<dyn OpIterator>) -> Self {
+        Self {
+            limit,
+            offset,
+            returned: 0,
+            skipped: false,
+            schema: child.get_schema().clone(),
+            open: false,
+            child,
+        }
+    }
+}
+
+impl OpIterator for LimitIterator {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.returned = 0;
+        self.skipped = false;
+        self.open = true;
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        if self.limit.map_or(false, |l| self.returned >= l) {
+            return Ok(None);
+        }
+        if !self.skipped {
+            self.skipped = true;
+            for _ in 0..self.offset {
+                if self.child.next()?.is_none() {
+                    return Ok(None);
+                }
+            }
+        }
+        let next = self.child.next()?;
+        if next.is_some() {
+            self.returned += 1;
+        }
+        Ok(next)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.open = false;
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.child.rewind()?;
+        self.returned = 0;
+        self.skipped = false;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TupleIterator;
+    use super::*;
+    use common::testutil::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Iterator over the values 0..n that counts how many tuples were pulled from it.
+    struct Counting {
+        child: TupleIterator,
+        pulled: Rc<Cell<usize>>,
+    }
+
+    impl OpIterator for Counting {
+        fn open(&mut self) -> Result<(), CrustyError> {
+            self.child.open()
+        }
+
+        fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+            self.pulled.set(self.pulled.get() + 1);
+            self.child.next()
+        }
+
+        fn close(&mut self) -> Result<(), CrustyError> {
+            self.child.close()
+        }
+
+        fn rewind(&mut self) -> Result<(), CrustyError> {
+            self.child.rewind()
+        }
+
+        fn get_schema(&self) -> &TableSchema {
+            self.child.get_schema()
+        }
+    }
+
+    fn get_limit(n: i32, limit: Option<usize>, offset: usize) -> (LimitIterator, Rc<Cell<usize>>) {
+        let rows = (0..n).map(|i| vec![i]).collect();
+        let pulled = Rc::new(Cell::new(0));
+        let child = Counting {
+            child: TupleIterator::new(create_tuple_list(rows), get_int_table_schema(1)),
+            pulled: pulled.clone(),
+        };
+        (LimitIterator::new(limit, offset, Box::new(child)), pulled)
+    }
+
+    fn collect(limit: &mut LimitIterator) -> Result<Vec<i32>, CrustyError> {
+        let mut values = Vec::new();
+        while let Some(t) = limit.next()? {
+            values.push(t.field(0)?.as_int()?);
+        }
+        Ok(values)
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let (mut limit, _) = get_limit(10, Some(1), 0);
+        limit.next().unwrap();
+    }
+
+    #[test]
+    fn test_limit_offset() -> Result<(), CrustyError> {
+        let (mut limit, pulled) = get_limit(100, Some(3), 5);
+        limit.open()?;
+        assert_eq!(vec![5, 6, 7], collect(&mut limit)?);
+        // the child is not pulled from past the limit
+        assert_eq!(8, pulled.get());
+        limit.rewind()?;
+        assert_eq!(vec![5, 6, 7], collect(&mut limit)?);
+        limit.close()?;
+
+        let (mut limit, _) = get_limit(10, None, 8);
+        limit.open()?;
+        assert_eq!(vec![8, 9], collect(&mut limit)?);
+        let (mut limit, _) = get_limit(10, Some(5), 20);
+        limit.open()?;
+        assert!(collect(&mut limit)?.is_empty());
+        let (mut limit, pulled) = get_limit(10, Some(0), 0);
+        limit.open()?;
+        assert!(limit.next()?.is_none());
+        assert_eq!(0, pulled.get());
+        Ok(())
+    }
+}
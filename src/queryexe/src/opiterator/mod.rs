@@ -1,19 +1,51 @@
 pub use self::aggregate::Aggregate;
-pub use self::filter::{Filter, FilterPredicate};
-pub use self::join::{Join, JoinPredicate};
+pub use self::cancel::{CancelToken, Cancellable, QueryDeadline};
+pub use self::cross_join::CrossJoinIterator;
+pub use self::distinct::DistinctIterator;
+pub use self::distinct_counter::DistinctCounter;
+pub use self::exchange::{Exchange, PartitionFactory};
+pub use self::expression::Expression;
+pub use self::external_sort::{ExternalSort, SortConfig, SortKey, SortedTuples};
+pub use self::filter::{Filter, FilterOperand, FilterPredicate};
+pub use self::index_scan::{IndexScan, KeyRange};
+pub use self::join::{HashEqJoin, Join, JoinPredicate};
+pub use self::limit::LimitIterator;
+pub use self::outer_join::OuterJoin;
+pub use self::profile::{OperatorStats, PlanProfile, Profiled};
 pub use self::project::ProjectIterator;
-pub use self::seqscan::SeqScan;
+pub use self::row_guard::RowGuard;
+pub use self::seqscan::{ScanOptions, SeqScan};
+pub use self::set_op::SetOpIterator;
+pub use self::sort::{SortIterator, SortOptions};
 pub use self::tuple_iterator::TupleIterator;
 use common::{CrustyError, TableSchema, Tuple};
 
 mod aggregate;
+mod cancel;
+mod cross_join;
+mod distinct;
+mod distinct_counter;
+mod exchange;
+mod expression;
+mod external_sort;
 mod filter;
+mod index_scan;
 mod join;
+mod limit;
+mod outer_join;
+mod profile;
 mod project;
+mod row_guard;
 mod seqscan;
+mod set_op;
+mod sort;
 mod testutil;
 mod tuple_iterator;
 
+/// Number of tuples `OpIterator::next_batch` returns at most, unless the operator batches its
+/// tuples itself.
+pub const BATCH_SIZE: usize = 64;
+
 pub trait OpIterator {
     /// Opens the iterator. This must be called before any of the other methods.
     fn open(&mut self) -> Result<(), CrustyError>;
@@ -27,18 +59,59 @@ pub trait OpIterator {
     /// Panic if iterator is not open.
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError>;
 
-    /// Closes the iterator.
+    /// Advances the iterator by several tuples at once and returns them, in the order `next`
+    /// would. Calls to `next` and `next_batch` can be mixed.
+    ///
+    /// Returns None when iteration is finished, and never an empty batch. Operators that
+    /// don't produce their tuples in batches get the default, which calls `next` up to
+    /// `BATCH_SIZE` times.
+    ///
+    /// # Panics
+    ///
+    /// Panic if iterator is not open.
+    fn next_batch(&mut self) -> Result<Option<Vec<Tuple>>, CrustyError> {
+        let mut batch = Vec::new();
+        while batch.len() < BATCH_SIZE {
+            match self.next()? {
+                Some(t) => batch.push(t),
+                None => break,
+            }
+        }
+        Ok(if batch.is_empty() { None } else { Some(batch) })
+    }
+
+    /// Closes the iterator and its children, releasing the pages, temporary containers and
+    /// memory it holds. Children are closed even if closing one of them fails.
     fn close(&mut self) -> Result<(), CrustyError>;
 
-    /// Returns the iterator to the start.
+    /// Returns the iterator to the start, so the next call to `next` returns its first tuple
+    /// again, e.g. for the inner side of a nested loop join.
     ///
-    /// Returns None when iteration is finished.
+    /// Unlike closing and opening it again, the iterator keeps what it can reuse, like the
+    /// hash table of a hash join, and rewinds its children rather than reopening them.
     ///
     /// # Panics
     ///
     /// Panic if iterator is not open.
     fn rewind(&mut self) -> Result<(), CrustyError>;
 
+    /// Keeps the pages the operator reads in the storage manager's cache until it is closed,
+    /// for an operator that is rewound over and over, like the inner side of a nested loop
+    /// join. Scans hold their pages, operators that pass their child's tuples through forward
+    /// it, and the others ignore it.
+    fn hold_pages(&mut self) {}
+
     /// Returns the schema associated with this OpIterator.
     fn get_schema(&self) -> &TableSchema;
 }
+
+/// Closes both children of a binary operator, the right one even if closing the left one
+/// fails, and returns the first error.
+pub(crate) fn close_both(
+    left: &mut dyn OpIterator,
+    right: &mut dyn OpIterator,
+) -> Result<(), CrustyError> {
+    let left = left.close();
+    let right = right.close();
+    left.and(right)
+}
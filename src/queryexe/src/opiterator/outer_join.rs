@@ -0,0 +1,334 @@
+use super::{close_both, OpIterator};
+use common::{Attribute, CrustyError, Field, JoinType, PredicateOp, TableSchema, Tuple};
+
+/// Nested loop outer join, for LEFT, RIGHT and FULL joins.
+///
+/// Returns the pairs of tuples matching the predicate like an inner join, and also the tuples of
+/// the kept sides that matched nothing, padded with NULL for each field of the other side.
+/// Unmatched right tuples are returned after all the pairs, from a last pass over the right child.
+pub struct OuterJoin {
+    /// Which unmatched tuples are returned.
+    join_type: JoinType,
+    /// Operation in join condition.
+    op: PredicateOp,
+    /// Index of the left field in join condition.
+    left_index: usize,
+    /// Index of the right field in join condition.
+    right_index: usize,
+    /// Left tuple being joined with the right child.
+    left_tuple: Option<Tuple>,
+    /// Whether the current left tuple matched a right tuple.
+    left_matched: bool,
+    /// Position of the next tuple of the right child.
+    right_pos: usize,
+    /// Whether each right tuple, by position, matched a left tuple.
+    right_matched: Vec<bool>,
+    /// Whether the pairs are done and the unmatched right tuples are being returned.
+    right_pass: bool,
+    /// Padding of unmatched right tuples.
+    left_padding: Tuple,
+    /// Padding of unmatched left tuples.
+    right_padding: Tuple,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Left child, read once.
+    left_child: Box<dyn OpIterator>,
+    /// Right child, read once per left tuple. Holds its pages so they aren't read again.
+    right_child: Box<dyn OpIterator>,
+}
+
+impl OuterJoin {
+    /// OuterJoin constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `join_type` - Which unmatched tuples are returned.
+    /// * `op` - Operation in join condition.
+    /// * `left_index` - Index of the left field in join condition.
+    /// * `right_index` - Index of the right field in join condition.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
+    pub fn new(
+        join_type: JoinType,
+        op: PredicateOp,
+        left_index: usize,
+        right_index: usize,
+        left_child: Box<dyn OpIterator>,
+        mut right_child: Box<dyn OpIterator>,
+    ) -> Self {
+        right_child.hold_pages();
+        Self {
+            join_type,
+            op,
+            left_index,
+            right_index,
+            left_tuple: None,
+            left_matched: false,
+            right_pos: 0,
+            right_matched: Vec::new(),
+            right_pass: false,
+            left_padding: Self::padding(left_child.get_schema()),
+            right_padding: Self::padding(right_child.get_schema()),
+            schema: Self::padded_schema(left_child.get_schema(), join_type.keeps_right()).merge(
+                &Self::padded_schema(right_child.get_schema(), join_type.keeps_left()),
+            ),
+            open: false,
+            left_child,
+            right_child,
+        }
+    }
+
+    /// Tuple of NULLs for the fields of a schema.
+    fn padding(schema: &TableSchema) -> Tuple {
+        Tuple::new(vec![Field::Null; schema.size()])
+    }
+
+    /// Schema of a side in the result, whose fields may be NULL if it is padded.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the side.
+    /// * `padded` - Whether unmatched tuples of the other side are padded with this one.
+    fn padded_schema(schema: &TableSchema, padded: bool) -> TableSchema {
+        TableSchema::new(
+            schema
+                .attributes()
+                .map(|a| Attribute {
+                    nullable: a.nullable() || padded,
+                    ..a.clone()
+                })
+                .collect(),
+        )
+    }
+
+    /// Forgets the tuples joined so far.
+    fn reset(&mut self) {
+        self.left_tuple = None;
+        self.left_matched = false;
+        self.right_pos = 0;
+        self.right_matched.clear();
+        self.right_pass = false;
+    }
+
+    /// Whether a right tuple has matched any left tuple.
+    fn right_was_matched(&self, pos: usize) -> bool {
+        self.right_matched.get(pos).copied().unwrap_or(false)
+    }
+}
+
+impl OpIterator for OuterJoin {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.left_child.open()?;
+        self.right_child.open()?;
+        self.reset();
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        loop {
+            if self.right_pass {
+                match self.right_child.next()? {
+                    Some(right) => {
+                        let pos = self.right_pos;
+                        self.right_pos += 1;
+                        if !self.right_was_matched(pos) {
+                            return Ok(Some(self.left_padding.merge(&right)));
+                        }
+                        continue;
+                    }
+                    None => return Ok(None),
+                }
+            }
+
+            if self.left_tuple.is_none() {
+                self.left_tuple = self.left_child.next()?;
+                self.left_matched = false;
+                if self.left_tuple.is_none() {
+                    if !self.join_type.keeps_right() {
+                        return Ok(None);
+                    }
+                    self.right_child.rewind()?;
+                    self.right_pos = 0;
+                    self.right_pass = true;
+                    continue;
+                }
+            }
+
+            match self.right_child.next()? {
+                Some(right) => {
+                    let pos = self.right_pos;
+                    self.right_pos += 1;
+                    if self.right_matched.len() <= pos {
+                        self.right_matched.push(false);
+                    }
+                    let left = self.left_tuple.as_ref().unwrap();
+                    if self
+                        .op
+                        .compare(left.field(self.left_index)?, right.field(self.right_index)?)?
+                    {
+                        self.left_matched = true;
+                        self.right_matched[pos] = true;
+                        return Ok(Some(left.merge(&right)));
+                    }
+                }
+                None => {
+                    self.right_child.rewind()?;
+                    self.right_pos = 0;
+                    let left = self.left_tuple.take().unwrap();
+                    if !self.left_matched && self.join_type.keeps_left() {
+                        return Ok(Some(left.merge(&self.right_padding)));
+                    }
+                }
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.reset();
+        self.open = false;
+        close_both(self.left_child.as_mut(), self.right_child.as_mut())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left_child.rewind()?;
+        self.right_child.rewind()?;
+        self.reset();
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::testutil::HoldSpy;
+    use super::super::TupleIterator;
+    use super::*;
+    use common::testutil::*;
+
+    /// Joins [1], [2], [3] with [2, 20], [3, 30], [3, 31], [4, 40] on equal first fields.
+    fn get_join(join_type: JoinType) -> OuterJoin {
+        let left = create_tuple_list(vec![vec![1], vec![2], vec![3]]);
+        let right = create_tuple_list(vec![vec![2, 20], vec![3, 30], vec![3, 31], vec![4, 40]]);
+        OuterJoin::new(
+            join_type,
+            PredicateOp::Equals,
+            0,
+            0,
+            Box::new(TupleIterator::new(left, get_int_table_schema(1))),
+            Box::new(TupleIterator::new(right, get_int_table_schema(2))),
+        )
+    }
+
+    /// The joined tuples, each as its fields separated by spaces.
+    fn collect(join: &mut OuterJoin) -> Result<Vec<String>, CrustyError> {
+        let mut rows = Vec::new();
+        while let Some(t) = join.next()? {
+            let fields: Vec<String> = t.field_vals().map(|f| f.to_string()).collect();
+            rows.push(fields.join(" "));
+        }
+        Ok(rows)
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let mut join = get_join(JoinType::LeftOuter);
+        join.next().unwrap();
+    }
+
+    #[test]
+    fn test_left() -> Result<(), CrustyError> {
+        let mut join = get_join(JoinType::LeftOuter);
+        assert_eq!(3, join.get_schema().size());
+        join.open()?;
+        let expected = vec!["1 NULL NULL", "2 2 20", "3 3 30", "3 3 31"];
+        assert_eq!(expected, collect(&mut join)?);
+        join.rewind()?;
+        assert_eq!(expected, collect(&mut join)?);
+        join.close()
+    }
+
+    #[test]
+    fn test_right() -> Result<(), CrustyError> {
+        let mut join = get_join(JoinType::RightOuter);
+        join.open()?;
+        let expected = vec!["2 2 20", "3 3 30", "3 3 31", "NULL 4 40"];
+        assert_eq!(expected, collect(&mut join)?);
+        join.close()
+    }
+
+    #[test]
+    fn test_full() -> Result<(), CrustyError> {
+        let mut join = get_join(JoinType::FullOuter);
+        join.open()?;
+        let expected = vec!["1 NULL NULL", "2 2 20", "3 3 30", "3 3 31", "NULL 4 40"];
+        assert_eq!(expected, collect(&mut join)?);
+        join.rewind()?;
+        assert_eq!(expected, collect(&mut join)?);
+        join.close()
+    }
+
+    #[test]
+    fn test_holds_right_pages() {
+        let left = TupleIterator::new(create_tuple_list(vec![vec![1]]), get_int_table_schema(1));
+        let right = TupleIterator::new(create_tuple_list(vec![vec![1]]), get_int_table_schema(1));
+        let (left, left_held) = HoldSpy::new(Box::new(left));
+        let (right, right_held) = HoldSpy::new(Box::new(right));
+        OuterJoin::new(
+            JoinType::LeftOuter,
+            PredicateOp::Equals,
+            0,
+            0,
+            Box::new(left),
+            Box::new(right),
+        );
+        assert!(!left_held.get());
+        assert!(right_held.get());
+    }
+
+    #[test]
+    fn test_empty_side() -> Result<(), CrustyError> {
+        let left = TupleIterator::new(Vec::new(), get_int_table_schema(1));
+        let right = TupleIterator::new(create_tuple_list(vec![vec![1]]), get_int_table_schema(1));
+        let mut join = OuterJoin::new(
+            JoinType::FullOuter,
+            PredicateOp::Equals,
+            0,
+            0,
+            Box::new(left),
+            Box::new(right),
+        );
+        join.open()?;
+        assert_eq!(vec!["NULL 1"], collect(&mut join)?);
+        join.close()
+    }
+
+    #[test]
+    fn test_null_keys() -> Result<(), CrustyError> {
+        // NULL is not equal to NULL, so neither tuple has a match
+        let null = || vec![Tuple::new(vec![Field::Null])];
+        let mut join = OuterJoin::new(
+            JoinType::FullOuter,
+            PredicateOp::Equals,
+            0,
+            0,
+            Box::new(TupleIterator::new(null(), get_int_table_schema(1))),
+            Box::new(TupleIterator::new(null(), get_int_table_schema(1))),
+        );
+        join.open()?;
+        assert_eq!(vec!["NULL NULL", "NULL NULL"], collect(&mut join)?);
+        join.close()
+    }
+}
@@ -0,0 +1,187 @@
+use super::OpIterator;
+use common::{CrustyError, TableSchema, Tuple};
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tuples an operator of a profiled plan produced and the time spent in it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct OperatorStats {
+    /// Name of the operator, with the table for scans.
+    pub operator: String,
+    /// Depth of the operator in the plan, 0 for the root.
+    pub depth: usize,
+    /// Tuples produced since the plan was opened.
+    pub rows: u64,
+    /// Microseconds spent opening, reading and closing the operator, its children included.
+    pub time_us: u64,
+}
+
+impl fmt::Display for OperatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:indent$}{}: {} rows, {:.3} ms",
+            "",
+            self.operator,
+            self.rows,
+            self.time_us as f64 / 1000.0,
+            indent = 2 * self.depth
+        )
+    }
+}
+
+/// Statistics of the operators of a plan, shared by the `Profiled` wrappers of its operators.
+///
+/// Operators are listed parents first, in the order they appear in the plan.
+#[derive(Clone, Debug, Default)]
+pub struct PlanProfile {
+    operators: Arc<Mutex<Vec<OperatorStats>>>,
+}
+
+impl PlanProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an operator that is not built yet and returns its position.
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - Depth of the operator in the plan.
+    pub(crate) fn reserve(&self, depth: usize) -> usize {
+        let mut operators = self.operators.lock().unwrap();
+        operators.push(OperatorStats {
+            depth,
+            ..OperatorStats::default()
+        });
+        operators.len() - 1
+    }
+
+    /// Returns the statistics of the operators, as of the last time they were closed.
+    pub fn operators(&self) -> Vec<OperatorStats> {
+        self.operators.lock().unwrap().clone()
+    }
+}
+
+/// Counts the tuples an operator produces and the time spent in it, for a `PlanProfile`.
+///
+/// The counts are kept in the wrapper and added to the profile when it is closed, so the
+/// profiled plan does not share a lock while it runs.
+pub struct Profiled {
+    profile: PlanProfile,
+    /// Position of the operator in the profile.
+    index: usize,
+    rows: u64,
+    time: Duration,
+    /// Schema of the child.
+    schema: TableSchema,
+    /// Profiled operator.
+    child: Box<dyn OpIterator>,
+}
+
+impl Profiled {
+    /// Profiled constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - Profile the statistics are added to.
+    /// * `index` - Position reserved for the operator in the profile.
+    /// * `operator` - Name of the operator.
+    /// * `child` - Profiled OpIterator.
+    pub(crate) fn new(
+        profile: &PlanProfile,
+        index: usize,
+        operator: String,
+        child: Box<dyn OpIterator>,
+    ) -> Self {
+        profile.operators.lock().unwrap()[index].operator = operator;
+        Self {
+            profile: profile.clone(),
+            index,
+            rows: 0,
+            time: Duration::default(),
+            schema: child.get_schema().clone(),
+            child,
+        }
+    }
+
+    /// Runs `f` on the child and adds the time it took.
+    fn timed<T>(&mut self, f: impl FnOnce(&mut dyn OpIterator) -> T) -> T {
+        let start = Instant::now();
+        let res = f(self.child.as_mut());
+        self.time += start.elapsed();
+        res
+    }
+}
+
+impl OpIterator for Profiled {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.timed(|c| c.open())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let next = self.timed(|c| c.next())?;
+        if next.is_some() {
+            self.rows += 1;
+        }
+        Ok(next)
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Vec<Tuple>>, CrustyError> {
+        let next = self.timed(|c| c.next_batch())?;
+        if let Some(batch) = &next {
+            self.rows += batch.len() as u64;
+        }
+        Ok(next)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        let res = self.timed(|c| c.close());
+        let mut operators = self.profile.operators.lock().unwrap();
+        let stats = &mut operators[self.index];
+        stats.rows = self.rows;
+        stats.time_us = self.time.as_micros() as u64;
+        res
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.timed(|c| c.rewind())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Filter, TupleIterator};
+    use super::*;
+    use common::logical_plan::PredicateOp;
+    use common::testutil::*;
+    use common::Field;
+
+    #[test]
+    fn test_profile() -> Result<(), CrustyError> {
+        let profile = PlanProfile::new();
+        let (root, leaf) = (profile.reserve(0), profile.reserve(1));
+        let rows = (0..10).map(|i| vec![i]).collect();
+        let scan = TupleIterator::new(create_tuple_list(rows), get_int_table_schema(1));
+        let scan = Profiled::new(&profile, leaf, String::from("Scan t"), Box::new(scan));
+        let filter = Filter::new(PredicateOp::LessThan, 0, Field::IntField(4), Box::new(scan));
+        let mut root = Profiled::new(&profile, root, String::from("Filter"), Box::new(filter));
+        root.open()?;
+        while root.next()?.is_some() {}
+        // nothing is counted until the plan is closed
+        assert_eq!(0, profile.operators()[1].rows);
+        root.close()?;
+        let operators = profile.operators();
+        assert_eq!(4, operators[0].rows);
+        assert_eq!(10, operators[1].rows);
+        assert!(operators[0].time_us >= operators[1].time_us);
+        assert!(operators[1].to_string().starts_with("  Scan t: 10 rows, "));
+        Ok(())
+    }
+}
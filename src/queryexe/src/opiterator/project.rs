@@ -1,9 +1,109 @@
-use super::OpIterator;
-use common::{CrustyError, TableSchema, Tuple};
+use super::{Expression, OpIterator};
+use common::{Attribute, CrustyError, DataType, Field, TableSchema, Tuple};
+
+/// How a single output field is produced from the input tuple.
+#[derive(Debug, PartialEq)]
+enum ProjectStep {
+    /// Take the source field; this is its last use.
+    Move(usize),
+    /// Copy the source field; it is used again by a later step.
+    Copy(usize),
+    /// Take the value of an expression, by its index among the computed ones.
+    Eval(usize),
+}
+
+/// Projection compiled once from the field list, so next() does no per-field lookups.
+#[derive(Debug)]
+struct CompiledProjection {
+    /// True when the projection keeps every input field in order.
+    pass_through: bool,
+    /// Steps producing the output fields, in output order.
+    steps: Vec<ProjectStep>,
+    /// Expressions computed from the input before the steps take its fields.
+    exprs: Vec<Expression>,
+    /// Number of fields the input tuples must have at least.
+    min_width: usize,
+}
+
+impl CompiledProjection {
+    /// Compiles a projection of `fields` over input tuples that are `width` fields wide.
+    fn new(fields: &[usize], width: usize) -> Self {
+        Self::with_expressions(
+            fields.iter().map(|f| Expression::Field(*f)).collect(),
+            width,
+        )
+    }
+
+    /// Compiles a projection of expressions over input tuples that are `width` fields wide.
+    /// Expressions that are a field take it from the input, the others are computed.
+    fn with_expressions(exprs: Vec<Expression>, width: usize) -> Self {
+        let fields: Vec<Option<usize>> = exprs
+            .iter()
+            .map(|e| match e {
+                Expression::Field(f) => Some(*f),
+                _ => None,
+            })
+            .collect();
+        let pass_through =
+            fields.len() == width && fields.iter().enumerate().all(|(i, f)| Some(i) == *f);
+        let mut steps = Vec::with_capacity(fields.len());
+        let mut computed = Vec::new();
+        for (pos, expr) in exprs.into_iter().enumerate() {
+            match fields[pos] {
+                Some(src) if fields[pos + 1..].contains(&Some(src)) => {
+                    steps.push(ProjectStep::Copy(src))
+                }
+                Some(src) => steps.push(ProjectStep::Move(src)),
+                None => {
+                    steps.push(ProjectStep::Eval(computed.len()));
+                    computed.push(expr);
+                }
+            }
+        }
+        let min_width = fields.iter().flatten().map(|f| f + 1).max().unwrap_or(0);
+        Self {
+            pass_through,
+            steps,
+            exprs: computed,
+            min_width,
+        }
+    }
+
+    /// Builds the output tuple, consuming the input. Error if the input is too narrow.
+    fn apply(&self, tuple: Tuple) -> Result<Tuple, CrustyError> {
+        if tuple.size() < self.min_width {
+            return Err(CrustyError::ExecutionError(format!(
+                "Tuple has {} fields, projection needs {}",
+                tuple.size(),
+                self.min_width
+            )));
+        }
+        if self.pass_through {
+            return Ok(Tuple::new(tuple.field_vals));
+        }
+        let mut computed = self
+            .exprs
+            .iter()
+            .map(|e| e.eval(&tuple))
+            .collect::<Result<Vec<Field>, CrustyError>>()?;
+        let mut src = tuple.field_vals;
+        let mut out = Vec::with_capacity(self.steps.len());
+        for step in &self.steps {
+            match step {
+                ProjectStep::Copy(i) => out.push(src[*i].clone()),
+                ProjectStep::Move(i) => {
+                    out.push(std::mem::replace(&mut src[*i], Field::IntField(0)))
+                }
+                ProjectStep::Eval(i) => out.push(std::mem::replace(&mut computed[*i], Field::Null)),
+            }
+        }
+        Ok(Tuple::new(out))
+    }
+}
 
 /// Projection operator.
 pub struct ProjectIterator {
-    fields: Vec<usize>,
+    projection: CompiledProjection,
     open: bool,
     schema: TableSchema,
     child: Box<dyn OpIterator>,
@@ -23,8 +123,9 @@ impl ProjectIterator {
             attributes.push(attr.clone());
         }
         let schema = TableSchema::new(attributes);
+        let projection = CompiledProjection::new(&fields, child.get_schema().size());
         Self {
-            fields,
+            projection,
             open: false,
             schema,
             child,
@@ -55,8 +156,52 @@ impl ProjectIterator {
             attributes.push(attr);
         }
         let schema = TableSchema::new(attributes);
+        let projection = CompiledProjection::new(&fields, child.get_schema().size());
+        Self {
+            projection,
+            open: false,
+            schema,
+            child,
+        }
+    }
+
+    /// Constructor for the projection operator computing expressions.
+    ///
+    /// # Arguments
+    ///
+    /// * `exprs` - Expressions computing the fields of the final projection.
+    /// * `field_names` - Names of the fields in the final projection.
+    /// * `child` - Child nodes to get data from.
+    ///
+    /// # Notes
+    ///
+    /// `field_names` has to correspond to `exprs`. Computed fields may be NULL, and are INTs if
+    /// they only have NULL literals.
+    pub fn new_with_expressions(
+        exprs: Vec<Expression>,
+        field_names: Vec<&str>,
+        child: Box<dyn OpIterator>,
+    ) -> Self {
+        let mut attributes = Vec::new();
+        let child_schema = child.get_schema();
+        for (expr, name) in exprs.iter().zip(field_names.iter()) {
+            let attr = match expr {
+                Expression::Field(i) => {
+                    let mut attr = child_schema.get_attribute(*i).unwrap().clone();
+                    attr.name = name.to_string();
+                    attr
+                }
+                _ => Attribute::new(
+                    name.to_string(),
+                    expr.dtype(child_schema).unwrap_or(DataType::Int),
+                ),
+            };
+            attributes.push(attr);
+        }
+        let schema = TableSchema::new(attributes);
+        let projection = CompiledProjection::with_expressions(exprs, child_schema.size());
         Self {
-            fields,
+            projection,
             open: false,
             schema,
             child,
@@ -76,34 +221,41 @@ impl OpIterator for ProjectIterator {
             panic!("Operator has not been opened")
         }
 
-        let next = self.child.next()?;
-        if let Some(tuple) = next {
-            let mut new_field_vals = Vec::new();
-            for i in &self.fields {
-                let t = match tuple.get_field(*i) {
-                    None => panic!("No such field"),
-                    Some(t) => t,
-                };
-                new_field_vals.push(t.clone());
-            }
-            return Ok(Some(Tuple::new(new_field_vals)));
+        match self.child.next()? {
+            Some(tuple) => Ok(Some(self.projection.apply(tuple)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Vec<Tuple>>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        match self.child.next_batch()? {
+            Some(batch) => Ok(Some(
+                batch
+                    .into_iter()
+                    .map(|tuple| self.projection.apply(tuple))
+                    .collect::<Result<Vec<Tuple>, CrustyError>>()?,
+            )),
+            None => Ok(None),
         }
-        Ok(next)
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
-        self.child.close()?;
         self.open = false;
-        Ok(())
+        self.child.close()
     }
 
     fn rewind(&mut self) -> Result<(), CrustyError> {
         if !self.open {
             panic!("Operator has not been opened")
         }
-        self.child.rewind()?;
-        self.close()?;
-        self.open()
+        self.child.rewind()
+    }
+
+    fn hold_pages(&mut self) {
+        self.child.hold_pages();
     }
 
     fn get_schema(&self) -> &TableSchema {
@@ -144,6 +296,17 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_next_batch() -> Result<(), CrustyError> {
+        let mut project = get_project(vec![2, 1]);
+        project.open()?;
+        let batch = project.next_batch()?.unwrap();
+        let expected = create_tuple_list(vec![vec![2, 1], vec![2, 1], vec![2, 1]]);
+        assert_eq!(expected, batch);
+        assert_eq!(None, project.next_batch()?);
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn test_next_not_open() {
@@ -178,4 +341,69 @@ mod test {
         assert_eq!(sum_before, sum_after);
         Ok(())
     }
+
+    #[test]
+    fn test_compile() {
+        let identity = CompiledProjection::new(&[0, 1, 2], WIDTH);
+        assert!(identity.pass_through);
+        let reorder = CompiledProjection::new(&[2, 0, 2], WIDTH);
+        assert!(!reorder.pass_through);
+        assert_eq!(
+            vec![
+                ProjectStep::Copy(2),
+                ProjectStep::Move(0),
+                ProjectStep::Move(2)
+            ],
+            reorder.steps
+        );
+        assert_eq!(3, reorder.min_width);
+    }
+
+    #[test]
+    fn test_next_reorder_and_repeat() -> Result<(), CrustyError> {
+        let mut project = get_project(vec![2, 0, 2]);
+        project.open()?;
+        let tuple = project.next()?.unwrap();
+        assert_eq!(create_tuple_list(vec![vec![2, 0, 2]])[0], tuple);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_too_narrow() {
+        let projection = CompiledProjection::new(&[2], WIDTH);
+        let narrow = create_tuple_list(vec![vec![0, 1]]).remove(0);
+        assert!(projection.apply(narrow).is_err());
+    }
+
+    #[test]
+    fn test_next_expressions() -> Result<(), CrustyError> {
+        use common::logical_plan::ArithOp;
+        let tuples = create_tuple_list(vec![vec![0, 1, 2], vec![3, 4, 5]]);
+        let ti = TupleIterator::new(tuples, get_int_table_schema(WIDTH));
+        let exprs = vec![
+            Expression::Field(1),
+            Expression::binary(
+                Expression::Field(1),
+                ArithOp::Multiply,
+                Expression::Field(2),
+            ),
+            Expression::Literal(Field::Null),
+        ];
+        let mut project =
+            ProjectIterator::new_with_expressions(exprs, vec!["b", "x", "n"], Box::new(ti));
+        assert_eq!(
+            vec!["b", "x", "n"],
+            project
+                .get_schema()
+                .attributes()
+                .map(|a| a.name())
+                .collect::<Vec<_>>()
+        );
+        project.open()?;
+        let row = |a, b| Tuple::new(vec![Field::IntField(a), Field::IntField(b), Field::Null]);
+        assert_eq!(Some(row(1, 2)), project.next()?);
+        assert_eq!(Some(row(4, 20)), project.next()?);
+        assert_eq!(None, project.next()?);
+        Ok(())
+    }
 }
@@ -0,0 +1,137 @@
+use super::OpIterator;
+use common::{CrustyError, TableSchema, Tuple};
+
+/// Aborts a query once an operator produced more tuples than allowed.
+///
+/// Wraps the operators whose output can grow with the product of their inputs, such as a join
+/// without a predicate, so a mistyped query fails with an error instead of running the server
+/// out of time and memory.
+pub struct RowGuard {
+    /// Maximum number of tuples the child may produce.
+    max_rows: usize,
+    /// Name of the guarded operator, for the error message.
+    operator: &'static str,
+    /// Number of tuples produced since open or rewind.
+    rows: usize,
+    /// Schema of the child.
+    schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Child operator passing data into operator.
+    child: Box<dyn OpIterator>,
+}
+
+impl RowGuard {
+    /// RowGuard constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_rows` - Maximum number of tuples the child may produce.
+    /// * `operator` - Name of the guarded operator, for the error message.
+    /// * `child` - Guarded OpIterator.
+    pub fn new(max_rows: usize, operator: &'static str, child: Box<dyn OpIterator>) -> Self {
+        Self {
+            max_rows,
+            operator,
+            rows: 0,
+            schema: child.get_schema().clone(),
+            open: false,
+            child,
+        }
+    }
+}
+
+impl OpIterator for RowGuard {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.rows = 0;
+        self.open = true;
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        let next = self.child.next()?;
+        if next.is_some() {
+            self.rows += 1;
+            if self.rows > self.max_rows {
+                return Err(CrustyError::ExecutionError(format!(
+                    "{} produced more than {} rows, the limit set by max_operator_rows. \
+                     Is a join predicate missing?",
+                    self.operator, self.max_rows
+                )));
+            }
+        }
+        Ok(next)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.open = false;
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.child.rewind()?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TupleIterator;
+    use super::*;
+    use common::testutil::*;
+
+    fn get_guard(n: i32, max_rows: usize) -> RowGuard {
+        let rows = (0..n).map(|i| vec![i]).collect();
+        let child = TupleIterator::new(create_tuple_list(rows), get_int_table_schema(1));
+        RowGuard::new(max_rows, "Join", Box::new(child))
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let mut guard = get_guard(10, 5);
+        guard.next().unwrap();
+    }
+
+    #[test]
+    fn test_within_limit() -> Result<(), CrustyError> {
+        let mut guard = get_guard(5, 5);
+        guard.open()?;
+        for _ in 0..2 {
+            let mut count = 0;
+            while guard.next()?.is_some() {
+                count += 1;
+            }
+            assert_eq!(5, count);
+            guard.rewind()?;
+        }
+        guard.close()
+    }
+
+    #[test]
+    fn test_exceeded() -> Result<(), CrustyError> {
+        let mut guard = get_guard(10, 3);
+        guard.open()?;
+        for _ in 0..3 {
+            assert!(guard.next()?.is_some());
+        }
+        match guard.next() {
+            Err(CrustyError::ExecutionError(msg)) => {
+                assert!(msg.starts_with("Join produced more than 3 rows"))
+            }
+            other => panic!("Expected an execution error, got {:?}", other),
+        }
+        Ok(())
+    }
+}
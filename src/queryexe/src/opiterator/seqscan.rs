@@ -2,19 +2,49 @@ use super::OpIterator;
 use crate::StorageManager;
 use common::ids::Permissions;
 use common::ids::{ContainerId, TransactionId};
-use common::storage_trait::StorageTrait;
+use common::storage_trait::{ContainerType, StorageTrait, ValueRefIterator};
 use common::table::*;
 use common::{Attribute, CrustyError, TableSchema, Tuple};
+use std::collections::VecDeque;
 use std::sync::{Arc, RwLock};
 
+/// Tunables of a sequential scan, set per session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// Number of values decoded at once. `next` hands them out one by one before decoding the
+    /// next batch.
+    pub batch_size: usize,
+    /// Pages the storage manager may read ahead of the page the scan is at.
+    pub prefetch_pages: usize,
+}
+
+impl Default for ScanOptions {
+    /// See the `scan_bench` benchmarks of queryexe and heapstore for how these were chosen.
+    fn default() -> Self {
+        Self {
+            batch_size: 64,
+            prefetch_pages: 1,
+        }
+    }
+}
+
 /// Sequential scan operator
 pub struct SeqScan {
-    file_iter: <StorageManager as StorageTrait>::ValIterator,
+    /// Iterator over the container. Acquired on open so the scan sees every write its
+    /// transaction made before the scan started.
+    file_iter: Option<<StorageManager as StorageTrait>::ValIterator>,
     schema: TableSchema,
     open: bool,
     storage_manager: Arc<StorageManager>,
     container_id: ContainerId,
     transaction_id: TransactionId,
+    options: ScanOptions,
+    /// Part of the table the scan reads, and the number of parts the table is split into.
+    partition: (usize, usize),
+    /// Tuples decoded but not returned yet.
+    batch: VecDeque<Tuple>,
+    /// Whether the pages read stay cached until the scan is closed, see `hold_pages`.
+    hold_pages: bool,
 }
 
 impl SeqScan {
@@ -25,34 +55,57 @@ impl SeqScan {
     /// * `table` - Table to scan over.
     /// * `table_alias` - Table alias given by the user.
     /// * `tid` - Transaction used to read the table.
+    /// * `options` - Batch size and prefetching of the scan.
     pub fn new(
         storage_manager: Arc<StorageManager>,
         table: Arc<RwLock<Table>>,
         table_alias: &str,
         tid: TransactionId,
+        options: ScanOptions,
     ) -> Self {
         let table_ref = table.read().unwrap();
         let schema = table_ref.schema.clone();
         let table_id_downcast = table_ref.id as u16;
-        storage_manager.create_container(table_id_downcast).unwrap();
-        let file_iter = storage_manager.get_iterator(table_id_downcast, tid, Permissions::ReadOnly);
+        storage_manager
+            .create_container(
+                table_id_downcast,
+                Some(table_ref.name.clone()),
+                ContainerType::Table,
+            )
+            .unwrap();
         let container_id = table_id_downcast as ContainerId;
         Self {
-            file_iter,
+            file_iter: None,
             schema: Self::schema(&schema, table_alias),
             open: false,
             storage_manager,
             container_id,
             transaction_id: tid,
+            options,
+            partition: (0, 1),
+            batch: VecDeque::with_capacity(options.batch_size),
+            hold_pages: false,
         }
     }
 
+    /// Reads only one of several parts of the table, e.g. for one of the workers of an
+    /// Exchange. Scanning the parts in order returns the tuples of the whole table in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `partition` - Part of the table to read.
+    /// * `partitions` - Number of parts the table is split into.
+    pub fn with_partition(mut self, partition: usize, partitions: usize) -> Self {
+        self.partition = (partition, partitions);
+        self
+    }
+
     /// Returns the schema of the table with aliases.
     ///
     /// # Arguments
     /// * `src_schema` - Schema of the source.
     /// * `alias` - Alias of the table.
-    fn schema(src_schema: &TableSchema, alias: &str) -> TableSchema {
+    pub(crate) fn schema(src_schema: &TableSchema, alias: &str) -> TableSchema {
         let mut attrs = Vec::new();
         for a in src_schema.attributes() {
             let new_name = format!("{}.{}", alias, a.name());
@@ -60,10 +113,56 @@ impl SeqScan {
         }
         TableSchema::new(attrs)
     }
+
+    /// Gets a new iterator over the container, failing if the table was dropped.
+    fn new_file_iter(&self) -> Result<<StorageManager as StorageTrait>::ValIterator, CrustyError> {
+        if !self.storage_manager.container_exists(self.container_id) {
+            return Err(CrustyError::ExecutionError(format!(
+                "Container {} of the scanned table no longer exists",
+                self.container_id
+            )));
+        }
+        let (partition, partitions) = self.partition;
+        let mut file_iter = self.storage_manager.get_partition_iterator(
+            self.container_id,
+            self.transaction_id,
+            Permissions::ReadOnly,
+            self.options.prefetch_pages,
+            partition,
+            partitions,
+        );
+        if self.hold_pages {
+            file_iter.hold_pages();
+        }
+        Ok(file_iter)
+    }
+
+    /// Decodes the next batch of values, in place in the pages the storage manager lends
+    /// them from. Leaves the batch empty once the container is done. Fails if the iterator
+    /// ended because a page could not be read, or the transaction was aborted waiting for a
+    /// lock.
+    fn fill_batch(&mut self) -> Result<(), CrustyError> {
+        let file_iter = self.file_iter.as_mut().unwrap();
+        for _ in 0..self.options.batch_size.max(1) {
+            match file_iter.next_ref() {
+                Some(bytes) => {
+                    let tuple = Tuple::from_bytes_with_schema(bytes?, &self.schema)?;
+                    self.batch.push_back(tuple);
+                }
+                None => break,
+            }
+        }
+        if self.batch.is_empty() {
+            self.storage_manager
+                .check_transaction(self.transaction_id)?;
+        }
+        Ok(())
+    }
 }
 
 impl OpIterator for SeqScan {
     fn open(&mut self) -> Result<(), CrustyError> {
+        self.file_iter = Some(self.new_file_iter()?);
         self.open = true;
         Ok(())
     }
@@ -72,13 +171,29 @@ impl OpIterator for SeqScan {
         if !self.open {
             panic!("Operator has not been opened")
         }
-        match self.file_iter.next() {
-            Some(bytes) => Ok(Some(Tuple::from_bytes(&bytes))),
-            None => Ok(None),
+        if self.batch.is_empty() {
+            self.fill_batch()?;
         }
+        Ok(self.batch.pop_front())
+    }
+
+    /// Returns the tuples decoded at once, at most `ScanOptions::batch_size` of them.
+    fn next_batch(&mut self) -> Result<Option<Vec<Tuple>>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        if self.batch.is_empty() {
+            self.fill_batch()?;
+        }
+        if self.batch.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Vec::from(std::mem::take(&mut self.batch))))
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
+        self.file_iter = None;
+        self.batch.clear();
         self.open = false;
         Ok(())
     }
@@ -87,14 +202,20 @@ impl OpIterator for SeqScan {
         if !self.open {
             panic!("Operator has not been opened")
         }
-        self.file_iter = self.storage_manager.get_iterator(
-            self.container_id,
-            self.transaction_id,
-            Permissions::ReadOnly,
-        );
+        self.file_iter = Some(self.new_file_iter()?);
+        self.batch.clear();
         Ok(())
     }
 
+    /// The iterator of the previous pass holds the pages until the new one replaces it on
+    /// rewind, so they are still cached when it reads them.
+    fn hold_pages(&mut self) {
+        self.hold_pages = true;
+        if let Some(file_iter) = self.file_iter.as_mut() {
+            file_iter.hold_pages();
+        }
+    }
+
     fn get_schema(&self) -> &TableSchema {
         &self.schema
     }
@@ -122,7 +243,8 @@ mod test {
         let sm = Arc::new(StorageManager::new_test_sm());
         let table_ref = table.read().unwrap();
         let table_id_downcast = table_ref.id as u16;
-        sm.create_container(table_id_downcast).unwrap();
+        sm.create_container(table_id_downcast, None, ContainerType::Table)
+            .unwrap();
         // Create test data
         let tuple = int_vec_to_tuple(vec![1, 2, 3]);
         let tuple2 = int_vec_to_tuple(vec![1, 2, 3]);
@@ -136,7 +258,13 @@ mod test {
         let _rid2 = sm.insert_value(table_id_downcast, tuple_bytes2.clone(), tid);
         let _rid3 = sm.insert_value(table_id_downcast, tuple_bytes3.clone(), tid);
 
-        Ok(SeqScan::new(sm.clone(), table.clone(), TABLE, tid))
+        Ok(SeqScan::new(
+            sm.clone(),
+            table.clone(),
+            TABLE,
+            tid,
+            ScanOptions::default(),
+        ))
     }
 
     #[test]
@@ -191,6 +319,75 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_batches() -> Result<(), CrustyError> {
+        let mut scan = get_scan()?;
+        scan.options = ScanOptions {
+            batch_size: 2,
+            prefetch_pages: 1,
+        };
+        scan.open()?;
+        assert!(scan.next()?.is_some());
+        // the second tuple of the batch waits to be returned
+        assert_eq!(1, scan.batch.len());
+        assert_eq!(sum_int_fields(&mut scan)?, CHECKSUM - 6);
+        scan.rewind()?;
+        assert!(scan.batch.is_empty());
+        assert_eq!(sum_int_fields(&mut scan)?, CHECKSUM);
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_batch() -> Result<(), CrustyError> {
+        let mut scan = get_scan()?;
+        scan.options = ScanOptions {
+            batch_size: 2,
+            prefetch_pages: 1,
+        };
+        scan.open()?;
+        assert!(scan.next()?.is_some());
+        // the rest of the decoded batch, then the next one
+        assert_eq!(1, scan.next_batch()?.unwrap().len());
+        assert_eq!(1, scan.next_batch()?.unwrap().len());
+        assert_eq!(None, scan.next_batch()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_own_writes() -> Result<(), CrustyError> {
+        let mut scan = get_scan()?;
+        // Insert with the scan's transaction after the scan is built but before it is opened.
+        let tuple_bytes = serde_cbor::to_vec(&int_vec_to_tuple(vec![1, 2, 3])).unwrap();
+        scan.storage_manager
+            .insert_value(scan.container_id, tuple_bytes.clone(), scan.transaction_id);
+        scan.open()?;
+        assert_eq!(sum_int_fields(&mut scan)?, CHECKSUM + 6);
+        // Writes made while the scan is open are picked up on rewind.
+        scan.storage_manager
+            .insert_value(scan.container_id, tuple_bytes, scan.transaction_id);
+        scan.rewind()?;
+        assert_eq!(sum_int_fields(&mut scan)?, CHECKSUM + 12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_removed() -> Result<(), CrustyError> {
+        let mut scan = get_scan()?;
+        scan.open()?;
+        assert!(scan.next()?.is_some());
+        scan.storage_manager.remove_container(scan.container_id)?;
+        // an open scan finishes against the values it started with
+        assert_eq!(sum_int_fields(&mut scan)?, CHECKSUM - 6);
+        // starting over fails cleanly
+        match scan.rewind() {
+            Err(CrustyError::ExecutionError(_)) => (),
+            _ => panic!("Expected an execution error"),
+        }
+        scan.close()?;
+        assert!(scan.open().is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_get_schema() {
         let scan = get_scan().unwrap();
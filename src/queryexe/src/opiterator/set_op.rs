@@ -0,0 +1,287 @@
+use super::{close_both, OpIterator};
+use common::logical_plan::SetOperator;
+use common::{CrustyError, Field, TableSchema, Tuple};
+use std::collections::HashMap;
+
+/// Set operator, for UNION ALL, INTERSECT ALL and EXCEPT ALL.
+///
+/// Duplicates are kept: a row the left child returns m times and the right child n times is
+/// returned m + n times by a union, min(m, n) times by an intersection and max(m - n, 0) times
+/// by a difference. NULLs are equal to each other. The forms without ALL remove duplicates
+/// with a DistinctIterator, above a union and below the left child of the others.
+///
+/// A union returns the left rows, then the right rows. The others count the rows of the right
+/// child in a hash table when opened, and return the left rows in their order.
+pub struct SetOpIterator {
+    /// How the rows of the children are combined.
+    op: SetOperator,
+    /// Number of times each row of the right child was returned, for INTERSECT and EXCEPT.
+    counts: HashMap<Vec<Field>, usize>,
+    /// Counts of the rows not matched with a left row yet.
+    remaining: HashMap<Vec<Field>, usize>,
+    /// Whether all rows of the left child were returned, for UNION.
+    left_done: bool,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Left child.
+    left_child: Box<dyn OpIterator>,
+    /// Right child.
+    right_child: Box<dyn OpIterator>,
+}
+
+impl SetOpIterator {
+    /// SetOpIterator constructor.
+    ///
+    /// The children must have as many columns, of the same types. The result has the column
+    /// names of the left child, and a union's columns may be NULL if either child's may.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - How the rows of the children are combined.
+    /// * `left_child` - Left child of the set operation.
+    /// * `right_child` - Right child of the set operation.
+    pub fn new(
+        op: SetOperator,
+        left_child: Box<dyn OpIterator>,
+        right_child: Box<dyn OpIterator>,
+    ) -> Result<Self, CrustyError> {
+        let schema = Self::schema(op, left_child.get_schema(), right_child.get_schema())?;
+        Ok(Self {
+            op,
+            counts: HashMap::new(),
+            remaining: HashMap::new(),
+            left_done: false,
+            schema,
+            open: false,
+            left_child,
+            right_child,
+        })
+    }
+
+    /// Schema of the result, or an error if the children's columns don't match.
+    fn schema(
+        op: SetOperator,
+        left: &TableSchema,
+        right: &TableSchema,
+    ) -> Result<TableSchema, CrustyError> {
+        if left.size() != right.size() {
+            return Err(CrustyError::ValidationError(format!(
+                "Each query of a {} must have as many columns, found {} and {}",
+                op,
+                left.size(),
+                right.size()
+            )));
+        }
+        let mut attrs = Vec::with_capacity(left.size());
+        for (l, r) in left.attributes().zip(right.attributes()) {
+            if l.dtype() != r.dtype() {
+                return Err(CrustyError::ValidationError(format!(
+                    "Column {} of type {:?} can't be combined by a {} with column {} of type {:?}",
+                    l.name(),
+                    l.dtype(),
+                    op,
+                    r.name(),
+                    r.dtype()
+                )));
+            }
+            let mut attr = l.clone();
+            if op == SetOperator::Union {
+                attr.nullable = l.nullable() || r.nullable();
+                attr.max_len = l.max_len().zip(r.max_len()).map(|(l, r)| l.max(r));
+            }
+            attrs.push(attr);
+        }
+        Ok(TableSchema::new(attrs))
+    }
+
+    /// Counts the rows of the right child.
+    fn count_right(&mut self) -> Result<(), CrustyError> {
+        self.counts.clear();
+        while let Some(t) = self.right_child.next()? {
+            *self.counts.entry(t.field_vals).or_insert(0) += 1;
+        }
+        self.remaining = self.counts.clone();
+        Ok(())
+    }
+}
+
+impl OpIterator for SetOpIterator {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.left_child.open()?;
+        self.right_child.open()?;
+        self.left_done = false;
+        self.open = true;
+        if self.op != SetOperator::Union {
+            self.count_right()?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        if self.op == SetOperator::Union {
+            if !self.left_done {
+                match self.left_child.next()? {
+                    Some(t) => return Ok(Some(t)),
+                    None => self.left_done = true,
+                }
+            }
+            return self.right_child.next();
+        }
+        while let Some(t) = self.left_child.next()? {
+            let matched = match self.remaining.get_mut(&t.field_vals) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    true
+                }
+                _ => false,
+            };
+            if matched == (self.op == SetOperator::Intersect) {
+                return Ok(Some(t));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.counts.clear();
+        self.remaining.clear();
+        self.open = false;
+        close_both(self.left_child.as_mut(), self.right_child.as_mut())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.left_child.rewind()?;
+        if self.op == SetOperator::Union {
+            self.right_child.rewind()?;
+            self.left_done = false;
+        } else {
+            self.remaining = self.counts.clone();
+        }
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TupleIterator;
+    use super::*;
+    use common::testutil::*;
+    use common::{Attribute, DataType};
+
+    fn get_set_op(op: SetOperator, left: Vec<Vec<i32>>, right: Vec<Vec<i32>>) -> SetOpIterator {
+        let left = TupleIterator::new(create_tuple_list(left), get_int_table_schema(2));
+        let right = TupleIterator::new(create_tuple_list(right), get_int_table_schema(2));
+        SetOpIterator::new(op, Box::new(left), Box::new(right)).unwrap()
+    }
+
+    fn collect(set_op: &mut SetOpIterator) -> Result<Vec<Vec<i32>>, CrustyError> {
+        let mut rows = Vec::new();
+        while let Some(t) = set_op.next()? {
+            rows.push(t.field_vals().map(|f| f.as_int().unwrap()).collect());
+        }
+        Ok(rows)
+    }
+
+    fn left() -> Vec<Vec<i32>> {
+        vec![vec![1, 1], vec![2, 2], vec![1, 1], vec![3, 3], vec![1, 1]]
+    }
+
+    fn right() -> Vec<Vec<i32>> {
+        vec![vec![1, 1], vec![4, 4], vec![1, 1], vec![3, 3]]
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let mut set_op = get_set_op(SetOperator::Union, left(), right());
+        set_op.next().unwrap();
+    }
+
+    #[test]
+    fn test_union() -> Result<(), CrustyError> {
+        let mut set_op = get_set_op(SetOperator::Union, left(), right());
+        set_op.open()?;
+        let mut expected = left();
+        expected.extend(right());
+        assert_eq!(expected, collect(&mut set_op)?);
+        set_op.rewind()?;
+        assert_eq!(expected, collect(&mut set_op)?);
+        set_op.close()
+    }
+
+    #[test]
+    fn test_intersect() -> Result<(), CrustyError> {
+        let mut set_op = get_set_op(SetOperator::Intersect, left(), right());
+        set_op.open()?;
+        let expected = vec![vec![1, 1], vec![1, 1], vec![3, 3]];
+        assert_eq!(expected, collect(&mut set_op)?);
+        set_op.rewind()?;
+        assert_eq!(expected, collect(&mut set_op)?);
+        set_op.close()
+    }
+
+    #[test]
+    fn test_except() -> Result<(), CrustyError> {
+        let mut set_op = get_set_op(SetOperator::Except, left(), right());
+        set_op.open()?;
+        let expected = vec![vec![2, 2], vec![1, 1]];
+        assert_eq!(expected, collect(&mut set_op)?);
+        set_op.rewind()?;
+        assert_eq!(expected, collect(&mut set_op)?);
+        set_op.close()
+    }
+
+    #[test]
+    fn test_nulls_are_equal() -> Result<(), CrustyError> {
+        let schema = TableSchema::from_vecs(vec!["a"], vec![DataType::Int]);
+        let rows = || {
+            vec![
+                Tuple::new(vec![Field::Null]),
+                Tuple::new(vec![Field::IntField(1)]),
+            ]
+        };
+        let left = TupleIterator::new(rows(), schema.clone());
+        let right = TupleIterator::new(vec![Tuple::new(vec![Field::Null])], schema);
+        let mut set_op = SetOpIterator::new(SetOperator::Except, Box::new(left), Box::new(right))?;
+        set_op.open()?;
+        assert_eq!(vec![Field::IntField(1)], set_op.next()?.unwrap().field_vals);
+        assert!(set_op.next()?.is_none());
+        set_op.close()
+    }
+
+    #[test]
+    fn test_schema() {
+        let left = TableSchema::new(vec![
+            Attribute::new_not_null(String::from("t.a"), DataType::Int),
+            Attribute::new_not_null(String::from("t.b"), DataType::String),
+        ]);
+        let right =
+            TableSchema::from_vecs(vec!["u.a", "u.b"], vec![DataType::Int, DataType::String]);
+        let schema = SetOpIterator::schema(SetOperator::Union, &left, &right).unwrap();
+        assert_eq!(
+            vec!["t.a", "t.b"],
+            schema.attributes().map(|a| a.name()).collect::<Vec<_>>()
+        );
+        assert!(schema.attributes().all(|a| a.nullable()));
+        let schema = SetOpIterator::schema(SetOperator::Except, &left, &right).unwrap();
+        assert!(schema.attributes().all(|a| !a.nullable()));
+
+        let narrow = TableSchema::from_vecs(vec!["u.a"], vec![DataType::Int]);
+        assert!(SetOpIterator::schema(SetOperator::Union, &left, &narrow).is_err());
+        let swapped =
+            TableSchema::from_vecs(vec!["u.b", "u.a"], vec![DataType::String, DataType::Int]);
+        assert!(SetOpIterator::schema(SetOperator::Intersect, &left, &swapped).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use super::{ExternalSort, OpIterator, SortConfig, SortKey, SortedTuples};
+use crate::memory_budget::MemoryBudget;
+use crate::StorageManager;
+use common::ids::TransactionId;
+use common::{CrustyError, TableSchema, Tuple};
+use std::sync::Arc;
+
+/// Memory and merging of the sorts of a plan.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortOptions {
+    /// Bytes of tuples a sort may hold in memory. Beyond that, sorted runs are spilled to
+    /// temporary containers and merged.
+    pub memory_limit: usize,
+    /// Run size and fan-in of the external sort.
+    pub config: SortConfig,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        Self {
+            memory_limit: 64 * 1024 * 1024,
+            config: SortConfig::default(),
+        }
+    }
+}
+
+/// Sort operator, for ORDER BY.
+///
+/// The whole input is read and sorted on the first call to `next`, spilling to temporary
+/// containers if it does not fit in the memory limit.
+pub struct SortIterator {
+    /// Fields to sort on, most significant first.
+    keys: Vec<SortKey>,
+    options: SortOptions,
+    storage_manager: Arc<StorageManager>,
+    /// Transaction the runs are written with.
+    tid: TransactionId,
+    /// Schema of the child.
+    schema: TableSchema,
+    /// Boolean determining if iterator is open.
+    open: bool,
+    /// Sorted tuples, None until the input has been sorted.
+    sorted: Option<SortedTuples>,
+    /// Child operator passing data into operator.
+    child: Box<dyn OpIterator>,
+}
+
+impl SortIterator {
+    /// Sort constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - Fields to sort on, most significant first.
+    /// * `options` - Memory limit and merging of the sort.
+    /// * `storage_manager` - Storage manager runs are spilled to.
+    /// * `tid` - Transaction the runs are written with.
+    /// * `child` - Child OpIterator passing data into the operator.
+    pub fn new(
+        keys: Vec<SortKey>,
+        options: SortOptions,
+        storage_manager: Arc<StorageManager>,
+        tid: TransactionId,
+        child: Box<dyn OpIterator>,
+    ) -> Self {
+        Self {
+            keys,
+            options,
+            storage_manager,
+            tid,
+            schema: child.get_schema().clone(),
+            open: false,
+            sorted: None,
+            child,
+        }
+    }
+
+    /// Reads the whole input into an external sort.
+    fn sort(&mut self) -> Result<SortedTuples, CrustyError> {
+        let mut sort = ExternalSort::new(
+            self.keys.clone(),
+            self.options.config,
+            Arc::new(MemoryBudget::new(self.options.memory_limit)),
+            self.storage_manager.clone(),
+            self.tid,
+        );
+        while let Some(t) = self.child.next()? {
+            sort.insert(t)?;
+        }
+        if sort.num_runs() > 0 {
+            debug!("Sort spilled {} runs", sort.num_runs());
+        }
+        sort.finish()
+    }
+}
+
+impl OpIterator for SortIterator {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.open = true;
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        if self.sorted.is_none() {
+            self.sorted = Some(self.sort()?);
+        }
+        self.sorted.as_mut().unwrap().next()
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        // Dropping the sorted tuples removes the runs and releases their memory.
+        self.sorted = None;
+        self.open = false;
+        self.child.close()
+    }
+
+    /// Sorts the input again on the next call to `next`.
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            panic!("Operator has not been opened")
+        }
+        self.sorted = None;
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TupleIterator;
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use common::Field;
+    use std::mem::size_of;
+
+    /// Rows (i % 7, i) for i in 0..n, in reverse.
+    fn mock_ti(n: i32) -> TupleIterator {
+        let rows = (0..n).rev().map(|i| vec![i % 7, i]).collect();
+        TupleIterator::new(create_tuple_list(rows), get_int_table_schema(2))
+    }
+
+    fn get_sort(n: i32, keys: Vec<SortKey>, options: SortOptions) -> SortIterator {
+        let sm = Arc::new(StorageManager::new(
+            gen_random_dir().to_str().unwrap().to_string(),
+        ));
+        SortIterator::new(
+            keys,
+            options,
+            sm,
+            TransactionId::new(),
+            Box::new(mock_ti(n)),
+        )
+    }
+
+    fn collect(sort: &mut SortIterator) -> Result<Vec<(i32, i32)>, CrustyError> {
+        let mut rows = Vec::new();
+        while let Some(t) = sort.next()? {
+            rows.push((t.field(0)?.as_int()?, t.field(1)?.as_int()?));
+        }
+        Ok(rows)
+    }
+
+    fn expected(n: i32) -> Vec<(i32, i32)> {
+        let mut rows: Vec<(i32, i32)> = (0..n).map(|i| (i % 7, i)).collect();
+        rows.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        rows
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_next_not_open() {
+        let mut sort = get_sort(10, vec![SortKey::new(0, true)], SortOptions::default());
+        sort.next().unwrap();
+    }
+
+    #[test]
+    fn test_multi_column() -> Result<(), CrustyError> {
+        let keys = vec![SortKey::new(0, true), SortKey::new(1, false)];
+        let mut sort = get_sort(100, keys, SortOptions::default());
+        sort.open()?;
+        assert_eq!(expected(100), collect(&mut sort)?);
+        assert!(sort.next()?.is_none());
+        sort.close()
+    }
+
+    #[test]
+    fn test_spill_and_rewind() -> Result<(), CrustyError> {
+        let keys = vec![SortKey::new(0, true), SortKey::new(1, false)];
+        let options = SortOptions {
+            memory_limit: 50 * (size_of::<Tuple>() + 2 * size_of::<Field>()),
+            config: SortConfig {
+                run_size: 10_000,
+                fan_in: 4,
+            },
+        };
+        let mut sort = get_sort(1000, keys, options);
+        sort.open()?;
+        assert_eq!(expected(1000), collect(&mut sort)?);
+        sort.rewind()?;
+        assert_eq!(Some((0, 994)), collect(&mut sort)?.first().copied());
+        sort.close()?;
+        assert!(sort.storage_manager.list_containers().is_empty());
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::opiterator::OpIterator;
-use common::CrustyError;
-use common::Field;
+use common::{CrustyError, TableSchema, Tuple};
+use std::cell::Cell;
+use std::rc::Rc;
 
 #[allow(dead_code)]
 /// Returns the count of the number of tuples in an OpIterator.
@@ -28,10 +29,7 @@ pub fn sum_int_fields(iter: &mut impl OpIterator) -> Result<i32, CrustyError> {
     let mut sum = 0;
     while let Some(t) = iter.next()? {
         for i in 0..t.size() {
-            sum += *match t.get_field(i).unwrap() {
-                Field::IntField(n) => n,
-                _ => panic!("Not an IntField"),
-            }
+            sum += t.field(i)?.as_int()?;
         }
     }
     Ok(sum)
@@ -50,3 +48,52 @@ pub fn match_all_tuples(
     assert!(iter2.next()?.is_none());
     Ok(())
 }
+
+#[allow(dead_code)]
+/// Passes the tuples of its child through, and records whether the operator above it asked
+/// it to hold its pages.
+pub struct HoldSpy {
+    child: Box<dyn OpIterator>,
+    held: Rc<Cell<bool>>,
+}
+
+#[allow(dead_code)]
+impl HoldSpy {
+    /// Wraps `child`, and returns the flag set once `hold_pages` is called.
+    pub fn new(child: Box<dyn OpIterator>) -> (Self, Rc<Cell<bool>>) {
+        let held = Rc::new(Cell::new(false));
+        (
+            Self {
+                child,
+                held: held.clone(),
+            },
+            held,
+        )
+    }
+}
+
+impl OpIterator for HoldSpy {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        self.child.next()
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.child.rewind()
+    }
+
+    fn hold_pages(&mut self) {
+        self.held.set(true);
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.child.get_schema()
+    }
+}
@@ -0,0 +1,274 @@
+use common::CrustyError;
+use sqlparser::ast::DateTimeField;
+use std::fmt;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A date, or a date and time of day to the second, without a time zone.
+///
+/// There are no date types yet, so dates and timestamps are stored in string columns in ISO
+/// format, `YYYY-MM-DD` and `YYYY-MM-DD HH:MM:SS`, which sort like the values they represent.
+/// Predicates compute with them as DateTime and compare with the formatted result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    /// Seconds since 1970-01-01 00:00:00.
+    seconds: i64,
+    /// Whether the value is a timestamp rather than a date.
+    has_time: bool,
+}
+
+/// A length of time, in months and seconds since months differ in length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interval {
+    pub months: i64,
+    pub seconds: i64,
+}
+
+impl Interval {
+    /// Parses the value and unit of an `INTERVAL '<value>' <unit>` literal.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Number of units, may be negative.
+    /// * `unit` - Unit of the interval.
+    pub fn new(value: &str, unit: &DateTimeField) -> Result<Self, CrustyError> {
+        let n = value.trim().parse::<i64>().map_err(|_| {
+            CrustyError::ValidationError(format!("Invalid interval '{}' {}", value, unit))
+        })?;
+        let (months, seconds) = match unit {
+            DateTimeField::Year => (12, 0),
+            DateTimeField::Month => (1, 0),
+            DateTimeField::Day => (0, SECONDS_PER_DAY),
+            DateTimeField::Hour => (0, 60 * 60),
+            DateTimeField::Minute => (0, 60),
+            DateTimeField::Second => (0, 1),
+        };
+        Ok(Self {
+            months: n * months,
+            seconds: n * seconds,
+        })
+    }
+
+    /// The interval in the opposite direction.
+    pub fn negate(self) -> Self {
+        Self {
+            months: -self.months,
+            seconds: -self.seconds,
+        }
+    }
+}
+
+impl DateTime {
+    /// Parses a `YYYY-MM-DD` date.
+    pub fn parse_date(s: &str) -> Result<Self, CrustyError> {
+        let days = parse_ymd(s.trim())
+            .ok_or_else(|| CrustyError::ValidationError(format!("Invalid date '{}'", s)))?;
+        Ok(Self {
+            seconds: days * SECONDS_PER_DAY,
+            has_time: false,
+        })
+    }
+
+    /// Parses a `YYYY-MM-DD HH:MM[:SS]` timestamp, also with a `T` between date and time.
+    pub fn parse_timestamp(s: &str) -> Result<Self, CrustyError> {
+        let err = || CrustyError::ValidationError(format!("Invalid timestamp '{}'", s));
+        let s = s.trim();
+        let (date, time) = match s.find([' ', 'T']) {
+            Some(i) => (&s[..i], s[i + 1..].trim()),
+            None => (s, "00:00:00"),
+        };
+        let days = parse_ymd(date).ok_or_else(err)?;
+        let parts = time
+            .split(':')
+            .map(|p| p.parse::<i64>().ok())
+            .collect::<Option<Vec<i64>>>()
+            .ok_or_else(err)?;
+        let (h, m, sec) = match parts[..] {
+            [h, m] => (h, m, 0),
+            [h, m, sec] => (h, m, sec),
+            _ => return Err(err()),
+        };
+        if !(0..24).contains(&h) || !(0..60).contains(&m) || !(0..60).contains(&sec) {
+            return Err(err());
+        }
+        Ok(Self {
+            seconds: days * SECONDS_PER_DAY + h * 3600 + m * 60 + sec,
+            has_time: true,
+        })
+    }
+
+    /// Adds an interval. Months are added first, moving to the last day of the month if the
+    /// day does not exist in it, like 2021-01-31 + 1 month = 2021-02-28. A date becomes a
+    /// timestamp if the interval is not a whole number of days.
+    pub fn add(self, interval: Interval) -> Self {
+        let days = self.seconds.div_euclid(SECONDS_PER_DAY);
+        let time = self.seconds.rem_euclid(SECONDS_PER_DAY);
+        let (y, m, d) = civil_from_days(days);
+        let months = y * 12 + (m - 1) + interval.months;
+        let (y, m) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+        let d = d.min(days_in_month(y, m));
+        Self {
+            seconds: days_from_civil(y, m, d) * SECONDS_PER_DAY + time + interval.seconds,
+            has_time: self.has_time || interval.seconds % SECONDS_PER_DAY != 0,
+        }
+    }
+
+    /// Truncates to the start of the year, month, day, hour, minute or second.
+    ///
+    /// # Arguments
+    ///
+    /// * `unit` - Unit to truncate to, as in `DATE_TRUNC('month', ...)`.
+    pub fn trunc(self, unit: &str) -> Result<Self, CrustyError> {
+        let days = self.seconds.div_euclid(SECONDS_PER_DAY);
+        let (y, m, _) = civil_from_days(days);
+        let seconds = match unit.to_lowercase().as_str() {
+            "year" => days_from_civil(y, 1, 1) * SECONDS_PER_DAY,
+            "month" => days_from_civil(y, m, 1) * SECONDS_PER_DAY,
+            "day" => days * SECONDS_PER_DAY,
+            "hour" => self.seconds - self.seconds.rem_euclid(3600),
+            "minute" => self.seconds - self.seconds.rem_euclid(60),
+            "second" => self.seconds,
+            _ => {
+                return Err(CrustyError::ValidationError(format!(
+                    "Unsupported date_trunc unit '{}', expected year, month, day, hour, minute \
+                     or second",
+                    unit
+                )))
+            }
+        };
+        Ok(Self {
+            seconds,
+            has_time: self.has_time,
+        })
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (y, m, d) = civil_from_days(self.seconds.div_euclid(SECONDS_PER_DAY));
+        write!(f, "{:04}-{:02}-{:02}", y, m, d)?;
+        if self.has_time {
+            let time = self.seconds.rem_euclid(SECONDS_PER_DAY);
+            write!(
+                f,
+                " {:02}:{:02}:{:02}",
+                time / 3600,
+                time / 60 % 60,
+                time % 60
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Days since 1970-01-01 of a `YYYY-MM-DD` date, None if it is not a valid date.
+fn parse_ymd(s: &str) -> Option<i64> {
+    let mut parts = s.split('-');
+    let (y, m, d) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || y.len() != 4 || m.len() != 2 || d.len() != 2 {
+        return None;
+    }
+    let (y, m, d) = (y.parse().ok()?, m.parse().ok()?, d.parse().ok()?);
+    if !(1..=12).contains(&m) || d < 1 || d > days_in_month(y, m) {
+        return None;
+    }
+    Some(days_from_civil(y, m, d))
+}
+
+fn days_in_month(y: i64, m: i64) -> i64 {
+    match m {
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Year, month and day of a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(s: &str) -> DateTime {
+        DateTime::parse_date(s).unwrap()
+    }
+
+    fn interval(value: &str, unit: DateTimeField) -> Interval {
+        Interval::new(value, &unit).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!("2021-03-08", date("2021-03-08").to_string());
+        assert_eq!("1969-12-31", date("1969-12-31").to_string());
+        assert_eq!("2020-02-29", date("2020-02-29").to_string());
+        assert!(DateTime::parse_date("2021-02-29").is_err());
+        assert!(DateTime::parse_date("2021-3-8").is_err());
+        let ts = DateTime::parse_timestamp("2021-03-08T07:05").unwrap();
+        assert_eq!("2021-03-08 07:05:00", ts.to_string());
+        assert!(DateTime::parse_timestamp("2021-03-08 24:00:00").is_err());
+        // a date is the start of its day
+        assert!(date("2021-03-08") < DateTime::parse_timestamp("2021-03-08 00:00:01").unwrap());
+    }
+
+    #[test]
+    fn test_add() {
+        let d = date("2021-01-31");
+        assert_eq!(
+            "2021-02-07",
+            d.add(interval("7", DateTimeField::Day)).to_string()
+        );
+        assert_eq!(
+            "2021-02-28",
+            d.add(interval("1", DateTimeField::Month)).to_string()
+        );
+        assert_eq!(
+            "2020-01-31",
+            d.add(interval("-1", DateTimeField::Year)).to_string()
+        );
+        assert_eq!(
+            "2020-12-31",
+            d.add(interval("1", DateTimeField::Month).negate())
+                .to_string()
+        );
+        assert_eq!(
+            "2021-01-30 22:00:00",
+            d.add(interval("-2", DateTimeField::Hour)).to_string()
+        );
+        assert!(Interval::new("1.5", &DateTimeField::Day).is_err());
+    }
+
+    #[test]
+    fn test_trunc() -> Result<(), CrustyError> {
+        let ts = DateTime::parse_timestamp("2021-03-08 07:05:09")?;
+        assert_eq!("2021-01-01 00:00:00", ts.trunc("YEAR")?.to_string());
+        assert_eq!("2021-03-01 00:00:00", ts.trunc("month")?.to_string());
+        assert_eq!("2021-03-08 07:00:00", ts.trunc("hour")?.to_string());
+        assert_eq!("2021-03-01", date("2021-03-08").trunc("month")?.to_string());
+        assert!(ts.trunc("week").is_err());
+        Ok(())
+    }
+}
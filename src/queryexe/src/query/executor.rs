@@ -2,16 +2,72 @@ use crate::opiterator::*;
 use crate::StorageManager;
 use common::catalog::Catalog;
 use common::ids::TransactionId;
+use common::io_stats::IoStats;
 use common::logical_plan::*;
+use common::storage_trait::StorageTrait;
 use common::table::*;
-use common::{CrustyError, QueryResult, TableSchema, Tuple};
+use common::{CrustyError, DataType, Field, QueryResult, TableSchema, Tuple};
 use std::sync::Arc;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Whether a query result carries a checksum of its rows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResultChecksum {
+    /// Only the rows.
+    #[default]
+    Off,
+    /// The rows followed by their checksum.
+    On,
+    /// Only the checksum, for clients that validate results without transferring them.
+    Only,
+}
+
+/// Receives the rows of a query run by `Executor::execute_streaming`, a batch at a time.
+///
+/// The next batch is produced only after `send` returns, so a sink that blocks until its
+/// consumer has room for more rows holds the query back to the consumer's pace.
+pub trait RowSink {
+    /// Called with the schema of the rows once the plan is open, before the first batch.
+    fn start(&mut self, schema: &TableSchema) -> Result<(), CrustyError>;
+
+    /// Called with each batch of rows, in order.
+    fn send(&mut self, tuples: Vec<Tuple>) -> Result<(), CrustyError>;
+}
+
+/// Options of the operators a logical plan is converted to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlanOptions {
+    /// Batch size and prefetching of the plan's scans.
+    pub scan: ScanOptions,
+    /// Memory limit and merging of the plan's sorts.
+    pub sort: SortOptions,
+    /// Maximum number of tuples a join or cross product may produce before the query is
+    /// aborted, None for no limit.
+    pub max_operator_rows: Option<usize>,
+    /// Token that stops the plan's scans once it is set, None if the plan can't be cancelled.
+    pub cancel: Option<CancelToken>,
+    /// Time by which the plan's scans stop, None for no limit.
+    pub deadline: Option<QueryDeadline>,
+    /// Number of threads scans, and the filters, aggregates and hash joins over them, are
+    /// split among. 0 and 1 run the plan on the calling thread only.
+    pub parallelism: usize,
+}
 
 /// Manages the execution of queries using OpIterators and converts a LogicalPlan to a tree of OpIterators and runs it.
 pub struct Executor {
     /// Executor state
     pub plan: Option<Box<dyn OpIterator>>,
     pub storage_manager: Option<Arc<StorageManager>>,
+    /// Transaction the plan runs in. The storage manager counts its IO while the plan is open.
+    tid: Option<TransactionId>,
+    /// Pages read and written by the plan between the last start and close.
+    io_stats: IoStats,
+    /// Whether the plan was started and not closed or aborted since.
+    running: bool,
+    /// Token that stops the plan once it is set.
+    cancel: CancelToken,
+    /// Time by which the plan is stopped, if it has a time limit.
+    deadline: Option<QueryDeadline>,
 }
 
 impl Executor {
@@ -30,6 +86,11 @@ impl Executor {
         Self {
             plan: None,
             storage_manager: None,
+            tid: None,
+            io_stats: IoStats::default(),
+            running: false,
+            cancel: CancelToken::new(),
+            deadline: None,
         }
     }
 
@@ -37,36 +98,241 @@ impl Executor {
         self.storage_manager = Some(storage_manager.clone());
     }
 
-    pub fn configure_query(&mut self, physical_plan: Box<dyn OpIterator>) {
+    /// Sets the plan to run and the transaction it runs in.
+    ///
+    /// # Arguments
+    ///
+    /// * `physical_plan` - Root of the plan's op iterators.
+    /// * `tid` - Transaction the plan was created with, its IO is counted for the plan.
+    pub fn configure_query(&mut self, physical_plan: Box<dyn OpIterator>, tid: TransactionId) {
+        // A previous plan that was not closed is aborted and its counters are dropped
+        self.abort();
+        if let (Some(sm), Some(old_tid)) = (&self.storage_manager, self.tid) {
+            sm.untrack_io(old_tid);
+        }
         self.plan = Some(physical_plan);
+        self.tid = Some(tid);
+        self.io_stats = IoStats::default();
+    }
+
+    /// Sets the token that stops the plans of the executor, e.g. the one of a client's session.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Token checked before every tuple of the plan.
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = token;
     }
 
-    /// Returns the physical plan iterator to begin execution.
+    /// Sets the time by which the next plans are stopped.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - Deadline checked before every tuple of the plan, None for no limit.
+    pub fn set_deadline(&mut self, deadline: Option<QueryDeadline>) {
+        self.deadline = deadline;
+    }
+
+    /// Returns the physical plan iterator to begin execution. The plan is aborted if it fails
+    /// to open.
     pub fn start(&mut self) -> Result<(), CrustyError> {
-        self.plan.as_mut().unwrap().open()
+        if let (Some(sm), Some(tid)) = (&self.storage_manager, self.tid) {
+            sm.track_io(tid);
+        }
+        self.running = true;
+        let res = self.plan.as_mut().unwrap().open();
+        self.abort_on_err(res)
     }
 
-    /// Returns the next tuple or None if there is no such tuple.
+    /// Returns the next tuple or None if there is no such tuple. The plan is aborted if it
+    /// fails, was cancelled or ran out of time.
     ///
     /// # Panics
     ///
-    /// Panics if physical plan iterator is closed
+    /// Panics if physical plan iterator is closed or was aborted.
     pub fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
-        self.plan.as_mut().unwrap().next()
+        let res = match self.check_stopped() {
+            Ok(()) => self.plan.as_mut().unwrap().next(),
+            Err(e) => Err(e),
+        };
+        self.abort_on_err(res)
+    }
+
+    /// Returns the next batch of tuples or None if there are no more, see
+    /// `OpIterator::next_batch`. The plan is aborted if it fails, was cancelled or ran out of
+    /// time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if physical plan iterator is closed or was aborted.
+    pub fn next_batch(&mut self) -> Result<Option<Vec<Tuple>>, CrustyError> {
+        let res = match self.check_stopped() {
+            Ok(()) => self.plan.as_mut().unwrap().next_batch(),
+            Err(e) => Err(e),
+        };
+        self.abort_on_err(res)
     }
 
-    /// Closes the physical plan iterator.
+    /// Closes the physical plan iterator. Does nothing if the plan was aborted.
     pub fn close(&mut self) -> Result<(), CrustyError> {
-        self.plan.as_mut().unwrap().close()
+        let plan = match self.plan.as_mut() {
+            Some(plan) if self.running => plan,
+            _ => return Ok(()),
+        };
+        self.running = false;
+        if let (Some(sm), Some(tid)) = (&self.storage_manager, self.tid) {
+            self.io_stats = sm.untrack_io(tid);
+        }
+        let res = plan.close();
+        if res.is_err() {
+            self.plan = None;
+        }
+        res
+    }
+
+    /// Stops a running plan after an error, or one that is not read to its end.
+    ///
+    /// The plan is closed, errors closing it are only logged, and dropped, which removes the
+    /// temporary containers of its sorts and unpins the pages of its scans even if an operator
+    /// did not close its children. There is no lock manager yet, so no locks are released.
+    /// The plan cannot be started again.
+    pub fn abort(&mut self) {
+        if let Some(mut plan) = self.plan.take() {
+            if self.running {
+                if let Err(e) = plan.close() {
+                    warn!("Error closing aborted plan: {:?}", e);
+                }
+            }
+        }
+        if self.running {
+            if let (Some(sm), Some(tid)) = (&self.storage_manager, self.tid) {
+                self.io_stats = sm.untrack_io(tid);
+            }
+            self.running = false;
+        }
+    }
+
+    /// Fails if the plan was cancelled or its deadline passed.
+    fn check_stopped(&self) -> Result<(), CrustyError> {
+        self.cancel.check()?;
+        match &self.deadline {
+            Some(deadline) => deadline.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// Aborts the plan if `res` is an error, and returns `res`.
+    fn abort_on_err<T>(&mut self, res: Result<T, CrustyError>) -> Result<T, CrustyError> {
+        if res.is_err() {
+            self.abort();
+        }
+        res
+    }
+
+    /// Returns the pages the plan read and wrote, counted from `start` to `close`.
+    pub fn io_stats(&self) -> IoStats {
+        self.io_stats
     }
 
     /// Consumes the physical plan iterator and stores the result in a QueryResult.
     pub fn execute(&mut self) -> Result<QueryResult, CrustyError> {
+        self.execute_with_checksum(ResultChecksum::Off)
+    }
+
+    /// Consumes the physical plan iterator and stores its rows in a QueryResult as they are,
+    /// for the client to format.
+    pub fn execute_rows(&mut self) -> Result<QueryResult, CrustyError> {
+        let schema = self.plan.as_mut().unwrap().get_schema().clone();
+        self.start()?;
+        let mut tuples = Vec::new();
+        while let Some(batch) = self.next_batch()? {
+            tuples.extend(batch);
+        }
+        self.close()?;
+        Ok(QueryResult::with_rows(schema, tuples))
+    }
+
+    /// Consumes the physical plan iterator, handing its rows to a sink in batches instead of
+    /// keeping them, so the memory a query needs does not grow with the size of its result.
+    ///
+    /// The plan is aborted if the sink fails. Rows handed to the sink can't be taken back, so
+    /// once the sink was started an aborted transaction is reported as an execution error,
+    /// which is not retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch_size` - Maximum number of rows in a batch.
+    /// * `sink` - Where the rows go.
+    pub fn execute_streaming(
+        &mut self,
+        batch_size: usize,
+        sink: &mut dyn RowSink,
+    ) -> Result<QueryResult, CrustyError> {
+        let schema = self.plan.as_mut().unwrap().get_schema().clone();
+        self.start()?;
+        let res = sink.start(&schema);
+        self.abort_on_err(res)?;
+        let res = self.stream_batches(batch_size.max(1), sink);
+        res.map_err(|e| match e {
+            CrustyError::TransactionAbortedError => CrustyError::ExecutionError(String::from(
+                "Transaction aborted after rows were sent, the query was not retried",
+            )),
+            e => e,
+        })
+    }
+
+    /// Hands the rows of a started plan to a sink and closes the plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch_size` - Maximum number of rows in a batch.
+    /// * `sink` - Where the rows go.
+    fn stream_batches(
+        &mut self,
+        batch_size: usize,
+        sink: &mut dyn RowSink,
+    ) -> Result<QueryResult, CrustyError> {
+        let mut rows = 0;
+        let mut batch = Vec::with_capacity(batch_size);
+        while let Some(tuples) = self.next_batch()? {
+            for t in tuples {
+                batch.push(t);
+                if batch.len() == batch_size {
+                    rows += batch.len();
+                    let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                    let res = sink.send(full);
+                    self.abort_on_err(res)?;
+                }
+            }
+        }
+        if !batch.is_empty() {
+            rows += batch.len();
+            let res = sink.send(batch);
+            self.abort_on_err(res)?;
+        }
+        self.close()?;
+        Ok(QueryResult::streamed(rows))
+    }
+
+    /// Consumes the physical plan iterator and stores the result in a QueryResult, with a
+    /// checksum of the rows after them or instead of them.
+    ///
+    /// The checksum is the wrapping sum of the xxh3 hashes of the rows' bytes, so it does not
+    /// depend on the order the rows are produced in and runs of the same query against
+    /// different storage managers or settings can be compared without keeping their rows.
+    ///
+    /// # Arguments
+    ///
+    /// * `checksum` - Whether to add a checksum line and whether to keep the rows.
+    pub fn execute_with_checksum(
+        &mut self,
+        checksum: ResultChecksum,
+    ) -> Result<QueryResult, CrustyError> {
         let schema = self.plan.as_mut().unwrap().get_schema();
         // TODO: Deal with the magic numbers.
         let width = schema
             .attributes()
-            .map(|a| a.name().len())
+            .map(|a| a.name().chars().count())
             .max()
             .unwrap_or(10)
             + 2;
@@ -77,16 +343,31 @@ impl Executor {
         }
         res += "\n";
 
-        &self.start()?;
-        while let Some(t) = &self.next()? {
-            for f in t.field_vals() {
-                let s = format!("{:width$}", f.to_string(), width = width);
-                res += &s;
+        self.start()?;
+        let (mut sum, mut rows) = (0u64, 0u64);
+        while let Some(batch) = self.next_batch()? {
+            for t in &batch {
+                rows += 1;
+                if checksum != ResultChecksum::Off {
+                    sum = sum.wrapping_add(xxh3_64(&t.get_bytes()));
+                }
+                if checksum == ResultChecksum::Only {
+                    continue;
+                }
+                for f in t.field_vals() {
+                    let s = format!("{:width$}", f.to_string(), width = width);
+                    res += &s;
+                }
+                res += "\n";
             }
-            res += "\n";
         }
-        &self.close()?;
-        Ok(QueryResult::new(&res))
+        self.close()?;
+        if checksum != ResultChecksum::Off {
+            res += &format!("checksum: {:016x} ({} rows)\n", sum, rows);
+        }
+        let mut res = QueryResult::new(&res);
+        res.metadata_mut().rows_returned = Some(rows);
+        Ok(res)
     }
 
     /// Converts a logical_plan to a physical_plan of op_iterators.
@@ -96,16 +377,61 @@ impl Executor {
     /// * `catalog` - Catalog of the database containing the metadata about the tables and such.
     /// * `logical_plan` - Translated logical plan of the query.
     /// * `tid` - Id of the transaction that this executor is running.
+    /// * `options` - Options of the plan's operators.
     pub fn logical_plan_to_op_iterator<T: Catalog>(
         storage_manager: &Arc<StorageManager>,
         catalog: &T,
         lp: &LogicalPlan,
         tid: TransactionId,
+        options: PlanOptions,
     ) -> Result<Box<dyn OpIterator>, CrustyError> {
         let start = lp
             .root()
             .ok_or_else(|| CrustyError::ExecutionError(String::from("No root node")))?;
-        Executor::logical_plan_to_op_iterator_helper(&storage_manager, catalog, lp, start, tid)
+        Executor::logical_plan_to_op_iterator_helper(
+            &storage_manager,
+            catalog,
+            lp,
+            start,
+            tid,
+            options,
+            None,
+        )
+    }
+
+    /// Converts a logical_plan to a physical_plan of op_iterators whose operators count the
+    /// tuples they produce and the time spent in them.
+    ///
+    /// Returns the plan and the profile its operators' statistics are added to when they are
+    /// closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `catalog` - Catalog of the database containing the metadata about the tables and such.
+    /// * `logical_plan` - Translated logical plan of the query.
+    /// * `tid` - Id of the transaction that this executor is running.
+    /// * `options` - Options of the plan's operators.
+    pub fn logical_plan_to_profiled_op_iterator<T: Catalog>(
+        storage_manager: &Arc<StorageManager>,
+        catalog: &T,
+        lp: &LogicalPlan,
+        tid: TransactionId,
+        options: PlanOptions,
+    ) -> Result<(Box<dyn OpIterator>, PlanProfile), CrustyError> {
+        let start = lp
+            .root()
+            .ok_or_else(|| CrustyError::ExecutionError(String::from("No root node")))?;
+        let profile = PlanProfile::new();
+        let plan = Executor::logical_plan_to_op_iterator_helper(
+            &storage_manager,
+            catalog,
+            lp,
+            start,
+            tid,
+            options,
+            Some((&profile, 0)),
+        )?;
+        Ok((plan, profile))
     }
 
     /// Recursive helper function to parse logical plan into physical plan.
@@ -117,32 +443,69 @@ impl Executor {
     /// * `catalog` - Catalog of the database containing the metadata about the tables and such.
     /// * `logical_plan` - Translated logical plan of the query.
     /// * `tid` - Id of the transaction that this executor is running.
+    /// * `options` - Options of the plan's operators.
+    /// * `profile` - Profile the operators are added to and the depth of the node, if the plan
+    ///   is profiled.
     fn logical_plan_to_op_iterator_helper<T: Catalog>(
         storage_manager: &Arc<StorageManager>,
         catalog: &T,
         lp: &LogicalPlan,
         start: OpIndex,
         tid: TransactionId,
+        options: PlanOptions,
+        profile: Option<(&PlanProfile, usize)>,
     ) -> Result<Box<dyn OpIterator>, CrustyError> {
         let err = CrustyError::ExecutionError(String::from("Malformed logical plan"));
+        // The node is added to the profile before its children, so parents are listed first.
+        let slot = profile.map(|(profile, depth)| (profile, profile.reserve(depth), depth));
+
+        let op = lp.get_operator(start).ok_or_else(|| err.clone())?;
+        // A filter of a scan reads the table through an index instead, if the predicate
+        // compares an indexed column with a value.
+        let mut index_scan = match op {
+            LogicalOp::Filter(FilterNode { predicate, .. }) => {
+                Executor::index_scan(storage_manager, catalog, lp, start, predicate, tid)?
+            }
+            _ => None,
+        };
+        let use_index = index_scan.is_some();
+        // A scan, or a filter of one, is read in parts on threads of their own.
+        let partitioned = match op {
+            LogicalOp::Scan(_) | LogicalOp::Filter(_) if !use_index => {
+                Executor::partition_factory(storage_manager, catalog, lp, start, tid, &options)?
+            }
+            _ => None,
+        };
+        let skip_children = use_index || partitioned.is_some();
 
         // Recursively convert the children in node of logical plan to physical plan.
-        let mut children = lp.edges(start).map(|n| {
-            Executor::logical_plan_to_op_iterator_helper(&storage_manager, catalog, lp, n, tid)
+        let mut children = lp.edges(start).filter(move |_| !skip_children).map(|n| {
+            Executor::logical_plan_to_op_iterator_helper(
+                &storage_manager,
+                catalog,
+                lp,
+                n,
+                tid,
+                options.clone(),
+                slot.map(|(profile, _, depth)| (profile, depth + 1)),
+            )
         });
 
         // Converts the current node in logical plan to a node in the physical plan.
-        let op = lp.get_operator(start).ok_or_else(|| err.clone())?;
         let result: Result<Box<dyn OpIterator>, CrustyError> = match op {
+            LogicalOp::Scan(_) | LogicalOp::Filter(_) if partitioned.is_some() => {
+                let (factory, schema) = partitioned.unwrap();
+                Ok(Box::new(Exchange::new(
+                    options.parallelism,
+                    factory,
+                    schema,
+                )))
+            }
             LogicalOp::Scan(ScanNode { alias }) => {
                 let alias_id = Table::get_table_id(alias);
                 let table = catalog.get_table_ptr(alias_id)?;
-                Ok(Box::new(SeqScan::new(
-                    storage_manager.clone(),
-                    table,
-                    &alias,
-                    tid,
-                )))
+                let scan = SeqScan::new(storage_manager.clone(), table, &alias, tid, options.scan);
+                Ok(Executor::cancellable(Box::new(scan), &options))
             }
             LogicalOp::Project(ProjectNode { identifiers }) => {
                 let child = children.next().ok_or_else(|| err.clone())??;
@@ -153,6 +516,25 @@ impl Executor {
                         // Ok(Box::new(ProjectIterator::new(field_indices, child)))
                         Ok(Box::new(project_iterator))
                     }
+                    ProjectIdentifiers::List(identifiers)
+                        if identifiers.iter().any(|id| id.expr().is_some()) =>
+                    {
+                        let mut exprs = Vec::new();
+                        let mut names = Vec::new();
+                        for id in identifiers {
+                            exprs.push(match id.expr() {
+                                Some(expr) => Executor::expression(expr, child.get_schema())?,
+                                None => Expression::Field(Executor::get_field_index(
+                                    id.column(),
+                                    child.get_schema(),
+                                )?),
+                            });
+                            names.push(id.alias().unwrap_or_else(|| id.column()));
+                        }
+                        let project_iterator =
+                            ProjectIterator::new_with_expressions(exprs, names, child);
+                        Ok(Box::new(project_iterator))
+                    }
                     ProjectIdentifiers::List(identifiers) => {
                         let (indices, names) =
                             Self::get_field_indices_names(identifiers, child.get_schema())?;
@@ -176,12 +558,29 @@ impl Executor {
                     Self::get_field_indices_names(&agg_fields, child.get_schema())?;
                 let (groupby_indices, groupby_names) =
                     Self::get_field_indices_names(group_by, child.get_schema())?;
-                let agg = Aggregate::new(
-                );
+                let mut agg = Aggregate::new(
+                    groupby_indices,
+                    groupby_names,
+                    agg_indices,
+                    agg_names,
+                    ops,
+                    child,
+                )
+                .with_spill(options.sort, storage_manager.clone(), tid);
+                let input = lp.edges(start).next().ok_or_else(|| err.clone())?;
+                if let Some((factory, _)) =
+                    Executor::partition_factory(storage_manager, catalog, lp, input, tid, &options)?
+                {
+                    agg = agg.with_partitions(options.parallelism, factory);
+                }
                 Ok(Box::new(agg))
             }
             LogicalOp::Join(JoinNode {
-                left, op, right, ..
+                left,
+                op,
+                right,
+                join_type,
+                ..
             }) => {
                 let left_child = children.next().ok_or_else(|| err.clone())??;
                 let left_schema = left_child.get_schema();
@@ -189,40 +588,365 @@ impl Executor {
                 let right_schema = right_child.get_schema();
 
                 // Sometimes the join condition is written in reverse of the join tables order.
-                if !left_schema.contains(left.column()) {
-                    let left_index = Executor::get_field_index(left.column(), right_schema)?;
-                    let right_index = Executor::get_field_index(right.column(), left_schema)?;
-                    Ok(Box::new(Join::new(
-                    )))
+                let (left_index, right_index, op) = if left_schema.contains(left.column()) {
+                    (
+                        Executor::get_field_index(left.column(), left_schema)?,
+                        Executor::get_field_index(right.column(), right_schema)?,
+                        *op,
+                    )
                 } else {
-                    let left_index = Executor::get_field_index(left.column(), left_schema)?;
-                    let right_index = Executor::get_field_index(right.column(), right_schema)?;
-                    Ok(Box::new(Join::new(
-                    )))
-                }
+                    (
+                        Executor::get_field_index(right.column(), left_schema)?,
+                        Executor::get_field_index(left.column(), right_schema)?,
+                        op.flip(),
+                    )
+                };
+                let join: Box<dyn OpIterator> = match (join_type, op) {
+                    (JoinType::Inner, PredicateOp::Equals) => {
+                        let mut join =
+                            HashEqJoin::new(op, left_index, right_index, left_child, right_child);
+                        // the left input is probed in parts if it can be, else the right one
+                        for (input, left) in lp.edges(start).zip([true, false]) {
+                            if let Some((factory, _)) = Executor::partition_factory(
+                                storage_manager,
+                                catalog,
+                                lp,
+                                input,
+                                tid,
+                                &options,
+                            )? {
+                                join = join.with_partitions(options.parallelism, factory, left);
+                                break;
+                            }
+                        }
+                        Box::new(join)
+                    }
+                    (JoinType::Inner, _) => Box::new(Join::new(
+                        op,
+                        left_index,
+                        right_index,
+                        left_child,
+                        right_child,
+                    )),
+                    _ => Box::new(OuterJoin::new(
+                        *join_type,
+                        op,
+                        left_index,
+                        right_index,
+                        left_child,
+                        right_child,
+                    )),
+                };
+                Ok(Executor::guard_rows(join, "Join", &options))
             }
             LogicalOp::Filter(FilterNode { predicate, .. }) => {
+                // The index only narrows down the rows, the filter still checks all of them.
+                let child: Box<dyn OpIterator> = match index_scan.take() {
+                    Some((scan, name)) => match slot {
+                        Some((profile, _, depth)) => {
+                            let index = profile.reserve(depth + 1);
+                            let scan = Executor::cancellable(Box::new(scan), &options);
+                            Box::new(Profiled::new(profile, index, name, scan))
+                        }
+                        None => Executor::cancellable(Box::new(scan), &options),
+                    },
+                    None => children.next().ok_or_else(|| err.clone())??,
+                };
+                let predicate = Executor::filter_predicate(predicate, child.get_schema())?;
+                Ok(Box::new(Filter::with_predicate(predicate, child)))
+            }
+            LogicalOp::Sort(SortNode { fields }) => {
                 let child = children.next().ok_or_else(|| err.clone())??;
-                let (identifier, op, operand) = match (&predicate.left, &predicate.right) {
-                    (PredExpr::Ident(i), PredExpr::Literal(f)) => (i, predicate.op, f),
-                    (PredExpr::Literal(f), PredExpr::Ident(i)) => (i, predicate.op.flip(), f),
-                    _ => {
-                        return Err(err.clone());
-                    }
+                let keys = fields
+                    .iter()
+                    .map(|f| {
+                        Executor::get_field_index(f.field.column(), child.get_schema())
+                            .map(|i| SortKey::new(i, f.ascending))
+                    })
+                    .collect::<Result<Vec<SortKey>, CrustyError>>()?;
+                Ok(Box::new(SortIterator::new(
+                    keys,
+                    options.sort,
+                    storage_manager.clone(),
+                    tid,
+                    child,
+                )))
+            }
+            LogicalOp::Limit(LimitNode { limit, offset }) => {
+                let child = children.next().ok_or_else(|| err.clone())??;
+                Ok(Box::new(LimitIterator::new(*limit, *offset, child)))
+            }
+            LogicalOp::CrossProduct => {
+                let left_child = children.next().ok_or_else(|| err.clone())??;
+                let right_child = children.next().ok_or_else(|| err.clone())??;
+                let cross = Box::new(CrossJoinIterator::new(left_child, right_child));
+                Ok(Executor::guard_rows(cross, "Cross product", &options))
+            }
+            LogicalOp::Distinct => {
+                let child = children.next().ok_or_else(|| err.clone())??;
+                Ok(Box::new(DistinctIterator::new(
+                    options.sort,
+                    storage_manager.clone(),
+                    tid,
+                    child,
+                )))
+            }
+            LogicalOp::SetOp(SetOpNode { op, all }) => {
+                let distinct = |child| -> Box<dyn OpIterator> {
+                    Box::new(DistinctIterator::new(
+                        options.sort,
+                        storage_manager.clone(),
+                        tid,
+                        child,
+                    ))
                 };
-                let idx = Executor::get_field_index(identifier.column(), child.get_schema())?;
-                let filter = Filter::new(op, idx, operand.clone(), child);
-                Ok(Box::new(filter))
+                let mut left_child = children.next().ok_or_else(|| err.clone())??;
+                let right_child = children.next().ok_or_else(|| err.clone())??;
+                // Without ALL a union removes the duplicates of its result, the others those of
+                // their left child, which is all their result holds.
+                if !all && *op != SetOperator::Union {
+                    left_child = distinct(left_child);
+                }
+                let set_op: Box<dyn OpIterator> =
+                    Box::new(SetOpIterator::new(*op, left_child, right_child)?);
+                if !all && *op == SetOperator::Union {
+                    Ok(distinct(set_op))
+                } else {
+                    Ok(set_op)
+                }
             }
         };
 
         if children.next().is_some() {
             Err(err)
         } else {
-            result
+            result.map(|physical| match slot {
+                Some((profile, index, _)) => Box::new(Profiled::new(
+                    profile,
+                    index,
+                    Executor::operator_name(op),
+                    physical,
+                )),
+                None => physical,
+            })
         }
     }
 
+    /// Name of an operator in the profile of a plan.
+    fn operator_name(op: &LogicalOp) -> String {
+        match op {
+            LogicalOp::Scan(ScanNode { alias }) => format!("Scan {}", alias),
+            LogicalOp::Project(_) => String::from("Project"),
+            LogicalOp::Aggregate(_) => String::from("Aggregate"),
+            LogicalOp::Join(_) => String::from("Join"),
+            LogicalOp::Filter(_) => String::from("Filter"),
+            LogicalOp::Sort(_) => String::from("Sort"),
+            LogicalOp::Limit(_) => String::from("Limit"),
+            LogicalOp::Distinct => String::from("Distinct"),
+            LogicalOp::CrossProduct => String::from("Cross product"),
+            LogicalOp::SetOp(node) => node.to_string(),
+        }
+    }
+
+    /// Returns a scan of the rows a filter of a scan can select through an index of the table and
+    /// the name of the scan, None if no index helps.
+    ///
+    /// An index helps if the filter's predicate, or one of the predicates it is a conjunction
+    /// of, compares the indexed column with a value of its type. Of several helpful indexes the
+    /// one read in a range bounded on both ends is preferred.
+    ///
+    /// # Arguments
+    ///
+    /// * `catalog` - Catalog of the database containing the metadata about the tables and such.
+    /// * `lp` - Logical plan of the filter.
+    /// * `start` - Index of the filter in the plan.
+    /// * `predicate` - Predicate of the filter.
+    /// * `tid` - Id of the transaction that this executor is running.
+    fn index_scan<T: Catalog>(
+        storage_manager: &Arc<StorageManager>,
+        catalog: &T,
+        lp: &LogicalPlan,
+        start: OpIndex,
+        predicate: &PredicateExpr,
+        tid: TransactionId,
+    ) -> Result<Option<(IndexScan, String)>, CrustyError> {
+        let mut edges = lp.edges(start);
+        let alias = match (edges.next().and_then(|n| lp.get_operator(n)), edges.next()) {
+            (Some(LogicalOp::Scan(ScanNode { alias })), None) => alias,
+            _ => return Ok(None),
+        };
+        let conjuncts = match predicate {
+            PredicateExpr::And(preds) => preds.as_slice(),
+            pred => std::slice::from_ref(pred),
+        };
+        let table = catalog.get_table_ptr(Table::get_table_id(alias))?;
+        let mut best: Option<(IndexInfo, KeyRange)> = None;
+        {
+            let table_ref = table.read().unwrap();
+            for index in &table_ref.indexes {
+                let dtype = match table_ref.schema.get_field_index(&index.column) {
+                    Some(i) => table_ref.schema.get_attribute(*i).unwrap().dtype(),
+                    None => continue,
+                };
+                let column = format!("{}.{}", alias, index.column);
+                let range = conjuncts
+                    .iter()
+                    .filter_map(|p| Executor::key_range(p, &column, dtype))
+                    .reduce(KeyRange::intersect);
+                if let Some(range) = range {
+                    let better = match &best {
+                        Some((_, b)) => range.is_bounded() && !b.is_bounded(),
+                        None => true,
+                    };
+                    if better {
+                        best = Some((index.clone(), range));
+                    }
+                }
+            }
+        }
+        Ok(best.map(|(index, range)| {
+            let name = format!("Index scan {} using {}", alias, index.name);
+            let scan = IndexScan::new(
+                storage_manager.clone(),
+                table,
+                alias,
+                index.container_id,
+                range,
+                tid,
+            );
+            (scan, name)
+        }))
+    }
+
+    /// Keys of an index on a column a predicate holds for, None if the predicate is not a
+    /// comparison of the column with a value of its type that a range of keys answers.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Predicate of a filter.
+    /// * `column` - Indexed column, qualified by the table alias.
+    /// * `dtype` - Type of the indexed column.
+    fn key_range(predicate: &PredicateExpr, column: &str, dtype: &DataType) -> Option<KeyRange> {
+        let (op, value) = match predicate {
+            PredicateExpr::Compare(p) => match (&p.left, &p.right) {
+                (PredExpr::Ident(i), PredExpr::Literal(f)) if i.column() == column => (p.op, f),
+                (PredExpr::Literal(f), PredExpr::Ident(i)) if i.column() == column => {
+                    (p.op.flip(), f)
+                }
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match (op, value, dtype) {
+            (PredicateOp::IsNull, _, _) | (PredicateOp::IsNotNull, _, _) => {}
+            (_, Field::IntField(_), DataType::Int)
+            | (_, Field::StringField(_), DataType::String) => {}
+            _ => return None,
+        }
+        KeyRange::from_comparison(op, value)
+    }
+
+    /// Wraps an operator in a RowGuard if the options limit the rows per operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operator to guard.
+    /// * `name` - Name of the operator, for the error message.
+    /// * `options` - Options of the plan's operators.
+    fn guard_rows(
+        op: Box<dyn OpIterator>,
+        name: &'static str,
+        options: &PlanOptions,
+    ) -> Box<dyn OpIterator> {
+        match options.max_operator_rows {
+            Some(max_rows) => Box::new(RowGuard::new(max_rows, name, op)),
+            None => op,
+        }
+    }
+
+    /// Factory of the operators of the parts of a node, and their schema, if the options run
+    /// the plan on several threads and the node is a scan or a filter of one that does not
+    /// use an index. The parts of the table are read in the order of a scan of the whole table.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage_manager` - The SM for the DB to get access to files/buffer pool.
+    /// * `catalog` - Catalog of the database containing the metadata about the tables and such.
+    /// * `lp` - Logical plan the node belongs to.
+    /// * `node` - Node to read in parts.
+    /// * `tid` - Id of the transaction that this executor is running.
+    /// * `options` - Options of the plan's operators.
+    fn partition_factory<T: Catalog>(
+        storage_manager: &Arc<StorageManager>,
+        catalog: &T,
+        lp: &LogicalPlan,
+        node: OpIndex,
+        tid: TransactionId,
+        options: &PlanOptions,
+    ) -> Result<Option<(PartitionFactory, TableSchema)>, CrustyError> {
+        if options.parallelism <= 1 {
+            return Ok(None);
+        }
+        let (predicate, scan) = match lp.get_operator(node) {
+            Some(LogicalOp::Scan(_)) => (None, node),
+            Some(LogicalOp::Filter(FilterNode { predicate, .. })) => {
+                let mut inputs = lp.edges(node);
+                match (inputs.next(), inputs.next()) {
+                    (Some(scan), None) => (Some(predicate.clone()), scan),
+                    _ => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        let alias = match lp.get_operator(scan) {
+            Some(LogicalOp::Scan(ScanNode { alias })) => alias.clone(),
+            _ => return Ok(None),
+        };
+        if let Some(predicate) = &predicate {
+            if Executor::index_scan(storage_manager, catalog, lp, node, predicate, tid)?.is_some() {
+                return Ok(None);
+            }
+        }
+        let table = catalog.get_table_ptr(Table::get_table_id(&alias))?;
+        let schema = SeqScan::schema(&table.read().unwrap().schema, &alias);
+        let (storage_manager, options) = (storage_manager.clone(), options.clone());
+        let partitions = options.parallelism;
+        let part_schema = schema.clone();
+        let factory: PartitionFactory = Arc::new(move |partition| {
+            let scan = SeqScan::new(
+                storage_manager.clone(),
+                table.clone(),
+                &alias,
+                tid,
+                options.scan,
+            )
+            .with_partition(partition, partitions);
+            let scan = Executor::cancellable(Box::new(scan), &options);
+            match &predicate {
+                Some(predicate) => {
+                    let predicate = Executor::filter_predicate(predicate, &part_schema)?;
+                    Ok(Box::new(Filter::with_predicate(predicate, scan)) as Box<dyn OpIterator>)
+                }
+                None => Ok(scan),
+            }
+        });
+        Ok(Some((factory, schema)))
+    }
+
+    /// Wraps a scan in a Cancellable if the options have a cancel token or a deadline.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Scan to stop once the token is set.
+    /// * `options` - Options of the plan's operators.
+    fn cancellable(op: Box<dyn OpIterator>, options: &PlanOptions) -> Box<dyn OpIterator> {
+        if options.cancel.is_none() && options.deadline.is_none() {
+            return op;
+        }
+        let token = options.cancel.clone().unwrap_or_default();
+        Box::new(Cancellable::new(token, op).with_deadline(options.deadline))
+    }
+
     /// Get the index of the column in the schema.
     ///
     /// # Arguments
@@ -236,6 +960,93 @@ impl Executor {
             .ok_or_else(|| CrustyError::ExecutionError(String::from("Unrecognized column name")))
     }
 
+    /// Converts the predicate of a filter to one over the fields of the child's schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Predicate of the logical filter.
+    /// * `schema` - Schema of the filter's child.
+    fn filter_predicate(
+        predicate: &PredicateExpr,
+        schema: &TableSchema,
+    ) -> Result<FilterPredicate, CrustyError> {
+        let convert = |preds: &[PredicateExpr]| {
+            preds
+                .iter()
+                .map(|p| Executor::filter_predicate(p, schema))
+                .collect::<Result<Vec<_>, CrustyError>>()
+        };
+        let operand = |side: &PredExpr| match side {
+            PredExpr::Ident(id) => match id.expr() {
+                Some(expr) => Executor::expression(expr, schema),
+                None => Ok(Expression::Field(Executor::get_field_index(
+                    id.column(),
+                    schema,
+                )?)),
+            },
+            PredExpr::Literal(f) => Ok(Expression::Literal(f.clone())),
+        };
+        match predicate {
+            // comparisons of computed values evaluate both sides
+            PredicateExpr::Compare(p)
+                if [&p.left, &p.right]
+                    .iter()
+                    .any(|side| side.ident().and_then(|id| id.expr()).is_some()) =>
+            {
+                Ok(FilterPredicate::expressions(
+                    p.op,
+                    operand(&p.left)?,
+                    operand(&p.right)?,
+                ))
+            }
+            PredicateExpr::Compare(p) => match (&p.left, &p.right) {
+                (PredExpr::Ident(i), PredExpr::Literal(f)) => {
+                    let idx = Executor::get_field_index(i.column(), schema)?;
+                    Ok(FilterPredicate::new(p.op, idx, f.clone()))
+                }
+                (PredExpr::Literal(f), PredExpr::Ident(i)) => {
+                    let idx = Executor::get_field_index(i.column(), schema)?;
+                    Ok(FilterPredicate::new(p.op.flip(), idx, f.clone()))
+                }
+                (PredExpr::Ident(l), PredExpr::Ident(r)) => {
+                    let left_idx = Executor::get_field_index(l.column(), schema)?;
+                    let right_idx = Executor::get_field_index(r.column(), schema)?;
+                    Ok(FilterPredicate::fields(p.op, left_idx, right_idx))
+                }
+                _ => Err(CrustyError::ExecutionError(String::from(
+                    "Malformed logical plan",
+                ))),
+            },
+            PredicateExpr::And(preds) => Ok(FilterPredicate::And(convert(preds)?)),
+            PredicateExpr::Or(preds) => Ok(FilterPredicate::Or(convert(preds)?)),
+        }
+    }
+
+    /// Compiles an expression of the logical plan to one over the fields of the child's schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression of the logical plan.
+    /// * `schema` - Schema of the tuples the expression is computed from.
+    fn expression(expr: &ScalarExpr, schema: &TableSchema) -> Result<Expression, CrustyError> {
+        match expr {
+            ScalarExpr::Column(id) => Ok(Expression::Field(Executor::get_field_index(
+                id.column(),
+                schema,
+            )?)),
+            ScalarExpr::Literal(field) => Ok(Expression::Literal(field.clone())),
+            ScalarExpr::Binary { left, op, right } => Ok(Expression::binary(
+                Executor::expression(left, schema)?,
+                *op,
+                Executor::expression(right, schema)?,
+            )),
+            ScalarExpr::Function { function, arg } => Ok(Expression::function(
+                *function,
+                Executor::expression(arg, schema)?,
+            )),
+        }
+    }
+
     // TODO: Fix test cases to be able to address the clippy warning of pointer arguments.
     /// Finds the column indices and names of column alias present in the given schema.
     ///
@@ -260,6 +1071,174 @@ impl Executor {
     }
 }
 
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+#[cfg(test)]
+mod checksum_test {
+    use super::*;
+    use common::{Attribute, DataType, Field};
+
+    fn run(rows: Vec<Vec<i32>>, checksum: ResultChecksum) -> String {
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("a"), DataType::Int),
+            Attribute::new(String::from("b"), DataType::Int),
+        ]);
+        let tuples = rows
+            .into_iter()
+            .map(|r| Tuple::new(r.into_iter().map(Field::IntField).collect()))
+            .collect();
+        let mut executor = Executor::new_ref();
+        executor.configure_query(
+            Box::new(TupleIterator::new(tuples, schema)),
+            TransactionId::new(),
+        );
+        executor
+            .execute_with_checksum(checksum)
+            .unwrap()
+            .result()
+            .to_string()
+    }
+
+    #[test]
+    fn test_checksum() {
+        let rows = vec![vec![1, 2], vec![3, 4], vec![3, 4]];
+        let plain = run(rows.clone(), ResultChecksum::Off);
+        assert!(!plain.contains("checksum"));
+        let with_rows = run(rows.clone(), ResultChecksum::On);
+        assert!(with_rows.starts_with(&plain));
+        let checksum = with_rows.lines().last().unwrap();
+        assert!(checksum.starts_with("checksum: ") && checksum.ends_with("(3 rows)"));
+        // the checksum does not depend on the order of the rows
+        let only = |rows| run(rows, ResultChecksum::Only);
+        let shuffled = only(vec![vec![3, 4], vec![1, 2], vec![3, 4]]);
+        assert_eq!(format!("a  b  \n{}\n", checksum), shuffled);
+        // but does on their values and how often they occur
+        assert_ne!(shuffled, only(vec![vec![1, 2], vec![3, 4]]));
+        assert_ne!(shuffled, only(vec![vec![2, 1], vec![3, 4], vec![3, 4]]));
+    }
+}
+
+#[cfg(test)]
+mod abort_test {
+    use super::*;
+    use common::testutil::*;
+    use common::Field;
+    use std::mem::size_of;
+
+    /// Keeps the batches it is handed, and fails once it was handed `fail_after` of them.
+    struct VecSink {
+        schema: Option<TableSchema>,
+        batches: Vec<Vec<Tuple>>,
+        fail_after: usize,
+    }
+
+    impl RowSink for VecSink {
+        fn start(&mut self, schema: &TableSchema) -> Result<(), CrustyError> {
+            self.schema = Some(schema.clone());
+            Ok(())
+        }
+
+        fn send(&mut self, tuples: Vec<Tuple>) -> Result<(), CrustyError> {
+            if self.batches.len() == self.fail_after {
+                return Err(CrustyError::IOError(String::from("client went away")));
+            }
+            self.batches.push(tuples);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_execute_streaming() {
+        let schema = TableSchema::from_vecs(vec!["a"], vec![DataType::Int]);
+        let tuples: Vec<Tuple> = (0..5).map(|i| Tuple::new(vec![Field::IntField(i)])).collect();
+        let mut executor = Executor::new_ref();
+        executor.configure_query(
+            Box::new(TupleIterator::new(tuples.clone(), schema.clone())),
+            TransactionId::new(),
+        );
+        let mut sink = VecSink {
+            schema: None,
+            batches: Vec::new(),
+            fail_after: usize::MAX,
+        };
+        let res = executor.execute_streaming(2, &mut sink).unwrap();
+        assert!(res.is_streamed());
+        assert_eq!("5 rows", res.result());
+        assert_eq!(Some(schema.clone()), sink.schema);
+        let sizes: Vec<usize> = sink.batches.iter().map(Vec::len).collect();
+        assert_eq!(vec![2, 2, 1], sizes);
+        assert_eq!(tuples, sink.batches.concat());
+
+        // a sink that fails aborts the plan
+        executor.configure_query(
+            Box::new(TupleIterator::new(tuples, schema)),
+            TransactionId::new(),
+        );
+        let mut sink = VecSink {
+            schema: None,
+            batches: Vec::new(),
+            fail_after: 1,
+        };
+        assert!(executor.execute_streaming(2, &mut sink).is_err());
+        assert_eq!(1, sink.batches.len());
+        assert!(executor.plan.is_none());
+    }
+
+    #[test]
+    fn test_abort_on_error() {
+        let sm = Arc::new(StorageManager::new(
+            gen_random_dir().to_str().unwrap().to_string(),
+        ));
+        let rows = (0..1000).rev().map(|i| vec![i]).collect();
+        let child = TupleIterator::new(create_tuple_list(rows), get_int_table_schema(1));
+        let options = SortOptions {
+            memory_limit: 50 * (size_of::<Tuple>() + size_of::<Field>()),
+            ..SortOptions::default()
+        };
+        let tid = TransactionId::new();
+        let sort = SortIterator::new(
+            vec![SortKey::new(0, true)],
+            options,
+            sm.clone(),
+            tid,
+            Box::new(child),
+        );
+        // fails after the sort spilled its runs and while it merges them
+        let guard = RowGuard::new(10, "Sort", Box::new(sort));
+        let mut executor = Executor::new_ref();
+        executor.configure_sm(&sm);
+        executor.configure_query(Box::new(guard), tid);
+        assert!(executor.execute().is_err());
+        assert!(executor.plan.is_none());
+        assert!(sm.list_containers().is_empty());
+        // closing the aborted plan does nothing
+        executor.close().unwrap();
+    }
+
+    #[test]
+    fn test_cancel() {
+        let rows = (0..10).map(|i| vec![i]).collect();
+        let child = TupleIterator::new(create_tuple_list(rows), get_int_table_schema(1));
+        let token = CancelToken::new();
+        let mut executor = Executor::new_ref();
+        executor.set_cancel_token(token.clone());
+        executor.configure_query(Box::new(child), TransactionId::new());
+        executor.start().unwrap();
+        assert!(executor.next().unwrap().is_some());
+        token.cancel();
+        match executor.next() {
+            Err(CrustyError::ExecutionError(msg)) => assert_eq!("Query cancelled", msg),
+            other => panic!("Expected an execution error, got {:?}", other),
+        }
+        // a cancelled plan is aborted
+        assert!(executor.plan.is_none());
+    }
+}
+
 /* FIXME
 #[cfg(test)]
 mod test {
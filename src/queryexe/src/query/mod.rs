@@ -1,5 +1,6 @@
-pub use executor::Executor;
+pub use executor::{Executor, PlanOptions, ResultChecksum, RowSink};
 pub use translate_and_validate::TranslateAndValidate;
+mod datetime;
 mod executor;
 mod translate_and_validate;
 
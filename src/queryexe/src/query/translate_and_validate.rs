@@ -1,13 +1,22 @@
+use super::datetime::{DateTime, Interval};
 use common::catalog::Catalog;
 use common::logical_plan::*;
 use common::table::*;
 use common::{get_name, CrustyError, DataType, Field, PredicateOp};
 use sqlparser::ast::{
-    BinaryOperator, Expr, Function, JoinConstraint, JoinOperator, SelectItem, SetExpr, TableFactor,
-    Value,
+    BinaryOperator, Expr, Function, JoinConstraint, JoinOperator, OrderByExpr, SelectItem, SetExpr,
+    TableFactor, UnaryOperator, Value,
 };
 use std::collections::HashSet;
 
+/// What an ORDER BY expression sorts on.
+enum SortTarget {
+    /// A field of the select list, by position.
+    Selected(usize),
+    /// A column of the tables in the query.
+    Field(FieldIdentifier),
+}
+
 /// Translates input to a LogicalPlan
 /// Validates the columns and tables referenced using the catalog
 /// Shares lifetime 'a with catalog
@@ -106,7 +115,8 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
         match &query.body {
             SetExpr::Select(b) => {
                 let select = &*b;
-                self.process_select(select)
+                self.process_select(select, &query.order_by)?;
+                self.process_limit(query)
             }
             SetExpr::Query(_) => {
                 //TODO NOT HANDLED
@@ -115,83 +125,290 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
                 )))
             }
             SetExpr::SetOperation {
-                op: _,
-                all: _,
-                left: _,
-                right: _,
+                op,
+                all,
+                left,
+                right,
             } => {
+                self.process_set_operation(op, *all, left, right)?;
+                self.process_set_order_by(&query.order_by)?;
+                self.process_limit(query)
+            }
+            SetExpr::Values(_) => {
                 //TODO NOT HANDLED
                 Err(CrustyError::ValidationError(String::from(
-                    "Set operations not supported ",
+                    "Value operation not supported ",
                 )))
             }
+        }
+    }
+
+    /// Translates the two queries of a set operation into plans of their own, and combines
+    /// them with a SetOp node, the left one first.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Set operator.
+    /// * `all` - Whether duplicate rows are kept.
+    /// * `left` - Left query.
+    /// * `right` - Right query.
+    fn process_set_operation(
+        &mut self,
+        op: &sqlparser::ast::SetOperator,
+        all: bool,
+        left: &SetExpr,
+        right: &SetExpr,
+    ) -> Result<(), CrustyError> {
+        let op = match op {
+            sqlparser::ast::SetOperator::Union => SetOperator::Union,
+            sqlparser::ast::SetOperator::Intersect => SetOperator::Intersect,
+            sqlparser::ast::SetOperator::Except => SetOperator::Except,
+        };
+        let left = self.set_operand(left)?;
+        let right = self.set_operand(right)?;
+        // The left query's tables name the columns of the result
+        self.plan = left.plan;
+        self.tables = left.tables;
+        let left_root = self.plan.root().unwrap();
+        let right_root = self.plan.add_plan(&right.plan).unwrap();
+        let idx = self.plan.add_node(LogicalOp::SetOp(SetOpNode { op, all }));
+        self.plan.add_edge(idx, right_root);
+        self.plan.add_edge(idx, left_root);
+        Ok(())
+    }
+
+    /// Translates a query of a set operation with a translator of its own, as its tables are
+    /// not those of the other query.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Query to translate.
+    fn set_operand(&self, expr: &SetExpr) -> Result<Self, CrustyError> {
+        let mut translator = TranslateAndValidate::new(self.catalog);
+        match expr {
+            SetExpr::Select(select) => translator.process_select(select, &[])?,
+            SetExpr::Query(query) => translator.process_query(query)?,
+            SetExpr::SetOperation {
+                op,
+                all,
+                left,
+                right,
+            } => translator.process_set_operation(op, *all, left, right)?,
             SetExpr::Values(_) => {
-                //TODO NOT HANDLED
-                Err(CrustyError::ValidationError(String::from(
+                return Err(CrustyError::ValidationError(String::from(
                     "Value operation not supported ",
                 )))
             }
         }
+        Ok(translator)
     }
 
-    /// Helper function to recursively process sqlparser::ast::Select
+    /// Adds a Sort node on top of a set operation for the ORDER BY of the query, if it has one.
+    ///
+    /// Rows are sorted on the columns of the result, named as those of the left query, like
+    /// `a` or `t.a`.
     ///
     /// # Arguments
     ///
-    /// * `query` - AST of a select query to process.
-    fn process_select(&mut self, select: &sqlparser::ast::Select) -> Result<(), CrustyError> {
-        // Pointer to the current node.
-        let mut node = None;
+    /// * `order_by` - ORDER BY clause of the query.
+    fn process_set_order_by(&mut self, order_by: &[OrderByExpr]) -> Result<(), CrustyError> {
+        if order_by.is_empty() {
+            return Ok(());
+        }
+        let columns = self.output_columns(self.plan.root().unwrap())?;
+        let mut fields = Vec::new();
+        for order in order_by {
+            let name = match &order.expr {
+                Expr::Identifier(name) => name.clone(),
+                Expr::CompoundIdentifier(names) => names.join("."),
+                _ => {
+                    return Err(CrustyError::ValidationError(String::from(
+                        "Order by unsupported expression",
+                    )));
+                }
+            };
+            let suffix = format!(".{}", name);
+            let mut matches = columns
+                .iter()
+                .filter(|c| **c == name || c.ends_with(&suffix));
+            let column = match (matches.next(), matches.next()) {
+                (Some(column), None) => column,
+                (None, _) => {
+                    return Err(CrustyError::ValidationError(format!(
+                        "The field {} is not a column of the set operation",
+                        name
+                    )))
+                }
+                (Some(_), Some(_)) => {
+                    return Err(CrustyError::ValidationError(format!(
+                        "The field {} could refer to more than one column of the set operation",
+                        name
+                    )))
+                }
+            };
+            fields.push(SortField {
+                field: FieldIdentifier::new("", column),
+                ascending: order.asc != Some(false),
+            });
+        }
+        let root = self.plan.root().unwrap();
+        let idx = self.plan.add_node(LogicalOp::Sort(SortNode { fields }));
+        self.plan.add_edge(idx, root);
+        Ok(())
+    }
 
-        // Distinct
-        if select.distinct {
-            //TODO NOT HANDLED
-            return Err(CrustyError::ValidationError(String::from(
-                "Distinct not supported ",
-            )));
+    /// Names of the columns a node of the plan returns, found through the nodes above the
+    /// projection of a query, and the left child of a set operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - Node whose columns are named.
+    fn output_columns(&self, node: OpIndex) -> Result<Vec<String>, CrustyError> {
+        match self.plan.get_operator(node) {
+            Some(LogicalOp::Project(ProjectNode {
+                identifiers: ProjectIdentifiers::List(ids),
+            })) => Ok(ids
+                .iter()
+                .map(|id| id.alias().unwrap_or_else(|| id.column()).to_string())
+                .collect()),
+            Some(LogicalOp::Project(ProjectNode {
+                identifiers: ProjectIdentifiers::Wildcard,
+            })) => {
+                let mut columns = Vec::new();
+                for table in &self.tables {
+                    let schema = self.catalog.get_table_schema(Table::get_table_id(table))?;
+                    columns.extend(
+                        schema
+                            .attributes()
+                            .map(|a| format!("{}.{}", table, a.name())),
+                    );
+                }
+                Ok(columns)
+            }
+            _ => match self.plan.edges(node).next() {
+                Some(child) => self.output_columns(child),
+                None => Err(CrustyError::ValidationError(String::from(
+                    "Malformed logical plan",
+                ))),
+            },
         }
+    }
 
-        // Doesn't need the for loop rn but keeping for the future when cross products are supported.
-        // From
-        if select.from.len() > 1 {
-            //TODO NOT HANDLED
-            return Err(CrustyError::ValidationError(String::from(
-                "Cross product not supported ",
-            )));
+    /// Adds a Limit node on top of the plan for the LIMIT and OFFSET of a query, if it has
+    /// either.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - AST of the query.
+    fn process_limit(&mut self, query: &sqlparser::ast::Query) -> Result<(), CrustyError> {
+        let count = |expr: &Expr, clause: &str| match expr {
+            Expr::Value(Value::Number(s)) => s
+                .parse::<usize>()
+                .map_err(|_| CrustyError::ValidationError(format!("Invalid {} {}", clause, s))),
+            _ => Err(CrustyError::ValidationError(format!(
+                "Unsupported {} expression",
+                clause
+            ))),
+        };
+        let limit = query
+            .limit
+            .as_ref()
+            .map(|e| count(e, "limit"))
+            .transpose()?;
+        let offset = query
+            .offset
+            .as_ref()
+            .map(|e| count(e, "offset"))
+            .transpose()?
+            .unwrap_or(0);
+        if limit.is_none() && offset == 0 {
+            return Ok(());
         }
+        let root = self.plan.root().unwrap();
+        let idx = self
+            .plan
+            .add_node(LogicalOp::Limit(LimitNode { limit, offset }));
+        self.plan.add_edge(idx, root);
+        Ok(())
+    }
+
+    /// Helper function to recursively process sqlparser::ast::Select
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - AST of a select query to process.
+    /// * `order_by` - ORDER BY clause of the query, empty if it has none.
+    fn process_select(
+        &mut self,
+        select: &sqlparser::ast::Select,
+        order_by: &[OrderByExpr],
+    ) -> Result<(), CrustyError> {
+        // From, each item with the tables it added, so columns of every item can be
+        // disambiguated before the items are combined
+        let mut from = Vec::new();
         for sel in &select.from {
-            node = Some(self.process_table_factor(&sel.relation)?);
+            let first_table = self.tables.len();
+            let mut item = self.process_table_factor(&sel.relation)?;
             // Join
             for join in &sel.joins {
-                let join_node = self.process_join(&join, node.unwrap())?;
-                node = Some(join_node);
+                item = self.process_join(&join, item)?;
             }
+            from.push((item, self.tables[first_table..].to_vec()));
         }
+        let mut from = from.into_iter();
+        let (mut current, mut tables) = from.next().ok_or_else(|| {
+            CrustyError::ValidationError(String::from("Select without from not supported"))
+        })?;
 
-        // Where
-        if let Some(expr) = &select.selection {
-            let predicate = self.process_binary_op(expr)?;
-            // table references in filter
-            let table = match (&predicate.left, &predicate.right) {
-                (PredExpr::Literal(_), PredExpr::Ident(id)) => id.table().to_string(),
-                (PredExpr::Ident(id), PredExpr::Literal(_)) => id.table().to_string(),
-                _ => {
-                    return Err(CrustyError::ValidationError(String::from("Only where predicates with at least one indentifier and at least one literal are supported")));
+        // Where, split into its conjuncts so any of them that compares columns of two items of
+        // the from list is used as their join predicate
+        let mut conjuncts = match select
+            .selection
+            .as_ref()
+            .map(|expr| self.process_predicate(expr))
+            .transpose()?
+        {
+            Some(PredicateExpr::And(preds)) => preds,
+            Some(pred) => vec![pred],
+            None => Vec::new(),
+        };
+        for (right, right_tables) in from {
+            let join = conjuncts.iter().enumerate().find_map(|(i, p)| match p {
+                PredicateExpr::Compare(p) => {
+                    Self::join_predicate(p, &tables, &right_tables).map(|join| (i, join))
                 }
+                _ => None,
+            });
+            let op = match join {
+                Some((i, (left_field, right_field, op))) => {
+                    conjuncts.remove(i);
+                    LogicalOp::Join(JoinNode {
+                        left: left_field,
+                        right: right_field,
+                        op,
+                        left_table: self.get_table_alias_from_op(current),
+                        right_table: self.get_table_alias_from_op(right),
+                        join_type: JoinType::Inner,
+                    })
+                }
+                None => LogicalOp::CrossProduct,
             };
-            let op = FilterNode { table, predicate };
+            let idx = self.plan.add_node(op);
+            self.plan.add_edge(idx, right);
+            self.plan.add_edge(idx, current);
+            current = idx;
+            tables.extend(right_tables);
+        }
+        // Pointer to the current node.
+        let mut node = Some(current);
+
+        if !conjuncts.is_empty() {
+            let op = Self::filter_node(PredicateExpr::and(conjuncts), "where")?;
             let idx = self.plan.add_node(LogicalOp::Filter(op));
             self.plan.add_edge(idx, node.unwrap());
             node = Some(idx);
         }
 
-        if select.having.is_some() {
-            //TODO NOT HANDLED
-            return Err(CrustyError::ValidationError(String::from(
-                "Having not supported",
-            )));
-        }
-
         // Select
         let mut fields = Vec::new();
         let mut has_agg = false;
@@ -207,9 +424,9 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
                     wildcard = true;
                     break;
                 }
-                SelectItem::UnnamedExpr(expr) => self.expr_to_ident(expr)?,
+                SelectItem::UnnamedExpr(expr) => self.select_expr_to_ident(expr)?,
                 SelectItem::ExprWithAlias { expr, alias } => {
-                    let mut field = self.expr_to_ident(expr)?;
+                    let mut field = self.select_expr_to_ident(expr)?;
                     field.set_alias(alias.to_string());
                     field
                 }
@@ -226,6 +443,30 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
             fields.push(field);
         }
 
+        // Having groups the rows even if no aggregates are selected
+        let having = match &select.having {
+            Some(expr) => {
+                has_agg = true;
+                Some(self.process_predicate(expr)?)
+            }
+            None => None,
+        };
+
+        // Order by, resolved before aggregate fields are renamed below
+        let mut sort_fields = Vec::new();
+        for order in order_by {
+            sort_fields.push((
+                self.order_by_field(order, &fields)?,
+                order.asc != Some(false),
+            ));
+        }
+        // Computed fields only exist above the projection, so sorting on one sorts there
+        let sort_on_top = select.distinct
+            || sort_fields.iter().any(|(target, _)| match target {
+                SortTarget::Selected(i) => fields[*i].expr().is_some(),
+                SortTarget::Field(_) => false,
+            });
+
         // Aggregates and group by
         if has_agg {
             let mut group_by = Vec::new();
@@ -255,14 +496,27 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
                     }
                 }
             }
+
+            // Aggregates only used in having are computed but not projected
+            let mut agg_fields = fields.clone();
+            let having = having
+                .map(|p| p.try_map(&mut |c| Self::process_having(c, &mut agg_fields, &group_by)))
+                .transpose()?;
             let op = AggregateNode {
-                fields: fields.clone(),
+                fields: agg_fields,
                 group_by,
             };
             let idx = self.plan.add_node(LogicalOp::Aggregate(op));
             self.plan.add_edge(idx, node.unwrap());
             node = Some(idx);
 
+            if let Some(predicate) = having {
+                let op = Self::filter_node(predicate, "having")?;
+                let idx = self.plan.add_node(LogicalOp::Filter(op));
+                self.plan.add_edge(idx, node.unwrap());
+                node = Some(idx);
+            }
+
             // Replace field column names with aliases to project
             fields = fields
                 .iter()
@@ -272,6 +526,32 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
                 })
                 .collect();
         }
+
+        let sort_fields = sort_fields
+            .into_iter()
+            .map(|(target, ascending)| {
+                let field = if sort_on_top {
+                    Self::output_sort_field(target, &fields, wildcard)?
+                } else {
+                    match target {
+                        SortTarget::Selected(i) => fields[i].clone(),
+                        SortTarget::Field(f) => f,
+                    }
+                };
+                Ok(SortField { field, ascending })
+            })
+            .collect::<Result<Vec<SortField>, CrustyError>>()?;
+
+        // Sort below the projection, so rows can be ordered by columns that are not selected.
+        // With DISTINCT the sort goes on top instead, as duplicates are removed after projecting.
+        if !sort_fields.is_empty() && !sort_on_top {
+            let idx = self.plan.add_node(LogicalOp::Sort(SortNode {
+                fields: sort_fields.clone(),
+            }));
+            self.plan.add_edge(idx, node.unwrap());
+            node = Some(idx);
+        }
+
         let identifiers = if wildcard {
             ProjectIdentifiers::Wildcard
         } else {
@@ -280,9 +560,178 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
         let op = ProjectNode { identifiers };
         let idx = self.plan.add_node(LogicalOp::Project(op));
         self.plan.add_edge(idx, node.unwrap());
+        node = Some(idx);
+
+        if select.distinct {
+            let idx = self.plan.add_node(LogicalOp::Distinct);
+            self.plan.add_edge(idx, node.unwrap());
+            node = Some(idx);
+        }
+        if !sort_fields.is_empty() && sort_on_top {
+            let idx = self.plan.add_node(LogicalOp::Sort(SortNode {
+                fields: sort_fields,
+            }));
+            self.plan.add_edge(idx, node.unwrap());
+        }
         Ok(())
     }
 
+    /// Creates a filter node, checking that each comparison of the predicate has an identifier
+    /// on at least one side.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Predicate to filter by.
+    /// * `clause` - Clause of the predicate, for errors.
+    fn filter_node(predicate: PredicateExpr, clause: &str) -> Result<FilterNode, CrustyError> {
+        let mut table = None;
+        for comparison in predicate.comparisons() {
+            let id = match (&comparison.left, &comparison.right) {
+                (PredExpr::Ident(id), _) | (PredExpr::Literal(_), PredExpr::Ident(id)) => id,
+                _ => {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Only {} predicates with at least one identifier are supported",
+                        clause
+                    )));
+                }
+            };
+            table.get_or_insert_with(|| id.table().to_string());
+        }
+        Ok(FilterNode {
+            table: table.unwrap_or_default(),
+            predicate,
+        })
+    }
+
+    /// Rewrites a HAVING comparison to refer to the output columns of the aggregate.
+    ///
+    /// Aggregates of the predicate that are not selected are added to the aggregate's fields,
+    /// and other columns have to be grouped by.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - HAVING comparison over the columns of the tables.
+    /// * `agg_fields` - Fields of the aggregate, extended with the aggregates of the predicate.
+    /// * `group_by` - Fields grouped by.
+    fn process_having(
+        mut predicate: PredicateNode,
+        agg_fields: &mut Vec<FieldIdentifier>,
+        group_by: &[FieldIdentifier],
+    ) -> Result<PredicateNode, CrustyError> {
+        for side in [&mut predicate.left, &mut predicate.right].iter_mut() {
+            let field = match side {
+                PredExpr::Ident(field) => field,
+                PredExpr::Literal(_) => continue,
+            };
+            let output = match field.agg_op() {
+                Some(op) => {
+                    let existing = agg_fields
+                        .iter()
+                        .find(|f| f.column() == field.column() && f.agg_op() == Some(op));
+                    match existing {
+                        Some(f) => f.clone(),
+                        None => {
+                            agg_fields.push(field.clone());
+                            field.clone()
+                        }
+                    }
+                }
+                None => group_by
+                    .iter()
+                    .find(|f| f.column() == field.column())
+                    .cloned()
+                    .ok_or_else(|| {
+                        CrustyError::ValidationError(format!(
+                            "The expression '{}' in having must be part of an aggregate function or group by",
+                            field.column()
+                        ))
+                    })?,
+            };
+            let name = output.alias().unwrap_or_else(|| output.column());
+            **side = PredExpr::Ident(FieldIdentifier::new(output.table(), name));
+        }
+        Ok(predicate)
+    }
+
+    /// Resolves an ORDER BY target to a column of the projected rows, for a SELECT DISTINCT or
+    /// one sorted on a computed field.
+    ///
+    /// The rows are sorted after duplicates are removed or fields are computed, so only
+    /// selected fields can be sorted on.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - Resolved ORDER BY expression.
+    /// * `fields` - Fields of the select list.
+    /// * `wildcard` - Whether all columns are selected.
+    fn output_sort_field(
+        target: SortTarget,
+        fields: &[FieldIdentifier],
+        wildcard: bool,
+    ) -> Result<FieldIdentifier, CrustyError> {
+        let selected = match target {
+            SortTarget::Field(f) if wildcard => return Ok(f),
+            SortTarget::Selected(i) => &fields[i],
+            SortTarget::Field(f) => fields
+                .iter()
+                .find(|s| s.agg_op().is_none() && s.column() == f.column())
+                .ok_or_else(|| {
+                    CrustyError::ValidationError(format!(
+                        "The expression '{}' must be selected to be used in order by with distinct or a computed field",
+                        f.column()
+                    ))
+                })?,
+        };
+        let name = selected.alias().unwrap_or_else(|| selected.column());
+        Ok(FieldIdentifier::new(selected.table(), name))
+    }
+
+    /// Resolves an ORDER BY expression to a selected field or a column of the tables.
+    ///
+    /// Names are looked up among the aliases of the selected fields first, and aggregates
+    /// have to be selected to be sorted on.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - ORDER BY expression to resolve.
+    /// * `fields` - Fields of the select list.
+    fn order_by_field(
+        &self,
+        order: &OrderByExpr,
+        fields: &[FieldIdentifier],
+    ) -> Result<SortTarget, CrustyError> {
+        if let Expr::Identifier(name) = &order.expr {
+            if let Some(i) = fields.iter().position(|f| f.alias() == Some(name.as_str())) {
+                return Ok(SortTarget::Selected(i));
+            }
+        }
+        let field = match &order.expr {
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) | Expr::Function(_) => {
+                self.expr_to_ident(&order.expr)?
+            }
+            _ => {
+                return Err(CrustyError::ValidationError(String::from(
+                    "Order by unsupported expression",
+                )));
+            }
+        };
+        match field.agg_op() {
+            None if field.expr().is_none() && !fields.iter().any(|f| f.agg_op().is_some()) => {
+                Ok(SortTarget::Field(field))
+            }
+            op => fields
+                .iter()
+                .position(|f| f.column() == field.column() && f.agg_op() == op)
+                .map(SortTarget::Selected)
+                .ok_or_else(|| {
+                    CrustyError::ValidationError(format!(
+                        "The expression '{}' must be selected to be used in order by",
+                        order.expr
+                    ))
+                }),
+        }
+    }
+
     /// Creates a corresponding LogicalOp, adds it to self.plan, and returns the OpIndex.
     ///
     /// Helper function to process sqlparser::ast::TableFactor.
@@ -338,8 +787,11 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
         left_table_node: OpIndex,
     ) -> Result<OpIndex, CrustyError> {
         let right_table_node = self.process_table_factor(&join.relation)?;
-        let jc = match &join.join_operator {
-            JoinOperator::Inner(jc) => jc,
+        let (jc, join_type) = match &join.join_operator {
+            JoinOperator::Inner(jc) => (jc, JoinType::Inner),
+            JoinOperator::LeftOuter(jc) => (jc, JoinType::LeftOuter),
+            JoinOperator::RightOuter(jc) => (jc, JoinType::RightOuter),
+            JoinOperator::FullOuter(jc) => (jc, JoinType::FullOuter),
             _ => {
                 return Err(CrustyError::ValidationError(String::from(
                     "Unsupported join type",
@@ -352,6 +804,7 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
             let left = pred
                 .left
                 .ident()
+                .filter(|id| id.expr().is_none())
                 .ok_or_else(|| {
                     CrustyError::ValidationError(String::from("Invalid join predicate"))
                 })?
@@ -359,6 +812,7 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
             let right = pred
                 .right
                 .ident()
+                .filter(|id| id.expr().is_none())
                 .ok_or_else(|| {
                     CrustyError::ValidationError(String::from("Invalid join predicate"))
                 })?
@@ -369,6 +823,7 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
                 op: pred.op,
                 left_table: self.get_table_alias_from_op(left_table_node),
                 right_table: self.get_table_alias_from_op(right_table_node),
+                join_type,
             };
             let idx = self.plan.add_node(LogicalOp::Join(op));
             self.plan.add_edge(idx, right_table_node);
@@ -380,6 +835,237 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
         )))
     }
 
+    /// Returns the fields and operator of a predicate comparing a column of the left tables to
+    /// a column of the right tables, oriented so the left field comes first, or None if the
+    /// predicate is not a join predicate between them.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - WHERE predicate.
+    /// * `left_tables` - Tables of the left input of the join.
+    /// * `right_tables` - Tables of the right input of the join.
+    fn join_predicate(
+        predicate: &PredicateNode,
+        left_tables: &[String],
+        right_tables: &[String],
+    ) -> Option<(FieldIdentifier, FieldIdentifier, PredicateOp)> {
+        let left = predicate.left.ident().filter(|id| id.expr().is_none())?;
+        let right = predicate.right.ident().filter(|id| id.expr().is_none())?;
+        let in_tables = |id: &FieldIdentifier, tables: &[String]| {
+            tables.iter().any(|t| t.as_str() == id.table())
+        };
+        if in_tables(left, left_tables) && in_tables(right, right_tables) {
+            Some((left.clone(), right.clone(), predicate.op))
+        } else if in_tables(right, left_tables) && in_tables(left, right_tables) {
+            Some((right.clone(), left.clone(), predicate.op.flip()))
+        } else {
+            None
+        }
+    }
+
+    /// Parses a condition to a predicate, comparisons combined with AND and OR.
+    ///
+    /// Nested conjunctions and disjunctions of the same kind are flattened, so `a AND (b AND c)`
+    /// has three conjuncts.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Condition to parse.
+    fn process_predicate(&self, expr: &Expr) -> Result<PredicateExpr, CrustyError> {
+        match expr {
+            Expr::BinaryOp {
+                left,
+                op: op @ BinaryOperator::And,
+                right,
+            }
+            | Expr::BinaryOp {
+                left,
+                op: op @ BinaryOperator::Or,
+                right,
+            } => {
+                let mut preds = Vec::new();
+                for side in [left, right].iter() {
+                    match (self.process_predicate(side)?, op) {
+                        (PredicateExpr::And(inner), BinaryOperator::And)
+                        | (PredicateExpr::Or(inner), BinaryOperator::Or) => preds.extend(inner),
+                        (pred, _) => preds.push(pred),
+                    }
+                }
+                Ok(match op {
+                    BinaryOperator::And => PredicateExpr::And(preds),
+                    _ => PredicateExpr::Or(preds),
+                })
+            }
+            // `x BETWEEN low AND high` is `x >= low AND x <= high`
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => {
+                let (low_op, high_op) = if *negated {
+                    (PredicateOp::LessThan, PredicateOp::GreaterThan)
+                } else {
+                    (PredicateOp::GreaterThanOrEq, PredicateOp::LessThanOrEq)
+                };
+                let preds = vec![
+                    self.column_comparison(expr, low_op, low, "BETWEEN")?,
+                    self.column_comparison(expr, high_op, high, "BETWEEN")?,
+                ];
+                Ok(if *negated {
+                    PredicateExpr::Or(preds)
+                } else {
+                    PredicateExpr::And(preds)
+                })
+            }
+            // `x IN (a, b)` is `x = a OR x = b`
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let op = if *negated {
+                    PredicateOp::NotEq
+                } else {
+                    PredicateOp::Equals
+                };
+                let mut preds = list
+                    .iter()
+                    .map(|item| self.column_comparison(expr, op, item, "IN"))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(match preds.len() {
+                    0 => {
+                        return Err(CrustyError::ValidationError(String::from(
+                            "IN needs at least one value",
+                        )))
+                    }
+                    1 => preds.remove(0),
+                    _ if *negated => PredicateExpr::And(preds),
+                    _ => PredicateExpr::Or(preds),
+                })
+            }
+            Expr::IsNull(column) | Expr::IsNotNull(column) => {
+                let op = match expr {
+                    Expr::IsNull(_) => PredicateOp::IsNull,
+                    _ => PredicateOp::IsNotNull,
+                };
+                Ok(PredicateExpr::Compare(PredicateNode {
+                    left: PredExpr::Ident(self.expr_to_ident(column)?),
+                    right: PredExpr::Literal(Field::Null),
+                    op,
+                }))
+            }
+            Expr::Nested(expr) => self.process_predicate(expr),
+            _ => Ok(PredicateExpr::Compare(self.process_binary_op(expr)?)),
+        }
+    }
+
+    /// Parses a comparison of a column to a literal of the same type, part of a BETWEEN or IN.
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - Column compared.
+    /// * `op` - Operator comparing the column to the literal.
+    /// * `literal` - Literal compared against.
+    /// * `clause` - Name of the construct, for errors.
+    fn column_comparison(
+        &self,
+        column: &Expr,
+        op: PredicateOp,
+        literal: &Expr,
+        clause: &str,
+    ) -> Result<PredicateExpr, CrustyError> {
+        let (left, right) = match (
+            self.expr_to_pred_expr(column)?,
+            self.expr_to_pred_expr(literal)?,
+        ) {
+            (PredExpr::Ident(id), PredExpr::Literal(f)) => (id, f),
+            _ => {
+                return Err(CrustyError::ValidationError(format!(
+                    "Only {} of a column and literals is supported",
+                    clause
+                )));
+            }
+        };
+        self.validate_literal_type(&left, &right)?;
+        Ok(PredicateExpr::Compare(PredicateNode {
+            left: PredExpr::Ident(left),
+            right: PredExpr::Literal(right),
+            op,
+        }))
+    }
+
+    /// Type of a column of a table or of a computed field, None for aggregates.
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - Disambiguated column.
+    fn column_type(&self, field: &FieldIdentifier) -> Option<DataType> {
+        if field.agg_op().is_some() {
+            return None;
+        }
+        if let Some(expr) = field.expr() {
+            return self.expr_type(expr);
+        }
+        let col_name = field.column().split('.').nth(1)?;
+        let schema = self
+            .catalog
+            .get_table_schema(Table::get_table_id(field.table()))
+            .ok()?;
+        let attr = schema.get_attribute(*schema.get_field_index(col_name)?)?;
+        Some(attr.dtype().clone())
+    }
+
+    /// Type of the values of a scalar expression, None if it only has NULL literals.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Validated expression.
+    fn expr_type(&self, expr: &ScalarExpr) -> Option<DataType> {
+        match expr {
+            ScalarExpr::Column(id) => self.column_type(id),
+            ScalarExpr::Literal(Field::IntField(_)) => Some(DataType::Int),
+            ScalarExpr::Literal(Field::StringField(_)) => Some(DataType::String),
+            ScalarExpr::Literal(Field::Null) => None,
+            ScalarExpr::Binary { left, right, .. } => {
+                self.expr_type(left).or_else(|| self.expr_type(right))
+            }
+            ScalarExpr::Function { function, .. } => Some(function.return_type()),
+        }
+    }
+
+    /// Validates that a literal has the type of the column it is compared to.
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - Column compared.
+    /// * `literal` - Literal compared against.
+    fn validate_literal_type(
+        &self,
+        field: &FieldIdentifier,
+        literal: &Field,
+    ) -> Result<(), CrustyError> {
+        let dtype = match self.column_type(field) {
+            Some(dtype) => dtype,
+            None => return Ok(()),
+        };
+        let matches = match literal {
+            Field::Null => true,
+            Field::IntField(_) => dtype == DataType::Int,
+            Field::StringField(_) => dtype == DataType::String,
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(CrustyError::ValidationError(format!(
+                "Cannot compare the {:?} column {} to {}",
+                dtype,
+                field.column(),
+                literal
+            )))
+        }
+    }
+
     /// Parses an expression to a predicate node.
     ///
     /// # Arguments
@@ -387,17 +1073,70 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
     /// * `expr` - Expression to parse.
     fn process_binary_op(&self, expr: &Expr) -> Result<PredicateNode, CrustyError> {
         match expr {
-            Expr::BinaryOp { left, op, right } => Ok(PredicateNode {
-                left: self.expr_to_pred_expr(left)?,
-                right: self.expr_to_pred_expr(right)?,
-                op: Self::binary_operator_to_predicate(op)?,
-            }),
+            Expr::BinaryOp { left, op, right } => {
+                let op = Self::binary_operator_to_predicate(op)?;
+                if op == PredicateOp::Like || op == PredicateOp::NotLike {
+                    return self.process_like(left, op, right);
+                }
+                // `col + INTERVAL ...` is compared by moving the interval to the other side.
+                if let Some((col, interval)) = Self::shifted_column(left)? {
+                    return Ok(PredicateNode {
+                        left: PredExpr::Ident(self.expr_to_ident(col)?),
+                        right: Self::shifted_literal(right, interval)?,
+                        op,
+                    });
+                }
+                if let Some((col, interval)) = Self::shifted_column(right)? {
+                    return Ok(PredicateNode {
+                        left: Self::shifted_literal(left, interval)?,
+                        right: PredExpr::Ident(self.expr_to_ident(col)?),
+                        op,
+                    });
+                }
+                Ok(PredicateNode {
+                    left: self.expr_to_pred_expr(left)?,
+                    right: self.expr_to_pred_expr(right)?,
+                    op,
+                })
+            }
             _ => Err(CrustyError::ValidationError(String::from(
                 "Unsupported binary operation",
             ))),
         }
     }
 
+    /// Parses a LIKE comparison of a string column to a string pattern.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Column matched.
+    /// * `op` - Like or NotLike.
+    /// * `right` - Pattern.
+    fn process_like(
+        &self,
+        left: &Expr,
+        op: PredicateOp,
+        right: &Expr,
+    ) -> Result<PredicateNode, CrustyError> {
+        match (
+            self.expr_to_pred_expr(left)?,
+            self.expr_to_pred_expr(right)?,
+        ) {
+            (PredExpr::Ident(id), PredExpr::Literal(pattern @ Field::StringField(_)))
+                if self.column_type(&id) != Some(DataType::Int) =>
+            {
+                Ok(PredicateNode {
+                    left: PredExpr::Ident(id),
+                    right: PredExpr::Literal(pattern),
+                    op,
+                })
+            }
+            _ => Err(CrustyError::ValidationError(String::from(
+                "Only LIKE of a string column and a string pattern is supported",
+            ))),
+        }
+    }
+
     /// Parses the non-operator parts of the expression to predicate expressions.
     ///
     /// # Arguments
@@ -413,15 +1152,131 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
                     let f = Field::IntField(i);
                     Ok(PredExpr::Literal(f))
                 }
-                Value::SingleQuotedString(s) => {
+                // The tokenizer has already unescaped '' in both kinds of string literal.
+                Value::SingleQuotedString(s) | Value::NationalStringLiteral(s) => {
                     let f = Field::StringField(s.to_string());
                     Ok(PredExpr::Literal(f))
                 }
+                Value::Date(_) | Value::Timestamp(_) => Ok(PredExpr::Literal(Field::StringField(
+                    Self::datetime_expr(expr)?.unwrap().to_string(),
+                ))),
                 _ => Err(CrustyError::ValidationError(String::from(
                     "Unsupported literal in predicate",
                 ))),
             },
-            _ => Ok(PredExpr::Ident(self.expr_to_ident(expr)?)),
+            _ => match Self::datetime_expr(expr)? {
+                Some(dt) => Ok(PredExpr::Literal(Field::StringField(dt.to_string()))),
+                None => Ok(PredExpr::Ident(self.expr_to_ident(expr)?)),
+            },
+        }
+    }
+
+    /// Evaluates an expression of date and timestamp literals, intervals and DATE_TRUNC.
+    ///
+    /// Returns None if the expression is not of that form.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression to evaluate.
+    fn datetime_expr(expr: &Expr) -> Result<Option<DateTime>, CrustyError> {
+        match expr {
+            Expr::Value(Value::Date(s)) => DateTime::parse_date(s).map(Some),
+            Expr::Value(Value::Timestamp(s)) => DateTime::parse_timestamp(s).map(Some),
+            Expr::Nested(e) => Self::datetime_expr(e),
+            Expr::BinaryOp { left, op, right } => {
+                let (dt, interval) = match (op, Self::interval(left)?, Self::interval(right)?) {
+                    (BinaryOperator::Plus, Some(interval), None) => (right, interval),
+                    (BinaryOperator::Plus, None, Some(interval)) => (left, interval),
+                    (BinaryOperator::Minus, None, Some(interval)) => (left, interval.negate()),
+                    _ => return Ok(None),
+                };
+                Ok(Self::datetime_expr(dt)?.map(|dt| dt.add(interval)))
+            }
+            Expr::Function(Function { name, args, .. })
+                if get_name(name)?.eq_ignore_ascii_case("date_trunc") =>
+            {
+                match &args[..] {
+                    [Expr::Value(Value::SingleQuotedString(unit)), arg] => {
+                        match Self::datetime_expr(arg)? {
+                            Some(dt) => dt.trunc(unit).map(Some),
+                            None => Err(CrustyError::ValidationError(String::from(
+                                "date_trunc is only supported on date and timestamp literals",
+                            ))),
+                        }
+                    }
+                    _ => Err(CrustyError::ValidationError(String::from(
+                        "date_trunc takes a unit and a date or timestamp",
+                    ))),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The interval of an `INTERVAL '<value>' <unit>` literal, None for other expressions.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression to parse.
+    fn interval(expr: &Expr) -> Result<Option<Interval>, CrustyError> {
+        match expr {
+            Expr::Value(Value::Interval {
+                value,
+                leading_field,
+                last_field: None,
+                ..
+            }) => Interval::new(value, leading_field).map(Some),
+            Expr::Value(Value::Interval { .. }) => Err(CrustyError::ValidationError(String::from(
+                "Only intervals of a single unit are supported",
+            ))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Splits `col + INTERVAL ...` or `col - INTERVAL ...` into the column and the interval
+    /// added to it. Returns None for other expressions.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression to split.
+    fn shifted_column(expr: &Expr) -> Result<Option<(&Expr, Interval)>, CrustyError> {
+        let (col, interval) = match expr {
+            Expr::BinaryOp { left, op, right } => {
+                let is_column = matches!(
+                    left.as_ref(),
+                    Expr::Identifier(_) | Expr::CompoundIdentifier(_)
+                );
+                match (op, Self::interval(right)?) {
+                    (BinaryOperator::Plus, Some(i)) if is_column => (left.as_ref(), i),
+                    (BinaryOperator::Minus, Some(i)) if is_column => (left.as_ref(), i.negate()),
+                    _ => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        // Moving months to the other side is not exact, as months differ in length.
+        if interval.months != 0 {
+            return Err(CrustyError::ValidationError(String::from(
+                "Only intervals of days, hours, minutes or seconds can be added to a column",
+            )));
+        }
+        Ok(Some((col, interval)))
+    }
+
+    /// The date or timestamp a column shifted by an interval is compared to, shifted back.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression the shifted column is compared to.
+    /// * `interval` - Interval added to the column.
+    fn shifted_literal(expr: &Expr, interval: Interval) -> Result<PredExpr, CrustyError> {
+        match Self::datetime_expr(expr)? {
+            Some(dt) => Ok(PredExpr::Literal(Field::StringField(
+                dt.add(interval.negate()).to_string(),
+            ))),
+            None => Err(CrustyError::ValidationError(String::from(
+                "A column plus an interval can only be compared to a date or timestamp",
+            ))),
         }
     }
 
@@ -438,6 +1293,8 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
             BinaryOperator::LtEq => Ok(PredicateOp::LessThanOrEq),
             BinaryOperator::Eq => Ok(PredicateOp::Equals),
             BinaryOperator::NotEq => Ok(PredicateOp::NotEq),
+            BinaryOperator::Like => Ok(PredicateOp::Like),
+            BinaryOperator::NotLike => Ok(PredicateOp::NotLike),
             _ => Err(CrustyError::ValidationError(String::from(
                 "Unsupported binary operation",
             ))),
@@ -471,7 +1328,7 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
         match attr.dtype() {
             DataType::Int => Ok(()),
             DataType::String => match op {
-                AggOp::Count | AggOp::Max | AggOp::Min => Ok(()),
+                AggOp::Count | AggOp::CountDistinct | AggOp::Max | AggOp::Min => Ok(()),
                 _ => Err(CrustyError::ValidationError(format!(
                     "Cannot perform operation {} on field {}",
                     op, alias,
@@ -480,8 +1337,200 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
         }
     }
 
+    /// Converts an expression of the select list to a field identifier, computed by an
+    /// expression if it is not a column or an aggregate. The field is named after the
+    /// expression, as written.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression to convert.
+    fn select_expr_to_ident(&self, expr: &Expr) -> Result<FieldIdentifier, CrustyError> {
+        match expr {
+            Expr::BinaryOp { .. } | Expr::UnaryOp { .. } | Expr::Nested(_) | Expr::Value(_) => {
+                match self.scalar_expr(expr)?.0 {
+                    ScalarExpr::Column(id) => Ok(id),
+                    scalar => Ok(FieldIdentifier::new_expr(scalar, &expr.to_string())),
+                }
+            }
+            _ => self.expr_to_ident(expr),
+        }
+    }
+
+    /// Converts an expression to a scalar expression and the type of its values, None if it
+    /// only has NULL literals.
+    ///
+    /// Arithmetic takes INTs, and `+` also concatenates VARCHARs. A NULL literal takes the type
+    /// of the other operand.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Expression to convert.
+    fn scalar_expr(&self, expr: &Expr) -> Result<(ScalarExpr, Option<DataType>), CrustyError> {
+        match expr {
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) => {
+                let id = self.expr_to_ident(expr)?;
+                let dtype = self.column_type(&id);
+                Ok((ScalarExpr::Column(id), dtype))
+            }
+            Expr::Value(Value::Number(s)) => {
+                let i = s.parse::<i32>().map_err(|_| {
+                    CrustyError::ValidationError(format!("Unsupported literal {}", s))
+                })?;
+                Ok((ScalarExpr::Literal(Field::IntField(i)), Some(DataType::Int)))
+            }
+            Expr::Value(Value::SingleQuotedString(s))
+            | Expr::Value(Value::NationalStringLiteral(s)) => {
+                let literal = ScalarExpr::Literal(Field::StringField(s.to_string()));
+                Ok((literal, Some(DataType::String)))
+            }
+            Expr::Value(Value::Null) => Ok((ScalarExpr::Literal(Field::Null), None)),
+            Expr::Nested(e) => self.scalar_expr(e),
+            Expr::UnaryOp { op, expr: e } => {
+                let (scalar, dtype) = self.scalar_expr(e)?;
+                match (op, scalar) {
+                    (UnaryOperator::Not, _) => Err(CrustyError::ValidationError(String::from(
+                        "Unsupported operator NOT in expression",
+                    ))),
+                    _ if dtype == Some(DataType::String) => Err(CrustyError::ValidationError(
+                        format!("{} can't be applied to a VARCHAR", op),
+                    )),
+                    (UnaryOperator::Plus, scalar) => Ok((scalar, dtype)),
+                    (UnaryOperator::Minus, ScalarExpr::Literal(Field::IntField(i))) => {
+                        let literal = ScalarExpr::Literal(Field::IntField(-i));
+                        Ok((literal, dtype))
+                    }
+                    (UnaryOperator::Minus, scalar) => {
+                        let zero = ScalarExpr::Literal(Field::IntField(0));
+                        let negated = Self::binary(zero, ArithOp::Minus, scalar);
+                        Ok((negated, Some(DataType::Int)))
+                    }
+                }
+            }
+            Expr::BinaryOp { left, op, right } => {
+                let op = match op {
+                    BinaryOperator::Plus => ArithOp::Plus,
+                    BinaryOperator::Minus => ArithOp::Minus,
+                    BinaryOperator::Multiply => ArithOp::Multiply,
+                    BinaryOperator::Divide => ArithOp::Divide,
+                    BinaryOperator::Modulus => ArithOp::Modulus,
+                    _ => {
+                        return Err(CrustyError::ValidationError(format!(
+                            "Unsupported operator {} in expression",
+                            op
+                        )));
+                    }
+                };
+                let (left, left_type) = self.scalar_expr(left)?;
+                let (right, right_type) = self.scalar_expr(right)?;
+                let dtype = match (left_type, right_type) {
+                    (Some(DataType::Int), Some(DataType::Int)) => Some(DataType::Int),
+                    (Some(DataType::String), Some(DataType::String)) if op == ArithOp::Plus => {
+                        Some(DataType::String)
+                    }
+                    (Some(dtype), None) | (None, Some(dtype))
+                        if op == ArithOp::Plus || dtype == DataType::Int =>
+                    {
+                        Some(dtype)
+                    }
+                    (None, None) => None,
+                    (l, r) => {
+                        return Err(CrustyError::ValidationError(format!(
+                            "{} can't be applied to {} and {}",
+                            op,
+                            Self::type_name(l),
+                            Self::type_name(r)
+                        )));
+                    }
+                };
+                Ok((Self::binary(left, op, right), dtype))
+            }
+            Expr::Function(Function {
+                name,
+                args,
+                distinct,
+                ..
+            }) => {
+                let name = get_name(name)?;
+                if *distinct {
+                    return Err(CrustyError::ValidationError(format!(
+                        "DISTINCT is only supported in COUNT, not in {}",
+                        name
+                    )));
+                }
+                let function = match ScalarFunction::lookup(&name) {
+                    Some(function) => function,
+                    None if Self::agg_op(&name).is_some() => {
+                        return Err(CrustyError::ValidationError(String::from(
+                            "Aggregates in expressions are not supported",
+                        )));
+                    }
+                    None => {
+                        return Err(CrustyError::ValidationError(format!(
+                            "Unsupported SQL function {}",
+                            name
+                        )));
+                    }
+                };
+                if args.len() != 1 {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Wrong number of args in {} operation",
+                        function
+                    )));
+                }
+                let (arg, dtype) = self.scalar_expr(&args[0])?;
+                if dtype.is_some() && dtype != Some(function.arg_type()) {
+                    return Err(CrustyError::ValidationError(format!(
+                        "{} can't be applied to {}",
+                        function,
+                        Self::type_name(dtype)
+                    )));
+                }
+                let call = ScalarExpr::Function {
+                    function,
+                    arg: Box::new(arg),
+                };
+                Ok((call, Some(function.return_type())))
+            }
+            _ => Err(CrustyError::ValidationError(String::from(
+                "Unsupported expression",
+            ))),
+        }
+    }
+
+    /// Name of a type in SQL, for errors.
+    fn type_name(dtype: Option<DataType>) -> &'static str {
+        match dtype {
+            Some(DataType::String) => "VARCHAR",
+            _ => "INT",
+        }
+    }
+
+    /// Aggregate operation called by a name, None if it isn't one.
+    fn agg_op(name: &str) -> Option<AggOp> {
+        match &name.to_uppercase()[..] {
+            "AVG" => Some(AggOp::Avg),
+            "COUNT" => Some(AggOp::Count),
+            "MAX" => Some(AggOp::Max),
+            "MIN" => Some(AggOp::Min),
+            "SUM" => Some(AggOp::Sum),
+            _ => None,
+        }
+    }
+
+    /// Creates an operation on two scalar expressions.
+    fn binary(left: ScalarExpr, op: ArithOp, right: ScalarExpr) -> ScalarExpr {
+        ScalarExpr::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
     /// Converts a sqparser::ast::Expr to a LogicalOp::FieldIdent.
     ///
+    /// A call of a scalar function of the registry is computed by an expression, and named
+    /// after the call as written.
+    ///
     /// # Arguments
     ///
     /// * `expr` - Expression to be converted.
@@ -491,14 +1540,27 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
             Expr::CompoundIdentifier(names) => {
                 self.disambiguate_name(names.iter().map(|s| s.as_ref()).collect())
             }
-            Expr::Function(Function { name, args, .. }) => {
-                let op = match &get_name(name)?.to_uppercase()[..] {
-                    "AVG" => AggOp::Avg,
-                    "COUNT" => AggOp::Count,
-                    "MAX" => AggOp::Max,
-                    "MIN" => AggOp::Min,
-                    "SUM" => AggOp::Sum,
-                    _ => {
+            Expr::Function(Function {
+                name,
+                args,
+                distinct,
+                ..
+            }) => {
+                let function_name = get_name(name)?;
+                if ScalarFunction::lookup(&function_name).is_some() {
+                    let (call, _) = self.scalar_expr(expr)?;
+                    return Ok(FieldIdentifier::new_expr(call, &expr.to_string()));
+                }
+                let op = match (Self::agg_op(&function_name), distinct) {
+                    (Some(AggOp::Count), true) => AggOp::CountDistinct,
+                    (Some(_), true) => {
+                        return Err(CrustyError::ValidationError(format!(
+                            "DISTINCT is only supported in COUNT, not in {}",
+                            function_name
+                        )));
+                    }
+                    (Some(op), false) => op,
+                    (None, _) => {
                         return Err(CrustyError::ValidationError(String::from(
                             "Unsupported SQL function",
                         )));
@@ -532,3 +1594,375 @@ impl<'a, T: 'a + Catalog> TranslateAndValidate<'a, T> {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::database::Database;
+    use common::{Attribute, TableSchema};
+    use sqlparser::ast::Statement;
+    use sqlparser::dialect::GenericDialect;
+    use sqlparser::parser::Parser;
+    use std::sync::{Arc, RwLock};
+
+    fn test_db() -> Database {
+        let db = Database::new(String::from("test"));
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("a"), DataType::Int),
+            Attribute::new(String::from("b"), DataType::Int),
+            Attribute::new(String::from("c"), DataType::String),
+        ]);
+        let table = Table::new(String::from("t"), schema);
+        db.tables
+            .write()
+            .unwrap()
+            .insert(table.id, Arc::new(RwLock::new(table)));
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("a"), DataType::Int),
+            Attribute::new(String::from("d"), DataType::Int),
+        ]);
+        let table = Table::new(String::from("u"), schema);
+        db.tables
+            .write()
+            .unwrap()
+            .insert(table.id, Arc::new(RwLock::new(table)));
+        db
+    }
+
+    fn explain(sql: &str) -> Result<String, CrustyError> {
+        let statements = Parser::parse_sql(&GenericDialect {}, sql.to_string()).unwrap();
+        match &statements[0] {
+            Statement::Query(query) => {
+                Ok(TranslateAndValidate::from_sql(query, &test_db())?.explain(None))
+            }
+            _ => panic!("Not a query"),
+        }
+    }
+
+    #[test]
+    fn test_order_by() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project *\n  Sort [t.b as b desc, t.a]\n    Scan t\n",
+            explain("SELECT * FROM t ORDER BY b DESC, t.a ASC")?
+        );
+        // by alias, and by a column that is not selected
+        assert_eq!(
+            "Project [t.a as x]\n  Sort [t.a as x, t.c as c]\n    Scan t\n",
+            explain("SELECT a AS x FROM t ORDER BY x, c")?
+        );
+        assert_eq!(
+            "Project [sum_t.b]\n  Sort [sum_t.b desc]\n    Aggregate [sum(t.b) as sum_t.b]\n      Scan t\n",
+            explain("SELECT SUM(b) FROM t ORDER BY SUM(b) DESC")?
+        );
+        assert!(explain("SELECT SUM(b) FROM t ORDER BY a").is_err());
+        assert!(explain("SELECT a FROM t ORDER BY d").is_err());
+        assert!(explain("SELECT a FROM t ORDER BY a + 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_list() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project *\n  CrossProduct\n    Scan t\n    Scan u\n",
+            explain("SELECT * FROM t, u")?
+        );
+        // a where predicate between the tables makes a join, oriented like the from list
+        assert_eq!(
+            "Project [t.c as c, u.d as d]\n  Join t.b < u.d\n    Scan t\n    Scan u\n",
+            explain("SELECT c, d FROM t, u WHERE u.d > t.b")?
+        );
+        assert_eq!(
+            "Project *\n  Filter u.d = 1\n    CrossProduct\n      Scan t\n      Scan u\n",
+            explain("SELECT * FROM t, u WHERE d = 1")?
+        );
+        assert!(explain("SELECT a FROM t, u").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compound_predicates() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project *\n  Filter t.a > 1 AND t.b < 5\n    Scan t\n",
+            explain("SELECT * FROM t WHERE a > 1 AND b < 5")?
+        );
+        // nested groups of the same kind are flattened
+        assert_eq!(
+            "Project *\n  Filter t.a = 1 OR t.a = 2 OR t.c = 'x'\n    Scan t\n",
+            explain("SELECT * FROM t WHERE a = 1 OR (a = 2 OR c = 'x')")?
+        );
+        assert_eq!(
+            "Project *\n  Filter t.a > 1 AND (t.b < 5 OR t.b > 8)\n    Scan t\n",
+            explain("SELECT * FROM t WHERE a > 1 AND (b < 5 OR b > 8)")?
+        );
+        // a conjunct between the tables of the from list is used as their join predicate
+        assert_eq!(
+            "Project *\n  Filter t.b > 2 AND u.d < 3\n    Join t.a = u.a\n      Scan t\n      Scan u\n",
+            explain("SELECT * FROM t, u WHERE t.b > 2 AND t.a = u.a AND u.d < 3")?
+        );
+        // but not a disjunct, which filters the cross product
+        assert_eq!(
+            "Project *\n  Filter t.a = u.a OR u.d < 3\n    CrossProduct\n      Scan t\n      Scan u\n",
+            explain("SELECT * FROM t, u WHERE t.a = u.a OR u.d < 3")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_between_in_like() -> Result<(), CrustyError> {
+        let filter = |sql: &str| -> Result<String, CrustyError> {
+            Ok(explain(sql)?.lines().nth(1).unwrap().trim().to_string())
+        };
+        assert_eq!(
+            "Filter t.a >= 1 AND t.a <= 5",
+            filter("SELECT * FROM t WHERE a BETWEEN 1 AND 5")?
+        );
+        assert_eq!(
+            "Filter t.a < 1 OR t.a > 5",
+            filter("SELECT * FROM t WHERE a NOT BETWEEN 1 AND 5")?
+        );
+        assert_eq!(
+            "Filter t.b = 2 AND (t.a = 1 OR t.a = 3 OR t.a = 5)",
+            filter("SELECT * FROM t WHERE b = 2 AND a IN (1, 3, 5)")?
+        );
+        assert_eq!(
+            "Filter t.c <> 'x' AND t.c <> 'y'",
+            filter("SELECT * FROM t WHERE c NOT IN ('x', 'y')")?
+        );
+        assert_eq!("Filter t.a = 1", filter("SELECT * FROM t WHERE a IN (1)")?);
+        assert_eq!(
+            "Filter t.c LIKE 'ab%' OR t.c NOT LIKE '_x'",
+            filter("SELECT * FROM t WHERE c LIKE 'ab%' OR c NOT LIKE '_x'")?
+        );
+        // operands have to match the type of the column
+        assert!(explain("SELECT * FROM t WHERE a BETWEEN 1 AND 'z'").is_err());
+        assert!(explain("SELECT * FROM t WHERE c IN ('x', 2)").is_err());
+        assert!(explain("SELECT * FROM t WHERE a LIKE '1%'").is_err());
+        assert!(explain("SELECT * FROM t WHERE c LIKE 1").is_err());
+        assert!(explain("SELECT * FROM t WHERE 'abc' LIKE c").is_err());
+        assert!(explain("SELECT * FROM t WHERE a BETWEEN b AND 5").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_null() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project *\n  Filter t.a IS NULL OR t.c IS NOT NULL\n    Scan t\n",
+            explain("SELECT * FROM t WHERE a IS NULL OR c IS NOT NULL")?
+        );
+        // `= NULL` is never true, IS NULL is meant
+        assert!(explain("SELECT * FROM t WHERE a = NULL").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_predicates() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project *\n  Filter t.a < t.b\n    Scan t\n",
+            explain("SELECT * FROM t WHERE t.a < b")?
+        );
+        assert_eq!(
+            "Project *\n  Filter t.a > 1 AND t.a = t.b\n    Scan t\n",
+            explain("SELECT * FROM t WHERE a > 1 AND a = b")?
+        );
+        // columns of the same table never join
+        assert_eq!(
+            "Project *\n  Filter t.a = t.b\n    Join t.a = u.a\n      Scan t\n      Scan u\n",
+            explain("SELECT * FROM t, u WHERE t.a = t.b AND t.a = u.a")?
+        );
+        assert!(explain("SELECT * FROM t WHERE 1 = 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_outer_join() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project *\n  Left join t.a = u.d\n    Scan t\n    Scan u\n",
+            explain("SELECT * FROM t LEFT JOIN u ON t.a = u.d")?
+        );
+        assert_eq!(
+            "Project *\n  Right join t.a = u.d\n    Scan t\n    Scan u\n",
+            explain("SELECT * FROM t RIGHT OUTER JOIN u ON t.a = u.d")?
+        );
+        assert_eq!(
+            "Project *\n  Full join u.d < t.b\n    Scan t\n    Scan u\n",
+            explain("SELECT * FROM t FULL JOIN u ON u.d < t.b")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_distinct() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Distinct\n  Project [t.a as a, t.b as b]\n    Scan t\n",
+            explain("SELECT DISTINCT a, b FROM t")?
+        );
+        // the sort goes above the distinct, on the projected columns
+        assert_eq!(
+            "Limit 3\n  Sort [x desc, b]\n    Distinct\n      Project [t.a as x, t.b as b]\n        Scan t\n",
+            explain("SELECT DISTINCT a AS x, b FROM t ORDER BY x DESC, t.b LIMIT 3")?
+        );
+        assert_eq!(
+            "Sort [t.c]\n  Distinct\n    Project *\n      Scan t\n",
+            explain("SELECT DISTINCT * FROM t ORDER BY t.c")?
+        );
+        assert!(explain("SELECT DISTINCT a FROM t ORDER BY c").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_having() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project [b, count_t.a]\n  Filter count_t.a > 5\n    Aggregate [t.b as b, count(t.a) as count_t.a] group by [t.b as b]\n      Scan t\n",
+            explain("SELECT b, COUNT(a) FROM t GROUP BY b HAVING COUNT(a) > 5")?
+        );
+        // aggregates that are not selected are computed for the predicate only
+        assert_eq!(
+            "Project [b]\n  Filter 10 <= sum_t.a\n    Aggregate [t.b as b, sum(t.a) as sum_t.a] group by [t.b as b]\n      Scan t\n",
+            explain("SELECT b FROM t GROUP BY b HAVING 10 <= SUM(a)")?
+        );
+        assert_eq!(
+            "Project [b]\n  Filter b = 1\n    Aggregate [t.b as b] group by [t.b as b]\n      Scan t\n",
+            explain("SELECT b FROM t GROUP BY b HAVING b = 1")?
+        );
+        assert!(explain("SELECT b FROM t GROUP BY b HAVING a = 1").is_err());
+        assert!(explain("SELECT a FROM t HAVING COUNT(a) > 1").is_err());
+        assert_eq!(
+            "Project [b]\n  Filter count_t.a > sum_t.a\n    Aggregate [t.b as b, count(t.a) as count_t.a, sum(t.a) as sum_t.a] group by [t.b as b]\n      Scan t\n",
+            explain("SELECT b FROM t GROUP BY b HAVING COUNT(a) > SUM(a)")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_datetime_predicates() -> Result<(), CrustyError> {
+        let filter = |sql: &str| -> Result<String, CrustyError> {
+            Ok(explain(sql)?.lines().nth(1).unwrap().trim().to_string())
+        };
+        assert_eq!(
+            "Filter t.c < '2021-03-15'",
+            filter("SELECT * FROM t WHERE c < DATE '2021-03-08' + INTERVAL '7' DAY")?
+        );
+        assert_eq!(
+            "Filter t.c >= '2021-03-01 00:00:00'",
+            filter(
+                "SELECT * FROM t WHERE c >= DATE_TRUNC('month', TIMESTAMP '2021-03-08 10:00:00')"
+            )?
+        );
+        // the interval added to the column is subtracted from the other side
+        assert_eq!(
+            "Filter t.c > '2021-03-01 12:00:00'",
+            filter("SELECT * FROM t WHERE c + INTERVAL '12' HOUR > DATE '2021-03-02'")?
+        );
+        assert_eq!(
+            "Filter '2021-01-31' = t.c",
+            filter("SELECT * FROM t WHERE DATE '2021-02-28' - INTERVAL '28' DAY = t.c - INTERVAL '0' DAY")?
+        );
+        assert!(
+            filter("SELECT * FROM t WHERE c + INTERVAL '1' MONTH > DATE '2021-03-02'").is_err()
+        );
+        assert!(filter("SELECT * FROM t WHERE c + INTERVAL '1' DAY > 'x'").is_err());
+        assert!(filter("SELECT * FROM t WHERE c < DATE '2021-02-30'").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_limit() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Limit 10 offset 5\n  Project *\n    Sort [t.a as a]\n      Scan t\n",
+            explain("SELECT * FROM t ORDER BY a LIMIT 10 OFFSET 5 ROWS")?
+        );
+        assert_eq!(
+            "Limit all offset 5\n  Project *\n    Scan t\n",
+            explain("SELECT * FROM t LIMIT ALL OFFSET 5 ROWS")?
+        );
+        assert_eq!(
+            "Project *\n  Scan t\n",
+            explain("SELECT * FROM t OFFSET 0 ROWS")?
+        );
+        assert!(explain("SELECT * FROM t LIMIT 1.5").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_operations() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Union\n  Project [t.a as a]\n    Scan t\n  Project [u.d as d]\n    Scan u\n",
+            explain("SELECT a FROM t UNION SELECT d FROM u")?
+        );
+        // each query has its own tables, and the left one is translated first
+        assert_eq!(
+            "Except all\n  Intersect\n    Project [t.a as a]\n      Filter t.a > 1\n        Scan t\n    Project [u.a as a]\n      Scan u\n  Project [t.b as b]\n    Scan t\n",
+            explain("SELECT a FROM t WHERE a > 1 INTERSECT SELECT a FROM u EXCEPT ALL SELECT b FROM t")?
+        );
+        // order by and limit apply to the result, whose columns are named by the left query
+        assert_eq!(
+            "Limit 2\n  Sort [x desc]\n    Union all\n      Project [t.a as x]\n        Scan t\n      Project [u.d as d]\n        Scan u\n",
+            explain("SELECT a AS x FROM t UNION ALL SELECT d FROM u ORDER BY x DESC LIMIT 2")?
+        );
+        assert_eq!(
+            "Sort [t.c]\n  Union\n    Project *\n      Scan t\n    Project *\n      Scan t\n",
+            explain("SELECT * FROM t UNION SELECT * FROM t ORDER BY c")?
+        );
+        assert!(explain("SELECT a FROM t UNION SELECT d FROM u ORDER BY d").is_err());
+        assert!(explain("SELECT a FROM t UNION SELECT x FROM u").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expressions() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project [t.a + 1 as a + 1, t.a * t.b as x]\n  Scan t\n",
+            explain("SELECT a + 1, a * b AS x FROM t")?
+        );
+        // nested operations are parenthesized, and strings are concatenated with +
+        assert_eq!(
+            "Project [(t.a - 1) * -2 as (a - 1) * - 2, t.c + '!' as y, 0 - t.b as - b]\n  Scan t\n",
+            explain("SELECT (a - 1) * -2, c + '!' AS y, -b FROM t")?
+        );
+        // a computed field is sorted on above the projection
+        assert_eq!(
+            "Sort [x desc]\n  Project [t.a % 3 as x, t.b as b]\n    Scan t\n",
+            explain("SELECT a % 3 AS x, b FROM t ORDER BY x DESC")?
+        );
+        assert!(explain("SELECT a % 3 AS x FROM t ORDER BY x, b").is_err());
+        // operand types
+        assert!(explain("SELECT a + c FROM t").is_err());
+        assert!(explain("SELECT c * 2 FROM t").is_err());
+        assert!(explain("SELECT -c FROM t").is_err());
+        assert!(explain("SELECT SUM(a) + 1 FROM t").is_err());
+        assert_eq!(
+            "Project [NULL + t.c as NULL + c]\n  Scan t\n",
+            explain("SELECT NULL + c FROM t")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_functions() -> Result<(), CrustyError> {
+        assert_eq!(
+            "Project [UPPER(t.c) as UPPER(c), LENGTH(t.c) as n, ABS(t.a - t.b) as ABS(a - b)]\n  Filter LOWER(t.c) = 'x' AND ABS(t.a) > 2\n    Scan t\n",
+            explain("SELECT UPPER(c), length(c) AS n, ABS(a - b) FROM t WHERE LOWER(c) = 'x' AND ABS(a) > 2")?
+        );
+        // a function of columns of both tables isn't a join predicate
+        assert_eq!(
+            "Project [t.b as b]\n  Filter ABS(t.a - u.d) < 2\n    CrossProduct\n      Scan t\n      Scan u\n",
+            explain("SELECT b FROM t, u WHERE ABS(t.a - u.d) < 2")?
+        );
+        assert_eq!(
+            "Project [t.a as a]\n  Filter LENGTH(t.c) >= 1 AND LENGTH(t.c) <= 3\n    Scan t\n",
+            explain("SELECT a FROM t WHERE LENGTH(c) BETWEEN 1 AND 3")?
+        );
+        assert!(explain("SELECT a FROM t WHERE LENGTH(c) IN ('a', 'b')").is_err());
+        // argument types and counts
+        assert!(explain("SELECT UPPER(a) FROM t").is_err());
+        assert!(explain("SELECT ABS(c) FROM t").is_err());
+        assert!(explain("SELECT ABS(a, b) FROM t").is_err());
+        assert!(explain("SELECT FOO(a) FROM t").is_err());
+        // only selected calls can be sorted on
+        assert_eq!(
+            "Sort [x]\n  Project [LOWER(t.c) as x]\n    Scan t\n",
+            explain("SELECT LOWER(c) AS x FROM t ORDER BY x")?
+        );
+        assert!(explain("SELECT a FROM t ORDER BY UPPER(c)").is_err());
+        Ok(())
+    }
+}
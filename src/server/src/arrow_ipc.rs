@@ -0,0 +1,224 @@
+use arrow_array::builder::{Int32Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType as ArrowType, Field as ArrowField, Schema};
+use common::{CrustyError, DataType, Field, QueryResult, TableSchema, Tuple};
+use queryexe::query::Executor;
+use std::sync::Arc;
+
+/// Rows encoded in one record batch of the stream.
+const BATCH_ROWS: usize = 8192;
+
+/// Builder of the values of one column of a record batch.
+enum Column {
+    Int(Int32Builder),
+    String(StringBuilder),
+}
+
+/// Encodes rows as an Apache Arrow IPC stream: the schema, then record batches of up to
+/// `BATCH_ROWS` rows.
+///
+/// `INT` columns become `Int32` and `VARCHAR` columns `Utf8`, NULL is an Arrow null.
+pub struct ArrowEncoder {
+    schema: Arc<Schema>,
+    writer: StreamWriter<Vec<u8>>,
+    columns: Vec<Column>,
+    /// Rows in the columns that are not written yet.
+    pending: usize,
+    /// Rows encoded so far.
+    rows: usize,
+}
+
+impl ArrowEncoder {
+    /// Starts a stream of rows of the given schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the rows.
+    pub fn new(schema: &TableSchema) -> Result<Self, CrustyError> {
+        let fields: Vec<ArrowField> = schema
+            .attributes()
+            .map(|a| {
+                let dtype = match a.dtype() {
+                    DataType::Int => ArrowType::Int32,
+                    DataType::String => ArrowType::Utf8,
+                };
+                ArrowField::new(a.name(), dtype, a.nullable())
+            })
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+        let writer = StreamWriter::try_new(Vec::new(), &schema).map_err(arrow_error)?;
+        let mut encoder = Self {
+            schema,
+            writer,
+            columns: Vec::new(),
+            pending: 0,
+            rows: 0,
+        };
+        encoder.columns = encoder.new_columns();
+        Ok(encoder)
+    }
+
+    fn new_columns(&self) -> Vec<Column> {
+        self.schema
+            .fields()
+            .iter()
+            .map(|f| match f.data_type() {
+                ArrowType::Int32 => Column::Int(Int32Builder::with_capacity(BATCH_ROWS)),
+                _ => Column::String(StringBuilder::new()),
+            })
+            .collect()
+    }
+
+    /// Adds a row to the stream. The stream can't be finished after a row fails to be added.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Row to add, of the schema the stream was started with.
+    pub fn push(&mut self, tuple: &Tuple) -> Result<(), CrustyError> {
+        if tuple.size() != self.columns.len() {
+            return Err(CrustyError::ExecutionError(format!(
+                "Row of {} values in a result of {} columns",
+                tuple.size(),
+                self.columns.len()
+            )));
+        }
+        for (column, field) in self.columns.iter_mut().zip(tuple.field_vals()) {
+            match (column, field) {
+                (Column::Int(b), Field::IntField(i)) => b.append_value(*i),
+                (Column::String(b), Field::StringField(s)) => b.append_value(s),
+                (Column::Int(b), Field::Null) => b.append_null(),
+                (Column::String(b), Field::Null) => b.append_null(),
+                (_, field) => {
+                    return Err(CrustyError::ExecutionError(format!(
+                        "Value {} does not match the type of its result column",
+                        field
+                    )))
+                }
+            }
+        }
+        self.pending += 1;
+        self.rows += 1;
+        if self.pending == BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pending rows as a record batch.
+    fn write_batch(&mut self) -> Result<(), CrustyError> {
+        let arrays: Vec<ArrayRef> = self
+            .columns
+            .iter_mut()
+            .map(|c| match c {
+                Column::Int(b) => Arc::new(b.finish()) as ArrayRef,
+                Column::String(b) => Arc::new(b.finish()) as ArrayRef,
+            })
+            .collect();
+        let batch = RecordBatch::try_new(Arc::clone(&self.schema), arrays).map_err(arrow_error)?;
+        self.writer.write(&batch).map_err(arrow_error)?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Ends the stream and returns its bytes and the number of rows in it.
+    pub fn finish(mut self) -> Result<(Vec<u8>, usize), CrustyError> {
+        if self.pending > 0 {
+            self.write_batch()?;
+        }
+        let bytes = self.writer.into_inner().map_err(arrow_error)?;
+        Ok((bytes, self.rows))
+    }
+}
+
+fn arrow_error(e: ArrowError) -> CrustyError {
+    CrustyError::ExecutionError(format!("Arrow IPC encoding failed: {}", e))
+}
+
+/// Runs the executor's query and returns its rows as an Arrow IPC stream, summarized by the
+/// number of rows.
+///
+/// # Arguments
+///
+/// * `executor` - Executor configured with the query to run.
+pub fn execute(executor: &mut Executor) -> Result<QueryResult, CrustyError> {
+    let mut encoder = ArrowEncoder::new(executor.plan.as_ref().unwrap().get_schema())?;
+    executor.start()?;
+    while let Some(t) = executor.next()? {
+        if let Err(e) = encoder.push(&t) {
+            executor.abort();
+            return Err(e);
+        }
+    }
+    executor.close()?;
+    let (bytes, rows) = encoder.finish()?;
+    let mut res = QueryResult::with_payload(&format!("{} rows", rows), bytes);
+    res.metadata_mut().rows_returned = Some(rows as u64);
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Int32Type;
+    use arrow_array::Array;
+    use arrow_ipc::reader::StreamReader;
+    use common::Attribute;
+
+    #[test]
+    fn test_encode_round_trip() {
+        let schema = TableSchema::new(vec![
+            Attribute::new_not_null(String::from("t.a"), DataType::Int),
+            Attribute::new(String::from("t.b"), DataType::String),
+        ]);
+        let mut encoder = ArrowEncoder::new(&schema).unwrap();
+        let rows = BATCH_ROWS + 3;
+        for i in 0..rows {
+            let b = match i % 3 {
+                2 => Field::Null,
+                _ => Field::StringField(format!("s{}", i)),
+            };
+            encoder
+                .push(&Tuple::new(vec![Field::IntField(i as i32), b]))
+                .unwrap();
+        }
+        let (bytes, encoded) = encoder.finish().unwrap();
+        assert_eq!(rows, encoded);
+
+        let reader = StreamReader::try_new(bytes.as_slice(), None).unwrap();
+        let read_schema = reader.schema();
+        assert_eq!("t.a", read_schema.field(0).name());
+        assert!(!read_schema.field(0).is_nullable());
+        assert_eq!(&ArrowType::Utf8, read_schema.field(1).data_type());
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(2, batches.len());
+        assert_eq!(rows, batches.iter().map(|b| b.num_rows()).sum::<usize>());
+        let last = &batches[1];
+        let a = last.column(0).as_primitive::<Int32Type>();
+        let b = last.column(1).as_string::<i32>();
+        assert_eq!(BATCH_ROWS as i32, a.value(0));
+        for j in 0..3 {
+            let i = BATCH_ROWS + j;
+            assert_eq!(i % 3 == 2, b.is_null(j));
+            if !b.is_null(j) {
+                assert_eq!(format!("s{}", i), b.value(j));
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_empty() {
+        let schema = TableSchema::new(vec![Attribute::new(String::from("a"), DataType::Int)]);
+        let (bytes, rows) = ArrowEncoder::new(&schema).unwrap().finish().unwrap();
+        assert_eq!(0, rows);
+        let reader = StreamReader::try_new(bytes.as_slice(), None).unwrap();
+        assert_eq!(1, reader.schema().fields().len());
+        assert_eq!(0, reader.count());
+
+        let mut encoder = ArrowEncoder::new(&schema).unwrap();
+        let string = Field::StringField(String::from("x"));
+        assert!(encoder.push(&Tuple::new(vec![string])).is_err());
+        assert!(encoder.push(&Tuple::new(Vec::new())).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use common::logical_plan::LogicalPlan;
+use common::CrustyError;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// What a cache holds and how well it is doing, as shown by `\cache stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub name: String,
+    pub entries: usize,
+    /// Bytes of the cached values, as estimated by whoever inserted them.
+    pub bytes: usize,
+    /// Bytes the cache may hold before it evicts its least recently used values.
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Values evicted to make room, not counting clears or replaced values.
+    pub evictions: u64,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} entries, {} of {} bytes, {} hits, {} misses, {} evictions",
+            self.name,
+            self.entries,
+            self.bytes,
+            self.capacity,
+            self.hits,
+            self.misses,
+            self.evictions
+        )
+    }
+}
+
+/// A cache the server keeps for all clients, which `\cache` reports on and clears.
+pub trait ManagedCache: Send + Sync {
+    /// Returns what the cache holds.
+    fn stats(&self) -> CacheStats;
+
+    /// Drops every value of the cache. Hit and miss counts are kept.
+    fn clear(&self);
+}
+
+struct Entry<V> {
+    value: V,
+    size: usize,
+    /// When the value was last inserted or read, the key of the entry in `LruState::recency`.
+    last_used: u64,
+}
+
+struct LruState<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Keys by when they were last used, the least recently used first.
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+/// A cache bounded by the bytes of its values, that evicts the least recently used values first.
+///
+/// Values are cloned out of the cache, so large values should be shared, e.g. behind an `Arc`.
+/// The cache does not know how big values are, callers give an estimate when inserting.
+pub struct LruCache<K, V> {
+    state: Mutex<LruState<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    /// Creates an empty cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name the cache is reported under.
+    /// * `capacity` - Bytes of values the cache may hold.
+    pub fn new(name: &str, capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(LruState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+                stats: CacheStats {
+                    name: name.to_string(),
+                    capacity,
+                    ..CacheStats::default()
+                },
+            }),
+        }
+    }
+
+    /// Returns the value of a key, if it is cached, and marks it as the most recently used.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key to look up.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.clock += 1;
+        match state.entries.get_mut(key) {
+            Some(entry) => {
+                state.recency.remove(&entry.last_used);
+                entry.last_used = state.clock;
+                state.recency.insert(state.clock, key.clone());
+                state.stats.hits += 1;
+                Some(entry.value.clone())
+            }
+            None => {
+                state.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Caches a value, replacing any value of the key, and evicts the least recently used values
+    /// until the cache is within its capacity. Returns false if the value is larger than the
+    /// whole cache, in which case it is not cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key of the value.
+    /// * `value` - Value to cache.
+    /// * `size` - Estimate of the bytes the value takes.
+    pub fn insert(&self, key: K, value: V, size: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        Self::remove_entry(&mut state, &key);
+        if size > state.stats.capacity {
+            return false;
+        }
+        while state.stats.bytes + size > state.stats.capacity {
+            Self::evict_one(&mut state);
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.recency.insert(last_used, key.clone());
+        state.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                last_used,
+            },
+        );
+        state.stats.bytes += size;
+        state.stats.entries += 1;
+        true
+    }
+
+    /// Drops the value of a key, if it is cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key of the value to drop.
+    // Not used by the plan cache, whose outdated plans are left to be evicted
+    #[allow(dead_code)]
+    pub fn remove(&self, key: &K) {
+        Self::remove_entry(&mut self.state.lock().unwrap(), key);
+    }
+
+    /// Changes the capacity of the cache, evicting values if it shrinks below what is cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Bytes of values the cache may hold.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.stats.capacity = capacity;
+        while state.stats.bytes > capacity {
+            Self::evict_one(&mut state);
+        }
+    }
+
+    fn remove_entry(state: &mut LruState<K, V>, key: &K) {
+        if let Some(entry) = state.entries.remove(key) {
+            state.recency.remove(&entry.last_used);
+            state.stats.bytes -= entry.size;
+            state.stats.entries -= 1;
+        }
+    }
+
+    fn evict_one(state: &mut LruState<K, V>) {
+        let oldest = state.recency.keys().next().copied();
+        if let Some(key) = oldest.and_then(|t| state.recency.remove(&t)) {
+            let entry = state.entries.remove(&key).unwrap();
+            state.stats.bytes -= entry.size;
+            state.stats.entries -= 1;
+            state.stats.evictions += 1;
+        }
+    }
+}
+
+impl<K, V> ManagedCache for LruCache<K, V>
+where
+    K: Hash + Eq + Clone + Send,
+    V: Clone + Send,
+{
+    fn stats(&self) -> CacheStats {
+        self.state.lock().unwrap().stats.clone()
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.recency.clear();
+        state.stats.bytes = 0;
+        state.stats.entries = 0;
+    }
+}
+
+/// What a query's plan is cached under. A plan is only reused for the same query text against
+/// the same version of the same database's catalog, so it is never used after the tables,
+/// indexes or statistics it was made with change.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PlanKey {
+    /// Id of the database.
+    pub database: u64,
+    /// Version of the database's catalog the plan was made with.
+    pub catalog_version: u64,
+    /// Query as printed by the parser, so it doesn't matter how it was spaced or how the
+    /// keywords were cased.
+    pub sql: String,
+}
+
+/// Validated and optimized logical plans of queries, shared by all clients.
+pub type PlanCache = LruCache<PlanKey, Arc<LogicalPlan>>;
+
+/// Name the plan cache is reported and cleared under.
+pub const PLAN_CACHE_NAME: &str = "plans";
+
+/// Bytes of plans cached unless the server is told otherwise.
+pub const DEFAULT_PLAN_CACHE_BYTES: usize = 4 << 20;
+
+/// The caches of the server, so they can be inspected and cleared together.
+#[derive(Default)]
+pub struct CacheRegistry {
+    caches: RwLock<Vec<Arc<dyn ManagedCache>>>,
+}
+
+impl CacheRegistry {
+    /// Adds a cache to the ones reported and cleared by `\cache`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - Cache to add.
+    pub fn register(&self, cache: Arc<dyn ManagedCache>) {
+        self.caches.write().unwrap().push(cache);
+    }
+
+    /// Describes every cache, one per line.
+    pub fn stats(&self) -> String {
+        let caches = self.caches.read().unwrap();
+        if caches.is_empty() {
+            return String::from("No caches");
+        }
+        caches
+            .iter()
+            .map(|c| c.stats().to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Clears one cache, or every cache if no name is given.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the cache to clear.
+    pub fn clear(&self, name: Option<&str>) -> Result<String, CrustyError> {
+        let caches = self.caches.read().unwrap();
+        let mut cleared = 0;
+        for cache in caches.iter() {
+            if name.is_none() || name == Some(cache.stats().name.as_str()) {
+                cache.clear();
+                cleared += 1;
+            }
+        }
+        match name {
+            Some(n) if cleared == 0 => {
+                Err(CrustyError::ValidationError(format!("Unknown cache {}", n)))
+            }
+            Some(n) => Ok(format!("Cleared cache {}", n)),
+            None => Ok(format!("Cleared {} caches", cleared)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache: LruCache<u32, String> = LruCache::new("test", 10);
+        assert!(cache.insert(1, String::from("a"), 4));
+        assert!(cache.insert(2, String::from("b"), 4));
+        // reading 1 makes 2 the least recently used
+        assert_eq!(Some(String::from("a")), cache.get(&1));
+        assert!(cache.insert(3, String::from("c"), 4));
+        assert_eq!(None, cache.get(&2));
+        assert_eq!(Some(String::from("c")), cache.get(&3));
+        // replacing a value frees its bytes first
+        assert!(cache.insert(3, String::from("cc"), 6));
+        assert_eq!(Some(String::from("a")), cache.get(&1));
+        // too large to ever fit, and the old value is gone
+        assert!(!cache.insert(1, String::from("huge"), 11));
+        assert_eq!(None, cache.get(&1));
+        assert_eq!(
+            CacheStats {
+                name: String::from("test"),
+                entries: 1,
+                bytes: 6,
+                capacity: 10,
+                hits: 3,
+                misses: 2,
+                evictions: 1,
+            },
+            cache.stats()
+        );
+
+        cache.set_capacity(5);
+        assert_eq!(0, cache.stats().bytes);
+        assert!(cache.insert(4, String::from("d"), 5));
+        cache.remove(&4);
+        assert_eq!(0, cache.stats().entries);
+    }
+
+    #[test]
+    fn test_registry() -> Result<(), CrustyError> {
+        let registry = CacheRegistry::default();
+        assert_eq!("No caches", registry.stats());
+        let a = Arc::new(LruCache::new("a", 100));
+        let b = Arc::new(LruCache::new("b", 100));
+        registry.register(a.clone());
+        registry.register(b.clone());
+        a.insert("x", 1, 10);
+        b.insert("y", 2, 20);
+        assert_eq!(
+            "a: 1 entries, 10 of 100 bytes, 0 hits, 0 misses, 0 evictions\n\
+             b: 1 entries, 20 of 100 bytes, 0 hits, 0 misses, 0 evictions",
+            registry.stats()
+        );
+        assert_eq!("Cleared cache a", registry.clear(Some("a"))?);
+        assert_eq!((0, 1), (a.stats().entries, b.stats().entries));
+        assert!(registry.clear(Some("c")).is_err());
+        assert_eq!("Cleared 2 caches", registry.clear(None)?);
+        assert_eq!(None, b.get(&"y"));
+        Ok(())
+    }
+}
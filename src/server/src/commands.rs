@@ -7,12 +7,50 @@ pub enum Commands {
     Connect(String),
     /// Import a database.
     Import(String),
+    /// Import a JSON array of objects into a table: path and table name.
+    ImportJson(String),
+    /// Import an Apache Parquet file into a table: path and table name.
+    ImportParquet(String),
+    /// Report what an import would load without loading it: path and table name.
+    AnalyzeImport(String),
     /// Show the tables of a database.
     ShowTables,
     /// List databases
     ShowDatabases,
+    /// List the sessions of the connected clients.
+    Sessions,
+    /// Stop the statement a session is running: id of the session.
+    Cancel(u64),
     /// Resets the database.
     Reset,
+    /// Print the catalog of every database as JSON.
+    CatalogDump,
+    /// Load databases from a JSON catalog file.
+    CatalogLoad(String),
+    /// Store the result of a query in a session variable: name and query.
+    Set(String, String),
+    /// List the session variables.
+    ShowVariables,
+    /// Show the space used by the storage of the connected database.
+    Stats,
+    /// Rewrite the rows of a table in the current row format.
+    Rewrite(String),
+    /// Show the space used by every database and table.
+    DiskUsage,
+    /// Identify the client as a user, whose role decides what the client may run.
+    Login(String),
+    /// Assign a role to a user: user and role name.
+    Grant(String, String),
+    /// Stream rows into a table, which becomes append-only.
+    Ingest(String),
+    /// Show the entries, size and hit rate of the server's caches.
+    CacheStats,
+    /// Empty one of the server's caches, or all of them.
+    CacheClear(Option<String>),
+    /// Read the first pages of a table into the buffer pool: table name and most pages to read.
+    Warm(String, Option<usize>),
+    /// List the recent statements of the slow query log.
+    SlowLog,
 }
 
 /// Parses the command to determine which type of command it is.
@@ -36,6 +74,15 @@ pub fn parse_command(mut cmd: String) -> Option<Commands> {
     } else if cmd.starts_with("\\c ") {
         // usage: \c <name>
         return Some(Commands::Connect(cmd[3..].to_string()));
+    } else if cmd.starts_with("\\i --analyze ") {
+        // usage: \i --analyze <path> <table_name>
+        return Some(Commands::AnalyzeImport(cmd[13..].trim().to_string()));
+    } else if cmd.starts_with("\\ij ") {
+        // usage: \ij <path> <table_name>
+        return Some(Commands::ImportJson(cmd[4..].to_string()));
+    } else if cmd.starts_with("\\ip ") {
+        // usage: \ip <path> <table_name>
+        return Some(Commands::ImportParquet(cmd[4..].to_string()));
     } else if cmd.starts_with("\\i ") {
         // usage: \i <path> <table_name>
         return Some(Commands::Import(cmd[3..].to_string()));
@@ -49,8 +96,94 @@ pub fn parse_command(mut cmd: String) -> Option<Commands> {
     } else if cmd == "\\l" {
         // usage: \l
         return Some(Commands::ShowDatabases);
+    } else if cmd == "\\catalog dump" {
+        // usage: \catalog dump
+        return Some(Commands::CatalogDump);
+    } else if cmd.starts_with("\\catalog load ") {
+        // usage: \catalog load <path>
+        return Some(Commands::CatalogLoad(cmd[14..].to_string()));
+    } else if cmd == "\\set" {
+        // usage: \set
+        return Some(Commands::ShowVariables);
+    } else if cmd.starts_with("\\set ") {
+        // usage: \set <name> = <query>
+        let mut parts = cmd[5..].splitn(2, '=');
+        let name = parts.next()?.trim();
+        let query = parts.next()?.trim();
+        if name.is_empty() || query.is_empty() {
+            return None;
+        }
+        return Some(Commands::Set(name.to_string(), query.to_string()));
+    } else if cmd == "\\stats" {
+        // usage: \stats
+        return Some(Commands::Stats);
+    } else if cmd.starts_with("\\rewrite ") {
+        // usage: \rewrite <table_name>
+        let table = cmd[9..].trim();
+        if table.is_empty() {
+            return None;
+        }
+        return Some(Commands::Rewrite(table.to_string()));
+    } else if cmd == "\\du" {
+        // usage: \du
+        return Some(Commands::DiskUsage);
+    } else if cmd.starts_with("\\login ") {
+        // usage: \login <user>
+        let user = cmd[7..].trim();
+        if user.is_empty() {
+            return None;
+        }
+        return Some(Commands::Login(user.to_string()));
+    } else if cmd.starts_with("\\grant ") {
+        // usage: \grant <user> <role>
+        let mut parts = cmd[7..].split_whitespace();
+        let user = parts.next()?;
+        let role = parts.next()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        return Some(Commands::Grant(user.to_string(), role.to_string()));
+    } else if cmd.starts_with("\\ingest ") {
+        // usage: \ingest <table_name>
+        let table = cmd[8..].trim();
+        if table.is_empty() {
+            return None;
+        }
+        return Some(Commands::Ingest(table.to_string()));
+    } else if cmd == "\\cache stats" {
+        // usage: \cache stats
+        return Some(Commands::CacheStats);
+    } else if cmd == "\\cache clear" || cmd.starts_with("\\cache clear ") {
+        // usage: \cache clear [cache_name]
+        let name = cmd[12..].trim();
+        return Some(Commands::CacheClear(if name.is_empty() {
+            None
+        } else {
+            Some(name.to_string())
+        }));
+    } else if cmd.starts_with("\\warm ") {
+        // usage: \warm <table_name> [pages]
+        let mut parts = cmd[6..].split_whitespace();
+        let table = parts.next()?;
+        let pages = match parts.next() {
+            Some(pages) => Some(pages.parse().ok()?),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        return Some(Commands::Warm(table.to_string(), pages));
+    } else if cmd == "\\slowlog" {
+        // usage: \slowlog
+        return Some(Commands::SlowLog);
+    } else if cmd == "\\sessions" {
+        // usage: \sessions
+        return Some(Commands::Sessions);
+    } else if cmd.starts_with("\\cancel ") {
+        // usage: \cancel <session_id>
+        return cmd[8..].trim().parse().ok().map(Commands::Cancel);
     } else if cmd == "\\reset" {
-        // usage: \l
+        // usage: \reset
         return Some(Commands::Reset);
     }
     None
@@ -85,11 +218,23 @@ mod test {
             Commands::Import("path name".to_string()),
             parse_command(import).unwrap()
         );
+        assert_eq!(
+            Commands::AnalyzeImport("path name".to_string()),
+            parse_command(String::from("\\i --analyze path name\n")).unwrap()
+        );
+        assert_eq!(
+            Commands::ImportJson("path name".to_string()),
+            parse_command(String::from("\\ij path name")).unwrap()
+        );
+        assert_eq!(
+            Commands::ImportParquet("path name".to_string()),
+            parse_command(String::from("\\ip path name")).unwrap()
+        );
     }
 
     #[test]
     fn test_reset() {
-        let reset: String = String::from("\\d\n");
+        let reset: String = String::from("\\reset\n");
         assert_eq!(Commands::Reset, parse_command(reset).unwrap());
     }
 
@@ -98,4 +243,132 @@ mod test {
         let show_tables: String = String::from("\\dt\n");
         assert_eq!(Commands::ShowTables, parse_command(show_tables).unwrap());
     }
+
+    #[test]
+    fn test_catalog() {
+        let dump: String = String::from("\\catalog dump\n");
+        assert_eq!(Commands::CatalogDump, parse_command(dump).unwrap());
+        let load: String = String::from("\\catalog load cat.json");
+        assert_eq!(
+            Commands::CatalogLoad("cat.json".to_string()),
+            parse_command(load).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set() {
+        let set: String = String::from("\\set maxid = SELECT MAX(t.id) FROM t\n");
+        assert_eq!(
+            Commands::Set("maxid".to_string(), "SELECT MAX(t.id) FROM t".to_string()),
+            parse_command(set).unwrap()
+        );
+        assert_eq!(
+            Commands::ShowVariables,
+            parse_command(String::from("\\set\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\set maxid")));
+        assert_eq!(None, parse_command(String::from("\\set maxid = ")));
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(
+            Commands::Stats,
+            parse_command(String::from("\\stats\n")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rewrite() {
+        assert_eq!(
+            Commands::Rewrite("t".to_string()),
+            parse_command(String::from("\\rewrite t\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\rewrite ")));
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        assert_eq!(
+            Commands::DiskUsage,
+            parse_command(String::from("\\du\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\dus")));
+    }
+
+    #[test]
+    fn test_login_and_grant() {
+        assert_eq!(
+            Commands::Login("ann".to_string()),
+            parse_command(String::from("\\login ann\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\login ")));
+        assert_eq!(
+            Commands::Grant("ann".to_string(), "readonly".to_string()),
+            parse_command(String::from("\\grant ann readonly\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\grant ann")));
+        assert_eq!(None, parse_command(String::from("\\grant ann readonly x")));
+    }
+
+    #[test]
+    fn test_ingest() {
+        assert_eq!(
+            Commands::Ingest("readings".to_string()),
+            parse_command(String::from("\\ingest readings\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\ingest ")));
+    }
+
+    #[test]
+    fn test_cache() {
+        assert_eq!(
+            Commands::CacheStats,
+            parse_command(String::from("\\cache stats\n")).unwrap()
+        );
+        assert_eq!(
+            Commands::CacheClear(None),
+            parse_command(String::from("\\cache clear\n")).unwrap()
+        );
+        assert_eq!(
+            Commands::CacheClear(Some("plans".to_string())),
+            parse_command(String::from("\\cache clear plans\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\cache")));
+        assert_eq!(None, parse_command(String::from("\\cache clearall")));
+    }
+
+    #[test]
+    fn test_warm() {
+        assert_eq!(
+            Commands::Warm("t".to_string(), None),
+            parse_command(String::from("\\warm t\n")).unwrap()
+        );
+        assert_eq!(
+            Commands::Warm("t".to_string(), Some(100)),
+            parse_command(String::from("\\warm t 100")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\warm ")));
+        assert_eq!(None, parse_command(String::from("\\warm t lots")));
+        assert_eq!(None, parse_command(String::from("\\warm t 1 2")));
+    }
+
+    #[test]
+    fn test_slowlog() {
+        assert_eq!(
+            Commands::SlowLog,
+            parse_command(String::from("\\slowlog\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\slowlog 10")));
+    }
+
+    #[test]
+    fn test_cancel() {
+        assert_eq!(
+            Commands::Cancel(3),
+            parse_command(String::from("\\cancel 3\n")).unwrap()
+        );
+        assert_eq!(None, parse_command(String::from("\\cancel ")));
+        assert_eq!(None, parse_command(String::from("\\cancel all")));
+    }
 }
@@ -1,18 +1,63 @@
+use crate::cache::{PlanCache, PlanKey, DEFAULT_PLAN_CACHE_BYTES, PLAN_CACHE_NAME};
 use crate::commands;
+use crate::csv_utils::ImportFormat;
 use crate::database_state::DatabaseState;
+use crate::ingest::IngestSession;
+use crate::retry::RetryPolicy;
+use crate::roles::{Role, RoleStore};
+use crate::scheduler::Scheduler;
 use crate::server_state::ServerState;
-use crate::sql_parser::SQLParser;
-use common::{get_name, CrustyError, QueryResult};
+use crate::session::{OutputFormat, SessionSettings, SessionVariables};
+use crate::slow_log::{SlowLog, SlowQuery};
+use crate::sql_parser::{AlterColumn, IndexStatement, SQLParser};
+use common::catalog::CatalogSnapshot;
+use common::ids::TransactionId;
+use common::logical_plan::LogicalPlan;
+use common::storage_trait::StorageTrait;
+use common::table::Table;
+use common::{get_name, CrustyError, Field, QueryResult};
 use optimizer::optimizer::Optimizer;
-use queryexe::query::{Executor, TranslateAndValidate};
-use sqlparser::ast::Statement;
+use queryexe::opiterator::{CancelToken, PlanProfile, QueryDeadline};
+use queryexe::query::{Executor, ResultChecksum, RowSink, TranslateAndValidate};
+use sqlparser::ast::{ObjectType, SetExpr, SetVariableValue, Statement};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use txn_manager::transactions::Transaction;
 
+/// Maximum number of rows in a batch of a query result streamed to the client.
+const STREAM_BATCH_ROWS: usize = 1024;
+
 pub struct Conductor {
     pub parser: SQLParser,
     pub optimizer: Optimizer,
     pub executor: Executor,
+    /// Retries of statements whose implicit transaction was aborted.
+    pub retry_policy: RetryPolicy,
+    /// User the client logged in as, if any.
+    pub user: Option<String>,
+    /// Roles of the users, deciding what the client may run.
+    pub roles: Arc<RoleStore>,
+    /// Settings changed with `SET`.
+    pub settings: SessionSettings,
+    /// Table the client is streaming rows into, if it is.
+    pub ingest: Option<IngestSession>,
+    /// Where the rows of the client's queries are streamed to while they run. Without one the
+    /// rows are kept until the query is done.
+    pub row_sink: Option<Box<dyn RowSink>>,
+    /// Admission of statements, shared with the other clients.
+    pub scheduler: Arc<Scheduler>,
+    /// Log of slow statements, shared with the other clients.
+    pub slow_log: Arc<SlowLog>,
+    /// Plans of queries, shared with the other clients.
+    pub plan_cache: Arc<PlanCache>,
+    /// Token that stops the client's queries, set with `\cancel` from another connection.
+    pub cancel: CancelToken,
+    /// Logical plan, as JSON, and operator profile of the last query planned while the slow
+    /// query log is on.
+    last_plan: Option<(serde_json::Value, PlanProfile)>,
+    /// Transaction the client opened with `BEGIN`, and the database it runs against, until it
+    /// is committed or rolled back.
+    transaction: Option<(Transaction, Arc<DatabaseState>)>,
 }
 
 impl Conductor {
@@ -25,24 +70,141 @@ impl Conductor {
             parser,
             optimizer,
             executor,
+            retry_policy: RetryPolicy::default(),
+            user: None,
+            roles: Arc::new(RoleStore::default()),
+            settings: SessionSettings::default(),
+            ingest: None,
+            row_sink: None,
+            scheduler: Arc::new(Scheduler::default()),
+            slow_log: Arc::new(SlowLog::default()),
+            plan_cache: Arc::new(PlanCache::new(PLAN_CACHE_NAME, DEFAULT_PLAN_CACHE_BYTES)),
+            cancel: CancelToken::new(),
+            last_plan: None,
+            transaction: None,
         };
         Ok(conductor)
     }
 
+    /// Returns the role of the client.
+    pub fn role(&self) -> Role {
+        self.roles.role_of(self.user.as_deref())
+    }
+
+    /// Checks that the client's role allows running something.
+    ///
+    /// # Arguments
+    ///
+    /// * `required` - Least role that may run it.
+    /// * `what` - What is run, for the error message.
+    pub fn authorize(&self, required: Role, what: &str) -> Result<(), CrustyError> {
+        let role = self.role();
+        if role >= required {
+            Ok(())
+        } else {
+            Err(CrustyError::ValidationError(format!(
+                "{} requires the {} role, the {} role is not enough",
+                what, required, role
+            )))
+        }
+    }
+
+    /// Returns true if the client opened a transaction that is not committed or rolled back yet.
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Id of the transaction the client opened, if it has one open.
+    pub fn transaction_id(&self) -> Option<TransactionId> {
+        self.transaction.as_ref().map(|(txn, _)| txn.tid())
+    }
+
+    /// Checks that the client has no transaction open, for what can't be rolled back.
+    ///
+    /// # Arguments
+    ///
+    /// * `what` - What is run, for the error message.
+    fn check_no_transaction(&self, what: &str) -> Result<(), CrustyError> {
+        if self.in_transaction() {
+            Err(CrustyError::ValidationError(format!(
+                "{} can't run inside a transaction, COMMIT or ROLLBACK first",
+                what
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Opens a transaction that the following statements run in until `COMMIT` or `ROLLBACK`.
+    ///
+    /// # Arguments
+    ///
+    /// * `db_state` - Database the transaction runs against.
+    fn begin(&mut self, db_state: &Arc<DatabaseState>) -> Result<QueryResult, CrustyError> {
+        self.check_no_transaction("BEGIN")?;
+        let mut txn = Transaction::new();
+        txn.start(&*db_state.storage_manager);
+        info!("Began transaction {:?}", txn.tid());
+        self.transaction = Some((txn, Arc::clone(db_state)));
+        Ok(QueryResult::new("BEGIN"))
+    }
+
+    /// Commits or rolls back the transaction the client opened.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit` - True to commit, false to roll back.
+    fn end_transaction(&mut self, commit: bool) -> Result<QueryResult, CrustyError> {
+        let (mut txn, db_state) = self.transaction.take().ok_or_else(|| {
+            CrustyError::ValidationError(String::from("No transaction in progress"))
+        })?;
+        info!("Ending transaction {:?}, commit: {}", txn.tid(), commit);
+        txn.complete(&*db_state.storage_manager, commit)?;
+        Ok(QueryResult::new(if commit { "COMMIT" } else { "ROLLBACK" }))
+    }
+
+    /// Rolls back the transaction the client opened, if any, e.g. when the client disconnects.
+    pub fn rollback_open_transaction(&mut self) {
+        if self.in_transaction() {
+            if let Err(e) = self.end_transaction(false) {
+                error!("Failed to roll back the open transaction: {}", e);
+            }
+        }
+    }
+
+    /// Id of the transaction a read runs in: the open transaction, so the read sees its writes,
+    /// else a new one that is never started.
+    fn read_tid(&self) -> TransactionId {
+        match &self.transaction {
+            Some((txn, _)) => txn.tid(),
+            None => Transaction::new().tid(),
+        }
+    }
+
     /// Processes command entered by the user.
     ///
-    /// Only processes `Create`, `Connect`, `Import`, `ShowTables`, and `Reset` commands.
+    /// Only processes `Create`, `Connect`, `Import`, `ImportJson`, `ImportParquet`, `AnalyzeImport`, `ShowTables`, `ShowDatabases`, `CatalogDump`,
+    /// `CatalogLoad`, `Set`, `ShowVariables`, `Stats`, `Rewrite`, `DiskUsage`, `Login`, `Grant`,
+    /// `Ingest`, `CacheStats`, `CacheClear`, `Warm`, `SlowLog`, `Sessions`, `Cancel` and `Reset` commands. Commands the client's role does
+    /// not allow fail, as do commands whose changes can't be rolled back while the client has a
+    /// transaction open.
     ///
     /// # Arguments
     ///
     /// * `cmd` - Command to execute.
-    /// * `id` - Thread id.
+    /// * `client_id` - Id of the client's session.
+    /// * `variables` - Session variables of the client.
     pub fn run_command(
-        &self,
+        &mut self,
         command: commands::Commands,
         client_id: u64,
         server_state: &Arc<ServerState>,
+        variables: &mut SessionVariables,
     ) -> Result<String, CrustyError> {
+        self.authorize(required_role(&command), "This command")?;
+        if outside_transaction(&command) {
+            self.check_no_transaction("This command")?;
+        }
         match command {
             commands::Commands::Create(name) => {
                 info!("Processing COMMAND::Create {:?}", name);
@@ -56,20 +218,46 @@ impl Conductor {
             }
             commands::Commands::Import(path_and_name) => {
                 info!("Processing COMMAND::Import {:?}", path_and_name);
-                server_state.import_database(path_and_name, client_id)
+                let _turn = self.scheduler.admit(self.settings.priority);
+                server_state.import_database(
+                    path_and_name,
+                    client_id,
+                    self.settings.import_commit_rows,
+                    ImportFormat::Csv,
+                )
+            }
+            commands::Commands::ImportJson(path_and_name) => {
+                info!("Processing COMMAND::ImportJson {:?}", path_and_name);
+                let _turn = self.scheduler.admit(self.settings.priority);
+                server_state.import_database(
+                    path_and_name,
+                    client_id,
+                    self.settings.import_commit_rows,
+                    ImportFormat::Json,
+                )
+            }
+            commands::Commands::ImportParquet(path_and_name) => {
+                info!("Processing COMMAND::ImportParquet {:?}", path_and_name);
+                let _turn = self.scheduler.admit(self.settings.priority);
+                server_state.import_database(
+                    path_and_name,
+                    client_id,
+                    self.settings.import_commit_rows,
+                    ImportFormat::Parquet,
+                )
+            }
+            commands::Commands::AnalyzeImport(path_and_name) => {
+                info!("Processing COMMAND::AnalyzeImport {:?}", path_and_name);
+                server_state.analyze_import(&path_and_name, client_id)
             }
             commands::Commands::ShowTables => {
                 info!("Processing COMMAND::ShowTables");
-                let db_id_ref = server_state.active_connections.read().unwrap();
-                match db_id_ref.get(&client_id) {
-                    Some(db_id) => {
-                        let db_ref = server_state.id_to_db.read().unwrap();
-                        let db_state = db_ref.get(db_id).unwrap();
-
+                match server_state.get_connected_db(client_id) {
+                    Ok(db_state) => {
                         let table_names = db_state.get_table_names().unwrap();
                         Ok(table_names)
                     }
-                    None => Ok(String::from("No active DB or DB not found")),
+                    Err(_) => Ok(String::from("No active DB or DB not found")),
                 }
             }
             commands::Commands::ShowDatabases => {
@@ -87,23 +275,120 @@ impl Conductor {
                 }
                 Ok(names.join(","))
             }
-            commands::Commands::Reset => {
-                info!("Processing COMMAND::Reset");
-                let db_id_ref = server_state.active_connections.read().unwrap();
-                match db_id_ref.get(&client_id) {
-                    Some(db_id) => {
-                        let db_ref = server_state.id_to_db.read().unwrap();
-                        let db_state = db_ref.get(db_id).unwrap();
-                        server_state.reset_database(&db_state.storage_manager)
+            commands::Commands::CatalogDump => {
+                info!("Processing COMMAND::CatalogDump");
+                server_state.dump_catalog()
+            }
+            commands::Commands::CatalogLoad(path) => {
+                info!("Processing COMMAND::CatalogLoad {:?}", path);
+                server_state.load_catalog(&path)
+            }
+            commands::Commands::Set(name, query) => {
+                info!("Processing COMMAND::Set {:?}", name);
+                if !SessionVariables::is_valid_name(&name) {
+                    return Err(CrustyError::ValidationError(format!(
+                        "Invalid variable name {}",
+                        name
+                    )));
+                }
+                let db_state = server_state.get_connected_db(client_id)?;
+                let query = variables.substitute(&query)?;
+                let ast = SQLParser::parse_sql(&query)
+                    .map_err(|e| CrustyError::ValidationError(format!("SQL error: {}", e)))?;
+                match ast.first() {
+                    Some(Statement::Query(qbox)) => {
+                        let value = self.run_scalar_query(qbox, &db_state)?;
+                        let res = format!("{} = {}", name, value);
+                        variables.set(&name, value);
+                        Ok(res)
                     }
-                    None => Ok(String::from("No active DB or DB not found")),
+                    _ => Err(CrustyError::ValidationError(String::from(
+                        "Variables can only be set from a query",
+                    ))),
                 }
             }
+            commands::Commands::ShowVariables => {
+                info!("Processing COMMAND::ShowVariables");
+                Ok(variables.list())
+            }
+            commands::Commands::Stats => {
+                info!("Processing COMMAND::Stats");
+                let db_state = server_state.get_connected_db(client_id)?;
+                Ok(db_state.storage_manager.storage_stats().to_string())
+            }
+            commands::Commands::Rewrite(table_name) => {
+                info!("Processing COMMAND::Rewrite {:?}", table_name);
+                let db_state = server_state.get_connected_db(client_id)?;
+                db_state.rewrite_table(&table_name)
+            }
+            commands::Commands::DiskUsage => {
+                info!("Processing COMMAND::DiskUsage");
+                server_state.disk_usage()
+            }
+            commands::Commands::Login(user) => {
+                info!("Processing COMMAND::Login {:?}", user);
+                let role = self.roles.role_of(Some(&user));
+                let res = format!("Logged in as {} with the {} role", user, role);
+                self.user = Some(user);
+                Ok(res)
+            }
+            commands::Commands::Grant(user, role) => {
+                info!("Processing COMMAND::Grant {:?} {:?}", user, role);
+                let role: Role = role.parse()?;
+                self.roles.assign(&user, role)?;
+                Ok(format!("Granted the {} role to {}", role, user))
+            }
+            commands::Commands::Ingest(table_name) => {
+                info!("Processing COMMAND::Ingest {:?}", table_name);
+                let db_state = server_state.get_connected_db(client_id)?;
+                self.ingest = Some(IngestSession::start(db_state, &table_name)?);
+                Ok(format!(
+                    "Ingesting into {}, send one frame of rows separated by ';' per line and \\end when done",
+                    table_name
+                ))
+            }
+            commands::Commands::CacheStats => {
+                info!("Processing COMMAND::CacheStats");
+                Ok(server_state.caches.stats())
+            }
+            commands::Commands::CacheClear(name) => {
+                info!("Processing COMMAND::CacheClear {:?}", name);
+                server_state.caches.clear(name.as_deref())
+            }
+            commands::Commands::Warm(table_name, pages) => {
+                info!("Processing COMMAND::Warm {:?} {:?}", table_name, pages);
+                let db_state = server_state.get_connected_db(client_id)?;
+                db_state.warm_table(&table_name, pages)
+            }
+            commands::Commands::SlowLog => {
+                info!("Processing COMMAND::SlowLog");
+                Ok(server_state.slow_log.list())
+            }
+            commands::Commands::Sessions => {
+                info!("Processing COMMAND::Sessions");
+                Ok(server_state.list_sessions())
+            }
+            commands::Commands::Cancel(id) => {
+                info!("Processing COMMAND::Cancel {:?}", id);
+                server_state.sessions.cancel(id)?;
+                Ok(format!("Cancelled the statement of session {}", id))
+            }
+            commands::Commands::Reset => {
+                info!("Processing COMMAND::Reset");
+                server_state.reset_database()
+            }
         }
     }
 
     /// Runs SQL commands depending on the first statement.
     ///
+    /// Outside of a transaction opened with `BEGIN`, every statement runs in a transaction of its
+    /// own, and one whose transaction is aborted is rerun according to `retry_policy`. Inside, a
+    /// statement that fails rolls the whole transaction back, since its writes can't be undone
+    /// on their own. Statements other than `SET`, `SHOW`, `BEGIN`, `COMMIT` and `ROLLBACK` wait
+    /// for their turn by the priority of the session. The metadata of a result has the time the
+    /// statement took once it was admitted, reruns included.
+    ///
     /// # Arguments
     ///
     /// * `cmd` - Tokenized command into statements.
@@ -113,28 +398,386 @@ impl Conductor {
         cmd: Vec<Statement>,
         db_state: &Arc<DatabaseState>,
     ) -> Result<common::QueryResult, CrustyError> {
-        if cmd.is_empty() {
-            Err(CrustyError::CrustyError(String::from("Empty SQL command")))
-        } else {
-            match cmd.first().unwrap() {
-                Statement::CreateTable {
-                    name: table_name,
-                    columns,
-                    constraints: _,  // ignoring
-                    with_options: _, // ignoring
-                    external: _,     // ignoring
-                    file_format: _,  // ignoring
-                    location: _,     // ignoring
-                } => {
-                    info!("Processing CREATE table: {:?}", table_name);
-                    db_state.create_table(&get_name(&table_name)?, columns)
+        match cmd.first() {
+            None => Err(CrustyError::CrustyError(String::from("Empty SQL command"))),
+            Some(stmt) => {
+                let required = match stmt {
+                    Statement::Query(_)
+                    | Statement::SetVariable { .. }
+                    | Statement::ShowVariable { .. }
+                    | Statement::StartTransaction { .. }
+                    | Statement::Commit { .. }
+                    | Statement::Rollback { .. } => Role::ReadOnly,
+                    _ => Role::ReadWrite,
+                };
+                self.authorize(required, "This statement")?;
+                match stmt {
+                    Statement::StartTransaction { .. } => return self.begin(db_state),
+                    Statement::Commit { .. } => return self.end_transaction(true),
+                    Statement::Rollback { .. } => return self.end_transaction(false),
+                    Statement::CreateTable { .. } | Statement::Drop { .. } => {
+                        self.check_no_transaction("CREATE and DROP")?
+                    }
+                    _ => {}
                 }
-                Statement::Query(qbox) => {
-                    info!("Processing SQL Query");
-                    self.run_query(qbox, &db_state)
+                let scheduler = Arc::clone(&self.scheduler);
+                let _turn = match stmt {
+                    Statement::SetVariable { .. } | Statement::ShowVariable { .. } => None,
+                    _ => Some(scheduler.admit(self.settings.priority)),
+                };
+                let retry_policy = self.retry_policy;
+                self.last_plan = None;
+                let started = Instant::now();
+                let res = match self.transaction.as_ref().map(|(txn, _)| txn.tid()) {
+                    Some(tid) => self
+                        .run_statement(stmt, db_state, tid)
+                        .map_err(|e| self.roll_back_after(e)),
+                    // Each statement runs in its own transaction, so it can be rerun if that is aborted.
+                    None => retry_policy.run(|| self.run_in_transaction(stmt, db_state)),
+                };
+                let elapsed = started.elapsed();
+                self.log_if_slow(stmt, db_state, &res, elapsed);
+                res.map(|mut qr| {
+                    qr.metadata_mut().elapsed = Some(elapsed);
+                    qr
+                })
+            }
+        }
+    }
+
+    /// Adds a statement to the slow query log if it took at least the log's threshold, with
+    /// the plan and operator profile of the query it ran, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `stmt` - Statement that ran.
+    /// * `db_state` - Database the statement ran against.
+    /// * `res` - Result of the statement.
+    /// * `elapsed` - Time the statement took, reruns included.
+    fn log_if_slow(
+        &mut self,
+        stmt: &Statement,
+        db_state: &DatabaseState,
+        res: &Result<QueryResult, CrustyError>,
+        elapsed: Duration,
+    ) {
+        if !self.slow_log.is_slow(elapsed) {
+            return;
+        }
+        let (plan, operators, io) = match self.last_plan.take() {
+            Some((plan, profile)) => (
+                Some(plan),
+                profile.operators(),
+                Some(self.executor.io_stats()),
+            ),
+            None => (None, Vec::new(), None),
+        };
+        self.slow_log.record(SlowQuery {
+            timestamp: SlowLog::now(),
+            user: self.user.clone(),
+            database: db_state.name.clone(),
+            sql: stmt.to_string(),
+            duration_ms: elapsed.as_millis() as u64,
+            error: res.as_ref().err().map(|e| e.to_string()),
+            plan,
+            operators,
+            io,
+        });
+    }
+
+    /// Runs a statement in a transaction of its own, committed if the statement succeeds and
+    /// rolled back if it fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `stmt` - Statement to run.
+    /// * `db_state` - Database the statement runs against.
+    fn run_in_transaction(
+        &mut self,
+        stmt: &Statement,
+        db_state: &Arc<DatabaseState>,
+    ) -> Result<QueryResult, CrustyError> {
+        let mut txn = Transaction::new();
+        txn.start(&*db_state.storage_manager);
+        let res = self.run_statement(stmt, db_state, txn.tid());
+        txn.complete(&*db_state.storage_manager, res.is_ok())?;
+        res
+    }
+
+    /// Rolls back the open transaction after a statement in it failed, and returns the error of
+    /// the statement, telling the client the transaction is gone.
+    ///
+    /// # Arguments
+    ///
+    /// * `e` - Error of the statement.
+    fn roll_back_after(&mut self, e: CrustyError) -> CrustyError {
+        self.rollback_open_transaction();
+        let note = |s: String| format!("{}, the transaction was rolled back", s);
+        match e {
+            CrustyError::IOError(s) => CrustyError::IOError(note(s)),
+            CrustyError::CrustyError(s) => CrustyError::CrustyError(note(s)),
+            CrustyError::ValidationError(s) => CrustyError::ValidationError(note(s)),
+            CrustyError::ExecutionError(s) => CrustyError::ExecutionError(note(s)),
+            CrustyError::TransactionAbortedError => CrustyError::TransactionAbortedError,
+        }
+    }
+
+    /// Runs a single SQL statement.
+    ///
+    /// # Arguments
+    ///
+    /// * `stmt` - Statement to run.
+    /// * `db_state` - Database the statement runs against.
+    /// * `tid` - Transaction the statement runs in.
+    fn run_statement(
+        &mut self,
+        stmt: &Statement,
+        db_state: &Arc<DatabaseState>,
+        tid: TransactionId,
+    ) -> Result<common::QueryResult, CrustyError> {
+        match stmt {
+            Statement::CreateTable {
+                name: table_name,
+                columns,
+                constraints: _,  // ignoring
+                with_options: _, // ignoring
+                external: _,     // ignoring
+                file_format: _,  // ignoring
+                location: _,     // ignoring
+            } => {
+                info!("Processing CREATE table: {:?}", table_name);
+                db_state.create_table(&get_name(table_name)?, columns)
+            }
+            Statement::Query(qbox) => {
+                info!("Processing SQL Query");
+                self.run_query(qbox, db_state, tid)
+            }
+            Statement::Insert {
+                table_name,
+                columns,
+                source,
+            } => {
+                info!("Processing INSERT into {:?}", table_name);
+                match &source.body {
+                    SetExpr::Values(values) => {
+                        db_state.insert_rows(&get_name(table_name)?, columns, &values.0, tid)
+                    }
+                    _ => Err(CrustyError::ValidationError(String::from(
+                        "Only INSERT INTO ... VALUES is supported",
+                    ))),
+                }
+            }
+            Statement::Drop {
+                object_type: ObjectType::Table,
+                if_exists,
+                names,
+                ..
+            } => {
+                info!("Processing DROP table: {:?}", names);
+                let names = names.iter().map(get_name).collect::<Result<Vec<_>, _>>()?;
+                let exists = |name: &str| {
+                    let tables = db_state.database.tables.read().unwrap();
+                    tables.contains_key(&Table::get_table_id(name))
+                };
+                // all or none of the tables are dropped
+                if let Some(missing) = names.iter().find(|name| !*if_exists && !exists(name)) {
+                    return Err(CrustyError::CrustyError(format!(
+                        "Table {} does not exist",
+                        missing
+                    )));
+                }
+                let mut res = Vec::new();
+                for name in names {
+                    if exists(&name) {
+                        res.push(db_state.drop_table(&name)?.result().to_string());
+                    } else {
+                        res.push(format!("Table {} does not exist, skipped", name));
+                    }
+                }
+                Ok(QueryResult::new(&res.join("\n")))
+            }
+            Statement::AlterTable { name, operation } => {
+                Err(CrustyError::ValidationError(format!(
+                    "ALTER TABLE {} {} is not supported, only columns can be added and dropped",
+                    get_name(name)?,
+                    operation
+                )))
+            }
+            Statement::SetVariable {
+                variable, value, ..
+            } => {
+                let value = match value {
+                    SetVariableValue::Ident(v) => v.to_string(),
+                    SetVariableValue::Literal(v) => v.to_string(),
+                };
+                self.settings.set(variable, &value)?;
+                Ok(QueryResult::new(&format!("{} = {}", variable, value)))
+            }
+            Statement::ShowVariable { variable } => Ok(QueryResult::new(&format!(
+                "{} = {}",
+                variable,
+                self.settings.get(variable)?
+            ))),
+            _ => Err(CrustyError::CrustyError(String::from("Not supported "))),
+        }
+    }
+
+    /// Empties a table without visiting its values.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table to truncate.
+    /// * `db_state` - Database the table belongs to.
+    pub fn run_truncate(
+        &self,
+        table_name: &str,
+        db_state: &Arc<DatabaseState>,
+    ) -> Result<QueryResult, CrustyError> {
+        info!("Processing TRUNCATE table: {:?}", table_name);
+        self.authorize(Role::ReadWrite, "TRUNCATE")?;
+        self.check_no_transaction("TRUNCATE")?;
+        db_state.truncate_table(table_name)
+    }
+
+    /// Collects the statistics the optimizer plans queries with.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table to analyze, None for all tables.
+    /// * `db_state` - Database the tables belong to.
+    pub fn run_analyze(
+        &self,
+        table_name: Option<&str>,
+        db_state: &Arc<DatabaseState>,
+    ) -> Result<QueryResult, CrustyError> {
+        info!("Processing ANALYZE of {:?}", table_name);
+        self.authorize(Role::ReadWrite, "ANALYZE")?;
+        let _turn = self.scheduler.admit(self.settings.priority);
+        db_state.analyze(table_name)
+    }
+
+    /// Adds or drops a column of a table, rewriting its rows.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table to alter.
+    /// * `change` - Column to add or drop.
+    /// * `db_state` - Database the table belongs to.
+    pub fn run_alter_table(
+        &self,
+        table_name: &str,
+        change: &AlterColumn,
+        db_state: &Arc<DatabaseState>,
+    ) -> Result<QueryResult, CrustyError> {
+        info!("Processing ALTER table: {:?} {:?}", table_name, change);
+        self.authorize(Role::ReadWrite, "ALTER TABLE")?;
+        self.check_no_transaction("ALTER TABLE")?;
+        let _turn = self.scheduler.admit(self.settings.priority);
+        match change {
+            AlterColumn::Add(column) => db_state.add_column(table_name, column),
+            AlterColumn::Drop(column) => db_state.drop_column(table_name, column),
+        }
+    }
+
+    /// Creates or drops an index.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index statement to run.
+    /// * `db_state` - Database the index belongs to.
+    pub fn run_index(
+        &self,
+        index: &IndexStatement,
+        db_state: &Arc<DatabaseState>,
+    ) -> Result<QueryResult, CrustyError> {
+        info!("Processing {:?}", index);
+        match index {
+            IndexStatement::Create {
+                name,
+                table,
+                column,
+            } => {
+                self.authorize(Role::ReadWrite, "CREATE INDEX")?;
+                self.check_no_transaction("CREATE INDEX")?;
+                let _turn = self.scheduler.admit(self.settings.priority);
+                db_state.create_index(name, table, column)
+            }
+            IndexStatement::Drop { name, if_exists } => {
+                self.authorize(Role::ReadWrite, "DROP INDEX")?;
+                self.check_no_transaction("DROP INDEX")?;
+                db_state.drop_index(name, *if_exists)
+            }
+        }
+    }
+
+    /// Describes the plan of a query. With `analyze` the query is also run, and the rows it
+    /// returned and the pages it read and wrote are added to the description.
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd` - Tokenized statement to explain.
+    /// * `analyze` - Whether to run the query.
+    /// * `db_state` - Database the query runs against.
+    pub fn run_explain(
+        &mut self,
+        cmd: Vec<Statement>,
+        analyze: bool,
+        db_state: &Arc<DatabaseState>,
+    ) -> Result<QueryResult, CrustyError> {
+        match cmd.first() {
+            Some(Statement::Query(qbox)) => {
+                info!("Processing EXPLAIN");
+                let (_, lp) = self.optimized_plan(qbox, db_state)?;
+                let mut res = {
+                    let stats = db_state.statistics.read().unwrap();
+                    let stats = if stats.is_empty() {
+                        None
+                    } else {
+                        Some(&*stats)
+                    };
+                    lp.explain(stats)
+                };
+                if analyze {
+                    let scheduler = Arc::clone(&self.scheduler);
+                    let _turn = scheduler.admit(self.settings.priority);
+                    self.plan_query(qbox, db_state, self.read_tid())?;
+                    self.executor.start()?;
+                    let mut rows = 0;
+                    while self.executor.next()?.is_some() {
+                        rows += 1;
+                    }
+                    self.executor.close()?;
+                    res += &format!("rows: {}, {}\n", rows, self.executor.io_stats());
                 }
-                _ => Err(CrustyError::CrustyError(String::from("Not supported "))),
+                Ok(QueryResult::new(&res))
             }
+            _ => Err(CrustyError::CrustyError(String::from(
+                "Only queries can be explained",
+            ))),
+        }
+    }
+
+    /// Runs a query that must produce a single row with a single column and returns that value.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Query to run.
+    /// * `db_state` - Database the query runs against.
+    fn run_scalar_query(
+        &mut self,
+        query: &sqlparser::ast::Query,
+        db_state: &DatabaseState,
+    ) -> Result<Field, CrustyError> {
+        self.plan_query(query, db_state, self.read_tid())?;
+        self.executor.start()?;
+        let first = self.executor.next()?;
+        let second = self.executor.next()?;
+        self.executor.close()?;
+        match (first, second) {
+            (Some(tuple), None) if tuple.size() == 1 => Ok(tuple.field_vals[0].clone()),
+            _ => Err(CrustyError::ExecutionError(String::from(
+                "Query must return exactly one row with one column",
+            ))),
         }
     }
 
@@ -143,21 +786,57 @@ impl Conductor {
     /// # Arguments
     ///
     /// * `query` - Query to run.
-    /// * `id` - Thread id for lock management.
+    /// * `db_state` - Database the query runs against.
+    /// * `tid` - Transaction the query runs in.
     fn run_query(
         &mut self,
         query: &sqlparser::ast::Query,
         db_state: &DatabaseState,
+        tid: TransactionId,
     ) -> Result<QueryResult, CrustyError> {
-        let db = &db_state.database;
-        // Parse query AST into a logical plan
-        debug!("Obtaining Logical Plan from query's AST");
-        let lp = TranslateAndValidate::from_sql(query, db)?;
-        debug!("Optimizing logical plan...TODO");
-        self.optimizer.do_your_work();
+        let plan = self.plan_query(query, db_state, tid)?;
 
-        // Start transaction
-        let txn = Transaction::new();
+        // Finally, execute the query
+        debug!("Executing query");
+        let res = match self.settings.output_format {
+            OutputFormat::Text => match (self.settings.checksum, self.row_sink.as_mut()) {
+                (ResultChecksum::Off, Some(sink)) => self
+                    .executor
+                    .execute_streaming(STREAM_BATCH_ROWS, sink.as_mut()),
+                (ResultChecksum::Off, None) => self.executor.execute_rows(),
+                (checksum, _) => self.executor.execute_with_checksum(checksum),
+            },
+            #[cfg(feature = "arrow")]
+            OutputFormat::Arrow => crate::arrow_ipc::execute(&mut self.executor),
+            #[cfg(not(feature = "arrow"))]
+            OutputFormat::Arrow => unreachable!("output_format arrow can't be set"),
+        };
+        match res {
+            Ok(mut qr) => {
+                info!("Query done, {}", self.executor.io_stats());
+                qr.metadata_mut().plan = Some(plan);
+                Ok(qr)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Translates, optimizes and plans a query, and configures the executor to run it. Returns
+    /// the operator tree of the optimized plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Query to plan.
+    /// * `db_state` - Database the query runs against.
+    /// * `tid` - Transaction the query runs in.
+    fn plan_query(
+        &mut self,
+        query: &sqlparser::ast::Query,
+        db_state: &DatabaseState,
+        tid: TransactionId,
+    ) -> Result<String, CrustyError> {
+        // Every step below sees the tables as they were when the statement started
+        let (catalog, lp) = self.optimized_plan(query, db_state)?;
 
         // After optimizer has done its job, we obtain a physical representation of this logical-plan
         // This physical representation depends on the Executor implementation, so Executors must
@@ -165,18 +844,418 @@ impl Conductor {
         // back a physical plan which is a thing that the Executor knows how to interpret
         debug!("Configuring Storage Manager");
         &self.executor.configure_sm(&db_state.storage_manager);
-        let physical_plan =
-            Executor::logical_plan_to_op_iterator(&db_state.storage_manager, db, &lp, txn.tid())?;
+        let mut options = self.settings.plan_options();
+        options.cancel = Some(self.cancel.clone());
+        // The time limit counts from when the plan is made, before it starts
+        let deadline = self.settings.max_query_time.map(QueryDeadline::after);
+        options.deadline = deadline;
+        let physical_plan = if self.slow_log.is_enabled() {
+            // The plan is profiled in case the query turns out to be slow.
+            let (plan, profile) = Executor::logical_plan_to_profiled_op_iterator(
+                &db_state.storage_manager,
+                &catalog,
+                &lp,
+                tid,
+                options,
+            )?;
+            self.last_plan = Some((lp.to_json(), profile));
+            plan
+        } else {
+            Executor::logical_plan_to_op_iterator(
+                &db_state.storage_manager,
+                &catalog,
+                &lp,
+                tid,
+                options,
+            )?
+        };
         // We populate the executor with the state: physical plan, and storage manager ref
         debug!("Configuring Physical Plan");
-        &self.executor.configure_query(physical_plan);
+        self.executor.set_cancel_token(self.cancel.clone());
+        self.executor.set_deadline(deadline);
+        self.executor.configure_query(physical_plan, tid);
+        Ok(lp.explain(None))
+    }
 
-        // Finally, execute the query
-        debug!("Executing query");
-        let res = self.executor.execute();
-        match res {
-            Ok(qr) => Ok(qr),
-            Err(e) => Err(e),
+    /// Takes a snapshot of the catalog, and translates and optimizes a query against it. The
+    /// plan is taken from the plan cache if the query was planned before against the same
+    /// version of the catalog, and cached otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Query to plan.
+    /// * `db_state` - Database the query runs against.
+    fn optimized_plan(
+        &self,
+        query: &sqlparser::ast::Query,
+        db_state: &DatabaseState,
+    ) -> Result<(CatalogSnapshot, Arc<LogicalPlan>), CrustyError> {
+        // The version is read first, so a plan is never cached under a version that is newer
+        // than the catalog it was made with.
+        let key = PlanKey {
+            database: db_state.id,
+            catalog_version: db_state.catalog_version(),
+            sql: query.to_string(),
+        };
+        let catalog = CatalogSnapshot::new(&db_state.database);
+        if let Some(lp) = self.plan_cache.get(&key) {
+            debug!("Using the cached plan of the query");
+            return Ok((catalog, lp));
+        }
+        // Parse query AST into a logical plan
+        debug!("Obtaining Logical Plan from query's AST");
+        let lp = TranslateAndValidate::from_sql(query, &catalog)?;
+        debug!("Optimizing logical plan");
+        let lp = {
+            let stats = db_state.statistics.read().unwrap();
+            self.optimizer.optimize(lp, &catalog, &stats)?
+        };
+        let lp = Arc::new(lp);
+        let size = key.sql.len() + lp.to_json().to_string().len();
+        self.plan_cache.insert(key, Arc::clone(&lp), size);
+        Ok((catalog, lp))
+    }
+}
+
+/// Returns true if a command makes changes that can't be rolled back, so it can't run inside a
+/// transaction.
+///
+/// # Arguments
+///
+/// * `command` - Command to run.
+fn outside_transaction(command: &commands::Commands) -> bool {
+    use commands::Commands::*;
+    matches!(
+        command,
+        Connect(_)
+            | Import(_)
+            | ImportJson(_)
+            | ImportParquet(_)
+            | CatalogLoad(_)
+            | Rewrite(_)
+            | Ingest(_)
+            | Reset
+    )
+}
+
+/// Returns the least role that may run a command.
+///
+/// # Arguments
+///
+/// * `command` - Command to run.
+fn required_role(command: &commands::Commands) -> Role {
+    use commands::Commands::*;
+    match command {
+        Reset | Grant(_, _) | CacheClear(_) | SlowLog | Sessions | Cancel(_) => Role::Admin,
+        Create(_) | Import(_) | ImportJson(_) | ImportParquet(_) | CatalogLoad(_) | Rewrite(_)
+        | Ingest(_) => Role::ReadWrite,
+        Connect(_)
+        | AnalyzeImport(_)
+        | ShowTables
+        | ShowDatabases
+        | CatalogDump
+        | Set(_, _)
+        | ShowVariables
+        | Stats
+        | DiskUsage
+        | CacheStats
+        | Warm(_, _)
+        | Login(_) => Role::ReadOnly,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::ManagedCache;
+    use common::testutil::gen_random_dir;
+    use std::fs;
+
+    /// Runs a statement and returns its result.
+    fn sql(
+        conductor: &mut Conductor,
+        db_state: &Arc<DatabaseState>,
+        sql: &str,
+    ) -> Result<String, CrustyError> {
+        let ast = SQLParser::parse_sql(sql).unwrap();
+        conductor
+            .run_sql(ast, db_state)
+            .map(|qr| qr.result().to_string())
+    }
+
+    /// Number of rows of table t, as seen by the transaction the client has open, if any.
+    fn rows(conductor: &mut Conductor, db_state: &Arc<DatabaseState>) -> usize {
+        // one line of column names, then one line per row
+        let res = sql(conductor, db_state, "SELECT a FROM t").unwrap();
+        res.lines().count() - 1
+    }
+
+    #[test]
+    fn test_transactions() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = Arc::new(DatabaseState::new_from_name("txn", storage_path)?);
+        let mut conductor =
+            Conductor::new(SQLParser::new(), Optimizer::new(), Executor::new_ref())?;
+        let c = &mut conductor;
+        sql(c, &db_state, "CREATE TABLE t (a INT)")?;
+        assert!(sql(c, &db_state, "COMMIT").is_err());
+
+        // the transaction reads its own writes, which are gone after ROLLBACK
+        assert_eq!("BEGIN", sql(c, &db_state, "BEGIN")?);
+        sql(c, &db_state, "INSERT INTO t VALUES (1), (2)")?;
+        sql(c, &db_state, "INSERT INTO t VALUES (3)")?;
+        assert_eq!(3, rows(c, &db_state));
+        assert!(sql(c, &db_state, "BEGIN").is_err());
+        // changes that can't be rolled back are refused and leave the transaction open
+        assert!(sql(c, &db_state, "CREATE TABLE u (b INT)").is_err());
+        assert!(c.run_truncate("t", &db_state).is_err());
+        assert!(c.in_transaction());
+        assert_eq!("ROLLBACK", sql(c, &db_state, "ROLLBACK")?);
+        assert_eq!(0, rows(c, &db_state));
+
+        sql(c, &db_state, "BEGIN")?;
+        sql(c, &db_state, "INSERT INTO t VALUES (1)")?;
+        assert_eq!("COMMIT", sql(c, &db_state, "COMMIT")?);
+        assert_eq!(1, rows(c, &db_state));
+
+        // a statement that fails rolls the transaction back
+        sql(c, &db_state, "BEGIN")?;
+        sql(c, &db_state, "INSERT INTO t VALUES (2)")?;
+        let err = sql(c, &db_state, "INSERT INTO missing VALUES (1)").unwrap_err();
+        assert!(err.to_string().ends_with("the transaction was rolled back"));
+        assert!(!c.in_transaction());
+        assert_eq!(1, rows(c, &db_state));
+
+        // so does a client that leaves
+        sql(c, &db_state, "BEGIN")?;
+        sql(c, &db_state, "INSERT INTO t VALUES (2)")?;
+        c.rollback_open_transaction();
+        assert_eq!(1, rows(c, &db_state));
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_locks() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = Arc::new(DatabaseState::new_from_name("locks", storage_path)?);
+        let new_conductor =
+            || Conductor::new(SQLParser::new(), Optimizer::new(), Executor::new_ref());
+        let (mut writer, mut reader) = (new_conductor()?, new_conductor()?);
+        sql(&mut writer, &db_state, "CREATE TABLE t (a INT)")?;
+        sql(&mut writer, &db_state, "BEGIN")?;
+        sql(&mut writer, &db_state, "INSERT INTO t VALUES (1)")?;
+
+        // the uncommitted row is locked, the reader gives up waiting for it
+        assert_eq!(
+            Err(CrustyError::TransactionAbortedError),
+            sql(&mut reader, &db_state, "SELECT a FROM t")
+        );
+        sql(&mut reader, &db_state, "BEGIN")?;
+        assert_eq!(
+            Err(CrustyError::TransactionAbortedError),
+            sql(&mut reader, &db_state, "SELECT a FROM t")
+        );
+        assert!(!reader.in_transaction());
+
+        sql(&mut writer, &db_state, "COMMIT")?;
+        assert_eq!(1, rows(&mut reader, &db_state));
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_cache() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = Arc::new(DatabaseState::new_from_name("plans", storage_path)?);
+        let mut conductor =
+            Conductor::new(SQLParser::new(), Optimizer::new(), Executor::new_ref())?;
+        let c = &mut conductor;
+        sql(c, &db_state, "CREATE TABLE t (a INT)")?;
+        sql(c, &db_state, "INSERT INTO t VALUES (1)")?;
+        let hits_misses = |c: &Conductor| {
+            let stats = c.plan_cache.stats();
+            (stats.hits, stats.misses)
+        };
+
+        // the same query, however it is spaced and cased, is planned once
+        sql(c, &db_state, "SELECT * FROM t")?;
+        assert_eq!("t.a  \n1    \n", sql(c, &db_state, "select *  from t")?);
+        assert_eq!((1, 1), hits_misses(c));
+
+        // plans made before a table changes aren't used after
+        sql(c, &db_state, "DROP TABLE t")?;
+        sql(c, &db_state, "CREATE TABLE t (a INT, b INT)")?;
+        assert!(sql(c, &db_state, "SELECT * FROM t")?.starts_with("t.a  t.b"));
+        assert_eq!((1, 2), hits_misses(c));
+        // so do plans made before the statistics change
+        c.run_analyze(None, &db_state)?;
+        sql(c, &db_state, "SELECT * FROM t")?;
+        assert_eq!((1, 3), hits_misses(c));
+
+        // queries that fail to plan aren't cached
+        assert!(sql(c, &db_state, "SELECT x FROM t").is_err());
+        assert_eq!((1, 4), hits_misses(c));
+        assert_eq!(3, c.plan_cache.stats().entries);
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+    #[test]
+    fn test_cancel() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = Arc::new(DatabaseState::new_from_name("cancel", storage_path)?);
+        let mut conductor =
+            Conductor::new(SQLParser::new(), Optimizer::new(), Executor::new_ref())?;
+        let c = &mut conductor;
+        sql(c, &db_state, "CREATE TABLE t (a INT)")?;
+        sql(c, &db_state, "INSERT INTO t VALUES (2), (1)")?;
+
+        // the sort stops reading its input once the query is cancelled
+        c.cancel.cancel();
+        match sql(c, &db_state, "SELECT * FROM t ORDER BY a") {
+            Err(CrustyError::ExecutionError(msg)) => assert_eq!("Query cancelled", msg),
+            other => panic!("Expected an execution error, got {:?}", other),
+        }
+        c.cancel.reset();
+        assert_eq!(
+            "t.a  \n1    \n2    \n",
+            sql(c, &db_state, "SELECT * FROM t ORDER BY a")?
+        );
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+    #[test]
+    fn test_max_query_time() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = Arc::new(DatabaseState::new_from_name("timeout", storage_path)?);
+        let mut conductor =
+            Conductor::new(SQLParser::new(), Optimizer::new(), Executor::new_ref())?;
+        let c = &mut conductor;
+        sql(c, &db_state, "CREATE TABLE t (a INT)")?;
+        sql(c, &db_state, "CREATE TABLE u (b INT)")?;
+        let values: Vec<String> = (0..300).map(|i| format!("({})", i)).collect();
+        let values = values.join(", ");
+        sql(c, &db_state, &format!("INSERT INTO t VALUES {}", values))?;
+        sql(c, &db_state, &format!("INSERT INTO u VALUES {}", values))?;
+
+        sql(c, &db_state, "SET max_query_time = 1")?;
+        match sql(c, &db_state, "SELECT COUNT(t.a) FROM t, u") {
+            Err(CrustyError::ExecutionError(msg)) => {
+                assert_eq!("Query exceeded max_query_time of 1 ms and was aborted", msg)
+            }
+            other => panic!("Expected an execution error, got {:?}", other),
+        }
+        sql(c, &db_state, "SET max_query_time = off")?;
+        assert!(sql(c, &db_state, "SELECT COUNT(t.a) FROM t, u")?.contains("90000"));
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallelism() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = Arc::new(DatabaseState::new_from_name("parallel", storage_path)?);
+        let mut conductor =
+            Conductor::new(SQLParser::new(), Optimizer::new(), Executor::new_ref())?;
+        let c = &mut conductor;
+        sql(c, &db_state, "CREATE TABLE t (a INT, b INT)")?;
+        sql(c, &db_state, "CREATE TABLE u (c INT, d INT)")?;
+        let values: Vec<String> = (0..3000).map(|i| format!("({}, {})", i, i % 7)).collect();
+        sql(
+            c,
+            &db_state,
+            &format!("INSERT INTO t VALUES {}", values.join(", ")),
+        )?;
+        let values: Vec<String> = (0..5).map(|i| format!("({}, {})", i, -i)).collect();
+        sql(
+            c,
+            &db_state,
+            &format!("INSERT INTO u VALUES {}", values.join(", ")),
+        )?;
+
+        let queries = [
+            "SELECT * FROM t",
+            "SELECT t.a FROM t WHERE t.b = 3",
+            "SELECT b, COUNT(a), SUM(a), MAX(a) FROM t GROUP BY b ORDER BY b",
+            "SELECT t.a, u.d FROM t JOIN u ON t.b = u.c WHERE t.a < 1000",
+        ];
+        let sequential = queries
+            .iter()
+            .map(|q| sql(c, &db_state, q))
+            .collect::<Result<Vec<String>, CrustyError>>()?;
+        sql(c, &db_state, "SET parallelism = 4")?;
+        // the parts are merged in the order of the table, so even unsorted rows match
+        for (query, expected) in queries.iter().zip(&sequential) {
+            assert_eq!(expected, &sql(c, &db_state, query)?, "{}", query);
+        }
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_distinct() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = Arc::new(DatabaseState::new_from_name("distinct", storage_path)?);
+        let mut conductor =
+            Conductor::new(SQLParser::new(), Optimizer::new(), Executor::new_ref())?;
+        let c = &mut conductor;
+        sql(c, &db_state, "CREATE TABLE t (a INT, b INT)")?;
+        // each value of b is on 1000 rows, with each of the 100 values of a 10 times
+        let values: Vec<String> = (0..3000)
+            .map(|i| format!("({}, {})", i % 100, i % 3))
+            .collect();
+        sql(
+            c,
+            &db_state,
+            &format!("INSERT INTO t VALUES {}", values.join(", ")),
+        )?;
+        sql(c, &db_state, "INSERT INTO t VALUES (NULL, 0)")?;
+
+        let query = "SELECT b, COUNT(DISTINCT a), COUNT(a) FROM t GROUP BY b ORDER BY b";
+        let expected = sql(c, &db_state, query)?;
+        let rows: Vec<Vec<&str>> = expected
+            .lines()
+            .skip(1)
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            vec![
+                vec!["0", "100", "1000"],
+                vec!["1", "100", "1000"],
+                vec!["2", "100", "1000"]
+            ],
+            rows
+        );
+        assert!(sql(c, &db_state, "SELECT COUNT(DISTINCT a) FROM t")?.contains("\n100 "));
+        let empty = "SELECT COUNT(DISTINCT a) FROM t WHERE a < 0";
+        assert!(sql(c, &db_state, empty)?.contains("\n0 "));
+        // the same counts when the distinct values don't fit in memory, or are counted in parts
+        sql(c, &db_state, "SET sort_memory = 4096")?;
+        assert_eq!(expected, sql(c, &db_state, query)?);
+        sql(c, &db_state, "SET parallelism = 4")?;
+        assert_eq!(expected, sql(c, &db_state, query)?);
+
+        match sql(c, &db_state, "SELECT SUM(DISTINCT a) FROM t") {
+            Err(CrustyError::ValidationError(msg)) => {
+                assert_eq!("DISTINCT is only supported in COUNT, not in SUM", msg)
+            }
+            other => panic!("Expected a validation error, got {:?}", other),
         }
+        fs::remove_dir_all(dir)?;
+        Ok(())
     }
 }
@@ -1,69 +1,983 @@
 use common::ids::{ContainerId, TransactionId};
-use common::storage_trait::StorageTrait;
+use common::storage_trait::{ContainerType, StorageTrait};
 use common::table::Table;
 use common::{CrustyError, DataType, Field, Tuple};
+use std::fmt;
 use std::fs::File;
+use std::sync::mpsc;
+use std::thread;
 
-use memstore::storage_manager::StorageManager;
+use crate::database_state::DatabaseState;
+use crate::StorageManager;
 
-/// Function to import csv data into an existing table within a database.
+/// Rows that would not load that `analyze_csv` describes.
+const SAMPLE_VIOLATIONS: usize = 5;
+
+/// Rejected rows whose errors `import_csv` reports.
+const REPORTED_REJECTIONS: usize = 10;
+/// Rows `import_csv` inserts at once, unless it commits every `commit_rows`.
+const IMPORT_CHUNK_ROWS: usize = 4096;
+/// Chunks the reader of a parallel import may read ahead of the inserts.
+const READ_AHEAD_CHUNKS: usize = 2;
+
+/// Formats of the files `\\i`, `\\ij` and `\\ip` import.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    /// An array of objects, see `json_import::import_json`.
+    Json,
+    /// Apache Parquet, see `parquet_import::import_parquet`.
+    Parquet,
+}
+
+/// How a CSV file is read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CsvOptions {
+    /// Whether the first line of the file names the columns, and is skipped.
+    pub header: bool,
+    /// Byte separating the fields of a row.
+    pub delimiter: u8,
+    /// Whether the file is read by a thread of its own while its rows are inserted.
+    pub parallel: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            header: false,
+            delimiter: b',',
+            parallel: false,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Reads the options among the arguments of `\i`, `--header`, `--delimiter <char>` and
+    /// `--parallel`, and returns them with the other arguments. The delimiter is a single ASCII
+    /// character, or `tab`.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Arguments of the command, separated by whitespace.
+    pub fn parse_args(args: &str) -> Result<(Self, Vec<&str>), CrustyError> {
+        let mut options = CsvOptions::default();
+        let mut rest = Vec::new();
+        let mut tokens = args.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token {
+                "--header" => options.header = true,
+                "--parallel" => options.parallel = true,
+                "--delimiter" => {
+                    options.delimiter = match tokens.next() {
+                        Some("tab") => b'\t',
+                        Some(d) if d.len() == 1 && d.is_ascii() => d.as_bytes()[0],
+                        _ => {
+                            return Err(CrustyError::ValidationError(String::from(
+                                "--delimiter takes a single ASCII character or tab",
+                            )))
+                        }
+                    }
+                }
+                _ => rest.push(token),
+            }
+        }
+        Ok((options, rest))
+    }
+
+    /// A reader of a file with these options. Rows may have any number of fields, so rows
+    /// with the wrong number are reported like other rows that don't match the table.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - File to read.
+    fn reader(&self, file: File) -> csv::Reader<File> {
+        csv::ReaderBuilder::new()
+            .has_headers(self.header)
+            .delimiter(self.delimiter)
+            .flexible(true)
+            .from_reader(file)
+    }
+}
+
+/// What an import did with the rows of a file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub table: String,
+    /// Rows inserted into the table.
+    pub imported: usize,
+    /// Rows that did not match the table and were skipped.
+    pub rejected: usize,
+    /// Why the first of those rows were skipped.
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Imported {} rows into {}, rejected {}",
+            self.imported, self.table, self.rejected
+        )?;
+        for error in &self.errors {
+            write!(f, "\n  {}", error)?;
+        }
+        if self.rejected > self.errors.len() {
+            write!(f, "\n  and {} more", self.rejected - self.errors.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts the values of a row of a file to a row of a table, or says why they do not match
+/// the table's columns. The values are in the order of the columns, as text, and None is a
+/// NULL.
+///
+/// # Arguments
+///
+/// * `values` - Values to convert.
+/// * `table` - Table the row is for.
+pub fn values_to_tuple(values: Vec<Option<String>>, table: &Table) -> Result<Tuple, String> {
+    let columns = table.schema.size();
+    if values.len() != columns {
+        return Err(format!(
+            "has {} fields, table {} has {} columns",
+            values.len(),
+            table.name,
+            columns
+        ));
+    }
+    let mut tuple = Tuple::new(Vec::with_capacity(columns));
+    for (value, attr) in values.into_iter().zip(table.schema.attributes()) {
+        let value = match value {
+            Some(value) => value,
+            None if attr.nullable() => {
+                tuple.field_vals.push(Field::Null);
+                continue;
+            }
+            None => return Err(format!("no value for NOT NULL column {}", attr.name())),
+        };
+        match attr.dtype() {
+            DataType::Int => match value.parse::<i32>() {
+                Ok(value) => tuple.field_vals.push(Field::IntField(value)),
+                Err(_) => {
+                    return Err(format!(
+                        "{} is not an INT for column {}",
+                        value,
+                        attr.name()
+                    ))
+                }
+            },
+            DataType::String => tuple.field_vals.push(Field::StringField(value)),
+        }
+    }
+    Ok(tuple)
+}
+
+/// Converts a record of a CSV file to a row of a table, or says why it does not match the
+/// table's columns.
+///
+/// Empty values are NULLs, or empty strings in NOT NULL VARCHAR columns. INT values are not
+/// trimmed.
+///
+/// # Arguments
 ///
-/// Note: This function does not perform any verification on column typing.
+/// * `rec` - Record to convert.
+/// * `table` - Table the row is for.
+fn record_to_tuple(rec: &csv::StringRecord, table: &Table) -> Result<Tuple, String> {
+    let values = rec
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let keeps_empty = table
+                .schema
+                .get_attribute(i)
+                .is_some_and(|a| !a.nullable() && *a.dtype() == DataType::String);
+            if field.is_empty() && !keeps_empty {
+                None
+            } else {
+                Some(field.to_string())
+            }
+        })
+        .collect();
+    values_to_tuple(values, table)
+}
+
+/// Encodes the rows of a file for a table, handing them on in chunks of at most `chunk_rows`
+/// rows. Rows that don't match the table are counted in `rejects` instead.
+///
+/// # Arguments
+///
+/// * `rows` - Rows of the file. An error stops the import, a row that does not match the
+///   table is rejected.
+/// * `table` - Table the rows are for.
+/// * `chunk_rows` - Rows of a full chunk.
+/// * `rejects` - Where rejected rows are counted.
+/// * `write` - Takes each chunk.
+fn read_chunks<I, F>(
+    rows: I,
+    table: &Table,
+    chunk_rows: usize,
+    rejects: &mut ImportSummary,
+    mut write: F,
+) -> Result<(), CrustyError>
+where
+    I: Iterator<Item = Result<Result<Tuple, String>, CrustyError>>,
+    F: FnMut(Vec<Vec<u8>>) -> Result<(), CrustyError>,
+{
+    let mut chunk = Vec::with_capacity(chunk_rows);
+    for (i, row) in rows.enumerate() {
+        let row = row?.and_then(|tuple| {
+            tuple
+                .to_bytes_with_schema(&table.schema)
+                .map_err(|e| e.to_string())
+        });
+        match row {
+            Ok(bytes) => {
+                chunk.push(bytes);
+                if chunk.len() == chunk_rows {
+                    let full = std::mem::replace(&mut chunk, Vec::with_capacity(chunk_rows));
+                    write(full)?;
+                }
+            }
+            Err(e) => {
+                rejects.rejected += 1;
+                if rejects.errors.len() < REPORTED_REJECTIONS {
+                    rejects.errors.push(format!("row {}: {}", i + 1, e));
+                }
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        write(chunk)?;
+    }
+    Ok(())
+}
+
+/// Inserts the rows of a file into an existing table, and returns how many rows were
+/// imported and why the others were rejected. The importers of each format convert their
+/// files to rows and leave the rest to this function.
+///
+/// The rows are inserted a chunk at a time with `insert_values`, which packs them into pages
+/// together, in a started transaction that locks the pages it writes. It is committed at the
+/// end of the file or every `commit_rows` rows, so queries wait for the locks instead of
+/// seeing a partly imported file or chunk. If the import fails, the rows that were not
+/// committed are undone. Rows that don't match the table's columns are skipped, the import
+/// goes on with the next one.
+///
+/// With `parallel` the rows are read and encoded by a thread of their own, up to a few chunks
+/// ahead of the inserts, so reading overlaps with inserting chunks.
+///
+/// # Arguments
+///
+/// * `table` - Pointer to table to store the data in.
+/// * `rows` - Rows of the file. An error stops the import, a row that does not match the
+///   table is rejected.
+/// * `tid` - Transaction id for inserting the tuples, started and finished by the import.
+/// * `commit_rows` - Rows committed at once, None to commit the whole file at its end.
+/// * `parallel` - Whether the rows are read by a thread of their own.
+pub fn import_rows<I>(
+    table: &Table,
+    rows: I,
+    tid: TransactionId,
+    storage_manager: &StorageManager,
+    commit_rows: Option<usize>,
+    parallel: bool,
+) -> Result<ImportSummary, CrustyError>
+where
+    I: Iterator<Item = Result<Result<Tuple, String>, CrustyError>> + Send,
+{
+    //get storage container
+    let table_id_downcast = table.id as u16;
+    let container_id = table_id_downcast as ContainerId;
+    storage_manager
+        .create_container(
+            table_id_downcast,
+            Some(table.name.clone()),
+            ContainerType::Table,
+        )
+        .unwrap();
+    let mut summary = ImportSummary {
+        table: table.name.clone(),
+        ..ImportSummary::default()
+    };
+    let chunk_rows = commit_rows.unwrap_or(IMPORT_CHUNK_ROWS);
+    let mut imported = 0;
+    storage_manager.start_transaction(tid);
+    let res = (|| {
+        let mut write = |chunk: Vec<Vec<u8>>| -> Result<(), CrustyError> {
+            debug!(
+                "server::csv_utils inserting {} rows into container_id: {:?}",
+                chunk.len(),
+                &container_id
+            );
+            imported += DatabaseState::insert_values(storage_manager, table, chunk, tid)?.len();
+            if commit_rows.is_some() {
+                storage_manager.transaction_finished(tid);
+                storage_manager.start_transaction(tid);
+            }
+            Ok(())
+        };
+        if parallel {
+            let (sender, receiver) = mpsc::sync_channel(READ_AHEAD_CHUNKS);
+            let (written, read) = thread::scope(|scope| {
+                let reader = scope.spawn(move || {
+                    let mut rejects = ImportSummary::default();
+                    read_chunks(rows, table, chunk_rows, &mut rejects, |chunk| {
+                        sender
+                            .send(chunk)
+                            .map_err(|_| CrustyError::CrustyError(String::from("Import stopped")))
+                    })
+                    .map(|_| rejects)
+                });
+                let written = receiver.iter().try_for_each(&mut write);
+                // a failed insert stops the reader at its next chunk
+                drop(receiver);
+                (written, reader.join().expect("Import reader panicked"))
+            });
+            written?;
+            let rejects = read?;
+            summary.rejected = rejects.rejected;
+            summary.errors = rejects.errors;
+        } else {
+            read_chunks(rows, table, chunk_rows, &mut summary, &mut write)?;
+        }
+        storage_manager.check_transaction(tid)
+    })();
+    if let Err(e) = res {
+        storage_manager.abort_transaction(tid)?;
+        return Err(e);
+    }
+    storage_manager.transaction_finished(tid);
+    summary.imported = imported;
+    info!(
+        "Num records imported: {:?}, rejected: {:?}",
+        summary.imported, summary.rejected
+    );
+    Ok(summary)
+}
+
+/// Function to import csv data into an existing table within a database, see `import_rows`.
 ///
 /// # Arguments
 ///
 /// * `table` - Pointer to table to store the data in.
 /// * `path` - Path to the csv file.
 /// * `tid` - Transaction id for inserting the tuples.
+/// * `commit_rows` - Rows committed at once, None to commit the whole file at its end.
+/// * `options` - How the file is read.
 pub fn import_csv(
     table: &Table,
     path: String,
     tid: TransactionId,
     storage_manager: &StorageManager,
-) -> Result<(), CrustyError> {
+    commit_rows: Option<usize>,
+    options: &CsvOptions,
+) -> Result<ImportSummary, CrustyError> {
     debug!("server::csv_utils trying to open file, path: {:?}", path);
     let file = File::open(path)?;
     // Create csv reader.
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(file);
+    let rdr = options.reader(file);
+    let rows = rdr.into_records().map(|result| {
+        Ok(result
+            .map_err(|e| e.to_string())
+            .and_then(|rec| record_to_tuple(&rec, table)))
+    });
+    import_rows(
+        table,
+        rows,
+        tid,
+        storage_manager,
+        commit_rows,
+        options.parallel,
+    )
+}
 
-    //get storage container
-    let table_id_downcast = table.id as u16;
-    let container_id = table_id_downcast as ContainerId;
-    storage_manager.create_container(table_id_downcast).unwrap();
-    // Iterate through csv records.
-    let mut inserted_records = 0;
-    for result in rdr.records() {
-        #[allow(clippy::single_match)]
-        match result {
+/// What the values of a CSV column look like, from the most to the least specific.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InferredType {
+    Int,
+    Float,
+    /// An ISO 8601 date, `YYYY-MM-DD`.
+    Date,
+    String,
+}
+
+impl fmt::Display for InferredType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            InferredType::Int => "INT",
+            InferredType::Float => "FLOAT",
+            InferredType::Date => "DATE",
+            InferredType::String => "STRING",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl InferredType {
+    /// The most specific type of a non-empty value.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Value of a CSV field, without surrounding whitespace.
+    pub fn of(value: &str) -> Self {
+        if value.parse::<i32>().is_ok() {
+            InferredType::Int
+        } else if is_float(value) {
+            InferredType::Float
+        } else if is_iso_date(value) {
+            InferredType::Date
+        } else {
+            InferredType::String
+        }
+    }
+
+    /// The most specific type both types' values are. Ints are floats, anything is a string.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Type to combine with.
+    pub fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (InferredType::Int, InferredType::Float) | (InferredType::Float, InferredType::Int) => {
+                InferredType::Float
+            }
+            _ => InferredType::String,
+        }
+    }
+}
+
+/// A decimal number that is not an integer of 32 bits, like `1.5`, `-2e10` or `4000000000`.
+fn is_float(value: &str) -> bool {
+    // Rust also parses inf and NaN
+    value.chars().any(|c| c.is_ascii_digit())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'))
+        && value.parse::<f64>().is_ok()
+}
+
+/// A valid date written as `YYYY-MM-DD`.
+fn is_iso_date(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+    if parts.len() != 3
+        || [4, 2, 2] != [parts[0].len(), parts[1].len(), parts[2].len()]
+        || !parts.iter().all(|p| p.chars().all(|c| c.is_ascii_digit()))
+    {
+        return false;
+    }
+    let (year, month, day): (u32, u32, u32) = (
+        parts[0].parse().unwrap(),
+        parts[1].parse().unwrap(),
+        parts[2].parse().unwrap(),
+    );
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+/// What a CSV column holds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnProfile {
+    /// Type of the non-empty values, None if all were empty.
+    pub inferred: Option<InferredType>,
+    /// Values that are empty, which will be NULLs once columns can hold them.
+    pub empty: usize,
+}
+
+/// The result of a dry run of `import_csv`, reported by `\i --analyze`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvAnalysis {
+    pub table: String,
+    /// Names and types of the table's columns.
+    pub columns: Vec<(String, DataType)>,
+    pub rows: usize,
+    /// One profile for each column of the widest row.
+    pub profiles: Vec<ColumnProfile>,
+    /// Rows that would not load.
+    pub violations: usize,
+    /// Why the first of those rows would not load.
+    pub samples: Vec<String>,
+}
+
+impl fmt::Display for CsvAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} rows for table {}", self.rows, self.table)?;
+        for (i, profile) in self.profiles.iter().enumerate() {
+            let target = match self.columns.get(i) {
+                Some((name, DataType::Int)) => format!("{} INT", name),
+                Some((name, DataType::String)) => format!("{} VARCHAR", name),
+                None => String::from("no column"),
+            };
+            let inferred = profile
+                .inferred
+                .map(|t| t.to_string())
+                .unwrap_or_else(|| String::from("EMPTY"));
+            writeln!(
+                f,
+                "column {} ({}): {}, {} empty",
+                i + 1,
+                target,
+                inferred,
+                profile.empty
+            )?;
+        }
+        if self.violations == 0 {
+            write!(f, "All rows would load")
+        } else {
+            write!(f, "{} rows would not load, for example:", self.violations)?;
+            for sample in &self.samples {
+                write!(f, "\n  {}", sample)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Reads a CSV file without loading it, and reports the type of each of its columns and the
+/// rows that would not load into a table.
+///
+/// Floats and dates are recognized, although there are no columns for them yet, and empty
+/// values counted, so a schema for the file can be chosen before importing it.
+///
+/// # Arguments
+///
+/// * `table` - Table the file would be imported into.
+/// * `path` - Path to the csv file.
+/// * `options` - How the file is read.
+pub fn analyze_csv(
+    table: &Table,
+    path: &str,
+    options: &CsvOptions,
+) -> Result<CsvAnalysis, CrustyError> {
+    let file = File::open(path)?;
+    let mut rdr = options.reader(file);
+    let columns: Vec<(String, DataType)> = table
+        .schema
+        .attributes()
+        .map(|a| (a.name().to_string(), a.dtype().clone()))
+        .collect();
+    let mut analysis = CsvAnalysis {
+        table: table.name.clone(),
+        columns,
+        rows: 0,
+        profiles: Vec::new(),
+        violations: 0,
+        samples: Vec::new(),
+    };
+    for (i, result) in rdr.records().enumerate() {
+        analysis.rows += 1;
+        let problem = match result {
             Ok(rec) => {
-                // Build tuple and infer types from schema.
-                let mut tuple = Tuple::new(Vec::new());
-                for (field, attr) in rec.iter().zip(table.schema.attributes()) {
-                    // TODO: Type mismatch between attributes and record data>
-                    match &attr.dtype() {
-                        DataType::Int => {
-                            let value: i32 = field.parse::<i32>().unwrap();
-                            tuple.field_vals.push(Field::IntField(value));
-                        }
-                        DataType::String => {
-                            let value: String = field.to_string().clone();
-                            tuple.field_vals.push(Field::StringField(value));
-                        }
+                if analysis.profiles.len() < rec.len() {
+                    analysis
+                        .profiles
+                        .resize(rec.len(), ColumnProfile::default());
+                }
+                for (j, field) in rec.iter().enumerate() {
+                    let value = field.trim();
+                    let profile = &mut analysis.profiles[j];
+                    if value.is_empty() {
+                        profile.empty += 1;
+                    } else {
+                        let found = InferredType::of(value);
+                        profile.inferred = Some(profile.inferred.map_or(found, |t| t.merge(found)));
                     }
                 }
-                //TODO: How should individual row insertion errors be handled?
-                debug!("server::csv_utils about to insert tuple into container_id: {:?}", &container_id);
-                storage_manager.insert_value(container_id, tuple.get_bytes(), tid);
-                inserted_records += 1;
+                record_to_tuple(&rec, table).err()
             }
-            _ => {
-                // FIXME: get error from csv reader
-                error!("Could not read row from CSV");
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(problem) = problem {
+            analysis.violations += 1;
+            if analysis.samples.len() < SAMPLE_VIOLATIONS {
+                analysis.samples.push(format!("row {}: {}", i + 1, problem));
             }
         }
     }
-    info!("Num records imported: {:?}", inserted_records);
-    Ok(())
+    Ok(analysis)
+}
+
+/// Encodes the rows of an ingestion frame: comma separated fields, rows separated by `;`.
+///
+/// Unlike `import_csv`, the whole frame is rejected if any row does not match the schema, so a
+/// client can resend the frame after fixing it.
+///
+/// # Arguments
+///
+/// * `frame` - Rows to encode.
+/// * `table` - Table the rows are for.
+pub fn encode_frame(frame: &str, table: &Table) -> Result<Vec<Vec<u8>>, CrustyError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .terminator(csv::Terminator::Any(b';'))
+        .from_reader(frame.as_bytes());
+    let attributes: Vec<_> = table.schema.attributes().collect();
+    let mut rows = Vec::new();
+    for (i, result) in rdr.records().enumerate() {
+        let rec = result.map_err(|e| {
+            CrustyError::ValidationError(format!("Row {} of the frame: {}", i + 1, e))
+        })?;
+        if rec.len() != attributes.len() {
+            return Err(CrustyError::ValidationError(format!(
+                "Row {} of the frame has {} fields, table {} has {} columns",
+                i + 1,
+                rec.len(),
+                table.name,
+                attributes.len()
+            )));
+        }
+        let mut tuple = Tuple::new(Vec::with_capacity(rec.len()));
+        for (field, attr) in rec.iter().zip(attributes.iter()) {
+            if field.is_empty() && attr.nullable() {
+                tuple.field_vals.push(Field::Null);
+                continue;
+            }
+            match attr.dtype() {
+                DataType::Int => {
+                    let value = field.trim().parse::<i32>().map_err(|_| {
+                        CrustyError::ValidationError(format!(
+                            "Row {} of the frame: {} is not an integer",
+                            i + 1,
+                            field
+                        ))
+                    })?;
+                    tuple.field_vals.push(Field::IntField(value));
+                }
+                DataType::String => tuple.field_vals.push(Field::StringField(field.to_string())),
+            }
+        }
+        rows.push(tuple.to_bytes_with_schema(&table.schema)?);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::ids::Permissions;
+    use common::storage_trait::ValueRefIterator;
+    use common::testutil::{gen_random_dir, get_int_table_schema};
+    use common::{Attribute, TableSchema};
+    use std::fs;
+
+    #[test]
+    fn test_import_utf8() {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("names.csv");
+        fs::write(&path, "1,café\n2,o'hare\n3,\"日本語, 中文\"\n").unwrap();
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("id"), DataType::Int),
+            Attribute::new(String::from("name"), DataType::String),
+        ]);
+        let table = Table::new(String::from("names"), schema);
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        let options = CsvOptions::default();
+        import_csv(
+            &table,
+            path.to_string_lossy().to_string(),
+            tid,
+            &sm,
+            None,
+            &options,
+        )
+        .unwrap();
+
+        let mut names: Vec<String> = sm
+            .get_iterator(table.id as ContainerId, tid, Permissions::ReadOnly)
+            .collect_values()
+            .unwrap()
+            .iter()
+            .map(|bytes| {
+                let tuple = Tuple::from_bytes_with_schema(bytes, &table.schema).unwrap();
+                tuple.get_string("name", &table.schema).unwrap().to_string()
+            })
+            .collect();
+        names.sort();
+        assert_eq!(vec!["café", "o'hare", "日本語, 中文"], names);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_nulls() {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("nulls.csv");
+        fs::write(&path, "1,a,\n,,x\n").unwrap();
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("id"), DataType::Int),
+            Attribute::new(String::from("name"), DataType::String),
+            Attribute::new_not_null(String::from("note"), DataType::String),
+        ]);
+        let table = Table::new(String::from("nulls"), schema);
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        let options = CsvOptions::default();
+        import_csv(
+            &table,
+            path.to_string_lossy().to_string(),
+            tid,
+            &sm,
+            None,
+            &options,
+        )
+        .unwrap();
+
+        let mut rows: Vec<Vec<Field>> = sm
+            .get_iterator(table.id as ContainerId, tid, Permissions::ReadOnly)
+            .collect_values()
+            .unwrap()
+            .iter()
+            .map(|bytes| {
+                Tuple::from_bytes_with_schema(bytes, &table.schema)
+                    .unwrap()
+                    .field_vals
+            })
+            .collect();
+        rows.sort();
+        // empty cells are NULL, or empty strings in NOT NULL columns
+        let s = |v: &str| Field::StringField(v.to_string());
+        assert_eq!(
+            vec![
+                vec![Field::Null, Field::Null, s("x")],
+                vec![Field::IntField(1), s("a"), s("")],
+            ],
+            rows
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_commit_rows() {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chunks.csv");
+        fs::write(&path, "1\n2\n3\n4\n5\n").unwrap();
+        let table = Table::new(String::from("chunks"), get_int_table_schema(1));
+        let path = path.to_string_lossy().to_string();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        let options = CsvOptions::default();
+        let summary = import_csv(&table, path.clone(), tid, &sm, None, &options).unwrap();
+        assert_eq!(5, summary.imported);
+        // the last chunk is committed at the end of the file
+        let summary = import_csv(&table, path, tid, &sm, Some(2), &options).unwrap();
+        assert_eq!(5, summary.imported);
+        let count = sm
+            .get_iterator(table.id as ContainerId, tid, Permissions::ReadOnly)
+            .collect_values()
+            .unwrap()
+            .len();
+        assert_eq!(10, count);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_failure() {
+        let table = Table::new(String::from("failure"), get_int_table_schema(1));
+        let sm = StorageManager::new_test_sm();
+        let rows = |n: i32| {
+            (0..n)
+                .map(|i| Ok(Ok(Tuple::new(vec![Field::IntField(i)]))))
+                .chain(std::iter::once(Err(CrustyError::IOError(String::from(
+                    "disk gone",
+                )))))
+        };
+        let count = || {
+            // a started reader waits for locks the import did not release
+            let reader = TransactionId::new();
+            sm.start_transaction(reader);
+            let count = sm
+                .get_iterator(table.id as ContainerId, reader, Permissions::ReadOnly)
+                .collect_values()
+                .unwrap()
+                .len();
+            sm.check_transaction(reader).unwrap();
+            sm.transaction_finished(reader);
+            count
+        };
+        let tid = TransactionId::new();
+        assert!(import_rows(&table, rows(5), tid, &sm, None, false).is_err());
+        assert_eq!(0, count());
+        // the chunks committed before the failure stay
+        let tid = TransactionId::new();
+        assert!(import_rows(&table, rows(5), tid, &sm, Some(2), false).is_err());
+        assert_eq!(4, count());
+    }
+
+    #[test]
+    fn test_import_rejected_rows() {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rejects.csv");
+        fs::write(&path, "id;name\n1;a\nx;b\n3\n;c\n5;e\n").unwrap();
+        let schema = TableSchema::new(vec![
+            Attribute::new_not_null(String::from("id"), DataType::Int),
+            Attribute::new(String::from("name"), DataType::String),
+        ]);
+        let table = Table::new(String::from("rejects"), schema);
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        let (options, args) = CsvOptions::parse_args("--header --delimiter ; rejects").unwrap();
+        assert_eq!(vec!["rejects"], args);
+        let path = path.to_string_lossy().to_string();
+        let summary = import_csv(&table, path, tid, &sm, None, &options).unwrap();
+        assert_eq!(
+            "Imported 2 rows into rejects, rejected 3\n  \
+             row 2: x is not an INT for column id\n  \
+             row 3: has 1 fields, table rejects has 2 columns\n  \
+             row 4: no value for NOT NULL column id",
+            summary.to_string()
+        );
+        let count = sm
+            .get_iterator(table.id as ContainerId, tid, Permissions::ReadOnly)
+            .collect_values()
+            .unwrap()
+            .len();
+        assert_eq!(2, count);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_parallel() {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("parallel.csv");
+        let mut data: String = (0..10_000).map(|i| format!("{}\n", i)).collect();
+        data.push_str("x\n");
+        fs::write(&path, data).unwrap();
+        let schema = TableSchema::new(vec![Attribute::new(String::from("a"), DataType::Int)]);
+        let table = Table::new(String::from("parallel"), schema);
+        let path = path.to_string_lossy().to_string();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        let (options, _) = CsvOptions::parse_args("--parallel").unwrap();
+        for commit_rows in &[None, Some(3000)] {
+            let summary = import_csv(&table, path.clone(), tid, &sm, *commit_rows, &options);
+            let summary = summary.unwrap();
+            assert_eq!(10_000, summary.imported);
+            assert_eq!(
+                vec!["row 10001: x is not an INT for column a"],
+                summary.errors
+            );
+        }
+        let mut values: Vec<i32> = sm
+            .get_iterator(table.id as ContainerId, tid, Permissions::ReadOnly)
+            .collect_values()
+            .unwrap()
+            .iter()
+            .map(|bytes| {
+                match Tuple::from_bytes_with_schema(bytes, &table.schema)
+                    .unwrap()
+                    .field_vals[0]
+                {
+                    Field::IntField(i) => i,
+                    _ => unreachable!(),
+                }
+            })
+            .collect();
+        values.sort_unstable();
+        let expected: Vec<i32> = (0..10_000).flat_map(|i| vec![i, i]).collect();
+        assert_eq!(expected, values);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let (options, args) = CsvOptions::parse_args("data.csv t").unwrap();
+        assert_eq!(CsvOptions::default(), options);
+        assert_eq!(vec!["data.csv", "t"], args);
+        let (options, _) = CsvOptions::parse_args("--delimiter tab data.tsv t").unwrap();
+        assert_eq!(b'\t', options.delimiter);
+        assert!(!options.header);
+        assert!(CsvOptions::parse_args("--delimiter data.csv t").is_err());
+        assert!(CsvOptions::parse_args("data.csv t --delimiter").is_err());
+    }
+
+    #[test]
+    fn test_inferred_type() {
+        use InferredType::*;
+        let of = |values: &[&str]| {
+            values
+                .iter()
+                .map(|v| InferredType::of(v))
+                .fold(None, |t: Option<InferredType>, v| {
+                    Some(t.map_or(v, |t| t.merge(v)))
+                })
+                .unwrap()
+        };
+        assert_eq!(Int, of(&["1", "-2", "+3"]));
+        assert_eq!(Float, of(&["1", "2.5", "-1e3", "4000000000"]));
+        assert_eq!(Date, of(&["2021-03-08", "2020-02-29"]));
+        assert_eq!(String, of(&["2021-02-29"]));
+        assert_eq!(String, of(&["2021-3-8"]));
+        assert_eq!(String, of(&["inf", "NaN"]));
+        assert_eq!(String, of(&["1", "2021-03-08"]));
+        assert_eq!(String, of(&["1.5", "x"]));
+    }
+
+    #[test]
+    fn test_analyze_csv() {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("readings.csv");
+        fs::write(
+            &path,
+            "1,2021-03-08,1.5,a\n2,2021-03-09,,b\nx,2021-03-10,2,c\n4,2021-03-11,3\n",
+        )
+        .unwrap();
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("id"), DataType::Int),
+            Attribute::new(String::from("day"), DataType::String),
+            Attribute::new(String::from("reading"), DataType::String),
+            Attribute::new(String::from("note"), DataType::String),
+        ]);
+        let table = Table::new(String::from("readings"), schema);
+        let options = CsvOptions::default();
+        let analysis = analyze_csv(&table, &path.to_string_lossy(), &options).unwrap();
+        assert_eq!(
+            "4 rows for table readings\n\
+             column 1 (id INT): STRING, 0 empty\n\
+             column 2 (day VARCHAR): DATE, 0 empty\n\
+             column 3 (reading VARCHAR): FLOAT, 1 empty\n\
+             column 4 (note VARCHAR): STRING, 0 empty\n\
+             2 rows would not load, for example:\n  \
+             row 3: x is not an INT for column id\n  \
+             row 4: has 3 fields, table readings has 4 columns",
+            analysis.to_string()
+        );
+        // nothing was loaded
+        assert!(analyze_csv(&table, "missing.csv", &options).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_encode_frame() {
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("id"), DataType::Int),
+            Attribute::new(String::from("name"), DataType::String),
+        ]);
+        let table = Table::new(String::from("names"), schema);
+        let rows = encode_frame("1,a;2,\"b; c\";3,d", &table).unwrap();
+        let names: Vec<String> = rows
+            .iter()
+            .map(|bytes| {
+                let tuple = Tuple::from_bytes_with_schema(bytes, &table.schema).unwrap();
+                tuple.get_string("name", &table.schema).unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(vec!["a", "b; c", "d"], names);
+        assert!(encode_frame("1,a;x,b", &table).is_err());
+        assert!(encode_frame("1,a;2", &table).is_err());
+    }
 }
@@ -1,21 +1,53 @@
 use std::fs;
-use std::io::prelude::*;
-use std::io::BufReader;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 
+use crate::csv_utils;
 use crate::StorageManager;
+use common::catalog;
 use common::catalog::Catalog;
 use common::database::Database;
-use common::ids::ContainerId;
-use common::storage_trait::StorageTrait;
-use common::table::Table;
-use common::{get_attr, Attribute, CrustyError, QueryResult, TableSchema};
-use sqlparser::ast::ColumnDef;
+use common::ids::{ContainerId, Permissions, TransactionId, ValueId};
+use common::row_format::RowFormat;
+use common::statistics::{Statistics, TableStats};
+use common::storage_trait::{ContainerType, ContainerUsage, StorageTrait};
+use common::table::{IndexInfo, Table};
+use common::{
+    get_attr, get_max_len, Attribute, CrustyError, DataType, Field, QueryResult, TableSchema, Tuple,
+};
+use sqlparser::ast::{ColumnDef, ColumnOption, Expr, Ident, UnaryOperator, Value};
+
+/// Space used by a table or a whole database, as reported by `\du`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Size of the files of the table and record containers.
+    pub disk_bytes: u64,
+    /// Bytes taken by the stored rows.
+    pub live_bytes: u64,
+    /// Bytes of those files that do not hold rows. Mostly deleted rows if it keeps growing.
+    pub free_bytes: u64,
+    /// Size of the files of the index containers.
+    pub index_bytes: u64,
+}
+
+impl DiskUsage {
+    /// Adds the space used by a container.
+    fn add(&mut self, container_type: ContainerType, usage: &ContainerUsage) {
+        match container_type {
+            ContainerType::Index => self.index_bytes += usage.disk_bytes,
+            _ => {
+                self.disk_bytes += usage.disk_bytes;
+                self.live_bytes += usage.live_bytes;
+                self.free_bytes += usage.free_bytes();
+            }
+        }
+    }
+}
 
 #[derive(Serialize)]
 pub struct DatabaseState {
@@ -31,40 +63,43 @@ pub struct DatabaseState {
     pub active_client_connections: RwLock<HashSet<u64>>,
 
     pub table_container_map: Arc<RwLock<HashMap<String, ContainerId>>>,
+
+    /// Table and column statistics used to annotate EXPLAIN output.
+    #[serde(skip_serializing)]
+    pub statistics: RwLock<Statistics>,
+
+    /// File the catalog is written to whenever it changes, None to keep it in memory only.
+    #[serde(skip_serializing)]
+    catalog_file: Option<PathBuf>,
+
+    /// Number of changes of the tables, indexes and statistics, so plans made before a change
+    /// aren't reused after it.
+    #[serde(skip_serializing)]
+    catalog_version: AtomicU64,
 }
 
 impl DatabaseState {
-    // initializing within here
+    /// Creates the state of a database whose catalog was persisted to a file, and keeps
+    /// persisting the catalog there.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - File the catalog was persisted to.
+    /// * `storage_path` - Where databases keep their table data, see `storage_dir`.
     pub fn new_from_path(path: PathBuf, storage_path: String) -> Result<Self, CrustyError> {
         debug!("Creating new DBState from path {:?}", path);
-        // TODO: Remove magic numbers to parse out db json file name.
-        let cand = path.display().to_string();
-        // FIXME: that 11 hard-coded there....
-        let cand_name = &cand[11..cand.len() - 5];
-        debug!("cand: {} cand_name {}", cand, cand_name);
-
-        match fs::File::open(cand.clone()) {
-            Ok(res) => {
-                let db_name = cand_name.to_string();
-                let db_id = DatabaseState::get_database_id(db_name.clone());
-
-                let storage_manager = Arc::new(StorageManager::new(storage_path));
-
-                let database =
-                    DatabaseState::load_database_from_file(res, &storage_manager).unwrap();
-
-                let db_state = DatabaseState {
-                    id: db_id,
-                    name: db_name,
-                    database,
-                    storage_manager,
-                    active_client_connections: RwLock::new(HashSet::new()),
-                    table_container_map: Arc::new(RwLock::new(HashMap::new())),
-                };
-                Ok(db_state)
-            }
-            _ => return Err(CrustyError::IOError(String::from("Failed to open db file"))),
+        let json = fs::read_to_string(&path)?;
+        let mut dbs = catalog::import_catalog_json(&json)?;
+        if dbs.len() != 1 {
+            return Err(CrustyError::ValidationError(format!(
+                "{:?} should hold the catalog of a single database, not {}",
+                path,
+                dbs.len()
+            )));
         }
+        let mut db_state = Self::new_from_database(dbs.pop().unwrap(), storage_path)?;
+        db_state.catalog_file = Some(path);
+        Ok(db_state)
     }
 
     pub fn get_database_id(db_name: String) -> u64 {
@@ -74,6 +109,26 @@ impl DatabaseState {
         db_id
     }
 
+    /// Returns the directory the storage manager of a database keeps its table data in, a
+    /// directory of its own so the containers of two databases never share files.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage_path` - Where databases keep their table data.
+    /// * `db_name` - Name of the database.
+    pub fn storage_dir(storage_path: &str, db_name: &str) -> String {
+        PathBuf::from(storage_path)
+            .join(db_name)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Creates the state for a new database without tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `db_name` - Name of the database.
+    /// * `storage_path` - Where databases keep their table data, see `storage_dir`.
     pub fn new_from_name(db_name: &str, storage_path: String) -> Result<Self, CrustyError> {
         let db_name: String = String::from(db_name);
         let db_id = DatabaseState::get_database_id(db_name.clone());
@@ -83,7 +138,10 @@ impl DatabaseState {
         );
         let database = Database::new(db_name.to_string());
 
-        let storage_manager = Arc::new(StorageManager::new(storage_path));
+        let storage_manager = Arc::new(StorageManager::new(Self::storage_dir(
+            &storage_path,
+            &db_name,
+        )));
 
         let db_state = DatabaseState {
             id: db_id,
@@ -92,10 +150,86 @@ impl DatabaseState {
             storage_manager,
             active_client_connections: RwLock::new(HashSet::new()),
             table_container_map: Arc::new(RwLock::new(HashMap::new())),
+            statistics: RwLock::new(Statistics::new()),
+            catalog_file: None,
+            catalog_version: AtomicU64::new(0),
         };
         Ok(db_state)
     }
 
+    /// Creates the state for an existing database, e.g. one imported from a catalog document.
+    ///
+    /// # Arguments
+    ///
+    /// * `database` - Database with its tables.
+    /// * `storage_path` - Where databases keep their table data, see `storage_dir`.
+    pub fn new_from_database(database: Database, storage_path: String) -> Result<Self, CrustyError> {
+        let db_id = DatabaseState::get_database_id(database.name.clone());
+        let storage_manager = Arc::new(StorageManager::new(Self::storage_dir(
+            &storage_path,
+            &database.name,
+        )));
+        let mut table_container_map = HashMap::new();
+        {
+            let tables = database.tables.read().unwrap();
+            for table_ptr in tables.values() {
+                let table = table_ptr.read().unwrap();
+                let table_id_downcast = table.id as ContainerId;
+                storage_manager.create_container(table_id_downcast, Some(table.name.clone()), ContainerType::Table)?;
+                Self::create_index_containers(&table, storage_manager.as_ref())?;
+                table_container_map.insert(table.name.clone(), table_id_downcast);
+            }
+        }
+        Ok(DatabaseState {
+            id: db_id,
+            name: database.name.clone(),
+            database,
+            storage_manager,
+            active_client_connections: RwLock::new(HashSet::new()),
+            table_container_map: Arc::new(RwLock::new(table_container_map)),
+            statistics: RwLock::new(Statistics::new()),
+            catalog_file: None,
+            catalog_version: AtomicU64::new(0),
+        })
+    }
+
+    /// Writes the catalog to a file right away, and again whenever it changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - File to write the catalog to.
+    pub fn persist_catalog_to(mut self, path: PathBuf) -> Result<Self, CrustyError> {
+        self.catalog_file = Some(path);
+        self.persist_catalog()?;
+        Ok(self)
+    }
+
+    /// Returns the number of changes of the tables, indexes and statistics so far.
+    pub fn catalog_version(&self) -> u64 {
+        self.catalog_version.load(Ordering::SeqCst)
+    }
+
+    /// Counts a change of the tables, indexes or statistics.
+    fn bump_catalog_version(&self) {
+        self.catalog_version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Counts a change of the catalog and writes it to its file, if it has one. The file is
+    /// replaced in a single rename, so a crash leaves either the old catalog or the new one.
+    fn persist_catalog(&self) -> Result<(), CrustyError> {
+        self.bump_catalog_version();
+        let path = match &self.catalog_file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let json = catalog::export_catalog_json(&[&self.database])?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, path)?;
+        debug!("Persisted the catalog of {} to {:?}", self.name, path);
+        Ok(())
+    }
+
     pub fn register_new_client_connection(&self, client_id: u64) {
         debug!(
             "Registering new client connection: {:?} to database: {:?}",
@@ -107,25 +241,13 @@ impl DatabaseState {
             .insert(client_id);
     }
 
-    pub fn close_client_connection(&self, client_id: u64, metadata_path: String) {
+    pub fn close_client_connection(&self, client_id: u64) {
         info!("Closing client connection: {:?}...", &client_id);
-        // Remove client from this db
+        // Remove client from this db. The catalog is persisted as it changes, not here.
         self.active_client_connections
             .write()
             .unwrap()
             .remove(&client_id);
-        // Check if that was the last client connected to this DB
-        if self.active_client_connections.read().unwrap().is_empty() {
-            // Construct path where db will be persisted
-            let mut persist_path = metadata_path.clone();
-            persist_path.push_str(&self.name);
-            persist_path.push_str(".json");
-            // Serialize DB into a string and write it to the path
-            if let Ok(s) = serde_json::to_string(&self) {
-                info!("Persisting db on: {:?}", &metadata_path);
-                fs::write(&persist_path, s).expect("Failed to write out db json");
-            }
-        }
         info!("Closing client connection: {:?}...DONE", &client_id);
     }
 
@@ -135,8 +257,17 @@ impl DatabaseState {
             let tables = self.database.get_tables();
             let tables_ref = tables.read().unwrap();
             for table in tables_ref.values() {
-                let name = table.read().unwrap().name.clone();
-                table_names.push(name);
+                let table = table.read().unwrap();
+                let indexes: Vec<String> = table
+                    .indexes
+                    .iter()
+                    .map(|i| format!("{} on {}", i.name, i.column))
+                    .collect();
+                if indexes.is_empty() {
+                    table_names.push(table.name.clone());
+                } else {
+                    table_names.push(format!("{} (indexes: {})", table.name, indexes.join(", ")));
+                }
             }
         }
         let table_names = table_names.join("\n");
@@ -147,35 +278,6 @@ impl DatabaseState {
         }
     }
 
-    /// Load in database.
-    ///
-    /// # Arguments
-    ///
-    /// * `db` - Name of database to load in.
-    /// * `id` - Thread id to get the lock.
-    pub fn load_database_from_file(
-        file: fs::File,
-        storage_manager: &StorageManager,
-    ) -> Result<Database, CrustyError> {
-        debug!("Loading DB from file {:?}", file);
-        let mut buf_reader = BufReader::new(file);
-        let mut contents = String::new();
-        buf_reader.read_to_string(&mut contents)?;
-        let db_content_str: &str = &contents;
-        let db_cand: Database = serde_json::from_str(db_content_str).unwrap();
-        {
-            let mut tables_ref = db_cand.tables.write().unwrap();
-            for table_ref in tables_ref.values_mut() {
-                let table = table_ref.read().unwrap();
-
-                debug!("Loading table: {:?}", table.name.clone());
-                let table_id_downcast: u16 = table.id as u16;
-                storage_manager.create_container(table_id_downcast).unwrap();
-            }
-        }
-        Ok(db_cand)
-    }
-
     /// Creates a new table.
     ///
     /// # Arguments
@@ -196,21 +298,1435 @@ impl DatabaseState {
             )));
         }
 
-        let mut attributes: Vec<Attribute> = Vec::new();
-        for col in columns {
-            let attr = Attribute {
-                name: col.name.clone(),
-                dtype: get_attr(&col.data_type)?,
-            };
-            attributes.push(attr);
-        }
+        let attributes = columns
+            .iter()
+            .map(Self::column_attribute)
+            .collect::<Result<Vec<_>, _>>()?;
         let schema = TableSchema::new(attributes);
         debug!("Creating table with schema: {:?}", schema);
 
         let table = Table::new(table_name.to_string(), schema);
         let table_id_downcast = table.id as u16;
-        &self.storage_manager.create_container(table_id_downcast);
+        self.storage_manager
+            .create_container(table_id_downcast, Some(table.name.clone()), ContainerType::Table)?;
         tables_ref.insert(table_id, Arc::new(RwLock::new(table)));
+        drop(tables_ref);
+        self.persist_catalog()?;
         Ok(QueryResult::new(&format!("Table {} created", table_name)))
     }
+
+    /// Converts a column definition of a CREATE or ALTER TABLE to an attribute.
+    fn column_attribute(col: &ColumnDef) -> Result<Attribute, CrustyError> {
+        // primary keys can't be NULL either
+        let not_null = col.options.iter().any(|o| {
+            matches!(
+                o.option,
+                ColumnOption::NotNull | ColumnOption::Unique { is_primary: true }
+            )
+        });
+        Ok(Attribute {
+            name: col.name.clone(),
+            dtype: get_attr(&col.data_type)?,
+            nullable: !not_null,
+            max_len: get_max_len(&col.data_type),
+        })
+    }
+
+    /// Adds a column to the end of a table's schema.
+    ///
+    /// The stored rows are rewritten right away with the new column NULL, so scans only ever
+    /// see rows of the current schema. A NOT NULL column can therefore only be added to an
+    /// empty table. Like `rewrite_table`, it should not run alongside writes to the table.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table.
+    /// * `column` - Definition of the new column.
+    pub fn add_column(
+        &self,
+        table_name: &str,
+        column: &ColumnDef,
+    ) -> Result<QueryResult, CrustyError> {
+        let table_ptr = self.altered_table(table_name)?;
+        let mut table = table_ptr.write().unwrap();
+        if table.schema.contains(&column.name) {
+            return Err(CrustyError::ValidationError(format!(
+                "Table {} already has a column {}",
+                table_name, column.name
+            )));
+        }
+        let attr = Self::column_attribute(column)?;
+        let container_id = table.id as ContainerId;
+        let tid = TransactionId::new();
+        if !attr.nullable() {
+            let mut values =
+                self.storage_manager
+                    .get_iterator(container_id, tid, Permissions::ReadOnly);
+            // a page that can't be read doesn't make the table empty
+            if values.next().transpose()?.is_some() {
+                return Err(CrustyError::ValidationError(format!(
+                    "Column {} is NOT NULL and {} is not empty",
+                    column.name, table_name
+                )));
+            }
+        }
+        let mut attributes: Vec<Attribute> = table.schema.attributes().cloned().collect();
+        attributes.push(attr);
+        let schema = TableSchema::new(attributes);
+        let rows = self.rewrite_rows(&table, &schema, |mut tuple| {
+            tuple.field_vals.push(Field::Null);
+            tuple
+        })?;
+        table.schema = schema;
+        self.rebuild_indexes(&table)?;
+        drop(table);
+        self.persist_catalog()?;
+        Ok(QueryResult::new(&format!(
+            "Column {} added to {}, {} rows rewritten",
+            column.name, table_name, rows
+        )))
+    }
+
+    /// Removes a column from a table's schema, and its values from the stored rows and the
+    /// statistics. Indexes on the column are dropped. Like `rewrite_table`, it should not run
+    /// alongside writes to the table.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table.
+    /// * `column_name` - Name of the column to drop.
+    pub fn drop_column(
+        &self,
+        table_name: &str,
+        column_name: &str,
+    ) -> Result<QueryResult, CrustyError> {
+        let table_ptr = self.altered_table(table_name)?;
+        let mut table = table_ptr.write().unwrap();
+        let index = match table.schema.get_field_index(column_name) {
+            Some(index) => *index,
+            None => {
+                return Err(CrustyError::ValidationError(format!(
+                    "Table {} has no column {}",
+                    table_name, column_name
+                )))
+            }
+        };
+        if table.schema.size() == 1 {
+            return Err(CrustyError::ValidationError(format!(
+                "Column {} is the only column of {}, drop the table instead",
+                column_name, table_name
+            )));
+        }
+        let attributes = table
+            .schema
+            .attributes()
+            .filter(|a| a.name() != column_name)
+            .cloned()
+            .collect();
+        let schema = TableSchema::new(attributes);
+        let rows = self.rewrite_rows(&table, &schema, |mut tuple| {
+            tuple.field_vals.remove(index);
+            tuple
+        })?;
+        table.schema = schema;
+        // indexes on the column go with it
+        let (dropped, kept) = table
+            .indexes
+            .drain(..)
+            .partition(|i| i.column == column_name);
+        table.indexes = kept;
+        for index in dropped {
+            self.storage_manager.remove_container(index.container_id)?;
+        }
+        self.rebuild_indexes(&table)?;
+        let mut statistics = self.statistics.write().unwrap();
+        if let Some(stats) = statistics.table(table_name) {
+            let mut stats = stats.clone();
+            stats.columns.remove(column_name);
+            statistics.set_table(table_name, stats);
+        }
+        drop(statistics);
+        drop(table);
+        self.persist_catalog()?;
+        Ok(QueryResult::new(&format!(
+            "Column {} dropped from {}, {} rows rewritten",
+            column_name, table_name, rows
+        )))
+    }
+
+    /// Gets a table whose schema is about to change.
+    fn altered_table(&self, table_name: &str) -> Result<Arc<RwLock<Table>>, CrustyError> {
+        let tables_ref = self.database.tables.read().unwrap();
+        match tables_ref.get(&Table::get_table_id(table_name)) {
+            None => Err(CrustyError::CrustyError(format!(
+                "Table {} does not exist",
+                table_name
+            ))),
+            Some(table_ptr) if table_ptr.read().unwrap().append_only => {
+                Err(Self::append_only_error(table_name, "altered"))
+            }
+            Some(table_ptr) => Ok(Arc::clone(table_ptr)),
+        }
+    }
+
+    /// Rewrites every row of a table in a new schema, and returns the number of rows. Nothing
+    /// is written if a converted row does not fit the new schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - Table whose rows are rewritten, still with the old schema.
+    /// * `schema` - New schema of the table.
+    /// * `convert` - Converts a row of the old schema to the new one.
+    fn rewrite_rows(
+        &self,
+        table: &Table,
+        schema: &TableSchema,
+        convert: impl Fn(Tuple) -> Tuple,
+    ) -> Result<usize, CrustyError> {
+        let container_id = table.id as ContainerId;
+        let tid = TransactionId::new();
+        let mut rows = Vec::new();
+        // the container is rewritten from the rows, none may be missing
+        for bytes in self
+            .storage_manager
+            .get_iterator(container_id, tid, Permissions::ReadOnly)
+        {
+            let tuple = convert(Tuple::from_bytes_with_schema(&bytes?, &table.schema)?);
+            rows.push(tuple.to_bytes_with_schema(schema)?);
+        }
+        let total = rows.len();
+        if total > 0 {
+            self.storage_manager.truncate_container(container_id)?;
+            self.storage_manager.insert_values(container_id, rows, tid);
+            self.storage_manager.reclaim_space(container_id)?;
+        }
+        self.storage_manager
+            .set_row_format(container_id, RowFormat::CURRENT)?;
+        Ok(total)
+    }
+
+    /// Removes every row of a table, keeping the table and its schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table to truncate.
+    pub fn truncate_table(&self, table_name: &str) -> Result<QueryResult, CrustyError> {
+        let tables_ref = self.database.tables.read().unwrap();
+        let table_id = Table::get_table_id(table_name);
+        let table_ptr = match tables_ref.get(&table_id) {
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Table {} does not exist",
+                    table_name
+                )))
+            }
+            Some(table_ptr) if table_ptr.read().unwrap().append_only => {
+                return Err(Self::append_only_error(table_name, "truncated"))
+            }
+            Some(table_ptr) => table_ptr,
+        };
+        // the table first, it fails if another transaction still writes to it
+        let table_id_downcast = table_id as u16;
+        self.storage_manager.truncate_container(table_id_downcast)?;
+        for index in &table_ptr.read().unwrap().indexes {
+            self.storage_manager
+                .truncate_container(index.container_id)?;
+        }
+        self.storage_manager.reclaim_space(table_id_downcast)?;
+        self.statistics.write().unwrap().set_table(table_name, TableStats::new(0));
+        self.bump_catalog_version();
+        Ok(QueryResult::new(&format!("Table {} truncated", table_name)))
+    }
+
+    /// Removes a table with its rows, its index containers and its statistics. The name can be
+    /// used for a new table right after.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table to drop.
+    pub fn drop_table(&self, table_name: &str) -> Result<QueryResult, CrustyError> {
+        let mut tables_ref = self.database.tables.write().unwrap();
+        let table_id = Table::get_table_id(table_name);
+        let table_ptr = match tables_ref.get(&table_id) {
+            Some(table_ptr) => table_ptr,
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Table {} does not exist",
+                    table_name
+                )))
+            }
+        };
+        for index in &table_ptr.read().unwrap().indexes {
+            self.storage_manager.remove_container(index.container_id)?;
+        }
+        self.storage_manager.remove_container(table_id as u16)?;
+        tables_ref.remove(&table_id);
+        drop(tables_ref);
+        self.table_container_map.write().unwrap().remove(table_name);
+        self.statistics.write().unwrap().remove_table(table_name);
+        self.persist_catalog()?;
+        Ok(QueryResult::new(&format!("Table {} dropped", table_name)))
+    }
+
+    /// Collects the statistics of a table from its rows, replacing the ones it had, and returns
+    /// the number of rows. The optimizer orders joins by them, and `EXPLAIN` shows them.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table.
+    pub fn analyze_table(&self, table_name: &str) -> Result<usize, CrustyError> {
+        let table_ptr = self
+            .database
+            .get_table_ptr(Table::get_table_id(table_name))
+            .map_err(|_| {
+                CrustyError::CrustyError(format!("Table {} does not exist", table_name))
+            })?;
+        let table = table_ptr.read().unwrap();
+        let tid = TransactionId::new();
+        // the rows are streamed, stopping at the first one that can't be read or decoded
+        let mut error = None;
+        let values =
+            self.storage_manager
+                .get_iterator(table.id as ContainerId, tid, Permissions::ReadOnly);
+        let rows = values.map_while(|bytes| {
+            match bytes.and_then(|bytes| Tuple::from_bytes_with_schema(&bytes, &table.schema)) {
+                Ok(tuple) => Some(tuple),
+                Err(e) => {
+                    error = Some(e);
+                    None
+                }
+            }
+        });
+        let stats = TableStats::collect(&table.schema, rows);
+        if let Some(e) = error {
+            return Err(e);
+        }
+        let cardinality = stats.cardinality;
+        self.statistics
+            .write()
+            .unwrap()
+            .set_table(table_name, stats);
+        self.bump_catalog_version();
+        Ok(cardinality)
+    }
+
+    /// Collects the statistics of a table, or of every table of the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table, None for all of them.
+    pub fn analyze(&self, table_name: Option<&str>) -> Result<QueryResult, CrustyError> {
+        if let Some(table_name) = table_name {
+            let rows = self.analyze_table(table_name)?;
+            return Ok(QueryResult::new(&format!(
+                "Table {} analyzed, {} rows",
+                table_name, rows
+            )));
+        }
+        let names: Vec<String> = {
+            let tables = self.database.tables.read().unwrap();
+            tables
+                .values()
+                .map(|t| t.read().unwrap().name.clone())
+                .collect()
+        };
+        for name in &names {
+            self.analyze_table(name)?;
+        }
+        Ok(QueryResult::new(&format!(
+            "{} tables analyzed",
+            names.len()
+        )))
+    }
+
+    /// Creates an index on a column of a table, and adds the rows already stored to it. From
+    /// then on rows are added to the index as they are inserted into the table.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_name` - Name of the index, unique within the database.
+    /// * `table_name` - Name of the table.
+    /// * `column_name` - Name of the indexed column.
+    pub fn create_index(
+        &self,
+        index_name: &str,
+        table_name: &str,
+        column_name: &str,
+    ) -> Result<QueryResult, CrustyError> {
+        // holding the tables keeps another index of the same name from being created meanwhile
+        let tables_ref = self.database.tables.write().unwrap();
+        if Self::find_index(&tables_ref, index_name).is_some() {
+            return Err(CrustyError::ValidationError(format!(
+                "Index {} already exists",
+                index_name
+            )));
+        }
+        let table_ptr = match tables_ref.get(&Table::get_table_id(table_name)) {
+            Some(table_ptr) => table_ptr,
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Table {} does not exist",
+                    table_name
+                )))
+            }
+        };
+        let mut table = table_ptr.write().unwrap();
+        if !table.schema.contains(column_name) {
+            return Err(CrustyError::ValidationError(format!(
+                "Table {} has no column {}",
+                table_name, column_name
+            )));
+        }
+        // the container is named after the table, so dropping the table drops it too
+        let container_name = format!("{}.{}", table_name, index_name);
+        let mut container_id = Table::get_table_id(&container_name) as ContainerId;
+        while self.storage_manager.container_exists(container_id) {
+            container_id = container_id.wrapping_add(1);
+        }
+        self.storage_manager.create_container(
+            container_id,
+            Some(container_name),
+            ContainerType::Index,
+        )?;
+        let index = IndexInfo {
+            name: index_name.to_string(),
+            column: column_name.to_string(),
+            container_id,
+        };
+        let rows = match self.build_index(&table, &index) {
+            Ok(rows) => rows,
+            Err(e) => {
+                self.storage_manager.remove_container(container_id)?;
+                return Err(e);
+            }
+        };
+        table.indexes.push(index);
+        drop(table);
+        drop(tables_ref);
+        self.persist_catalog()?;
+        Ok(QueryResult::new(&format!(
+            "Index {} created on {}({}), {} rows indexed",
+            index_name, table_name, column_name, rows
+        )))
+    }
+
+    /// Removes an index and its entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_name` - Name of the index.
+    /// * `if_exists` - Whether a missing index is skipped rather than an error.
+    pub fn drop_index(
+        &self,
+        index_name: &str,
+        if_exists: bool,
+    ) -> Result<QueryResult, CrustyError> {
+        let tables_ref = self.database.tables.write().unwrap();
+        let table_ptr = match Self::find_index(&tables_ref, index_name) {
+            Some(table_ptr) => table_ptr,
+            None if if_exists => {
+                return Ok(QueryResult::new(&format!(
+                    "Index {} does not exist, skipped",
+                    index_name
+                )))
+            }
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Index {} does not exist",
+                    index_name
+                )))
+            }
+        };
+        let mut table = table_ptr.write().unwrap();
+        let pos = table
+            .indexes
+            .iter()
+            .position(|i| i.name == index_name)
+            .unwrap();
+        let index = table.indexes.remove(pos);
+        drop(table);
+        drop(tables_ref);
+        self.storage_manager.remove_container(index.container_id)?;
+        self.persist_catalog()?;
+        Ok(QueryResult::new(&format!("Index {} dropped", index_name)))
+    }
+
+    /// Gets the table an index is on, None if there is no index of that name.
+    fn find_index(
+        tables_ref: &HashMap<u64, Arc<RwLock<Table>>>,
+        index_name: &str,
+    ) -> Option<Arc<RwLock<Table>>> {
+        tables_ref
+            .values()
+            .find(|t| {
+                t.read()
+                    .unwrap()
+                    .indexes
+                    .iter()
+                    .any(|i| i.name == index_name)
+            })
+            .cloned()
+    }
+
+    /// Creates the containers of the indexes of a table that was loaded from a catalog.
+    fn create_index_containers(
+        table: &Table,
+        storage_manager: &StorageManager,
+    ) -> Result<(), CrustyError> {
+        for index in &table.indexes {
+            storage_manager.create_container(
+                index.container_id,
+                Some(format!("{}.{}", table.name, index.name)),
+                ContainerType::Index,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Fills an index with the rows stored in its table, replacing the entries it had. Returns
+    /// the number of rows.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - Table the index is on.
+    /// * `index` - Index to fill.
+    fn build_index(&self, table: &Table, index: &IndexInfo) -> Result<usize, CrustyError> {
+        let position = Self::index_position(table, index)?;
+        let tid = TransactionId::new();
+        let mut entries = Vec::new();
+        for (id, bytes) in self.storage_manager.get_values_with_ids(
+            table.id as ContainerId,
+            tid,
+            Permissions::ReadOnly,
+        )? {
+            let mut tuple = Tuple::from_bytes_with_schema(&bytes, &table.schema)?;
+            entries.push((tuple.field_vals.swap_remove(position), id.pack()));
+        }
+        // entries in key order fill the index one leaf after another
+        entries.sort();
+        let rows = entries.len();
+        self.storage_manager
+            .truncate_container(index.container_id)?;
+        self.storage_manager
+            .index_insert(index.container_id, entries, tid)?;
+        Ok(rows)
+    }
+
+    /// Rebuilds every index of a table, e.g. after its rows were rewritten and got new ids.
+    fn rebuild_indexes(&self, table: &Table) -> Result<(), CrustyError> {
+        for index in &table.indexes {
+            self.build_index(table, index)?;
+        }
+        Ok(())
+    }
+
+    /// Position of the indexed column in the rows of a table.
+    fn index_position(table: &Table, index: &IndexInfo) -> Result<usize, CrustyError> {
+        table
+            .schema
+            .get_field_index(&index.column)
+            .copied()
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "Index {} is on {}, which {} does not have",
+                    index.name, index.column, table.name
+                ))
+            })
+    }
+
+    /// Inserts rows of a table in one call to the storage manager and adds them to the table's
+    /// indexes. Returns the ids the rows got, in their order.
+    ///
+    /// Nothing is stored if a row can't be decoded. The rows are stored before they are added
+    /// to the indexes, so if an index can't take a key the rows are in the table but missing
+    /// from that index until it is rebuilt.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage_manager` - Storage manager of the table's database.
+    /// * `table` - Table the rows are inserted into.
+    /// * `rows` - Encoded rows.
+    /// * `tid` - Transaction inserting the rows.
+    pub fn insert_values(
+        storage_manager: &StorageManager,
+        table: &Table,
+        rows: Vec<Vec<u8>>,
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        let positions = table
+            .indexes
+            .iter()
+            .map(|index| Self::index_position(table, index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut keys = vec![Vec::with_capacity(rows.len()); positions.len()];
+        if !positions.is_empty() {
+            for bytes in &rows {
+                let tuple = Tuple::from_bytes_with_schema(bytes, &table.schema)?;
+                for (keys, position) in keys.iter_mut().zip(&positions) {
+                    keys.push(tuple.field_vals[*position].clone());
+                }
+            }
+        }
+        let ids = storage_manager.insert_values(table.id as ContainerId, rows, tid);
+        for (index, keys) in table.indexes.iter().zip(keys) {
+            let entries = keys
+                .into_iter()
+                .zip(ids.iter().map(ValueId::pack))
+                .collect();
+            storage_manager.index_insert(index.container_id, entries, tid)?;
+        }
+        Ok(ids)
+    }
+
+    /// Reads the first pages of a table into the storage manager's buffer pool, so queries
+    /// right after a restart don't have to wait for them.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table to warm.
+    /// * `max_pages` - Most pages to read, as many as the buffer pool holds if None.
+    pub fn warm_table(
+        &self,
+        table_name: &str,
+        max_pages: Option<usize>,
+    ) -> Result<String, CrustyError> {
+        let table_id = Table::get_table_id(table_name);
+        if !self.database.tables.read().unwrap().contains_key(&table_id) {
+            return Err(CrustyError::CrustyError(format!(
+                "Table {} does not exist",
+                table_name
+            )));
+        }
+        let cached = self
+            .storage_manager
+            .warm_container(table_id as ContainerId, max_pages.unwrap_or(usize::MAX))?;
+        Ok(format!("Warmed {} pages of {}", cached, table_name))
+    }
+
+    /// Rewrites the rows of a table that are in an older format in the current row format.
+    ///
+    /// Scans can read rows of every format, so this is only needed to finish migrating a table
+    /// after the row format changed. Rows inserted while the table is being rewritten may be
+    /// lost, so it should not run alongside writes to the table.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table to rewrite.
+    pub fn rewrite_table(&self, table_name: &str) -> Result<String, CrustyError> {
+        let tables_ref = self.database.tables.read().unwrap();
+        let table_id = Table::get_table_id(table_name);
+        let table = match tables_ref.get(&table_id) {
+            Some(table_ptr) => table_ptr.read().unwrap(),
+            None => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Table {} does not exist",
+                    table_name
+                )))
+            }
+        };
+        if table.append_only {
+            return Err(Self::append_only_error(table_name, "rewritten"));
+        }
+        let container_id = table_id as ContainerId;
+        let tid = TransactionId::new();
+        let mut rows = Vec::new();
+        let mut converted = 0;
+        for bytes in self
+            .storage_manager
+            .get_iterator(container_id, tid, Permissions::ReadOnly)
+        {
+            let bytes = bytes?;
+            if RowFormat::of(&bytes)? == RowFormat::CURRENT {
+                rows.push(bytes);
+            } else {
+                let tuple = Tuple::from_bytes_with_schema(&bytes, &table.schema)?;
+                rows.push(tuple.to_bytes_with_schema(&table.schema)?);
+                converted += 1;
+            }
+        }
+        let total = rows.len();
+        if converted > 0 {
+            self.storage_manager.truncate_container(container_id)?;
+            self.storage_manager.insert_values(container_id, rows, tid);
+            self.storage_manager.reclaim_space(container_id)?;
+            self.rebuild_indexes(&table)?;
+        }
+        self.storage_manager
+            .set_row_format(container_id, RowFormat::CURRENT)?;
+        Ok(format!(
+            "Table {} rewritten: {} of {} rows converted",
+            table_name, converted, total
+        ))
+    }
+
+    /// Makes a table append-only: from then on its rows are only added, by `append_rows` or
+    /// statements, and the values already stored keep their ids. Persisted with the catalog.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table.
+    pub fn set_append_only(&self, table_name: &str) -> Result<(), CrustyError> {
+        let table_ptr = self
+            .database
+            .get_table_ptr(Table::get_table_id(table_name))?;
+        table_ptr.write().unwrap().append_only = true;
+        self.persist_catalog()
+    }
+
+    /// Appends the rows of an ingestion frame to an append-only table in one call to the storage
+    /// manager, and returns the ids the values got in the order of the rows. Nothing is appended
+    /// if a row does not match the table's schema.
+    ///
+    /// The rows are not written to disk before this returns, they are written when the storage
+    /// manager next writes back its pages or shuts down.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table.
+    /// * `frame` - Rows in the format of `csv_utils::encode_frame`.
+    /// * `tid` - Transaction appending the rows.
+    pub fn append_rows(
+        &self,
+        table_name: &str,
+        frame: &str,
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        let table_ptr = self
+            .database
+            .get_table_ptr(Table::get_table_id(table_name))?;
+        let table = table_ptr.read().unwrap();
+        if !table.append_only {
+            return Err(CrustyError::ValidationError(format!(
+                "Table {} is not append-only",
+                table_name
+            )));
+        }
+        let rows = csv_utils::encode_frame(frame, &table)?;
+        Self::insert_values(&self.storage_manager, &table, rows, tid)
+    }
+
+    /// Inserts the rows of an `INSERT INTO ... VALUES` statement in one call to the storage
+    /// manager. Nothing is inserted if a row does not match the table's schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_name` - Name of the table.
+    /// * `columns` - Columns the values are given for, in the order of the values. Empty for the
+    ///   table's columns in their order. Columns left out are NULL.
+    /// * `rows` - Rows of literal values.
+    /// * `tid` - Transaction inserting the rows.
+    pub fn insert_rows(
+        &self,
+        table_name: &str,
+        columns: &[Ident],
+        rows: &[Vec<Expr>],
+        tid: TransactionId,
+    ) -> Result<QueryResult, CrustyError> {
+        let table_ptr = self
+            .database
+            .get_table_ptr(Table::get_table_id(table_name))?;
+        let table = table_ptr.read().unwrap();
+        let attributes: Vec<&Attribute> = table.schema.attributes().collect();
+        // Position of each column of the table in the rows, None for columns left out
+        let positions: Vec<Option<usize>> = if columns.is_empty() {
+            (0..attributes.len()).map(Some).collect()
+        } else {
+            if let Some(c) = columns
+                .iter()
+                .find(|c| !attributes.iter().any(|a| a.name() == c.as_str()))
+            {
+                return Err(CrustyError::ValidationError(format!(
+                    "Table {} has no column {}",
+                    table_name, c
+                )));
+            }
+            attributes
+                .iter()
+                .map(|attr| columns.iter().position(|c| c == attr.name()))
+                .collect()
+        };
+        let num_values = positions.iter().flatten().count();
+
+        let mut values = Vec::with_capacity(rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            if row.len() != num_values {
+                return Err(CrustyError::ValidationError(format!(
+                    "Row {} has {} values, {} columns of {} are given",
+                    i + 1,
+                    row.len(),
+                    num_values,
+                    table_name
+                )));
+            }
+            let mut tuple = Tuple::new(Vec::with_capacity(attributes.len()));
+            for (attr, pos) in attributes.iter().zip(positions.iter()) {
+                let pos = match pos {
+                    Some(pos) => *pos,
+                    None => {
+                        tuple.field_vals.push(Field::Null);
+                        continue;
+                    }
+                };
+                let field = Self::literal_to_field(&row[pos], attr.dtype()).ok_or_else(|| {
+                    CrustyError::ValidationError(format!(
+                        "Row {}: {} is not a valid {:?} for column {}",
+                        i + 1,
+                        row[pos],
+                        attr.dtype(),
+                        attr.name()
+                    ))
+                })?;
+                tuple.field_vals.push(field);
+            }
+            values.push(tuple.to_bytes_with_schema(&table.schema)?);
+        }
+        let inserted = Self::insert_values(&self.storage_manager, &table, values, tid)?.len();
+        let mut res = QueryResult::new(&format!("Inserted {} rows into {}", inserted, table_name));
+        res.metadata_mut().rows_affected = Some(inserted as u64);
+        Ok(res)
+    }
+
+    /// Converts a literal of an INSERT to a field of the column's type, None if it is not a
+    /// literal of that type. NULL is a literal of every type.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Value given for the column.
+    /// * `dtype` - Type of the column.
+    fn literal_to_field(expr: &Expr, dtype: &DataType) -> Option<Field> {
+        match (expr, dtype) {
+            (Expr::Value(Value::Null), _) => Some(Field::Null),
+            (Expr::Value(Value::Number(n)), DataType::Int) => n.parse().ok().map(Field::IntField),
+            (
+                Expr::UnaryOp {
+                    op: UnaryOperator::Minus,
+                    expr,
+                },
+                DataType::Int,
+            ) => match expr.as_ref() {
+                Expr::Value(Value::Number(n)) => {
+                    format!("-{}", n).parse().ok().map(Field::IntField)
+                }
+                _ => None,
+            },
+            (Expr::Value(Value::SingleQuotedString(s)), DataType::String)
+            | (Expr::Value(Value::NationalStringLiteral(s)), DataType::String) => {
+                Some(Field::StringField(s.to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    fn append_only_error(table_name: &str, action: &str) -> CrustyError {
+        CrustyError::ValidationError(format!(
+            "Table {} is append-only and can't be {}",
+            table_name, action
+        ))
+    }
+
+    /// Reports the space used by the database and by each of its tables, sorted by name.
+    ///
+    /// Index containers count towards the table their name starts with, as in `t.idx`.
+    pub fn disk_usage(&self) -> Result<(DiskUsage, Vec<(String, DiskUsage)>), CrustyError> {
+        let mut total = DiskUsage::default();
+        let mut tables: HashMap<String, DiskUsage> = HashMap::new();
+        {
+            let tables_ref = self.database.tables.read().unwrap();
+            for table_ptr in tables_ref.values() {
+                tables.insert(table_ptr.read().unwrap().name.clone(), DiskUsage::default());
+            }
+        }
+        for meta in self.storage_manager.list_containers() {
+            let usage = self.storage_manager.container_usage(meta.container_id)?;
+            total.add(meta.container_type, &usage);
+            let table_name = match (&meta.name, meta.container_type) {
+                (Some(name), ContainerType::Table) => name.as_str(),
+                (Some(name), ContainerType::Index) => name.split('.').next().unwrap(),
+                _ => continue,
+            };
+            if let Some(table_usage) = tables.get_mut(table_name) {
+                table_usage.add(meta.container_type, &usage);
+            }
+        }
+        let mut tables: Vec<(String, DiskUsage)> = tables.into_iter().collect();
+        tables.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok((total, tables))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::logical_plan::*;
+    use common::statistics::ColumnStats;
+    use common::testutil::gen_random_dir;
+    use queryexe::query::{Executor, PlanOptions};
+    use sqlparser::ast::{SetExpr, Statement};
+    use sqlparser::dialect::GenericDialect;
+    use sqlparser::parser::Parser;
+    use std::ops::Bound;
+
+    #[test]
+    fn test_rewrite_table() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = DatabaseState::new_from_name("rewrite", storage_path)?;
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("id"), DataType::Int),
+            Attribute::new(String::from("name"), DataType::String),
+        ]);
+        let table = Table::new(String::from("t"), schema.clone());
+        let cid = table.id as ContainerId;
+        db_state
+            .database
+            .tables
+            .write()
+            .unwrap()
+            .insert(table.id, Arc::new(RwLock::new(table)));
+        let sm = &db_state.storage_manager;
+        sm.create_container(cid, Some(String::from("t")), ContainerType::Table)?;
+        sm.set_row_format(cid, RowFormat::Cbor)?;
+
+        // Half the rows in the old format, half in the current one
+        let tid = TransactionId::new();
+        let tuples: Vec<Tuple> = (0..10)
+            .map(|i| Tuple::new(vec![Field::IntField(i), Field::StringField(i.to_string())]))
+            .collect();
+        for (i, tuple) in tuples.iter().enumerate() {
+            let format = if i % 2 == 0 {
+                RowFormat::Cbor
+            } else {
+                RowFormat::CURRENT
+            };
+            sm.insert_value(cid, format.encode(tuple, &schema)?, tid);
+        }
+
+        assert_eq!(
+            "Table t rewritten: 5 of 10 rows converted",
+            db_state.rewrite_table("t")?
+        );
+        let mut read = Vec::new();
+        for bytes in sm.get_iterator(cid, tid, Permissions::ReadOnly) {
+            let bytes = bytes?;
+            assert_eq!(RowFormat::CURRENT, RowFormat::of(&bytes)?);
+            read.push(RowFormat::decode(&bytes, &schema)?);
+        }
+        read.sort_by_key(|t| t.get_field(0).cloned());
+        assert_eq!(tuples, read);
+        assert_eq!(RowFormat::CURRENT, sm.list_containers()[0].row_format);
+
+        assert_eq!(
+            "Table t rewritten: 0 of 10 rows converted",
+            db_state.rewrite_table("t")?
+        );
+        assert!(db_state.rewrite_table("missing").is_err());
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_rows() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = DatabaseState::new_from_name("insert", storage_path)?;
+        let sql = "CREATE TABLE t (id INT, name VARCHAR(10) NOT NULL)";
+        if let Statement::CreateTable { columns, .. } =
+            &Parser::parse_sql(&GenericDialect {}, sql.to_string()).unwrap()[0]
+        {
+            db_state.create_table("t", columns)?;
+        }
+        let insert = |sql: &str| {
+            let statement = Parser::parse_sql(&GenericDialect {}, sql.to_string()).unwrap();
+            match &statement[0] {
+                Statement::Insert {
+                    columns, source, ..
+                } => match &source.body {
+                    SetExpr::Values(values) => {
+                        db_state.insert_rows("t", columns, &values.0, TransactionId::new())
+                    }
+                    _ => panic!("Not VALUES"),
+                },
+                _ => panic!("Not an INSERT"),
+            }
+        };
+
+        assert_eq!(
+            "Inserted 2 rows into t",
+            insert("INSERT INTO t VALUES (1, 'a'), (-2, 'it''s')")?.result()
+        );
+        assert_eq!(
+            "Inserted 1 rows into t",
+            insert("INSERT INTO t (name, id) VALUES ('c', 3)")?.result()
+        );
+        // a bad row rejects the whole statement
+        assert!(insert("INSERT INTO t VALUES (4, 'd'), ('e', 5)").is_err());
+        assert!(insert("INSERT INTO t VALUES (4)").is_err());
+        assert!(insert("INSERT INTO t (id, id) VALUES (4, 4)").is_err());
+        assert!(insert("INSERT INTO t VALUES (99999999999, 'f')").is_err());
+        // VARCHAR(10) holds 10 characters
+        assert!(insert("INSERT INTO t VALUES (4, 'abcdefghijk')").is_err());
+        // columns left out are NULL, unless they are NOT NULL
+        assert_eq!(
+            "Inserted 2 rows into t",
+            insert("INSERT INTO t (name) VALUES ('m'), ('n')")?.result()
+        );
+        assert_eq!(
+            "Inserted 1 rows into t",
+            insert("INSERT INTO t VALUES (NULL, 'o')")?.result()
+        );
+        assert!(insert("INSERT INTO t (id) VALUES (4)").is_err());
+        assert!(insert("INSERT INTO t VALUES (4, NULL)").is_err());
+        assert!(insert("INSERT INTO t (nope) VALUES (4)").is_err());
+
+        let table = db_state.database.get_table_ptr(Table::get_table_id("t"))?;
+        let schema = table.read().unwrap().schema.clone();
+        let mut rows: Vec<Tuple> = db_state
+            .storage_manager
+            .get_iterator(
+                Table::get_table_id("t") as ContainerId,
+                TransactionId::new(),
+                Permissions::ReadOnly,
+            )
+            .map(|bytes| RowFormat::decode(&bytes.unwrap(), &schema).unwrap())
+            .collect();
+        rows.sort_by_key(|t| t.field_vals.clone());
+        assert_eq!(
+            vec![
+                Tuple::new(vec![Field::Null, Field::StringField("m".into())]),
+                Tuple::new(vec![Field::Null, Field::StringField("n".into())]),
+                Tuple::new(vec![Field::Null, Field::StringField("o".into())]),
+                Tuple::new(vec![Field::IntField(-2), Field::StringField("it's".into())]),
+                Tuple::new(vec![Field::IntField(1), Field::StringField("a".into())]),
+                Tuple::new(vec![Field::IntField(3), Field::StringField("c".into())]),
+            ],
+            rows
+        );
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_table() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = DatabaseState::new_from_name("drop", storage_path)?;
+        let create = |sql: &str| {
+            if let Statement::CreateTable { columns, .. } =
+                &Parser::parse_sql(&GenericDialect {}, sql.to_string()).unwrap()[0]
+            {
+                db_state.create_table("t", columns)
+            } else {
+                panic!("Not a CREATE TABLE")
+            }
+        };
+        create("CREATE TABLE t (id INT)")?;
+        let sm = &db_state.storage_manager;
+        let cid = Table::get_table_id("t") as ContainerId;
+        db_state.create_index("t_id", "t", "id")?;
+        sm.insert_value(cid, vec![1; 10], TransactionId::new());
+        let index_ids: Vec<ContainerId> = db_state.database.tables.read().unwrap()
+            [&Table::get_table_id("t")]
+            .read()
+            .unwrap()
+            .indexes
+            .iter()
+            .map(|index| index.container_id)
+            .collect();
+        // named like an index of t, but not one of its indexes
+        sm.create_container(8, Some(String::from("t.idx")), ContainerType::Index)?;
+        db_state
+            .statistics
+            .write()
+            .unwrap()
+            .set_table("t", TableStats::new(1));
+
+        assert_eq!("Table t dropped", db_state.drop_table("t")?.result());
+        assert!(!sm.container_exists(cid));
+        assert!(index_ids.iter().all(|id| !sm.container_exists(*id)));
+        assert!(sm.container_exists(8));
+        assert!(db_state.statistics.read().unwrap().table("t").is_none());
+        assert!(db_state.drop_table("t").is_err());
+
+        // the name is free again, and the new table starts empty
+        create("CREATE TABLE t (id INT, name VARCHAR(4))")?;
+        let tid = TransactionId::new();
+        assert_eq!(0, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+        let tables = db_state.database.tables.read().unwrap();
+        let table = tables[&Table::get_table_id("t")].read().unwrap();
+        assert_eq!(2, table.schema.size());
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_persist_catalog() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("persist.catalog.json");
+        let storage_path = dir.join("hf").to_string_lossy().to_string();
+        let db_state = DatabaseState::new_from_name("persist", storage_path.clone())?
+            .persist_catalog_to(path.clone())?;
+        let loaded_tables = || -> Result<Vec<Table>, CrustyError> {
+            let loaded = DatabaseState::new_from_path(path.clone(), storage_path.clone())?;
+            let tables = loaded.database.tables.read().unwrap();
+            let mut tables: Vec<Table> =
+                tables.values().map(|t| t.read().unwrap().clone()).collect();
+            tables.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(tables)
+        };
+        assert!(loaded_tables()?.is_empty());
+
+        // every change to the catalog is on disk once it returns
+        for name in &["t", "u"] {
+            let sql = format!("CREATE TABLE {} (id INT, name VARCHAR(8))", name);
+            if let Statement::CreateTable { columns, .. } =
+                &Parser::parse_sql(&GenericDialect {}, sql).unwrap()[0]
+            {
+                db_state.create_table(name, columns)?;
+            }
+        }
+        db_state.create_index("t_id", "t", "id")?;
+        db_state.set_append_only("t")?;
+        db_state.drop_column("u", "name")?;
+        let tables = loaded_tables()?;
+        assert_eq!(
+            vec!["t", "u"],
+            tables.iter().map(|t| t.name.as_str()).collect::<Vec<_>>()
+        );
+        assert!(tables[0].append_only);
+        assert_eq!("t_id", tables[0].indexes[0].name);
+        assert_eq!(1, tables[1].schema.size());
+
+        db_state.drop_index("t_id", false)?;
+        db_state.drop_table("u")?;
+        let tables = loaded_tables()?;
+        assert_eq!(1, tables.len());
+        assert!(tables[0].indexes.is_empty());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_alter_table() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = DatabaseState::new_from_name("alter", storage_path)?;
+        let columns = |sql: &str| match Parser::parse_sql(&GenericDialect {}, sql.to_string())
+            .unwrap()
+            .remove(0)
+        {
+            Statement::CreateTable { columns, .. } => columns,
+            _ => panic!("Not a CREATE TABLE"),
+        };
+        db_state.create_table("t", &columns("CREATE TABLE t (id INT, name VARCHAR(4))"))?;
+        let cid = Table::get_table_id("t") as ContainerId;
+        let table = db_state.database.get_table_ptr(Table::get_table_id("t"))?;
+        let read = || {
+            let schema = table.read().unwrap().schema.clone();
+            let mut rows: Vec<Vec<Field>> = db_state
+                .storage_manager
+                .get_iterator(cid, TransactionId::new(), Permissions::ReadOnly)
+                .map(|bytes| {
+                    RowFormat::decode(&bytes.unwrap(), &schema)
+                        .unwrap()
+                        .field_vals
+                })
+                .collect();
+            rows.sort();
+            (schema.size(), rows)
+        };
+        let tid = TransactionId::new();
+        let schema = table.read().unwrap().schema.clone();
+        for i in 0..2 {
+            let tuple = Tuple::new(vec![Field::IntField(i), Field::StringField(i.to_string())]);
+            let bytes = RowFormat::CURRENT.encode(&tuple, &schema)?;
+            db_state.storage_manager.insert_value(cid, bytes, tid);
+        }
+        let mut stats = TableStats::new(2);
+        let column = ColumnStats {
+            distinct_count: 2,
+            min: None,
+            max: None,
+        };
+        stats.columns.insert(String::from("name"), column);
+        db_state.statistics.write().unwrap().set_table("t", stats);
+
+        let added = columns("CREATE TABLE t (score INT)").remove(0);
+        assert_eq!(
+            "Column score added to t, 2 rows rewritten",
+            db_state.add_column("t", &added)?.result()
+        );
+        let int = Field::IntField;
+        let string = |s: &str| Field::StringField(s.to_string());
+        assert_eq!(
+            (
+                3,
+                vec![
+                    vec![int(0), string("0"), Field::Null],
+                    vec![int(1), string("1"), Field::Null]
+                ]
+            ),
+            read()
+        );
+        assert!(db_state.add_column("t", &added).is_err());
+        // the stored rows would be NULL
+        let not_null = columns("CREATE TABLE t (b INT NOT NULL)").remove(0);
+        assert!(db_state.add_column("t", &not_null).is_err());
+        assert_eq!(3, read().0);
+
+        assert_eq!(
+            "Column name dropped from t, 2 rows rewritten",
+            db_state.drop_column("t", "name")?.result()
+        );
+        assert_eq!(
+            (
+                2,
+                vec![vec![int(0), Field::Null], vec![int(1), Field::Null]]
+            ),
+            read()
+        );
+        let statistics = db_state.statistics.read().unwrap();
+        assert!(statistics.table("t").unwrap().columns.is_empty());
+        drop(statistics);
+        assert!(db_state.drop_column("t", "name").is_err());
+        db_state.drop_column("t", "score")?;
+        assert!(db_state.drop_column("t", "id").is_err());
+        assert!(db_state.drop_column("missing", "id").is_err());
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_indexes() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = DatabaseState::new_from_name("indexes", storage_path)?;
+        let columns = match Parser::parse_sql(
+            &GenericDialect {},
+            String::from("CREATE TABLE t (id INT, name VARCHAR(8))"),
+        )
+        .unwrap()
+        .remove(0)
+        {
+            Statement::CreateTable { columns, .. } => columns,
+            _ => panic!("Not a CREATE TABLE"),
+        };
+        db_state.create_table("t", &columns)?;
+        let table = db_state.database.get_table_ptr(Table::get_table_id("t"))?;
+        let sm = &db_state.storage_manager;
+        let tid = TransactionId::new();
+        let insert = |ids: std::ops::Range<i32>| -> Result<(), CrustyError> {
+            let table = table.read().unwrap();
+            let rows = ids
+                .map(|i| {
+                    let tuple = Tuple::new(vec![
+                        Field::IntField(i % 5),
+                        Field::StringField(i.to_string()),
+                    ]);
+                    RowFormat::CURRENT.encode(&tuple, &table.schema)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            DatabaseState::insert_values(sm, &table, rows, tid)?;
+            Ok(())
+        };
+        // names of the rows with the given id, found through the index on id
+        let lookup = |id: i32| -> Result<Vec<String>, CrustyError> {
+            let table = table.read().unwrap();
+            let key = Field::IntField(id);
+            let container_id = table.indexes[0].container_id;
+            let mut names = Vec::new();
+            let bounds = (Bound::Included(&key), Bound::Included(&key));
+            for packed in sm.index_range(container_id, bounds.0, bounds.1, tid)? {
+                let bytes = sm.get_value(ValueId::unpack(packed)?, tid, Permissions::ReadOnly)?;
+                names.push(RowFormat::decode(&bytes, &table.schema)?.field_vals[1].to_string());
+            }
+            names.sort();
+            Ok(names)
+        };
+
+        insert(0..10)?;
+        assert_eq!(
+            "Index t_id created on t(id), 10 rows indexed",
+            db_state.create_index("t_id", "t", "id")?.result()
+        );
+        insert(10..12)?;
+        assert_eq!(vec!["1", "11", "6"], lookup(1)?);
+
+        // a filter of t.id reads the table through the index
+        let mut lp = LogicalPlan::new();
+        let scan = lp.add_node(LogicalOp::Scan(ScanNode {
+            alias: String::from("t"),
+        }));
+        let compare = |op, value| {
+            PredicateExpr::Compare(PredicateNode {
+                left: PredExpr::Ident(FieldIdentifier::new("t", "t.id")),
+                op,
+                right: PredExpr::Literal(Field::IntField(value)),
+            })
+        };
+        let predicate = vec![
+            compare(PredicateOp::GreaterThan, 1),
+            compare(PredicateOp::LessThanOrEq, 3),
+            compare(PredicateOp::NotEq, 2),
+        ];
+        let filter = lp.add_node(LogicalOp::Filter(FilterNode {
+            table: String::from("t"),
+            predicate: PredicateExpr::And(predicate),
+        }));
+        lp.add_edge(filter, scan);
+        let (mut plan, profile) = Executor::logical_plan_to_profiled_op_iterator(
+            sm,
+            &db_state.database,
+            &lp,
+            tid,
+            PlanOptions::default(),
+        )?;
+        plan.open()?;
+        let mut names = Vec::new();
+        while let Some(t) = plan.next()? {
+            names.push(t.field_vals[1].to_string());
+        }
+        plan.close()?;
+        assert_eq!(vec!["3", "8"], names);
+        let operators = profile.operators();
+        assert_eq!("Index scan t using t_id", operators[1].operator);
+        // 2 and 3 are in the range
+        assert_eq!(4, operators[1].rows);
+        assert_eq!("t (indexes: t_id on id)", db_state.get_table_names()?);
+        assert!(db_state.create_index("t_id", "t", "name").is_err());
+        assert!(db_state.create_index("t_x", "t", "x").is_err());
+        assert!(db_state.create_index("u_id", "u", "id").is_err());
+
+        // the rows get new ids when they are rewritten, and the index follows them
+        db_state.create_index("t_name", "t", "name")?;
+        let added = match Parser::parse_sql(
+            &GenericDialect {},
+            String::from("CREATE TABLE t (score INT)"),
+        )
+        .unwrap()
+        .remove(0)
+        {
+            Statement::CreateTable { mut columns, .. } => columns.remove(0),
+            _ => panic!("Not a CREATE TABLE"),
+        };
+        db_state.add_column("t", &added)?;
+        assert_eq!(vec!["1", "11", "6"], lookup(1)?);
+        let name_index = table.read().unwrap().indexes[1].container_id;
+        db_state.drop_column("t", "name")?;
+        assert_eq!(1, table.read().unwrap().indexes.len());
+        assert!(!sm.container_exists(name_index));
+
+        db_state.truncate_table("t")?;
+        assert!(lookup(1)?.is_empty());
+
+        assert_eq!(
+            "Index t_id dropped",
+            db_state.drop_index("t_id", false)?.result()
+        );
+        assert!(table.read().unwrap().indexes.is_empty());
+        assert!(db_state.drop_index("t_id", false).is_err());
+        assert!(db_state.drop_index("t_id", true).is_ok());
+        assert_eq!(1, sm.list_containers().len());
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_analyze() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = DatabaseState::new_from_name("analyze", storage_path)?;
+        let columns = match Parser::parse_sql(
+            &GenericDialect {},
+            String::from("CREATE TABLE t (id INT, name VARCHAR(8))"),
+        )
+        .unwrap()
+        .remove(0)
+        {
+            Statement::CreateTable { columns, .. } => columns,
+            _ => panic!("Not a CREATE TABLE"),
+        };
+        db_state.create_table("t", &columns)?;
+        let table = db_state.database.get_table_ptr(Table::get_table_id("t"))?;
+        let table = table.read().unwrap();
+        let rows = (0..6)
+            .map(|i| {
+                let id = if i < 2 {
+                    Field::Null
+                } else {
+                    Field::IntField(i)
+                };
+                let tuple = Tuple::new(vec![id, Field::StringField((i % 3).to_string())]);
+                RowFormat::CURRENT.encode(&tuple, &table.schema)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        DatabaseState::insert_values(
+            &db_state.storage_manager,
+            &table,
+            rows,
+            TransactionId::new(),
+        )?;
+        drop(table);
+
+        assert_eq!(
+            "Table t analyzed, 6 rows",
+            db_state.analyze(Some("t"))?.result()
+        );
+        let statistics = db_state.statistics.read().unwrap();
+        let stats = statistics.table("t").unwrap();
+        assert_eq!(6, stats.cardinality);
+        let id = stats.column("id").unwrap();
+        assert_eq!(4, id.distinct_count);
+        assert_eq!(Some(Field::IntField(2)), id.min);
+        assert_eq!(3, stats.column("name").unwrap().distinct_count);
+        drop(statistics);
+
+        assert_eq!("1 tables analyzed", db_state.analyze(None)?.result());
+        assert!(db_state.analyze(Some("u")).is_err());
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_usage() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = DatabaseState::new_from_name("du", storage_path)?;
+        let sm = &db_state.storage_manager;
+        for name in &["a", "b"] {
+            let table = Table::new(name.to_string(), TableSchema::new(Vec::new()));
+            let cid = table.id as ContainerId;
+            db_state
+                .database
+                .tables
+                .write()
+                .unwrap()
+                .insert(table.id, Arc::new(RwLock::new(table)));
+            sm.create_container(cid, Some(name.to_string()), ContainerType::Table)?;
+        }
+        sm.create_container(7, Some(String::from("a.idx")), ContainerType::Index)?;
+        sm.create_container(8, None, ContainerType::Temp)?;
+        let tid = TransactionId::new();
+        let a_id = Table::get_table_id("a") as ContainerId;
+        for _ in 0..3 {
+            sm.insert_value(a_id, vec![1; 10], tid);
+        }
+        sm.insert_value(8, vec![1; 5], tid);
+
+        let (total, tables) = db_state.disk_usage()?;
+        assert_eq!(35, total.live_bytes);
+        let names: Vec<&str> = tables.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["a", "b"], names);
+        assert_eq!(30, tables[0].1.live_bytes);
+        assert_eq!(DiskUsage::default(), tables[1].1);
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
 }
@@ -1,28 +1,39 @@
 extern crate sqlparser;
-use sqlparser::parser::*;
 
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader};
 use std::sync::Arc;
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 use std::net::{Shutdown, TcpStream};
 
 use crate::conductor::Conductor;
+use crate::roles::Role;
 use crate::server_state::ServerState;
+use crate::session::SessionVariables;
 
 use crate::commands;
-use crate::sql_parser::SQLParser;
+use crate::sql_parser::{AlterColumn, IndexStatement, SQLParser, SqlError};
+use common::protocol::{self, ClientCommand, Response};
+use common::{CrustyError, QueryResult, TableSchema, Tuple};
 use optimizer::optimizer::Optimizer;
-use queryexe::query::Executor;
+use queryexe::query::{Executor, RowSink};
 use sqlparser::ast::Statement;
-use sqlparser::parser::ParserError;
 
 pub enum Request {
     Err,
     Command(commands::Commands),
-    SQLError(ParserError),
+    SQLError(SqlError),
     SQL(Vec<Statement>),
+    Truncate(String),
+    /// `ALTER TABLE` adding or dropping a column of the table.
+    AlterTable(String, AlterColumn),
+    /// `CREATE INDEX` or `DROP INDEX`.
+    Index(IndexStatement),
+    /// `ANALYZE` of a table, or of all tables if None.
+    Analyze(Option<String>),
+    /// `EXPLAIN` of the statements, which are also run if it is `EXPLAIN ANALYZE`.
+    Explain(Vec<Statement>, bool),
+    /// Input that could not be turned into a request.
+    Invalid(CrustyError),
 }
 
 /// Separates user input requests into commands and SQL inputs.
@@ -30,21 +41,138 @@ pub enum Request {
 /// # Arguments
 ///
 /// * `cmd` - String containing user's input.
-fn parse_input_request(cmd: String) -> Request {
-    let dialect = sqlparser::dialect::GenericDialect {};
+/// * `variables` - Session variables substituted into SQL inputs.
+fn parse_input_request(cmd: String, variables: &SessionVariables) -> Request {
     if cmd.starts_with('\\') {
-        match commands::parse_command(cmd) {
+        return match commands::parse_command(cmd) {
             Some(c) => Request::Command(c),
             None => Request::Err,
+        };
+    }
+    let cmd = match variables.substitute(&cmd) {
+        Ok(cmd) => cmd,
+        Err(e) => return Request::Invalid(e),
+    };
+    if SQLParser::is_as_of(&cmd) {
+        Request::Invalid(CrustyError::ValidationError(String::from(
+            "AS OF is not supported, old versions of rows are not kept",
+        )))
+    } else if SQLParser::is_upsert(&cmd) {
+        Request::Invalid(CrustyError::ValidationError(String::from(
+            "INSERT ... ON CONFLICT is not supported, tables have no unique constraints to conflict on",
+        )))
+    } else if let Some(alter) = SQLParser::parse_alter_table(&cmd) {
+        match alter {
+            Ok((table_name, change)) => Request::AlterTable(table_name, change),
+            Err(e) => Request::Invalid(e),
+        }
+    } else if let Some(index) = SQLParser::parse_index(&cmd) {
+        match index {
+            Ok(index) => Request::Index(index),
+            Err(e) => Request::Invalid(e),
+        }
+    } else if let Some(table_name) = SQLParser::parse_truncate(&cmd) {
+        Request::Truncate(table_name)
+    } else if let Some(table_name) = SQLParser::parse_analyze(&cmd) {
+        Request::Analyze(table_name)
+    } else if let Some((analyze, sql)) = SQLParser::parse_explain(&cmd) {
+        match SQLParser::parse_sql(&sql) {
+            Ok(a) => Request::Explain(a, analyze),
+            Err(e) => Request::SQLError(e),
         }
     } else {
-        match Parser::parse_sql(&dialect, cmd) {
+        match SQLParser::parse_sql(&cmd) {
             Ok(a) => Request::SQL(a),
             Err(e) => Request::SQLError(e),
         }
     }
 }
 
+/// Writes a response, prefixed with its length.
+///
+/// # Arguments
+///
+/// * `stream` - TCP stream to the client.
+/// * `response` - Response to send.
+fn send_response(stream: &mut TcpStream, response: &Response) {
+    if let Err(e) = protocol::write_response(stream, response) {
+        error!("Failed to send response: {}", e);
+    }
+}
+
+/// Streams the rows of the client's queries to it while they run.
+///
+/// The socket blocks writes while the client's receive window is full, so a client that reads
+/// slowly holds the query back instead of the server buffering its rows.
+struct ClientSink {
+    stream: TcpStream,
+}
+
+impl ClientSink {
+    /// Sends a response, failing the query if the client can't be reached.
+    fn write(&mut self, response: &Response) -> Result<(), CrustyError> {
+        protocol::write_response(&mut self.stream, response)
+            .map_err(|e| CrustyError::IOError(format!("Failed to stream rows: {}", e)))
+    }
+}
+
+impl RowSink for ClientSink {
+    fn start(&mut self, schema: &TableSchema) -> Result<(), CrustyError> {
+        self.write(&Response::Header(schema.clone()))
+    }
+
+    fn send(&mut self, tuples: Vec<Tuple>) -> Result<(), CrustyError> {
+        self.write(&Response::Batch(tuples))
+    }
+}
+
+/// Turns the result of a request into the response sent for it: the end of rows streamed
+/// while a query ran, the rows of a query, a query result encoded in a binary format for the
+/// client to save, or a message. Rows and messages carry the metadata of the result, if it
+/// has any. An error also ends rows streamed before it.
+///
+/// # Arguments
+///
+/// * `res` - Result of the request.
+fn to_response(res: Result<QueryResult, CrustyError>) -> Response {
+    match res {
+        Ok(mut qr) => {
+            let metadata = qr.metadata().clone();
+            if qr.is_streamed() {
+                Response::End(metadata)
+            } else if let Some((schema, tuples)) = qr.take_rows() {
+                Response::Rows {
+                    schema,
+                    tuples,
+                    metadata,
+                }
+            } else if let Some(bytes) = qr.take_payload() {
+                let summary = qr.result().to_string();
+                Response::Command(ClientCommand::Save { summary, bytes })
+            } else if metadata.is_empty() {
+                Response::Ok(qr.result().to_string())
+            } else {
+                let message = qr.result().to_string();
+                Response::Done { message, metadata }
+            }
+        }
+        Err(err) => Response::Error(err.to_string()),
+    }
+}
+
+/// Logs whether a request succeeded.
+///
+/// # Arguments
+///
+/// * `what` - What was requested.
+/// * `res` - Result of the request.
+fn log_result<T>(what: &str, res: &Result<T, CrustyError>) {
+    match res {
+        Ok(_) => info!("Success running {}", what),
+        Err(err) => info!("Error while executing {}; error: {:?}", what, err),
+    }
+}
+
 /// Waits for user commands and dispatches the commands.
 ///
 /// # Arguments
@@ -59,33 +187,70 @@ pub fn handle_client_request(mut stream: TcpStream, server_state: Arc<ServerStat
     let executor = Executor::new_ref();
     let optimizer = Optimizer::new();
     let mut conductor = Conductor::new(parser, optimizer, executor).unwrap();
+    conductor.retry_policy = server_state.retry_policy;
+    conductor.settings.max_operator_rows = server_state.max_operator_rows;
+    conductor.settings.max_query_time = server_state.max_query_time;
+    conductor.settings.parallelism = server_state.parallelism;
+    conductor.roles = Arc::clone(&server_state.roles);
+    conductor.scheduler = Arc::clone(&server_state.scheduler);
+    conductor.slow_log = Arc::clone(&server_state.slow_log);
+    conductor.plan_cache = Arc::clone(&server_state.plan_cache);
+    conductor.row_sink = Some(Box::new(ClientSink {
+        stream: stream.try_clone().expect("Failed to clone stream"),
+    }));
 
-    // FIXME: id is hash(incoming-ip), make this right
-    // TODO: create a session for this client
-    let peer_ip_string = stream.peer_addr().unwrap().ip().to_string();
-    let mut s = DefaultHasher::new();
-    peer_ip_string.hash(&mut s);
-    let client_id = s.finish();
+    let peer = stream.peer_addr().unwrap().to_string();
+    let client_id = server_state.sessions.open(&peer);
+    info!("Opened session {} for {}", client_id, peer);
+    conductor.cancel = server_state.sessions.cancel_token(client_id).unwrap();
 
     let mut quiet = false;
+    let mut variables = SessionVariables::new();
     while match buf_stream.read_line(&mut data) {
         Ok(size) => {
             debug!("{}", data);
             //TODO: Better way to handle client end?
             // FIXME: and close connection should be just another command
             if size == 0 || data == "\\close\n" {
-                server_state.close_client_connection(client_id);
                 false
             } else if data == "\\shutdown\n" {
-                let quit = String::from("\\quit");
-                stream.write_all(quit.as_bytes()).unwrap();
+                if let Err(err) = conductor.authorize(Role::Admin, "\\shutdown") {
+                    send_response(&mut stream, &Response::Error(err.to_string()));
+                    data.clear();
+                    true
+                } else {
+                    send_response(&mut stream, &Response::Command(ClientCommand::Quit));
+                    data.clear();
+                    stream.shutdown(Shutdown::Both).unwrap();
+                    conductor.rollback_open_transaction();
+                    if let Err(err) = server_state.shutdown() {
+                        error!("Could not shut down cleanly: {}", err);
+                    }
+                    std::process::exit(1);
+                }
+            } else if let Some(session) = conductor.ingest.as_mut() {
+                // Every line is a frame of rows until the client ends the ingestion
+                let frame = data.trim_end_matches(|c| c == '\n' || c == '\r');
+                let response = if frame == "\\end" {
+                    Response::Ok(conductor.ingest.take().unwrap().finish())
+                } else {
+                    let _turn = conductor.scheduler.admit(conductor.settings.priority);
+                    match session.ingest_frame(frame) {
+                        Ok(ack) => Response::Ok(ack),
+                        Err(err) => {
+                            info!("Error while ingesting frame");
+                            Response::Error(err.to_string())
+                        }
+                    }
+                };
+                send_response(&mut stream, &response);
                 data.clear();
-                stream.shutdown(Shutdown::Both).unwrap();
-                server_state.shutdown().unwrap();
-                std::process::exit(1);
+                true
             } else if data == "\\quiet\n" {
                 quiet = true;
-                stream.write_all("QUIET MODE".to_string().as_bytes()).unwrap();
+                conductor.row_sink = None;
+                send_response(&mut stream, &Response::Ok(String::from("QUIET MODE")));
+                data.clear();
                 true
             } else {
                 let line = match String::from_utf8(data.as_bytes()[0..size].to_vec()) {
@@ -93,47 +258,91 @@ pub fn handle_client_request(mut stream: TcpStream, server_state: Arc<ServerStat
                     _ => return,
                 };
 
-                let response: String = match parse_input_request(line.to_string()) {
+                // A cancellation only stops the statement that was running when it came in
+                conductor.cancel.reset();
+                let response = match parse_input_request(line.to_string(), &variables) {
                     // COMMAND
-                    Request::Command(a) => match conductor.run_command(a, client_id, &server_state)
-                    {
-                        Ok(qr) => {
-                            info!("Success COMMAND::Create {:?}", qr);
-                            qr.to_string()
-                        }
-                        Err(err) => {
-                            info!("Error while executing COMMAND::Create; error: {:?}", err);
-                            err.to_string()
-                        }
-                    },
+                    Request::Command(a) => {
+                        let what = format!("COMMAND::{:?}", a);
+                        let res =
+                            conductor.run_command(a, client_id, &server_state, &mut variables);
+                        log_result(&what, &res);
+                        to_response(res.map(|res| QueryResult::new(&res)))
+                    }
                     // SQL Query
                     Request::SQL(ast) => {
-                        let db_state = {
-                            let db_id_ref = server_state.active_connections.read().unwrap();
-                            let db_id = db_id_ref.get(&client_id).unwrap();
-                            let db_ref = server_state.id_to_db.read().unwrap();
-                            db_ref.get(db_id).unwrap().clone()
-                        };
-                        match conductor.run_sql(ast, &db_state) {
-                            Ok(qr) => {
-                                info!("Success running SQL query");
-                                qr.result().to_string()
-                            }
-                            Err(err) => {
-                                info!("Error while executing SQL query");
-                                err.to_string()
-                            }
-                        }
+                        let res = server_state
+                            .get_connected_db(client_id)
+                            .and_then(|db_state| conductor.run_sql(ast, &db_state));
+                        log_result("SQL query", &res);
+                        to_response(res)
+                    }
+                    // TRUNCATE TABLE, which the SQL parser does not understand
+                    Request::Truncate(table_name) => {
+                        let res = server_state
+                            .get_connected_db(client_id)
+                            .and_then(|db_state| conductor.run_truncate(&table_name, &db_state));
+                        log_result("TRUNCATE", &res);
+                        to_response(res)
+                    }
+                    // ALTER TABLE of a column, which the SQL parser does not understand
+                    Request::AlterTable(table_name, change) => {
+                        let res = server_state
+                            .get_connected_db(client_id)
+                            .and_then(|db_state| {
+                                conductor.run_alter_table(&table_name, &change, &db_state)
+                            });
+                        log_result("ALTER TABLE", &res);
+                        to_response(res)
+                    }
+                    // CREATE and DROP INDEX, which the SQL parser does not understand
+                    Request::Index(index) => {
+                        let res = server_state
+                            .get_connected_db(client_id)
+                            .and_then(|db_state| conductor.run_index(&index, &db_state));
+                        log_result(&format!("{:?}", index), &res);
+                        to_response(res)
+                    }
+                    // ANALYZE, which the SQL parser does not understand
+                    Request::Analyze(table_name) => {
+                        let res = server_state
+                            .get_connected_db(client_id)
+                            .and_then(|db_state| {
+                                conductor.run_analyze(table_name.as_deref(), &db_state)
+                            });
+                        log_result("ANALYZE", &res);
+                        to_response(res)
+                    }
+                    // EXPLAIN, which the SQL parser does not understand
+                    Request::Explain(ast, analyze) => {
+                        let res = server_state
+                            .get_connected_db(client_id)
+                            .and_then(|db_state| conductor.run_explain(ast, analyze, &db_state));
+                        log_result("EXPLAIN", &res);
+                        to_response(res)
                     }
                     // Errors
-                    Request::SQLError(e) => format!("SQL error: {}", e),
-                    Request::Err => "Unknown command".to_string(),
+                    Request::SQLError(e) => Response::Error(format!("SQL error: {}", e)),
+                    Request::Invalid(e) => Response::Error(e.to_string()),
+                    Request::Err => Response::Error(String::from("Unknown command")),
                 };
                 if quiet {
-                     stream.write_all("ok".to_string().as_bytes()).unwrap();
-                 } else {
-                     stream.write_all(response.as_bytes()).unwrap();
-                 }
+                    // only what the statement did, for benchmarks to report
+                    let message = String::from("ok");
+                    let quiet_response = match response.metadata() {
+                        Some(metadata) => Response::Done {
+                            message,
+                            metadata: metadata.clone(),
+                        },
+                        None => Response::Ok(message),
+                    };
+                    send_response(&mut stream, &quiet_response);
+                } else {
+                    send_response(&mut stream, &response);
+                }
+                server_state
+                    .sessions
+                    .set_transaction(client_id, conductor.transaction_id());
                 data.clear();
                 true
             }
@@ -144,9 +353,15 @@ pub fn handle_client_request(mut stream: TcpStream, server_state: Arc<ServerStat
                 stream.peer_addr().unwrap()
             );
             stream.shutdown(Shutdown::Both).unwrap();
-            // FIXME: (raul) shut this down properly
             error!("Shutting down crustydbd due to error...");
+            conductor.rollback_open_transaction();
+            if let Err(err) = server_state.shutdown() {
+                error!("Could not shut down cleanly: {}", err);
+            }
             std::process::exit(0);
         }
     } {}
+    // writes of a transaction the client left open are not kept
+    conductor.rollback_open_transaction();
+    server_state.close_client_connection(client_id);
 }
@@ -0,0 +1,187 @@
+use crate::database_state::DatabaseState;
+use common::ids::ValueId;
+use common::CrustyError;
+use std::sync::Arc;
+use txn_manager::transactions::Transaction;
+
+/// A client streaming rows into an append-only table, started with `\ingest` and ended with
+/// `\end`.
+///
+/// Every line the client sends while ingesting is a frame of rows, appended with a single call
+/// to the storage manager and acknowledged with the ids the values got. Acknowledged rows are
+/// not on disk yet: they are written whenever the storage manager writes back its pages, so a
+/// crash loses the rows acknowledged since then. That is the trade-off for sensor and log
+/// streams, where losing the last moments of a stream is cheaper than a write per frame.
+pub struct IngestSession {
+    db_state: Arc<DatabaseState>,
+    table_name: String,
+    frames: usize,
+    rows: usize,
+}
+
+impl IngestSession {
+    /// Makes the table append-only and starts ingesting into it.
+    ///
+    /// # Arguments
+    ///
+    /// * `db_state` - Database the table belongs to.
+    /// * `table_name` - Table to append the rows to.
+    pub fn start(db_state: Arc<DatabaseState>, table_name: &str) -> Result<Self, CrustyError> {
+        db_state.set_append_only(table_name)?;
+        Ok(Self {
+            db_state,
+            table_name: table_name.to_string(),
+            frames: 0,
+            rows: 0,
+        })
+    }
+
+    /// Appends the rows of a frame and returns the acknowledgement sent to the client, which
+    /// lists the ranges of ids the values got.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - Rows separated by `;`, each with comma separated fields.
+    pub fn ingest_frame(&mut self, frame: &str) -> Result<String, CrustyError> {
+        let txn = Transaction::new();
+        let ids = self
+            .db_state
+            .append_rows(&self.table_name, frame, txn.tid())?;
+        self.frames += 1;
+        self.rows += ids.len();
+        Ok(format!(
+            "ack {} rows {}",
+            ids.len(),
+            Self::format_ranges(&ids)
+        ))
+    }
+
+    /// Ends the session and describes what was ingested.
+    pub fn finish(self) -> String {
+        format!(
+            "Ingested {} rows in {} frames into {}",
+            self.rows, self.frames, self.table_name
+        )
+    }
+
+    /// Lists ids as ranges of consecutive slots of the same page, as in `0-99` or, for storage
+    /// managers that place values on pages, `3:0-41,4:0-12`.
+    fn format_ranges(ids: &[ValueId]) -> String {
+        let mut ranges: Vec<(ValueId, ValueId)> = Vec::new();
+        for id in ids {
+            match ranges.last_mut() {
+                Some((_, last))
+                    if last.container_id == id.container_id
+                        && last.segment_id == id.segment_id
+                        && last.page_id == id.page_id
+                        && last.slot_id.zip(id.slot_id).map(|(l, s)| l + 1 == s) == Some(true) =>
+                {
+                    *last = *id
+                }
+                _ => ranges.push((*id, *id)),
+            }
+        }
+        ranges
+            .iter()
+            .map(|(first, last)| {
+                let page = first.page_id.map(|p| format!("{}:", p)).unwrap_or_default();
+                let slot = |id: &ValueId| id.slot_id.map(|s| s.to_string()).unwrap_or_default();
+                if first == last {
+                    format!("{}{}", page, slot(first))
+                } else {
+                    format!("{}{}-{}", page, slot(first), slot(last))
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::ids::{ContainerId, Permissions, TransactionId};
+    use common::storage_trait::{ContainerType, StorageTrait, ValueRefIterator};
+    use common::table::Table;
+    use common::testutil::gen_random_dir;
+    use common::{Attribute, DataType, TableSchema};
+    use std::fs;
+    use std::sync::RwLock;
+
+    #[test]
+    fn test_ingest() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let storage_path = dir.to_string_lossy().to_string();
+        let db_state = Arc::new(DatabaseState::new_from_name("ingest", storage_path)?);
+        let schema = TableSchema::new(vec![
+            Attribute::new(String::from("sensor"), DataType::Int),
+            Attribute::new(String::from("reading"), DataType::Int),
+        ]);
+        let table = Table::new(String::from("readings"), schema);
+        let cid = table.id as ContainerId;
+        db_state
+            .database
+            .tables
+            .write()
+            .unwrap()
+            .insert(table.id, Arc::new(RwLock::new(table)));
+        db_state
+            .storage_manager
+            .create_container(cid, None, ContainerType::Table)?;
+
+        assert!(db_state
+            .append_rows("readings", "1,1", TransactionId::new())
+            .is_err());
+        let mut session = IngestSession::start(db_state.clone(), "readings")?;
+        // heapstore places the values on pages, memstore does not
+        let page = if cfg!(feature = "heapstore") {
+            "0:"
+        } else {
+            ""
+        };
+        assert_eq!(
+            format!("ack 3 rows {}0-2", page),
+            session.ingest_frame("1,10;2,20;1,11")?
+        );
+        assert_eq!(
+            format!("ack 1 rows {}3", page),
+            session.ingest_frame("2,21")?
+        );
+        // a bad row rejects the whole frame
+        assert!(session.ingest_frame("1,12;2,x").is_err());
+        assert_eq!(
+            "Ingested 4 rows in 2 frames into readings",
+            session.finish()
+        );
+        let count = db_state
+            .storage_manager
+            .get_iterator(cid, TransactionId::new(), Permissions::ReadOnly)
+            .collect_values()?
+            .len();
+        assert_eq!(4, count);
+        assert!(db_state.truncate_table("readings").is_err());
+        assert!(db_state.rewrite_table("readings").is_err());
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_ranges() {
+        let id = |page: Option<u16>, slot: u16| ValueId {
+            container_id: 1,
+            segment_id: None,
+            page_id: page,
+            slot_id: Some(slot),
+        };
+        assert_eq!("", IngestSession::format_ranges(&[]));
+        assert_eq!(
+            "0-1,5",
+            IngestSession::format_ranges(&[id(None, 0), id(None, 1), id(None, 5)])
+        );
+        assert_eq!(
+            "3:7-8,4:0",
+            IngestSession::format_ranges(&[id(Some(3), 7), id(Some(3), 8), id(Some(4), 0)])
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use common::ids::TransactionId;
+use common::table::Table;
+use common::{Attribute, CrustyError, DataType, Tuple};
+use serde_json::Value;
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::csv_utils::{self, ImportSummary};
+use crate::StorageManager;
+
+/// The value of an object for a column, by the column's name, or by its name without the
+/// table, like `a` for `t.a`.
+///
+/// # Arguments
+///
+/// * `object` - Object of the file.
+/// * `attr` - Column the value is for.
+fn take_value(object: &mut serde_json::Map<String, Value>, attr: &Attribute) -> Option<Value> {
+    object.remove(attr.name()).or_else(|| {
+        attr.name()
+            .rsplit_once('.')
+            .and_then(|(_, column)| object.remove(column))
+    })
+}
+
+/// Converts an object of a JSON file to a row of a table, or says why it does not match the
+/// table's columns.
+///
+/// Keys are column names, missing keys and nulls are NULLs. INT columns take numbers and
+/// VARCHAR columns strings, a number is not read from a string or the other way around.
+///
+/// # Arguments
+///
+/// * `value` - Element of the file's array.
+/// * `table` - Table the row is for.
+fn object_to_tuple(value: Value, table: &Table) -> Result<Tuple, String> {
+    let mut object = match value {
+        Value::Object(object) => object,
+        other => return Err(format!("{} is not an object", other)),
+    };
+    let mut values = Vec::with_capacity(table.schema.size());
+    for attr in table.schema.attributes() {
+        let value = match (take_value(&mut object, attr), attr.dtype()) {
+            (None, _) | (Some(Value::Null), _) => None,
+            // out of range and fractional numbers are rejected by values_to_tuple
+            (Some(Value::Number(n)), DataType::Int) => Some(n.to_string()),
+            (Some(Value::String(s)), DataType::String) => Some(s),
+            (Some(other), DataType::Int) => {
+                return Err(format!(
+                    "{} is not an INT for column {}",
+                    other,
+                    attr.name()
+                ))
+            }
+            (Some(other), DataType::String) => {
+                return Err(format!(
+                    "{} is not a VARCHAR for column {}",
+                    other,
+                    attr.name()
+                ))
+            }
+        };
+        values.push(value);
+    }
+    if let Some(key) = object.keys().next() {
+        return Err(format!("{} is not a column of table {}", key, table.name));
+    }
+    csv_utils::values_to_tuple(values, table)
+}
+
+/// Imports a JSON file, an array of objects whose keys are the table's columns, into an
+/// existing table, see `csv_utils::import_rows`.
+///
+/// The whole file is parsed before its rows are inserted, a file that is not an array is
+/// refused without inserting anything.
+///
+/// # Arguments
+///
+/// * `table` - Table to store the data in.
+/// * `path` - Path to the JSON file.
+/// * `tid` - Transaction id for inserting the tuples.
+/// * `commit_rows` - Rows committed at once, None to commit the whole file at its end.
+/// * `parallel` - Whether the rows are converted by a thread of their own.
+pub fn import_json(
+    table: &Table,
+    path: &str,
+    tid: TransactionId,
+    storage_manager: &StorageManager,
+    commit_rows: Option<usize>,
+    parallel: bool,
+) -> Result<ImportSummary, CrustyError> {
+    debug!("server::json_import trying to open file, path: {:?}", path);
+    let file = File::open(path)?;
+    let elements = match serde_json::from_reader(BufReader::new(file)) {
+        Ok(Value::Array(elements)) => elements,
+        Ok(_) => {
+            return Err(CrustyError::ValidationError(format!(
+                "{} is not a JSON array of objects",
+                path
+            )))
+        }
+        Err(e) => {
+            return Err(CrustyError::ValidationError(format!(
+                "{} is not valid JSON: {}",
+                path, e
+            )))
+        }
+    };
+    let rows = elements
+        .into_iter()
+        .map(|element| Ok(object_to_tuple(element, table)));
+    csv_utils::import_rows(table, rows, tid, storage_manager, commit_rows, parallel)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::ids::{ContainerId, Permissions};
+    use common::row_format::RowFormat;
+    use common::storage_trait::{StorageTrait, ValueRefIterator};
+    use common::testutil::gen_random_dir;
+    use common::{Field, TableSchema};
+    use std::fs;
+
+    #[test]
+    fn test_import_json() {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("people.json");
+        fs::write(
+            &path,
+            r#"[
+                {"id": 1, "name": "ann"},
+                {"id": 2},
+                {"id": "3", "name": "bob"},
+                {"id": 4, "name": 5},
+                {"id": 2147483648, "name": "big"},
+                {"name": "nobody"},
+                {"id": 7, "name": "eve", "age": 30},
+                [8, "dan"]
+            ]"#,
+        )
+        .unwrap();
+        let schema = TableSchema::new(vec![
+            Attribute::new_not_null(String::from("id"), DataType::Int),
+            Attribute::new(String::from("people.name"), DataType::String),
+        ]);
+        let table = Table::new(String::from("people"), schema);
+        let path = path.to_string_lossy().to_string();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        let summary = import_json(&table, &path, tid, &sm, None, false).unwrap();
+        assert_eq!(2, summary.imported);
+        assert_eq!(
+            vec![
+                "row 3: \"3\" is not an INT for column id",
+                "row 4: 5 is not a VARCHAR for column people.name",
+                "row 5: 2147483648 is not an INT for column id",
+                "row 6: no value for NOT NULL column id",
+                "row 7: age is not a column of table people",
+                "row 8: [8,\"dan\"] is not an object",
+            ],
+            summary.errors
+        );
+
+        let mut rows: Vec<Vec<Field>> = sm
+            .get_iterator(table.id as ContainerId, tid, Permissions::ReadOnly)
+            .collect_values()
+            .unwrap()
+            .iter()
+            .map(|bytes| RowFormat::decode(bytes, &table.schema).unwrap().field_vals)
+            .collect();
+        rows.sort();
+        assert_eq!(
+            vec![
+                vec![Field::IntField(1), Field::StringField(String::from("ann"))],
+                vec![Field::IntField(2), Field::Null],
+            ],
+            rows
+        );
+
+        fs::write(dir.join("object.json"), r#"{"id": 1}"#).unwrap();
+        let object = dir.join("object.json").to_string_lossy().to_string();
+        assert!(import_json(&table, &object, tid, &sm, None, false).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
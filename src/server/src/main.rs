@@ -10,19 +10,41 @@ use std::fs;
 use std::net::TcpListener;
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
+use crate::cache::DEFAULT_PLAN_CACHE_BYTES;
+use crate::retry::RetryPolicy;
+use crate::roles::Role;
+use crate::scheduler::Scheduler;
 use crate::server_state::ServerState;
+use crate::slow_log::SlowLog;
 
+#[cfg(feature = "arrow")]
+mod arrow_ipc;
+mod cache;
 mod commands;
 mod conductor;
 mod csv_utils;
 mod database_state;
 mod handler;
+mod ingest;
+mod json_import;
+#[cfg(feature = "parquet")]
+mod parquet_import;
+mod retry;
+mod roles;
+mod scheduler;
 mod server_state;
+mod session;
+mod slow_log;
 mod sql_parser;
 
 /// Re-export Storage manager here for this crate to use. This allows us to change
-/// the storage manager by changing one use statement.
+/// the storage manager by changing one use statement, or by building with the `heapstore`
+/// feature.
+#[cfg(feature = "heapstore")]
+pub use heapstore::storage_manager::StorageManager;
+#[cfg(not(feature = "heapstore"))]
 pub use memstore::storage_manager::StorageManager;
 
 #[derive(Deserialize, Debug)]
@@ -31,6 +53,40 @@ struct ServerConfig {
     port: String,
     db_path: String,
     hf_path: String,
+    #[serde(default)]
+    txn_retries: u32,
+    #[serde(default = "default_txn_retry_backoff_ms")]
+    txn_retry_backoff_ms: u64,
+    #[serde(default = "default_role")]
+    default_role: Role,
+    #[serde(default)]
+    max_operator_rows: usize,
+    #[serde(default)]
+    max_query_time: u64,
+    #[serde(default = "default_parallelism")]
+    parallelism: usize,
+    #[serde(default)]
+    max_running_statements: usize,
+    #[serde(default)]
+    slow_query_ms: u64,
+    #[serde(default = "default_plan_cache_bytes")]
+    plan_cache_bytes: usize,
+}
+
+fn default_txn_retry_backoff_ms() -> u64 {
+    10
+}
+
+fn default_role() -> Role {
+    Role::Admin
+}
+
+fn default_parallelism() -> usize {
+    1
+}
+
+fn default_plan_cache_bytes() -> usize {
+    DEFAULT_PLAN_CACHE_BYTES
 }
 
 /// Entry point for server.
@@ -88,6 +144,78 @@ fn main() {
                 .help("????")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("txn_retries")
+                .long("txn_retries")
+                .value_name("txn_retries")
+                .default_value("0")
+                .help("Times a statement is rerun when its transaction is aborted")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("txn_retry_backoff_ms")
+                .long("txn_retry_backoff_ms")
+                .value_name("txn_retry_backoff_ms")
+                .default_value("10")
+                .help("Wait in ms before the first rerun, doubled for every further rerun up to 30 s")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("default_role")
+                .long("default_role")
+                .value_name("default_role")
+                .default_value("admin")
+                .help("Role of users without an assigned role: admin, readwrite or readonly")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_operator_rows")
+                .long("max_operator_rows")
+                .value_name("max_operator_rows")
+                .default_value("0")
+                .help("Rows a join may produce before its query is aborted, 0 for no limit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_query_time")
+                .long("max_query_time")
+                .value_name("max_query_time")
+                .default_value("0")
+                .help("Time in ms a query may run before it is aborted, 0 for no limit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("parallelism")
+                .long("parallelism")
+                .value_name("parallelism")
+                .default_value("1")
+                .help("Threads the scans of a query are split among, 1 for none but the client's")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_running_statements")
+                .long("max_running_statements")
+                .value_name("max_running_statements")
+                .default_value("0")
+                .help("Statements of all clients that run at once, 0 for no limit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("slow_query_ms")
+                .long("slow_query_ms")
+                .value_name("slow_query_ms")
+                .default_value("0")
+                .help("Time in ms from which statements are added to the slow query log, 0 for off")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("plan_cache_bytes")
+                .long("plan_cache_bytes")
+                .value_name("plan_cache_bytes")
+                .default_value("4194304")
+                .help("Bytes of query plans cached for reuse by all clients, 0 for off")
+                .takes_value(true),
+        )
         .get_matches();
 
     let config = if let Some(c) = matches.value_of("config") {
@@ -99,17 +227,72 @@ fn main() {
         let port = matches.value_of("port").unwrap();
         let db_path = matches.value_of("db_path").unwrap();
         let hf_path = matches.value_of("hf_path").unwrap();
+        let txn_retries = matches.value_of("txn_retries").unwrap();
+        let txn_retry_backoff_ms = matches.value_of("txn_retry_backoff_ms").unwrap();
+        let default_role = matches.value_of("default_role").unwrap();
+        let max_operator_rows = matches.value_of("max_operator_rows").unwrap();
+        let max_query_time = matches.value_of("max_query_time").unwrap();
+        let parallelism = matches.value_of("parallelism").unwrap();
+        let max_running_statements = matches.value_of("max_running_statements").unwrap();
+        let slow_query_ms = matches.value_of("slow_query_ms").unwrap();
+        let plan_cache_bytes = matches.value_of("plan_cache_bytes").unwrap();
         ServerConfig {
             host: host.to_string(),
             port: port.to_string(),
             db_path: db_path.to_string(),
             hf_path: hf_path.to_string(),
+            txn_retries: txn_retries.parse().expect("txn_retries must be a number"),
+            txn_retry_backoff_ms: txn_retry_backoff_ms
+                .parse()
+                .expect("txn_retry_backoff_ms must be a number"),
+            default_role: default_role
+                .parse()
+                .expect("default_role must be admin, readwrite or readonly"),
+            max_operator_rows: max_operator_rows
+                .parse()
+                .expect("max_operator_rows must be a number"),
+            max_query_time: max_query_time
+                .parse()
+                .expect("max_query_time must be a number"),
+            parallelism: parallelism
+                .parse()
+                .expect("parallelism must be a number"),
+            max_running_statements: max_running_statements
+                .parse()
+                .expect("max_running_statements must be a number"),
+            slow_query_ms: slow_query_ms
+                .parse()
+                .expect("slow_query_ms must be a number"),
+            plan_cache_bytes: plan_cache_bytes
+                .parse()
+                .expect("plan_cache_bytes must be a number"),
         }
     };
 
     info!("Starting crustydb... {:?}", config);
 
-    let server_state = Arc::new(ServerState::new(config.db_path, config.hf_path).unwrap());
+    let mut server_state = ServerState::new(config.db_path, config.hf_path).unwrap();
+    server_state.retry_policy = RetryPolicy::new(
+        config.txn_retries,
+        Duration::from_millis(config.txn_retry_backoff_ms),
+    );
+    server_state.roles.set_default_role(config.default_role);
+    if config.max_operator_rows > 0 {
+        server_state.max_operator_rows = Some(config.max_operator_rows);
+    }
+    if config.max_query_time > 0 {
+        server_state.max_query_time = Some(Duration::from_millis(config.max_query_time));
+    }
+    server_state.parallelism = config.parallelism.max(1);
+    server_state.scheduler = Arc::new(Scheduler::new(config.max_running_statements));
+    if config.slow_query_ms > 0 {
+        server_state.slow_log = Arc::new(SlowLog::new(
+            Duration::from_millis(config.slow_query_ms),
+            &server_state.metadata_path,
+        ));
+    }
+    server_state.plan_cache.set_capacity(config.plan_cache_bytes);
+    let server_state = Arc::new(server_state);
 
     let mut bind_addr = config.host.clone();
     bind_addr.push_str(":");
@@ -0,0 +1,241 @@
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Int16Type, Int32Type, Int64Type, Int8Type};
+use arrow_array::{Array, RecordBatch};
+use arrow_schema::{DataType as ArrowType, Schema};
+use common::ids::TransactionId;
+use common::table::Table;
+use common::{CrustyError, DataType, Tuple};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::fs::File;
+
+use crate::csv_utils::{self, ImportSummary};
+use crate::StorageManager;
+
+/// Rows decoded from the file at once.
+const BATCH_ROWS: usize = 4096;
+
+fn parquet_error(e: impl std::fmt::Display) -> CrustyError {
+    CrustyError::ValidationError(format!("Parquet decoding failed: {}", e))
+}
+
+/// Finds the column of the file for each column of the table, by the table column's name or
+/// its name without the table, like `a` for `t.a`. Refuses files whose columns can't be
+/// imported: a column the table does not have, a missing NOT NULL column, or a column of the
+/// wrong type.
+///
+/// # Arguments
+///
+/// * `schema` - Schema of the file.
+/// * `table` - Table the rows are for.
+fn match_columns(schema: &Schema, table: &Table) -> Result<Vec<Option<usize>>, CrustyError> {
+    let refuse = |reason: String| Err(CrustyError::ValidationError(reason));
+    let mut columns = Vec::with_capacity(table.schema.size());
+    for attr in table.schema.attributes() {
+        let unqualified = attr.name().rsplit('.').next().unwrap_or_default();
+        let index = schema
+            .index_of(attr.name())
+            .or_else(|_| schema.index_of(unqualified))
+            .ok();
+        let index = match index {
+            Some(index) => index,
+            None if attr.nullable() => {
+                columns.push(None);
+                continue;
+            }
+            None => return refuse(format!("no column for NOT NULL column {}", attr.name())),
+        };
+        let found = schema.field(index).data_type();
+        let matches = match attr.dtype() {
+            DataType::Int => matches!(
+                found,
+                ArrowType::Int8 | ArrowType::Int16 | ArrowType::Int32 | ArrowType::Int64
+            ),
+            DataType::String => matches!(found, ArrowType::Utf8 | ArrowType::LargeUtf8),
+        };
+        if !matches {
+            return refuse(format!(
+                "{} column {} can't be imported into column {}",
+                found,
+                schema.field(index).name(),
+                attr.name()
+            ));
+        }
+        columns.push(Some(index));
+    }
+    for (i, field) in schema.fields().iter().enumerate() {
+        if !columns.contains(&Some(i)) {
+            return refuse(format!(
+                "{} is not a column of table {}",
+                field.name(),
+                table.name
+            ));
+        }
+    }
+    Ok(columns)
+}
+
+/// The value of a row of a column as text, None for a null. Its type is one of those
+/// `match_columns` accepts.
+fn value_text(array: &dyn Array, row: usize) -> Option<String> {
+    if array.is_null(row) {
+        return None;
+    }
+    let text = match array.data_type() {
+        ArrowType::Int8 => array.as_primitive::<Int8Type>().value(row).to_string(),
+        ArrowType::Int16 => array.as_primitive::<Int16Type>().value(row).to_string(),
+        ArrowType::Int32 => array.as_primitive::<Int32Type>().value(row).to_string(),
+        ArrowType::Int64 => array.as_primitive::<Int64Type>().value(row).to_string(),
+        ArrowType::Utf8 => array.as_string::<i32>().value(row).to_string(),
+        ArrowType::LargeUtf8 => array.as_string::<i64>().value(row).to_string(),
+        other => unreachable!("{} columns are refused before reading", other),
+    };
+    Some(text)
+}
+
+/// Converts the rows of a record batch to rows of a table, or says why they do not match the
+/// table's columns.
+///
+/// # Arguments
+///
+/// * `batch` - Rows of the file.
+/// * `columns` - Column of the batch for each column of the table, None for NULLs.
+/// * `table` - Table the rows are for.
+fn batch_to_tuples(
+    batch: &RecordBatch,
+    columns: &[Option<usize>],
+    table: &Table,
+) -> Vec<Result<Tuple, String>> {
+    (0..batch.num_rows())
+        .map(|row| {
+            let values = columns
+                .iter()
+                .map(|column| column.and_then(|i| value_text(batch.column(i).as_ref(), row)))
+                .collect();
+            csv_utils::values_to_tuple(values, table)
+        })
+        .collect()
+}
+
+/// Imports an Apache Parquet file into an existing table, see `csv_utils::import_rows`.
+///
+/// The file's columns are matched with the table's by name. `INT` columns take 8 to 64 bit
+/// integers, the values out of their range are rejected, and `VARCHAR` columns take strings.
+/// Columns missing from the file are NULL.
+///
+/// # Arguments
+///
+/// * `table` - Table to store the data in.
+/// * `path` - Path to the Parquet file.
+/// * `tid` - Transaction id for inserting the tuples.
+/// * `commit_rows` - Rows committed at once, None to commit the whole file at its end.
+/// * `parallel` - Whether the file is decoded by a thread of its own.
+pub fn import_parquet(
+    table: &Table,
+    path: &str,
+    tid: TransactionId,
+    storage_manager: &StorageManager,
+    commit_rows: Option<usize>,
+    parallel: bool,
+) -> Result<ImportSummary, CrustyError> {
+    debug!(
+        "server::parquet_import trying to open file, path: {:?}",
+        path
+    );
+    let file = File::open(path)?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file).map_err(parquet_error)?;
+    let columns = match_columns(builder.schema(), table)?;
+    let reader = builder
+        .with_batch_size(BATCH_ROWS)
+        .build()
+        .map_err(parquet_error)?;
+    let rows = reader.flat_map(move |batch| match batch {
+        Ok(batch) => batch_to_tuples(&batch, &columns, table)
+            .into_iter()
+            .map(Ok)
+            .collect(),
+        Err(e) => vec![Err(parquet_error(e))],
+    });
+    csv_utils::import_rows(table, rows, tid, storage_manager, commit_rows, parallel)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow_array::{ArrayRef, Int64Array, StringArray};
+    use common::ids::{ContainerId, Permissions};
+    use common::row_format::RowFormat;
+    use common::storage_trait::{StorageTrait, ValueRefIterator};
+    use common::testutil::gen_random_dir;
+    use common::{Attribute, Field, TableSchema};
+    use parquet::arrow::ArrowWriter;
+    use std::fs;
+    use std::sync::Arc;
+
+    fn write_parquet(path: &std::path::Path, columns: Vec<(&str, ArrayRef)>) {
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_import_parquet() {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("people.parquet");
+        let ids: ArrayRef = Arc::new(Int64Array::from(vec![
+            Some(1),
+            Some(2),
+            None,
+            Some(1 << 40),
+        ]));
+        let names: ArrayRef = Arc::new(StringArray::from(vec![Some("ann"), None, Some("x"), None]));
+        write_parquet(&path, vec![("id", ids.clone()), ("name", names)]);
+        let schema = TableSchema::new(vec![
+            Attribute::new_not_null(String::from("people.id"), DataType::Int),
+            Attribute::new(String::from("name"), DataType::String),
+        ]);
+        let table = Table::new(String::from("people"), schema);
+        let path = path.to_string_lossy().to_string();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        let summary = import_parquet(&table, &path, tid, &sm, None, true).unwrap();
+        assert_eq!(2, summary.imported);
+        assert_eq!(
+            vec![
+                "row 3: no value for NOT NULL column people.id",
+                "row 4: 1099511627776 is not an INT for column people.id",
+            ],
+            summary.errors
+        );
+        let mut rows: Vec<Vec<Field>> = sm
+            .get_iterator(table.id as ContainerId, tid, Permissions::ReadOnly)
+            .collect_values()
+            .unwrap()
+            .iter()
+            .map(|bytes| RowFormat::decode(&bytes, &table.schema).unwrap().field_vals)
+            .collect();
+        rows.sort();
+        assert_eq!(
+            vec![
+                vec![Field::IntField(1), Field::StringField(String::from("ann"))],
+                vec![Field::IntField(2), Field::Null],
+            ],
+            rows
+        );
+
+        // a string column can't fill an INT column
+        let strings = dir.join("strings.parquet");
+        let names: ArrayRef = Arc::new(StringArray::from(vec!["1"]));
+        write_parquet(&strings, vec![("id", names)]);
+        let strings = strings.to_string_lossy().to_string();
+        assert!(import_parquet(&table, &strings, tid, &sm, None, false).is_err());
+        // nor can a file with a column the table does not have
+        let extra = dir.join("extra.parquet");
+        write_parquet(&extra, vec![("id", ids.clone()), ("age", ids)]);
+        let extra = extra.to_string_lossy().to_string();
+        assert!(import_parquet(&table, &extra, tid, &sm, None, false).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use common::CrustyError;
+use std::thread;
+use std::time::Duration;
+
+/// Longest wait between two attempts, however large the backoff has grown.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How a statement that runs in its own implicit transaction is retried when the transaction is
+/// aborted, e.g. by deadlock detection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt. 0 disables retrying.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every further retry up to `MAX_BACKOFF`.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(0, Duration::from_millis(10))
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_retries` - Number of retries after the first attempt.
+    /// * `backoff` - Wait before the first retry.
+    pub fn new(max_retries: u32, backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            backoff,
+        }
+    }
+
+    /// Wait before the given retry, counted from 0.
+    fn wait(&self, attempt: u32) -> Duration {
+        self.backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(MAX_BACKOFF)
+            .min(MAX_BACKOFF)
+    }
+
+    /// Runs `f` until it succeeds, fails with an error that is not retry advised, or runs out
+    /// of retries. The last error is returned in the latter cases.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Runs one attempt, in a new transaction each time.
+    pub fn run<T, F>(&self, mut f: F) -> Result<T, CrustyError>
+    where
+        F: FnMut() -> Result<T, CrustyError>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(e) if e.is_retry_advised() && attempt < self.max_retries => {
+                    let wait = self.wait(attempt);
+                    attempt += 1;
+                    info!(
+                        "Transaction aborted, retry {} of {} in {:?}",
+                        attempt, self.max_retries, wait
+                    );
+                    thread::sleep(wait);
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry() {
+        let policy = RetryPolicy::new(2, Duration::from_millis(1));
+        // Succeeds on the last retry.
+        let mut calls = 0;
+        let res = policy.run(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(CrustyError::TransactionAbortedError)
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(Ok(3), res);
+
+        // Gives up after the retries.
+        let mut calls = 0;
+        let res: Result<(), _> = policy.run(|| {
+            calls += 1;
+            Err(CrustyError::TransactionAbortedError)
+        });
+        assert_eq!(Err(CrustyError::TransactionAbortedError), res);
+        assert_eq!(3, calls);
+
+        // Other errors are not retried, and neither is anything by the default policy.
+        let mut calls = 0;
+        let res: Result<(), _> = policy.run(|| {
+            calls += 1;
+            Err(CrustyError::ExecutionError(String::from("bad")))
+        });
+        assert!(res.is_err());
+        assert_eq!(1, calls);
+        let mut calls = 0;
+        let res: Result<(), _> = RetryPolicy::default().run(|| {
+            calls += 1;
+            Err(CrustyError::TransactionAbortedError)
+        });
+        assert!(res.is_err());
+        assert_eq!(1, calls);
+    }
+
+    #[test]
+    fn test_retry_wait() {
+        let policy = RetryPolicy::new(u32::MAX, Duration::from_millis(10));
+        assert_eq!(Duration::from_millis(10), policy.wait(0));
+        assert_eq!(Duration::from_millis(40), policy.wait(2));
+        // the doubling stops at the cap instead of overflowing
+        assert_eq!(MAX_BACKOFF, policy.wait(40));
+        assert_eq!(MAX_BACKOFF, policy.wait(u32::MAX));
+        let policy = RetryPolicy::new(1, Duration::MAX);
+        assert_eq!(MAX_BACKOFF, policy.wait(0));
+        assert_eq!(MAX_BACKOFF, policy.wait(1));
+    }
+}
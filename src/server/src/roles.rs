@@ -0,0 +1,151 @@
+use common::CrustyError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// Name of the file in the metadata path that role assignments are persisted to.
+const ROLES_FILE: &str = "roles.json";
+
+/// What a user may do. Each role may do everything the roles before it may.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Queries and commands that only read.
+    ReadOnly,
+    /// Also statements and commands that create or change tables and their rows.
+    ReadWrite,
+    /// Also `\reset`, `\shutdown` and assigning roles.
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::ReadOnly => "readonly",
+            Role::ReadWrite => "readwrite",
+            Role::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = CrustyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "readonly" => Ok(Role::ReadOnly),
+            "readwrite" => Ok(Role::ReadWrite),
+            "admin" => Ok(Role::Admin),
+            _ => Err(CrustyError::ValidationError(format!(
+                "Unknown role {}, expected admin, readwrite or readonly",
+                s
+            ))),
+        }
+    }
+}
+
+/// Roles assigned to users, shared by all clients.
+pub struct RoleStore {
+    /// File the assignments are persisted to, if any.
+    path: Option<PathBuf>,
+    /// Role of clients that did not log in or whose user has no role assigned.
+    default_role: RwLock<Role>,
+    assignments: RwLock<BTreeMap<String, Role>>,
+}
+
+impl Default for RoleStore {
+    /// Assignments kept in memory only, everybody is an admin unless assigned another role.
+    fn default() -> Self {
+        Self {
+            path: None,
+            default_role: RwLock::new(Role::Admin),
+            assignments: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl RoleStore {
+    /// Loads the role assignments persisted in a metadata directory, if there are any.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata_path` - Directory the assignments are persisted in.
+    pub fn load(metadata_path: &str) -> Result<Self, CrustyError> {
+        let mut path = PathBuf::from(metadata_path);
+        path.push(ROLES_FILE);
+        let assignments = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| CrustyError::IOError(format!("Corrupt {}: {}", ROLES_FILE, e)))?,
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(Self {
+            path: Some(path),
+            assignments: RwLock::new(assignments),
+            ..Self::default()
+        })
+    }
+
+    /// Sets the role of users without an assigned role.
+    pub fn set_default_role(&self, role: Role) {
+        *self.default_role.write().unwrap() = role;
+    }
+
+    /// Returns the role of a user, or the default role if the user is not known.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - Name the client logged in with, if any.
+    pub fn role_of(&self, user: Option<&str>) -> Role {
+        user.and_then(|u| self.assignments.read().unwrap().get(u).copied())
+            .unwrap_or(*self.default_role.read().unwrap())
+    }
+
+    /// Assigns a role to a user and persists the assignments.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - Name of the user.
+    /// * `role` - Role the user gets.
+    pub fn assign(&self, user: &str, role: Role) -> Result<(), CrustyError> {
+        let mut assignments = self.assignments.write().unwrap();
+        assignments.insert(user.to_string(), role);
+        if let Some(path) = &self.path {
+            let json = serde_json::to_string_pretty(&*assignments)
+                .map_err(|e| CrustyError::IOError(e.to_string()))?;
+            fs::write(path, json)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::gen_random_dir;
+
+    #[test]
+    fn test_roles() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir)?;
+        let path = dir.to_string_lossy().to_string();
+        let roles = RoleStore::load(&path)?;
+        assert_eq!(Role::Admin, roles.role_of(None));
+        roles.set_default_role(Role::ReadOnly);
+        assert_eq!(Role::ReadOnly, roles.role_of(Some("ann")));
+        roles.assign("ann", "readwrite".parse()?)?;
+        assert_eq!(Role::ReadWrite, roles.role_of(Some("ann")));
+        assert!(Role::Admin > Role::ReadWrite && Role::ReadWrite > Role::ReadOnly);
+        assert!("root".parse::<Role>().is_err());
+
+        // Assignments survive a restart
+        let reloaded = RoleStore::load(&path)?;
+        assert_eq!(Role::ReadWrite, reloaded.role_of(Some("ann")));
+        assert_eq!(Role::Admin, reloaded.role_of(Some("bob")));
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
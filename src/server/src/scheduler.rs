@@ -0,0 +1,219 @@
+use common::CrustyError;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Condvar, Mutex};
+
+/// Interactive statements admitted in a row, while batch statements wait, before a batch
+/// statement gets the next turn. Keeps a steady stream of interactive queries from holding
+/// batch work off forever.
+const INTERACTIVE_BURST: usize = 8;
+
+/// How urgent the statements of a session are when the server is busy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Priority {
+    /// Queries a user waits on. Admitted ahead of waiting batch statements.
+    #[default]
+    Interactive,
+    /// Imports, reports and other long running work.
+    Batch,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Priority::Interactive => "interactive",
+            Priority::Batch => "batch",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Priority {
+    type Err = CrustyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "interactive" => Ok(Priority::Interactive),
+            "batch" => Ok(Priority::Batch),
+            _ => Err(CrustyError::ValidationError(format!(
+                "priority must be interactive or batch, not {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    /// Statements admitted and not finished yet.
+    running: usize,
+    /// Interactive statements waiting to be admitted.
+    interactive_waiting: usize,
+    /// Batch statements waiting to be admitted.
+    batch_waiting: usize,
+    /// Interactive statements admitted since a batch statement last was, while one waited.
+    interactive_streak: usize,
+}
+
+/// Admission control of the statements of all clients.
+///
+/// At most `max_running` statements run at once, the others wait for one to finish. Waiting
+/// interactive statements are admitted before waiting batch statements, except that a batch
+/// statement gets a turn after `INTERACTIVE_BURST` interactive ones.
+pub struct Scheduler {
+    /// Most statements that run at once, 0 for no limit.
+    max_running: usize,
+    state: Mutex<SchedulerState>,
+    turn: Condvar,
+}
+
+/// A statement's turn to run, given back when dropped.
+pub struct Admission<'a> {
+    scheduler: &'a Scheduler,
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        self.scheduler.state.lock().unwrap().running -= 1;
+        self.scheduler.turn.notify_all();
+    }
+}
+
+impl Default for Scheduler {
+    /// Admits every statement right away.
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Scheduler {
+    /// Creates a scheduler.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_running` - Most statements that run at once, 0 for no limit.
+    pub fn new(max_running: usize) -> Self {
+        Self {
+            max_running,
+            state: Mutex::new(SchedulerState::default()),
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Waits until a statement of the given priority may run.
+    ///
+    /// # Arguments
+    ///
+    /// * `priority` - Priority of the session the statement belongs to.
+    pub fn admit(&self, priority: Priority) -> Admission<'_> {
+        let mut state = self.state.lock().unwrap();
+        match priority {
+            Priority::Interactive => state.interactive_waiting += 1,
+            Priority::Batch => state.batch_waiting += 1,
+        }
+        while !self.may_run(&state, priority) {
+            state = self.turn.wait(state).unwrap();
+        }
+        match priority {
+            Priority::Interactive => {
+                state.interactive_waiting -= 1;
+                if state.batch_waiting > 0 {
+                    state.interactive_streak += 1;
+                }
+            }
+            Priority::Batch => {
+                state.batch_waiting -= 1;
+                state.interactive_streak = 0;
+            }
+        }
+        state.running += 1;
+        Admission { scheduler: self }
+    }
+
+    /// Whether a waiting statement of the given priority is next.
+    fn may_run(&self, state: &SchedulerState, priority: Priority) -> bool {
+        if self.max_running > 0 && state.running >= self.max_running {
+            return false;
+        }
+        let batch_turn = state.batch_waiting > 0 && state.interactive_streak >= INTERACTIVE_BURST;
+        match priority {
+            Priority::Interactive => !batch_turn,
+            Priority::Batch => state.interactive_waiting == 0 || batch_turn,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Waits until `n` statements wait for their turn.
+    fn wait_for_waiting(scheduler: &Scheduler, n: usize) {
+        while {
+            let state = scheduler.state.lock().unwrap();
+            state.interactive_waiting + state.batch_waiting < n
+        } {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!(Priority::Batch, "BATCH".parse().unwrap());
+        assert_eq!("interactive", Priority::default().to_string());
+        assert!("urgent".parse::<Priority>().is_err());
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let scheduler = Scheduler::default();
+        let a = scheduler.admit(Priority::Batch);
+        let b = scheduler.admit(Priority::Interactive);
+        assert_eq!(2, scheduler.state.lock().unwrap().running);
+        drop(a);
+        drop(b);
+        assert_eq!(0, scheduler.state.lock().unwrap().running);
+    }
+
+    #[test]
+    fn test_interactive_first() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let first = scheduler.admit(Priority::Batch);
+        let mut handles = Vec::new();
+        for (i, priority) in [Priority::Batch, Priority::Interactive].iter().enumerate() {
+            let (shared, order, priority) = (Arc::clone(&scheduler), Arc::clone(&order), *priority);
+            handles.push(thread::spawn(move || {
+                let _turn = shared.admit(priority);
+                order.lock().unwrap().push(priority);
+            }));
+            wait_for_waiting(&scheduler, i + 1);
+        }
+        // the batch statement waited longer, the interactive one still goes first
+        drop(first);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(
+            vec![Priority::Interactive, Priority::Batch],
+            *order.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_batch_not_starved() {
+        let scheduler = Scheduler::new(1);
+        let mut state = scheduler.state.lock().unwrap();
+        state.batch_waiting = 1;
+        state.interactive_waiting = 1;
+        assert!(!scheduler.may_run(&state, Priority::Batch));
+        state.interactive_streak = INTERACTIVE_BURST;
+        assert!(scheduler.may_run(&state, Priority::Batch));
+        assert!(!scheduler.may_run(&state, Priority::Interactive));
+        state.running = 1;
+        assert!(!scheduler.may_run(&state, Priority::Batch));
+    }
+}
@@ -1,27 +1,70 @@
 use std::collections::HashMap;
 use std::fs;
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use crate::cache::{CacheRegistry, PlanCache, DEFAULT_PLAN_CACHE_BYTES, PLAN_CACHE_NAME};
 use crate::csv_utils;
 use crate::database_state::DatabaseState;
+use crate::json_import;
+#[cfg(feature = "parquet")]
+use crate::parquet_import;
+use crate::retry::RetryPolicy;
+use crate::roles::RoleStore;
+use crate::scheduler::Scheduler;
+use crate::session::SessionRegistry;
+use crate::slow_log::SlowLog;
+use common::catalog;
+use common::catalog::Catalog;
+use common::storage_trait::StorageTrait;
 use common::table::Table;
 use common::CrustyError;
 use txn_manager::transactions::Transaction;
 
-use crate::StorageManager;
+/// Ending of the names of the files in the metadata path that the catalog of a database is
+/// persisted to, as in `<database>.catalog.json`.
+const CATALOG_FILE_SUFFIX: &str = ".catalog.json";
 
 pub struct ServerState {
-    /// Path to database metadata files.
+    /// Path to the table data, in a directory per database as in `<storage_path>/<database>`.
     pub storage_path: String,
-    /// Path to heap files of the tables.
+    /// Path to database metadata files.
     pub metadata_path: String,
 
     // maps database id to DatabaseState
     pub id_to_db: RwLock<HashMap<u64, Arc<DatabaseState>>>,
 
     // runtime_information
-    /// active connections indicates what client_id is connected to what db_id
-    pub active_connections: RwLock<HashMap<u64, u64>>,
+    /// Sessions of the connected clients, with the database each is connected to.
+    pub sessions: SessionRegistry,
+
+    /// Retries of statements whose implicit transaction was aborted, for every client.
+    pub retry_policy: RetryPolicy,
+
+    /// Rows a join may produce before its query is aborted, the default of every client.
+    pub max_operator_rows: Option<usize>,
+
+    /// Time a query may run before it is aborted, the default of every client.
+    pub max_query_time: Option<Duration>,
+
+    /// Threads the scans of a query are split among, the default of every client.
+    pub parallelism: usize,
+
+    /// Roles of the users, persisted with the database metadata.
+    pub roles: Arc<RoleStore>,
+
+    /// Caches shared by all clients, inspected and cleared with `\cache`.
+    pub caches: CacheRegistry,
+
+    /// Plans of the queries of all clients, registered with the other caches.
+    pub plan_cache: Arc<PlanCache>,
+
+    /// Admission of the statements of all clients, by the priority of their sessions.
+    pub scheduler: Arc<Scheduler>,
+
+    /// Statements of all clients that ran for too long, listed with `\slowlog`.
+    pub slow_log: Arc<SlowLog>,
 }
 
 impl ServerState {
@@ -30,9 +73,21 @@ impl ServerState {
     pub fn new(metadata_path: String, storage_path: String) -> Result<Self, CrustyError> {
         // let meta_path = metadata_path.clone();
         // let stor_path = storage_path.clone();
+        let caches = CacheRegistry::default();
+        let plan_cache = Arc::new(PlanCache::new(PLAN_CACHE_NAME, DEFAULT_PLAN_CACHE_BYTES));
+        caches.register(plan_cache.clone());
         let server_state = ServerState {
             id_to_db: RwLock::new(HashMap::new()),
-            active_connections: RwLock::new(HashMap::new()),
+            sessions: SessionRegistry::default(),
+            retry_policy: RetryPolicy::default(),
+            max_operator_rows: None,
+            max_query_time: None,
+            parallelism: 1,
+            roles: Arc::new(RoleStore::load(&metadata_path)?),
+            caches,
+            plan_cache,
+            scheduler: Arc::new(Scheduler::default()),
+            slow_log: Arc::new(SlowLog::default()),
             /// Path to database metadata files.
             metadata_path,
             /// Path to heap files of the tables.
@@ -43,28 +98,25 @@ impl ServerState {
         fs::create_dir_all(&server_state.storage_path)?;
         fs::create_dir_all(&server_state.metadata_path)?;
 
-/*
-        // Create databases
-        debug!("Looking for databases in {}", &server_state.storage_path);
-        let paths = fs::read_dir(&server_state.storage_path).unwrap();
-        {
-            // for each path, create a DatabaseState
-            for entry in paths {
-                let path = entry.unwrap().path();
-                debug!("Creating DatabaseState from path {:?}", path);
-                let db_state = Arc::new(
-                    DatabaseState::new_from_path(path, server_state.storage_path.clone()).unwrap(),
-                );
-                server_state
-                    .id_to_db
-                    .write()
-                    .unwrap()
-                    .insert(db_state.id, db_state);
+        // Load the databases whose catalogs were persisted.
+        debug!("Looking for databases in {}", &server_state.metadata_path);
+        for entry in fs::read_dir(&server_state.metadata_path)? {
+            let path = entry?.path();
+            let file_name = path.file_name().and_then(|name| name.to_str());
+            if !matches!(file_name, Some(name) if name.ends_with(CATALOG_FILE_SUFFIX)) {
+                continue;
             }
+            let db_state = Arc::new(DatabaseState::new_from_path(
+                path,
+                server_state.storage_path.clone(),
+            )?);
+            info!("Loaded database {}", db_state.name);
+            server_state
+                .id_to_db
+                .write()
+                .unwrap()
+                .insert(db_state.id, db_state);
         }
-        // TODO: does this pattern to make mutable things immutable make sense?
-        let server_state = server_state;
-*/
         Ok(server_state)
     }
 
@@ -78,128 +130,349 @@ impl ServerState {
         Err(CrustyError::CrustyError(String::from("db_name not found!")))
     }
 
+    /// Gets the database a client is connected to.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_id` - Id of the client.
+    pub fn get_connected_db(&self, client_id: u64) -> Result<Arc<DatabaseState>, CrustyError> {
+        let db_id = self.sessions.database(client_id);
+        let db_ref = self.id_to_db.read().unwrap();
+        db_id
+            .and_then(|db_id| db_ref.get(&db_id))
+            .cloned()
+            .ok_or_else(|| CrustyError::CrustyError(String::from("No active DB or DB not found")))
+    }
+
+    /// Shuts down the storage of every database, so the writes it still holds in memory are
+    /// persisted. Must be called before the process exits, which skips dropping the databases.
+    /// Every database is shut down even if one fails, the first error is returned.
     pub(crate) fn shutdown(&self) -> Result<(), CrustyError> {
         info!("Shutting down");
-        Ok(())
+        let mut res = Ok(());
+        for db_state in self.id_to_db.read().unwrap().values() {
+            if let Err(e) = db_state.storage_manager.shutdown() {
+                error!("Could not shut down the storage of {}: {}", db_state.name, e);
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
     }
 
-    /// Resets database to an empty database.
-    pub fn reset_database(&self, _storage_manager: &StorageManager) -> Result<String, CrustyError> {
-        // Clear data structures state
-        info!("Resetting database... [To implement]");
-        // self.id_to_db.write().unwrap().clear();
-        // self.active_connections.write().unwrap().clear();
-        // FIXME: uncomment when sm.reset is implemented
-        // storage_manager.reset();
+    /// Resets the server to have no databases. The storage of every database is reset, the
+    /// clients are disconnected from their databases, the caches are cleared and the persisted
+    /// catalogs are removed. Roles and the slow query log are kept.
+    pub fn reset_database(&self) -> Result<String, CrustyError> {
+        info!("Resetting database...");
+        let mut id_map = self.id_to_db.write().unwrap();
+        for db_state in id_map.values() {
+            db_state.storage_manager.reset()?;
+        }
+        id_map.clear();
+        self.sessions.disconnect_all();
+        self.caches.clear(None)?;
 
-        // Clear storage.
-        // fs::remove_dir_all(&self.metadata_path).unwrap();
-        // fs::remove_dir_all(&self.storage_path).unwrap();
-        // fs::create_dir_all(&self.metadata_path).unwrap();
-        // fs::create_dir_all(&self.storage_path).unwrap();
+        // Clear the catalogs, or the databases would be loaded again on the next start.
+        for entry in fs::read_dir(&self.metadata_path)? {
+            let path = entry?.path();
+            let file_name = path.file_name().and_then(|name| name.to_str());
+            if matches!(file_name, Some(name) if name.ends_with(CATALOG_FILE_SUFFIX)) {
+                fs::remove_file(path)?;
+            }
+        }
 
         info!("Resetting database...DONE");
         Ok(String::from("Reset"))
     }
 
+    /// Closes the session of a client that disconnected, and disconnects it from its database.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_id` - Id of the client's session.
     pub fn close_client_connection(&self, client_id: u64) {
         // indicate DB this client is disconnecting
-        let db_id_ref = self.active_connections.read().unwrap();
-        match db_id_ref.get(&client_id) {
+        let session = self.sessions.close(client_id);
+        match session.and_then(|session| session.db_id) {
             Some(db_id) => {
-                let db_ref = self.id_to_db.read().unwrap();
-                let db = db_ref.get(db_id).unwrap();
-                db.close_client_connection(client_id, self.metadata_path.clone());
+                if let Some(db) = self.id_to_db.read().unwrap().get(&db_id) {
+                    db.close_client_connection(client_id);
+                }
             }
             None => {
                 debug!("Client was not connected to DB");
             }
         };
-
-        // remove this client from active connections
-        self.active_connections.write().unwrap().remove(&client_id);
         info!(
             "Shutting down client connection with ID: {:?}...",
             client_id
         );
     }
 
-    /// Creates a new database with name.
+    /// Lists the sessions of the connected clients, one line each, with the database each is
+    /// connected to and the transaction it has open.
+    pub fn list_sessions(&self) -> String {
+        let id_map = self.id_to_db.read().unwrap();
+        let lines: Vec<String> = self
+            .sessions
+            .list()
+            .into_iter()
+            .map(|(id, session)| {
+                let db_name = session
+                    .db_id
+                    .and_then(|db_id| id_map.get(&db_id))
+                    .map_or("-", |db| db.name.as_str());
+                let transaction = session
+                    .transaction
+                    .map_or(String::from("-"), |tid| tid.id().to_string());
+                format!(
+                    "{} peer={} database={} transaction={}",
+                    id, session.peer, db_name, transaction
+                )
+            })
+            .collect();
+        if lines.is_empty() {
+            return String::from("No sessions");
+        }
+        lines.join("\n")
+    }
+
+    /// File in the metadata path the catalog of a database is persisted to.
     ///
     /// # Arguments
     ///
-    /// * `name` - Name of the new database.
+    /// * `db_name` - Name of the database.
+    fn catalog_file(&self, db_name: &str) -> PathBuf {
+        PathBuf::from(&self.metadata_path).join(format!("{}{}", db_name, CATALOG_FILE_SUFFIX))
+    }
+
+    /// Creates a new database with name. Its catalog is persisted right away and whenever it
+    /// changes, so the database is loaded again when the server restarts.
     ///
-    /// # Notes
+    /// # Arguments
     ///
-    /// * The database is currently in-memory.
+    /// * `name` - Name of the new database.
     pub fn create_database(&self, name: String) -> Result<String, CrustyError> {
+        let mut id_map = self.id_to_db.write().unwrap();
+        if id_map.contains_key(&DatabaseState::get_database_id(name.clone())) {
+            return Err(CrustyError::CrustyError(format!(
+                "Database {} already exists",
+                name
+            )));
+        }
         // Create new DB
-        let db_state =
-            Arc::new(DatabaseState::new_from_name(&name, self.storage_path.clone()).unwrap());
+        let db_state = DatabaseState::new_from_name(&name, self.storage_path.clone())?
+            .persist_catalog_to(self.catalog_file(&name))?;
         // Represent newly created DB in server state
-        self.id_to_db.write().unwrap().insert(db_state.id, db_state);
+        id_map.insert(db_state.id, Arc::new(db_state));
         Ok(format!("Created database {:?}", &name))
     }
 
+    /// Reports the space used by every database and each of its tables, one line each.
+    pub fn disk_usage(&self) -> Result<String, CrustyError> {
+        let id_map = self.id_to_db.read().unwrap();
+        let mut dbs: Vec<&Arc<DatabaseState>> = id_map.values().collect();
+        dbs.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut rows = Vec::new();
+        for db_state in dbs {
+            let (total, tables) = db_state.disk_usage()?;
+            rows.push((db_state.name.clone(), total));
+            for (name, usage) in tables {
+                rows.push((format!("  {}", name), usage));
+            }
+        }
+        if rows.is_empty() {
+            return Ok(String::from("No databases"));
+        }
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap().max(4);
+        let mut lines = vec![format!(
+            "{:<width$} {:>12} {:>12} {:>12} {:>12}",
+            "name",
+            "disk",
+            "live",
+            "free",
+            "index",
+            width = width
+        )];
+        for (name, usage) in rows {
+            lines.push(format!(
+                "{:<width$} {:>12} {:>12} {:>12} {:>12}",
+                name,
+                usage.disk_bytes,
+                usage.live_bytes,
+                usage.free_bytes,
+                usage.index_bytes,
+                width = width
+            ));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Exports the catalog of every database as a JSON document.
+    pub fn dump_catalog(&self) -> Result<String, CrustyError> {
+        let id_map = self.id_to_db.read().unwrap();
+        let dbs: Vec<&common::database::Database> =
+            id_map.values().map(|db_state| &db_state.database).collect();
+        catalog::export_catalog_json(&dbs)
+    }
+
+    /// Creates the databases described by a JSON catalog file, and persists their catalogs.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the catalog file, as written by `\catalog dump`.
+    pub fn load_catalog(&self, path: &str) -> Result<String, CrustyError> {
+        let json = fs::read_to_string(path)?;
+        let dbs = catalog::import_catalog_json(&json)?;
+        let mut id_map = self.id_to_db.write().unwrap();
+        for db in &dbs {
+            if id_map.contains_key(&DatabaseState::get_database_id(db.name.clone())) {
+                return Err(CrustyError::CrustyError(format!(
+                    "Database {} already exists",
+                    db.name
+                )));
+            }
+        }
+        let mut names = Vec::new();
+        for db in dbs {
+            names.push(db.name.clone());
+            let catalog_file = self.catalog_file(&db.name);
+            let db_state = DatabaseState::new_from_database(db, self.storage_path.clone())?
+                .persist_catalog_to(catalog_file)?;
+            id_map.insert(db_state.id, Arc::new(db_state));
+        }
+        Ok(format!("Loaded databases {:?}", names))
+    }
+
     pub fn connect_to_db(&self, db_name: String, client_id: u64) -> Result<String, CrustyError> {
         let db_id = self.get_db_id_from_db_name(&db_name)?;
         let map_ref = self.id_to_db.read().unwrap();
         let db_state = map_ref.get(&db_id).unwrap();
-        {
-            let mut reference = self.active_connections.write().unwrap();
-            reference.insert(client_id, db_state.id);
+        let previous = self.sessions.set_database(client_id, db_state.id)?;
+        if let Some(previous) = previous.and_then(|id| map_ref.get(&id)) {
+            previous.close_client_connection(client_id);
         }
         db_state.register_new_client_connection(client_id);
         Ok(format!("Connected to database {:?}", &db_name))
     }
 
-    /// Import database from csv file at path.
+    /// Reports the inferred schema of a CSV file and the rows that would not load into a table of
+    /// the connected database, without loading anything.
     ///
     /// # Arguments
     ///
-    /// * `path` - Path of the csv file containing database.
-    pub fn import_database(&self, path: String, client_id: u64) -> Result<String, CrustyError> {
-        // TODO: Fix serialization.
-        let mut flag = false;
-
-        // TODO: Use itertools to clean this up?
-        let mut new_path = "";
-        let mut table_name = "";
-        for token in path.split_whitespace() {
-            if flag {
-                table_name = token;
-            } else {
-                new_path = token;
-                flag = true;
+    /// * `path_and_name` - Options of the file, see `CsvOptions::parse_args`, the path of the
+    ///   CSV file and the name of the table, separated by whitespace.
+    /// * `client_id` - Client whose database the table is in.
+    pub fn analyze_import(
+        &self,
+        path_and_name: &str,
+        client_id: u64,
+    ) -> Result<String, CrustyError> {
+        let (options, args) = csv_utils::CsvOptions::parse_args(path_and_name)?;
+        let (path, table_name) = match args[..] {
+            [path, name] => (path, name),
+            _ => {
+                return Err(CrustyError::ValidationError(String::from(
+                    "usage: \\i --analyze [--header] [--delimiter <char>] <path> <table_name>",
+                )))
             }
-        }
+        };
+        let db_state = self.get_connected_db(client_id)?;
+        let table_ptr = db_state
+            .database
+            .get_table_ptr(Table::get_table_id(table_name))?;
+        let table = table_ptr.read().unwrap();
+        Ok(csv_utils::analyze_csv(&table, path, &options)?.to_string())
+    }
 
+    /// Imports the rows of a file into a table of the client's database, and returns how
+    /// many were imported and why the others were rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Arguments of `\\i`: options of the file, see `CsvOptions::parse_args`, the
+    ///   path of the file and the name of the table.
+    /// * `client_id` - Client whose database the table is in.
+    /// * `commit_rows` - Rows committed at once, None to commit the whole file at its end.
+    /// * `format` - Format of the file. Only CSV files take `--header` and `--delimiter`.
+    pub fn import_database(
+        &self,
+        path: String,
+        client_id: u64,
+        commit_rows: Option<usize>,
+        format: csv_utils::ImportFormat,
+    ) -> Result<String, CrustyError> {
+        let (options, args) = csv_utils::CsvOptions::parse_args(&path)?;
+        let command = match format {
+            csv_utils::ImportFormat::Csv => "\\i [--header] [--delimiter <char>]",
+            csv_utils::ImportFormat::Json => "\\ij",
+            csv_utils::ImportFormat::Parquet => "\\ip",
+        };
+        let csv_only =
+            options.header || options.delimiter != csv_utils::CsvOptions::default().delimiter;
+        let (new_path, table_name) = match args[..] {
+            [path, name] if format == csv_utils::ImportFormat::Csv || !csv_only => (path, name),
+            _ => {
+                return Err(CrustyError::ValidationError(format!(
+                    "usage: {} [--parallel] <path> <table_name>",
+                    command
+                )))
+            }
+        };
         let txn = Transaction::new();
 
-        let db_id_ref = self.active_connections.read().unwrap();
-        let db_id = db_id_ref.get(&client_id).unwrap();
-        let db_state_ref = self.id_to_db.read().unwrap();
-        let db_state = db_state_ref.get(db_id).unwrap();
+        let db_state = self.get_connected_db(client_id)?;
         let db = &db_state.database;
         let tables = db.tables.read().unwrap();
         let table_id = Table::get_table_id(table_name);
 
         // Check if table name exists in active database.
         if let Some(table) = tables.get(&table_id) {
-            let table_ref = &table.read().unwrap();
-            // FIXME: Error check on import_csv.
-            let _ = csv_utils::import_csv(
-                table_ref,
-                new_path.to_string(),
-                txn.tid(),
-                &db_state.storage_manager,
-            )?;
-            Ok(format!(
-                "Data from path: {:?} imported to table: {:?}",
-                &path,
-                table_name.clone()
-            ))
+            let summary = {
+                let table_ref = &table.read().unwrap();
+                let sm = &db_state.storage_manager;
+                match format {
+                    csv_utils::ImportFormat::Csv => csv_utils::import_csv(
+                        table_ref,
+                        new_path.to_string(),
+                        txn.tid(),
+                        sm,
+                        commit_rows,
+                        &options,
+                    )?,
+                    csv_utils::ImportFormat::Json => json_import::import_json(
+                        table_ref,
+                        new_path,
+                        txn.tid(),
+                        sm,
+                        commit_rows,
+                        options.parallel,
+                    )?,
+                    #[cfg(feature = "parquet")]
+                    csv_utils::ImportFormat::Parquet => parquet_import::import_parquet(
+                        table_ref,
+                        new_path,
+                        txn.tid(),
+                        sm,
+                        commit_rows,
+                        options.parallel,
+                    )?,
+                    #[cfg(not(feature = "parquet"))]
+                    csv_utils::ImportFormat::Parquet => {
+                        return Err(CrustyError::ValidationError(String::from(
+                            "Parquet files can't be imported, the server was built without \
+                             the parquet feature",
+                        )))
+                    }
+                }
+            };
+            // the imported rows are likely most of the table, so its statistics are refreshed
+            drop(tables);
+            db_state.analyze_table(table_name)?;
+            Ok(summary.to_string())
         } else {
             Err(CrustyError::CrustyError(String::from(
                 "Table does not exist",
@@ -207,3 +480,41 @@ impl ServerState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::gen_random_dir;
+    use sqlparser::ast::Statement;
+    use sqlparser::dialect::GenericDialect;
+    use sqlparser::parser::Parser;
+
+    #[test]
+    fn test_reset_database() -> Result<(), CrustyError> {
+        let dir = gen_random_dir();
+        let metadata_path = dir.join("db").to_string_lossy().to_string();
+        let storage_path = dir.join("hf").to_string_lossy().to_string();
+        let server_state = ServerState::new(metadata_path.clone(), storage_path.clone())?;
+        let client_id = server_state.sessions.open("127.0.0.1");
+        server_state.create_database(String::from("reset"))?;
+        server_state.connect_to_db(String::from("reset"), client_id)?;
+        let sql = String::from("CREATE TABLE t (a INT)");
+        if let Statement::CreateTable { columns, .. } =
+            &Parser::parse_sql(&GenericDialect {}, sql).unwrap()[0]
+        {
+            server_state
+                .get_connected_db(client_id)?
+                .create_table("t", columns)?;
+        }
+
+        assert_eq!("Reset", server_state.reset_database()?);
+        assert!(server_state.id_to_db.read().unwrap().is_empty());
+        assert!(server_state.get_connected_db(client_id).is_err());
+        // the database is not loaded again on the next start, and can be created again
+        let restarted = ServerState::new(metadata_path, storage_path)?;
+        assert!(restarted.id_to_db.read().unwrap().is_empty());
+        server_state.create_database(String::from("reset"))?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
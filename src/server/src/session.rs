@@ -0,0 +1,558 @@
+use crate::scheduler::Priority;
+use common::ids::TransactionId;
+use common::{CrustyError, Field};
+use queryexe::opiterator::{CancelToken, ScanOptions, SortOptions};
+use queryexe::query::{PlanOptions, ResultChecksum};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Variables a client stored with `\set`, valid until the client disconnects.
+///
+/// Variables are referenced in later statements as `:name`.
+#[derive(Default)]
+pub struct SessionVariables {
+    vars: BTreeMap<String, Field>,
+}
+
+impl SessionVariables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores a value, replacing any previous value of the variable.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the variable.
+    /// * `value` - Value of the variable.
+    pub fn set(&mut self, name: &str, value: Field) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    /// Checks that a variable name is made of letters, digits and underscores.
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    /// Lists the variables as `name = value` lines.
+    pub fn list(&self) -> String {
+        if self.vars.is_empty() {
+            return String::from("No variables");
+        }
+        self.vars
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, Self::to_literal(value)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// SQL literal for a field.
+    fn to_literal(value: &Field) -> String {
+        match value {
+            Field::Null => String::from("NULL"),
+            Field::IntField(i) => i.to_string(),
+            Field::StringField(s) => format!("'{}'", s.replace('\'', "''")),
+        }
+    }
+
+    /// Replaces every `:name` outside of string literals with the variable's value as a SQL literal.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Statement to substitute variables in.
+    pub fn substitute(&self, sql: &str) -> Result<String, CrustyError> {
+        let mut out = String::with_capacity(sql.len());
+        let mut chars = sql.chars().peekable();
+        let mut in_string = false;
+        while let Some(c) = chars.next() {
+            if c == '\'' {
+                in_string = !in_string;
+                out.push(c);
+            } else if c == ':' && !in_string {
+                let mut name = String::new();
+                while let Some(&n) = chars.peek() {
+                    if n.is_ascii_alphanumeric() || n == '_' {
+                        name.push(n);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if name.is_empty() {
+                    out.push(c);
+                    continue;
+                }
+                match self.vars.get(&name) {
+                    Some(value) => out.push_str(&Self::to_literal(value)),
+                    None => {
+                        return Err(CrustyError::ValidationError(format!(
+                            "Unknown variable :{}",
+                            name
+                        )))
+                    }
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Format query results are sent to the client in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Rows as aligned text.
+    #[default]
+    Text,
+    /// Rows encoded as an Apache Arrow IPC stream, which the client saves to a file.
+    Arrow,
+}
+
+/// Settings a client changed with `SET name = value`, valid until the client disconnects.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SessionSettings {
+    /// Batch size and prefetching of the scans of the client's queries.
+    pub scan: ScanOptions,
+    /// Memory the sorts of the client's queries may use before spilling.
+    pub sort: SortOptions,
+    /// Whether query results carry a checksum of their rows.
+    pub checksum: ResultChecksum,
+    /// Maximum number of rows a join of the client's queries may produce, None for no limit.
+    pub max_operator_rows: Option<usize>,
+    /// Time a query of the client may run before it is aborted, None for no limit.
+    pub max_query_time: Option<Duration>,
+    /// Number of threads the scans of the client's queries are split among, 0 and 1 for
+    /// none but the client's.
+    pub parallelism: usize,
+    /// Whether the client's statements are admitted ahead of batch work when the server is busy.
+    pub priority: Priority,
+    /// Rows of a CSV import committed at once, None to commit the whole file at its end.
+    pub import_commit_rows: Option<usize>,
+    /// Format the client's query results are sent in.
+    pub output_format: OutputFormat,
+}
+
+impl SessionSettings {
+    /// Changes a setting.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the setting, `scan_batch_size`, `scan_prefetch_pages`, `sort_memory`,
+    ///   `result_checksum`, `max_operator_rows`, `max_query_time`, `parallelism`, `priority`,
+    ///   `import_commit_rows` or `output_format`.
+    /// * `value` - New value of the setting.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), CrustyError> {
+        let parsed = value.parse::<usize>().map_err(|_| {
+            CrustyError::ValidationError(format!(
+                "{} must be a non-negative integer, not {}",
+                name, value
+            ))
+        });
+        match name.to_lowercase().as_str() {
+            "scan_batch_size" => match parsed? {
+                0 => {
+                    return Err(CrustyError::ValidationError(String::from(
+                        "scan_batch_size must be at least 1",
+                    )))
+                }
+                n => self.scan.batch_size = n,
+            },
+            "scan_prefetch_pages" => self.scan.prefetch_pages = parsed?,
+            "sort_memory" => match parsed? {
+                0 => {
+                    return Err(CrustyError::ValidationError(String::from(
+                        "sort_memory must be at least 1",
+                    )))
+                }
+                n => self.sort.memory_limit = n,
+            },
+            "result_checksum" => {
+                self.checksum = match value.to_lowercase().as_str() {
+                    "off" => ResultChecksum::Off,
+                    "on" => ResultChecksum::On,
+                    "only" => ResultChecksum::Only,
+                    _ => {
+                        return Err(CrustyError::ValidationError(format!(
+                            "result_checksum must be off, on or only, not {}",
+                            value
+                        )))
+                    }
+                }
+            }
+            "max_operator_rows" => {
+                self.max_operator_rows = match value.to_lowercase().as_str() {
+                    "off" => None,
+                    _ => match parsed? {
+                        0 => None,
+                        n => Some(n),
+                    },
+                }
+            }
+            "max_query_time" => {
+                self.max_query_time = match value.to_lowercase().as_str() {
+                    "off" => None,
+                    _ => match parsed? {
+                        0 => None,
+                        ms => Some(Duration::from_millis(ms as u64)),
+                    },
+                }
+            }
+            "parallelism" => match parsed? {
+                0 => {
+                    return Err(CrustyError::ValidationError(String::from(
+                        "parallelism must be at least 1",
+                    )))
+                }
+                n => self.parallelism = n,
+            },
+            "priority" => self.priority = value.parse()?,
+            "import_commit_rows" => {
+                self.import_commit_rows = match value.to_lowercase().as_str() {
+                    "off" => None,
+                    _ => match parsed? {
+                        0 => None,
+                        n => Some(n),
+                    },
+                }
+            }
+            "output_format" => {
+                self.output_format = match value.to_lowercase().as_str() {
+                    "text" => OutputFormat::Text,
+                    "arrow" if cfg!(feature = "arrow") => OutputFormat::Arrow,
+                    "arrow" => {
+                        return Err(CrustyError::ValidationError(String::from(
+                            "output_format arrow needs a server built with the arrow feature",
+                        )))
+                    }
+                    _ => {
+                        return Err(CrustyError::ValidationError(format!(
+                            "output_format must be text or arrow, not {}",
+                            value
+                        )))
+                    }
+                }
+            }
+            _ => return Err(Self::unknown(name)),
+        }
+        Ok(())
+    }
+
+    /// Gets the value of a setting.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the setting.
+    pub fn get(&self, name: &str) -> Result<String, CrustyError> {
+        match name.to_lowercase().as_str() {
+            "scan_batch_size" => Ok(self.scan.batch_size.to_string()),
+            "scan_prefetch_pages" => Ok(self.scan.prefetch_pages.to_string()),
+            "sort_memory" => Ok(self.sort.memory_limit.to_string()),
+            "result_checksum" => Ok(match self.checksum {
+                ResultChecksum::Off => String::from("off"),
+                ResultChecksum::On => String::from("on"),
+                ResultChecksum::Only => String::from("only"),
+            }),
+            "max_operator_rows" => Ok(self
+                .max_operator_rows
+                .map_or_else(|| String::from("off"), |n| n.to_string())),
+            "max_query_time" => Ok(self
+                .max_query_time
+                .map_or_else(|| String::from("off"), |t| t.as_millis().to_string())),
+            "parallelism" => Ok(self.parallelism.max(1).to_string()),
+            "priority" => Ok(self.priority.to_string()),
+            "import_commit_rows" => Ok(self
+                .import_commit_rows
+                .map_or_else(|| String::from("off"), |n| n.to_string())),
+            "output_format" => Ok(match self.output_format {
+                OutputFormat::Text => String::from("text"),
+                OutputFormat::Arrow => String::from("arrow"),
+            }),
+            _ => Err(Self::unknown(name)),
+        }
+    }
+
+    /// Options of the operators of the client's queries.
+    pub fn plan_options(&self) -> PlanOptions {
+        PlanOptions {
+            scan: self.scan,
+            sort: self.sort,
+            max_operator_rows: self.max_operator_rows,
+            cancel: None,
+            deadline: None,
+            parallelism: self.parallelism,
+        }
+    }
+
+    fn unknown(name: &str) -> CrustyError {
+        CrustyError::ValidationError(format!(
+            "Unknown setting {}, expected scan_batch_size, scan_prefetch_pages, sort_memory, \
+             result_checksum, max_operator_rows, max_query_time, parallelism, priority, \
+             import_commit_rows or output_format",
+            name
+        ))
+    }
+}
+
+/// What the server knows about a connected client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionInfo {
+    /// Address the client connected from.
+    pub peer: String,
+    /// Id of the database the client is connected to, if any.
+    pub db_id: Option<u64>,
+    /// Transaction the client opened with `BEGIN` and has not committed or rolled back yet.
+    pub transaction: Option<TransactionId>,
+    /// Token that stops the statement the client is running.
+    pub cancel: CancelToken,
+}
+
+/// The sessions of the clients connected to the server. Every client gets an id of its own
+/// when it connects, so clients on the same host don't share a database or a transaction, and
+/// ids are not reused while the server runs.
+#[derive(Default)]
+pub struct SessionRegistry {
+    next_id: AtomicU64,
+    sessions: RwLock<HashMap<u64, SessionInfo>>,
+}
+
+impl SessionRegistry {
+    /// Registers a client that connected and returns the id of its session.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Address the client connected from.
+    pub fn open(&self, peer: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let session = SessionInfo {
+            peer: peer.to_string(),
+            db_id: None,
+            transaction: None,
+            cancel: CancelToken::new(),
+        };
+        self.sessions.write().unwrap().insert(id, session);
+        id
+    }
+
+    /// Forgets the session of a client that disconnected and returns what was known about it.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the session.
+    pub fn close(&self, id: u64) -> Option<SessionInfo> {
+        self.sessions.write().unwrap().remove(&id)
+    }
+
+    /// Gets the id of the database a session is connected to.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the session.
+    pub fn database(&self, id: u64) -> Option<u64> {
+        self.sessions.read().unwrap().get(&id).and_then(|s| s.db_id)
+    }
+
+    /// Connects a session to a database and returns the id of the database it was connected to
+    /// before, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the session.
+    /// * `db_id` - Id of the database.
+    pub fn set_database(&self, id: u64, db_id: u64) -> Result<Option<u64>, CrustyError> {
+        match self.sessions.write().unwrap().get_mut(&id) {
+            Some(session) => Ok(session.db_id.replace(db_id)),
+            None => Err(CrustyError::CrustyError(format!("No session {}", id))),
+        }
+    }
+
+    /// Records the transaction a session has open, None once it committed or rolled back.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the session.
+    /// * `transaction` - Transaction the session has open.
+    pub fn set_transaction(&self, id: u64, transaction: Option<TransactionId>) {
+        if let Some(session) = self.sessions.write().unwrap().get_mut(&id) {
+            session.transaction = transaction;
+        }
+    }
+
+    /// Gets the token that stops the statement a session is running.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the session.
+    pub fn cancel_token(&self, id: u64) -> Option<CancelToken> {
+        self.sessions
+            .read()
+            .unwrap()
+            .get(&id)
+            .map(|s| s.cancel.clone())
+    }
+
+    /// Stops the statement a session is running. A session that runs none is not affected.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the session.
+    pub fn cancel(&self, id: u64) -> Result<(), CrustyError> {
+        match self.sessions.read().unwrap().get(&id) {
+            Some(session) => {
+                session.cancel.cancel();
+                Ok(())
+            }
+            None => Err(CrustyError::CrustyError(format!("No session {}", id))),
+        }
+    }
+
+    /// Disconnects every session from its database, e.g. once the databases are dropped.
+    pub fn disconnect_all(&self) {
+        for session in self.sessions.write().unwrap().values_mut() {
+            session.db_id = None;
+        }
+    }
+
+    /// Lists the open sessions, by id.
+    pub fn list(&self) -> Vec<(u64, SessionInfo)> {
+        let mut sessions: Vec<(u64, SessionInfo)> = self
+            .sessions
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, session)| (*id, session.clone()))
+            .collect();
+        sessions.sort_by_key(|(id, _)| *id);
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let mut vars = SessionVariables::new();
+        vars.set("maxid", Field::IntField(42));
+        vars.set("name", Field::StringField(String::from("o'hare")));
+        assert_eq!(
+            "SELECT * FROM t WHERE t.id = 42 AND t.n = 'o''hare';",
+            vars.substitute("SELECT * FROM t WHERE t.id = :maxid AND t.n = :name;")
+                .unwrap()
+        );
+        // no substitution inside string literals
+        assert_eq!(
+            "SELECT * FROM t WHERE t.n = ':maxid'",
+            vars.substitute("SELECT * FROM t WHERE t.n = ':maxid'").unwrap()
+        );
+        assert!(vars.substitute("SELECT :missing").is_err());
+    }
+
+    #[test]
+    fn test_list_and_names() {
+        let mut vars = SessionVariables::new();
+        assert_eq!("No variables", vars.list());
+        vars.set("b", Field::IntField(2));
+        vars.set("a", Field::IntField(1));
+        assert_eq!("a = 1\nb = 2", vars.list());
+        assert!(SessionVariables::is_valid_name("max_id2"));
+        assert!(!SessionVariables::is_valid_name("2max"));
+        assert!(!SessionVariables::is_valid_name("max-id"));
+    }
+
+    #[test]
+    fn test_settings() -> Result<(), CrustyError> {
+        let mut settings = SessionSettings::default();
+        assert_eq!(
+            ScanOptions::default().batch_size.to_string(),
+            settings.get("scan_batch_size")?
+        );
+        settings.set("SCAN_BATCH_SIZE", "16")?;
+        settings.set("scan_prefetch_pages", "0")?;
+        assert_eq!(16, settings.scan.batch_size);
+        assert_eq!("0", settings.get("scan_prefetch_pages")?);
+        settings.set("sort_memory", "4096")?;
+        assert_eq!(4096, settings.sort.memory_limit);
+        assert!(settings.set("sort_memory", "0").is_err());
+        assert_eq!("off", settings.get("result_checksum")?);
+        settings.set("result_checksum", "ONLY")?;
+        assert_eq!(ResultChecksum::Only, settings.checksum);
+        assert!(settings.set("result_checksum", "1").is_err());
+        assert_eq!("off", settings.get("max_operator_rows")?);
+        settings.set("max_operator_rows", "1000")?;
+        assert_eq!(Some(1000), settings.plan_options().max_operator_rows);
+        settings.set("max_operator_rows", "OFF")?;
+        assert_eq!(None, settings.max_operator_rows);
+        settings.set("max_operator_rows", "5")?;
+        settings.set("max_operator_rows", "0")?;
+        assert_eq!("off", settings.get("max_operator_rows")?);
+        assert!(settings.set("max_operator_rows", "many").is_err());
+        assert_eq!("off", settings.get("max_query_time")?);
+        settings.set("max_query_time", "1500")?;
+        assert_eq!(Some(Duration::from_millis(1500)), settings.max_query_time);
+        assert_eq!("1500", settings.get("max_query_time")?);
+        settings.set("max_query_time", "off")?;
+        assert_eq!(None, settings.max_query_time);
+        assert!(settings.set("max_query_time", "1s").is_err());
+        assert_eq!("1", settings.get("parallelism")?);
+        settings.set("parallelism", "4")?;
+        assert_eq!(4, settings.plan_options().parallelism);
+        assert!(settings.set("parallelism", "0").is_err());
+        assert_eq!("interactive", settings.get("priority")?);
+        settings.set("priority", "Batch")?;
+        assert_eq!(Priority::Batch, settings.priority);
+        assert!(settings.set("priority", "1").is_err());
+        assert_eq!("off", settings.get("import_commit_rows")?);
+        settings.set("import_commit_rows", "10000")?;
+        assert_eq!(Some(10000), settings.import_commit_rows);
+        settings.set("import_commit_rows", "0")?;
+        assert_eq!(None, settings.import_commit_rows);
+        assert_eq!("text", settings.get("output_format")?);
+        assert_eq!(
+            cfg!(feature = "arrow"),
+            settings.set("output_format", "ARROW").is_ok()
+        );
+        assert!(settings.set("output_format", "csv").is_err());
+        assert!(settings.set("scan_batch_size", "0").is_err());
+        assert!(settings.set("scan_batch_size", "-1").is_err());
+        assert!(settings.set("work_mem", "1").is_err());
+        assert!(settings.get("work_mem").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_registry() -> Result<(), CrustyError> {
+        let sessions = SessionRegistry::default();
+        // clients on the same host get sessions of their own
+        let (a, b) = (sessions.open("127.0.0.1"), sessions.open("127.0.0.1"));
+        assert_ne!(a, b);
+        assert_eq!(None, sessions.set_database(a, 7)?);
+        assert_eq!(Some(7), sessions.set_database(a, 8)?);
+        assert_eq!(Some(8), sessions.database(a));
+        assert_eq!(None, sessions.database(b));
+        let tid = TransactionId::new();
+        sessions.set_transaction(b, Some(tid));
+        assert_eq!(Some(tid), sessions.list()[1].1.transaction);
+        let ids: Vec<u64> = sessions.list().iter().map(|(id, _)| *id).collect();
+        assert_eq!(vec![a, b], ids);
+        // cancelling a session stops only its statements
+        let token = sessions.cancel_token(b).unwrap();
+        sessions.cancel(b)?;
+        assert!(token.is_cancelled());
+        assert!(!sessions.cancel_token(a).unwrap().is_cancelled());
+
+        sessions.disconnect_all();
+        assert_eq!(None, sessions.database(a));
+        assert_eq!("127.0.0.1", sessions.close(a).unwrap().peer);
+        assert!(sessions.set_database(a, 7).is_err());
+        assert!(sessions.cancel(a).is_err());
+        // ids are not reused
+        assert!(sessions.open("127.0.0.1") > b);
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use common::io_stats::IoStats;
+use queryexe::opiterator::OperatorStats;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the file in the metadata path that slow statements are appended to.
+const SLOW_LOG_FILE: &str = "slow_queries.log";
+
+/// Slow statements kept for `\slowlog`. Older ones are only in the file.
+const RECENT_ENTRIES: usize = 50;
+
+/// A statement that ran for at least the slow query threshold.
+#[derive(Clone, Debug, Serialize)]
+pub struct SlowQuery {
+    /// When the statement finished, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// User the client logged in as, if any.
+    pub user: Option<String>,
+    pub database: String,
+    pub sql: String,
+    pub duration_ms: u64,
+    /// Why the statement failed, if it did.
+    pub error: Option<String>,
+    /// Logical plan of a query, as JSON.
+    pub plan: Option<Value>,
+    /// Tuples produced by and time spent in the operators of a query, parents first.
+    pub operators: Vec<OperatorStats>,
+    /// Pages a query read and wrote.
+    pub io: Option<IoStats>,
+}
+
+impl fmt::Display for SlowQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "at {} by {} on {}, {} ms: {}",
+            self.timestamp,
+            self.user.as_deref().unwrap_or("-"),
+            self.database,
+            self.duration_ms,
+            self.sql
+        )?;
+        if let Some(e) = &self.error {
+            write!(f, "\n  failed: {}", e)?;
+        }
+        for op in &self.operators {
+            write!(f, "\n  {}", op)?;
+        }
+        if let Some(io) = &self.io {
+            write!(f, "\n  {}", io)?;
+        }
+        Ok(())
+    }
+}
+
+/// Statements of all clients that ran for at least a threshold, appended as JSON lines to a
+/// file in the metadata directory and listed by `\slowlog`.
+#[derive(Default)]
+pub struct SlowLog {
+    /// Time from which a statement is logged, None if the log is off.
+    threshold: Option<Duration>,
+    /// File the statements are appended to, if any.
+    path: Option<PathBuf>,
+    /// The last `RECENT_ENTRIES` statements logged, the oldest first.
+    recent: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl SlowLog {
+    /// Creates a log of the statements that take at least `threshold`.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - Time from which a statement is logged.
+    /// * `metadata_path` - Directory of the file the statements are appended to.
+    pub fn new(threshold: Duration, metadata_path: &str) -> Self {
+        let mut path = PathBuf::from(metadata_path);
+        path.push(SLOW_LOG_FILE);
+        Self {
+            threshold: Some(threshold),
+            path: Some(path),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Returns whether statements are logged, so their plans have to be profiled.
+    pub fn is_enabled(&self) -> bool {
+        self.threshold.is_some()
+    }
+
+    /// Returns whether a statement that took `elapsed` is logged.
+    pub fn is_slow(&self, elapsed: Duration) -> bool {
+        self.threshold.map_or(false, |t| elapsed >= t)
+    }
+
+    /// Logs a slow statement. A failure to write the file is only reported in the server log,
+    /// the statement is still listed.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - Slow statement.
+    pub fn record(&self, entry: SlowQuery) {
+        warn!("Slow statement: {}", entry);
+        if let Some(path) = &self.path {
+            let res = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| {
+                    let line = serde_json::to_string(&entry)?;
+                    writeln!(file, "{}", line)
+                });
+            if let Err(e) = res {
+                error!("Failed to write slow query log {:?}: {}", path, e);
+            }
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_ENTRIES {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+
+    /// Lists the recent slow statements, the oldest first.
+    pub fn list(&self) -> String {
+        let threshold = match self.threshold {
+            Some(t) => t,
+            None => {
+                return String::from(
+                    "The slow query log is off, start the server with --slow_query_ms N",
+                )
+            }
+        };
+        let recent = self.recent.lock().unwrap();
+        if recent.is_empty() {
+            return format!("No statements took {} ms or more", threshold.as_millis());
+        }
+        recent
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Seconds since the Unix epoch, for the timestamp of an entry.
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::testutil::gen_random_dir;
+    use std::fs;
+
+    fn entry(sql: &str) -> SlowQuery {
+        SlowQuery {
+            timestamp: SlowLog::now(),
+            user: None,
+            database: String::from("db"),
+            sql: String::from(sql),
+            duration_ms: 1200,
+            error: None,
+            plan: Some(serde_json::json!({"root": 0})),
+            operators: vec![OperatorStats {
+                operator: String::from("Scan t"),
+                depth: 1,
+                rows: 3,
+                time_us: 1500,
+            }],
+            io: Some(IoStats::default()),
+        }
+    }
+
+    #[test]
+    fn test_slow_log() {
+        let off = SlowLog::default();
+        assert!(!off.is_enabled());
+        assert!(!off.is_slow(Duration::from_secs(60)));
+        assert!(off.list().contains("off"));
+
+        let dir = gen_random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let log = SlowLog::new(Duration::from_millis(1000), dir.to_str().unwrap());
+        assert!(log.is_slow(Duration::from_millis(1000)));
+        assert!(!log.is_slow(Duration::from_millis(999)));
+        assert_eq!("No statements took 1000 ms or more", log.list());
+        for i in 0..RECENT_ENTRIES + 1 {
+            log.record(entry(&format!("SELECT {}", i)));
+        }
+        let listed = log.list();
+        assert_eq!(RECENT_ENTRIES, listed.matches(" ms: SELECT ").count());
+        assert!(!listed.contains(" ms: SELECT 0\n"));
+        assert!(listed.ends_with(&format!(
+            "1200 ms: SELECT {}\n    Scan t: 3 rows, 1.500 ms\n  pages read: 0, pages written: 0",
+            RECENT_ENTRIES
+        )));
+
+        // every entry is in the file, one JSON object per line
+        let file = fs::read_to_string(dir.join(SLOW_LOG_FILE)).unwrap();
+        assert_eq!(RECENT_ENTRIES + 1, file.lines().count());
+        let first: Value = serde_json::from_str(file.lines().next().unwrap()).unwrap();
+        assert_eq!("SELECT 0", first["sql"]);
+        assert_eq!(3, first["operators"][0]["rows"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
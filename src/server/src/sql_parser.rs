@@ -1,3 +1,106 @@
+use common::CrustyError;
+use sqlparser::ast::{ColumnDef, Statement};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Token, Tokenizer, Whitespace};
+use std::fmt;
+
+/// A statement the SQL parser rejected, with where in the statement it gave up.
+#[derive(Debug, PartialEq)]
+pub struct SqlError {
+    /// Error of the SQL parser.
+    pub message: String,
+    /// Line the error is on, starting at 1.
+    pub line: usize,
+    /// Column of the offending token, in characters and starting at 1.
+    pub column: usize,
+    /// Offending token, empty if the statement ended too early.
+    pub token: String,
+    /// The line of the statement the error is on.
+    snippet: String,
+}
+
+impl fmt::Display for SqlError {
+    /// The error followed by the line it is on, with carets under the offending token:
+    ///
+    /// ```text
+    /// sql parser error: Expected an expression, found: ; at line 1, column 27
+    /// SELECT a FROM t WHERE a = ;
+    ///                           ^
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keep tabs so the carets line up however the client shows them
+        let indent: String = self
+            .snippet
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(
+            f,
+            "{} at line {}, column {}\n{}\n{}{}",
+            self.message,
+            self.line,
+            self.column,
+            self.snippet,
+            indent,
+            "^".repeat(self.token.chars().count().max(1))
+        )
+    }
+}
+
+impl SqlError {
+    /// Locates an error in the statement it was raised for.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Statement that was parsed.
+    /// * `message` - Error of the SQL parser.
+    /// * `offset` - Offset of the offending token in the statement, in characters.
+    /// * `token` - Offending token.
+    fn new(sql: &str, message: String, offset: usize, token: String) -> Self {
+        let before: String = sql.chars().take(offset).collect();
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let column = before[line_start..].chars().count() + 1;
+        let snippet = sql[line_start..]
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('\r')
+            .to_string();
+        Self {
+            message,
+            line,
+            column,
+            token,
+            snippet,
+        }
+    }
+}
+
+/// Column change of an `ALTER TABLE` statement.
+#[derive(Debug, PartialEq)]
+pub enum AlterColumn {
+    /// `ADD [COLUMN] <column definition>`
+    Add(ColumnDef),
+    /// `DROP [COLUMN] <column>`
+    Drop(String),
+}
+
+/// Statement creating or dropping an index, which the SQL parser does not support.
+#[derive(Debug, PartialEq)]
+pub enum IndexStatement {
+    /// `CREATE INDEX <name> ON <table> (<column>)`
+    Create {
+        name: String,
+        table: String,
+        column: String,
+    },
+    /// `DROP INDEX [IF EXISTS] <name>`
+    Drop { name: String, if_exists: bool },
+}
+
 pub struct SQLParser {}
 
 impl SQLParser {
@@ -5,4 +108,630 @@ impl SQLParser {
         let sm = SQLParser {};
         sm
     }
+
+    /// Parses SQL statements like `sqlparser::parser::Parser::parse_sql`, but reports errors
+    /// with the line, column and token the parser gave up at.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Raw SQL input from the user.
+    pub fn parse_sql(sql: &str) -> Result<Vec<Statement>, SqlError> {
+        let dialect = GenericDialect {};
+        let mut tokenizer = Tokenizer::new(&dialect, sql);
+        let tokens = match tokenizer.tokenize() {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                // The tokenizer error is only reachable through its Debug output,
+                // `TokenizerError("...")`
+                let debug = format!("{:?}", e);
+                let message = format!(
+                    "sql parser error: {}",
+                    debug
+                        .trim_start_matches("TokenizerError(\"")
+                        .trim_end_matches("\")")
+                );
+                // The tokenizer knows where the token it failed on starts, counting tabs as 4
+                let lines: Vec<&str> = sql.split('\n').collect();
+                let line = (tokenizer.line as usize).min(lines.len()) - 1;
+                let mut offset: usize = lines[..line].iter().map(|l| l.chars().count() + 1).sum();
+                let mut col = 1;
+                for c in lines[line].chars() {
+                    if col >= tokenizer.col as usize {
+                        break;
+                    }
+                    col += if c == '\t' { 4 } else { 1 };
+                    offset += 1;
+                }
+                return Err(SqlError::new(sql, message, offset, String::new()));
+            }
+        };
+        let mut tokens = tokens;
+        let mut offsets = Self::token_offsets(sql, &tokens);
+        Self::add_offset_rows(&mut tokens, &mut offsets);
+        // Positions of the tokens the parser does not skip
+        let significant: Vec<usize> = (0..tokens.len())
+            .filter(|&i| !matches!(tokens[i], Token::Whitespace(_)))
+            .collect();
+        let mut parser = Parser::new(tokens.clone());
+        let mut statements = Vec::new();
+        let mut expecting_delimiter = false;
+        // Same loop as Parser::parse_sql
+        let error = loop {
+            while parser.consume_token(&Token::SemiColon) {
+                expecting_delimiter = false;
+            }
+            if parser.peek_token().is_none() {
+                return Ok(statements);
+            } else if expecting_delimiter {
+                break ParserError::ParserError(format!(
+                    "Expected end of statement, found: {}",
+                    parser.peek_token().unwrap()
+                ));
+            }
+            match parser.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(e) => break e,
+            }
+            expecting_delimiter = true;
+        };
+
+        // The parser does not say where it stopped, but the tokens it did not consume do
+        let mut remaining = 0;
+        while parser.next_token().is_some() {
+            remaining += 1;
+        }
+        let next = significant.len() - remaining;
+        let message = error.to_string();
+        let found = message.rsplit("found: ").next().unwrap_or_default();
+        // The parser reports either the token it just consumed or the one it peeked at
+        let index = [Some(next), next.checked_sub(1)]
+            .iter()
+            .flatten()
+            .find(|&&i| i < significant.len() && tokens[significant[i]].to_string() == found)
+            .copied()
+            .unwrap_or(next);
+        match significant.get(index) {
+            Some(&i) => Err(SqlError::new(
+                sql,
+                message,
+                offsets[i],
+                tokens[i].to_string(),
+            )),
+            None => {
+                // Past the last token, point right after it
+                let end = significant
+                    .last()
+                    .map(|&i| offsets[i] + tokens[i].to_string().chars().count())
+                    .unwrap_or(0);
+                Err(SqlError::new(sql, message, end, String::new()))
+            }
+        }
+    }
+
+    /// Adds the `ROWS` keyword to `OFFSET <n>` clauses that lack it. The SQL parser requires
+    /// it, but PostgreSQL and MySQL do not, and `LIMIT 10 OFFSET 5` is how most people write
+    /// it.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens` - Tokens of the statement, including whitespace.
+    /// * `offsets` - Offsets of the tokens, the added ones get the offset right after `<n>`.
+    fn add_offset_rows(tokens: &mut Vec<Token>, offsets: &mut Vec<usize>) {
+        let is_word = |token: &Token, words: &[&str]| match token {
+            Token::Word(w) => w.quote_style.is_none() && words.contains(&w.keyword.as_str()),
+            _ => false,
+        };
+        let significant: Vec<usize> = (0..tokens.len())
+            .filter(|&i| !matches!(tokens[i], Token::Whitespace(_)))
+            .collect();
+        let numbers: Vec<usize> = significant
+            .windows(3)
+            .chain(std::iter::once(
+                &significant[significant.len().saturating_sub(2)..],
+            ))
+            .filter(|w| {
+                w.len() >= 2
+                    && is_word(&tokens[w[0]], &["OFFSET"])
+                    && matches!(tokens[w[1]], Token::Number(_))
+                    && w.get(2)
+                        .map_or(true, |&j| !is_word(&tokens[j], &["ROW", "ROWS"]))
+            })
+            .map(|w| w[1])
+            .collect();
+        // From the back, so the positions of the earlier numbers stay valid
+        for &n in numbers.iter().rev() {
+            let end = offsets[n] + tokens[n].to_string().chars().count();
+            tokens.splice(
+                n + 1..n + 1,
+                vec![
+                    Token::Whitespace(Whitespace::Space),
+                    Token::make_keyword("ROWS"),
+                ],
+            );
+            offsets.splice(n + 1..n + 1, vec![end, end]);
+        }
+    }
+
+    /// Finds the offset of each token in the statement it was read from, in characters.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Statement the tokens were read from.
+    /// * `tokens` - Tokens of the statement, including whitespace.
+    fn token_offsets(sql: &str, tokens: &[Token]) -> Vec<usize> {
+        let chars: Vec<char> = sql.chars().collect();
+        let mut offset = 0;
+        tokens
+            .iter()
+            .map(|token| {
+                let start = offset;
+                offset += match token {
+                    // Written back with single quotes where the input had escaped ones
+                    Token::SingleQuotedString(s)
+                    | Token::NationalStringLiteral(s)
+                    | Token::HexStringLiteral(s) => {
+                        token.to_string().chars().count() + s.matches('\'').count()
+                    }
+                    Token::Whitespace(Whitespace::Newline)
+                        if chars.get(start) == Some(&'\r')
+                            && chars.get(start + 1) == Some(&'\n') =>
+                    {
+                        2
+                    }
+                    _ => token.to_string().chars().count(),
+                };
+                start
+            })
+            .collect()
+    }
+
+    /// Recognizes `TRUNCATE [TABLE] <name>`, which the SQL parser does not support.
+    ///
+    /// Returns the table name if the input is a truncate statement.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Raw SQL input from the user.
+    pub fn parse_truncate(sql: &str) -> Option<String> {
+        let sql = sql.trim().trim_end_matches(';');
+        let mut tokens = sql.split_whitespace();
+        if !tokens.next()?.eq_ignore_ascii_case("truncate") {
+            return None;
+        }
+        let mut name = tokens.next()?;
+        if name.eq_ignore_ascii_case("table") {
+            name = tokens.next()?;
+        }
+        match tokens.next() {
+            Some(_) => None,
+            None => Some(name.to_string()),
+        }
+    }
+
+    /// Recognizes `ANALYZE [<name>]`, which the SQL parser does not support.
+    ///
+    /// Returns the table name, or None for every table, if the input is an analyze statement.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Raw SQL input from the user.
+    pub fn parse_analyze(sql: &str) -> Option<Option<String>> {
+        let sql = sql.trim().trim_end_matches(';');
+        let mut tokens = Self::strip_word(sql, "analyze")?.split_whitespace();
+        match (tokens.next(), tokens.next()) {
+            (None, _) => Some(None),
+            (Some(name), None) => Some(Some(name.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Recognizes `ALTER TABLE <name> ADD [COLUMN] <column definition>` and
+    /// `ALTER TABLE <name> DROP [COLUMN] <column>`, which the SQL parser does not support.
+    ///
+    /// Returns the table name and the change if the input is one of those, or an error if the
+    /// column definition is not valid. Constraint changes are left to the SQL parser.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Raw SQL input from the user.
+    pub fn parse_alter_table(sql: &str) -> Option<Result<(String, AlterColumn), CrustyError>> {
+        let sql = sql.trim().trim_end_matches(';');
+        let rest = Self::strip_word(Self::strip_word(sql, "alter")?, "table")?.trim_start();
+        let name = rest.split_whitespace().next()?;
+        let rest = &rest[name.len()..];
+        if let Some(column) = Self::strip_word(rest, "add") {
+            let column = Self::strip_word(column, "column").unwrap_or(column);
+            let first = column.split_whitespace().next()?;
+            let constraint = ["constraint", "primary", "unique", "foreign", "check"]
+                .iter()
+                .any(|w| first.eq_ignore_ascii_case(w));
+            if constraint {
+                return None;
+            }
+            // The column definition is parsed as the only column of a table
+            let invalid = |message: String| {
+                CrustyError::ValidationError(format!(
+                    "Invalid column definition {}: {}",
+                    column.trim(),
+                    message
+                ))
+            };
+            let create = format!("CREATE TABLE t ({})", column);
+            let change = match Self::parse_sql(&create).map(|mut s| s.pop()) {
+                Ok(Some(Statement::CreateTable {
+                    mut columns,
+                    constraints,
+                    ..
+                })) if columns.len() == 1 && constraints.is_empty() => {
+                    Ok((name.to_string(), AlterColumn::Add(columns.remove(0))))
+                }
+                Ok(_) => Err(invalid(String::from("expected a single column"))),
+                Err(e) => Err(invalid(e.message)),
+            };
+            return Some(change);
+        }
+        let column = Self::strip_word(rest, "drop")?;
+        let column = Self::strip_word(column, "column").unwrap_or(column);
+        let mut words = column.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(column), None) if !column.eq_ignore_ascii_case("constraint") => Some(Ok((
+                name.to_string(),
+                AlterColumn::Drop(column.to_string()),
+            ))),
+            _ => None,
+        }
+    }
+
+    /// Recognizes `CREATE INDEX <name> ON <table> (<column>)` and
+    /// `DROP INDEX [IF EXISTS] <name>`, which the SQL parser does not support.
+    ///
+    /// Returns the statement if the input is one of those, or an error if it is an index
+    /// statement of another form, e.g. on several columns.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Raw SQL input from the user.
+    pub fn parse_index(sql: &str) -> Option<Result<IndexStatement, CrustyError>> {
+        let sql = sql.trim().trim_end_matches(';');
+        if let Some(rest) = Self::strip_word(sql, "drop").and_then(|r| Self::strip_word(r, "index"))
+        {
+            let words: Vec<&str> = rest.split_whitespace().collect();
+            let drop = match words.as_slice() {
+                [name] => Ok(IndexStatement::Drop {
+                    name: name.to_string(),
+                    if_exists: false,
+                }),
+                [if_, exists, name]
+                    if if_.eq_ignore_ascii_case("if") && exists.eq_ignore_ascii_case("exists") =>
+                {
+                    Ok(IndexStatement::Drop {
+                        name: name.to_string(),
+                        if_exists: true,
+                    })
+                }
+                _ => Err(CrustyError::ValidationError(String::from(
+                    "Expected DROP INDEX [IF EXISTS] <name>",
+                ))),
+            };
+            return Some(drop);
+        }
+        let rest = Self::strip_word(sql, "create")?;
+        if Self::strip_word(rest, "unique")
+            .and_then(|r| Self::strip_word(r, "index"))
+            .is_some()
+        {
+            return Some(Err(CrustyError::ValidationError(String::from(
+                "UNIQUE indexes are not supported",
+            ))));
+        }
+        let rest = Self::strip_word(rest, "index")?;
+        let spaced = rest.replace('(', " ( ").replace(')', " ) ");
+        let words: Vec<&str> = spaced.split_whitespace().collect();
+        let create = match words.as_slice() {
+            [name, on, table, "(", column, ")"]
+                if on.eq_ignore_ascii_case("on") && !column.contains(',') =>
+            {
+                Ok(IndexStatement::Create {
+                    name: name.to_string(),
+                    table: table.to_string(),
+                    column: column.to_string(),
+                })
+            }
+            _ => Err(CrustyError::ValidationError(String::from(
+                "Expected CREATE INDEX <name> ON <table> (<column>), an index is on a single column",
+            ))),
+        };
+        Some(create)
+    }
+
+    /// Returns what follows a statement's first word if it is `word`, ignoring case.
+    fn strip_word<'a>(sql: &'a str, word: &str) -> Option<&'a str> {
+        let sql = sql.trim_start();
+        let first = sql.split_whitespace().next()?;
+        if first.eq_ignore_ascii_case(word) {
+            Some(&sql[first.len()..])
+        } else {
+            None
+        }
+    }
+
+    /// Recognizes `EXPLAIN [ANALYZE] <query>`, which the SQL parser does not support.
+    ///
+    /// Returns the statement to explain, and whether it should also be run, if the input is an
+    /// explain statement.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Raw SQL input from the user.
+    pub fn parse_explain(sql: &str) -> Option<(bool, String)> {
+        let sql = sql.trim_start();
+        let keyword = sql.split_whitespace().next()?;
+        if !keyword.eq_ignore_ascii_case("explain") {
+            return None;
+        }
+        let mut rest = sql[keyword.len()..].trim();
+        let analyze = match rest.split_whitespace().next() {
+            Some(word) if word.eq_ignore_ascii_case("analyze") => {
+                rest = rest[word.len()..].trim();
+                true
+            }
+            _ => false,
+        };
+        if rest.is_empty() {
+            None
+        } else {
+            Some((analyze, rest.to_string()))
+        }
+    }
+
+    /// Recognizes a time-travel query, `SELECT ... FROM t AS OF <txn-id|timestamp>`.
+    ///
+    /// Time travel needs the old versions of the rows, which the storage managers do not keep,
+    /// so these queries are rejected with an error rather than read as `t` aliased to `OF`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Raw SQL input from the user.
+    pub fn is_as_of(sql: &str) -> bool {
+        Self::has_words(sql, "as", "of")
+    }
+
+    /// Recognizes an upsert, `INSERT INTO t ... ON CONFLICT ...`.
+    ///
+    /// A conflict is a duplicate of a unique key, and tables have no unique constraints, so
+    /// upserts are rejected with an error rather than a parse error at `ON`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Raw SQL input from the user.
+    pub fn is_upsert(sql: &str) -> bool {
+        let first = sql.split_whitespace().next().unwrap_or_default();
+        first.eq_ignore_ascii_case("insert") && Self::has_words(sql, "on", "conflict")
+    }
+
+    /// Whether two words follow each other outside the string literals of a statement,
+    /// ignoring case.
+    fn has_words(sql: &str, first: &str, second: &str) -> bool {
+        let mut words = Vec::new();
+        let mut in_quote = false;
+        for part in sql.split('\'') {
+            if !in_quote {
+                words.extend(part.split_whitespace());
+            }
+            in_quote = !in_quote;
+        }
+        words
+            .windows(2)
+            .any(|w| w[0].eq_ignore_ascii_case(first) && w[1].eq_ignore_ascii_case(second))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error(sql: &str) -> SqlError {
+        SQLParser::parse_sql(sql).unwrap_err()
+    }
+
+    #[test]
+    fn test_parse_sql_errors() {
+        assert_eq!(
+            2,
+            SQLParser::parse_sql("SELECT 1; SELECT 2;").unwrap().len()
+        );
+
+        let e = error("SELECT a FROM t ORDER a;");
+        assert_eq!((1, 17, "ORDER"), (e.line, e.column, e.token.as_str()));
+        assert_eq!(
+            "sql parser error: Expected end of statement, found: ORDER at line 1, column 17\n\
+             SELECT a FROM t ORDER a;\n                ^^^^^",
+            e.to_string()
+        );
+        // peeked tokens, on later lines, after quoted strings
+        let e = error("SELECT a\r\nFROM t\nWHERE b = 'it''s' c;");
+        assert_eq!((3, 19, "c"), (e.line, e.column, e.token.as_str()));
+        assert!(e
+            .to_string()
+            .ends_with("WHERE b = 'it''s' c;\n                  ^"));
+        let e = error("CREATE TABLE t (a INT");
+        assert_eq!((1, 22, ""), (e.line, e.column, e.token.as_str()));
+        assert!(e.message.contains("found: EOF"));
+        let e = error("SELECT a FROM t WHERE a = ;");
+        assert_eq!((1, 27, ";"), (e.line, e.column, e.token.as_str()));
+        let e = error("SELECT a\tFROM t\nWHERE a ! 1");
+        assert_eq!((2, 9), (e.line, e.column));
+        assert!(e.message.starts_with("sql parser error: Tokenizer Error"));
+    }
+
+    #[test]
+    fn test_offset_without_rows() {
+        let query = |sql: &str| match SQLParser::parse_sql(sql).unwrap().remove(0) {
+            Statement::Query(q) => (
+                q.limit.map(|e| e.to_string()),
+                q.offset.map(|e| e.to_string()),
+            ),
+            _ => panic!("Not a query"),
+        };
+        let expected = (Some(String::from("10")), Some(String::from("5")));
+        assert_eq!(expected, query("SELECT * FROM t LIMIT 10 OFFSET 5"));
+        assert_eq!(expected, query("SELECT * FROM t LIMIT 10 OFFSET 5 ROWS;"));
+        assert_eq!(
+            (None, Some(String::from("1"))),
+            query("select * from t offset 1;")
+        );
+        // errors after the added keyword still point at the input
+        let e = error("SELECT * FROM t OFFSET 5 x");
+        assert_eq!((1, 26, String::from("x")), (e.line, e.column, e.token));
+    }
+
+    #[test]
+    fn test_parse_truncate() {
+        assert_eq!(
+            Some("t".to_string()),
+            SQLParser::parse_truncate("TRUNCATE TABLE t;\n")
+        );
+        assert_eq!(
+            Some("t".to_string()),
+            SQLParser::parse_truncate("truncate t")
+        );
+        assert_eq!(None, SQLParser::parse_truncate("truncate table"));
+        assert_eq!(None, SQLParser::parse_truncate("truncate table t u"));
+        assert_eq!(None, SQLParser::parse_truncate("select * from t"));
+    }
+
+    #[test]
+    fn test_parse_analyze() {
+        assert_eq!(Some(None), SQLParser::parse_analyze("ANALYZE;\n"));
+        assert_eq!(
+            Some(Some("t".to_string())),
+            SQLParser::parse_analyze("analyze t")
+        );
+        assert_eq!(None, SQLParser::parse_analyze("analyze t u"));
+        assert_eq!(None, SQLParser::parse_analyze("analyzed"));
+        assert_eq!(
+            None,
+            SQLParser::parse_analyze("explain analyze select * from t")
+        );
+    }
+
+    #[test]
+    fn test_parse_alter_table() {
+        let alter = SQLParser::parse_alter_table;
+        match alter("ALTER TABLE t ADD COLUMN name VARCHAR(10) NOT NULL;\n") {
+            Some(Ok((table, AlterColumn::Add(column)))) => {
+                assert_eq!(("t", "name"), (table.as_str(), column.name.as_str()));
+                assert_eq!(1, column.options.len());
+            }
+            other => panic!("Not an added column: {:?}", other),
+        }
+        assert!(matches!(
+            alter("alter table t add a int"),
+            Some(Ok((_, AlterColumn::Add(_))))
+        ));
+        assert_eq!(
+            Some(Ok((
+                String::from("t"),
+                AlterColumn::Drop(String::from("a"))
+            ))),
+            alter("alter table t drop column a;")
+        );
+        assert_eq!(
+            Some(Ok((
+                String::from("t"),
+                AlterColumn::Drop(String::from("a"))
+            ))),
+            alter("ALTER TABLE t DROP a")
+        );
+        assert!(matches!(alter("ALTER TABLE t ADD COLUMN a"), Some(Err(_))));
+        assert!(matches!(
+            alter("ALTER TABLE t ADD a INT, b INT"),
+            Some(Err(_))
+        ));
+        // constraints are left to the SQL parser
+        assert_eq!(None, alter("ALTER TABLE t ADD PRIMARY KEY (a)"));
+        assert_eq!(None, alter("ALTER TABLE t DROP CONSTRAINT c"));
+        assert_eq!(None, alter("ALTER TABLE t DROP a b"));
+        assert_eq!(None, alter("select * from t"));
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let index = SQLParser::parse_index;
+        assert_eq!(
+            Some(Ok(IndexStatement::Create {
+                name: String::from("t_a"),
+                table: String::from("t"),
+                column: String::from("a"),
+            })),
+            index("CREATE INDEX t_a ON t(a);")
+        );
+        assert_eq!(
+            Some(Ok(IndexStatement::Drop {
+                name: String::from("t_a"),
+                if_exists: true,
+            })),
+            index("drop index if exists t_a")
+        );
+        assert!(matches!(
+            index("DROP INDEX t_a"),
+            Some(Ok(IndexStatement::Drop {
+                if_exists: false,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            index("CREATE INDEX t_ab ON t (a, b)"),
+            Some(Err(_))
+        ));
+        assert!(matches!(
+            index("CREATE UNIQUE INDEX u ON t (a)"),
+            Some(Err(_))
+        ));
+        assert!(matches!(index("DROP INDEX a b"), Some(Err(_))));
+        assert_eq!(None, index("CREATE TABLE t (a INT)"));
+        assert_eq!(None, index("DROP TABLE t"));
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        assert_eq!(
+            Some((false, "select * from t;".to_string())),
+            SQLParser::parse_explain("EXPLAIN select * from t;\n")
+        );
+        assert_eq!(
+            Some((true, "select * from t;".to_string())),
+            SQLParser::parse_explain("explain Analyze select * from t;\n")
+        );
+        assert_eq!(None, SQLParser::parse_explain("explain"));
+        assert_eq!(None, SQLParser::parse_explain("explain analyze"));
+        assert_eq!(None, SQLParser::parse_explain("explained"));
+        assert_eq!(None, SQLParser::parse_explain("select * from t"));
+    }
+
+    #[test]
+    fn test_is_as_of() {
+        assert!(SQLParser::is_as_of("SELECT * FROM t as of 12;"));
+        assert!(SQLParser::is_as_of("select a from t AS OF '2021-01-01'"));
+        assert!(!SQLParser::is_as_of("select a as of_a from t"));
+        assert!(!SQLParser::is_as_of(
+            "select a from t where b = 'x as of y'"
+        ));
+    }
+
+    #[test]
+    fn test_is_upsert() {
+        assert!(SQLParser::is_upsert(
+            "INSERT INTO t VALUES (1, 2) ON CONFLICT (a) DO UPDATE SET b = 2"
+        ));
+        assert!(SQLParser::is_upsert(
+            "insert into t values (1, 2) on conflict do nothing;"
+        ));
+        assert!(!SQLParser::is_upsert(
+            "INSERT INTO t VALUES ('on conflict')"
+        ));
+        assert!(!SQLParser::is_upsert(
+            "SELECT * FROM t JOIN conflict ON a = b"
+        ));
+    }
 }
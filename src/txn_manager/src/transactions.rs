@@ -1,4 +1,5 @@
 use common::ids::TransactionId;
+use common::storage_trait::StorageTrait;
 use common::CrustyError;
 
 /// Transaction implementation.
@@ -22,38 +23,68 @@ impl Transaction {
         }
     }
 
-    /// Starts the transaction.
-    pub fn start(&mut self) {
+    /// Starts the transaction, so its writes to the storage manager can be undone until it
+    /// completes.
+    ///
+    /// # Arguments
+    ///
+    /// * `sm` - Storage manager the transaction writes to.
+    pub fn start<T: StorageTrait>(&mut self, sm: &T) {
+        sm.start_transaction(self.tid);
         self.started = true
     }
 
+    /// Returns true if the transaction was started and has not completed.
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
     /// Returns the transaction id.
     pub fn tid(&self) -> TransactionId {
         self.tid
     }
 
-    /// Commits the transaction.
-    pub fn commit(&mut self) -> Result<(), CrustyError> {
-        self.complete(true)
+    /// Commits the transaction. If it was aborted waiting for a lock, it is rolled back instead
+    /// and `CrustyError::TransactionAbortedError` is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `sm` - Storage manager the transaction was started on.
+    pub fn commit<T: StorageTrait>(&mut self, sm: &T) -> Result<(), CrustyError> {
+        self.complete(sm, true)
     }
 
-    /// Aborts the transaction.
-    pub fn abort(&mut self) -> Result<(), CrustyError> {
-        self.complete(false)
+    /// Aborts the transaction, undoing its writes.
+    ///
+    /// # Arguments
+    ///
+    /// * `sm` - Storage manager the transaction was started on.
+    pub fn abort<T: StorageTrait>(&mut self, sm: &T) -> Result<(), CrustyError> {
+        self.complete(sm, false)
     }
 
-    /// Completes the transaction.
+    /// Completes the transaction. Does nothing if it was not started. A transaction that was
+    /// aborted waiting for a lock is rolled back even if it should commit, and then
+    /// `CrustyError::TransactionAbortedError` is returned.
     ///
     /// # Arguments
     ///
+    /// * `sm` - Storage manager the transaction was started on.
     /// * `commit` - True if the transaction should commit.
-    pub fn complete(&mut self, commit: bool) -> Result<(), CrustyError> {
+    pub fn complete<T: StorageTrait>(&mut self, sm: &T, commit: bool) -> Result<(), CrustyError> {
         if self.started {
-            error!("Error: FIXME, need to notify on txn complete {}", commit);
-            //FIXME DBSERVER.transaction_complete(self.tid, commit)?;
+            debug!("Completing transaction {:?}, commit: {}", self.tid, commit);
             self.started = false;
+            let killed = sm.check_transaction(self.tid);
+            if commit && killed.is_ok() {
+                sm.transaction_finished(self.tid);
+            } else {
+                sm.abort_transaction(self.tid)?;
+            }
+            if commit {
+                return killed;
+            }
         }
         Ok(())
     }
 }
-
@@ -0,0 +1,100 @@
+//! Sends random queries to a server and fails if the server panics or hangs on one of them.
+//!
+//! Usage: `cargo run --release -p utilities --bin sql_fuzz -- [QUERIES] [SEED]`
+//!
+//! Any result or error is accepted as a response. A query that gets no response within the
+//! timeout, or makes the server close the connection, is printed with the seed to rerun it, and
+//! the fuzzer reconnects and goes on. It exits with an error if any query failed, or as soon as
+//! the server exits. The seed is random unless given.
+use rand::prelude::*;
+use std::process::exit;
+use std::time::Duration;
+use utilities::fuzz::{FuzzColumn, FuzzTable, QueryGenerator};
+use utilities::serverwrapper::ServerWrapper;
+
+/// How long a query may run before the server is considered hung.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn tables() -> Vec<FuzzTable> {
+    let column = |name, is_string| FuzzColumn { name, is_string };
+    vec![
+        FuzzTable {
+            name: "t",
+            columns: vec![column("a", false), column("b", false), column("c", true)],
+        },
+        FuzzTable {
+            name: "u",
+            columns: vec![column("a", false), column("d", false), column("e", true)],
+        },
+    ]
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let queries = args
+        .next()
+        .map_or(1000, |n| n.parse().expect("Invalid number of queries"));
+    let seed = args
+        .next()
+        .map_or_else(|| thread_rng().gen(), |s| s.parse().expect("Invalid seed"));
+    println!("Fuzzing with {} queries, seed {}", queries, seed);
+
+    let mut generator = QueryGenerator::new(seed, tables());
+    let mut server = ServerWrapper::new().unwrap();
+    // connecting again after a failure reruns the commands before the table setup
+    let connect = [
+        String::from("\\c fuzz"),
+        // bounds the cross products of malformed joins
+        String::from("SET max_operator_rows = 100000"),
+    ];
+    let mut setup = vec![String::from("\\r fuzz")];
+    setup.extend(connect.iter().cloned());
+    for table in generator.tables() {
+        setup.extend(table.setup(50));
+    }
+    for command in &setup {
+        if let Err(e) = server.try_command(command, TIMEOUT) {
+            eprintln!("Setup command {:?} failed: {}", command, e);
+            server.kill();
+            exit(1);
+        }
+    }
+
+    let mut errors = 0;
+    let mut failures = 0;
+    for i in 0..queries {
+        let query = generator.next_query();
+        match server.try_command(&query, TIMEOUT) {
+            Ok(response) => {
+                if response.contains("Error") || response.starts_with("SQL error") {
+                    errors += 1;
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                eprintln!("Query {} of seed {} failed ({}):\n{}", i, seed, e, query);
+                if server.has_exited() {
+                    eprintln!("The server exited");
+                    break;
+                }
+                let reconnected = server.reconnect().and_then(|_| {
+                    connect
+                        .iter()
+                        .try_for_each(|c| server.try_command(c, TIMEOUT).map(|_| ()))
+                });
+                if let Err(e) = reconnected {
+                    eprintln!("Could not reconnect to the server: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+    server.kill();
+    println!(
+        "{} queries, {} returned errors, {} panicked or hung",
+        queries, errors, failures
+    );
+    if failures > 0 {
+        exit(1);
+    }
+}
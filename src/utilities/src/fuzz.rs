@@ -0,0 +1,311 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+/// Column of a table the generated queries run against.
+pub struct FuzzColumn {
+    pub name: &'static str,
+    pub is_string: bool,
+}
+
+/// Table the generated queries run against.
+pub struct FuzzTable {
+    pub name: &'static str,
+    pub columns: Vec<FuzzColumn>,
+}
+
+impl FuzzTable {
+    /// Statements creating the table and inserting `rows` rows of small values, so joins and
+    /// comparisons with the generated literals match some rows.
+    pub fn setup(&self, rows: i32) -> Vec<String> {
+        let columns = self
+            .columns
+            .iter()
+            .map(|c| {
+                let dtype = if c.is_string { "varchar(32)" } else { "int" };
+                format!("{} {}", c.name, dtype)
+            })
+            .collect::<Vec<_>>();
+        let values = (0..rows)
+            .map(|i| {
+                let fields = self
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .map(|(j, c)| {
+                        let v = (i * 7 + j as i32 * 3) % 10;
+                        if c.is_string {
+                            format!("'s{}'", v)
+                        } else {
+                            v.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                format!("({})", fields.join(", "))
+            })
+            .collect::<Vec<_>>();
+        vec![
+            format!("CREATE TABLE {} ({});", self.name, columns.join(", ")),
+            format!("INSERT INTO {} VALUES {};", self.name, values.join(", ")),
+        ]
+    }
+}
+
+/// Tokens spliced into queries to make them malformed.
+const NOISE: &[&str] = &[
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "GROUP",
+    "BY",
+    "HAVING",
+    "ORDER",
+    "LIMIT",
+    "OFFSET",
+    "JOIN",
+    "ON",
+    "AND",
+    "OR",
+    "NOT",
+    "NULL",
+    "DISTINCT",
+    "AS",
+    "*",
+    ",",
+    "(",
+    ")",
+    "=",
+    "<",
+    ">=",
+    "+",
+    "-",
+    ".",
+    ";",
+    "'",
+    "''",
+    "0",
+    "-1",
+    "2147483648",
+    "99999999999999999999",
+    "'s1'",
+    "DATE '2021-02-30'",
+    "INTERVAL '1' DAY",
+    "COUNT(*)",
+    "SUM(",
+    "nosuchcolumn",
+    "nosuchtable.a",
+    "\\",
+    "é",
+];
+
+/// Generates random SQL queries from a grammar of the SELECT statements the server supports,
+/// over a known schema. Some queries reference unknown names or have tokens dropped, repeated
+/// or replaced, so the front end is also given malformed input. Queries are reproducible from
+/// the seed.
+pub struct QueryGenerator {
+    rng: StdRng,
+    tables: Vec<FuzzTable>,
+}
+
+impl QueryGenerator {
+    /// Creates a generator of queries over `tables`.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - Seed of the random queries.
+    /// * `tables` - Tables the queries select from.
+    pub fn new(seed: u64, tables: Vec<FuzzTable>) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            tables,
+        }
+    }
+
+    /// Tables the queries select from.
+    pub fn tables(&self) -> &[FuzzTable] {
+        &self.tables
+    }
+
+    /// Generates the next query, on a single line.
+    pub fn next_query(&mut self) -> String {
+        let mut tokens = self.select();
+        if self.rng.gen_bool(0.1) {
+            tokens.insert(0, String::from("EXPLAIN"));
+        }
+        if self.rng.gen_bool(0.2) {
+            self.mutate(&mut tokens);
+        }
+        tokens.join(" ")
+    }
+
+    fn select(&mut self) -> Vec<String> {
+        let from = self.from();
+        let mut tokens = vec![String::from("SELECT")];
+        if self.rng.gen_bool(0.15) {
+            tokens.push(String::from("DISTINCT"));
+        }
+
+        let grouped = self.rng.gen_bool(0.3);
+        let mut group_by = Vec::new();
+        if grouped {
+            for _ in 0..self.rng.gen_range(0, 3) {
+                group_by.push(self.column(&from));
+            }
+        }
+        let mut projection = Vec::new();
+        if !grouped && self.rng.gen_bool(0.3) {
+            projection.push(String::from("*"));
+        } else {
+            for _ in 0..self.rng.gen_range(1, 4) {
+                let item = if grouped && (group_by.is_empty() || self.rng.gen_bool(0.5)) {
+                    self.aggregate(&from)
+                } else if grouped {
+                    group_by.choose(&mut self.rng).unwrap().clone()
+                } else {
+                    self.column(&from)
+                };
+                if self.rng.gen_bool(0.2) {
+                    projection.push(format!("{} AS x{}", item, projection.len()));
+                } else {
+                    projection.push(item);
+                }
+            }
+        }
+        tokens.push(projection.join(", "));
+
+        tokens.push(String::from("FROM"));
+        tokens.push(self.from_clause(&from));
+        if self.rng.gen_bool(0.5) {
+            tokens.push(String::from("WHERE"));
+            tokens.push(self.predicate(&from));
+        }
+        if grouped && !group_by.is_empty() {
+            tokens.push(String::from("GROUP BY"));
+            tokens.push(group_by.join(", "));
+        }
+        if grouped && self.rng.gen_bool(0.3) {
+            let agg = self.aggregate(&from);
+            let op = self.comparison();
+            let lit = self.rng.gen_range(-2, 12);
+            tokens.push(format!("HAVING {} {} {}", agg, op, lit));
+        }
+        if self.rng.gen_bool(0.3) {
+            let mut keys = Vec::new();
+            for _ in 0..self.rng.gen_range(1, 3) {
+                let key = if self.rng.gen_bool(0.3) {
+                    projection.choose(&mut self.rng).unwrap().clone()
+                } else {
+                    self.column(&from)
+                };
+                let dir = ["", " ASC", " DESC"].choose(&mut self.rng).unwrap();
+                keys.push(format!("{}{}", key, dir));
+            }
+            tokens.push(format!("ORDER BY {}", keys.join(", ")));
+        }
+        if self.rng.gen_bool(0.2) {
+            tokens.push(format!("LIMIT {}", self.rng.gen_range(0, 20)));
+            if self.rng.gen_bool(0.5) {
+                tokens.push(format!("OFFSET {}", self.rng.gen_range(0, 20)));
+            }
+        }
+        tokens
+    }
+
+    /// Indexes of the tables in the FROM clause, one or two.
+    fn from(&mut self) -> Vec<usize> {
+        let first = self.rng.gen_range(0, self.tables.len());
+        if self.rng.gen_bool(0.4) {
+            vec![first, self.rng.gen_range(0, self.tables.len())]
+        } else {
+            vec![first]
+        }
+    }
+
+    fn from_clause(&mut self, from: &[usize]) -> String {
+        let first = self.tables[from[0]].name;
+        match from.get(1) {
+            None => first.to_string(),
+            Some(&second) => {
+                let second = self.tables[second].name;
+                if self.rng.gen_bool(0.5) {
+                    format!("{}, {}", first, second)
+                } else {
+                    let (l, r) = (self.column(&from[..1]), self.column(&from[1..]));
+                    format!("{} JOIN {} ON {} = {}", first, second, l, r)
+                }
+            }
+        }
+    }
+
+    /// A column of one of the tables, sometimes qualified and sometimes unknown.
+    fn column(&mut self, from: &[usize]) -> String {
+        if self.rng.gen_bool(0.03) {
+            return String::from("nosuchcolumn");
+        }
+        let table = &self.tables[*from.choose(&mut self.rng).unwrap()];
+        let column = table.columns.choose(&mut self.rng).unwrap();
+        if self.rng.gen_bool(0.5) {
+            format!("{}.{}", table.name, column.name)
+        } else {
+            column.name.to_string()
+        }
+    }
+
+    fn aggregate(&mut self, from: &[usize]) -> String {
+        let op = ["COUNT", "SUM", "AVG", "MIN", "MAX"]
+            .choose(&mut self.rng)
+            .unwrap();
+        let column = self.column(from);
+        format!("{}({})", op, column)
+    }
+
+    fn comparison(&mut self) -> &'static str {
+        ["=", "<>", "<", "<=", ">", ">="]
+            .choose(&mut self.rng)
+            .unwrap()
+    }
+
+    fn literal(&mut self) -> String {
+        match self.rng.gen_range(0, 5) {
+            0 => format!("'s{}'", self.rng.gen_range(0, 10)),
+            1 => String::from("DATE '2021-03-08'"),
+            2 => format!("{}", self.rng.gen::<i32>()),
+            _ => format!("{}", self.rng.gen_range(-2, 12)),
+        }
+    }
+
+    fn predicate(&mut self, from: &[usize]) -> String {
+        let left = self.column(from);
+        let op = self.comparison();
+        let right = if self.rng.gen_bool(0.3) {
+            self.column(from)
+        } else {
+            self.literal()
+        };
+        if self.rng.gen_bool(0.5) {
+            format!("{} {} {}", left, op, right)
+        } else {
+            format!("{} {} {}", right, op, left)
+        }
+    }
+
+    /// Drops, repeats or replaces a token, or cuts the query short.
+    fn mutate(&mut self, tokens: &mut Vec<String>) {
+        let mut words: Vec<String> = tokens
+            .iter()
+            .flat_map(|t| t.split(' ').map(String::from).collect::<Vec<_>>())
+            .collect();
+        let i = self.rng.gen_range(0, words.len());
+        match self.rng.gen_range(0, 4) {
+            0 => {
+                words.remove(i);
+            }
+            1 => {
+                let word = words[i].clone();
+                words.insert(i, word);
+            }
+            2 => words[i] = NOISE.choose(&mut self.rng).unwrap().to_string(),
+            _ => words.truncate(i.max(1)),
+        }
+        *tokens = words;
+    }
+}
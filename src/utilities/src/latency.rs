@@ -0,0 +1,154 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Environment variable that overrides the directory latency reports are written to.
+pub const REPORT_DIR_VAR: &str = "CRUSTY_BENCH_REPORT_DIR";
+
+/// Latencies of one command. Every sample is kept, benchmarks run few enough commands that
+/// exact percentiles are cheaper than bucketing them.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyHistogram {
+    samples: Vec<Duration>,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+    }
+
+    /// Latency at or below which `p` percent of the samples are (nearest rank), zero if there
+    /// are none.
+    pub fn percentile(&self, p: f64) -> Duration {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        Self::nearest_rank(&sorted, p)
+    }
+
+    fn nearest_rank(sorted: &[Duration], p: f64) -> Duration {
+        if sorted.is_empty() {
+            return Duration::default();
+        }
+        let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+
+    /// Summary of the samples for the report of `command`.
+    pub fn summary(&self, command: &str) -> LatencySummary {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let total: Duration = sorted.iter().sum();
+        let micros = |d: Duration| d.as_micros() as u64;
+        LatencySummary {
+            command: command.to_owned(),
+            samples: sorted.len(),
+            mean_us: if sorted.is_empty() {
+                0
+            } else {
+                micros(total) / sorted.len() as u64
+            },
+            min_us: sorted.first().copied().map_or(0, micros),
+            p50_us: micros(Self::nearest_rank(&sorted, 50.0)),
+            p95_us: micros(Self::nearest_rank(&sorted, 95.0)),
+            p99_us: micros(Self::nearest_rank(&sorted, 99.0)),
+            max_us: sorted.last().copied().map_or(0, micros),
+        }
+    }
+}
+
+/// Latency distribution of one command, in microseconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct LatencySummary {
+    pub command: String,
+    pub samples: usize,
+    pub mean_us: u64,
+    pub min_us: u64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// Machine-readable result of a benchmark run, one JSON file per run.
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchReport {
+    /// Name of the benchmark.
+    pub benchmark: String,
+    /// When the run finished, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Peak memory of the server process in KB, if the platform reports it.
+    pub server_peak_memory_kb: Option<u64>,
+    /// Latencies of the benchmarked commands, in the order they are run.
+    pub commands: Vec<LatencySummary>,
+    /// Time the server reported executing the commands that are statements, without the
+    /// round trip to the client.
+    pub execution: Vec<LatencySummary>,
+}
+
+impl BenchReport {
+    /// Creates a report of the commands' latencies, in the order of `commands`.
+    ///
+    /// # Arguments
+    ///
+    /// * `benchmark` - Name of the benchmark.
+    /// * `commands` - Commands that were run.
+    /// * `latencies` - Latencies recorded by command.
+    pub fn new(
+        benchmark: &str,
+        commands: &[String],
+        latencies: &BTreeMap<String, LatencyHistogram>,
+    ) -> Self {
+        BenchReport {
+            benchmark: benchmark.to_owned(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            server_peak_memory_kb: None,
+            commands: Self::summaries(commands, latencies),
+            execution: Vec::new(),
+        }
+    }
+
+    /// Summaries of the latencies of the commands that have any, in the order of `commands`,
+    /// each command once.
+    ///
+    /// # Arguments
+    ///
+    /// * `commands` - Commands that were run.
+    /// * `latencies` - Latencies recorded by command.
+    pub fn summaries(
+        commands: &[String],
+        latencies: &BTreeMap<String, LatencyHistogram>,
+    ) -> Vec<LatencySummary> {
+        let mut seen = HashSet::new();
+        commands
+            .iter()
+            .filter(|c| seen.insert(c.as_str()))
+            .filter_map(|c| latencies.get(c).map(|h| h.summary(c)))
+            .collect()
+    }
+
+    /// Directory reports are written to: `CRUSTY_BENCH_REPORT_DIR` if set, else
+    /// `target/bench-reports` of the workspace.
+    pub fn default_dir() -> PathBuf {
+        match std::env::var_os(REPORT_DIR_VAR) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../../target/bench-reports"
+            )),
+        }
+    }
+
+    /// Writes the report to `<dir>/<benchmark>-<timestamp>.json`, creating `dir` if needed, and
+    /// returns the path of the file.
+    pub fn write(&self, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}-{}.json", self.benchmark, self.timestamp));
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
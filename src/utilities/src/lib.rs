@@ -1,2 +1,4 @@
+pub mod fuzz;
+pub mod latency;
 pub mod serverwrapper;
 pub mod template;
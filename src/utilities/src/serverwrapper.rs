@@ -1,42 +1,99 @@
+use common::protocol;
+use common::ResultMetadata;
 use escargot::CargoBuild;
-use std::io::{Read, Result, Write};
+use std::io::{Error, ErrorKind, Result, Write};
 use std::net::{Shutdown, TcpStream};
 use std::process::{Child, Stdio};
+use std::time::{Duration, Instant};
 
 pub struct ServerWrapper {
     stream: TcpStream,
     child: Child,
+    port: u16,
+}
+
+/// How the server is built and started.
+#[derive(Clone, Debug)]
+pub struct ServerOptions {
+    /// Cargo features of the server, e.g. `heapstore`.
+    pub features: Vec<String>,
+    /// Port the server listens on.
+    pub port: u16,
+    /// Further arguments of the server, e.g. `--db_path`.
+    pub args: Vec<String>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            features: Vec::new(),
+            port: 3333,
+            args: Vec::new(),
+        }
+    }
 }
 
 impl ServerWrapper {
-    fn setup_server() -> Result<Child> {
-        CargoBuild::new()
+    fn setup_server(options: &ServerOptions) -> Result<Child> {
+        let mut build = CargoBuild::new()
             .bin("server")
             .current_release()
             .current_target()
-            .manifest_path("../src/server/Cargo.toml")
+            .manifest_path(concat!(env!("CARGO_MANIFEST_DIR"), "/../server/Cargo.toml"));
+        if !options.features.is_empty() {
+            // a target directory per feature set, so the default server isn't rebuilt over
+            build = build
+                .features(options.features.join(" "))
+                .target_dir(format!(
+                    "{}/../../target/features/{}",
+                    env!("CARGO_MANIFEST_DIR"),
+                    options.features.join("-")
+                ));
+        }
+        build
             .run()
             .unwrap()
             .command()
+            .arg("--port")
+            .arg(options.port.to_string())
+            .args(&options.args)
             // .stderr(Stdio::null())
             // .stdout(Stdio::null())
             .spawn()
     }
 
-    fn try_connect() -> Result<TcpStream> {
-        let bind_addr = "127.0.0.1:3333".to_string();
+    fn try_connect(port: u16) -> Result<TcpStream> {
+        let bind_addr = format!("127.0.0.1:{}", port);
         let stream = TcpStream::connect(bind_addr)?;
         stream.set_nodelay(true).unwrap();
         Ok(stream)
     }
 
     pub fn new() -> std::result::Result<ServerWrapper, String> {
-        // Configure log environment
-        let child = ServerWrapper::setup_server().unwrap();
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        match ServerWrapper::try_connect() {
-            Ok(stream) => Ok(ServerWrapper { stream, child }),
-            _ => Err("Failed to connect to server".to_owned()),
+        ServerWrapper::with_options(&ServerOptions::default())
+    }
+
+    /// Builds and starts a server with the given options and connects to it, waiting up to
+    /// a few seconds for it to listen.
+    pub fn with_options(options: &ServerOptions) -> std::result::Result<ServerWrapper, String> {
+        let mut child = ServerWrapper::setup_server(options).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            match ServerWrapper::try_connect(options.port) {
+                Ok(stream) => {
+                    return Ok(ServerWrapper {
+                        stream,
+                        child,
+                        port: options.port,
+                    })
+                }
+                Err(_) if Instant::now() < deadline => continue,
+                Err(_) => {
+                    let _ = child.kill();
+                    return Err("Failed to connect to server".to_owned());
+                }
+            }
         }
     }
 
@@ -68,37 +125,75 @@ impl ServerWrapper {
         self.stream
             .write_all(format!("{}\n", command).as_bytes())
             .expect("Failed to write");
-        // Read server response
-        let mut data = [0 as u8; 256];
-        while match self.stream.read(&mut data) {
-            Ok(_size) => {
-                //TODO: Remove echo and change to from_utf8
-                // let s = String::from_utf8_lossy(&data);
-
-                //TODO this is dirty. Should likely be response type sent to client.
-                // //quit command received from server
-                // if s.starts_with("\\") {
-                //     if s.starts_with("\\quit") {
-                //         info!("Received Quit Command");
-                //         cont = false;
-                //     } else {
-                //         info!("command received {}", s);
-                //         panic!("No action specified for command {}", s);
-                //     }
-                // }
-                // info!("{}", s);
-                false
+        // Read server response, as the client shows it
+        match protocol::read_whole_response(&mut self.stream) {
+            Ok(Some(response)) => response.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Sends a command and returns its response as the client shows it, with what the
+    /// statement did as the server reported it, if the command was a statement.
+    pub fn run_command_with_metadata(&mut self, command: &str) -> (String, Option<ResultMetadata>) {
+        self.stream
+            .write_all(format!("{}\n", command).as_bytes())
+            .expect("Failed to write");
+        match protocol::read_whole_response(&mut self.stream) {
+            Ok(Some(response)) => (response.to_string(), response.metadata().cloned()),
+            _ => (String::new(), None),
+        }
+    }
+
+    /// Sends a command and waits up to `timeout` for the whole response. Unlike
+    /// run_command_with_out, an error is returned if the server closes the connection before
+    /// responding, as it does when the thread serving the client panics, or takes too long.
+    pub fn try_command(&mut self, command: &str, timeout: Duration) -> Result<String> {
+        self.stream.write_all(format!("{}\n", command).as_bytes())?;
+        self.stream.set_read_timeout(Some(timeout))?;
+        let res = protocol::read_whole_response(&mut self.stream);
+        self.stream.set_read_timeout(None)?;
+        match res {
+            Ok(Some(response)) => Ok(response.to_string()),
+            Ok(None) => Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Connection closed before the response ended",
+            )),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                Err(Error::new(ErrorKind::TimedOut, "No response in time"))
             }
-            Err(_) => false,
-        } {}
-        String::from_utf8(data.to_vec()).unwrap()
-
-        // FIXME: this is a better way of reading the answer
-        // println!("Command sent, waiting for response...");
-        // let mut out = [0 as u8; 256];
-        // self.stream.read_exact(&mut out).unwrap();
-        // println!("response received!");
-        // String::from_utf8(out.to_vec()).unwrap()
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens a new connection to the server, for a client whose connection was closed.
+    pub fn reconnect(&mut self) -> Result<()> {
+        self.stream = ServerWrapper::try_connect(self.port)?;
+        Ok(())
+    }
+
+    /// Kills the server without closing the client first.
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    /// Whether the server process has exited.
+    pub fn has_exited(&mut self) -> bool {
+        self.child
+            .try_wait()
+            .map_or(true, |status| status.is_some())
+    }
+
+    /// Peak resident memory of the server process in KB, read from /proc on Linux.
+    pub fn peak_memory_kb(&self) -> Option<u64> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.child.id())).ok()?;
+        status
+            .lines()
+            .find(|l| l.starts_with("VmHWM:"))?
+            .split_whitespace()
+            .nth(1)?
+            .parse()
+            .ok()
     }
 
     pub fn run_command(&mut self, command: &str) -> &mut Self {
@@ -1,9 +1,12 @@
+use crate::latency::{BenchReport, LatencyHistogram};
 use crate::serverwrapper::ServerWrapper;
 
 use rand::prelude::*;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
+use std::time::Instant;
 
 use common::{Field, Tuple};
 
@@ -12,6 +15,10 @@ pub struct Template {
     commands: Vec<String>,
     cleanup: Vec<String>,
     server: ServerWrapper,
+    /// Latencies of the commands, recorded every time they are run.
+    latencies: BTreeMap<String, LatencyHistogram>,
+    /// Time the server reported executing the commands, for those that are statements.
+    execution_times: BTreeMap<String, LatencyHistogram>,
 }
 
 impl Default for Template {
@@ -27,6 +34,8 @@ impl Template {
             commands: Vec::new(),
             cleanup: Vec::new(),
             server: ServerWrapper::new().unwrap(),
+            latencies: BTreeMap::new(),
+            execution_times: BTreeMap::new(),
         }
     }
 
@@ -41,6 +50,8 @@ impl Template {
         for tup in tuples.iter() {
             for field in tup.field_vals() {
                 let val = match field {
+                    // imported as NULL
+                    Field::Null => String::new(),
                     Field::IntField(i) => i.to_string(),
                     Field::StringField(s) => s.to_string(),
                 };
@@ -70,10 +81,38 @@ impl Template {
         tuples
     }
 
+    /// Generates a table `(f0 varchar, f1 int)` whose string column holds `groups` distinct keys.
+    pub fn generate_string_key_table(&mut self, name: &str, groups: i32, rows: i32) -> Vec<Tuple> {
+        let mut rng = rand::thread_rng();
+
+        let mut tuples: Vec<Tuple> = Vec::new();
+        for i in 0..rows {
+            // Every tenth key has a multi-byte character to exercise the string codec.
+            let group = i % groups;
+            let key = if group % 10 == 0 {
+                format!("grüppe_{:06}", group)
+            } else {
+                format!("group_{:06}", group)
+            };
+            tuples.push(Tuple::new(vec![
+                Field::StringField(key),
+                Field::IntField(rng.gen_range(0, 1000)),
+            ]));
+        }
+        self.push_table(name, 2, &tuples);
+
+        tuples
+    }
+
+    /// Column types are taken from the first tuple, int if there are no tuples.
     pub fn push_table(&mut self, name: &str, columns: i32, tuples: &[Tuple]) {
         let mut fs = "(".to_owned();
         for i in 0..columns {
-            fs.push_str(&format!("f{} int,", i));
+            let dtype = match tuples.first().and_then(|t| t.get_field(i as usize)) {
+                Some(Field::StringField(_)) => "varchar(32)",
+                _ => "int",
+            };
+            fs.push_str(&format!("f{} {},", i, dtype));
         }
         fs.pop();
         fs.push(')');
@@ -103,10 +142,35 @@ impl Template {
         // println!("# commands to run: {:?}", self.commands);
         for command in self.commands.iter() {
             // println!("Running command: {:?}", command);
-            self.server.run_command(command);
+            let start = Instant::now();
+            let (_, metadata) = self.server.run_command_with_metadata(command);
+            self.latencies
+                .entry(command.clone())
+                .or_default()
+                .record(start.elapsed());
+            if let Some(elapsed) = metadata.and_then(|m| m.elapsed) {
+                self.execution_times
+                    .entry(command.clone())
+                    .or_default()
+                    .record(elapsed);
+            }
         }
     }
 
+    /// Report of the latencies of the commands over every time they were run, and of the time
+    /// the server spent executing them.
+    pub fn latency_report(&self, name: &str) -> BenchReport {
+        let mut report = BenchReport::new(name, &self.commands, &self.latencies);
+        report.execution = BenchReport::summaries(&self.commands, &self.execution_times);
+        report.server_peak_memory_kb = self.server_peak_memory_kb();
+        report
+    }
+
+    /// Peak memory of the server process in KB, if the platform reports it.
+    pub fn server_peak_memory_kb(&self) -> Option<u64> {
+        self.server.peak_memory_kb()
+    }
+
     pub fn run_cleanup(&mut self) {
         // println!("rust_cleanup...");
         for command in self.cleanup.iter() {
@@ -0,0 +1,276 @@
+//! Runs queries end to end against a server built with the heapstore storage manager, checking
+//! the results against ones computed here. The benchmarks and the other server tests use the
+//! default in-memory storage manager, so this is what catches regressions of the heap files
+//! and the buffer pool on the full query path.
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use utilities::serverwrapper::{ServerOptions, ServerWrapper};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Rows of t, (a, b), enough to fill several pages.
+fn t_rows() -> Vec<(i32, i32)> {
+    (0..2000).map(|i| (i, i * 7 % 50)).collect()
+}
+
+/// Rows of u, (a, c), joining with every third row of t.
+fn u_rows() -> Vec<(i32, String)> {
+    (0..100).map(|i| (i * 3, format!("c{}", i))).collect()
+}
+
+/// A server on heapstore with its own database and heap file directories, and t and u loaded
+/// from CSV files.
+struct Fixture {
+    server: ServerWrapper,
+    options: ServerOptions,
+    dir: PathBuf,
+}
+
+impl Fixture {
+    fn new(name: &str, port: u16) -> Self {
+        let dir = std::env::temp_dir().join(format!("crusty_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let options = ServerOptions {
+            features: vec![String::from("heapstore")],
+            port,
+            args: vec![
+                String::from("--db_path"),
+                dir.join("db/").to_string_lossy().to_string(),
+                String::from("--hf_path"),
+                dir.join("hf/").to_string_lossy().to_string(),
+            ],
+        };
+        let mut fixture = Fixture {
+            server: ServerWrapper::with_options(&options).unwrap(),
+            options,
+            dir,
+        };
+
+        let t_csv = fixture.write_csv(
+            "t.csv",
+            t_rows().iter().map(|(a, b)| format!("{},{}", a, b)),
+        );
+        let u_csv = fixture.write_csv(
+            "u.csv",
+            u_rows().iter().map(|(a, c)| format!("{},{}", a, c)),
+        );
+        for command in &[
+            String::from("\\r db"),
+            String::from("\\c db"),
+            String::from("CREATE TABLE t (a INT, b INT);"),
+            String::from("CREATE TABLE u (a INT, c VARCHAR(10));"),
+            format!("\\i {} t", t_csv),
+            format!("\\i {} u", u_csv),
+        ] {
+            fixture.run(command);
+        }
+        fixture
+    }
+
+    fn write_csv(&self, name: &str, lines: impl Iterator<Item = String>) -> String {
+        let path = self.dir.join(name);
+        fs::write(&path, lines.collect::<Vec<_>>().join("\n") + "\n").unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    /// Shuts the server down with \shutdown and waits for it to exit.
+    fn shutdown(&mut self) {
+        self.run("\\shutdown");
+        let deadline = Instant::now() + TIMEOUT;
+        while !self.server.has_exited() {
+            assert!(Instant::now() < deadline, "the server did not exit");
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Starts the server again on the same directories, connected to db.
+    fn restart(&mut self) {
+        self.server = ServerWrapper::with_options(&self.options).unwrap();
+        self.run("\\c db");
+    }
+
+    /// Runs a command, failing the test if the server doesn't answer or answers with an error.
+    fn run(&mut self, command: &str) -> String {
+        let response = self
+            .server
+            .try_command(command, TIMEOUT)
+            .unwrap_or_else(|e| panic!("{:?} failed: {}", command, e));
+        assert!(
+            !response.contains("Error") && !response.starts_with("SQL error"),
+            "{:?} returned {:?}",
+            command,
+            response
+        );
+        response
+    }
+
+    /// Runs a query and returns the fields of its rows, without the header.
+    fn query(&mut self, sql: &str) -> Vec<Vec<String>> {
+        self.run(sql)
+            .lines()
+            .skip(1)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split_whitespace().map(String::from).collect())
+            .collect()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        self.server.kill();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Rows as strings, for comparing to the rows of a query.
+fn to_rows<T: ToString>(rows: impl Iterator<Item = Vec<T>>) -> Vec<Vec<String>> {
+    rows.map(|row| row.iter().map(|f| f.to_string()).collect())
+        .collect()
+}
+
+fn sorted(mut rows: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    rows.sort();
+    rows
+}
+
+#[test]
+fn heapstore_queries() {
+    let mut db = Fixture::new("heapstore_queries", 3341);
+    let t = t_rows();
+    let u = u_rows();
+
+    let all = to_rows(t.iter().map(|(a, b)| vec![*a, *b]));
+    assert_eq!(sorted(all), sorted(db.query("SELECT * FROM t;")));
+
+    let filtered = to_rows(
+        t.iter()
+            .filter(|(a, b)| *b < 5 && *a >= 1000)
+            .map(|(a, _)| vec![*a]),
+    );
+    assert_eq!(
+        sorted(filtered),
+        sorted(db.query("SELECT a FROM t WHERE b < 5 AND a >= 1000;"))
+    );
+
+    let joined = to_rows(t.iter().flat_map(|(a, b)| {
+        u.iter()
+            .filter(move |(ua, _)| ua == a)
+            .map(move |(_, c)| vec![a.to_string(), b.to_string(), c.clone()])
+    }));
+    assert_eq!(joined.len(), u.len());
+    assert_eq!(
+        sorted(joined),
+        sorted(db.query("SELECT t.a, b, c FROM t JOIN u ON t.a = u.a;"))
+    );
+
+    // a nested loop join, whose condition names the right table first
+    let less = to_rows(t.iter().filter(|(a, _)| *a < 5).flat_map(|(a, _)| {
+        u.iter()
+            .filter(move |(ua, _)| ua > a)
+            .map(move |(ua, _)| vec![*a, *ua])
+    }));
+    assert_eq!(
+        sorted(less),
+        sorted(db.query("SELECT t.a, u.a FROM t JOIN u ON u.a > t.a WHERE t.a < 5;"))
+    );
+
+    // unmatched rows of t are padded with NULL
+    let u_keys: HashSet<i32> = u.iter().map(|(a, _)| *a).collect();
+    let left = to_rows(t.iter().map(|(a, _)| {
+        let ua = if u_keys.contains(a) {
+            a.to_string()
+        } else {
+            String::from("NULL")
+        };
+        vec![a.to_string(), ua]
+    }));
+    assert_eq!(
+        sorted(left),
+        sorted(db.query("SELECT t.a, u.a FROM t LEFT JOIN u ON t.a = u.a;"))
+    );
+
+    let distinct = to_rows((0..50).map(|b| vec![b]));
+    assert_eq!(distinct, db.query("SELECT DISTINCT b FROM t ORDER BY b;"));
+    assert_eq!(
+        to_rows((1995..2000).rev().map(|a| vec![a])),
+        db.query("SELECT a FROM t ORDER BY a DESC LIMIT 5;")
+    );
+
+    // set operations, without ALL the duplicates are removed
+    let union: BTreeSet<i32> = t
+        .iter()
+        .map(|(_, b)| *b)
+        .chain(u.iter().map(|(a, _)| *a))
+        .collect();
+    assert_eq!(
+        to_rows(union.into_iter().map(|v| vec![v])),
+        db.query("SELECT b FROM t UNION SELECT a FROM u ORDER BY b;")
+    );
+    let except = to_rows(
+        t.iter()
+            .filter(|(a, _)| !u_keys.contains(a))
+            .map(|(a, _)| vec![*a]),
+    );
+    assert_eq!(
+        sorted(except),
+        sorted(db.query("SELECT a FROM t EXCEPT ALL SELECT a FROM u;"))
+    );
+
+    // inserted rows are read back with the imported ones
+    db.run("INSERT INTO t VALUES (5000, 1), (5001, 2);");
+    assert_eq!(
+        to_rows(vec![vec![5000], vec![5001]].into_iter()),
+        sorted(db.query("SELECT a FROM t WHERE a > 4000;"))
+    );
+    assert_eq!(2002, db.query("SELECT * FROM t;").len());
+
+    let warmed = db.run("\\warm t");
+    assert!(
+        warmed.starts_with("Warmed ") && !warmed.starts_with("Warmed 0 "),
+        "{:?}",
+        warmed
+    );
+}
+
+#[test]
+fn heapstore_aggregates() {
+    let mut db = Fixture::new("heapstore_aggregates", 3342);
+    let t = t_rows();
+
+    let sum: i32 = t.iter().map(|(_, b)| b).sum();
+    assert_eq!(
+        to_rows(vec![vec![t.len() as i32, sum]].into_iter()),
+        db.query("SELECT COUNT(a), SUM(b) FROM t;")
+    );
+
+    let groups = to_rows((0..50).map(|b| {
+        let count = t.iter().filter(|(_, tb)| *tb == b).count() as i32;
+        vec![b, count]
+    }));
+    assert_eq!(
+        groups,
+        db.query("SELECT b, COUNT(a) FROM t GROUP BY b ORDER BY b;")
+    );
+}
+
+#[test]
+fn heapstore_shutdown() {
+    let mut db = Fixture::new("heapstore_shutdown", 3343);
+    db.run("INSERT INTO t VALUES (5000, 1), (5001, 2);");
+    let expected = sorted(db.query("SELECT * FROM t;"));
+    assert_eq!(t_rows().len() + 2, expected.len());
+
+    // the writes still cached in the buffer pool are written back before the server exits, and
+    // the pages it cached are recorded for the next start, next to the database's heapfiles
+    db.shutdown();
+    assert!(db.dir.join("hf").join("db").join("warmup.meta").exists());
+    db.restart();
+    assert_eq!(expected, sorted(db.query("SELECT * FROM t;")));
+    assert_eq!(
+        to_rows(vec![vec![5000], vec![5001]].into_iter()),
+        sorted(db.query("SELECT a FROM t WHERE a > 4000;"))
+    );
+    assert_eq!(u_rows().len(), db.query("SELECT * FROM u;").len());
+}